@@ -0,0 +1,119 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Time and heap allocations of processing the input buffers, run with `cargo bench`.
+//!
+//! Fails when processing a buffer into the reused channel data allocates memory.
+
+use audio_in_stream_rs::{process_input_buffer, process_input_buffer_into};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+/// the system allocator, counting the allocations
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const ITERATIONS: usize = 10_000;
+
+/// 10 ms stereo buffers at 48 kHz
+const NUM_CHANNELS: usize = 2;
+const NUM_FRAMES: usize = 480;
+
+/// Run the processing of a buffer many times,
+/// returns the time per buffer in microseconds and the allocations per buffer.
+fn measure(mut process: impl FnMut()) -> (f64, f64) {
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        process();
+    }
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+    (
+        elapsed.as_secs_f64() * 1e6 / ITERATIONS as f64,
+        allocations as f64 / ITERATIONS as f64,
+    )
+}
+
+fn main() {
+    let i16_buffer: Vec<i16> = (0..NUM_CHANNELS * NUM_FRAMES)
+        .map(|index| ((index as f32 * 0.01).sin() * 16384.0) as i16)
+        .collect();
+    let f32_buffer: Vec<f32> = i16_buffer.iter().map(|&s| s as f32 / 32768.0).collect();
+
+    let (time, allocations) = measure(|| {
+        std::hint::black_box(process_input_buffer(
+            std::hint::black_box(&f32_buffer),
+            NUM_CHANNELS,
+        ));
+    });
+    println!(
+        "process_input_buffer f32:      {:8.3} us/buffer, {:5.1} allocations/buffer",
+        time, allocations
+    );
+
+    let reused_allocations =
+        measure_reused("f32", &f32_buffer) + measure_reused("i16", &i16_buffer);
+    if reused_allocations > 0.0 {
+        eprintln!("error: processing into the reused channel data allocated memory");
+        std::process::exit(1);
+    }
+}
+
+/// Print the time and allocations of processing into the reused channel data,
+/// returns the allocations per buffer.
+fn measure_reused<T>(name: &str, buffer: &[T]) -> f64
+where
+    T: cpal::Sample,
+    f32: cpal::FromSample<T>,
+{
+    // the first buffer allocates the channel data
+    let mut channel_data = Vec::new();
+    process_input_buffer_into(buffer, NUM_CHANNELS, &mut channel_data);
+
+    let (time, allocations) = measure(|| {
+        process_input_buffer_into(
+            std::hint::black_box(buffer),
+            NUM_CHANNELS,
+            &mut channel_data,
+        )
+    });
+    println!(
+        "process_input_buffer_into {}: {:8.3} us/buffer, {:5.1} allocations/buffer",
+        name, time, allocations
+    );
+    allocations
+}
@@ -0,0 +1,111 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! AAC-LC encoding of a stream of interleaved samples, with the libfdk-aac of fdk-aac.
+
+use fdk_aac::enc::{AudioObjectType, BitRate, ChannelMode, Encoder, EncoderParams, Transport};
+use std::convert::TryFrom;
+
+pub const DEFAULT_BITRATE: i32 = 128000;
+/// Frames of audio of each access unit of AAC-LC.
+pub const FRAME_LEN: usize = 1024;
+/// Largest access unit of 2 channels, 6144 bits per channel.
+const MAX_ACCESS_UNIT_LEN: usize = 2 * 768;
+
+/// Encodes interleaved samples as AAC-LC access units of `FRAME_LEN` frames, mono for
+/// 1 channel and stereo of the first 2 channels otherwise.
+pub struct AacEncoder {
+    encoder: Encoder,
+    input_channels: usize,
+    aac_channels: usize,
+    sample_rate: u32,
+    audio_specific_config: Vec<u8>,
+}
+
+impl AacEncoder {
+    pub fn new(channels: u16, sample_rate: u32, bitrate: i32) -> Result<Self, String> {
+        let input_channels = channels as usize;
+        let aac_channels = input_channels.min(2);
+        let invalid = |err| {
+            format!(
+                "invalid AAC encoder parameters, {} Hz at {} bit/s, {}",
+                sample_rate, bitrate, err
+            )
+        };
+        let encoder = Encoder::new(EncoderParams {
+            bit_rate: BitRate::Cbr(u32::try_from(bitrate).unwrap_or_default()),
+            sample_rate,
+            transport: Transport::Raw,
+            channels: if aac_channels == 1 {
+                ChannelMode::Mono
+            } else {
+                ChannelMode::Stereo
+            },
+            audio_object_type: AudioObjectType::Mpeg4LowComplexity,
+        })
+        .map_err(invalid)?;
+        let info = encoder.info().map_err(invalid)?;
+        let audio_specific_config = info.confBuf[..info.confSize as usize].to_vec();
+        Ok(AacEncoder {
+            encoder,
+            input_channels,
+            aac_channels,
+            sample_rate,
+            audio_specific_config,
+        })
+    }
+
+    /// Channels of the AAC stream, 1 or 2.
+    pub fn channels(&self) -> usize {
+        self.aac_channels
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// `AudioSpecificConfig` of the stream, the decoder configuration of the containers.
+    pub fn audio_specific_config(&self) -> &[u8] {
+        &self.audio_specific_config
+    }
+
+    /// Encode a block of interleaved samples, returns the access units of the whole
+    /// AAC frames there is samples for so far.
+    pub fn encode(&mut self, samples: &[f32]) -> Result<Vec<Vec<u8>>, String> {
+        let mut aac_input = Vec::with_capacity(samples.len());
+        for frame in samples.chunks_exact(self.input_channels) {
+            for &sample in &frame[..self.aac_channels] {
+                aac_input.push((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16);
+            }
+        }
+
+        let mut access_units = Vec::new();
+        let mut offset = 0;
+        let mut access_unit = [0_u8; MAX_ACCESS_UNIT_LEN];
+        loop {
+            let info = self
+                .encoder
+                .encode(&aac_input[offset..], &mut access_unit)
+                .map_err(|err| format!("failed to encode AAC frame, {}", err))?;
+            offset += info.input_consumed;
+            if info.output_size > 0 {
+                access_units.push(access_unit[..info.output_size].to_vec());
+            } else if info.input_consumed == 0 || offset == aac_input.len() {
+                break;
+            }
+        }
+        Ok(access_units)
+    }
+}
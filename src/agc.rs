@@ -0,0 +1,138 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Automatic gain control of a stream of interleaved samples, for listeners not to ride
+//! their volume when the level of the source wanders, followed by a limiter.
+//!
+//! The gain brings the RMS level of the stream, averaged over a few hundred milliseconds,
+//! to the target level, up to the maximum gain, and is held while the stream is too quiet
+//! to be raised to the target, not to raise the noise of the pauses. It falls faster than
+//! it rises, and is the same on every channel not to move the stereo image.
+
+/// dBFS, the RMS level of a sine in dBFS as in AES17
+pub const DEFAULT_TARGET: f32 = -20.0;
+/// dB
+pub const DEFAULT_MAX_GAIN: f32 = 20.0;
+/// dBFS, the highest peak out of the limiter
+const LIMIT: f32 = -1.0;
+/// seconds, time constant of the RMS level
+const LEVEL_TIME: f32 = 0.4;
+/// seconds, time constants of the gain falling and rising
+const ATTACK_TIME: f32 = 0.5;
+const RELEASE_TIME: f32 = 3.0;
+/// seconds, time constant of the limiter releasing its reduction of the gain
+const LIMITER_RELEASE_TIME: f32 = 0.1;
+/// dB, under the lowest level raised to the target where the gain is held
+const GATE_MARGIN: f32 = 10.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AgcConfig {
+    /// dBFS, RMS level of the stream
+    pub target: f32,
+    /// dB, highest gain, and reduction of the gain
+    pub max_gain: f32,
+}
+
+impl Default for AgcConfig {
+    fn default() -> Self {
+        AgcConfig {
+            target: DEFAULT_TARGET,
+            max_gain: DEFAULT_MAX_GAIN,
+        }
+    }
+}
+
+/// One-pole smoothing coefficient of a time constant, at a sample rate.
+fn coefficient(time: f32, sample_rate: u32) -> f32 {
+    1.0 - (-1.0 / (time * sample_rate as f32)).exp()
+}
+
+pub struct AutomaticGainControl {
+    config: AgcConfig,
+    num_channels: usize,
+    /// dBFS, the level under which the gain is held
+    gate: f32,
+    level_coefficient: f32,
+    attack_coefficient: f32,
+    release_coefficient: f32,
+    limiter_release_coefficient: f32,
+    /// mean square of the samples of every channel, averaged
+    mean_square: f32,
+    /// dB
+    gain: f32,
+    /// linear, of the limiter, at most 1
+    limiter_gain: f32,
+    limit: f32,
+}
+
+impl AutomaticGainControl {
+    pub fn new(config: AgcConfig, num_channels: usize, sample_rate: u32) -> Self {
+        AutomaticGainControl {
+            config,
+            num_channels,
+            gate: config.target - config.max_gain - GATE_MARGIN,
+            level_coefficient: coefficient(LEVEL_TIME, sample_rate),
+            attack_coefficient: coefficient(ATTACK_TIME, sample_rate),
+            release_coefficient: coefficient(RELEASE_TIME, sample_rate),
+            limiter_release_coefficient: coefficient(LIMITER_RELEASE_TIME, sample_rate),
+            mean_square: 0.0,
+            gain: 0.0,
+            limiter_gain: 1.0,
+            limit: 10_f32.powf(LIMIT / 20.0),
+        }
+    }
+
+    /// Apply the gain and the limiter to a buffer of interleaved samples in place.
+    pub fn process(&mut self, samples: &mut [f32]) {
+        for frame in samples.chunks_exact_mut(self.num_channels) {
+            let mean_square =
+                frame.iter().map(|sample| sample * sample).sum::<f32>() / frame.len() as f32;
+            self.mean_square += self.level_coefficient * (mean_square - self.mean_square);
+            // a sine reads its peak level
+            let level = 10.0 * (2.0 * self.mean_square).log10();
+            if level > self.gate {
+                let gain =
+                    (self.config.target - level).clamp(-self.config.max_gain, self.config.max_gain);
+                let coefficient = if gain < self.gain {
+                    self.attack_coefficient
+                } else {
+                    self.release_coefficient
+                };
+                self.gain += coefficient * (gain - self.gain);
+            }
+
+            let gain = 10_f32.powf(self.gain / 20.0);
+            // the gain reduction of the limiter is immediate, and released slowly
+            let peak = frame
+                .iter()
+                .fold(0.0_f32, |peak, sample| peak.max(sample.abs()))
+                * gain;
+            let limiter_gain = if peak > self.limit {
+                self.limit / peak
+            } else {
+                1.0
+            };
+            self.limiter_gain = if limiter_gain < self.limiter_gain {
+                limiter_gain
+            } else {
+                (self.limiter_gain + self.limiter_release_coefficient * (1.0 - self.limiter_gain))
+                    .min(limiter_gain)
+            };
+            for sample in frame {
+                *sample *= gain * self.limiter_gain;
+            }
+        }
+    }
+}
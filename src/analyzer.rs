@@ -0,0 +1,229 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Audio analyzer of a test tone on the input: THD+N, THD and SNR of each channel,
+//! and the frequency response from the level of the tone as it is stepped or swept.
+//!
+//! Each analysis window is transformed with a 4-term Blackman-Harris window, its
+//! sidelobes below -92 dB, and the power of the fundamental and its harmonics is
+//! summed over their main lobes, the rest being noise, in the band from 20 Hz to 20 kHz
+//! as in AES17.
+
+use crate::ChannelData;
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+/// samples of each analysis window, 0.68 s at 48 kHz
+pub const ANALYSIS_SIZE: usize = 32768;
+/// Hz, band of the noise and distortion
+const LOW_FREQUENCY: f32 = 20.0;
+const HIGH_FREQUENCY: f32 = 20_000.0;
+/// bins each side of a tone, the main lobe of the window
+const LOBE_BINS: usize = 5;
+/// harmonics summed in the THD, from the second
+const HARMONICS: usize = 10;
+/// relative distance of the expected frequency to the fundamental found
+const EXPECTED_TOLERANCE: f32 = 0.05;
+
+/// Measurements of the tone of a channel.
+#[derive(Clone, Debug, Serialize)]
+pub struct ToneAnalysis {
+    pub channel: usize,
+    /// Hz, of the fundamental
+    pub frequency: f32,
+    /// dBFS, of the fundamental
+    pub level: f32,
+    /// dB, the distortion and noise relative to the whole signal
+    pub thd_n: f32,
+    pub thd_n_percent: f32,
+    /// dB, the harmonics up to the tenth relative to the fundamental
+    pub thd: f32,
+    /// dB, the fundamental relative to the noise without the harmonics
+    pub snr: f32,
+    /// level of the fundamental in each third octave band it was analyzed, by frequency
+    pub frequency_response: Vec<ResponsePoint>,
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct ResponsePoint {
+    /// Hz, center of the third octave band
+    pub frequency: f32,
+    /// dBFS
+    pub level: f32,
+}
+
+/// Analyzes the test tone of each channel in windows of [`ANALYSIS_SIZE`] samples.
+pub struct ToneAnalyzer {
+    fft: Arc<dyn Fft<f32>>,
+    window_coefficients: Vec<f32>,
+    /// scale of the power of a tone to the square of its amplitude
+    power_scale: f32,
+    bin_width: f32,
+    /// Hz, of the test tone, otherwise the strongest in the band
+    expected_frequency: Option<f32>,
+    /// samples of the current window of each channel
+    windows: Vec<Vec<f32>>,
+    /// level of each third octave band from 1 kHz of each channel
+    responses: Vec<BTreeMap<i32, f32>>,
+    buffer: Vec<Complex<f32>>,
+    scratch: Vec<Complex<f32>>,
+}
+
+impl ToneAnalyzer {
+    pub fn new(sample_rate: u32, num_channels: usize, expected_frequency: Option<f32>) -> Self {
+        let fft = FftPlanner::new().plan_fft_forward(ANALYSIS_SIZE);
+        let window_coefficients: Vec<f32> = (0..ANALYSIS_SIZE)
+            .map(|n| {
+                let phase = 2.0 * PI * n as f32 / ANALYSIS_SIZE as f32;
+                0.35875 - 0.48829 * phase.cos() + 0.14128 * (2.0 * phase).cos()
+                    - 0.01168 * (3.0 * phase).cos()
+            })
+            .collect();
+        // the one-sided power of a tone of amplitude A is A² N Σw² / 4
+        let power_scale = 4.0
+            / (ANALYSIS_SIZE as f32
+                * window_coefficients
+                    .iter()
+                    .map(|coefficient| coefficient * coefficient)
+                    .sum::<f32>());
+        let scratch = vec![Complex::default(); fft.get_inplace_scratch_len()];
+        ToneAnalyzer {
+            fft,
+            window_coefficients,
+            power_scale,
+            bin_width: sample_rate as f32 / ANALYSIS_SIZE as f32,
+            expected_frequency,
+            windows: vec![Vec::with_capacity(ANALYSIS_SIZE); num_channels],
+            responses: vec![BTreeMap::new(); num_channels],
+            buffer: vec![Complex::default(); ANALYSIS_SIZE],
+            scratch,
+        }
+    }
+
+    /// Add the samples of an input buffer, returns the analysis of each channel
+    /// once a window is complete.
+    pub fn process(&mut self, channels: &[ChannelData]) -> Option<Vec<ToneAnalysis>> {
+        let mut analyses = None;
+        for (position, channel) in channels.iter().enumerate() {
+            let mut samples = &channel.samples[..];
+            while !samples.is_empty() {
+                let window = &mut self.windows[position];
+                let len = samples.len().min(ANALYSIS_SIZE - window.len());
+                window.extend_from_slice(&samples[..len]);
+                samples = &samples[len..];
+                if window.len() == ANALYSIS_SIZE {
+                    let analysis = self.analyze(position, channel.index);
+                    self.windows[position].clear();
+                    analyses
+                        .get_or_insert_with(|| Vec::with_capacity(channels.len()))
+                        .push(analysis);
+                }
+            }
+        }
+        analyses
+    }
+
+    fn analyze(&mut self, position: usize, channel: usize) -> ToneAnalysis {
+        for ((value, &sample), coefficient) in self
+            .buffer
+            .iter_mut()
+            .zip(&self.windows[position])
+            .zip(&self.window_coefficients)
+        {
+            *value = Complex::new(sample * coefficient, 0.0);
+        }
+        self.fft
+            .process_with_scratch(&mut self.buffer, &mut self.scratch);
+        // in f64, the noise is orders of magnitude below the fundamental
+        let powers: Vec<f64> = self.buffer[..=ANALYSIS_SIZE / 2]
+            .iter()
+            .map(|value| value.norm_sqr() as f64)
+            .collect();
+
+        let low_bin = ((LOW_FREQUENCY / self.bin_width).ceil() as usize).max(LOBE_BINS + 1);
+        let high_bin = ((HIGH_FREQUENCY / self.bin_width).floor() as usize).min(powers.len() - 1);
+        let lobe = |bin: usize| {
+            bin.saturating_sub(LOBE_BINS).max(low_bin)..=(bin + LOBE_BINS).min(high_bin)
+        };
+
+        let search = match self.expected_frequency {
+            Some(frequency) => {
+                let low = frequency * (1.0 - EXPECTED_TOLERANCE) / self.bin_width;
+                let high = frequency * (1.0 + EXPECTED_TOLERANCE) / self.bin_width;
+                (low.floor() as usize).max(low_bin)..=(high.ceil() as usize).min(high_bin)
+            }
+            None => low_bin..=high_bin,
+        };
+        let peak_bin = search
+            .max_by(|&a, &b| powers[a].total_cmp(&powers[b]))
+            .unwrap_or(low_bin);
+        let fundamental_bins = lobe(peak_bin);
+        let fundamental: f64 = powers[fundamental_bins.clone()].iter().sum();
+        // the centroid of the power of the lobe, finer than a bin
+        let frequency = (fundamental_bins
+            .clone()
+            .map(|bin| bin as f64 * powers[bin])
+            .sum::<f64>()
+            / fundamental) as f32
+            * self.bin_width;
+
+        // the bins of the harmonics, then of the fundamental, the others being noise
+        let mut is_harmonic = vec![false; powers.len()];
+        for harmonic in 2..=HARMONICS {
+            let bin = (frequency * harmonic as f32 / self.bin_width).round() as usize;
+            if bin <= high_bin {
+                lobe(bin).for_each(|bin| is_harmonic[bin] = true);
+            }
+        }
+        fundamental_bins.for_each(|bin| is_harmonic[bin] = false);
+        let (mut harmonics, mut noise) = (0.0, 0.0);
+        for bin in (low_bin..=high_bin).filter(|bin| !lobe(peak_bin).contains(bin)) {
+            if is_harmonic[bin] {
+                harmonics += powers[bin];
+            } else {
+                noise += powers[bin];
+            }
+        }
+        let residual = harmonics + noise;
+        let total = fundamental + residual;
+
+        let level = 10.0 * (fundamental * self.power_scale as f64).log10() as f32;
+        let response = &mut self.responses[position];
+        if level.is_finite() {
+            let band = (3.0 * (frequency / 1000.0).log2()).round() as i32;
+            response.insert(band, level);
+        }
+        ToneAnalysis {
+            channel,
+            frequency,
+            level,
+            thd_n: 10.0 * (residual / total).log10() as f32,
+            thd_n_percent: 100.0 * (residual / total).sqrt() as f32,
+            thd: 10.0 * (harmonics / fundamental).log10() as f32,
+            snr: 10.0 * (fundamental / noise).log10() as f32,
+            frequency_response: response
+                .iter()
+                .map(|(&band, &level)| ResponsePoint {
+                    frequency: 1000.0 * 2f32.powf(band as f32 / 3.0),
+                    level,
+                })
+                .collect(),
+        }
+    }
+}
@@ -0,0 +1,222 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! The recordings directory as served over HTTP: listing of the recordings
+//! with their sizes and durations, and byte ranges of their files.
+
+use crate::report;
+use crate::unix_time;
+use serde::Serialize;
+use std::convert::TryInto;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// extensions of the files served, the recording formats and the loudness reports
+const EXTENSIONS: [&str; 7] = ["wav", "flac", "opus", "ogg", "oga", "mp3", "json"];
+
+/// A file of the recordings directory.
+#[derive(Clone, Debug, Serialize)]
+pub struct RecordingFile {
+    pub name: String,
+    /// bytes
+    pub size: u64,
+    /// time of the last modification, in seconds since the Unix epoch
+    pub modified: f64,
+    /// seconds, `null` if unknown
+    pub duration: Option<f64>,
+}
+
+/// Whether the file name is one of the files served: without path separators,
+/// not hidden and with any of the extensions.
+fn is_served(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && !name.contains(['/', '\\'])
+        && Path::new(name)
+            .extension()
+            .is_some_and(|extension| EXTENSIONS.iter().any(|served| extension == *served))
+}
+
+/// The files of the directory, the oldest first.
+pub fn list(directory: &Path) -> io::Result<Vec<RecordingFile>> {
+    let mut files: Vec<RecordingFile> = fs::read_dir(directory)?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let metadata = entry.metadata().ok()?;
+            if !metadata.is_file() || !is_served(&name) {
+                return None;
+            }
+            Some(RecordingFile {
+                duration: duration(&entry.path()),
+                name,
+                size: metadata.len(),
+                modified: metadata.modified().map_or(0.0, unix_time),
+            })
+        })
+        .collect();
+    files.sort_by(|a, b| a.modified.total_cmp(&b.modified).then(a.name.cmp(&b.name)));
+    Ok(files)
+}
+
+/// Path of a file of the directory from its percent-encoded name in an URL,
+/// `None` if it is not one of the files served.
+pub fn file_path(directory: &Path, encoded_name: &str) -> Option<PathBuf> {
+    let name = percent_decode(encoded_name)?;
+    if !is_served(&name) {
+        return None;
+    }
+    Some(directory.join(name))
+}
+
+fn percent_decode(encoded: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut iter = encoded.bytes();
+    while let Some(byte) = iter.next() {
+        if byte == b'%' {
+            let hex = [iter.next()?, iter.next()?];
+            bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            bytes.push(byte);
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Content type of a file served, from its extension.
+pub fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("wav") => "audio/wav",
+        Some("flac") => "audio/flac",
+        Some("opus" | "ogg" | "oga") => "audio/ogg",
+        Some("mp3") => "audio/mpeg",
+        Some("json") => "application/json",
+        _ => "application/octet-stream",
+    }
+}
+
+/// First and last byte of the single range of a `Range` header, e.g. `bytes=100-199`,
+/// `bytes=100-` or `bytes=-100`, `None` if it is not satisfiable.
+fn parse_range(range: &str, len: u64) -> Option<(u64, u64)> {
+    let (first, last) = range.trim().strip_prefix("bytes=")?.split_once('-')?;
+    let (first, last) = match (first.trim(), last.trim()) {
+        ("", suffix) => {
+            let suffix: u64 = suffix.parse().ok()?;
+            (len.checked_sub(suffix.min(len))?, len.checked_sub(1)?)
+        }
+        (first, "") => (first.parse().ok()?, len.checked_sub(1)?),
+        (first, last) => (
+            first.parse().ok()?,
+            last.parse::<u64>().ok()?.min(len.checked_sub(1)?),
+        ),
+    };
+    if first > last || first >= len {
+        return None;
+    }
+    Some((first, last))
+}
+
+/// Bytes of a file of `len` bytes to respond with for the `Range` header of a request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileRange {
+    /// without a `Range` header, all the file
+    Whole,
+    /// first and last byte of the range
+    Partial(u64, u64),
+    NotSatisfiable,
+}
+
+pub fn file_range(range: Option<&str>, len: u64) -> FileRange {
+    match range.map(|range| parse_range(range, len)) {
+        None => FileRange::Whole,
+        Some(Some((first, last))) => FileRange::Partial(first, last),
+        Some(None) => FileRange::NotSatisfiable,
+    }
+}
+
+/// Duration in seconds of a WAV or FLAC file from its header, or of any recording
+/// from its loudness report.
+fn duration(path: &Path) -> Option<f64> {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("wav") => wav_duration(path),
+        Some("flac") => flac_duration(path),
+        Some("json") => None,
+        _ => {
+            let report = fs::read_to_string(report::sidecar_path(path)).ok()?;
+            let report: serde_json::Value = serde_json::from_str(&report).ok()?;
+            report["duration"].as_f64()
+        }
+    }
+}
+
+/// Duration of the `data` chunk at the byte rate of the `fmt ` chunk, up to the end
+/// of the file while its chunk size is not written yet, or of a RF64 file.
+fn wav_duration(path: &Path) -> Option<f64> {
+    let file_len = fs::metadata(path).ok()?.len();
+    let mut header = Vec::new();
+    File::open(path)
+        .ok()?
+        .take(64 * 1024)
+        .read_to_end(&mut header)
+        .ok()?;
+    if !matches!(header.get(..4)?, b"RIFF" | b"RF64") || header.get(8..12)? != b"WAVE" {
+        return None;
+    }
+    let mut byte_rate = None;
+    let mut offset = 12;
+    while let Some(chunk) = header.get(offset..offset + 8) {
+        let chunk_len = u32::from_le_bytes(chunk[4..8].try_into().unwrap()) as u64;
+        let data_offset = offset as u64 + 8;
+        match &chunk[..4] {
+            b"fmt " => {
+                let byte_rate_bytes = header.get(offset + 16..offset + 20)?;
+                byte_rate = Some(u32::from_le_bytes(byte_rate_bytes.try_into().unwrap()));
+            }
+            b"data" => {
+                let available = file_len.saturating_sub(data_offset);
+                let data_len = if chunk_len == 0 || chunk_len > available {
+                    available
+                } else {
+                    chunk_len
+                };
+                return byte_rate
+                    .filter(|&byte_rate| byte_rate > 0)
+                    .map(|byte_rate| data_len as f64 / byte_rate as f64);
+            }
+            _ => {}
+        }
+        offset = (data_offset + chunk_len + chunk_len % 2) as usize;
+    }
+    None
+}
+
+/// Total samples and sample rate of the `STREAMINFO`, written once the recording is
+/// finalized.
+fn flac_duration(path: &Path) -> Option<f64> {
+    let mut header = [0; 26];
+    File::open(path).ok()?.read_exact(&mut header).ok()?;
+    if &header[..4] != b"fLaC" || header[4] & 0x7f != 0 {
+        return None;
+    }
+    let info = &header[18..26];
+    let sample_rate = (info[0] as u32) << 12 | (info[1] as u32) << 4 | (info[2] as u32) >> 4;
+    let total_samples =
+        ((info[3] & 0x0f) as u64) << 32 | u32::from_be_bytes(info[4..8].try_into().unwrap()) as u64;
+    if sample_rate == 0 || total_samples == 0 {
+        return None;
+    }
+    Some(total_samples as f64 / sample_rate as f64)
+}
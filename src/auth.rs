@@ -0,0 +1,91 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Authentication of the requests of the http server, with a bearer token or basic auth.
+
+use base64::Engine;
+
+/// Realm of the basic auth, shown by the browsers when asking for the credentials.
+pub const REALM: &str = "audio-in-stream-rs";
+/// Query param with the bearer token, for the clients that can not set headers,
+/// e.g. the `WebSocket` and `EventSource` of the browsers.
+pub const TOKEN_PARAM: &str = "access_token";
+
+#[derive(Clone)]
+pub enum Auth {
+    /// `Authorization: Bearer <token>`, or the [`TOKEN_PARAM`] query param
+    Token(String),
+    /// `Authorization: Basic <credentials>`, the base64 of `user:password` as sent
+    Basic(String),
+}
+
+impl Auth {
+    /// Parse `token:<secret>` or `basic:<user>:<password>`.
+    pub fn parse(auth: &str) -> Result<Self, String> {
+        match auth.split_once(':') {
+            Some(("token", token)) if !token.is_empty() => Ok(Auth::Token(token.to_owned())),
+            Some(("basic", user_password)) => match user_password.split_once(':') {
+                Some((user, password)) if !user.is_empty() && !password.is_empty() => Ok(
+                    Auth::Basic(base64::engine::general_purpose::STANDARD.encode(user_password)),
+                ),
+                _ => Err(String::from(
+                    "invalid basic auth, expected basic:<user>:<password>",
+                )),
+            },
+            _ => Err(format!(
+                "invalid auth '{}', expected token:<secret> or basic:<user>:<password>",
+                auth.split(':').next().unwrap_or_default()
+            )),
+        }
+    }
+
+    /// Whether the value of the `Authorization` header, or the token of the query param,
+    /// carries the credentials.
+    pub fn is_authorized(&self, authorization: Option<&str>, query_token: Option<&str>) -> bool {
+        let (scheme, expected) = match self {
+            Auth::Token(token) => {
+                if query_token.is_some_and(|query_token| constant_time_eq(query_token, token)) {
+                    return true;
+                }
+                ("Bearer", token)
+            }
+            Auth::Basic(credentials) => ("Basic", credentials),
+        };
+        authorization
+            .and_then(|value| value.trim().split_once(' '))
+            .is_some_and(|(value_scheme, value)| {
+                value_scheme.eq_ignore_ascii_case(scheme)
+                    && constant_time_eq(value.trim(), expected)
+            })
+    }
+
+    /// Value of the `WWW-Authenticate` header of the unauthorized responses.
+    pub fn challenge(&self) -> String {
+        match self {
+            Auth::Token(_) => format!("Bearer realm=\"{}\"", REALM),
+            Auth::Basic(_) => format!("Basic realm=\"{}\", charset=\"UTF-8\"", REALM),
+        }
+    }
+}
+
+/// Compare without leaking, in the time taken, how much of the secret matches.
+fn constant_time_eq(value: &str, secret: &str) -> bool {
+    value.len() == secret.len()
+        && value
+            .bytes()
+            .zip(secret.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
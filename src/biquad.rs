@@ -0,0 +1,92 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Second order IIR filters.
+
+/// Second order IIR filter, direct form I.
+#[derive(Clone)]
+pub struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    /// Coefficients normalized by `a0`: `b0, b1, b2` and `a1, a2`.
+    pub fn new(b: [f64; 3], a: [f64; 2]) -> Self {
+        Biquad {
+            b,
+            a,
+            x: [0.0; 2],
+            y: [0.0; 2],
+        }
+    }
+
+    /// Change the coefficients, keeping the state, as to sweep the frequency of a filter.
+    pub fn set_coefficients(&mut self, b: [f64; 3], a: [f64; 2]) {
+        self.b = b;
+        self.a = a;
+    }
+
+    /// Digital filter of an analog filter `(b0 s² + b1 s + b2) / (a0 s² + a1 s + a2)`,
+    /// by the bilinear transform.
+    pub fn bilinear(b: [f64; 3], a: [f64; 3], sample_rate: f64) -> Self {
+        let k = 2.0 * sample_rate;
+        let k2 = k * k;
+        let transform = |c: [f64; 3]| {
+            [
+                c[0] * k2 + c[1] * k + c[2],
+                2.0 * (c[2] - c[0] * k2),
+                c[0] * k2 - c[1] * k + c[2],
+            ]
+        };
+        let b = transform(b);
+        let a = transform(a);
+        Biquad::new(
+            [b[0] / a[0], b[1] / a[0], b[2] / a[0]],
+            [a[1] / a[0], a[2] / a[0]],
+        )
+    }
+
+    /// The same filter, with its output multiplied by `gain`.
+    pub fn with_gain(mut self, gain: f64) -> Self {
+        for b in &mut self.b {
+            *b *= gain;
+        }
+        self
+    }
+
+    /// Gain of the filter at the given frequency.
+    pub fn magnitude(&self, frequency: f64, sample_rate: f64) -> f64 {
+        let w = 2.0 * std::f64::consts::PI * frequency / sample_rate;
+        // evaluate the polynomials of z^-1 = e^-jw
+        let evaluate = |c: [f64; 3]| {
+            let re = c[0] + c[1] * w.cos() + c[2] * (2.0 * w).cos();
+            let im = -c[1] * w.sin() - c[2] * (2.0 * w).sin();
+            (re * re + im * im).sqrt()
+        };
+        evaluate(self.b) / evaluate([1.0, self.a[0], self.a[1]])
+    }
+
+    pub fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0]
+            - self.a[1] * self.y[1];
+        self.x = [x, self.x[0]];
+        self.y = [y, self.y[0]];
+        y
+    }
+}
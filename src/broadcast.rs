@@ -0,0 +1,99 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Fan-out of messages from the audio callback to any number of consumers,
+//! the tasks of the runtime or the blocking threads.
+
+use std::sync::{Arc, Mutex, PoisonError};
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+/// Number of messages queued for each subscriber before dropping new ones.
+pub const DEFAULT_CAPACITY: usize = 64;
+
+/// Fan-out of messages to any number of subscribers, each with a bounded queue.
+///
+/// Sending never blocks, nor needs the runtime: when a subscriber queue is full
+/// (a slow consumer) the message is dropped for that subscriber, and the subscribers
+/// that went away are removed on the next send. The tasks receive with `recv().await`,
+/// the blocking threads with `blocking_recv()`.
+pub struct Broadcast<T> {
+    senders: Arc<Mutex<Vec<mpsc::Sender<Arc<T>>>>>,
+}
+
+impl<T> Clone for Broadcast<T> {
+    fn clone(&self) -> Self {
+        Broadcast {
+            senders: Arc::clone(&self.senders),
+        }
+    }
+}
+
+impl<T> Default for Broadcast<T> {
+    fn default() -> Self {
+        Broadcast {
+            senders: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+}
+
+impl<T> Broadcast<T> {
+    pub fn subscribe(&self) -> mpsc::Receiver<Arc<T>> {
+        self.subscribe_with_capacity(DEFAULT_CAPACITY)
+    }
+
+    pub fn subscribe_with_capacity(&self, capacity: usize) -> mpsc::Receiver<Arc<T>> {
+        let (sender, receiver) = mpsc::channel(capacity);
+        self.senders
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(sender);
+        receiver
+    }
+
+    /// Send the message to all subscribers,
+    /// returns the number of subscribers it was dropped for.
+    pub fn send(&self, message: impl Into<Arc<T>>) -> usize {
+        let message = message.into();
+        let mut dropped = 0;
+        self.senders
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain(|sender| match sender.try_send(Arc::clone(&message)) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    dropped += 1;
+                    true
+                }
+                Err(TrySendError::Closed(_)) => false,
+            });
+        dropped
+    }
+
+    /// Remove all the subscribers, so they see the sender went away.
+    pub fn close(&self) {
+        self.senders
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+    }
+
+    pub fn has_subscribers(&self) -> bool {
+        !self
+            .senders
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .is_empty()
+    }
+}
@@ -0,0 +1,113 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Calibration of the levels in dB SPL, from the level in dBov of a known
+//! sound pressure level, either given or measured from a calibrator tone.
+
+use crate::{decibels_overload, ChannelData};
+
+/// dB SPL, tone of the usual acoustic calibrators (1 Pa)
+pub const CALIBRATOR_LEVEL: f32 = 94.0;
+/// seconds of calibrator tone measured
+const MEASUREMENT_DURATION: f32 = 5.0;
+
+/// Offset between the dBov and the dB SPL levels,
+/// unknown until the calibrator tone is measured.
+pub struct Calibration {
+    /// dB SPL of the reference level
+    spl: f32,
+    /// dB added to the dBov levels, `None` while measuring
+    offset: Option<f32>,
+    /// sum of the squares of the samples of each channel while measuring
+    square_sums: Vec<f64>,
+    num_samples: usize,
+    measurement_samples: usize,
+}
+
+impl Calibration {
+    /// Parse either `<dB-SPL>@<dBov>`, the level in dBov of a known sound pressure level,
+    /// e.g. `94@-20.5`, or only `<dB-SPL>`, the level of a calibrator tone
+    /// measured from the start of the capture on the loudest channel.
+    pub fn parse(calibration: &str, sample_rate: u32, num_channels: usize) -> Result<Self, String> {
+        let parse_db = |level: &str, unit: &str| {
+            match level.trim().parse::<f32>() {
+            Ok(level) if level.is_finite() => Ok(level),
+            _ => Err(format!(
+                "invalid calibration '{}', expected <dB-SPL>@<dBov> or <dB-SPL>, the level {} is not a number",
+                calibration, unit
+            )),
+        }
+        };
+
+        let (spl, offset) = match calibration.split_once('@') {
+            Some((spl, dbov)) => {
+                let spl = parse_db(spl, "in dB SPL")?;
+                (spl, Some(spl - parse_db(dbov, "in dBov")?))
+            }
+            None => (parse_db(calibration, "in dB SPL")?, None),
+        };
+        Ok(Calibration {
+            spl,
+            offset,
+            square_sums: vec![0.0; num_channels],
+            num_samples: 0,
+            measurement_samples: (MEASUREMENT_DURATION * sample_rate as f32) as usize,
+        })
+    }
+
+    /// dB to add to a level in dBov for dB SPL, `None` while measuring the calibrator tone.
+    pub fn offset(&self) -> Option<f32> {
+        self.offset
+    }
+
+    /// Level in dB SPL of a level in dBov, `None` while measuring the calibrator tone.
+    pub fn spl(&self, dbov: f32) -> Option<f32> {
+        self.offset.map(|offset| dbov + offset)
+    }
+
+    /// Measure the calibrator tone with an input buffer, until calibrated,
+    /// returns the measured level in dBov when done.
+    pub fn process(&mut self, channels: &[ChannelData]) -> Option<f32> {
+        if self.offset.is_some() {
+            return None;
+        }
+        for (channel, square_sum) in channels.iter().zip(&mut self.square_sums) {
+            let level = channel.loudness_level as f64;
+            *square_sum += level * level * channel.samples.len() as f64;
+        }
+        self.num_samples += channels.first().map_or(0, |channel| channel.samples.len());
+        if self.num_samples < self.measurement_samples {
+            return None;
+        }
+
+        let loudest = self.square_sums.iter().copied().fold(0.0, f64::max);
+        let dbov = decibels_overload((loudest / self.num_samples as f64).sqrt() as f32);
+        self.square_sums
+            .iter_mut()
+            .for_each(|square_sum| *square_sum = 0.0);
+        self.num_samples = 0;
+        if !dbov.is_finite() {
+            // no tone, measure again
+            return None;
+        }
+        self.offset = Some(self.spl - dbov);
+        Some(dbov)
+    }
+
+    /// dB SPL of the reference level.
+    pub fn reference_spl(&self) -> f32 {
+        self.spl
+    }
+}
@@ -0,0 +1,192 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Wiring faults between channels: dual mono (bit-identical channels),
+//! inverted copies of a channel, and dead channels, silent while others are active.
+
+use crate::{unix_time, ChannelData};
+use serde::Serialize;
+use std::time::{Duration, SystemTime};
+
+/// seconds
+pub const DEFAULT_DURATION: f32 = 5.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FaultKind {
+    /// two channels with the same samples
+    DualMono,
+    /// two channels with the same samples of opposite sign
+    Inverted,
+    /// a silent channel while other channels are active
+    Dead,
+}
+
+impl FaultKind {
+    pub fn description(self) -> &'static str {
+        match self {
+            FaultKind::DualMono => "dual mono",
+            FaultKind::Inverted => "inverted copies",
+            FaultKind::Dead => "dead",
+        }
+    }
+}
+
+/// A fault of one channel, or of two for dual mono and inverted channels,
+/// given by their indices in the input stream.
+#[derive(Clone, Debug, Serialize)]
+pub struct ChannelFault {
+    pub kind: FaultKind,
+    pub channels: Vec<usize>,
+    /// time since the fault is detected, in seconds since the Unix epoch
+    pub since: f64,
+}
+
+#[derive(Clone, Debug)]
+pub enum FaultEvent {
+    /// the fault is detected since the given time, for long enough to report it
+    Start { fault: ChannelFault },
+    /// the fault was detected since the given time, until the given time
+    End {
+        fault: ChannelFault,
+        until: SystemTime,
+    },
+}
+
+/// A possible fault, of the channels in the given positions.
+struct FaultState {
+    kind: FaultKind,
+    positions: Vec<usize>,
+    present_since: Option<SystemTime>,
+    reported: bool,
+}
+
+/// Detection of the faults of the metered channels, present for long enough.
+pub struct FaultDetector {
+    /// indices of the metered channels in the input stream
+    channels: Vec<usize>,
+    /// loudness level below which a channel is silent, root mean square of the samples
+    threshold: f32,
+    duration: Duration,
+    states: Vec<FaultState>,
+}
+
+impl FaultDetector {
+    /// Faults of the metered channels, given by their indices in the input stream,
+    /// silent below the loudness level `threshold`.
+    pub fn new(channels: &[usize], threshold: f32, duration: Duration) -> Self {
+        let mut states = Vec::new();
+        for first in 0..channels.len() {
+            for second in first + 1..channels.len() {
+                for kind in [FaultKind::DualMono, FaultKind::Inverted] {
+                    states.push(FaultState {
+                        kind,
+                        positions: vec![first, second],
+                        present_since: None,
+                        reported: false,
+                    });
+                }
+            }
+        }
+        // a single channel can not be dead while others are active
+        if channels.len() > 1 {
+            states.extend((0..channels.len()).map(|position| FaultState {
+                kind: FaultKind::Dead,
+                positions: vec![position],
+                present_since: None,
+                reported: false,
+            }));
+        }
+        FaultDetector {
+            channels: channels.to_vec(),
+            threshold,
+            duration,
+            states,
+        }
+    }
+
+    /// Update the faults with an input buffer captured at the given time,
+    /// returns the start and end of the faults.
+    pub fn process(&mut self, channels: &[ChannelData], timestamp: SystemTime) -> Vec<FaultEvent> {
+        let mut events = Vec::new();
+        let threshold = self.threshold;
+        let silent = |position: usize| channels[position].loudness_level < threshold;
+        for index in 0..self.states.len() {
+            let state = &self.states[index];
+            let present = match (state.kind, &state.positions[..]) {
+                (FaultKind::DualMono, &[first, second]) => {
+                    !silent(first) && channels[first].samples == channels[second].samples
+                }
+                (FaultKind::Inverted, &[first, second]) => {
+                    !silent(first)
+                        && channels[first]
+                            .samples
+                            .iter()
+                            .zip(&channels[second].samples)
+                            .all(|(&first, &second)| first == -second)
+                }
+                (FaultKind::Dead, &[position]) => {
+                    silent(position) && (0..channels.len()).any(|other| !silent(other))
+                }
+                _ => false,
+            };
+
+            let duration = self.duration;
+            let state = &mut self.states[index];
+            if present {
+                let since = *state.present_since.get_or_insert(timestamp);
+                let present_for = timestamp.duration_since(since).unwrap_or_default();
+                if !state.reported && present_for >= duration {
+                    state.reported = true;
+                    events.push(FaultEvent::Start {
+                        fault: self.fault(index),
+                    });
+                }
+            } else if state.present_since.is_some() {
+                if state.reported {
+                    let fault = self.fault(index);
+                    self.states[index].reported = false;
+                    events.push(FaultEvent::End {
+                        fault,
+                        until: timestamp,
+                    });
+                }
+                self.states[index].present_since = None;
+            }
+        }
+        events
+    }
+
+    /// The faults reported and not ended yet.
+    pub fn faults(&self) -> Vec<ChannelFault> {
+        (0..self.states.len())
+            .filter(|&index| self.states[index].reported)
+            .map(|index| self.fault(index))
+            .collect()
+    }
+
+    fn fault(&self, index: usize) -> ChannelFault {
+        let state = &self.states[index];
+        ChannelFault {
+            kind: state.kind,
+            channels: state
+                .positions
+                .iter()
+                .map(|&position| self.channels[position])
+                .collect(),
+            since: state.present_since.map_or(0.0, unix_time),
+        }
+    }
+}
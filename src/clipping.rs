@@ -0,0 +1,98 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Clipping detection: runs of consecutive samples at or near full scale.
+
+use crate::{unix_time, ChannelData};
+use serde::Serialize;
+use std::time::SystemTime;
+
+/// the highest 16-bit sample value, so any sample at full scale is clipped
+/// regardless of the sample format
+pub const DEFAULT_THRESHOLD: f32 = 32767.0 / 32768.0;
+pub const DEFAULT_CONSECUTIVE_SAMPLES: usize = 1;
+
+#[derive(Clone, Copy, Debug)]
+pub struct ClipConfig {
+    /// absolute sample value considered clipped
+    pub threshold: f32,
+    /// consecutive clipped samples that make a clip
+    pub consecutive_samples: usize,
+}
+
+impl Default for ClipConfig {
+    fn default() -> Self {
+        ClipConfig {
+            threshold: DEFAULT_THRESHOLD,
+            consecutive_samples: DEFAULT_CONSECUTIVE_SAMPLES,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct ChannelClipping {
+    /// clips since the start
+    pub count: u64,
+    /// capture time of the last clip, in seconds since the Unix epoch, `null` if none
+    pub last_clip: Option<f64>,
+}
+
+pub struct ClipDetector {
+    config: ClipConfig,
+    channels: Vec<ChannelClipping>,
+    /// clipped samples in a row at the end of the previous buffer, of each channel
+    run_lengths: Vec<usize>,
+}
+
+impl ClipDetector {
+    pub fn new(config: ClipConfig, num_channels: usize) -> Self {
+        ClipDetector {
+            config,
+            channels: vec![ChannelClipping::default(); num_channels],
+            run_lengths: vec![0; num_channels],
+        }
+    }
+
+    /// Count the clips of each channel in an input buffer,
+    /// a run of clipped samples counts once, even across buffers.
+    pub fn process(
+        &mut self,
+        channels: &[ChannelData],
+        timestamp: SystemTime,
+    ) -> &[ChannelClipping] {
+        let config = self.config;
+        for ((channel, clipping), run_length) in channels
+            .iter()
+            .zip(&mut self.channels)
+            .zip(&mut self.run_lengths)
+        {
+            let count = clipping.count;
+            for sample in &channel.samples {
+                if sample.abs() >= config.threshold {
+                    *run_length += 1;
+                    if *run_length == config.consecutive_samples {
+                        clipping.count += 1;
+                    }
+                } else {
+                    *run_length = 0;
+                }
+            }
+            if clipping.count > count {
+                clipping.last_clip = Some(unix_time(timestamp));
+            }
+        }
+        &self.channels
+    }
+}
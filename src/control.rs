@@ -0,0 +1,210 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Runtime controls of the capture, shared by the control api and the terminal interface:
+//! pausing the meter, starting and stopping the recording, the noise suppression of the streams,
+//! and the mute and gain of each channel.
+
+use crate::gain::{self, ChannelGains};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// A change of the controls, the JSON body of `POST /api/control`,
+/// e.g. `{"action": "gain", "channel": 0, "db": -6}`.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum ControlCommand {
+    PauseMeter,
+    ResumeMeter,
+    StartRecording,
+    StopRecording,
+    /// of the streams and the monitor output
+    EnableDenoise,
+    DisableDenoise,
+    /// of a channel, or all of them without one
+    Mute {
+        channel: Option<usize>,
+    },
+    Unmute {
+        channel: Option<usize>,
+    },
+    /// set the gain in dB of a channel, or all of them without one
+    Gain {
+        channel: Option<usize>,
+        db: f32,
+    },
+    /// add to the gain in dB of a channel, or all of them without one
+    AdjustGain {
+        channel: Option<usize>,
+        db: f32,
+    },
+}
+
+/// State of the controls, the JSON response of the control api.
+#[derive(Clone, Debug, Serialize)]
+pub struct ControlState {
+    pub meter_paused: bool,
+    /// `null` without a recording that can be started and stopped
+    pub recording: Option<bool>,
+    /// `null` without the noise suppression of `--denoise`
+    pub denoise: Option<bool>,
+    pub channels: Vec<ChannelControl>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ChannelControl {
+    pub channel: usize,
+    /// in decibels, kept while muted
+    pub gain: f32,
+    pub muted: bool,
+}
+
+/// The controls, cloned for each thread that reads or changes them.
+#[derive(Clone)]
+pub struct Controls {
+    meter_paused: Arc<AtomicBool>,
+    /// `None` without a recording that can be started and stopped
+    recording: Option<Arc<AtomicBool>>,
+    /// `None` without the noise suppression
+    denoise: Option<Arc<AtomicBool>>,
+    gains: ChannelGains,
+}
+
+impl Controls {
+    /// Controls of the gains of the channels, of a recording, started, if `can_record`,
+    /// and of the noise suppression, enabled, if `can_denoise`.
+    pub fn new(gains: ChannelGains, can_record: bool, can_denoise: bool) -> Self {
+        Controls {
+            meter_paused: Arc::new(AtomicBool::new(false)),
+            recording: if can_record {
+                Some(Arc::new(AtomicBool::new(true)))
+            } else {
+                None
+            },
+            denoise: if can_denoise {
+                Some(Arc::new(AtomicBool::new(true)))
+            } else {
+                None
+            },
+            gains,
+        }
+    }
+
+    pub fn is_meter_paused(&self) -> bool {
+        self.meter_paused.load(Ordering::Relaxed)
+    }
+
+    /// Whether the recording is started, `true` without a recording that can be stopped.
+    pub fn is_recording(&self) -> bool {
+        self.recording
+            .as_ref()
+            .is_none_or(|recording| recording.load(Ordering::Relaxed))
+    }
+
+    /// Whether the noise suppression is enabled, `false` without it.
+    pub fn is_denoising(&self) -> bool {
+        self.denoise
+            .as_ref()
+            .is_some_and(|denoise| denoise.load(Ordering::Relaxed))
+    }
+
+    pub fn gains(&self) -> &ChannelGains {
+        &self.gains
+    }
+
+    /// Change the controls, or the error of an invalid command.
+    pub fn apply(&self, command: ControlCommand) -> Result<(), String> {
+        match command {
+            ControlCommand::PauseMeter => self.meter_paused.store(true, Ordering::Relaxed),
+            ControlCommand::ResumeMeter => self.meter_paused.store(false, Ordering::Relaxed),
+            ControlCommand::StartRecording | ControlCommand::StopRecording => {
+                let recording = self.recording.as_ref().ok_or_else(|| {
+                    String::from(
+                        "the recording can only be started and stopped with --record-template",
+                    )
+                })?;
+                recording.store(command == ControlCommand::StartRecording, Ordering::Relaxed);
+            }
+            ControlCommand::EnableDenoise | ControlCommand::DisableDenoise => {
+                let denoise = self.denoise.as_ref().ok_or_else(|| {
+                    String::from(
+                        "the noise suppression can only be enabled and disabled with --denoise",
+                    )
+                })?;
+                denoise.store(command == ControlCommand::EnableDenoise, Ordering::Relaxed);
+            }
+            ControlCommand::Mute { channel } | ControlCommand::Unmute { channel } => {
+                let muted = matches!(command, ControlCommand::Mute { .. });
+                for channel in self.channels(channel)? {
+                    self.gains.set_muted(channel, muted);
+                }
+            }
+            ControlCommand::Gain { channel, db } | ControlCommand::AdjustGain { channel, db } => {
+                let channels = self.channels(channel)?;
+                for channel in channels {
+                    let gain = match command {
+                        ControlCommand::AdjustGain { .. } => self.gains.db(channel) + db,
+                        _ => db,
+                    };
+                    if !gain::is_valid_db(gain) {
+                        return Err(format!(
+                            "invalid gain {} dB, it must be between {} and +{} dB",
+                            gain,
+                            gain::MIN_DB,
+                            gain::MAX_DB
+                        ));
+                    }
+                    self.gains.set_db(channel, gain);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn state(&self) -> ControlState {
+        ControlState {
+            meter_paused: self.is_meter_paused(),
+            recording: self
+                .recording
+                .as_ref()
+                .map(|recording| recording.load(Ordering::Relaxed)),
+            denoise: self
+                .denoise
+                .as_ref()
+                .map(|denoise| denoise.load(Ordering::Relaxed)),
+            channels: (0..self.gains.num_channels())
+                .map(|channel| ChannelControl {
+                    channel,
+                    gain: self.gains.db(channel),
+                    muted: self.gains.is_muted(channel),
+                })
+                .collect(),
+        }
+    }
+
+    /// The channel, or all of them without one.
+    fn channels(&self, channel: Option<usize>) -> Result<std::ops::Range<usize>, String> {
+        match channel {
+            None => Ok(0..self.gains.num_channels()),
+            Some(channel) if channel < self.gains.num_channels() => Ok(channel..channel + 1),
+            Some(channel) => Err(format!(
+                "invalid channel {}, there are {} channel(s)",
+                channel,
+                self.gains.num_channels()
+            )),
+        }
+    }
+}
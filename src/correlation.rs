@@ -0,0 +1,186 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Phase correlation of stereo pairs, from +1 (in phase, mono) through 0 (unrelated)
+//! to -1 (out of phase), with an alarm while it stays strongly negative.
+
+use crate::{unix_time, ChannelData};
+use serde::Serialize;
+use std::time::{Duration, SystemTime};
+
+/// correlation below which a pair is out of phase
+pub const DEFAULT_ALARM_THRESHOLD: f32 = -0.5;
+/// seconds
+pub const DEFAULT_ALARM_DURATION: f32 = 5.0;
+
+/// seconds, time constant of the averages of the products of the samples
+const INTEGRATION_TIME: f64 = 0.3;
+/// mean square below which a channel is taken as silent, and the correlation as unknown
+const MIN_MEAN_SQUARE: f64 = 1e-12;
+
+#[derive(Clone, Copy, Debug)]
+pub struct CorrelationConfig {
+    /// correlation below which a pair is out of phase
+    pub threshold: f32,
+    /// time a pair has to be out of phase to raise the alarm
+    pub duration: Duration,
+}
+
+impl Default for CorrelationConfig {
+    fn default() -> Self {
+        CorrelationConfig {
+            threshold: DEFAULT_ALARM_THRESHOLD,
+            duration: Duration::from_secs_f32(DEFAULT_ALARM_DURATION),
+        }
+    }
+}
+
+/// Correlation of a stereo pair, given by the indices of its channels in the input stream.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct PairCorrelation {
+    pub left: usize,
+    pub right: usize,
+    /// `null` in JSON while any of the channels is silent
+    pub correlation: Option<f32>,
+    /// time since the alarm is raised, in seconds since the Unix epoch, `null` if not raised
+    pub alarm_since: Option<f64>,
+}
+
+#[derive(Clone, Debug)]
+pub enum CorrelationEvent {
+    /// the pair has been out of phase since the given time, for long enough to raise the alarm
+    AlarmStart {
+        pair: PairCorrelation,
+        since: SystemTime,
+    },
+    /// the pair was out of phase since the given time, until the given time
+    AlarmEnd {
+        pair: PairCorrelation,
+        since: SystemTime,
+        until: SystemTime,
+    },
+}
+
+#[derive(Clone, Default)]
+struct PairState {
+    /// averages of left * right, left² and right²
+    left_right: f64,
+    left_square: f64,
+    right_square: f64,
+    out_of_phase_since: Option<SystemTime>,
+    alarm: bool,
+}
+
+/// Correlation of each pair of consecutive metered channels, 0 and 1, 2 and 3, and so on.
+pub struct CorrelationMeter {
+    config: CorrelationConfig,
+    sample_rate: u32,
+    pairs: Vec<PairCorrelation>,
+    states: Vec<PairState>,
+}
+
+impl CorrelationMeter {
+    /// Pairs of the metered channels, given by their indices in the input stream,
+    /// a last channel without pair is not metered.
+    pub fn new(config: CorrelationConfig, sample_rate: u32, channels: &[usize]) -> Self {
+        let pairs: Vec<PairCorrelation> = channels
+            .chunks_exact(2)
+            .map(|pair| PairCorrelation {
+                left: pair[0],
+                right: pair[1],
+                correlation: None,
+                alarm_since: None,
+            })
+            .collect();
+        CorrelationMeter {
+            config,
+            sample_rate,
+            states: vec![PairState::default(); pairs.len()],
+            pairs,
+        }
+    }
+
+    /// Update the correlation of each pair with an input buffer captured at the given time,
+    /// returns the start and end of alarms.
+    pub fn process(
+        &mut self,
+        channels: &[ChannelData],
+        timestamp: SystemTime,
+    ) -> Vec<CorrelationEvent> {
+        let mut events = Vec::new();
+        let config = self.config;
+        for ((pair_channels, pair), state) in channels
+            .chunks_exact(2)
+            .zip(&mut self.pairs)
+            .zip(&mut self.states)
+        {
+            let (left, right) = (&pair_channels[0].samples, &pair_channels[1].samples);
+            if left.is_empty() {
+                continue;
+            }
+            let (mut left_right, mut left_square, mut right_square) = (0.0, 0.0, 0.0);
+            for (&left, &right) in left.iter().zip(right) {
+                let (left, right) = (left as f64, right as f64);
+                left_right += left * right;
+                left_square += left * left;
+                right_square += right * right;
+            }
+            let len = left.len() as f64;
+            let duration = len / self.sample_rate as f64;
+            let factor = 1.0 - (-duration / INTEGRATION_TIME).exp();
+            state.left_right += (left_right / len - state.left_right) * factor;
+            state.left_square += (left_square / len - state.left_square) * factor;
+            state.right_square += (right_square / len - state.right_square) * factor;
+
+            pair.correlation =
+                if state.left_square < MIN_MEAN_SQUARE || state.right_square < MIN_MEAN_SQUARE {
+                    None
+                } else {
+                    Some(
+                        (state.left_right / (state.left_square * state.right_square).sqrt())
+                            .clamp(-1.0, 1.0) as f32,
+                    )
+                };
+
+            if pair
+                .correlation
+                .is_some_and(|correlation| correlation < config.threshold)
+            {
+                let since = *state.out_of_phase_since.get_or_insert(timestamp);
+                let out_of_phase_for = timestamp.duration_since(since).unwrap_or_default();
+                if !state.alarm && out_of_phase_for >= config.duration {
+                    state.alarm = true;
+                    pair.alarm_since = Some(unix_time(since));
+                    events.push(CorrelationEvent::AlarmStart { pair: *pair, since });
+                }
+            } else if let Some(since) = state.out_of_phase_since.take() {
+                if state.alarm {
+                    state.alarm = false;
+                    pair.alarm_since = None;
+                    events.push(CorrelationEvent::AlarmEnd {
+                        pair: *pair,
+                        since,
+                        until: timestamp,
+                    });
+                }
+            }
+        }
+        events
+    }
+
+    pub fn pairs(&self) -> &[PairCorrelation] {
+        &self.pairs
+    }
+}
@@ -0,0 +1,83 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Cross-origin resource sharing (CORS) headers, for the browser dashboards
+//! hosted elsewhere to read the responses of the http server.
+//!
+//! WebSockets are not subject to CORS, the browsers connect them from any origin.
+
+/// Methods of the preflight requests.
+const ALLOW_METHODS: &str = "GET, HEAD, POST, DELETE, OPTIONS";
+/// Headers of the preflight requests, for the control api and the auth.
+const ALLOW_HEADERS: &str = "Authorization, Content-Type, Range";
+/// Seconds the browsers cache the preflight responses.
+const MAX_AGE: u32 = 600;
+
+/// The allowed origins, or any origin with `*`.
+#[derive(Clone, Debug)]
+pub struct Cors {
+    origins: Vec<String>,
+}
+
+impl Cors {
+    /// Origins as `scheme://host[:port]`, e.g. `https://dashboard.example.com`, or `*`.
+    pub fn new(origins: Vec<String>) -> Result<Self, String> {
+        for origin in &origins {
+            let is_valid = origin == "*"
+                || origin
+                    .strip_prefix("https://")
+                    .or_else(|| origin.strip_prefix("http://"))
+                    .is_some_and(|host| !host.is_empty() && !host.contains('/'));
+            if !is_valid {
+                return Err(format!(
+                    "invalid CORS origin '{}', expected scheme://host[:port] or *",
+                    origin
+                ));
+            }
+        }
+        Ok(Cors { origins })
+    }
+
+    /// Headers of the response to a request from the origin, none if not allowed.
+    pub fn headers(&self, origin: Option<&str>) -> Vec<(&'static str, String)> {
+        let origin = match origin {
+            Some(origin) => origin,
+            None => return Vec::new(),
+        };
+        if self.origins.iter().any(|allowed| allowed == origin) {
+            // with the credentials of the auth, which are never sent to `*`
+            vec![
+                ("Access-Control-Allow-Origin", origin.to_owned()),
+                ("Access-Control-Allow-Credentials", String::from("true")),
+                ("Vary", String::from("Origin")),
+            ]
+        } else if self.origins.iter().any(|allowed| allowed == "*") {
+            vec![("Access-Control-Allow-Origin", String::from("*"))]
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Headers of the response to a preflight `OPTIONS` request from the origin.
+    pub fn preflight_headers(&self, origin: Option<&str>) -> Vec<(&'static str, String)> {
+        let mut headers = self.headers(origin);
+        if !headers.is_empty() {
+            headers.push(("Access-Control-Allow-Methods", String::from(ALLOW_METHODS)));
+            headers.push(("Access-Control-Allow-Headers", String::from(ALLOW_HEADERS)));
+            headers.push(("Access-Control-Max-Age", MAX_AGE.to_string()));
+        }
+        headers
+    }
+}
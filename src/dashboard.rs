@@ -0,0 +1,55 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! The web dashboard: live meters with peak hold, the loudness, a scrolling history
+//! of the levels and the clip and silence indicators, fed by the WebSocket of the levels,
+//! and the page to listen to the live input, played from the WebSocket of the samples.
+//!
+//! Their assets are embedded in the binary.
+
+/// The page, also served at `/info`.
+pub const PAGE_PATH: &str = "/";
+const HTML: &str = "text/html; charset=UTF-8";
+
+/// Path, content type and content of each asset.
+const ASSETS: [(&str, &str, &[u8]); 4] = [
+    (PAGE_PATH, HTML, include_bytes!("dashboard/index.html")),
+    ("/listen", HTML, include_bytes!("dashboard/listen.html")),
+    (
+        "/dashboard.js",
+        "text/javascript; charset=UTF-8",
+        include_bytes!("dashboard/dashboard.js"),
+    ),
+    (
+        "/dashboard.css",
+        "text/css; charset=UTF-8",
+        include_bytes!("dashboard/dashboard.css"),
+    ),
+];
+
+/// Content type and content of the asset at the path.
+pub fn asset(path: &str) -> Option<(&'static str, &'static [u8])> {
+    let path = if path == "/info" { PAGE_PATH } else { path };
+    ASSETS
+        .iter()
+        .find(|(asset_path, _, _)| *asset_path == path)
+        .map(|&(_, content_type, content)| (content_type, content))
+}
+
+/// Whether the path is of the script or the style of the pages, served without auth
+/// as they have nothing of the input, for the pages loaded with the token in their url.
+pub fn is_static_asset(path: &str) -> bool {
+    asset(path).is_some_and(|(content_type, _)| content_type != HTML)
+}
@@ -0,0 +1,214 @@
+// live levels of the /ws/levels WebSocket, or of the /events Server-Sent Events
+// where WebSockets are not available
+
+// bottom of the meters and of the history chart, the zones are at -18 and -6 dBFS
+// as in the terminal meter
+const MeterBottomDecibels = -60;
+// seconds the peak is held before falling
+const PeakHoldSeconds = 2;
+// fall of the held peak, in dB per second
+const PeakFallRate = 20;
+// seconds the clip indicator stays lit after the last clip
+const ClipHoldSeconds = 3;
+// seconds of the history chart
+const HistorySeconds = 60;
+const ChannelColors = ['#3c3', '#39f', '#f93', '#c6f', '#3cc', '#fc3', '#f66', '#9c6'];
+
+const status = document.getElementById('status');
+const channels = document.getElementById('channels');
+const history_canvas = document.getElementById('history');
+const legend = document.getElementById('legend');
+const pairs = document.getElementById('pairs');
+const faults = document.getElementById('faults');
+
+// held peak of each channel, and the levels of each channel over time for the chart
+const peaks = [];
+const history = [];
+
+// levels are null for silence (-inf), loudness values until there is enough samples
+function decibels(value) {
+    return value === null ? -Infinity : value;
+}
+
+function format_decibels(value) {
+    return value === null ? '-inf' : value.toFixed(1);
+}
+
+// position of the level in the meters, from 0 to 1
+function meter_position(level) {
+    return Math.min(Math.max(1 - level / MeterBottomDecibels, 0), 1);
+}
+
+function channel_element(channel_index) {
+    while (channels.children.length <= channel_index) {
+        const channel = document.createElement('div');
+        channel.className = 'channel';
+        channel.innerHTML = '<span class="name"></span>' +
+            '<div class="meter"><div class="bar"><div class="zones"></div></div><div class="peak"></div></div>' +
+            '<span class="readout"></span>' +
+            '<span class="flag clip">CLIP</span><span class="flag silent">SILENT</span>';
+        channels.appendChild(channel);
+    }
+    return channels.children[channel_index];
+}
+
+function update_peak(channel_index, true_peak, timestamp) {
+    const peak = peaks[channel_index];
+    if (peak === undefined || true_peak >= peak.level) {
+        peaks[channel_index] = { level: true_peak, time: timestamp };
+    } else if (timestamp - peak.time > PeakHoldSeconds) {
+        peak.level = Math.max(peak.level - PeakFallRate * (timestamp - peak.time - PeakHoldSeconds),
+            true_peak);
+        peak.time = timestamp - PeakHoldSeconds;
+    }
+    return peaks[channel_index].level;
+}
+
+function update(levels) {
+    status.textContent = new Date(levels.timestamp * 1000).toISOString() +
+        ', ' + levels.channel_count + ' channel(s), ' + levels.sample_format +
+        (levels.weighting === 'Z' ? '' : ', ' + levels.weighting + '-weighted levels');
+    document.getElementById('momentary').textContent = format_decibels(levels.loudness.momentary);
+    document.getElementById('short_term').textContent = format_decibels(levels.loudness.short_term);
+    document.getElementById('integrated').textContent = format_decibels(levels.loudness.integrated);
+
+    while (channels.children.length > levels.channels.length) {
+        channels.removeChild(channels.lastChild);
+    }
+    peaks.length = Math.min(peaks.length, levels.channels.length);
+    levels.channels.forEach(function (channel, channel_index) {
+        const dbov = decibels(channel.dbov);
+        const peak = update_peak(channel_index, decibels(channel.true_peak), levels.timestamp);
+        const element = channel_element(channel_index);
+        // index of the channel in the input, only some of them are metered with --channels-map
+        element.querySelector('.name').textContent = 'ch ' + channel.channel;
+        const bar = element.querySelector('.bar');
+        bar.style.width = (100 * meter_position(dbov)) + '%';
+        // the zones span the whole meter, whatever the width of the bar
+        bar.firstChild.style.width = (bar.parentNode.clientWidth) + 'px';
+        element.querySelector('.peak').style.left = 'calc(' + (100 * meter_position(peak)) + '% - 2px)';
+        element.querySelector('.readout').textContent = format_decibels(channel.dbov) + ' dBov ' +
+            (peak === -Infinity ? '-inf' : peak.toFixed(1)) + ' dBTP' +
+            (channel.muted ? ' MUTE' : '');
+        const last_clip = channel.clipping.last_clip;
+        element.querySelector('.clip').classList.toggle('on',
+            last_clip !== null && levels.timestamp - last_clip < ClipHoldSeconds);
+        element.querySelector('.clip').title = channel.clipping.count + ' clips';
+        element.querySelector('.silent').classList.toggle('on', channel.silent_since !== null);
+    });
+
+    history.push({
+        timestamp: levels.timestamp,
+        levels: levels.channels.map(function (channel) { return decibels(channel.dbov); }),
+    });
+    while (history.length > 0 && levels.timestamp - history[0].timestamp > HistorySeconds) {
+        history.shift();
+    }
+    draw_history(levels.channels);
+
+    // stereo pairs, the mid/side levels only with --ms
+    const mid_side = levels.mid_side || [];
+    pairs.textContent = (levels.correlation || []).map(function (pair, pair_index) {
+        const line = 'channels ' + pair.left + '-' + pair.right + ' correlation: ' +
+            (pair.correlation === null ? '-' : pair.correlation.toFixed(2)) +
+            (pair.alarm_since === null ? '' : ' OUT OF PHASE');
+        const pair_levels = mid_side[pair_index];
+        return pair_levels === undefined ? line : line + ', mid: ' + format_decibels(pair_levels.mid) +
+            ' dBov, side: ' + format_decibels(pair_levels.side) +
+            ' dBov, S/M: ' + format_decibels(pair_levels.side_to_mid) + ' dB';
+    }).join('\n');
+    faults.textContent = levels.faults.map(function (fault) {
+        return 'FAULT: ' + fault.kind.replace('_', ' ') + ', channel(s) ' + fault.channels.join(' and ') +
+            ' since ' + new Date(fault.since * 1000).toISOString();
+    }).join('\n');
+}
+
+// the levels of the last seconds, scrolling to the left
+function draw_history(level_channels) {
+    const width = history_canvas.clientWidth;
+    const height = history_canvas.height;
+    if (history_canvas.width !== width) {
+        history_canvas.width = width;
+    }
+    const context = history_canvas.getContext('2d');
+    context.clearRect(0, 0, width, height);
+
+    // grid every 12 dB
+    context.strokeStyle = '#444';
+    context.fillStyle = '#888';
+    context.font = '10px monospace';
+    for (let level = 0; level > MeterBottomDecibels; level -= 12) {
+        const y = height * (1 - meter_position(level));
+        context.beginPath();
+        context.moveTo(0, y);
+        context.lineTo(width, y);
+        context.stroke();
+        context.fillText(level + ' dB', 2, y + 10);
+    }
+
+    const now = history[history.length - 1].timestamp;
+    level_channels.forEach(function (channel, channel_index) {
+        context.strokeStyle = ChannelColors[channel_index % ChannelColors.length];
+        context.beginPath();
+        history.forEach(function (point, point_index) {
+            const x = width * (1 - (now - point.timestamp) / HistorySeconds);
+            const level = point.levels[channel_index];
+            const y = height * (1 - meter_position(level === undefined ? -Infinity : level));
+            if (point_index === 0) {
+                context.moveTo(x, y);
+            } else {
+                context.lineTo(x, y);
+            }
+        });
+        context.stroke();
+    });
+
+    if (legend.children.length !== level_channels.length) {
+        legend.innerHTML = '';
+        level_channels.forEach(function (channel, channel_index) {
+            const item = document.createElement('span');
+            item.style.color = ChannelColors[channel_index % ChannelColors.length];
+            item.textContent = 'ch ' + channel.channel;
+            legend.appendChild(item);
+        });
+    }
+}
+
+// the access_token query param of the page, with --auth token:<secret>
+function endpoint_url(path) {
+    const token = new URLSearchParams(location.search).get('access_token');
+    return path + (token ? '?access_token=' + encodeURIComponent(token) : '');
+}
+
+function disconnected(reconnect) {
+    status.textContent = 'disconnected, reconnecting...';
+    setTimeout(reconnect, 1000);
+}
+
+function connect_events() {
+    const events = new EventSource(endpoint_url('/events'));
+    events.onmessage = function (event) {
+        update(JSON.parse(event.data));
+    };
+    events.onerror = function () {
+        events.close();
+        disconnected(connect_events);
+    };
+}
+
+function connect() {
+    if (typeof WebSocket === 'undefined') {
+        connect_events();
+        return;
+    }
+    const protocol = location.protocol === 'https:' ? 'wss:' : 'ws:';
+    const socket = new WebSocket(protocol + '//' + location.host + endpoint_url('/ws/levels'));
+    socket.onmessage = function (event) {
+        update(JSON.parse(event.data));
+    };
+    socket.onclose = function () {
+        disconnected(connect);
+    };
+}
+
+connect();
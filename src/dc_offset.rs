@@ -0,0 +1,84 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! DC offset: measurement of the mean of the samples of each channel,
+//! and a DC-blocking high-pass filter to remove it.
+
+use crate::ChannelData;
+
+/// dBFS, absolute DC offset considered significant
+pub const DEFAULT_THRESHOLD: f32 = -40.0;
+/// Hz, cutoff frequency of the DC-blocking filter
+pub const DEFAULT_CUTOFF: f32 = 5.0;
+
+/// seconds, time constant of the average of the samples,
+/// long enough not to take low frequencies for a DC offset
+const TIME_CONSTANT: f32 = 1.0;
+
+/// Mean of the samples of each channel, averaged over time.
+pub struct DcOffsetMeter {
+    sample_rate: u32,
+    offsets: Vec<f32>,
+}
+
+impl DcOffsetMeter {
+    pub fn new(sample_rate: u32, num_channels: usize) -> Self {
+        DcOffsetMeter {
+            sample_rate,
+            offsets: vec![0.0; num_channels],
+        }
+    }
+
+    /// Update the DC offset of each channel with an input buffer,
+    /// returns the offsets as linear values, with their sign.
+    pub fn process(&mut self, channels: &[ChannelData]) -> &[f32] {
+        for (channel, offset) in channels.iter().zip(&mut self.offsets) {
+            if channel.samples.is_empty() {
+                continue;
+            }
+            let mean = channel.samples.iter().sum::<f32>() / channel.samples.len() as f32;
+            let duration = channel.samples.len() as f32 / self.sample_rate as f32;
+            *offset += (mean - *offset) * (1.0 - (-duration / TIME_CONSTANT).exp());
+        }
+        &self.offsets
+    }
+}
+
+/// One pole high-pass filter of each channel, removing the DC offset:
+/// `y[n] = x[n] - x[n-1] + r * y[n-1]`
+#[derive(Clone)]
+pub struct DcBlocker {
+    r: f32,
+    /// previous input and output of each channel
+    states: Vec<(f32, f32)>,
+}
+
+impl DcBlocker {
+    pub fn new(cutoff: f32, sample_rate: u32, num_channels: usize) -> Self {
+        DcBlocker {
+            r: (-2.0 * std::f32::consts::PI * cutoff / sample_rate as f32).exp(),
+            states: vec![(0.0, 0.0); num_channels],
+        }
+    }
+
+    /// Filter the next sample of the channel.
+    pub fn process(&mut self, channel: usize, sample: f32) -> f32 {
+        let (previous_input, previous_output) = &mut self.states[channel];
+        let output = sample - *previous_input + self.r * *previous_output;
+        *previous_input = sample;
+        *previous_output = output;
+        output
+    }
+}
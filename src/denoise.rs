@@ -0,0 +1,145 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Noise suppression of a stream of interleaved samples, with the recurrent neural network
+//! of RNNoise ported to Rust by nnnoiseless, for listening to the streams of a noisy room.
+
+use nnnoiseless::DenoiseState;
+use std::collections::VecDeque;
+
+/// the only sample rate of RNNoise
+pub const SAMPLE_RATE: u32 = 48000;
+/// frames of each block processed by RNNoise, 10 ms
+const FRAME_SIZE: usize = DenoiseState::FRAME_SIZE;
+/// RNNoise takes the samples in the range of 16-bit integers
+const SCALE: f32 = 32768.0;
+
+/// Suppresses the noise of each channel, delaying the samples by a block of RNNoise.
+pub struct Denoiser {
+    /// of RNNoise, for each channel
+    states: Vec<Box<DenoiseState<'static>>>,
+    /// samples of the current block of each channel
+    blocks: Vec<Vec<f32>>,
+    /// interleaved samples processed, not yet output
+    output: VecDeque<f32>,
+    enabled: bool,
+    denoised: Vec<f32>,
+}
+
+impl Denoiser {
+    pub fn new(num_channels: usize) -> Self {
+        Denoiser {
+            // the default model
+            states: (0..num_channels).map(|_| DenoiseState::new()).collect(),
+            blocks: vec![Vec::with_capacity(FRAME_SIZE); num_channels],
+            output: VecDeque::with_capacity(2 * FRAME_SIZE * num_channels),
+            enabled: false,
+            denoised: vec![0.0; FRAME_SIZE],
+        }
+    }
+    /// Suppress the noise of a buffer of interleaved samples in place, or leave them
+    /// as they are if not `enabled`.
+    pub fn process(&mut self, samples: &mut [f32], enabled: bool) {
+        let num_channels = self.states.len();
+        if enabled != self.enabled {
+            self.enabled = enabled;
+            for block in &mut self.blocks {
+                block.clear();
+            }
+            // the delay of the block
+            self.output.clear();
+            if enabled {
+                self.output.resize(FRAME_SIZE * num_channels, 0.0);
+            }
+        }
+        if !enabled {
+            return;
+        }
+
+        for frame in samples.chunks_exact_mut(num_channels) {
+            for (block, &sample) in self.blocks.iter_mut().zip(frame.iter()) {
+                block.push(sample * SCALE);
+            }
+            if self.blocks[0].len() == FRAME_SIZE {
+                let start = self.output.len();
+                self.output.resize(start + FRAME_SIZE * num_channels, 0.0);
+                for (channel, (state, block)) in
+                    self.states.iter_mut().zip(&mut self.blocks).enumerate()
+                {
+                    state.process_frame(&mut self.denoised, block);
+                    for (index, &sample) in self.denoised.iter().enumerate() {
+                        self.output[start + index * num_channels + channel] = sample / SCALE;
+                    }
+                    block.clear();
+                }
+            }
+            for sample in frame {
+                *sample = self.output.pop_front().unwrap_or_default();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pseudo-random white noise of the amplitude.
+    fn noise(len: usize, amplitude: f32) -> Vec<f32> {
+        let mut state = 1_u32;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                amplitude * ((state >> 8) as f32 / (1 << 24) as f32 * 2.0 - 1.0)
+            })
+            .collect()
+    }
+
+    fn energy(samples: &[f32]) -> f32 {
+        samples.iter().map(|sample| sample * sample).sum()
+    }
+
+    #[test]
+    fn disabled_leaves_the_samples() {
+        let mut denoiser = Denoiser::new(2);
+        let input = noise(4 * FRAME_SIZE, 0.1);
+        let mut samples = input.clone();
+        denoiser.process(&mut samples, false);
+        assert_eq!(samples, input);
+    }
+
+    #[test]
+    fn delays_by_a_block() {
+        let mut denoiser = Denoiser::new(2);
+        let mut samples = noise(2 * FRAME_SIZE, 0.1);
+        denoiser.process(&mut samples, true);
+        // a block of frames of both channels
+        assert!(samples[..2 * FRAME_SIZE]
+            .iter()
+            .all(|&sample| sample == 0.0));
+    }
+
+    #[test]
+    fn attenuates_white_noise() {
+        let mut denoiser = Denoiser::new(1);
+        let input = noise(SAMPLE_RATE as usize * 5, 0.01);
+        let mut samples = input.clone();
+        denoiser.process(&mut samples, true);
+        // the last second, once the network adapted
+        let last_second = SAMPLE_RATE as usize * 4..;
+        let ratio = energy(&samples[last_second.clone()]) / energy(&input[last_second]);
+        assert!(ratio < 0.7, "{}", ratio);
+    }
+}
@@ -0,0 +1,637 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Detection of DTMF digits and call progress tones, to log the digits dialed and the
+//! busy tones on the return of a telephone hybrid.
+//!
+//! The power of each tone is measured with the Goertzel algorithm in blocks of 25.6 ms,
+//! the 205 samples at 8 kHz of the usual DTMF decoders. A digit is a row and a column
+//! tone, both loud enough, close in level and most of the power of the block, held for
+//! two blocks. The call progress tones of the North American precise tone plan and the
+//! 425 Hz tones of the ITU-T E.180 plan are told apart by their cadence.
+
+use crate::ChannelData;
+use std::f32::consts::PI;
+use std::time::{Duration, SystemTime};
+
+/// seconds of each block
+const BLOCK_DURATION: f32 = 205.0 / 8000.0;
+/// Hz, the DTMF rows and columns
+const ROWS: [f32; 4] = [697.0, 770.0, 852.0, 941.0];
+const COLUMNS: [f32; 4] = [1209.0, 1336.0, 1477.0, 1633.0];
+const DIGITS: [[char; 4]; 4] = [
+    ['1', '2', '3', 'A'],
+    ['4', '5', '6', 'B'],
+    ['7', '8', '9', 'C'],
+    ['*', '0', '#', 'D'],
+];
+/// amplitude of the quietest tone, -40 dBFS
+const MIN_AMPLITUDE: f32 = 0.01;
+/// share of the power of the block in the tones
+const MIN_PURITY: f32 = 0.8;
+/// dB, difference of level of the row and column tones
+const MAX_TWIST: f32 = 8.0;
+/// blocks a digit is held, or absent, to start or end it
+const DIGIT_BLOCKS: usize = 2;
+/// seconds off ending the cadence of a call progress tone, over the 4 s of a ringback
+const MAX_OFF: f32 = 4.5;
+/// seconds a call progress tone is absent before it can be detected again
+const TONE_RESET: f32 = 5.0;
+
+/// A call progress tone.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CallProgressTone {
+    /// continuous
+    Dial,
+    /// 2 s on, 4 s off, or 1 s on, 4 s off at 425 Hz
+    Ringback,
+    /// 0.5 s on, 0.5 s off
+    Busy,
+    /// 0.25 s on, 0.25 s off, the fast busy or congestion
+    Reorder,
+}
+
+impl CallProgressTone {
+    pub fn name(self) -> &'static str {
+        match self {
+            CallProgressTone::Dial => "dial",
+            CallProgressTone::Ringback => "ringback",
+            CallProgressTone::Busy => "busy",
+            CallProgressTone::Reorder => "reorder",
+        }
+    }
+}
+
+/// Frequencies of a call progress tone, and the cadences they are.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ToneFrequencies {
+    /// 350 + 440 Hz
+    Dial,
+    /// 440 + 480 Hz
+    Ringback,
+    /// 480 + 620 Hz, busy or reorder
+    Busy,
+    /// 425 Hz, all the tones of the E.180 plan
+    Single,
+}
+
+impl ToneFrequencies {
+    const ALL: [ToneFrequencies; 4] = [
+        ToneFrequencies::Dial,
+        ToneFrequencies::Ringback,
+        ToneFrequencies::Busy,
+        ToneFrequencies::Single,
+    ];
+
+    fn frequencies(self) -> &'static [f32] {
+        match self {
+            ToneFrequencies::Dial => &[350.0, 440.0],
+            ToneFrequencies::Ringback => &[440.0, 480.0],
+            ToneFrequencies::Busy => &[480.0, 620.0],
+            ToneFrequencies::Single => &[425.0],
+        }
+    }
+
+    /// the tone of a segment on for `on` seconds then off for `off` seconds,
+    /// `off` is `None` while still on
+    fn cadence(self, on: f32, off: Option<f32>) -> Option<CallProgressTone> {
+        let within = |value: f32, low: f32, high: f32| value >= low && value <= high;
+        match (self, off) {
+            (ToneFrequencies::Dial, None) if on >= 1.0 => Some(CallProgressTone::Dial),
+            (ToneFrequencies::Ringback, Some(off)) if within(on, 1.5, 2.5) && off >= 3.0 => {
+                Some(CallProgressTone::Ringback)
+            }
+            (ToneFrequencies::Busy, Some(off)) | (ToneFrequencies::Single, Some(off))
+                if within(on, 0.35, 0.65) && within(off, 0.35, 0.65) =>
+            {
+                Some(CallProgressTone::Busy)
+            }
+            (ToneFrequencies::Busy, Some(off)) | (ToneFrequencies::Single, Some(off))
+                if within(on, 0.15, 0.35) && within(off, 0.15, 0.35) =>
+            {
+                Some(CallProgressTone::Reorder)
+            }
+            (ToneFrequencies::Single, None) if on >= 3.0 => Some(CallProgressTone::Dial),
+            (ToneFrequencies::Single, Some(off)) if within(on, 0.7, 1.3) && off >= 3.0 => {
+                Some(CallProgressTone::Ringback)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum ToneEvent {
+    /// a digit held from `since` until `until`
+    Digit {
+        channel: usize,
+        digit: char,
+        since: SystemTime,
+        until: SystemTime,
+    },
+    /// a call progress tone, since the start of its cadence
+    CallProgress {
+        channel: usize,
+        tone: CallProgressTone,
+        since: SystemTime,
+    },
+}
+
+/// Power of a frequency over a block of samples.
+struct Goertzel {
+    coefficient: f32,
+}
+
+impl Goertzel {
+    fn new(frequency: f32, sample_rate: f32) -> Self {
+        Goertzel {
+            coefficient: 2.0 * (2.0 * PI * frequency / sample_rate).cos(),
+        }
+    }
+
+    /// amplitude of the frequency in the samples
+    fn amplitude(&self, samples: &[f32]) -> f32 {
+        let (mut previous, mut before) = (0.0, 0.0);
+        for &sample in samples {
+            let current = sample + self.coefficient * previous - before;
+            before = previous;
+            previous = current;
+        }
+        let power = previous * previous + before * before - self.coefficient * previous * before;
+        2.0 * power.max(0.0).sqrt() / samples.len() as f32
+    }
+}
+
+/// A run of blocks with the frequencies of a call progress tone.
+#[derive(Clone, Copy)]
+struct ToneSegment {
+    frequencies: ToneFrequencies,
+    since: SystemTime,
+    /// blocks on, then off
+    on: usize,
+    off: usize,
+}
+
+#[derive(Clone)]
+struct ChannelTones {
+    /// samples of the current block
+    block: Vec<f32>,
+    /// the digit of the previous blocks and since when, with the blocks it was present
+    /// and absent
+    digit: Option<(char, SystemTime, usize, usize)>,
+    segment: Option<ToneSegment>,
+    /// the tone detected, and the blocks without any since
+    tone: Option<CallProgressTone>,
+    silent_blocks: usize,
+}
+
+pub struct ToneDetector {
+    sample_rate: f32,
+    block_size: usize,
+    rows: Vec<Goertzel>,
+    columns: Vec<Goertzel>,
+    /// of each frequency of each call progress tone
+    tones: Vec<Vec<Goertzel>>,
+    channels: Vec<ChannelTones>,
+}
+
+impl ToneDetector {
+    pub fn new(sample_rate: u32, num_channels: usize) -> Self {
+        let rate = sample_rate as f32;
+        ToneDetector {
+            sample_rate: rate,
+            block_size: (BLOCK_DURATION * rate).round() as usize,
+            rows: ROWS.iter().map(|&row| Goertzel::new(row, rate)).collect(),
+            columns: COLUMNS
+                .iter()
+                .map(|&column| Goertzel::new(column, rate))
+                .collect(),
+            tones: ToneFrequencies::ALL
+                .iter()
+                .map(|tone| {
+                    tone.frequencies()
+                        .iter()
+                        .map(|&frequency| Goertzel::new(frequency, rate))
+                        .collect()
+                })
+                .collect(),
+            channels: vec![
+                ChannelTones {
+                    block: Vec::new(),
+                    digit: None,
+                    segment: None,
+                    tone: None,
+                    silent_blocks: 0,
+                };
+                num_channels
+            ],
+        }
+    }
+
+    /// Process the samples of an input buffer captured at the given time,
+    /// returns the digits ended and the call progress tones detected.
+    pub fn process(&mut self, channels: &[ChannelData], timestamp: SystemTime) -> Vec<ToneEvent> {
+        let mut events = Vec::new();
+        for (position, channel) in channels.iter().enumerate() {
+            for (index, &sample) in channel.samples.iter().enumerate() {
+                self.channels[position].block.push(sample);
+                if self.channels[position].block.len() < self.block_size {
+                    continue;
+                }
+                // the time of the first sample of the block
+                let start = (index + 1) as f32 - self.block_size as f32;
+                let time = if start >= 0.0 {
+                    timestamp + Duration::from_secs_f32(start / self.sample_rate)
+                } else {
+                    timestamp - Duration::from_secs_f32(-start / self.sample_rate)
+                };
+                self.process_block(position, channel.index, time, &mut events);
+                self.channels[position].block.clear();
+            }
+        }
+        events
+    }
+
+    fn process_block(
+        &mut self,
+        position: usize,
+        channel: usize,
+        time: SystemTime,
+        events: &mut Vec<ToneEvent>,
+    ) {
+        let block_duration = self.block_size as f32 / self.sample_rate;
+        let block = &self.channels[position].block;
+        // amplitude of a sine of the power of the block
+        let amplitude = (2.0 * block.iter().map(|sample| sample * sample).sum::<f32>()
+            / block.len() as f32)
+            .sqrt();
+        // the tones are most of the power of the block
+        let is_pure = |amplitudes: &[f32]| {
+            amplitudes.iter().all(|&tone| tone >= MIN_AMPLITUDE)
+                && amplitudes.iter().map(|tone| tone * tone).sum::<f32>()
+                    >= MIN_PURITY * amplitude * amplitude
+        };
+
+        let strongest = |goertzels: &[Goertzel]| {
+            let amplitudes: Vec<f32> = goertzels
+                .iter()
+                .map(|goertzel| goertzel.amplitude(block))
+                .collect();
+            let (index, &max) = amplitudes
+                .iter()
+                .enumerate()
+                .max_by(|a, b| a.1.total_cmp(b.1))
+                .expect("4 tones");
+            (index, max)
+        };
+        let (row, row_amplitude) = strongest(&self.rows);
+        let (column, column_amplitude) = strongest(&self.columns);
+        let twist = 20.0 * (row_amplitude / column_amplitude).log10();
+        let digit = if is_pure(&[row_amplitude, column_amplitude]) && twist.abs() <= MAX_TWIST {
+            Some(DIGITS[row][column])
+        } else {
+            None
+        };
+
+        let frequencies = ToneFrequencies::ALL
+            .iter()
+            .zip(&self.tones)
+            .find(|(_, goertzels)| {
+                let amplitudes: Vec<f32> = goertzels
+                    .iter()
+                    .map(|goertzel| goertzel.amplitude(block))
+                    .collect();
+                is_pure(&amplitudes)
+            })
+            .map(|(&frequencies, _)| frequencies);
+
+        let state = &mut self.channels[position];
+        match (state.digit, digit) {
+            (Some((held, since, present, _)), Some(digit)) if held == digit => {
+                state.digit = Some((held, since, present + 1, 0));
+            }
+            (Some((held, since, present, absent)), _)
+                if present >= DIGIT_BLOCKS && absent + 1 < DIGIT_BLOCKS =>
+            {
+                state.digit = Some((held, since, present, absent + 1));
+            }
+            (Some((held, since, present, absent)), _) => {
+                if present >= DIGIT_BLOCKS {
+                    events.push(ToneEvent::Digit {
+                        channel,
+                        digit: held,
+                        since,
+                        until: time - Duration::from_secs_f32(absent as f32 * block_duration),
+                    });
+                }
+                state.digit = digit.map(|digit| (digit, time, 1, 0));
+            }
+            (None, digit) => state.digit = digit.map(|digit| (digit, time, 1, 0)),
+        }
+
+        let mut detected = None;
+        match (state.segment.as_mut(), frequencies) {
+            (Some(segment), Some(frequencies))
+                if segment.frequencies == frequencies && segment.off == 0 =>
+            {
+                segment.on += 1;
+                detected = segment
+                    .frequencies
+                    .cadence(segment.on as f32 * block_duration, None)
+                    .map(|tone| (tone, segment.since));
+            }
+            (Some(segment), None) if (segment.off as f32) * block_duration < MAX_OFF => {
+                segment.off += 1;
+            }
+            (segment, frequencies) => {
+                // the next segment starts, the cadence of the previous one is complete
+                if let Some(segment) = segment {
+                    detected = segment
+                        .frequencies
+                        .cadence(
+                            segment.on as f32 * block_duration,
+                            Some(segment.off as f32 * block_duration),
+                        )
+                        .map(|tone| (tone, segment.since));
+                }
+                state.segment = frequencies.map(|frequencies| ToneSegment {
+                    frequencies,
+                    since: time,
+                    on: 1,
+                    off: 0,
+                });
+            }
+        }
+        if frequencies.is_some() {
+            state.silent_blocks = 0;
+        } else {
+            state.silent_blocks += 1;
+            if state.silent_blocks as f32 * block_duration >= TONE_RESET {
+                state.tone = None;
+            }
+        }
+        if let Some((tone, since)) = detected {
+            if state.tone != Some(tone) {
+                state.tone = Some(tone);
+                events.push(ToneEvent::CallProgress {
+                    channel,
+                    tone,
+                    since,
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    /// The sum of sines of the frequencies and amplitudes, for `duration` seconds.
+    fn tones(sample_rate: u32, tones: &[(f32, f32)], duration: f32) -> Vec<f32> {
+        (0..(duration * sample_rate as f32) as usize)
+            .map(|index| {
+                let time = index as f32 / sample_rate as f32;
+                tones
+                    .iter()
+                    .map(|&(frequency, amplitude)| amplitude * (2.0 * PI * frequency * time).sin())
+                    .sum()
+            })
+            .collect()
+    }
+
+    fn digit(sample_rate: u32, digit: char, duration: f32) -> Vec<f32> {
+        let (row, column) = (0..4)
+            .flat_map(|row| (0..4).map(move |column| (row, column)))
+            .find(|&(row, column)| DIGITS[row][column] == digit)
+            .unwrap();
+        tones(
+            sample_rate,
+            &[(ROWS[row], 0.2), (COLUMNS[column], 0.2)],
+            duration,
+        )
+    }
+
+    /// The signal repeating `on` seconds of the tones and `off` seconds of silence.
+    fn cadence(
+        sample_rate: u32,
+        frequencies: &[f32],
+        on: f32,
+        off: f32,
+        duration: f32,
+    ) -> Vec<f32> {
+        let tone: Vec<(f32, f32)> = frequencies
+            .iter()
+            .map(|&frequency| (frequency, 0.2))
+            .collect();
+        let mut signal = Vec::new();
+        while (signal.len() as f32) < duration * sample_rate as f32 {
+            signal.extend(tones(sample_rate, &tone, on));
+            signal.extend(tones(sample_rate, &[], off));
+        }
+        signal
+    }
+
+    /// Events of the signal on channel 3, in buffers of 10 ms from the Unix epoch.
+    fn detect(sample_rate: u32, signal: &[f32]) -> Vec<ToneEvent> {
+        let mut detector = ToneDetector::new(sample_rate, 1);
+        let buffer_len = sample_rate as usize / 100;
+        let mut events = Vec::new();
+        for (index, buffer) in signal.chunks(buffer_len).enumerate() {
+            let channel = ChannelData {
+                index: 3,
+                loudness_level: 0.0,
+                samples: buffer.to_vec(),
+            };
+            let timestamp = UNIX_EPOCH + Duration::from_millis(10 * index as u64);
+            events.extend(detector.process(&[channel], timestamp));
+        }
+        events
+    }
+
+    fn seconds(time: SystemTime) -> f32 {
+        time.duration_since(UNIX_EPOCH).unwrap().as_secs_f32()
+    }
+
+    fn digits(events: &[ToneEvent]) -> String {
+        events
+            .iter()
+            .filter_map(|event| match event {
+                ToneEvent::Digit { digit, .. } => Some(*digit),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn call_progress_tones(events: &[ToneEvent]) -> Vec<CallProgressTone> {
+        events
+            .iter()
+            .filter_map(|event| match event {
+                ToneEvent::CallProgress { tone, .. } => Some(*tone),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn goertzel_amplitude() {
+        let signal = tones(8000, &[(1000.0, 0.5), (2000.0, 0.25)], 0.1);
+        assert!((Goertzel::new(1000.0, 8000.0).amplitude(&signal) - 0.5).abs() < 0.01);
+        assert!((Goertzel::new(2000.0, 8000.0).amplitude(&signal) - 0.25).abs() < 0.01);
+        assert!(Goertzel::new(1500.0, 8000.0).amplitude(&signal) < 0.01);
+    }
+
+    #[test]
+    fn dialed_digits() {
+        for &sample_rate in [8000, 48000].iter() {
+            // 70 ms tones and pauses, a fast automatic dialer
+            let mut signal = tones(sample_rate, &[], 0.1);
+            for digit_char in "1234567890*#ABCD".chars() {
+                signal.extend(digit(sample_rate, digit_char, 0.07));
+                signal.extend(tones(sample_rate, &[], 0.07));
+            }
+            let events = detect(sample_rate, &signal);
+            assert_eq!(digits(&events), "1234567890*#ABCD");
+            assert_eq!(call_progress_tones(&events), []);
+
+            match events[0] {
+                ToneEvent::Digit {
+                    channel,
+                    since,
+                    until,
+                    ..
+                } => {
+                    assert_eq!(channel, 3);
+                    // within a block of the tone
+                    assert!((seconds(since) - 0.1).abs() <= BLOCK_DURATION);
+                    assert!((seconds(until) - 0.17).abs() <= BLOCK_DURATION);
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    #[test]
+    fn held_digits_end_once() {
+        let mut signal = digit(8000, '5', 1.0);
+        signal.extend(tones(8000, &[], 0.1));
+        assert_eq!(digits(&detect(8000, &signal)), "5");
+    }
+
+    #[test]
+    fn not_digits() {
+        // a single block
+        let mut signal = digit(8000, '1', BLOCK_DURATION);
+        signal.extend(tones(8000, &[], 0.1));
+        // too quiet
+        signal.extend(tones(8000, &[(697.0, 0.005), (1209.0, 0.005)], 0.2));
+        // a twist of 20 dB
+        signal.extend(tones(8000, &[(697.0, 0.5), (1209.0, 0.05)], 0.2));
+        // a single tone
+        signal.extend(tones(8000, &[(697.0, 0.5)], 0.2));
+        // the tones under speech-like harmonics
+        signal.extend(tones(
+            8000,
+            &[
+                (697.0, 0.2),
+                (1209.0, 0.2),
+                (200.0, 0.3),
+                (400.0, 0.2),
+                (600.0, 0.2),
+            ],
+            0.2,
+        ));
+        signal.extend(tones(8000, &[], 0.1));
+        assert_eq!(digits(&detect(8000, &signal)), "");
+    }
+
+    #[test]
+    fn north_american_call_progress() {
+        let cases = [
+            (
+                cadence(8000, &[350.0, 440.0], 2.0, 0.0, 2.0),
+                CallProgressTone::Dial,
+            ),
+            (
+                cadence(8000, &[440.0, 480.0], 2.0, 4.0, 8.0),
+                CallProgressTone::Ringback,
+            ),
+            (
+                cadence(8000, &[480.0, 620.0], 0.5, 0.5, 3.0),
+                CallProgressTone::Busy,
+            ),
+            (
+                cadence(8000, &[480.0, 620.0], 0.25, 0.25, 2.0),
+                CallProgressTone::Reorder,
+            ),
+        ];
+        for (signal, tone) in cases.iter() {
+            let events = detect(8000, signal);
+            // reported once, not on each cycle
+            assert_eq!(call_progress_tones(&events), [*tone]);
+            assert_eq!(digits(&events), "");
+        }
+    }
+
+    #[test]
+    fn e180_call_progress() {
+        let cases = [
+            (
+                cadence(48000, &[425.0], 4.0, 0.0, 4.0),
+                CallProgressTone::Dial,
+            ),
+            (
+                cadence(48000, &[425.0], 1.0, 4.0, 6.0),
+                CallProgressTone::Ringback,
+            ),
+            (
+                cadence(48000, &[425.0], 0.5, 0.5, 3.0),
+                CallProgressTone::Busy,
+            ),
+            (
+                cadence(48000, &[425.0], 0.2, 0.2, 2.0),
+                CallProgressTone::Reorder,
+            ),
+        ];
+        for (signal, tone) in cases.iter() {
+            assert_eq!(call_progress_tones(&detect(48000, signal)), [*tone]);
+        }
+    }
+
+    #[test]
+    fn call_progress_since_the_start_of_the_cadence() {
+        let mut signal = tones(8000, &[], 1.0);
+        signal.extend(cadence(8000, &[480.0, 620.0], 0.5, 0.5, 3.0));
+        match detect(8000, &signal).first() {
+            Some(ToneEvent::CallProgress { since, .. }) => {
+                assert!((seconds(*since) - 1.0).abs() <= BLOCK_DURATION);
+            }
+            event => panic!("unexpected {:?}", event),
+        }
+    }
+
+    #[test]
+    fn call_progress_detected_again_after_a_silence() {
+        let mut signal = cadence(8000, &[480.0, 620.0], 0.5, 0.5, 2.0);
+        signal.extend(tones(8000, &[], TONE_RESET + 1.0));
+        signal.extend(cadence(8000, &[480.0, 620.0], 0.5, 0.5, 2.0));
+        assert_eq!(
+            call_progress_tones(&detect(8000, &signal)),
+            [CallProgressTone::Busy, CallProgressTone::Busy]
+        );
+    }
+}
@@ -0,0 +1,45 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Lossy encoders of a stream of interleaved samples, shared by the streaming
+//! and the recordings.
+
+/// Encodes interleaved samples into the bytes of a stream.
+pub trait StreamEncoder: Send {
+    /// Encode a block of interleaved samples, the encoded bytes are available
+    /// as soon as there is enough samples.
+    fn encode(&mut self, samples: &[f32]) -> Result<(), String>;
+
+    /// Encode the samples left and end the stream.
+    fn finish(&mut self) -> Result<(), String>;
+
+    /// Take the bytes encoded so far.
+    fn take_bytes(&mut self) -> Vec<u8>;
+}
+
+/// Parse a bitrate in bits per second, with an optional `k` suffix of kbit/s, e.g. `192k`.
+pub fn parse_bitrate(bitrate: &str) -> Result<i32, String> {
+    let invalid = || format!("invalid bitrate '{}'", bitrate);
+    let (number, scale) = match bitrate.strip_suffix(['k', 'K']) {
+        Some(number) => (number, 1000.0),
+        None => (bitrate, 1.0),
+    };
+    match number.trim().parse::<f64>() {
+        Ok(number) if number > 0.0 && number * scale <= i32::MAX as f64 => {
+            Ok((number * scale).round() as i32)
+        }
+        _ => Err(invalid()),
+    }
+}
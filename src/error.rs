@@ -0,0 +1,166 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Errors of the selection of the host and the device, of the capture and of the threads
+//! processing it, reported to the [`Supervisor`](crate::supervisor::Supervisor) instead of
+//! panicking.
+
+use std::any::Any;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// of the input stream of the device
+    #[error("input stream error: {0}")]
+    Stream(#[from] cpal::StreamError),
+    /// of the other inputs, as the input file or the JACK client, and of the recording
+    #[error("{0}")]
+    Input(String),
+    /// a thread panicked, so it no longer processes the capture
+    #[error("the {thread} thread panicked: {message}")]
+    Panic { thread: String, message: String },
+    /// a request of the http server panicked, the other requests are still served
+    #[error("a request of the http server panicked: {0}")]
+    Request(String),
+    /// of the outputs besides the http server, as the streams, the uploads and the
+    /// publishing, and of its TLS connections, which retry or stop alone
+    #[error("{0}")]
+    Output(String),
+    /// of the command line args or of the setup of the capture, before it starts
+    #[error("{0}")]
+    Setup(String),
+    /// a host unknown by name, or not available on this platform
+    #[error("unknown host '{name}', available hosts: {}", quoted(available))]
+    UnknownHost {
+        name: String,
+        available: Vec<String>,
+    },
+    /// a host of a feature not enabled in the build
+    #[error("the {host} host requires the '{feature}' feature")]
+    HostFeature {
+        host: &'static str,
+        feature: &'static str,
+    },
+    #[error("failed to initialise host '{host}': {source}")]
+    Host {
+        host: &'static str,
+        source: cpal::HostUnavailable,
+    },
+    /// `direction` is `input` or `output`
+    #[error("no default {direction} device in host '{host}'")]
+    NoDefaultDevice {
+        direction: &'static str,
+        host: &'static str,
+    },
+    #[error("failed to get {direction} devices of host '{host}': {source}")]
+    Devices {
+        direction: &'static str,
+        host: &'static str,
+        source: cpal::DevicesError,
+    },
+    #[error("{direction} device index {index} out of range, host '{host}' has {count} {direction} device(s)")]
+    DeviceIndex {
+        direction: &'static str,
+        index: usize,
+        host: &'static str,
+        count: usize,
+    },
+    /// a device unknown by name, with the names of the devices of the host
+    #[error(
+        "unknown {direction} device '{name}' in host '{host}', available {direction} devices: {}",
+        indexed(available)
+    )]
+    UnknownDevice {
+        direction: &'static str,
+        name: String,
+        host: &'static str,
+        available: Vec<String>,
+    },
+    #[error("failed to get supported input configs: {0}")]
+    SupportedConfigs(#[from] cpal::SupportedStreamConfigsError),
+    #[error("no supported input configs reported")]
+    NoSupportedConfig,
+    #[error("unsupported sample format {0:?}")]
+    SampleFormat(cpal::SampleFormat),
+    #[error("failed to build input stream, maybe invalid input device: {0}")]
+    BuildStream(#[from] cpal::BuildStreamError),
+    #[error("failed to play stream: {0}")]
+    PlayStream(#[from] cpal::PlayStreamError),
+}
+
+impl Error {
+    /// The kinds of the errors, as counted by the [`Health`](crate::health::Health),
+    /// but for the setup errors which end the program before it serves it.
+    pub const KINDS: [&'static str; 5] = ["stream", "input", "panic", "request", "output"];
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::Stream(_) => "stream",
+            Error::Input(_) => "input",
+            Error::Panic { .. } => "panic",
+            Error::Request(_) => "request",
+            Error::Output(_) => "output",
+            _ => "setup",
+        }
+    }
+
+    /// Whether the capture cannot go on after the error: the device is gone, the input ended
+    /// on error or a thread processing the capture died. The errors specific to the host,
+    /// as some overruns, the failed requests and the errors of the outputs are transient.
+    pub fn is_fatal(&self) -> bool {
+        !matches!(
+            self,
+            Error::Stream(cpal::StreamError::BackendSpecific { .. })
+                | Error::Request(_)
+                | Error::Output(_)
+        )
+    }
+}
+
+/// The errors of the setup are the messages of the parsing of the args.
+impl From<String> for Error {
+    fn from(message: String) -> Self {
+        Error::Setup(message)
+    }
+}
+
+/// The names quoted, as `'a', 'b'`.
+fn quoted(names: &[String]) -> String {
+    names
+        .iter()
+        .map(|name| format!("'{}'", name))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// The names quoted after their index, as `0: 'a', 1: 'b'`.
+fn indexed(names: &[String]) -> String {
+    names
+        .iter()
+        .enumerate()
+        .map(|(index, name)| format!("{}: '{}'", index, name))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// The message of the payload of a panic.
+pub fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        String::from(*message)
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        String::from("unknown panic")
+    }
+}
@@ -0,0 +1,247 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Audio events, handled out of the audio thread.
+
+use crate::channel_faults::FaultKind;
+use crate::dtmf::CallProgressTone;
+use crate::pilot::PilotFault;
+use crate::report::LoudnessReport;
+use serde::Serialize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Events queued before dropping new ones.
+const QUEUE_CAPACITY: usize = 64;
+
+/// An audio event, serialized as a JSON object with its name in the `event` field
+/// and the times in seconds since the Unix epoch.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    StreamStart {
+        timestamp: f64,
+        device: String,
+        sample_format: String,
+        sample_rate: u32,
+        channel_count: usize,
+    },
+    StreamStop {
+        timestamp: f64,
+    },
+    DeviceError {
+        timestamp: f64,
+        message: String,
+    },
+    /// `count` is the number of clips of the channel since the start
+    Clipping {
+        timestamp: f64,
+        channel: usize,
+        count: u64,
+    },
+    SilenceStart {
+        timestamp: f64,
+        channel: usize,
+        since: f64,
+    },
+    SilenceEnd {
+        timestamp: f64,
+        channel: usize,
+        since: f64,
+        duration: f64,
+    },
+    /// the stereo pair of channels `left` and `right` is out of phase
+    CorrelationAlarmStart {
+        timestamp: f64,
+        left: usize,
+        right: usize,
+        correlation: f32,
+        since: f64,
+    },
+    CorrelationAlarmEnd {
+        timestamp: f64,
+        left: usize,
+        right: usize,
+        since: f64,
+        duration: f64,
+    },
+    /// `fault` is one of `dual_mono`, `inverted` or `dead`, of the given channels
+    ChannelFaultStart {
+        timestamp: f64,
+        fault: FaultKind,
+        channels: Vec<usize>,
+        since: f64,
+    },
+    ChannelFaultEnd {
+        timestamp: f64,
+        fault: FaultKind,
+        channels: Vec<usize>,
+        since: f64,
+        duration: f64,
+    },
+    /// a DTMF digit held from `since` for `duration` seconds
+    DtmfDigit {
+        timestamp: f64,
+        channel: usize,
+        digit: char,
+        since: f64,
+        duration: f64,
+    },
+    /// `tone` is one of `dial`, `ringback`, `busy` or `reorder`, since the start of its cadence
+    CallProgressTone {
+        timestamp: f64,
+        channel: usize,
+        tone: CallProgressTone,
+        since: f64,
+    },
+    /// the pilot tone of `frequency` Hz of the channel is lost since `since`, `fault` is
+    /// `missing` or `drift`, with the frequency and level in dBFS measured last
+    PilotToneLost {
+        timestamp: f64,
+        channel: usize,
+        frequency: f32,
+        fault: PilotFault,
+        measured_frequency: f32,
+        level: f32,
+        since: f64,
+    },
+    PilotToneRestored {
+        timestamp: f64,
+        channel: usize,
+        frequency: f32,
+        since: f64,
+        duration: f64,
+    },
+    /// someone is talking on the channel since `since`, only with `--vad`
+    SpeechStart {
+        timestamp: f64,
+        channel: usize,
+        since: f64,
+    },
+    SpeechEnd {
+        timestamp: f64,
+        channel: usize,
+        since: f64,
+        duration: f64,
+    },
+    /// a file of the recording finalized, with its loudness report if enabled
+    RecordingCompleted {
+        timestamp: f64,
+        path: String,
+        start: f64,
+        duration: f64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        report: Option<LoudnessReport>,
+    },
+    /// a file of the recording uploaded to the S3 bucket, at the URL of the object
+    RecordingUploaded {
+        timestamp: f64,
+        path: String,
+        url: String,
+    },
+    /// a segment of the recording deleted by the retention policy
+    RecordingDeleted {
+        timestamp: f64,
+        path: String,
+        reason: String,
+    },
+    /// the recording stopped before the disk fills
+    RecordingStopped {
+        timestamp: f64,
+        reason: String,
+    },
+    /// an alarm of the `--script`
+    ScriptAlarm {
+        timestamp: f64,
+        message: String,
+    },
+    /// a thread processing the capture panicked
+    ThreadPanic {
+        timestamp: f64,
+        thread: String,
+        message: String,
+    },
+}
+
+impl Event {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("failed to serialize event")
+    }
+}
+
+/// Count of the events not completely handled yet, to wait for them before exiting.
+#[derive(Clone, Default)]
+pub struct PendingEvents(Arc<AtomicUsize>);
+
+impl PendingEvents {
+    pub fn begin(&self) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn end(&self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Wait until all the events are handled, or the timeout,
+    /// returns whether all the events were handled.
+    pub fn wait(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        while self.0.load(Ordering::SeqCst) > 0 {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        true
+    }
+}
+
+/// Bounded queue of events, handled from its own thread.
+///
+/// Emitting never blocks: when the queue is full the event is dropped.
+#[derive(Clone)]
+pub struct EventQueue {
+    sender: mpsc::SyncSender<Event>,
+    pending: PendingEvents,
+}
+
+impl EventQueue {
+    /// Start the thread handling the events, `pending` counts the events queued
+    /// until the handler returns.
+    pub fn start<F>(pending: PendingEvents, mut handler: F) -> Self
+    where
+        F: FnMut(Event) + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::sync_channel(QUEUE_CAPACITY);
+        let handler_pending = pending.clone();
+        thread::spawn(move || {
+            for event in receiver {
+                handler(event);
+                handler_pending.end();
+            }
+        });
+        EventQueue { sender, pending }
+    }
+
+    pub fn emit(&self, event: Event) {
+        self.pending.begin();
+        if self.sender.try_send(event).is_err() {
+            self.pending.end();
+        }
+    }
+}
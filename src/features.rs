@@ -0,0 +1,331 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Mel spectrogram and MFCC frames of the input, the features of sound classification
+//! models, computed as librosa does by default so a model trained on its features can
+//! be fed the live input: the power spectrum of Hann windowed frames, a mel filter bank
+//! of the Slaney scale and area normalization, the power in dB and the orthonormal
+//! DCT-II of it for the MFCCs.
+//!
+//! Unlike librosa the frames are not centered on their timestamp, the input is live.
+
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+use serde::Serialize;
+use std::f32::consts::PI;
+use std::fs::File;
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+/// librosa defaults
+pub const DEFAULT_FRAME_SIZE: usize = 2048;
+pub const DEFAULT_HOP_SIZE: usize = 512;
+pub const DEFAULT_MEL_BANDS: usize = 128;
+pub const DEFAULT_MFCC: usize = 20;
+/// smallest power in dB, 10^-10
+const MIN_POWER: f32 = 1e-10;
+/// bytes of the NPY header, with room for any shape
+const NPY_HEADER_SIZE: usize = 128;
+/// time between updates of the frame count in the NPY header
+const NPY_UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FeatureConfig {
+    /// samples of each frame, the FFT size
+    pub frame_size: usize,
+    /// samples between the starts of the frames
+    pub hop_size: usize,
+    pub mel_bands: usize,
+    /// Hz, of the mel filter bank
+    pub min_frequency: f32,
+    pub max_frequency: f32,
+    /// coefficients of each frame, none without MFCCs
+    pub mfcc: usize,
+}
+
+impl FeatureConfig {
+    pub fn new(sample_rate: u32) -> Self {
+        FeatureConfig {
+            frame_size: DEFAULT_FRAME_SIZE,
+            hop_size: DEFAULT_HOP_SIZE,
+            mel_bands: DEFAULT_MEL_BANDS,
+            min_frequency: 0.0,
+            max_frequency: sample_rate as f32 / 2.0,
+            mfcc: DEFAULT_MFCC,
+        }
+    }
+}
+
+/// The features of a frame of the input.
+#[derive(Clone, Debug, Serialize)]
+pub struct FeatureFrame {
+    /// capture time of the first sample, in seconds since the Unix epoch
+    pub timestamp: f64,
+    /// power of each mel band, in dB
+    pub mel: Vec<f32>,
+    /// empty without MFCCs
+    pub mfcc: Vec<f32>,
+}
+
+impl FeatureFrame {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("failed to serialize features")
+    }
+}
+
+/// frequency in Hz to mel in the Slaney scale, linear below 1 kHz and logarithmic above
+fn hz_to_mel(frequency: f32) -> f32 {
+    const LOG_STEP: f32 = 0.068_751_78; // ln(6.4) / 27
+    if frequency < 1000.0 {
+        frequency * 3.0 / 200.0
+    } else {
+        15.0 + (frequency / 1000.0).ln() / LOG_STEP
+    }
+}
+
+fn mel_to_hz(mel: f32) -> f32 {
+    const LOG_STEP: f32 = 0.068_751_78;
+    if mel < 15.0 {
+        mel * 200.0 / 3.0
+    } else {
+        1000.0 * (LOG_STEP * (mel - 15.0)).exp()
+    }
+}
+
+/// Computes the features of the frames of a mono input.
+pub struct FeatureExtractor {
+    config: FeatureConfig,
+    sample_rate: f64,
+    fft: Arc<dyn Fft<f32>>,
+    window_coefficients: Vec<f32>,
+    /// weight of each FFT bin of each mel band
+    mel_filters: Vec<Vec<f32>>,
+    /// DCT-II basis of each coefficient
+    dct: Vec<Vec<f32>>,
+    /// samples of the next frames and the capture time of the first one
+    samples: Vec<f32>,
+    samples_start: Option<SystemTime>,
+    buffer: Vec<Complex<f32>>,
+    scratch: Vec<Complex<f32>>,
+}
+
+impl FeatureExtractor {
+    pub fn new(config: FeatureConfig, sample_rate: u32) -> Self {
+        let fft = FftPlanner::new().plan_fft_forward(config.frame_size);
+        // periodic Hann window, as scipy.signal.get_window
+        let window_coefficients = (0..config.frame_size)
+            .map(|n| 0.5 - 0.5 * (2.0 * PI * n as f32 / config.frame_size as f32).cos())
+            .collect();
+
+        let num_bins = config.frame_size / 2 + 1;
+        let bin_frequency = |bin: usize| bin as f32 * sample_rate as f32 / config.frame_size as f32;
+        let min_mel = hz_to_mel(config.min_frequency);
+        let max_mel = hz_to_mel(config.max_frequency);
+        let edges: Vec<f32> = (0..config.mel_bands + 2)
+            .map(|index| {
+                mel_to_hz(
+                    min_mel + (max_mel - min_mel) * index as f32 / (config.mel_bands + 1) as f32,
+                )
+            })
+            .collect();
+        let mel_filters = edges
+            .windows(3)
+            .map(|edges| {
+                let (low, center, high) = (edges[0], edges[1], edges[2]);
+                // triangles of unit area
+                let normalization = 2.0 / (high - low);
+                (0..num_bins)
+                    .map(|bin| {
+                        let frequency = bin_frequency(bin);
+                        let rising = (frequency - low) / (center - low);
+                        let falling = (high - frequency) / (high - center);
+                        rising.min(falling).max(0.0) * normalization
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let dct = (0..config.mfcc)
+            .map(|coefficient| {
+                let scale = if coefficient == 0 {
+                    (1.0 / config.mel_bands as f32).sqrt()
+                } else {
+                    (2.0 / config.mel_bands as f32).sqrt()
+                };
+                (0..config.mel_bands)
+                    .map(|band| {
+                        scale
+                            * (PI * coefficient as f32 * (2 * band + 1) as f32
+                                / (2 * config.mel_bands) as f32)
+                                .cos()
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let scratch = vec![Complex::default(); fft.get_inplace_scratch_len()];
+        FeatureExtractor {
+            config,
+            sample_rate: sample_rate as f64,
+            fft,
+            window_coefficients,
+            mel_filters,
+            dct,
+            samples: Vec::with_capacity(2 * config.frame_size),
+            samples_start: None,
+            buffer: vec![Complex::default(); config.frame_size],
+            scratch,
+        }
+    }
+
+    /// Add the samples of an input buffer captured at the given time,
+    /// returns the features of the frames completed.
+    pub fn process(&mut self, samples: &[f32], timestamp: SystemTime) -> Vec<FeatureFrame> {
+        self.start_samples(timestamp);
+        self.samples.extend_from_slice(samples);
+        self.complete_frames()
+    }
+
+    /// Add the `channels` of the interleaved samples of `num_channels` of an input buffer
+    /// captured at the given time, mixed to mono, returns the features of the frames completed.
+    pub fn process_channels(
+        &mut self,
+        samples: &[f32],
+        num_channels: usize,
+        channels: &[usize],
+        timestamp: SystemTime,
+    ) -> Vec<FeatureFrame> {
+        self.start_samples(timestamp);
+        self.samples
+            .extend(samples.chunks_exact(num_channels).map(|frame| {
+                channels.iter().map(|&channel| frame[channel]).sum::<f32>() / channels.len() as f32
+            }));
+        self.complete_frames()
+    }
+
+    fn start_samples(&mut self, timestamp: SystemTime) {
+        if self.samples_start.is_none() || self.samples.is_empty() {
+            self.samples_start = Some(timestamp);
+        }
+    }
+
+    fn complete_frames(&mut self) -> Vec<FeatureFrame> {
+        let hop_duration = Duration::from_secs_f64(self.config.hop_size as f64 / self.sample_rate);
+        let mut frames = Vec::new();
+        while self.samples.len() >= self.config.frame_size {
+            let start = self.samples_start.expect("set with the samples");
+            frames.push(self.frame(start));
+            self.samples
+                .drain(..self.config.hop_size.min(self.samples.len()));
+            self.samples_start = Some(start + hop_duration);
+        }
+        frames
+    }
+
+    fn frame(&mut self, start: SystemTime) -> FeatureFrame {
+        for ((value, &sample), coefficient) in self
+            .buffer
+            .iter_mut()
+            .zip(&self.samples)
+            .zip(&self.window_coefficients)
+        {
+            *value = Complex::new(sample * coefficient, 0.0);
+        }
+        self.fft
+            .process_with_scratch(&mut self.buffer, &mut self.scratch);
+        let powers: Vec<f32> = self.buffer[..=self.config.frame_size / 2]
+            .iter()
+            .map(|value| value.norm_sqr())
+            .collect();
+
+        let mel: Vec<f32> = self
+            .mel_filters
+            .iter()
+            .map(|filter| {
+                let power: f32 = filter.iter().zip(&powers).map(|(w, p)| w * p).sum();
+                10.0 * power.max(MIN_POWER).log10()
+            })
+            .collect();
+        let mfcc = self
+            .dct
+            .iter()
+            .map(|basis| basis.iter().zip(&mel).map(|(b, m)| b * m).sum())
+            .collect();
+        FeatureFrame {
+            timestamp: crate::unix_time(start),
+            mel,
+            mfcc,
+        }
+    }
+}
+
+/// Writes rows of `f32` to a NumPy `.npy` file of shape (rows, columns), the count of rows
+/// in the header updated each second and when dropped.
+pub struct NpyWriter {
+    file: File,
+    columns: usize,
+    rows: usize,
+    updated: Instant,
+}
+
+impl NpyWriter {
+    pub fn create(path: &Path, columns: usize) -> io::Result<Self> {
+        let mut writer = NpyWriter {
+            file: File::create(path)?,
+            columns,
+            rows: 0,
+            updated: Instant::now(),
+        };
+        writer.write_header()?;
+        Ok(writer)
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        let dictionary = format!(
+            "{{'descr': '<f4', 'fortran_order': False, 'shape': ({}, {}), }}",
+            self.rows, self.columns
+        );
+        // magic, version 1.0, header length, then the dictionary padded with spaces
+        // and ended by a newline
+        let mut header = b"\x93NUMPY\x01\x00".to_vec();
+        header.extend_from_slice(&((NPY_HEADER_SIZE - 10) as u16).to_le_bytes());
+        header.extend_from_slice(dictionary.as_bytes());
+        header.resize(NPY_HEADER_SIZE - 1, b' ');
+        header.push(b'\n');
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(&header)?;
+        self.file.seek(SeekFrom::End(0))?;
+        Ok(())
+    }
+
+    pub fn write_row(&mut self, row: &[f32]) -> io::Result<()> {
+        let bytes: Vec<u8> = row.iter().flat_map(|value| value.to_le_bytes()).collect();
+        self.file.write_all(&bytes)?;
+        self.rows += 1;
+        if self.updated.elapsed() >= NPY_UPDATE_INTERVAL {
+            self.updated = Instant::now();
+            self.write_header()?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for NpyWriter {
+    fn drop(&mut self) {
+        self.write_header().ok();
+    }
+}
@@ -0,0 +1,463 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Input of the samples from a WAV file, or from a WAV or raw PCM stream on the
+//! standard input, instead of a device, in real time or as fast as the consumers
+//! read them.
+
+use crate::pcm::Endianness;
+use crate::ring::RingWriter;
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Duration of the blocks written to the ring, as the input buffers of a device.
+const BLOCK_DURATION: f64 = 0.02;
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xfffe;
+
+/// Encoding of the samples of a raw PCM input.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SampleEncoding {
+    U8,
+    S16,
+    S24,
+    S32,
+    F32,
+    F64,
+}
+
+impl SampleEncoding {
+    pub fn bytes_per_sample(self) -> usize {
+        match self {
+            SampleEncoding::U8 => 1,
+            SampleEncoding::S16 => 2,
+            SampleEncoding::S24 => 3,
+            SampleEncoding::S32 | SampleEncoding::F32 => 4,
+            SampleEncoding::F64 => 8,
+        }
+    }
+}
+
+/// Format of a raw PCM input, interleaved samples without any header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RawFormat {
+    pub encoding: SampleEncoding,
+    pub endianness: Endianness,
+    pub sample_rate: u32,
+    pub channels: u16,
+}
+
+impl RawFormat {
+    /// Parse the sample encoding, the sample rate and the channels, e.g. `s16le,48000,2`,
+    /// the encoding is one of `u8`, `s16`, `s24`, `s32`, `f32` or `f64` followed by
+    /// the endianness, `le` or `be`, but for `u8`.
+    pub fn parse(format: &str) -> Result<Self, String> {
+        let invalid = || {
+            format!(
+                "invalid input format '{}', expected e.g. 's16le,48000,2'",
+                format
+            )
+        };
+        let mut parts = format.split(',');
+        let (encoding, sample_rate, channels) =
+            match (parts.next(), parts.next(), parts.next(), parts.next()) {
+                (Some(encoding), Some(sample_rate), Some(channels), None) => {
+                    (encoding.trim(), sample_rate.trim(), channels.trim())
+                }
+                _ => return Err(invalid()),
+            };
+        let (encoding, endianness) = match encoding {
+            "u8" => (SampleEncoding::U8, Endianness::Little),
+            _ if encoding.len() > 2 => {
+                let (name, endianness) = encoding.split_at(encoding.len() - 2);
+                let encoding = match name {
+                    "s16" => SampleEncoding::S16,
+                    "s24" => SampleEncoding::S24,
+                    "s32" => SampleEncoding::S32,
+                    "f32" => SampleEncoding::F32,
+                    "f64" => SampleEncoding::F64,
+                    _ => return Err(invalid()),
+                };
+                let endianness = match endianness {
+                    "le" => Endianness::Little,
+                    "be" => Endianness::Big,
+                    _ => return Err(invalid()),
+                };
+                (encoding, endianness)
+            }
+            _ => return Err(invalid()),
+        };
+        let sample_rate = sample_rate.parse().map_err(|_| invalid())?;
+        let channels = channels.parse().map_err(|_| invalid())?;
+        if sample_rate == 0 || channels == 0 {
+            return Err(invalid());
+        }
+        Ok(RawFormat {
+            encoding,
+            endianness,
+            sample_rate,
+            channels,
+        })
+    }
+
+    pub fn bytes_per_frame(&self) -> usize {
+        self.encoding.bytes_per_sample() * self.channels as usize
+    }
+
+    /// Decode the whole samples of `bytes`, appending them to `samples`.
+    pub fn decode(&self, bytes: &[u8], samples: &mut Vec<f32>) {
+        let little_endian = self.endianness == Endianness::Little;
+        let len = self.encoding.bytes_per_sample();
+        let bytes = bytes.chunks_exact(len);
+        match self.encoding {
+            SampleEncoding::U8 => {
+                samples.extend(bytes.map(|bytes| (bytes[0] as f32 - 128.0) / 128.0))
+            }
+            SampleEncoding::S16 => samples.extend(bytes.map(|bytes| {
+                let bytes = [bytes[0], bytes[1]];
+                let sample = if little_endian {
+                    i16::from_le_bytes(bytes)
+                } else {
+                    i16::from_be_bytes(bytes)
+                };
+                sample as f32 / 32768.0
+            })),
+            SampleEncoding::S24 => samples.extend(bytes.map(|bytes| {
+                // in the upper bytes of an i32
+                let sample = if little_endian {
+                    i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]])
+                } else {
+                    i32::from_be_bytes([bytes[0], bytes[1], bytes[2], 0])
+                };
+                sample as f32 / 2_147_483_648.0
+            })),
+            SampleEncoding::S32 => samples.extend(bytes.map(|bytes| {
+                let bytes = bytes.try_into().unwrap();
+                let sample = if little_endian {
+                    i32::from_le_bytes(bytes)
+                } else {
+                    i32::from_be_bytes(bytes)
+                };
+                sample as f32 / 2_147_483_648.0
+            })),
+            SampleEncoding::F32 => samples.extend(bytes.map(|bytes| {
+                let bytes = bytes.try_into().unwrap();
+                if little_endian {
+                    f32::from_le_bytes(bytes)
+                } else {
+                    f32::from_be_bytes(bytes)
+                }
+            })),
+            SampleEncoding::F64 => samples.extend(bytes.map(|bytes| {
+                let bytes = bytes.try_into().unwrap();
+                let sample = if little_endian {
+                    f64::from_le_bytes(bytes)
+                } else {
+                    f64::from_be_bytes(bytes)
+                };
+                sample as f32
+            })),
+        }
+    }
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+/// Read the header of a WAV stream up to its samples, returns their format and
+/// their length in bytes, `None` for a stream of unknown length.
+fn read_wav_header<R: Read>(reader: &mut R) -> Result<(RawFormat, Option<u64>), String> {
+    let mut riff = [0; 12];
+    reader
+        .read_exact(&mut riff)
+        .map_err(|err| format!("failed to read the WAV header: {}", err))?;
+    if !matches!(&riff[..4], b"RIFF" | b"RF64") || &riff[8..] != b"WAVE" {
+        return Err(String::from("not a WAV file"));
+    }
+
+    let mut format = None;
+    loop {
+        let mut chunk_header = [0; 8];
+        reader
+            .read_exact(&mut chunk_header)
+            .map_err(|err| format!("failed to read the WAV header: {}", err))?;
+        let chunk_len = read_u32(&chunk_header, 4);
+        match &chunk_header[..4] {
+            b"fmt " => {
+                if !(16..=1024).contains(&chunk_len) {
+                    return Err(String::from("invalid WAV format chunk"));
+                }
+                let mut fmt = vec![0; chunk_len as usize + chunk_len as usize % 2];
+                reader
+                    .read_exact(&mut fmt)
+                    .map_err(|err| format!("failed to read the WAV header: {}", err))?;
+                format = Some(wav_format(&fmt)?);
+            }
+            b"data" => {
+                let format =
+                    format.ok_or_else(|| String::from("WAV data chunk before the format chunk"))?;
+                // the sizes of a stream, as written by `/stream.wav`, are unknown,
+                // and those of a RF64 file are in its `ds64` chunk, read up to the end
+                let data_len = match chunk_len {
+                    0 | 0xffff_ffff => None,
+                    len => Some(len as u64),
+                };
+                return Ok((format, data_len));
+            }
+            _ => {
+                let skipped = io::copy(
+                    &mut reader.take(chunk_len as u64 + chunk_len as u64 % 2),
+                    &mut io::sink(),
+                )
+                .map_err(|err| format!("failed to read the WAV header: {}", err))?;
+                if skipped < chunk_len as u64 {
+                    return Err(String::from("truncated WAV header"));
+                }
+            }
+        }
+    }
+}
+
+/// Format of the samples of a WAV format chunk.
+fn wav_format(fmt: &[u8]) -> Result<RawFormat, String> {
+    let mut format_tag = read_u16(fmt, 0);
+    let channels = read_u16(fmt, 2);
+    let sample_rate = read_u32(fmt, 4);
+    let block_align = read_u16(fmt, 12);
+    let bits_per_sample = read_u16(fmt, 14);
+    if format_tag == WAVE_FORMAT_EXTENSIBLE && fmt.len() >= 40 {
+        // the first 2 bytes of the sub format GUID
+        format_tag = read_u16(fmt, 24);
+    }
+    let encoding = match (format_tag, bits_per_sample) {
+        (WAVE_FORMAT_PCM, 8) => SampleEncoding::U8,
+        (WAVE_FORMAT_PCM, 16) => SampleEncoding::S16,
+        (WAVE_FORMAT_PCM, 24) => SampleEncoding::S24,
+        (WAVE_FORMAT_PCM, 32) => SampleEncoding::S32,
+        (WAVE_FORMAT_IEEE_FLOAT, 32) => SampleEncoding::F32,
+        (WAVE_FORMAT_IEEE_FLOAT, 64) => SampleEncoding::F64,
+        _ => {
+            return Err(format!(
+                "unsupported WAV format 0x{:04x} of {} bits per sample",
+                format_tag, bits_per_sample
+            ))
+        }
+    };
+    let format = RawFormat {
+        encoding,
+        endianness: Endianness::Little,
+        sample_rate,
+        channels,
+    };
+    if channels == 0 || sample_rate == 0 || block_align as usize != format.bytes_per_frame() {
+        return Err(String::from("invalid WAV format chunk"));
+    }
+    Ok(format)
+}
+
+/// A WAV file or the standard input, the source of the samples.
+pub struct FileInput {
+    reader: Box<dyn Read + Send>,
+    format: RawFormat,
+    /// length of the samples in bytes, `None` up to the end of the input
+    data_len: Option<u64>,
+    name: String,
+}
+
+impl FileInput {
+    /// Open a WAV file, or a raw PCM file of the given format.
+    pub fn open(path: &str, format: Option<RawFormat>) -> Result<Self, String> {
+        let file = File::open(path).map_err(|err| format!("failed to open '{}': {}", path, err))?;
+        FileInput::new(BufReader::new(file), format, path.to_string())
+            .map_err(|err| format!("failed to read '{}': {}", path, err))
+    }
+
+    /// The standard input, a WAV stream or raw PCM of the given format.
+    pub fn stdin(format: Option<RawFormat>) -> Result<Self, String> {
+        FileInput::new(io::stdin(), format, String::from("stdin"))
+            .map_err(|err| format!("failed to read the standard input: {}", err))
+    }
+
+    fn new<R: Read + Send + 'static>(
+        mut reader: R,
+        format: Option<RawFormat>,
+        name: String,
+    ) -> Result<Self, String> {
+        let (format, data_len) = match format {
+            Some(format) => (format, None),
+            None => read_wav_header(&mut reader)?,
+        };
+        Ok(FileInput {
+            reader: Box::new(reader),
+            format,
+            data_len,
+            name,
+        })
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.format.channels
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.format.sample_rate
+    }
+
+    /// Sample format of the input, as recorded.
+    pub fn sample_format(&self) -> cpal::SampleFormat {
+        match self.format.encoding {
+            SampleEncoding::U8 | SampleEncoding::S16 => cpal::SampleFormat::I16,
+            SampleEncoding::S24
+            | SampleEncoding::S32
+            | SampleEncoding::F32
+            | SampleEncoding::F64 => cpal::SampleFormat::F32,
+        }
+    }
+
+    /// Name of the input, the path of the file or `stdin`.
+    pub fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    /// Start reading, writing the samples to the ring in blocks paced in real time,
+    /// or as fast as the readers of the ring read them when not `realtime`,
+    /// and calling `end` at the end of the input, with the error reading it if any.
+    ///
+    /// Reading stops, and the readers of the ring end, when the returned handle is dropped.
+    pub fn start<E>(self, ring_writer: RingWriter, realtime: bool, end: E) -> FileInputThread
+    where
+        E: FnOnce(Result<(), String>) + Send + 'static,
+    {
+        let stop = Arc::new(AtomicBool::new(false));
+        let ring_writer = Arc::new(Mutex::new(Some(ring_writer)));
+        let thread_stop = Arc::clone(&stop);
+        let thread_ring_writer = Arc::clone(&ring_writer);
+        thread::spawn(move || {
+            let FileInput {
+                reader,
+                format,
+                data_len,
+                name,
+            } = self;
+            let mut reader: Box<dyn Read + Send> = match data_len {
+                Some(data_len) => Box::new(reader.take(data_len)),
+                None => reader,
+            };
+            let sample_rate = format.sample_rate as u64;
+            let frame_len = format.bytes_per_frame();
+            let block_frames = (format.sample_rate as f64 * BLOCK_DURATION).ceil() as usize;
+            let mut bytes = vec![0; block_frames * frame_len];
+            let mut samples = Vec::with_capacity(block_frames * format.channels as usize);
+            let start = Instant::now();
+            let start_time = SystemTime::now();
+            let mut frames = 0;
+
+            let result = loop {
+                let len = match read_block(&mut reader, &mut bytes) {
+                    Ok(len) => len,
+                    Err(err) => break Err(format!("failed to read '{}': {}", name, err)),
+                };
+                let block_len = len - len % frame_len;
+                samples.clear();
+                format.decode(&bytes[..block_len], &mut samples);
+                let block_frames = (block_len / frame_len) as u64;
+                if realtime {
+                    // written as captured, at the end of the block
+                    let end =
+                        Duration::from_nanos((frames + block_frames) * 1_000_000_000 / sample_rate);
+                    if let Some(wait) = end.checked_sub(start.elapsed()) {
+                        thread::sleep(wait);
+                    }
+                }
+                if thread_stop.load(Ordering::Relaxed) {
+                    return;
+                }
+                if let Some(ring_writer) = &mut *thread_ring_writer
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                {
+                    if !realtime {
+                        ring_writer.wait_for_room();
+                    }
+                    if block_frames > 0 {
+                        ring_writer.push(
+                            &samples,
+                            start_time + Duration::from_nanos(frames * 1_000_000_000 / sample_rate),
+                        );
+                    }
+                }
+                frames += block_frames;
+                if len < bytes.len() {
+                    break Ok(());
+                }
+            };
+            // the last blocks are consumed before the end
+            if let Some(ring_writer) = &*thread_ring_writer
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+            {
+                ring_writer.wait_for_readers();
+            }
+            end(result);
+        });
+
+        FileInputThread { stop, ring_writer }
+    }
+}
+
+/// Read until `bytes` is full or the end of the input, returns the length read.
+fn read_block<R: Read>(reader: &mut R, bytes: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+    while len < bytes.len() {
+        match reader.read(&mut bytes[len..]) {
+            Ok(0) => break,
+            Ok(read) => len += read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(len)
+}
+
+/// The thread reading the input, stopped when dropped.
+///
+/// The ring writer is dropped right away, ending its readers, while the thread
+/// may still be blocked reading the standard input.
+pub struct FileInputThread {
+    stop: Arc<AtomicBool>,
+    ring_writer: Arc<Mutex<Option<RingWriter>>>,
+}
+
+impl Drop for FileInputThread {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        self.ring_writer
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+    }
+}
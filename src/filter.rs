@@ -0,0 +1,216 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Chain of high-pass, low-pass and notch filters of each channel, as notching the mains
+//! hum out before measuring low levels.
+//!
+//! The filters are second order, with the coefficients of the Audio EQ Cookbook of
+//! Robert Bristow-Johnson.
+
+use crate::biquad::Biquad;
+use crate::processor::{self, Processor};
+use crate::{parse_frequency, InputBufferSourceData};
+use std::f64::consts::PI;
+
+/// Butterworth, of the high-pass and low-pass filters
+pub const DEFAULT_Q: f64 = std::f64::consts::FRAC_1_SQRT_2;
+/// a narrow notch, 1.7 Hz wide at 50 Hz
+pub const DEFAULT_NOTCH_Q: f64 = 30.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FilterKind {
+    HighPass,
+    LowPass,
+    Notch,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FilterSpec {
+    pub kind: FilterKind,
+    /// Hz, of the cutoff or the center
+    pub frequency: f64,
+    pub q: f64,
+}
+
+impl FilterSpec {
+    /// Parse `<kind>:<frequency>[:Q=<q>]`, with the kind `highpass`, `lowpass` or `notch`
+    /// and the frequency in Hz or kHz, e.g. `highpass:80Hz` or `notch:50Hz:Q=30`.
+    pub fn parse(filter: &str) -> Result<Self, String> {
+        let invalid = |reason: String| format!("invalid filter '{}', {}", filter, reason);
+        let mut fields = filter.split(':');
+        let kind = match fields.next().unwrap_or_default().trim() {
+            "highpass" => FilterKind::HighPass,
+            "lowpass" => FilterKind::LowPass,
+            "notch" => FilterKind::Notch,
+            kind => {
+                return Err(invalid(format!(
+                    "unknown kind '{}', expected one of: highpass, lowpass, notch",
+                    kind
+                )))
+            }
+        };
+        let frequency = parse_frequency(fields.next().unwrap_or_default()).map_err(invalid)?;
+        let mut q = match kind {
+            FilterKind::Notch => DEFAULT_NOTCH_Q,
+            _ => DEFAULT_Q,
+        };
+        for field in fields {
+            match field.split_once('=') {
+                Some((name, value)) if name.trim().eq_ignore_ascii_case("q") => {
+                    q = match value.trim().parse::<f64>() {
+                        Ok(value) if value > 0.0 && value.is_finite() => value,
+                        _ => return Err(invalid(format!("invalid Q '{}'", value))),
+                    };
+                }
+                _ => return Err(invalid(format!("unknown parameter '{}'", field))),
+            }
+        }
+        Ok(FilterSpec { kind, frequency, q })
+    }
+
+    /// Coefficients normalized by `a0`, as of [`Biquad::new`].
+    fn coefficients(&self, sample_rate: u32) -> ([f64; 3], [f64; 2]) {
+        let w0 = 2.0 * PI * self.frequency / sample_rate as f64;
+        let alpha = w0.sin() / (2.0 * self.q);
+        let cos = w0.cos();
+        let b = match self.kind {
+            FilterKind::HighPass => [(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0],
+            FilterKind::LowPass => [(1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0],
+            FilterKind::Notch => [1.0, -2.0 * cos, 1.0],
+        };
+        let a0 = 1.0 + alpha;
+        (
+            [b[0] / a0, b[1] / a0, b[2] / a0],
+            [-2.0 * cos / a0, (1.0 - alpha) / a0],
+        )
+    }
+
+    fn validate(&self, sample_rate: u32) -> Result<(), String> {
+        if self.frequency >= sample_rate as f64 / 2.0 {
+            return Err(format!(
+                "invalid filter frequency of {} Hz, over the Nyquist frequency of {} Hz",
+                self.frequency,
+                sample_rate / 2
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// The filters in turn, on each channel of interleaved samples.
+#[derive(Clone)]
+pub struct FilterChain {
+    specs: Vec<FilterSpec>,
+    sample_rate: u32,
+    /// of each channel
+    biquads: Vec<Vec<Biquad>>,
+}
+
+impl FilterChain {
+    pub fn new(
+        specs: &[FilterSpec],
+        num_channels: usize,
+        sample_rate: u32,
+    ) -> Result<Self, String> {
+        let mut biquads = Vec::with_capacity(specs.len());
+        for spec in specs {
+            spec.validate(sample_rate)?;
+            let (b, a) = spec.coefficients(sample_rate);
+            biquads.push(Biquad::new(b, a));
+        }
+        Ok(FilterChain {
+            specs: specs.to_vec(),
+            sample_rate,
+            biquads: vec![biquads; num_channels],
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.biquads.iter().all(Vec::is_empty)
+    }
+
+    /// Filter a buffer of interleaved samples in place.
+    pub fn process(&mut self, samples: &mut [f32]) {
+        if self.is_empty() {
+            return;
+        }
+        for frame in samples.chunks_exact_mut(self.biquads.len()) {
+            for (sample, biquads) in frame.iter_mut().zip(&mut self.biquads) {
+                *sample = biquads
+                    .iter_mut()
+                    .fold(f64::from(*sample), |sample, biquad| biquad.process(sample))
+                    as f32;
+            }
+        }
+    }
+}
+
+/// Filters the samples of the processed channels, with the parameters `<n>.frequency` and
+/// `<n>.q` of the n-th filter, from 0.
+impl Processor for FilterChain {
+    fn name(&self) -> &str {
+        "filter"
+    }
+
+    fn process(&mut self, buffer: &mut InputBufferSourceData) {
+        for channel in &mut buffer.channels {
+            if let Some(biquads) = self.biquads.get_mut(channel.index) {
+                for sample in &mut channel.samples {
+                    *sample = biquads
+                        .iter_mut()
+                        .fold(f64::from(*sample), |sample, biquad| biquad.process(sample))
+                        as f32;
+                }
+            }
+        }
+    }
+
+    fn parameters(&self) -> Vec<(String, f64)> {
+        self.specs
+            .iter()
+            .enumerate()
+            .flat_map(|(index, spec)| {
+                vec![
+                    (format!("{}.frequency", index), spec.frequency),
+                    (format!("{}.q", index), spec.q),
+                ]
+            })
+            .collect()
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f64) -> Result<(), String> {
+        let (index, parameter) = name
+            .split_once('.')
+            .and_then(|(index, parameter)| Some((index.parse::<usize>().ok()?, parameter)))
+            .filter(|(index, _)| *index < self.specs.len())
+            .ok_or_else(|| processor::unknown_parameter(self, name))?;
+        if !(value > 0.0 && value.is_finite()) {
+            return Err(format!("invalid value {} of the filter {}", value, name));
+        }
+        let mut spec = self.specs[index];
+        match parameter {
+            "frequency" => spec.frequency = value,
+            "q" => spec.q = value,
+            _ => return Err(processor::unknown_parameter(self, name)),
+        }
+        spec.validate(self.sample_rate)?;
+        let (b, a) = spec.coefficients(self.sample_rate);
+        for biquads in &mut self.biquads {
+            biquads[index].set_coefficients(b, a);
+        }
+        self.specs[index] = spec;
+        Ok(())
+    }
+}
@@ -0,0 +1,722 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! FLAC file writer, lossless compression of the recordings.
+//!
+//! The encoder uses the fixed polynomial predictors of FLAC, with Rice coded residuals
+//! in partitions and the stereo decorrelation of pairs of channels: the subset of FLAC
+//! that compresses well for the cost of a few integer operations per sample.
+
+use crate::wav::f32_to_i16;
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// frames per FLAC frame
+const BLOCK_SIZE: usize = 4096;
+/// size of the STREAMINFO metadata block
+const STREAMINFO_LEN: usize = 34;
+/// the FLAC format allows from 1 to 8 channels
+const MAX_CHANNELS: u16 = 8;
+
+pub const DEFAULT_COMPRESSION_LEVEL: u8 = 5;
+pub const MAX_COMPRESSION_LEVEL: u8 = 8;
+
+/// Convert a sample in the nominal interval of [-1,+1] to a 24 bits signed sample.
+fn f32_to_i24(sample: f32) -> i32 {
+    (sample * 8388608.0).round().clamp(-8388608.0, 8388607.0) as i32
+}
+
+/// CRC-8 of the frame headers, polynomial x^8 + x^2 + x + 1
+fn crc8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// CRC-16 of the frames, polynomial x^16 + x^15 + x^2 + 1
+fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ ((byte as u16) << 8), |crc, _| {
+            if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// Big endian bit writer.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    /// pending bits, in the low `bits` bits
+    accumulator: u64,
+    bits: u32,
+}
+
+impl BitWriter {
+    /// Write the low `bits` bits of `value`, at most 32.
+    fn write(&mut self, bits: u32, value: u64) {
+        debug_assert!(bits <= 32);
+        if bits == 0 {
+            return;
+        }
+        self.accumulator = (self.accumulator << bits) | (value & ((1 << bits) - 1));
+        self.bits += bits;
+        while self.bits >= 8 {
+            self.bits -= 8;
+            self.bytes.push((self.accumulator >> self.bits) as u8);
+        }
+    }
+
+    fn write_signed(&mut self, bits: u32, value: i64) {
+        self.write(bits, value as u64);
+    }
+
+    /// `count` zero bits followed by a one bit
+    fn write_unary(&mut self, mut count: u64) {
+        while count >= 32 {
+            self.write(32, 0);
+            count -= 32;
+        }
+        self.write(count as u32 + 1, 1);
+    }
+
+    /// Pad with zero bits to the next byte.
+    fn align(&mut self) {
+        if self.bits > 0 {
+            self.write(8 - self.bits, 0);
+        }
+    }
+}
+
+/// Encoding parameters of a compression level.
+#[derive(Clone, Copy, Debug)]
+struct Settings {
+    max_order: usize,
+    max_partition_order: u32,
+    stereo_decorrelation: bool,
+}
+
+impl Settings {
+    fn new(compression_level: u8) -> Self {
+        let level = compression_level.min(MAX_COMPRESSION_LEVEL);
+        Settings {
+            max_order: [1, 2, 2, 3, 4, 4, 4, 4, 4][level as usize],
+            max_partition_order: [2, 3, 3, 4, 4, 5, 6, 7, 8][level as usize],
+            stereo_decorrelation: level > 0,
+        }
+    }
+}
+
+/// Zigzag mapping of the signed residuals to the Rice coded values.
+fn fold(residual: i64) -> u64 {
+    ((residual << 1) ^ (residual >> 63)) as u64
+}
+
+/// Best Rice parameter for values with the given sum, and its cost in bits.
+fn rice_parameter(values: &[u64], max_parameter: u32) -> (u32, u64) {
+    let sum: u64 = values.iter().sum();
+    let mean = sum / values.len().max(1) as u64;
+    let estimate = (64 - mean.leading_zeros()).min(max_parameter);
+    (estimate.saturating_sub(1)..=(estimate + 1).min(max_parameter))
+        .map(|parameter| {
+            let cost = values.len() as u64 * (parameter as u64 + 1)
+                + values.iter().map(|&value| value >> parameter).sum::<u64>();
+            (parameter, cost)
+        })
+        .min_by_key(|&(_, cost)| cost)
+        .unwrap()
+}
+
+/// Residual of the fixed predictor of the given order of each sample after the warm-up.
+fn fixed_residuals(samples: &[i64], order: usize) -> Vec<i64> {
+    samples
+        .windows(order + 1)
+        .map(|window| {
+            let s = |delay: usize| window[order - delay];
+            match order {
+                0 => s(0),
+                1 => s(0) - s(1),
+                2 => s(0) - 2 * s(1) + s(2),
+                3 => s(0) - 3 * s(1) + 3 * s(2) - s(3),
+                _ => s(0) - 4 * s(1) + 6 * s(2) - 4 * s(3) + s(4),
+            }
+        })
+        .collect()
+}
+
+/// Rice coded residual: the partition order and the Rice parameter of each partition.
+struct Residual {
+    folded: Vec<u64>,
+    /// order of the predictor, the warm-up samples are not in the first partition
+    order: usize,
+    partition_order: u32,
+    parameters: Vec<u32>,
+    /// 5 bits Rice parameters, when any of them is above 14
+    wide_parameters: bool,
+    bits: u64,
+}
+
+impl Residual {
+    /// Choose the partition order of the lowest cost.
+    fn new(residuals: &[i64], block_size: usize, order: usize, max_partition_order: u32) -> Self {
+        let folded: Vec<u64> = residuals.iter().map(|&residual| fold(residual)).collect();
+        let mut best: Option<Residual> = None;
+        for partition_order in 0..=max_partition_order {
+            let partitions = 1 << partition_order;
+            if !block_size.is_multiple_of(partitions) || block_size / partitions <= order {
+                break;
+            }
+            let partition_len = block_size / partitions;
+            let mut parameters = Vec::with_capacity(partitions);
+            let mut bits = 0;
+            let mut start = 0;
+            for partition in 0..partitions {
+                // the warm-up samples are not in the first partition
+                let len = if partition == 0 {
+                    partition_len - order
+                } else {
+                    partition_len
+                };
+                let (parameter, cost) = rice_parameter(&folded[start..start + len], 30);
+                parameters.push(parameter);
+                bits += cost;
+                start += len;
+            }
+            let wide_parameters = parameters.iter().any(|&parameter| parameter > 14);
+            bits += 2 + 4 + partitions as u64 * if wide_parameters { 5 } else { 4 };
+            if best.as_ref().is_none_or(|best| bits < best.bits) {
+                best = Some(Residual {
+                    folded: Vec::new(),
+                    order,
+                    partition_order,
+                    parameters,
+                    wide_parameters,
+                    bits,
+                });
+            }
+        }
+        let mut best = best.expect("no partition order for the block size");
+        best.folded = folded;
+        best
+    }
+
+    fn write(&self, writer: &mut BitWriter) {
+        let parameter_bits = if self.wide_parameters { 5 } else { 4 };
+        writer.write(2, self.wide_parameters as u64);
+        writer.write(4, self.partition_order as u64);
+        let partition_len = (self.folded.len() + self.order) >> self.partition_order;
+        let mut start = 0;
+        for (partition, &parameter) in self.parameters.iter().enumerate() {
+            let len = if partition == 0 {
+                partition_len - self.order
+            } else {
+                partition_len
+            };
+            writer.write(parameter_bits, parameter as u64);
+            for &value in &self.folded[start..start + len] {
+                writer.write_unary(value >> parameter);
+                writer.write(parameter, value);
+            }
+            start += len;
+        }
+    }
+}
+
+enum SubframeKind {
+    Constant,
+    Verbatim,
+    Fixed(usize, Residual),
+}
+
+/// Encode a subframe with the lowest cost of the constant, verbatim and fixed predictors.
+fn encode_subframe(
+    writer: &mut BitWriter,
+    samples: &[i64],
+    bits_per_sample: u32,
+    settings: Settings,
+) {
+    let kind = if samples.iter().all(|&sample| sample == samples[0]) {
+        SubframeKind::Constant
+    } else {
+        let verbatim_bits = samples.len() as u64 * bits_per_sample as u64;
+        (0..=settings.max_order.min(samples.len() - 1))
+            .map(|order| {
+                let residual = Residual::new(
+                    &fixed_residuals(samples, order),
+                    samples.len(),
+                    order,
+                    settings.max_partition_order,
+                );
+                (order, residual)
+            })
+            .filter(|(order, residual)| {
+                (*order as u64 * bits_per_sample as u64 + residual.bits) < verbatim_bits
+            })
+            .min_by_key(|(order, residual)| *order as u64 * bits_per_sample as u64 + residual.bits)
+            .map_or(SubframeKind::Verbatim, |(order, residual)| {
+                SubframeKind::Fixed(order, residual)
+            })
+    };
+
+    // zero padding bit, subframe type and no wasted bits
+    match kind {
+        SubframeKind::Constant => {
+            writer.write(8, 0b0000_0000);
+            writer.write_signed(bits_per_sample, samples[0]);
+        }
+        SubframeKind::Verbatim => {
+            writer.write(8, 0b0000_0010);
+            for &sample in samples {
+                writer.write_signed(bits_per_sample, sample);
+            }
+        }
+        SubframeKind::Fixed(order, residual) => {
+            writer.write(8, (0b0000_1000 | order as u64) << 1);
+            for &sample in &samples[..order] {
+                writer.write_signed(bits_per_sample, sample);
+            }
+            residual.write(writer);
+        }
+    }
+}
+
+/// Cost in bits of the residual of the fixed predictor of order 2, to choose the
+/// stereo decorrelation.
+fn estimate_bits(samples: &[i64]) -> u64 {
+    if samples.len() < 3 {
+        return 0;
+    }
+    let folded: Vec<u64> = fixed_residuals(samples, 2).into_iter().map(fold).collect();
+    rice_parameter(&folded, 30).1
+}
+
+/// Writes interleaved samples to a FLAC file: 16 bits for integer sample formats
+/// and 24 bits for floating point samples.
+///
+/// The STREAMINFO of the file is only complete after [`FlacWriter::finalize`],
+/// without the MD5 signature of the samples, which is left unset.
+pub struct FlacWriter<W: Write + Seek> {
+    writer: W,
+    settings: Settings,
+    channels: u16,
+    sample_rate: u32,
+    bits_per_sample: u32,
+    /// samples of the next frame not written yet, of each channel
+    pending: Vec<Vec<i64>>,
+    frame_number: u64,
+    total_samples: u64,
+    min_frame_len: u32,
+    max_frame_len: u32,
+    finalized: bool,
+}
+
+impl FlacWriter<BufWriter<File>> {
+    pub fn create(
+        path: impl AsRef<Path>,
+        config: &cpal::SupportedStreamConfig,
+        compression_level: u8,
+    ) -> io::Result<Self> {
+        FlacWriter::new(
+            BufWriter::new(File::create(path)?),
+            config,
+            compression_level,
+        )
+    }
+}
+
+impl<W: Write + Seek> FlacWriter<W> {
+    /// The compression level goes from 0, the fastest, to 8, the smallest.
+    pub fn new(
+        writer: W,
+        config: &cpal::SupportedStreamConfig,
+        compression_level: u8,
+    ) -> io::Result<Self> {
+        let bits_per_sample = match config.sample_format() {
+            cpal::SampleFormat::U16 | cpal::SampleFormat::I16 => 16,
+            cpal::SampleFormat::I32 | cpal::SampleFormat::F32 => 24,
+            sample_format => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("unsupported sample format {:?}", sample_format),
+                ))
+            }
+        };
+        let channels = config.channels();
+        if channels == 0 || channels > MAX_CHANNELS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "unsupported {} channel(s), FLAC has at most {}",
+                    channels, MAX_CHANNELS
+                ),
+            ));
+        }
+
+        let mut flac_writer = FlacWriter {
+            writer,
+            settings: Settings::new(compression_level),
+            channels,
+            sample_rate: config.sample_rate().0,
+            bits_per_sample,
+            pending: vec![Vec::with_capacity(BLOCK_SIZE); channels as usize],
+            frame_number: 0,
+            total_samples: 0,
+            min_frame_len: u32::MAX,
+            max_frame_len: 0,
+            finalized: false,
+        };
+        // the frame sizes and the total samples are written on finalize
+        let streaminfo = flac_writer.streaminfo();
+        flac_writer.writer.write_all(b"fLaC")?;
+        flac_writer.writer.write_all(&streaminfo)?;
+        Ok(flac_writer)
+    }
+
+    /// The only metadata block, with the last metadata block flag.
+    fn streaminfo(&self) -> Vec<u8> {
+        let mut writer = BitWriter::default();
+        writer.write(1, 1);
+        writer.write(7, 0);
+        writer.write(24, STREAMINFO_LEN as u64);
+        // the last block may be shorter
+        writer.write(16, BLOCK_SIZE as u64);
+        writer.write(16, BLOCK_SIZE as u64);
+        let (min_frame_len, max_frame_len) = if self.max_frame_len > 0 {
+            (self.min_frame_len, self.max_frame_len)
+        } else {
+            // unknown
+            (0, 0)
+        };
+        writer.write(24, min_frame_len as u64);
+        writer.write(24, max_frame_len as u64);
+        writer.write(20, self.sample_rate as u64);
+        writer.write(3, self.channels as u64 - 1);
+        writer.write(5, self.bits_per_sample as u64 - 1);
+        writer.write(4, self.total_samples >> 32);
+        writer.write(32, self.total_samples);
+        // unset MD5 signature
+        for _ in 0..4 {
+            writer.write(32, 0);
+        }
+        writer.bytes
+    }
+
+    /// Write the samples of an input buffer, already interleaved.
+    pub fn write_interleaved(&mut self, samples: &[f32]) -> io::Result<()> {
+        assert!(samples.len().is_multiple_of(self.channels as usize));
+        assert!(!self.finalized, "write to a finalized FLAC writer");
+        for frame in samples.chunks_exact(self.channels as usize) {
+            for (&sample, pending) in frame.iter().zip(&mut self.pending) {
+                pending.push(if self.bits_per_sample == 16 {
+                    f32_to_i16(sample) as i64
+                } else {
+                    f32_to_i24(sample) as i64
+                });
+            }
+            if self.pending[0].len() == BLOCK_SIZE {
+                self.write_frame()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Encode the pending samples as a frame.
+    fn write_frame(&mut self) -> io::Result<()> {
+        let block_size = self.pending[0].len();
+        if block_size == 0 {
+            return Ok(());
+        }
+
+        // independent channels, or left/side, right/side or mid/side of a stereo pair
+        let mut channel_assignment = self.channels as u64 - 1;
+        let mut subframes: Vec<(Vec<i64>, u32)> = Vec::with_capacity(self.channels as usize);
+        if self.channels == 2 && self.settings.stereo_decorrelation {
+            let (left, right) = (&self.pending[0], &self.pending[1]);
+            let mid: Vec<i64> = left.iter().zip(right).map(|(l, r)| (l + r) >> 1).collect();
+            let side: Vec<i64> = left.iter().zip(right).map(|(l, r)| l - r).collect();
+            let (left_bits, right_bits) = (estimate_bits(left), estimate_bits(right));
+            let (mid_bits, side_bits) = (estimate_bits(&mid), estimate_bits(&side));
+            let bps = self.bits_per_sample;
+            let assignments = [
+                (1, left_bits + right_bits),
+                (8, left_bits + side_bits),
+                (9, right_bits + side_bits),
+                (10, mid_bits + side_bits),
+            ];
+            let &(assignment, _) = assignments.iter().min_by_key(|(_, bits)| bits).unwrap();
+            channel_assignment = assignment;
+            subframes = match assignment {
+                8 => vec![(left.clone(), bps), (side, bps + 1)],
+                9 => vec![(side, bps + 1), (right.clone(), bps)],
+                10 => vec![(mid, bps), (side, bps + 1)],
+                _ => vec![(left.clone(), bps), (right.clone(), bps)],
+            };
+        } else {
+            for channel in &self.pending {
+                subframes.push((channel.clone(), self.bits_per_sample));
+            }
+        }
+
+        let mut writer = BitWriter::default();
+        // sync code, fixed block size strategy, 16 bits block size at the end of the header,
+        // sample rate of the STREAMINFO and explicit sample size, that some decoders require
+        writer.write(16, 0xfff8);
+        writer.write(4, 0b0111);
+        writer.write(4, 0);
+        writer.write(4, channel_assignment);
+        writer.write(
+            3,
+            if self.bits_per_sample == 16 {
+                0b100
+            } else {
+                0b110
+            },
+        );
+        writer.write(1, 0);
+        write_utf8_number(&mut writer, self.frame_number);
+        writer.write(16, block_size as u64 - 1);
+        let crc = crc8(&writer.bytes);
+        writer.write(8, crc as u64);
+
+        for (samples, bits_per_sample) in &subframes {
+            encode_subframe(&mut writer, samples, *bits_per_sample, self.settings);
+        }
+        writer.align();
+        let crc = crc16(&writer.bytes);
+        writer.write(16, crc as u64);
+
+        self.writer.write_all(&writer.bytes)?;
+        let frame_len = writer.bytes.len() as u32;
+        self.min_frame_len = self.min_frame_len.min(frame_len);
+        self.max_frame_len = self.max_frame_len.max(frame_len);
+        self.total_samples += block_size as u64;
+        self.frame_number += 1;
+        for pending in &mut self.pending {
+            pending.clear();
+        }
+        Ok(())
+    }
+
+    /// Write the last frame and the STREAMINFO with the frame sizes and the total samples,
+    /// and flush, the writer can not be written after that.
+    pub fn finalize(&mut self) -> io::Result<()> {
+        if self.finalized {
+            return self.writer.flush();
+        }
+        self.write_frame()?;
+        self.finalized = true;
+
+        let end_pos = self.writer.stream_position()?;
+        self.writer.seek(SeekFrom::Start(4))?;
+        let streaminfo = self.streaminfo();
+        self.writer.write_all(&streaminfo)?;
+        self.writer.seek(SeekFrom::Start(end_pos))?;
+        self.writer.flush()
+    }
+}
+
+/// Frame number coded as in UTF-8, extended up to 36 bits.
+fn write_utf8_number(writer: &mut BitWriter, number: u64) {
+    if number < 0x80 {
+        writer.write(8, number);
+        return;
+    }
+    // bytes after the first one, 6 bits each
+    let continuation_bytes = (1..6)
+        .find(|&bytes| number < 1 << (6 - bytes + 6 * bytes))
+        .unwrap_or(6);
+    let prefix = (0xff00_u64 >> (continuation_bytes + 1)) & 0xff;
+    writer.write(8, prefix | (number >> (6 * continuation_bytes)));
+    for byte in (0..continuation_bytes).rev() {
+        writer.write(8, 0x80 | ((number >> (6 * byte)) & 0x3f));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// The finalized file of the interleaved samples.
+    fn write_file(
+        samples: &[f32],
+        channels: u16,
+        sample_format: cpal::SampleFormat,
+        compression_level: u8,
+    ) -> Vec<u8> {
+        let config = cpal::SupportedStreamConfig::new(
+            channels,
+            cpal::SampleRate(48000),
+            cpal::SupportedBufferSize::Unknown,
+            sample_format,
+        );
+        let mut file = Cursor::new(Vec::new());
+        let mut writer = FlacWriter::new(&mut file, &config, compression_level).unwrap();
+        // in buffers that do not match the block size
+        for buffer in samples.chunks(1000 * channels as usize) {
+            writer.write_interleaved(buffer).unwrap();
+        }
+        writer.finalize().unwrap();
+        drop(writer);
+        file.into_inner()
+    }
+
+    /// A sine of a different frequency on each channel, with some noise.
+    fn sine(num_frames: usize, channels: u16) -> Vec<f32> {
+        let mut seed = 1_u32;
+        (0..num_frames)
+            .flat_map(|frame| (0..channels).map(move |channel| (frame, channel)))
+            .map(|(frame, channel)| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                let noise = (seed >> 16) as f32 / 65536.0 - 0.5;
+                let frequency = 440.0 * (channel + 1) as f32;
+                0.8 * (std::f32::consts::TAU * frequency * frame as f32 / 48000.0).sin()
+                    + 0.001 * noise
+            })
+            .collect()
+    }
+
+    fn assert_round_trip(
+        samples: &[f32],
+        channels: u16,
+        sample_format: cpal::SampleFormat,
+        compression_level: u8,
+    ) {
+        let file = write_file(samples, channels, sample_format, compression_level);
+        let mut reader = claxon::FlacReader::new(Cursor::new(&file)).unwrap();
+        let bits_per_sample = if sample_format == cpal::SampleFormat::I16 {
+            16
+        } else {
+            24
+        };
+        let info = reader.streaminfo();
+        assert_eq!(info.sample_rate, 48000);
+        assert_eq!(info.channels, channels as u32);
+        assert_eq!(info.bits_per_sample, bits_per_sample);
+        assert_eq!(
+            info.samples,
+            Some((samples.len() / channels as usize) as u64)
+        );
+        assert!(info.min_frame_size.unwrap() > 0);
+        assert!(info.max_frame_size.unwrap() >= info.min_frame_size.unwrap());
+
+        // claxon checks the CRC-8 and CRC-16 of every frame
+        let decoded: Vec<i32> = reader.samples().map(Result::unwrap).collect();
+        let expected: Vec<i32> = samples
+            .iter()
+            .map(|&sample| {
+                if bits_per_sample == 16 {
+                    f32_to_i16(sample) as i32
+                } else {
+                    f32_to_i24(sample)
+                }
+            })
+            .collect();
+        assert_eq!(decoded, expected);
+    }
+
+    #[test]
+    fn crc_check_values() {
+        assert_eq!(crc8(b"123456789"), 0xf4);
+        assert_eq!(crc16(b"123456789"), 0xfee8);
+    }
+
+    #[test]
+    fn utf8_frame_numbers() {
+        let encode = |number| {
+            let mut writer = BitWriter::default();
+            write_utf8_number(&mut writer, number);
+            writer.bytes
+        };
+        assert_eq!(encode(0x7f), [0x7f]);
+        assert_eq!(encode(0x80), [0xc2, 0x80]);
+        assert_eq!(encode(0x7ff), [0xdf, 0xbf]);
+        assert_eq!(encode(0x800), [0xe0, 0xa0, 0x80]);
+        assert_eq!(encode(0x10000), [0xf0, 0x90, 0x80, 0x80]);
+    }
+
+    #[test]
+    fn header_before_any_frame() {
+        let file = write_file(&[], 2, cpal::SampleFormat::I16, DEFAULT_COMPRESSION_LEVEL);
+        assert_eq!(&file[..4], b"fLaC");
+        // last metadata block, STREAMINFO, 34 bytes
+        assert_eq!(file[4..8], [0x80, 0, 0, STREAMINFO_LEN as u8]);
+        assert_eq!(file.len(), 8 + STREAMINFO_LEN);
+    }
+
+    #[test]
+    fn stereo_16_bits_round_trip() {
+        // several blocks and a last shorter one
+        let samples = sine(3 * BLOCK_SIZE + 123, 2);
+        for compression_level in 0..=MAX_COMPRESSION_LEVEL {
+            assert_round_trip(&samples, 2, cpal::SampleFormat::I16, compression_level);
+        }
+    }
+
+    #[test]
+    fn float_24_bits_round_trip() {
+        assert_round_trip(
+            &sine(BLOCK_SIZE + 1, 1),
+            1,
+            cpal::SampleFormat::F32,
+            DEFAULT_COMPRESSION_LEVEL,
+        );
+        assert_round_trip(
+            &sine(2 * BLOCK_SIZE, 3),
+            3,
+            cpal::SampleFormat::F32,
+            DEFAULT_COMPRESSION_LEVEL,
+        );
+    }
+
+    #[test]
+    fn silence_and_full_scale_round_trip() {
+        let mut samples = vec![0.0; 2 * BLOCK_SIZE];
+        samples.extend((0..2 * BLOCK_SIZE).map(|i| if i % 4 < 2 { 1.0 } else { -1.0 }));
+        assert_round_trip(
+            &samples,
+            2,
+            cpal::SampleFormat::I16,
+            DEFAULT_COMPRESSION_LEVEL,
+        );
+        assert_round_trip(
+            &samples,
+            2,
+            cpal::SampleFormat::F32,
+            DEFAULT_COMPRESSION_LEVEL,
+        );
+    }
+
+    #[test]
+    fn rejects_too_many_channels() {
+        let config = cpal::SupportedStreamConfig::new(
+            9,
+            cpal::SampleRate(48000),
+            cpal::SupportedBufferSize::Unknown,
+            cpal::SampleFormat::I16,
+        );
+        assert!(FlacWriter::new(Cursor::new(Vec::new()), &config, 5).is_err());
+    }
+}
@@ -0,0 +1,241 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Fragmented MP4 (ISO/IEC 14496-12) of a single audio track, AAC or Opus, as the
+//! initialization and the media segments of HLS or DASH.
+
+/// Decoder configuration of the audio track.
+#[derive(Clone, Debug)]
+pub enum Fmp4Codec {
+    /// AAC, with its `AudioSpecificConfig`
+    Aac { audio_specific_config: Vec<u8> },
+    /// Opus, with the samples at 48 kHz the decoder skips at the start
+    Opus { pre_skip: u16 },
+}
+
+const TRACK_ID: u32 = 1;
+/// Unity matrix of the movie and the track headers.
+const MATRIX: [u32; 9] = [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000];
+
+/// A box with its type and content.
+fn mp4_box(box_type: &[u8; 4], content: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(8 + content.len());
+    bytes.extend_from_slice(&(8 + content.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(box_type);
+    bytes.extend_from_slice(content);
+    bytes
+}
+
+/// A full box, with its version and flags before the content.
+fn full_box(box_type: &[u8; 4], version: u8, flags: u32, content: &[u8]) -> Vec<u8> {
+    let mut bytes = ((version as u32) << 24 | flags).to_be_bytes().to_vec();
+    bytes.extend_from_slice(content);
+    mp4_box(box_type, &bytes)
+}
+
+/// A box of the concatenated children.
+fn container(box_type: &[u8; 4], children: &[Vec<u8>]) -> Vec<u8> {
+    mp4_box(box_type, &children.concat())
+}
+
+fn u32s(values: &[u32]) -> Vec<u8> {
+    values
+        .iter()
+        .flat_map(|value| value.to_be_bytes())
+        .collect()
+}
+
+/// An MPEG-4 descriptor of the `esds` box, with a length of a single byte.
+fn descriptor(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![tag, content.len() as u8];
+    bytes.extend_from_slice(content);
+    bytes
+}
+
+/// Writes the segments of the track, of samples of the same duration.
+pub struct Fmp4Writer {
+    codec: Fmp4Codec,
+    channels: u16,
+    /// of the timestamps and the durations of the samples
+    timescale: u32,
+    bitrate: u32,
+    sequence_number: u32,
+}
+
+impl Fmp4Writer {
+    pub fn new(codec: Fmp4Codec, channels: u16, timescale: u32, bitrate: u32) -> Self {
+        Fmp4Writer {
+            codec,
+            channels,
+            timescale,
+            bitrate,
+            sequence_number: 0,
+        }
+    }
+
+    /// Initialization segment, with the description of the track.
+    pub fn init_segment(&self) -> Vec<u8> {
+        let ftyp = mp4_box(b"ftyp", b"iso6\0\0\0\0iso6mp41");
+        let mut mvhd = u32s(&[0, 0, self.timescale, 0, 0x0001_0000]);
+        // volume and reserved
+        mvhd.extend_from_slice(&[0x01, 0x00, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        mvhd.extend_from_slice(&u32s(&MATRIX));
+        mvhd.extend_from_slice(&[0; 24]);
+        mvhd.extend_from_slice(&(TRACK_ID + 1).to_be_bytes());
+        let mut tkhd = u32s(&[0, 0, TRACK_ID, 0, 0, 0, 0]);
+        // layer, alternate group, volume and reserved
+        tkhd.extend_from_slice(&[0, 0, 0, 0, 0x01, 0x00, 0, 0]);
+        tkhd.extend_from_slice(&u32s(&MATRIX));
+        tkhd.extend_from_slice(&[0; 8]);
+        let mut mdhd = u32s(&[0, 0, self.timescale, 0]);
+        // language `und` and pre-defined
+        mdhd.extend_from_slice(&[0x55, 0xc4, 0, 0]);
+        let mut hdlr = u32s(&[0]);
+        hdlr.extend_from_slice(b"soun");
+        hdlr.extend_from_slice(&[0; 12]);
+        hdlr.extend_from_slice(b"SoundHandler\0");
+        let dref = full_box(
+            b"dref",
+            0,
+            0,
+            &[&1_u32.to_be_bytes()[..], &full_box(b"url ", 0, 1, &[])].concat(),
+        );
+        let stbl = container(
+            b"stbl",
+            &[
+                full_box(
+                    b"stsd",
+                    0,
+                    0,
+                    &[&1_u32.to_be_bytes()[..], &self.sample_entry()].concat(),
+                ),
+                full_box(b"stts", 0, 0, &u32s(&[0])),
+                full_box(b"stsc", 0, 0, &u32s(&[0])),
+                full_box(b"stsz", 0, 0, &u32s(&[0, 0])),
+                full_box(b"stco", 0, 0, &u32s(&[0])),
+            ],
+        );
+        let minf = container(
+            b"minf",
+            &[
+                full_box(b"smhd", 0, 0, &[0; 4]),
+                container(b"dinf", &[dref]),
+                stbl,
+            ],
+        );
+        let trak = container(
+            b"trak",
+            &[
+                // enabled and in the movie
+                full_box(b"tkhd", 0, 0x03, &tkhd),
+                container(
+                    b"mdia",
+                    &[
+                        full_box(b"mdhd", 0, 0, &mdhd),
+                        full_box(b"hdlr", 0, 0, &hdlr),
+                        minf,
+                    ],
+                ),
+            ],
+        );
+        let mvex = container(
+            b"mvex",
+            &[full_box(b"trex", 0, 0, &u32s(&[TRACK_ID, 1, 0, 0, 0]))],
+        );
+        let moov = container(b"moov", &[full_box(b"mvhd", 0, 0, &mvhd), trak, mvex]);
+        [ftyp, moov].concat()
+    }
+
+    /// Audio sample entry of the codec.
+    fn sample_entry(&self) -> Vec<u8> {
+        let sample_rate = match self.codec {
+            Fmp4Codec::Aac { .. } => self.timescale,
+            Fmp4Codec::Opus { .. } => 48000,
+        };
+        // reserved, data reference index, reserved, channels, sample size,
+        // pre-defined, reserved and the sample rate in 16.16
+        let mut entry = vec![0, 0, 0, 0, 0, 0, 0, 1];
+        entry.extend_from_slice(&[0; 8]);
+        entry.extend_from_slice(&self.channels.to_be_bytes());
+        entry.extend_from_slice(&16_u16.to_be_bytes());
+        entry.extend_from_slice(&[0; 4]);
+        entry.extend_from_slice(&(sample_rate << 16).to_be_bytes());
+        match &self.codec {
+            Fmp4Codec::Aac {
+                audio_specific_config,
+            } => {
+                // MPEG-4 audio, an audio stream, buffer size, maximum and average bitrate
+                let mut decoder_config = vec![0x40, 0x15, 0, 0, 0];
+                decoder_config.extend_from_slice(&u32s(&[self.bitrate, self.bitrate]));
+                decoder_config.extend_from_slice(&descriptor(0x05, audio_specific_config));
+                // ES id and flags, the decoder configuration and the predefined SL configuration
+                let mut es = vec![0, TRACK_ID as u8, 0];
+                es.extend_from_slice(&descriptor(0x04, &decoder_config));
+                es.extend_from_slice(&descriptor(0x06, &[0x02]));
+                entry.extend_from_slice(&full_box(b"esds", 0, 0, &descriptor(0x03, &es)));
+                mp4_box(b"mp4a", &entry)
+            }
+            Fmp4Codec::Opus { pre_skip } => {
+                // version, channels, pre-skip, input sample rate, gain and mapping family 0
+                let mut dops = vec![0, self.channels as u8];
+                dops.extend_from_slice(&pre_skip.to_be_bytes());
+                dops.extend_from_slice(&self.timescale.to_be_bytes());
+                dops.extend_from_slice(&[0, 0, 0]);
+                entry.extend_from_slice(&mp4_box(b"dOps", &dops));
+                mp4_box(b"Opus", &entry)
+            }
+        }
+    }
+
+    /// Media segment of the samples of `sample_duration` each, starting at
+    /// `decode_time` in timescale units.
+    pub fn media_segment(
+        &mut self,
+        samples: &[Vec<u8>],
+        decode_time: u64,
+        sample_duration: u32,
+    ) -> Vec<u8> {
+        self.sequence_number += 1;
+        // data offset, and the duration and the size of each sample
+        let mut trun = u32s(&[samples.len() as u32, 0]);
+        for sample in samples {
+            trun.extend_from_slice(&u32s(&[sample_duration, sample.len() as u32]));
+        }
+        let traf = |trun: &[u8]| {
+            container(
+                b"traf",
+                &[
+                    // base data offset at the start of the moof box
+                    full_box(b"tfhd", 0, 0x02_0000, &TRACK_ID.to_be_bytes()),
+                    full_box(b"tfdt", 1, 0, &decode_time.to_be_bytes()),
+                    full_box(b"trun", 0, 0x0301, trun),
+                ],
+            )
+        };
+        let moof = |trun: &[u8]| {
+            container(
+                b"moof",
+                &[
+                    full_box(b"mfhd", 0, 0, &self.sequence_number.to_be_bytes()),
+                    traf(trun),
+                ],
+            )
+        };
+        // the data offset is from the start of the moof box to the data of the mdat box
+        let data_offset = moof(&trun).len() as u32 + 8;
+        trun[4..8].copy_from_slice(&data_offset.to_be_bytes());
+        [moof(&trun), mp4_box(b"mdat", &samples.concat())].concat()
+    }
+}
@@ -0,0 +1,120 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Software gain of each channel, adjustable and mutable while capturing.
+
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
+
+/// lowest gain in dB, below the noise floor of 16 bits samples
+pub const MIN_DB: f32 = -96.0;
+/// highest gain in dB
+pub const MAX_DB: f32 = 48.0;
+
+/// Whether the gain in dB is between [`MIN_DB`] and [`MAX_DB`].
+pub fn is_valid_db(gain: f32) -> bool {
+    (MIN_DB..=MAX_DB).contains(&gain)
+}
+
+/// Gain of each channel in decibels, shared between the audio thread
+/// and whoever adjusts it, without locking.
+#[derive(Clone)]
+pub struct ChannelGains {
+    /// bits of the linear `f32` gains
+    gains: Arc<[AtomicU32]>,
+    muted: Arc<[AtomicBool]>,
+}
+
+impl ChannelGains {
+    /// 0 dB and not muted for all the channels.
+    pub fn new(num_channels: usize) -> Self {
+        ChannelGains {
+            gains: (0..num_channels)
+                .map(|_| AtomicU32::new(1_f32.to_bits()))
+                .collect(),
+            muted: (0..num_channels).map(|_| AtomicBool::new(false)).collect(),
+        }
+    }
+
+    pub fn num_channels(&self) -> usize {
+        self.gains.len()
+    }
+
+    pub fn set_db(&self, channel: usize, gain: f32) {
+        self.gains[channel].store(10_f32.powf(gain / 20.0).to_bits(), Ordering::Relaxed);
+    }
+
+    /// Gain of the channel, kept while muted.
+    pub fn db(&self, channel: usize) -> f32 {
+        20.0 * f32::from_bits(self.gains[channel].load(Ordering::Relaxed)).log10()
+    }
+
+    pub fn set_muted(&self, channel: usize, muted: bool) {
+        self.muted[channel].store(muted, Ordering::Relaxed);
+    }
+
+    pub fn is_muted(&self, channel: usize) -> bool {
+        self.muted[channel].load(Ordering::Relaxed)
+    }
+
+    /// Factor to multiply the samples of the channel, 0 while muted.
+    pub fn linear(&self, channel: usize) -> f32 {
+        if self.is_muted(channel) {
+            return 0.0;
+        }
+        f32::from_bits(self.gains[channel].load(Ordering::Relaxed))
+    }
+
+    /// Parse the gains as either a gain in dB for all the channels, e.g. `+6`,
+    /// or comma separated gains of some channels, e.g. `0:+6,1:-3`.
+    pub fn parse(&self, gains: &str) -> Result<(), String> {
+        let parse_db = |gain: &str| match gain.trim().parse::<f32>() {
+            Ok(gain) if is_valid_db(gain) => Ok(gain),
+            _ => Err(format!(
+                "invalid gain '{}' dB, it must be between {} and +{} dB",
+                gain, MIN_DB, MAX_DB
+            )),
+        };
+
+        if !gains.contains(':') {
+            let gain = parse_db(gains)?;
+            for channel in 0..self.num_channels() {
+                self.set_db(channel, gain);
+            }
+            return Ok(());
+        }
+
+        for channel_gain in gains.split(',') {
+            let (channel, gain) = channel_gain.split_once(':').ok_or_else(|| {
+                format!(
+                    "invalid channel gain '{}', expected CHANNEL:dB",
+                    channel_gain
+                )
+            })?;
+            let channel = match channel.trim().parse::<usize>() {
+                Ok(channel) if channel < self.num_channels() => channel,
+                _ => {
+                    return Err(format!(
+                        "invalid channel '{}' for the gain, there are {} channel(s)",
+                        channel,
+                        self.num_channels()
+                    ))
+                }
+            };
+            self.set_db(channel, parse_db(gain)?);
+        }
+        Ok(())
+    }
+}
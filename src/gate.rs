@@ -0,0 +1,133 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Noise gate and downward expander of a stream of interleaved samples, to keep the bleed
+//! and the noise between takes out of the recordings and the streams.
+//!
+//! Each channel opens when its peak level rises over the threshold, and closes when it
+//! falls under the threshold minus the hysteresis. While closed, the level under the
+//! threshold is expanded by the ratio, an infinite ratio muting the channel as a gate.
+
+/// dBFS
+pub const DEFAULT_THRESHOLD: f32 = -50.0;
+/// an infinite ratio is a gate
+pub const DEFAULT_RATIO: f32 = f32::INFINITY;
+/// seconds
+pub const DEFAULT_ATTACK: f32 = 0.001;
+pub const DEFAULT_RELEASE: f32 = 0.1;
+/// dB
+pub const DEFAULT_HYSTERESIS: f32 = 6.0;
+/// seconds, time constant of the decay of the peak level
+const DETECTOR_RELEASE: f32 = 0.05;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GateConfig {
+    /// dBFS, peak level opening the gate
+    pub threshold: f32,
+    /// of the expansion of the level under the threshold, at least 1
+    pub ratio: f32,
+    /// seconds, time constants of the gain rising as the gate opens, and falling
+    pub attack: f32,
+    pub release: f32,
+    /// dB, under the threshold, where the gate closes
+    pub hysteresis: f32,
+}
+
+impl Default for GateConfig {
+    fn default() -> Self {
+        GateConfig {
+            threshold: DEFAULT_THRESHOLD,
+            ratio: DEFAULT_RATIO,
+            attack: DEFAULT_ATTACK,
+            release: DEFAULT_RELEASE,
+            hysteresis: DEFAULT_HYSTERESIS,
+        }
+    }
+}
+
+/// One-pole smoothing coefficient of a time constant, at a sample rate,
+/// 1 without smoothing.
+fn coefficient(time: f32, sample_rate: u32) -> f32 {
+    if time > 0.0 {
+        1.0 - (-1.0 / (time * sample_rate as f32)).exp()
+    } else {
+        1.0
+    }
+}
+
+#[derive(Clone, Default)]
+struct ChannelGate {
+    /// linear
+    peak: f32,
+    open: bool,
+    /// linear
+    gain: f32,
+}
+
+pub struct NoiseGate {
+    config: GateConfig,
+    detector_coefficient: f32,
+    attack_coefficient: f32,
+    release_coefficient: f32,
+    channels: Vec<ChannelGate>,
+}
+
+impl NoiseGate {
+    pub fn new(config: GateConfig, num_channels: usize, sample_rate: u32) -> Self {
+        NoiseGate {
+            config,
+            detector_coefficient: coefficient(DETECTOR_RELEASE, sample_rate),
+            attack_coefficient: coefficient(config.attack, sample_rate),
+            release_coefficient: coefficient(config.release, sample_rate),
+            channels: vec![ChannelGate::default(); num_channels],
+        }
+    }
+
+    /// Gate a buffer of interleaved samples in place.
+    pub fn process(&mut self, samples: &mut [f32]) {
+        let config = self.config;
+        for frame in samples.chunks_exact_mut(self.channels.len()) {
+            for (sample, gate) in frame.iter_mut().zip(&mut self.channels) {
+                let magnitude = sample.abs();
+                gate.peak = if magnitude > gate.peak {
+                    magnitude
+                } else {
+                    gate.peak + self.detector_coefficient * (magnitude - gate.peak)
+                };
+                let level = 20.0 * gate.peak.log10();
+                if level > config.threshold {
+                    gate.open = true;
+                } else if level < config.threshold - config.hysteresis {
+                    gate.open = false;
+                }
+                let gain = if gate.open {
+                    1.0
+                } else if config.ratio.is_infinite() || gate.peak == 0.0 {
+                    0.0
+                } else {
+                    let expansion = (level - config.threshold) * (config.ratio - 1.0);
+                    10_f32.powf(expansion.min(0.0) / 20.0)
+                };
+                let coefficient = if gain > gate.gain {
+                    self.attack_coefficient
+                } else {
+                    self.release_coefficient
+                };
+                gate.gain += coefficient * (gain - gate.gain);
+                *sample *= gate.gain;
+            }
+        }
+    }
+}
@@ -0,0 +1,263 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Test signal generator playing on an output device: a sine alignment tone, white or
+//! pink noise, or a logarithmic sweep, for loopback tests of the input metering.
+//!
+//! The levels are in dBFS as the meters (AES17), the RMS of a full scale sine wave is
+//! 0 dBFS, so a noise at the level of a tone reads the same.
+
+use crate::{parse_duration, parse_frequency, parse_level};
+use cpal::traits::{DeviceTrait, StreamTrait};
+use std::f64::consts::PI;
+use std::time::Duration;
+
+/// dBFS, level of the signals when not given
+pub const DEFAULT_LEVEL: f32 = -20.0;
+
+/// RMS of the pink noise filter of uniform white noise from -1 to 1
+const PINK_NOISE_RMS: f32 = 1.7312;
+
+/// Test signal, with its level in dBFS.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Signal {
+    /// sine wave of the frequency in Hz
+    Sine { frequency: f64, level: f32 },
+    /// noise of equal power per Hz
+    WhiteNoise { level: f32 },
+    /// noise of equal power per octave
+    PinkNoise { level: f32 },
+    /// sine wave from the start to the end frequency in Hz, exponentially in the
+    /// duration in seconds, repeated
+    Sweep {
+        start: f64,
+        end: f64,
+        duration: f64,
+        level: f32,
+    },
+}
+
+impl Signal {
+    /// Parse `sine:<frequency>[:<level>]`, `white[:<level>]`, `pink[:<level>]` or
+    /// `sweep:<start>:<end>:<duration>[:<level>]`, with the frequencies in Hz or kHz
+    /// as `1000Hz` or `1kHz`, the level in dBFS as `-20dBFS` and the duration as `10s`.
+    pub fn parse(signal: &str) -> Result<Self, String> {
+        let invalid = |reason: &str| format!("invalid signal '{}', {}", signal, reason);
+        let fields: Vec<&str> = signal.split(':').collect();
+        let (kind, params) = fields.split_first().expect("split returns a field");
+        let level = |index: usize| match params.get(index) {
+            Some(level) => parse_level(level).map_err(|err| invalid(&err)),
+            None => Ok(DEFAULT_LEVEL),
+        };
+        let frequency = |index: usize| match params.get(index) {
+            Some(frequency) => parse_frequency(frequency).map_err(|err| invalid(&err)),
+            None => Err(invalid("missing frequency")),
+        };
+        let (signal, num_params) = match kind.to_ascii_lowercase().as_str() {
+            "sine" => (
+                Signal::Sine {
+                    frequency: frequency(0)?,
+                    level: level(1)?,
+                },
+                2,
+            ),
+            "white" => (Signal::WhiteNoise { level: level(0)? }, 1),
+            "pink" => (Signal::PinkNoise { level: level(0)? }, 1),
+            "sweep" => (
+                Signal::Sweep {
+                    start: frequency(0)?,
+                    end: frequency(1)?,
+                    duration: match params.get(2) {
+                        Some(duration) => match parse_duration(duration) {
+                            Ok(duration) if duration > Duration::ZERO => duration.as_secs_f64(),
+                            _ => return Err(invalid(&format!("invalid duration '{}'", duration))),
+                        },
+                        None => return Err(invalid("missing duration")),
+                    },
+                    level: level(3)?,
+                },
+                4,
+            ),
+            _ => {
+                return Err(invalid(
+                    "expected one of: sine, white, pink, sweep, as sine:1000Hz:-20dBFS",
+                ))
+            }
+        };
+        if params.len() > num_params {
+            return Err(invalid("too many fields"));
+        }
+        Ok(signal)
+    }
+}
+
+/// Samples of a test signal at a sample rate.
+pub struct Generator {
+    signal: Signal,
+    sample_rate: f64,
+    /// peak of the sine and sweep, RMS of the noises
+    amplitude: f32,
+    /// radians of the sine and sweep
+    phase: f64,
+    /// seconds into the sweep
+    time: f64,
+    /// state of the xorshift noise
+    random: u32,
+    /// state of the pink noise filter
+    pink: [f32; 3],
+}
+
+impl Generator {
+    pub fn new(signal: Signal, sample_rate: u32) -> Self {
+        let level = match signal {
+            Signal::Sine { level, .. }
+            | Signal::WhiteNoise { level }
+            | Signal::PinkNoise { level }
+            | Signal::Sweep { level, .. } => level,
+        };
+        let peak = 10f32.powf(level / 20.0);
+        let amplitude = match signal {
+            Signal::Sine { .. } | Signal::Sweep { .. } => peak,
+            Signal::WhiteNoise { .. } | Signal::PinkNoise { .. } => peak / 2f32.sqrt(),
+        };
+        Generator {
+            signal,
+            sample_rate: sample_rate as f64,
+            amplitude,
+            phase: 0.0,
+            time: 0.0,
+            random: 0x2545_f491,
+            pink: [0.0; 3],
+        }
+    }
+
+    /// uniform white noise from -1 to 1
+    fn white(&mut self) -> f32 {
+        self.random ^= self.random << 13;
+        self.random ^= self.random >> 17;
+        self.random ^= self.random << 5;
+        (self.random as f64 / u32::MAX as f64 * 2.0 - 1.0) as f32
+    }
+
+    /// The next sample, from -1 to 1.
+    pub fn next_sample(&mut self) -> f32 {
+        let sample = match self.signal {
+            Signal::Sine { frequency, .. } => {
+                let sample = self.phase.sin() as f32;
+                self.phase = (self.phase + 2.0 * PI * frequency / self.sample_rate) % (2.0 * PI);
+                sample
+            }
+            // uniform with an RMS of 1
+            Signal::WhiteNoise { .. } => self.white() * 3f32.sqrt(),
+            // the economy filter of Paul Kellett, 3 dB per octave from 40 Hz
+            Signal::PinkNoise { .. } => {
+                let white = self.white();
+                let pink = &mut self.pink;
+                pink[0] = 0.99765 * pink[0] + white * 0.099_046;
+                pink[1] = 0.963 * pink[1] + white * 0.296_516_4;
+                pink[2] = 0.57 * pink[2] + white * 1.052_691_3;
+                (pink[0] + pink[1] + pink[2] + white * 0.1848) / PINK_NOISE_RMS
+            }
+            Signal::Sweep {
+                start,
+                end,
+                duration,
+                ..
+            } => {
+                let sample = self.phase.sin() as f32;
+                let frequency = start * (end / start).powf(self.time / duration);
+                self.phase = (self.phase + 2.0 * PI * frequency / self.sample_rate) % (2.0 * PI);
+                self.time += 1.0 / self.sample_rate;
+                if self.time >= duration {
+                    self.time = 0.0;
+                    self.phase = 0.0;
+                }
+                sample
+            }
+        };
+        (sample * self.amplitude).clamp(-1.0, 1.0)
+    }
+}
+
+/// Plays a test signal until dropped.
+pub struct GeneratorOutput {
+    _stream: cpal::Stream,
+}
+
+impl GeneratorOutput {
+    /// Play `signal` on all the channels of `device`, calling `error_callback`
+    /// with the errors of the output stream.
+    pub fn start<E>(
+        device: &cpal::Device,
+        signal: Signal,
+        error_callback: E,
+    ) -> Result<Self, String>
+    where
+        E: FnMut(cpal::StreamError) + Send + 'static,
+    {
+        let config = device
+            .default_output_config()
+            .map_err(|err| format!("failed to get the generator output config: {}", err))?;
+        let generator = Generator::new(signal, config.sample_rate().0);
+        let stream = match config.sample_format() {
+            cpal::SampleFormat::U16 => {
+                build_output_stream::<u16, _>(device, &config, generator, error_callback)
+            }
+            cpal::SampleFormat::I16 => {
+                build_output_stream::<i16, _>(device, &config, generator, error_callback)
+            }
+            cpal::SampleFormat::I32 => {
+                build_output_stream::<i32, _>(device, &config, generator, error_callback)
+            }
+            cpal::SampleFormat::F32 => {
+                build_output_stream::<f32, _>(device, &config, generator, error_callback)
+            }
+            sample_format => {
+                return Err(format!(
+                    "unsupported generator output sample format {:?}",
+                    sample_format
+                ))
+            }
+        }
+        .map_err(|err| format!("failed to build the generator output stream: {}", err))?;
+        stream
+            .play()
+            .map_err(|err| format!("failed to play the generator output stream: {}", err))?;
+        Ok(GeneratorOutput { _stream: stream })
+    }
+}
+
+fn build_output_stream<T, E>(
+    device: &cpal::Device,
+    config: &cpal::SupportedStreamConfig,
+    mut generator: Generator,
+    error_callback: E,
+) -> Result<cpal::Stream, cpal::BuildStreamError>
+where
+    T: cpal::SizedSample + cpal::FromSample<f32>,
+    E: FnMut(cpal::StreamError) + Send + 'static,
+{
+    let channels = config.channels() as usize;
+    device.build_output_stream(
+        &config.config(),
+        move |output: &mut [T], _: &cpal::OutputCallbackInfo| {
+            for frame in output.chunks_exact_mut(channels) {
+                frame.fill(T::from_sample(generator.next_sample()));
+            }
+        },
+        error_callback,
+        None,
+    )
+}
@@ -0,0 +1,278 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! gRPC api of the levels, the events, the controls and the status of the capture,
+//! the service `audio_in_stream.v1.Monitor` of `proto/audio_in_stream.proto`.
+
+pub mod proto;
+
+use crate::auth::Auth;
+use crate::broadcast::Broadcast;
+use crate::control::{ControlCommand, Controls};
+use crate::events::Event;
+use crate::health::{Health, RecordingStatus};
+use crate::levels::Levels;
+use crate::rate_interval;
+use futures_util::{future, stream, Stream, StreamExt};
+use proto::control_request::Action;
+use proto::monitor_server::{Monitor, MonitorServer};
+use std::convert::TryFrom;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tonic::{Request, Response, Status};
+
+/// Messages of a server streaming call, ending when the capture stops.
+type MessageStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
+
+/// The service of the gRPC api, checking the credentials of `--auth`
+/// in the `authorization` metadata like the http api.
+pub struct MonitorService {
+    levels_broadcast: Broadcast<Levels>,
+    events_broadcast: Broadcast<Event>,
+    controls: Controls,
+    health: Arc<Health>,
+    /// credentials required by all the calls, the controls are disabled without them
+    auth: Option<Auth>,
+    /// whether the levels and the status are left open
+    auth_open_levels: bool,
+}
+
+impl MonitorService {
+    pub fn new(
+        levels_broadcast: Broadcast<Levels>,
+        events_broadcast: Broadcast<Event>,
+        controls: Controls,
+        health: Arc<Health>,
+        auth: Option<Auth>,
+        auth_open_levels: bool,
+    ) -> Self {
+        MonitorService {
+            levels_broadcast,
+            events_broadcast,
+            controls,
+            health,
+            auth,
+            auth_open_levels,
+        }
+    }
+
+    /// The router of the gRPC calls, served over HTTP/2.
+    pub fn into_router(self) -> axum::Router {
+        tonic::service::Routes::new(MonitorServer::new(self)).into_axum_router()
+    }
+
+    /// `Unauthenticated` unless the call carries the credentials, or is `open`.
+    fn authorize<T>(&self, request: &Request<T>, open: bool) -> Result<(), Status> {
+        match &self.auth {
+            Some(auth) if !(open && self.auth_open_levels) => {
+                let authorization = request
+                    .metadata()
+                    .get("authorization")
+                    .and_then(|value| value.to_str().ok());
+                if auth.is_authorized(authorization, None) {
+                    Ok(())
+                } else {
+                    Err(Status::unauthenticated("unauthorized"))
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The controls, `FailedPrecondition` without `--auth` like the control api.
+    fn controls<T>(&self, request: &Request<T>) -> Result<&Controls, Status> {
+        if self.auth.is_none() {
+            return Err(Status::failed_precondition(
+                "the control api is disabled, enable it with --auth",
+            ));
+        }
+        self.authorize(request, false)?;
+        Ok(&self.controls)
+    }
+}
+
+#[tonic::async_trait]
+impl Monitor for MonitorService {
+    type SubscribeLevelsStream = MessageStream<proto::Levels>;
+    type SubscribeEventsStream = MessageStream<proto::Event>;
+
+    async fn subscribe_levels(
+        &self,
+        request: Request<proto::SubscribeLevelsRequest>,
+    ) -> Result<Response<Self::SubscribeLevelsStream>, Status> {
+        self.authorize(&request, true)?;
+        let max_rate = request.get_ref().max_rate;
+        let min_interval = match max_rate {
+            0.0 => Duration::ZERO,
+            max_rate => rate_interval(max_rate).ok_or_else(|| {
+                Status::invalid_argument(format!("invalid max rate {}", max_rate))
+            })?,
+        };
+        // levels received less than `min_interval` after the last ones sent are dropped
+        let levels = stream::unfold(
+            (self.levels_broadcast.subscribe(), None::<Instant>),
+            move |(mut messages, last_sent)| async move {
+                loop {
+                    let levels = messages.recv().await?;
+                    let now = Instant::now();
+                    if last_sent.is_some_and(|last_sent| now - last_sent < min_interval) {
+                        continue;
+                    }
+                    return Some((Ok(levels_message(&levels)), (messages, Some(now))));
+                }
+            },
+        );
+        Ok(Response::new(Box::pin(levels)))
+    }
+
+    async fn subscribe_events(
+        &self,
+        request: Request<proto::SubscribeEventsRequest>,
+    ) -> Result<Response<Self::SubscribeEventsStream>, Status> {
+        self.authorize(&request, false)?;
+        let names = request.into_inner().events;
+        let events = stream::unfold(self.events_broadcast.subscribe(), |mut messages| async {
+            let event = messages.recv().await?;
+            Some((event_message(&event), messages))
+        })
+        .filter(move |event| future::ready(names.is_empty() || names.contains(&event.event)))
+        .map(Ok);
+        Ok(Response::new(Box::pin(events)))
+    }
+
+    async fn get_controls(
+        &self,
+        request: Request<proto::GetControlsRequest>,
+    ) -> Result<Response<proto::ControlState>, Status> {
+        let controls = self.controls(&request)?;
+        Ok(Response::new(control_state_message(controls)))
+    }
+
+    async fn control(
+        &self,
+        request: Request<proto::ControlRequest>,
+    ) -> Result<Response<proto::ControlState>, Status> {
+        let controls = self.controls(&request)?;
+        control_command(request.get_ref())
+            .and_then(|command| controls.apply(command))
+            .map_err(Status::invalid_argument)?;
+        Ok(Response::new(control_state_message(controls)))
+    }
+
+    async fn get_status(
+        &self,
+        request: Request<proto::GetStatusRequest>,
+    ) -> Result<Response<proto::Status>, Status> {
+        self.authorize(&request, true)?;
+        let report = self.health.report();
+        let recording = match report.recording {
+            RecordingStatus::Disabled => proto::status::Recording::Disabled,
+            RecordingStatus::Recording => proto::status::Recording::Recording,
+            RecordingStatus::Paused => proto::status::Recording::Paused,
+            RecordingStatus::Stopped => proto::status::Recording::Stopped,
+            RecordingStatus::Failed => proto::status::Recording::Failed,
+        };
+        Ok(Response::new(proto::Status {
+            healthy: report.is_healthy(),
+            ready: report.is_ready(),
+            stream_running: report.stream_running,
+            last_buffer_age: report.last_buffer_age,
+            max_buffer_age: report.max_buffer_age,
+            uptime: report.uptime,
+            recording: recording as i32,
+            errors: report
+                .errors
+                .iter()
+                .map(|(&kind, &count)| (String::from(kind), count))
+                .collect(),
+        }))
+    }
+}
+
+fn levels_message(levels: &Levels) -> proto::Levels {
+    proto::Levels {
+        timestamp: levels.timestamp,
+        buffer_duration: levels.buffer_duration,
+        sample_format: levels.sample_format.clone(),
+        channel_count: levels.channel_count as u32,
+        weighting: String::from(levels.weighting),
+        channels: levels
+            .channels
+            .iter()
+            .map(|channel| proto::ChannelLevels {
+                channel: channel.channel as u32,
+                rms: channel.rms,
+                dbov: channel.dbov,
+                spl: channel.spl,
+                true_peak: channel.true_peak,
+                silent_since: channel.silent_since,
+                gain: channel.gain,
+                muted: channel.muted,
+                dc_offset: channel.dc_offset,
+            })
+            .collect(),
+        json: levels.to_json(),
+    }
+}
+
+/// The event with its name and timestamp, the fields of all of them.
+fn event_message(event: &Event) -> proto::Event {
+    let value = serde_json::to_value(event).expect("failed to serialize event");
+    proto::Event {
+        event: value["event"].as_str().unwrap_or_default().to_owned(),
+        timestamp: value["timestamp"].as_f64().unwrap_or_default(),
+        json: value.to_string(),
+    }
+}
+
+fn control_state_message(controls: &Controls) -> proto::ControlState {
+    let state = controls.state();
+    proto::ControlState {
+        meter_paused: state.meter_paused,
+        recording: state.recording,
+        denoise: state.denoise,
+        channels: state
+            .channels
+            .iter()
+            .map(|channel| proto::ChannelControl {
+                channel: channel.channel as u32,
+                gain: channel.gain,
+                muted: channel.muted,
+            })
+            .collect(),
+    }
+}
+
+/// The command of the control request, or the error of an invalid action.
+fn control_command(request: &proto::ControlRequest) -> Result<ControlCommand, String> {
+    let channel = request.channel.map(|channel| channel as usize);
+    let db = request.db;
+    match Action::try_from(request.action) {
+        Ok(Action::PauseMeter) => Ok(ControlCommand::PauseMeter),
+        Ok(Action::ResumeMeter) => Ok(ControlCommand::ResumeMeter),
+        Ok(Action::StartRecording) => Ok(ControlCommand::StartRecording),
+        Ok(Action::StopRecording) => Ok(ControlCommand::StopRecording),
+        Ok(Action::EnableDenoise) => Ok(ControlCommand::EnableDenoise),
+        Ok(Action::DisableDenoise) => Ok(ControlCommand::DisableDenoise),
+        Ok(Action::Mute) => Ok(ControlCommand::Mute { channel }),
+        Ok(Action::Unmute) => Ok(ControlCommand::Unmute { channel }),
+        Ok(Action::Gain) => Ok(ControlCommand::Gain { channel, db }),
+        Ok(Action::AdjustGain) => Ok(ControlCommand::AdjustGain { channel, db }),
+        Ok(Action::Unspecified) | Err(_) => {
+            Err(format!("invalid control action {}", request.action))
+        }
+    }
+}
@@ -0,0 +1,767 @@
+// This file is @generated by prost-build and tonic-prost-build from
+// proto/audio_in_stream.proto, checked in so the build needs no protoc.
+// Keep it in sync with the .proto.
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct SubscribeLevelsRequest {
+    /// at most this number of levels per second, the levels of every input buffer with 0
+    #[prost(double, tag = "1")]
+    pub max_rate: f64,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Levels {
+    /// capture time of the input buffer
+    #[prost(double, tag = "1")]
+    pub timestamp: f64,
+    /// in seconds
+    #[prost(double, tag = "2")]
+    pub buffer_duration: f64,
+    #[prost(string, tag = "3")]
+    pub sample_format: ::prost::alloc::string::String,
+    #[prost(uint32, tag = "4")]
+    pub channel_count: u32,
+    /// frequency weighting of the meter, `Z` for none
+    #[prost(string, tag = "5")]
+    pub weighting: ::prost::alloc::string::String,
+    /// the metered channels
+    #[prost(message, repeated, tag = "6")]
+    pub channels: ::prost::alloc::vec::Vec<ChannelLevels>,
+    /// the whole levels as the JSON object of /api/levels, with the loudness,
+    /// the spectrum and the analyses enabled
+    #[prost(string, tag = "7")]
+    pub json: ::prost::alloc::string::String,
+}
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct ChannelLevels {
+    /// index of the channel in the input stream
+    #[prost(uint32, tag = "1")]
+    pub channel: u32,
+    /// root mean square of the samples
+    #[prost(float, tag = "2")]
+    pub rms: f32,
+    /// dBov, -inf for silence
+    #[prost(float, tag = "3")]
+    pub dbov: f32,
+    /// dB SPL, only once calibrated with --calibrate
+    #[prost(float, optional, tag = "4")]
+    pub spl: ::core::option::Option<f32>,
+    /// dBTP, -inf for silence
+    #[prost(float, tag = "5")]
+    pub true_peak: f32,
+    /// time since the channel is silent, none if not silent
+    #[prost(double, optional, tag = "6")]
+    pub silent_since: ::core::option::Option<f64>,
+    /// software gain applied to the channel
+    #[prost(float, tag = "7")]
+    pub gain: f32,
+    #[prost(bool, tag = "8")]
+    pub muted: bool,
+    /// mean of the samples averaged over time, linear with its sign
+    #[prost(float, tag = "9")]
+    pub dc_offset: f32,
+}
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct SubscribeEventsRequest {
+    /// names of the events streamed, e.g. `silence_start`, all of them if empty
+    #[prost(string, repeated, tag = "1")]
+    pub events: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Event {
+    /// name of the event, e.g. `silence_start`
+    #[prost(string, tag = "1")]
+    pub event: ::prost::alloc::string::String,
+    #[prost(double, tag = "2")]
+    pub timestamp: f64,
+    /// the whole event as the JSON object of the webhooks and MQTT, with its fields
+    #[prost(string, tag = "3")]
+    pub json: ::prost::alloc::string::String,
+}
+#[derive(Clone, Copy, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct GetControlsRequest {}
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct ControlRequest {
+    #[prost(enumeration = "control_request::Action", tag = "1")]
+    pub action: i32,
+    /// of mute, unmute and the gains, all of the channels without one
+    #[prost(uint32, optional, tag = "2")]
+    pub channel: ::core::option::Option<u32>,
+    #[prost(float, tag = "3")]
+    pub db: f32,
+}
+/// Nested message and enum types in `ControlRequest`.
+pub mod control_request {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+    #[repr(i32)]
+    pub enum Action {
+        Unspecified = 0,
+        PauseMeter = 1,
+        ResumeMeter = 2,
+        StartRecording = 3,
+        StopRecording = 4,
+        EnableDenoise = 5,
+        DisableDenoise = 6,
+        Mute = 7,
+        Unmute = 8,
+        /// set the gain of `db`
+        Gain = 9,
+        /// add `db` to the gain
+        AdjustGain = 10,
+    }
+    impl Action {
+        /// String value of the enum field names used in the ProtoBuf definition.
+        ///
+        /// The values are not transformed in any way and thus are considered stable
+        /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+        pub fn as_str_name(&self) -> &'static str {
+            match self {
+                Self::Unspecified => "ACTION_UNSPECIFIED",
+                Self::PauseMeter => "PAUSE_METER",
+                Self::ResumeMeter => "RESUME_METER",
+                Self::StartRecording => "START_RECORDING",
+                Self::StopRecording => "STOP_RECORDING",
+                Self::EnableDenoise => "ENABLE_DENOISE",
+                Self::DisableDenoise => "DISABLE_DENOISE",
+                Self::Mute => "MUTE",
+                Self::Unmute => "UNMUTE",
+                Self::Gain => "GAIN",
+                Self::AdjustGain => "ADJUST_GAIN",
+            }
+        }
+        /// Creates an enum from field names used in the ProtoBuf definition.
+        pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+            match value {
+                "ACTION_UNSPECIFIED" => Some(Self::Unspecified),
+                "PAUSE_METER" => Some(Self::PauseMeter),
+                "RESUME_METER" => Some(Self::ResumeMeter),
+                "START_RECORDING" => Some(Self::StartRecording),
+                "STOP_RECORDING" => Some(Self::StopRecording),
+                "ENABLE_DENOISE" => Some(Self::EnableDenoise),
+                "DISABLE_DENOISE" => Some(Self::DisableDenoise),
+                "MUTE" => Some(Self::Mute),
+                "UNMUTE" => Some(Self::Unmute),
+                "GAIN" => Some(Self::Gain),
+                "ADJUST_GAIN" => Some(Self::AdjustGain),
+                _ => None,
+            }
+        }
+    }
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ControlState {
+    #[prost(bool, tag = "1")]
+    pub meter_paused: bool,
+    /// none without a recording that can be started and stopped
+    #[prost(bool, optional, tag = "2")]
+    pub recording: ::core::option::Option<bool>,
+    /// none without the noise suppression of --denoise
+    #[prost(bool, optional, tag = "3")]
+    pub denoise: ::core::option::Option<bool>,
+    #[prost(message, repeated, tag = "4")]
+    pub channels: ::prost::alloc::vec::Vec<ChannelControl>,
+}
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct ChannelControl {
+    #[prost(uint32, tag = "1")]
+    pub channel: u32,
+    /// kept while muted
+    #[prost(float, tag = "2")]
+    pub gain: f32,
+    #[prost(bool, tag = "3")]
+    pub muted: bool,
+}
+#[derive(Clone, Copy, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct GetStatusRequest {}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Status {
+    /// whether the input stream runs and its last buffer is recent, as /healthz
+    #[prost(bool, tag = "1")]
+    pub healthy: bool,
+    /// whether healthy, a buffer has been received and the recording did not fail, as /readyz
+    #[prost(bool, tag = "2")]
+    pub ready: bool,
+    #[prost(bool, tag = "3")]
+    pub stream_running: bool,
+    /// age of the last input buffer in seconds, none before the first one
+    #[prost(double, optional, tag = "4")]
+    pub last_buffer_age: ::core::option::Option<f64>,
+    #[prost(double, tag = "5")]
+    pub max_buffer_age: f64,
+    #[prost(double, tag = "6")]
+    pub uptime: f64,
+    #[prost(enumeration = "status::Recording", tag = "7")]
+    pub recording: i32,
+    /// errors reported of each kind since the start
+    #[prost(map = "string, uint64", tag = "8")]
+    pub errors: ::std::collections::HashMap<::prost::alloc::string::String, u64>,
+}
+/// Nested message and enum types in `Status`.
+pub mod status {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+    #[repr(i32)]
+    pub enum Recording {
+        Unspecified = 0,
+        Disabled = 1,
+        Recording = 2,
+        /// out of the windows of the schedule, or by the controls
+        Paused = 3,
+        /// by the retention policy, before the disk fills
+        Stopped = 4,
+        /// the recording thread panicked
+        Failed = 5,
+    }
+    impl Recording {
+        /// String value of the enum field names used in the ProtoBuf definition.
+        ///
+        /// The values are not transformed in any way and thus are considered stable
+        /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+        pub fn as_str_name(&self) -> &'static str {
+            match self {
+                Self::Unspecified => "RECORDING_UNSPECIFIED",
+                Self::Disabled => "DISABLED",
+                Self::Recording => "RECORDING",
+                Self::Paused => "PAUSED",
+                Self::Stopped => "STOPPED",
+                Self::Failed => "FAILED",
+            }
+        }
+        /// Creates an enum from field names used in the ProtoBuf definition.
+        pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+            match value {
+                "RECORDING_UNSPECIFIED" => Some(Self::Unspecified),
+                "DISABLED" => Some(Self::Disabled),
+                "RECORDING" => Some(Self::Recording),
+                "PAUSED" => Some(Self::Paused),
+                "STOPPED" => Some(Self::Stopped),
+                "FAILED" => Some(Self::Failed),
+                _ => None,
+            }
+        }
+    }
+}
+/// Generated client implementations.
+pub mod monitor_client {
+    #![allow(
+        unused_variables,
+        dead_code,
+        missing_docs,
+        clippy::wildcard_imports,
+        clippy::let_unit_value
+    )]
+    use tonic::codegen::http::Uri;
+    use tonic::codegen::*;
+    #[derive(Debug, Clone)]
+    pub struct MonitorClient<T> {
+        inner: tonic::client::Grpc<T>,
+    }
+    impl<T> MonitorClient<T>
+    where
+        T: tonic::client::GrpcService<tonic::body::Body>,
+        T::Error: Into<StdError>,
+        T::ResponseBody: Body<Data = Bytes> + std::marker::Send + 'static,
+        <T::ResponseBody as Body>::Error: Into<StdError> + std::marker::Send,
+    {
+        pub fn new(inner: T) -> Self {
+            let inner = tonic::client::Grpc::new(inner);
+            Self { inner }
+        }
+        pub fn with_origin(inner: T, origin: Uri) -> Self {
+            let inner = tonic::client::Grpc::with_origin(inner, origin);
+            Self { inner }
+        }
+        pub fn with_interceptor<F>(
+            inner: T,
+            interceptor: F,
+        ) -> MonitorClient<InterceptedService<T, F>>
+        where
+            F: tonic::service::Interceptor,
+            T::ResponseBody: Default,
+            T: tonic::codegen::Service<
+                http::Request<tonic::body::Body>,
+                Response = http::Response<
+                    <T as tonic::client::GrpcService<tonic::body::Body>>::ResponseBody,
+                >,
+            >,
+            <T as tonic::codegen::Service<http::Request<tonic::body::Body>>>::Error:
+                Into<StdError> + std::marker::Send + std::marker::Sync,
+        {
+            MonitorClient::new(InterceptedService::new(inner, interceptor))
+        }
+        /// Compress requests with the given encoding.
+        ///
+        /// This requires the server to support it otherwise it might respond with an
+        /// error.
+        #[must_use]
+        pub fn send_compressed(mut self, encoding: CompressionEncoding) -> Self {
+            self.inner = self.inner.send_compressed(encoding);
+            self
+        }
+        /// Enable decompressing responses.
+        #[must_use]
+        pub fn accept_compressed(mut self, encoding: CompressionEncoding) -> Self {
+            self.inner = self.inner.accept_compressed(encoding);
+            self
+        }
+        /// Limits the maximum size of a decoded message.
+        ///
+        /// Default: `4MB`
+        #[must_use]
+        pub fn max_decoding_message_size(mut self, limit: usize) -> Self {
+            self.inner = self.inner.max_decoding_message_size(limit);
+            self
+        }
+        /// Limits the maximum size of an encoded message.
+        ///
+        /// Default: `usize::MAX`
+        #[must_use]
+        pub fn max_encoding_message_size(mut self, limit: usize) -> Self {
+            self.inner = self.inner.max_encoding_message_size(limit);
+            self
+        }
+        /// The levels of each input buffer, until the capture stops.
+        pub async fn subscribe_levels(
+            &mut self,
+            request: impl tonic::IntoRequest<super::SubscribeLevelsRequest>,
+        ) -> std::result::Result<
+            tonic::Response<tonic::codec::Streaming<super::Levels>>,
+            tonic::Status,
+        > {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::unknown(format!("Service was not ready: {}", e.into()))
+            })?;
+            let codec = tonic_prost::ProstCodec::default();
+            let path =
+                http::uri::PathAndQuery::from_static("/audio_in_stream.v1.Monitor/SubscribeLevels");
+            let mut req = request.into_request();
+            req.extensions_mut().insert(GrpcMethod::new(
+                "audio_in_stream.v1.Monitor",
+                "SubscribeLevels",
+            ));
+            self.inner.server_streaming(req, path, codec).await
+        }
+        /// The audio events, until the capture stops.
+        pub async fn subscribe_events(
+            &mut self,
+            request: impl tonic::IntoRequest<super::SubscribeEventsRequest>,
+        ) -> std::result::Result<
+            tonic::Response<tonic::codec::Streaming<super::Event>>,
+            tonic::Status,
+        > {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::unknown(format!("Service was not ready: {}", e.into()))
+            })?;
+            let codec = tonic_prost::ProstCodec::default();
+            let path =
+                http::uri::PathAndQuery::from_static("/audio_in_stream.v1.Monitor/SubscribeEvents");
+            let mut req = request.into_request();
+            req.extensions_mut().insert(GrpcMethod::new(
+                "audio_in_stream.v1.Monitor",
+                "SubscribeEvents",
+            ));
+            self.inner.server_streaming(req, path, codec).await
+        }
+        /// The state of the controls, only with --auth.
+        pub async fn get_controls(
+            &mut self,
+            request: impl tonic::IntoRequest<super::GetControlsRequest>,
+        ) -> std::result::Result<tonic::Response<super::ControlState>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::unknown(format!("Service was not ready: {}", e.into()))
+            })?;
+            let codec = tonic_prost::ProstCodec::default();
+            let path =
+                http::uri::PathAndQuery::from_static("/audio_in_stream.v1.Monitor/GetControls");
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("audio_in_stream.v1.Monitor", "GetControls"));
+            self.inner.unary(req, path, codec).await
+        }
+        /// Change the controls, then their state, only with --auth.
+        pub async fn control(
+            &mut self,
+            request: impl tonic::IntoRequest<super::ControlRequest>,
+        ) -> std::result::Result<tonic::Response<super::ControlState>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::unknown(format!("Service was not ready: {}", e.into()))
+            })?;
+            let codec = tonic_prost::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/audio_in_stream.v1.Monitor/Control");
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("audio_in_stream.v1.Monitor", "Control"));
+            self.inner.unary(req, path, codec).await
+        }
+        /// The health of the capture and the status of the recording.
+        pub async fn get_status(
+            &mut self,
+            request: impl tonic::IntoRequest<super::GetStatusRequest>,
+        ) -> std::result::Result<tonic::Response<super::Status>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::unknown(format!("Service was not ready: {}", e.into()))
+            })?;
+            let codec = tonic_prost::ProstCodec::default();
+            let path =
+                http::uri::PathAndQuery::from_static("/audio_in_stream.v1.Monitor/GetStatus");
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("audio_in_stream.v1.Monitor", "GetStatus"));
+            self.inner.unary(req, path, codec).await
+        }
+    }
+}
+/// Generated server implementations.
+pub mod monitor_server {
+    #![allow(
+        unused_variables,
+        dead_code,
+        missing_docs,
+        clippy::wildcard_imports,
+        clippy::let_unit_value
+    )]
+    use tonic::codegen::*;
+    /// Generated trait containing gRPC methods that should be implemented for use with MonitorServer.
+    #[async_trait]
+    pub trait Monitor: std::marker::Send + std::marker::Sync + 'static {
+        /// Server streaming response type for the SubscribeLevels method.
+        type SubscribeLevelsStream: tonic::codegen::tokio_stream::Stream<
+                Item = std::result::Result<super::Levels, tonic::Status>,
+            > + std::marker::Send
+            + 'static;
+        /// The levels of each input buffer, until the capture stops.
+        async fn subscribe_levels(
+            &self,
+            request: tonic::Request<super::SubscribeLevelsRequest>,
+        ) -> std::result::Result<tonic::Response<Self::SubscribeLevelsStream>, tonic::Status>;
+        /// Server streaming response type for the SubscribeEvents method.
+        type SubscribeEventsStream: tonic::codegen::tokio_stream::Stream<
+                Item = std::result::Result<super::Event, tonic::Status>,
+            > + std::marker::Send
+            + 'static;
+        /// The audio events, until the capture stops.
+        async fn subscribe_events(
+            &self,
+            request: tonic::Request<super::SubscribeEventsRequest>,
+        ) -> std::result::Result<tonic::Response<Self::SubscribeEventsStream>, tonic::Status>;
+        /// The state of the controls, only with --auth.
+        async fn get_controls(
+            &self,
+            request: tonic::Request<super::GetControlsRequest>,
+        ) -> std::result::Result<tonic::Response<super::ControlState>, tonic::Status>;
+        /// Change the controls, then their state, only with --auth.
+        async fn control(
+            &self,
+            request: tonic::Request<super::ControlRequest>,
+        ) -> std::result::Result<tonic::Response<super::ControlState>, tonic::Status>;
+        /// The health of the capture and the status of the recording.
+        async fn get_status(
+            &self,
+            request: tonic::Request<super::GetStatusRequest>,
+        ) -> std::result::Result<tonic::Response<super::Status>, tonic::Status>;
+    }
+    #[derive(Debug)]
+    pub struct MonitorServer<T> {
+        inner: Arc<T>,
+        accept_compression_encodings: EnabledCompressionEncodings,
+        send_compression_encodings: EnabledCompressionEncodings,
+        max_decoding_message_size: Option<usize>,
+        max_encoding_message_size: Option<usize>,
+    }
+    impl<T> MonitorServer<T> {
+        pub fn new(inner: T) -> Self {
+            Self::from_arc(Arc::new(inner))
+        }
+        pub fn from_arc(inner: Arc<T>) -> Self {
+            Self {
+                inner,
+                accept_compression_encodings: Default::default(),
+                send_compression_encodings: Default::default(),
+                max_decoding_message_size: None,
+                max_encoding_message_size: None,
+            }
+        }
+        pub fn with_interceptor<F>(inner: T, interceptor: F) -> InterceptedService<Self, F>
+        where
+            F: tonic::service::Interceptor,
+        {
+            InterceptedService::new(Self::new(inner), interceptor)
+        }
+        /// Enable decompressing requests with the given encoding.
+        #[must_use]
+        pub fn accept_compressed(mut self, encoding: CompressionEncoding) -> Self {
+            self.accept_compression_encodings.enable(encoding);
+            self
+        }
+        /// Compress responses with the given encoding, if the client supports it.
+        #[must_use]
+        pub fn send_compressed(mut self, encoding: CompressionEncoding) -> Self {
+            self.send_compression_encodings.enable(encoding);
+            self
+        }
+        /// Limits the maximum size of a decoded message.
+        ///
+        /// Default: `4MB`
+        #[must_use]
+        pub fn max_decoding_message_size(mut self, limit: usize) -> Self {
+            self.max_decoding_message_size = Some(limit);
+            self
+        }
+        /// Limits the maximum size of an encoded message.
+        ///
+        /// Default: `usize::MAX`
+        #[must_use]
+        pub fn max_encoding_message_size(mut self, limit: usize) -> Self {
+            self.max_encoding_message_size = Some(limit);
+            self
+        }
+    }
+    impl<T, B> tonic::codegen::Service<http::Request<B>> for MonitorServer<T>
+    where
+        T: Monitor,
+        B: Body + std::marker::Send + 'static,
+        B::Error: Into<StdError> + std::marker::Send + 'static,
+    {
+        type Response = http::Response<tonic::body::Body>;
+        type Error = std::convert::Infallible;
+        type Future = BoxFuture<Self::Response, Self::Error>;
+        fn poll_ready(
+            &mut self,
+            _cx: &mut Context<'_>,
+        ) -> Poll<std::result::Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+        fn call(&mut self, req: http::Request<B>) -> Self::Future {
+            match req.uri().path() {
+                "/audio_in_stream.v1.Monitor/SubscribeLevels" => {
+                    #[allow(non_camel_case_types)]
+                    struct SubscribeLevelsSvc<T: Monitor>(pub Arc<T>);
+                    impl<T: Monitor>
+                        tonic::server::ServerStreamingService<super::SubscribeLevelsRequest>
+                        for SubscribeLevelsSvc<T>
+                    {
+                        type Response = super::Levels;
+                        type ResponseStream = T::SubscribeLevelsStream;
+                        type Future =
+                            BoxFuture<tonic::Response<Self::ResponseStream>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::SubscribeLevelsRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as Monitor>::subscribe_levels(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = SubscribeLevelsSvc(inner);
+                        let codec = tonic_prost::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.server_streaming(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/audio_in_stream.v1.Monitor/SubscribeEvents" => {
+                    #[allow(non_camel_case_types)]
+                    struct SubscribeEventsSvc<T: Monitor>(pub Arc<T>);
+                    impl<T: Monitor>
+                        tonic::server::ServerStreamingService<super::SubscribeEventsRequest>
+                        for SubscribeEventsSvc<T>
+                    {
+                        type Response = super::Event;
+                        type ResponseStream = T::SubscribeEventsStream;
+                        type Future =
+                            BoxFuture<tonic::Response<Self::ResponseStream>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::SubscribeEventsRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as Monitor>::subscribe_events(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = SubscribeEventsSvc(inner);
+                        let codec = tonic_prost::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.server_streaming(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/audio_in_stream.v1.Monitor/GetControls" => {
+                    #[allow(non_camel_case_types)]
+                    struct GetControlsSvc<T: Monitor>(pub Arc<T>);
+                    impl<T: Monitor> tonic::server::UnaryService<super::GetControlsRequest> for GetControlsSvc<T> {
+                        type Response = super::ControlState;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::GetControlsRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut =
+                                async move { <T as Monitor>::get_controls(&inner, request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = GetControlsSvc(inner);
+                        let codec = tonic_prost::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/audio_in_stream.v1.Monitor/Control" => {
+                    #[allow(non_camel_case_types)]
+                    struct ControlSvc<T: Monitor>(pub Arc<T>);
+                    impl<T: Monitor> tonic::server::UnaryService<super::ControlRequest> for ControlSvc<T> {
+                        type Response = super::ControlState;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::ControlRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move { <T as Monitor>::control(&inner, request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = ControlSvc(inner);
+                        let codec = tonic_prost::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/audio_in_stream.v1.Monitor/GetStatus" => {
+                    #[allow(non_camel_case_types)]
+                    struct GetStatusSvc<T: Monitor>(pub Arc<T>);
+                    impl<T: Monitor> tonic::server::UnaryService<super::GetStatusRequest> for GetStatusSvc<T> {
+                        type Response = super::Status;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::GetStatusRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut =
+                                async move { <T as Monitor>::get_status(&inner, request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = GetStatusSvc(inner);
+                        let codec = tonic_prost::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                _ => Box::pin(async move {
+                    let mut response = http::Response::new(tonic::body::Body::default());
+                    let headers = response.headers_mut();
+                    headers.insert(
+                        tonic::Status::GRPC_STATUS,
+                        (tonic::Code::Unimplemented as i32).into(),
+                    );
+                    headers.insert(
+                        http::header::CONTENT_TYPE,
+                        tonic::metadata::GRPC_CONTENT_TYPE,
+                    );
+                    Ok(response)
+                }),
+            }
+        }
+    }
+    impl<T> Clone for MonitorServer<T> {
+        fn clone(&self) -> Self {
+            let inner = self.inner.clone();
+            Self {
+                inner,
+                accept_compression_encodings: self.accept_compression_encodings,
+                send_compression_encodings: self.send_compression_encodings,
+                max_decoding_message_size: self.max_decoding_message_size,
+                max_encoding_message_size: self.max_encoding_message_size,
+            }
+        }
+    }
+    /// Generated gRPC service name
+    pub const SERVICE_NAME: &str = "audio_in_stream.v1.Monitor";
+    impl<T> tonic::server::NamedService for MonitorServer<T> {
+        const NAME: &'static str = SERVICE_NAME;
+    }
+}
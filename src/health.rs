@@ -0,0 +1,154 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Health of the capture, served by `/healthz` and `/readyz`: whether the input stream runs,
+//! the age of its last buffer, the status of the recording and the errors reported.
+
+use crate::error::Error;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+use std::time::{Duration, Instant};
+
+/// Default age of the last input buffer, in seconds, above which the capture is unhealthy.
+pub const DEFAULT_MAX_BUFFER_AGE: f64 = 2.0;
+
+/// Status of the recording of the input.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordingStatus {
+    Disabled,
+    Recording,
+    /// out of the windows of the schedule, or by the controls
+    Paused,
+    /// by the retention policy, before the disk fills
+    Stopped,
+    /// the recording thread panicked
+    Failed,
+}
+
+impl RecordingStatus {
+    const ALL: [RecordingStatus; 5] = [
+        RecordingStatus::Disabled,
+        RecordingStatus::Recording,
+        RecordingStatus::Paused,
+        RecordingStatus::Stopped,
+        RecordingStatus::Failed,
+    ];
+}
+
+/// Health of the capture, updated from the processing threads and the supervisor
+/// without locking.
+pub struct Health {
+    started: Instant,
+    max_buffer_age: Duration,
+    /// milliseconds since the start at the last input buffer, plus one, 0 before the first one
+    last_buffer: AtomicU64,
+    /// whether a fatal error stopped the input stream
+    failed: AtomicBool,
+    recording: AtomicU8,
+    /// errors reported of each kind of [`Error::KINDS`]
+    errors: [AtomicU64; Error::KINDS.len()],
+}
+
+/// The health at a time, serialized as the JSON object of `/healthz` and `/readyz`.
+#[derive(Clone, Debug, Serialize)]
+pub struct HealthReport {
+    pub stream_running: bool,
+    /// age of the last input buffer in seconds, none before the first one
+    pub last_buffer_age: Option<f64>,
+    pub max_buffer_age: f64,
+    pub uptime: f64,
+    pub recording: RecordingStatus,
+    /// errors reported of each kind since the start
+    pub errors: BTreeMap<&'static str, u64>,
+}
+
+impl Health {
+    pub fn new(max_buffer_age: Duration, recording: RecordingStatus) -> Self {
+        Health {
+            started: Instant::now(),
+            max_buffer_age,
+            last_buffer: AtomicU64::new(0),
+            failed: AtomicBool::new(false),
+            recording: AtomicU8::new(recording as u8),
+            errors: Default::default(),
+        }
+    }
+
+    /// Record an input buffer processed.
+    pub fn record_buffer(&self) {
+        let elapsed = self.started.elapsed().as_millis() as u64;
+        self.last_buffer.store(elapsed + 1, Ordering::Relaxed);
+    }
+
+    /// Count an error reported, a fatal one stopping the input stream.
+    pub fn record_error(&self, error: &Error) {
+        if let Some(index) = Error::KINDS.iter().position(|&kind| kind == error.kind()) {
+            self.errors[index].fetch_add(1, Ordering::Relaxed);
+        }
+        if error.is_fatal() {
+            self.failed.store(true, Ordering::Relaxed);
+        }
+    }
+
+    pub fn set_recording(&self, status: RecordingStatus) {
+        self.recording.store(status as u8, Ordering::Relaxed);
+    }
+
+    pub fn report(&self) -> HealthReport {
+        let uptime = self.started.elapsed();
+        let last_buffer_age = match self.last_buffer.load(Ordering::Relaxed) {
+            0 => None,
+            last_buffer => {
+                let last_buffer = Duration::from_millis(last_buffer - 1);
+                Some(uptime.saturating_sub(last_buffer).as_secs_f64())
+            }
+        };
+        HealthReport {
+            stream_running: !self.failed.load(Ordering::Relaxed),
+            last_buffer_age,
+            max_buffer_age: self.max_buffer_age.as_secs_f64(),
+            uptime: uptime.as_secs_f64(),
+            recording: RecordingStatus::ALL[self.recording.load(Ordering::Relaxed) as usize],
+            errors: Error::KINDS
+                .iter()
+                .zip(&self.errors)
+                .map(|(&kind, count)| (kind, count.load(Ordering::Relaxed)))
+                .collect(),
+        }
+    }
+}
+
+impl HealthReport {
+    /// Whether the capture is alive: the input stream runs, and its last buffer is recent,
+    /// or the first one is still awaited since the start.
+    pub fn is_healthy(&self) -> bool {
+        let age = self.last_buffer_age.unwrap_or(self.uptime);
+        self.stream_running && age <= self.max_buffer_age
+    }
+
+    /// Whether the capture serves the levels and the streams: it is healthy,
+    /// an input buffer has been received, and the recording has not failed.
+    pub fn is_ready(&self) -> bool {
+        self.is_healthy()
+            && self.last_buffer_age.is_some()
+            && self.recording != RecordingStatus::Failed
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("failed to serialize health")
+    }
+}
@@ -0,0 +1,267 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! History of the levels: statistics of each second kept in memory for a configurable
+//! window, and queried as a time series of a given resolution.
+
+use crate::levels::Levels;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Largest number of points of a query without a resolution.
+pub const DEFAULT_MAX_POINTS: usize = 1000;
+
+/// Statistics of the levels of a channel over a period.
+#[derive(Clone, Debug, Serialize)]
+pub struct ChannelHistory {
+    pub channel: usize,
+    /// RMS level over the period, in dBov, `null` in JSON for silence (-inf)
+    pub rms: f32,
+    /// highest true peak of the period, in dBTP, `null` in JSON for silence (-inf)
+    pub peak: f32,
+}
+
+/// Statistics of the levels over a period.
+#[derive(Clone, Debug, Serialize)]
+pub struct HistoryPoint {
+    /// start of the period, in seconds since the Unix epoch
+    pub timestamp: f64,
+    /// energy mean of the short-term loudness over the period, in LUFS,
+    /// `null` in JSON until there is enough samples
+    pub short_term: f32,
+    pub channels: Vec<ChannelHistory>,
+}
+
+/// Sums of the levels of the period being accumulated.
+struct Accumulator {
+    start: f64,
+    duration: f64,
+    /// of the mean squares of each channel, weighted by the duration of the buffers
+    mean_squares: Vec<f64>,
+    peaks: Vec<f32>,
+    /// of the short-term loudness as power, weighted by the duration of the buffers
+    short_term_power: f64,
+    short_term_duration: f64,
+}
+
+/// Statistics of the levels of each period, from the levels of each input buffer.
+pub struct PeriodLevels {
+    /// in seconds, the periods start at multiples of it since the Unix epoch
+    period: f64,
+    accumulator: Option<Accumulator>,
+}
+
+impl PeriodLevels {
+    pub fn new(period: f64) -> Self {
+        PeriodLevels {
+            period,
+            accumulator: None,
+        }
+    }
+
+    /// Add the levels of an input buffer, returns the point of the previous period
+    /// once a new one starts.
+    pub fn push(&mut self, levels: &Levels) -> Option<HistoryPoint> {
+        let start = (levels.timestamp / self.period).floor() * self.period;
+        let point = match &self.accumulator {
+            Some(accumulator) if accumulator.start != start => self
+                .accumulator
+                .take()
+                .map(|accumulator| accumulator.point(levels)),
+            _ => None,
+        };
+        let accumulator = self.accumulator.get_or_insert_with(|| Accumulator {
+            start,
+            duration: 0.0,
+            mean_squares: vec![0.0; levels.channels.len()],
+            peaks: vec![f32::NEG_INFINITY; levels.channels.len()],
+            short_term_power: 0.0,
+            short_term_duration: 0.0,
+        });
+        accumulator.duration += levels.buffer_duration;
+        for ((channel, mean_square), peak) in levels
+            .channels
+            .iter()
+            .zip(&mut accumulator.mean_squares)
+            .zip(&mut accumulator.peaks)
+        {
+            *mean_square += (channel.rms as f64).powi(2) * levels.buffer_duration;
+            *peak = peak.max(channel.true_peak);
+        }
+        if levels.loudness.short_term.is_finite() {
+            accumulator.short_term_power +=
+                10_f64.powf(levels.loudness.short_term as f64 / 10.0) * levels.buffer_duration;
+            accumulator.short_term_duration += levels.buffer_duration;
+        }
+        point
+    }
+}
+
+struct Points {
+    points: VecDeque<HistoryPoint>,
+    seconds: PeriodLevels,
+}
+
+/// The points of each second of the last window, shared between the metering thread
+/// and the queries.
+#[derive(Clone)]
+pub struct LevelHistory {
+    points: Arc<Mutex<Points>>,
+    max_points: usize,
+}
+
+impl LevelHistory {
+    /// History of the last `duration` seconds.
+    pub fn new(duration: f64) -> Self {
+        LevelHistory {
+            points: Arc::new(Mutex::new(Points {
+                points: VecDeque::new(),
+                seconds: PeriodLevels::new(1.0),
+            })),
+            max_points: duration.ceil().max(1.0) as usize,
+        }
+    }
+
+    /// Add the levels of an input buffer, the point of a second is kept once it ends.
+    pub fn push(&self, levels: &Levels) {
+        let mut points = self.points.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(point) = points.seconds.push(levels) {
+            if points.points.len() == self.max_points {
+                points.points.pop_front();
+            }
+            points.points.push_back(point);
+        }
+    }
+
+    /// Timestamp of the oldest point, if any.
+    pub fn start(&self) -> Option<f64> {
+        let points = self.points.lock().unwrap_or_else(PoisonError::into_inner);
+        points.points.front().map(|point| point.timestamp)
+    }
+
+    /// The points from `from` to `to`, in seconds since the Unix epoch,
+    /// merged into points of `resolution` seconds.
+    pub fn query(&self, from: f64, to: f64, resolution: f64) -> Vec<HistoryPoint> {
+        let points = self.points.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut merged = Vec::new();
+        let mut merging: Option<Merging> = None;
+        for point in points
+            .points
+            .iter()
+            .filter(|point| point.timestamp >= from && point.timestamp <= to)
+        {
+            let timestamp = from + ((point.timestamp - from) / resolution).floor() * resolution;
+            if merging
+                .as_ref()
+                .is_some_and(|merging| merging.point.timestamp != timestamp)
+            {
+                merged.extend(merging.take().map(Merging::finish));
+            }
+            merging
+                .get_or_insert_with(|| Merging::new(timestamp, point))
+                .add(point);
+        }
+        merged.extend(merging.map(Merging::finish));
+        merged
+    }
+}
+
+/// Sums of the points being merged into one.
+struct Merging {
+    point: HistoryPoint,
+    /// of the RMS levels as power
+    mean_squares: Vec<f64>,
+    short_term_power: f64,
+    short_term_count: usize,
+    count: usize,
+}
+
+impl Merging {
+    fn new(timestamp: f64, first: &HistoryPoint) -> Self {
+        Merging {
+            point: HistoryPoint {
+                timestamp,
+                short_term: f32::NEG_INFINITY,
+                channels: first
+                    .channels
+                    .iter()
+                    .map(|channel| ChannelHistory {
+                        peak: f32::NEG_INFINITY,
+                        ..channel.clone()
+                    })
+                    .collect(),
+            },
+            mean_squares: vec![0.0; first.channels.len()],
+            short_term_power: 0.0,
+            short_term_count: 0,
+            count: 0,
+        }
+    }
+
+    fn add(&mut self, point: &HistoryPoint) {
+        for ((channel, merged_channel), mean_square) in point
+            .channels
+            .iter()
+            .zip(&mut self.point.channels)
+            .zip(&mut self.mean_squares)
+        {
+            *mean_square += 10_f64.powf(channel.rms as f64 / 10.0);
+            merged_channel.peak = merged_channel.peak.max(channel.peak);
+        }
+        if point.short_term.is_finite() {
+            self.short_term_power += 10_f64.powf(point.short_term as f64 / 10.0);
+            self.short_term_count += 1;
+        }
+        self.count += 1;
+    }
+
+    fn finish(mut self) -> HistoryPoint {
+        for (channel, mean_square) in self.point.channels.iter_mut().zip(self.mean_squares) {
+            channel.rms = (10.0 * (mean_square / self.count as f64).log10()) as f32;
+        }
+        if self.short_term_count > 0 {
+            self.point.short_term =
+                (10.0 * (self.short_term_power / self.short_term_count as f64).log10()) as f32;
+        }
+        self.point
+    }
+}
+
+impl Accumulator {
+    /// The point of the period, with the channel numbers of the levels.
+    fn point(self, levels: &Levels) -> HistoryPoint {
+        let duration = self.duration;
+        HistoryPoint {
+            timestamp: self.start,
+            short_term: if self.short_term_duration > 0.0 {
+                (10.0 * (self.short_term_power / self.short_term_duration).log10()) as f32
+            } else {
+                f32::NEG_INFINITY
+            },
+            channels: levels
+                .channels
+                .iter()
+                .zip(self.mean_squares)
+                .zip(self.peaks)
+                .map(|((channel, mean_square), peak)| ChannelHistory {
+                    channel: channel.channel,
+                    rms: (10.0 * (mean_square / duration).log10()) as f32,
+                    peak,
+                })
+                .collect(),
+        }
+    }
+}
@@ -0,0 +1,432 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! HLS output of the live capture: segments of AAC or Opus, in MPEG-TS or fragmented
+//! MP4, kept in memory for a rolling playlist served by the HTTP server.
+
+#[cfg(feature = "aac")]
+use crate::aac::{self, AacEncoder};
+use crate::error::Error;
+use crate::fmp4::{Fmp4Codec, Fmp4Writer};
+use crate::mpegts::{self, TsMuxer, TsStream};
+#[cfg(feature = "opus")]
+use crate::ogg_opus::{self, OpusPacketEncoder};
+use crate::supervisor::Supervisor;
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex, PoisonError};
+use tokio::sync::mpsc;
+
+/// Duration of each segment, in seconds, as recommended by Apple.
+pub const DEFAULT_SEGMENT_DURATION: f64 = 6.0;
+/// Duration of the segments of the playlist, in seconds.
+pub const DEFAULT_WINDOW: f64 = 60.0;
+/// Least segments of the playlist, for the players to start 3 segments from the end.
+const MIN_WINDOW_SEGMENTS: usize = 3;
+/// Segments kept after leaving the playlist, for the players still loading them.
+const EXTRA_SEGMENTS: usize = 2;
+
+pub const PLAYLIST_NAME: &str = "live.m3u8";
+const INIT_SEGMENT_NAME: &str = "init.mp4";
+
+/// Audio codec of the segments.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HlsCodec {
+    Aac,
+    Opus,
+}
+
+impl HlsCodec {
+    pub fn parse(codec: &str) -> Result<Self, String> {
+        match codec.to_ascii_lowercase().as_str() {
+            "aac" => Ok(HlsCodec::Aac),
+            "opus" => Ok(HlsCodec::Opus),
+            _ => Err(format!(
+                "invalid HLS codec '{}', expected aac or opus",
+                codec
+            )),
+        }
+    }
+
+    /// Codec of the builds with its encoder, AAC if available, played by all the players.
+    pub fn default_codec() -> Self {
+        if cfg!(feature = "aac") {
+            HlsCodec::Aac
+        } else {
+            HlsCodec::Opus
+        }
+    }
+
+    /// Error if this build has no encoder of the codec.
+    pub fn check_feature(self) -> Result<(), String> {
+        match self {
+            HlsCodec::Aac if cfg!(not(feature = "aac")) => {
+                Err(String::from("the AAC codec requires the 'aac' feature"))
+            }
+            HlsCodec::Opus if cfg!(not(feature = "opus")) => {
+                Err(String::from("the Opus codec requires the 'opus' feature"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn default_bitrate(self) -> i32 {
+        match self {
+            #[cfg(feature = "aac")]
+            HlsCodec::Aac => aac::DEFAULT_BITRATE,
+            #[cfg(feature = "opus")]
+            HlsCodec::Opus => ogg_opus::DEFAULT_BITRATE,
+            #[allow(unreachable_patterns)]
+            _ => 128000,
+        }
+    }
+}
+
+/// Container of the segments.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HlsContainer {
+    Ts,
+    Fmp4,
+}
+
+impl HlsContainer {
+    pub fn parse(container: &str) -> Result<Self, String> {
+        match container.to_ascii_lowercase().as_str() {
+            "ts" | "mpegts" => Ok(HlsContainer::Ts),
+            "fmp4" | "mp4" => Ok(HlsContainer::Fmp4),
+            _ => Err(format!(
+                "invalid HLS container '{}', expected ts or fmp4",
+                container
+            )),
+        }
+    }
+
+    /// Container of the codec in the HLS specification, Opus is only in fragmented MP4.
+    pub fn default_container(codec: HlsCodec) -> Self {
+        match codec {
+            HlsCodec::Aac => HlsContainer::Ts,
+            HlsCodec::Opus => HlsContainer::Fmp4,
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            HlsContainer::Ts => "ts",
+            HlsContainer::Fmp4 => "m4s",
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            HlsContainer::Ts => "video/mp2t",
+            HlsContainer::Fmp4 => "audio/mp4",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct HlsConfig {
+    pub codec: HlsCodec,
+    pub container: HlsContainer,
+    pub bitrate: i32,
+    /// seconds
+    pub segment_duration: f64,
+    /// seconds of the segments of the playlist
+    pub window: f64,
+}
+
+/// Encodes the interleaved samples into access units of a fixed number of frames.
+enum Encoder {
+    #[cfg(feature = "aac")]
+    Aac(AacEncoder),
+    #[cfg(feature = "opus")]
+    Opus(OpusPacketEncoder),
+}
+
+impl Encoder {
+    fn new(config: &HlsConfig, channels: u16, sample_rate: u32) -> Result<Self, String> {
+        match config.codec {
+            #[cfg(feature = "aac")]
+            HlsCodec::Aac => Ok(Encoder::Aac(AacEncoder::new(
+                channels,
+                sample_rate,
+                config.bitrate,
+            )?)),
+            #[cfg(feature = "opus")]
+            HlsCodec::Opus => Ok(Encoder::Opus(OpusPacketEncoder::new(
+                channels,
+                sample_rate,
+                config.bitrate,
+            )?)),
+            #[allow(unreachable_patterns)]
+            codec => {
+                codec.check_feature()?;
+                Err(format!("no encoder of {:?} in this build", codec))
+            }
+        }
+    }
+
+    fn encode(&mut self, samples: &[f32]) -> Result<Vec<Vec<u8>>, String> {
+        match self {
+            #[cfg(feature = "aac")]
+            Encoder::Aac(encoder) => encoder.encode(samples),
+            #[cfg(feature = "opus")]
+            Encoder::Opus(encoder) => encoder.encode(samples),
+        }
+    }
+
+    /// Frames of each access unit, and the rate of the frames.
+    fn frames(&self) -> (usize, u32) {
+        match self {
+            #[cfg(feature = "aac")]
+            Encoder::Aac(encoder) => (aac::FRAME_LEN, encoder.sample_rate()),
+            #[cfg(feature = "opus")]
+            Encoder::Opus(_) => (ogg_opus::OPUS_FRAME_LEN, ogg_opus::OPUS_SAMPLE_RATE),
+        }
+    }
+
+    /// Channels of the stream, 1 or 2.
+    fn channels(&self) -> usize {
+        match self {
+            #[cfg(feature = "aac")]
+            Encoder::Aac(encoder) => encoder.channels(),
+            #[cfg(feature = "opus")]
+            Encoder::Opus(encoder) => encoder.channels(),
+        }
+    }
+
+    fn ts_stream(&self) -> TsStream {
+        match self {
+            #[cfg(feature = "aac")]
+            Encoder::Aac(encoder) => TsStream::Aac {
+                audio_specific_config: encoder.audio_specific_config().to_vec(),
+            },
+            #[cfg(feature = "opus")]
+            Encoder::Opus(encoder) => TsStream::Opus {
+                channels: encoder.channels(),
+            },
+        }
+    }
+
+    fn fmp4_codec(&self) -> Result<Fmp4Codec, String> {
+        match self {
+            #[cfg(feature = "aac")]
+            Encoder::Aac(encoder) => Ok(Fmp4Codec::Aac {
+                audio_specific_config: encoder.audio_specific_config().to_vec(),
+            }),
+            #[cfg(feature = "opus")]
+            Encoder::Opus(encoder) => Ok(Fmp4Codec::Opus {
+                pre_skip: encoder.lookahead()? as u16,
+            }),
+        }
+    }
+}
+
+/// Writes the access units into the segments of the container.
+enum SegmentWriter {
+    Ts(TsMuxer),
+    Fmp4(Fmp4Writer),
+}
+
+struct Segment {
+    sequence: u64,
+    /// seconds
+    duration: f64,
+    bytes: Arc<Vec<u8>>,
+}
+
+struct Segments {
+    container: HlsContainer,
+    /// whole seconds of the longest segment
+    target_duration: u64,
+    /// segments of the playlist
+    window_len: usize,
+    init_segment: Option<Arc<Vec<u8>>>,
+    /// the segments of the playlist, after the ones kept for the players still loading them
+    segments: VecDeque<Segment>,
+}
+
+/// The segments of the last `window` seconds of the live capture, shared with the
+/// HTTP server.
+#[derive(Clone)]
+pub struct HlsStream {
+    segments: Arc<Mutex<Segments>>,
+}
+
+impl HlsStream {
+    /// Start encoding the interleaved samples received from a broadcast into segments,
+    /// until the sender goes away or the encoding fails, reported to the supervisor.
+    pub fn start(
+        config: HlsConfig,
+        channels: u16,
+        sample_rate: u32,
+        mut samples: mpsc::Receiver<Arc<Vec<f32>>>,
+        supervisor: Supervisor,
+    ) -> Result<Self, String> {
+        let mut encoder = Encoder::new(&config, channels, sample_rate)?;
+        let (frame_len, frame_rate) = encoder.frames();
+        let mut writer = match config.container {
+            HlsContainer::Ts => SegmentWriter::Ts(TsMuxer::new(encoder.ts_stream())),
+            HlsContainer::Fmp4 => SegmentWriter::Fmp4(Fmp4Writer::new(
+                encoder.fmp4_codec()?,
+                encoder.channels() as u16,
+                frame_rate,
+                config.bitrate as u32,
+            )),
+        };
+        let init_segment = match &writer {
+            SegmentWriter::Fmp4(writer) => Some(Arc::new(writer.init_segment())),
+            SegmentWriter::Ts(_) => None,
+        };
+        // the segments end on the first access unit after their duration
+        let segment_len = (config.segment_duration * frame_rate as f64 / frame_len as f64)
+            .ceil()
+            .max(1.0) as usize;
+        let window_len =
+            ((config.window / config.segment_duration).ceil() as usize).max(MIN_WINDOW_SEGMENTS);
+        let stream = HlsStream {
+            segments: Arc::new(Mutex::new(Segments {
+                container: config.container,
+                target_duration: (segment_len * frame_len).div_ceil(frame_rate as usize) as u64,
+                window_len,
+                init_segment,
+                segments: VecDeque::new(),
+            })),
+        };
+
+        let segments = Arc::clone(&stream.segments);
+        tokio::spawn(async move {
+            let mut access_units = Vec::new();
+            let mut ts_bytes = Vec::new();
+            // frames encoded before the current segment
+            let mut frames = 0_u64;
+            let mut sequence = 0;
+            while let Some(samples) = samples.recv().await {
+                let encoded = match encoder.encode(&samples) {
+                    Ok(encoded) => encoded,
+                    Err(err) => {
+                        supervisor
+                            .report(Error::Output(format!("stopped the HLS stream: {}", err)));
+                        return;
+                    }
+                };
+                for access_unit in encoded {
+                    if let SegmentWriter::Ts(muxer) = &mut writer {
+                        let frame = frames + (access_units.len() * frame_len) as u64;
+                        let pts = frame * mpegts::CLOCK_RATE / frame_rate as u64;
+                        ts_bytes.extend_from_slice(&muxer.push(&access_unit, pts));
+                    }
+                    access_units.push(access_unit);
+                    if access_units.len() < segment_len {
+                        continue;
+                    }
+
+                    let bytes = match &mut writer {
+                        SegmentWriter::Ts(muxer) => {
+                            ts_bytes.extend_from_slice(&muxer.flush());
+                            std::mem::take(&mut ts_bytes)
+                        }
+                        SegmentWriter::Fmp4(writer) => {
+                            writer.media_segment(&access_units, frames, frame_len as u32)
+                        }
+                    };
+                    let segment_frames = (access_units.len() * frame_len) as u64;
+                    access_units.clear();
+                    frames += segment_frames;
+                    let segment = Segment {
+                        sequence,
+                        duration: segment_frames as f64 / frame_rate as f64,
+                        bytes: Arc::new(bytes),
+                    };
+                    sequence += 1;
+
+                    let mut segments = segments.lock().unwrap_or_else(PoisonError::into_inner);
+                    let max_len = segments.window_len + EXTRA_SEGMENTS;
+                    segments.segments.push_back(segment);
+                    while segments.segments.len() > max_len {
+                        segments.segments.pop_front();
+                    }
+                }
+            }
+        });
+        Ok(stream)
+    }
+
+    /// The media playlist of the segments of the window.
+    pub fn playlist(&self) -> String {
+        let segments = self.segments.lock().unwrap_or_else(PoisonError::into_inner);
+        let skipped = segments.segments.len().saturating_sub(segments.window_len);
+        let window = segments.segments.iter().skip(skipped);
+        let media_sequence = segments
+            .segments
+            .get(skipped)
+            .map_or(0, |segment| segment.sequence);
+
+        let mut playlist = String::from("#EXTM3U\n");
+        let version = match segments.container {
+            HlsContainer::Ts => 3,
+            HlsContainer::Fmp4 => 7,
+        };
+        writeln!(playlist, "#EXT-X-VERSION:{}", version).unwrap();
+        writeln!(
+            playlist,
+            "#EXT-X-TARGETDURATION:{}",
+            segments.target_duration
+        )
+        .unwrap();
+        writeln!(playlist, "#EXT-X-MEDIA-SEQUENCE:{}", media_sequence).unwrap();
+        if segments.init_segment.is_some() {
+            writeln!(playlist, "#EXT-X-MAP:URI=\"{}\"", INIT_SEGMENT_NAME).unwrap();
+        }
+        for segment in window {
+            writeln!(
+                playlist,
+                "#EXTINF:{:.3},\n{}.{}",
+                segment.duration,
+                segment.sequence,
+                segments.container.extension()
+            )
+            .unwrap();
+        }
+        playlist
+    }
+
+    /// Content type and bytes of the initialization segment or of a media segment,
+    /// from its name in the playlist.
+    pub fn segment(&self, name: &str) -> Option<(&'static str, Arc<Vec<u8>>)> {
+        let segments = self.segments.lock().unwrap_or_else(PoisonError::into_inner);
+        if name == INIT_SEGMENT_NAME {
+            return segments
+                .init_segment
+                .clone()
+                .map(|bytes| ("audio/mp4", bytes));
+        }
+        let sequence = name
+            .strip_suffix(segments.container.extension())?
+            .strip_suffix('.')?
+            .parse::<u64>()
+            .ok()?;
+        segments
+            .segments
+            .iter()
+            .find(|segment| segment.sequence == sequence)
+            .map(|segment| {
+                (
+                    segments.container.content_type(),
+                    Arc::clone(&segment.bytes),
+                )
+            })
+    }
+}
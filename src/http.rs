@@ -0,0 +1,966 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! The http server: the levels as JSON, over WebSocket and as Server-Sent Events,
+//! the live capture as raw PCM, WAV, Ogg/Opus and HLS, the recordings, the snapshots,
+//! the images of the spectrogram and the waveform, the metrics, the health and the control api,
+//! behind the auth and with the CORS headers of the options.
+
+use crate::archive::{self, FileRange};
+use crate::auth::{self, Auth};
+use crate::broadcast::Broadcast;
+use crate::control::{ControlCommand, Controls};
+use crate::cors::Cors;
+use crate::dashboard;
+use crate::features::FeatureFrame;
+use crate::health::Health;
+use crate::history::{self, LevelHistory};
+#[cfg(any(feature = "aac", feature = "opus"))]
+use crate::hls::{self, HlsStream};
+use crate::levels::{LevelSnapshot, Levels};
+use crate::metrics::{self, Metrics};
+use crate::pcm::{PcmFormat, PcmStream};
+use crate::recording;
+use crate::snapshot::{self, SnapshotBuffer};
+use crate::spectrogram::{self, SpectrogramConfig};
+use crate::spectrum::{Window, DEFAULT_FFT_SIZE, MIN_FFT_SIZE};
+use crate::sse;
+use crate::supervisor::Supervisor;
+use crate::tls;
+use crate::wav;
+use crate::waveform::{self, WaveformHistory};
+use crate::websocket;
+use crate::{parse_duration, rate_interval, unix_time};
+use axum::body::Body;
+use axum::extract::ws::{Message, WebSocketUpgrade};
+use axum::extract::{Request, State};
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue, Method, StatusCode, Uri};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{any, get};
+use axum::{Extension, Router};
+use futures_util::stream::{self, StreamExt};
+use std::future::IntoFuture;
+use std::io::{self, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_util::io::ReaderStream;
+
+/// Longest body of a control api request.
+const MAX_CONTROL_BODY: usize = 4096;
+/// Read-only endpoints of the levels and the dashboard, left open by `--auth-open-levels`.
+const LEVEL_PATHS: [&str; 9] = [
+    dashboard::PAGE_PATH,
+    "/info",
+    "/api/levels",
+    "/api/history",
+    "/ws/levels",
+    "/events",
+    "/metrics",
+    "/healthz",
+    "/readyz",
+];
+
+/// The query params of an url, with their values.
+fn query_params(query: Option<&str>) -> Vec<(&str, &str)> {
+    query.map_or_else(Vec::new, |query| {
+        query
+            .split('&')
+            .filter(|param| !param.is_empty())
+            .map(|param| param.split_once('=').unwrap_or((param, "")))
+            .collect()
+    })
+}
+
+fn query_param<'a>(query: &[(&str, &'a str)], name: &str) -> Option<&'a str> {
+    query
+        .iter()
+        .find(|(param_name, _)| *param_name == name)
+        .map(|(_, value)| *value)
+}
+
+/// Parse the value of the query param, if any.
+fn parse_query_param<T: std::str::FromStr>(
+    query: &[(&str, &str)],
+    name: &str,
+) -> Result<Option<T>, String> {
+    query_param(query, name)
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|_| format!("invalid value '{}' for {}", value, name))
+        })
+        .transpose()
+}
+
+/// Respond with the live capture as Ogg/Opus, at the bitrate of the query param.
+#[cfg(feature = "opus")]
+async fn stream_ogg(State(state): State<Arc<HttpState>>, uri: Uri) -> Response {
+    use crate::ogg_opus::{OggOpusEncoder, OggOpusStream, DEFAULT_BITRATE};
+
+    let query = query_params(uri.query());
+    let encoder = query_param(&query, "bitrate")
+        .map_or(Ok(DEFAULT_BITRATE), |bitrate| {
+            bitrate
+                .parse()
+                .map_err(|_| format!("invalid bitrate '{}'", bitrate))
+        })
+        .and_then(|bitrate| OggOpusEncoder::new(state.num_channels, state.sample_rate, bitrate));
+    match encoder {
+        Ok(encoder) => {
+            let stream = OggOpusStream::new(encoder, state.samples_broadcast.subscribe());
+            (
+                [(header::CONTENT_TYPE, "audio/ogg")],
+                Body::from_stream(stream),
+            )
+                .into_response()
+        }
+        Err(err) => (StatusCode::BAD_REQUEST, err).into_response(),
+    }
+}
+
+#[cfg(not(feature = "opus"))]
+async fn stream_ogg() -> Response {
+    (
+        StatusCode::NOT_IMPLEMENTED,
+        "built without the 'opus' feature",
+    )
+        .into_response()
+}
+
+/// Respond with the HLS playlist or a segment of the name in `/hls/<name>`.
+#[cfg(any(feature = "aac", feature = "opus"))]
+async fn hls_file(
+    State(state): State<Arc<HttpState>>,
+    Extension(cors_headers): Extension<CorsHeaders>,
+    uri: Uri,
+) -> Response {
+    let hls = match &state.hls {
+        Some(hls) => hls,
+        None => {
+            return (
+                StatusCode::NOT_FOUND,
+                "there is no HLS stream, enable it with --hls",
+            )
+                .into_response()
+        }
+    };
+    let name = uri.path().trim_start_matches("/hls/");
+    if name == hls::PLAYLIST_NAME {
+        // the playlist changes with each segment
+        let response = (
+            [
+                (header::CONTENT_TYPE, "application/vnd.apple.mpegurl"),
+                (header::CACHE_CONTROL, "no-cache"),
+            ],
+            hls.playlist(),
+        );
+        with_headers(response.into_response(), &cors_headers.0)
+    } else if let Some((content_type, bytes)) = hls.segment(name) {
+        let response = ([(header::CONTENT_TYPE, content_type)], bytes.to_vec());
+        with_headers(response.into_response(), &cors_headers.0)
+    } else {
+        (StatusCode::NOT_FOUND, "not found").into_response()
+    }
+}
+
+#[cfg(not(any(feature = "aac", feature = "opus")))]
+async fn hls_file() -> Response {
+    (
+        StatusCode::NOT_IMPLEMENTED,
+        "built without the 'aac' or the 'opus' feature",
+    )
+        .into_response()
+}
+
+/// Shared state of the http request handlers.
+pub struct HttpState {
+    pub level_snapshot: LevelSnapshot,
+    pub levels_broadcast: Broadcast<Levels>,
+    pub samples_broadcast: Broadcast<Vec<f32>>,
+    /// Mel spectrogram and MFCC frames, only with `--features`.
+    pub features_broadcast: Option<Broadcast<FeatureFrame>>,
+    pub metrics: Arc<Metrics>,
+    pub num_channels: u16,
+    pub sample_rate: u32,
+    /// The last seconds of the input and the directory of the saved snapshots.
+    pub snapshot: Option<(SnapshotBuffer, PathBuf)>,
+    /// Envelope of the last minutes of the input.
+    pub waveform: WaveformHistory,
+    /// Statistics of the levels of each second of the last hours.
+    pub level_history: Option<LevelHistory>,
+    /// SDP description of the RTP stream.
+    pub rtp_sdp: Option<String>,
+    /// Segments of the HLS stream.
+    #[cfg(any(feature = "aac", feature = "opus"))]
+    pub hls: Option<HlsStream>,
+    /// Directory of the recordings served.
+    pub recordings_dir: Option<PathBuf>,
+    /// Whether the recordings can be deleted.
+    pub recordings_delete: bool,
+    pub controls: Controls,
+    /// Credentials required by all the requests, the control api is disabled without them.
+    pub auth: Option<Auth>,
+    /// Whether the endpoints of the levels are left open.
+    pub auth_open_levels: bool,
+    /// Origins of the browser dashboards allowed to read the JSON and SSE endpoints.
+    pub cors: Option<Cors>,
+    pub health: Arc<Health>,
+    /// Reports the panics of the requests.
+    pub supervisor: Supervisor,
+}
+
+/// Parse the query params of the spectrogram: the channel, the seconds of the last ones kept
+/// in memory, the size of the image, the FFT size and the window.
+fn spectrogram_query(
+    query: &[(&str, &str)],
+    num_channels: u16,
+) -> Result<(usize, Option<f64>, SpectrogramConfig), String> {
+    let channel = parse_query_param::<usize>(query, "channel")?.unwrap_or(0);
+    if channel >= num_channels as usize {
+        return Err(format!(
+            "invalid channel {}, there are {} channel(s)",
+            channel, num_channels
+        ));
+    }
+    let seconds = match parse_query_param::<f64>(query, "seconds")? {
+        Some(seconds) if !(seconds > 0.0 && seconds.is_finite()) => {
+            return Err(format!("invalid value '{}' for seconds", seconds))
+        }
+        seconds => seconds,
+    };
+    let width = parse_query_param(query, "width")?.unwrap_or(spectrogram::DEFAULT_WIDTH);
+    let height = parse_query_param(query, "height")?.unwrap_or(spectrogram::DEFAULT_HEIGHT);
+    if !(1..=spectrogram::MAX_WIDTH).contains(&width)
+        || !(1..=spectrogram::MAX_HEIGHT).contains(&height)
+    {
+        return Err(format!(
+            "invalid size {}x{}, the maximum is {}x{}",
+            width,
+            height,
+            spectrogram::MAX_WIDTH,
+            spectrogram::MAX_HEIGHT
+        ));
+    }
+    let fft_size = parse_query_param(query, "fft_size")?.unwrap_or(DEFAULT_FFT_SIZE);
+    if !(MIN_FFT_SIZE..=spectrogram::MAX_FFT_SIZE).contains(&fft_size) {
+        return Err(format!(
+            "invalid FFT size {}, it must be from {} to {}",
+            fft_size,
+            MIN_FFT_SIZE,
+            spectrogram::MAX_FFT_SIZE
+        ));
+    }
+    let window = match query_param(query, "window") {
+        Some(window) => Window::parse(window)?,
+        None => Window::default(),
+    };
+    Ok((
+        channel,
+        seconds,
+        SpectrogramConfig {
+            width,
+            height,
+            fft_size,
+            window,
+        },
+    ))
+}
+
+/// Parse the query params of the waveform: the channel, all of them by default,
+/// the seconds of the last ones kept in memory and the size of the image.
+fn waveform_query(
+    query: &[(&str, &str)],
+    num_channels: u16,
+) -> Result<(Option<usize>, Option<f64>, u32, u32), String> {
+    let channel = parse_query_param::<usize>(query, "channel")?;
+    if let Some(channel) = channel.filter(|&channel| channel >= num_channels as usize) {
+        return Err(format!(
+            "invalid channel {}, there are {} channel(s)",
+            channel, num_channels
+        ));
+    }
+    let seconds = match parse_query_param::<f64>(query, "seconds")? {
+        Some(seconds) if !(seconds > 0.0 && seconds.is_finite()) => {
+            return Err(format!("invalid value '{}' for seconds", seconds))
+        }
+        seconds => seconds,
+    };
+    let width = parse_query_param(query, "width")?.unwrap_or(waveform::DEFAULT_WIDTH);
+    let height = parse_query_param(query, "height")?.unwrap_or(waveform::DEFAULT_HEIGHT);
+    if !(1..=waveform::MAX_WIDTH).contains(&width) || !(1..=waveform::MAX_HEIGHT).contains(&height)
+    {
+        return Err(format!(
+            "invalid size {}x{}, the maximum is {}x{}",
+            width,
+            height,
+            waveform::MAX_WIDTH,
+            waveform::MAX_HEIGHT
+        ));
+    }
+    Ok((channel, seconds, width, height))
+}
+
+/// Parse the query params of the level history: the time range, in seconds since the
+/// Unix epoch, the whole history by default, and the resolution, by default the one
+/// giving at most `history::DEFAULT_MAX_POINTS` points.
+fn history_query(
+    query: &[(&str, &str)],
+    level_history: &LevelHistory,
+) -> Result<(f64, f64, f64), String> {
+    let now = unix_time(SystemTime::now());
+    let from = parse_query_param::<f64>(query, "from")?
+        .or_else(|| level_history.start())
+        .unwrap_or(now);
+    let to = parse_query_param::<f64>(query, "to")?.unwrap_or(now);
+    if !(from.is_finite() && to.is_finite() && from <= to) {
+        return Err(format!("invalid time range from {} to {}", from, to));
+    }
+    let resolution = match query_param(query, "resolution") {
+        Some(resolution) => match parse_duration(resolution)?.as_secs_f64() {
+            seconds if seconds >= 1.0 => seconds,
+            _ => return Err(format!("invalid resolution '{}'", resolution)),
+        },
+        None => ((to - from) / history::DEFAULT_MAX_POINTS as f64)
+            .ceil()
+            .max(1.0),
+    };
+    Ok((from, to, resolution))
+}
+
+/// CORS headers of the responses of the JSON and SSE endpoints, for the origin of the request.
+#[derive(Clone)]
+struct CorsHeaders(Vec<(&'static str, String)>);
+
+/// The response with more headers, e.g. of CORS.
+fn with_headers(mut response: Response, headers: &[(&str, String)]) -> Response {
+    for (name, value) in headers {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(value),
+        ) {
+            response.headers_mut().append(name, value);
+        }
+    }
+    response
+}
+
+fn json_response(json: String) -> Response {
+    ([(header::CONTENT_TYPE, "application/json")], json).into_response()
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+/// Answer the CORS preflight requests without the auth, which browsers never send in them,
+/// check the auth, then handle the request with the CORS headers of its origin,
+/// responding with an error if the handler panics.
+async fn serve_request(
+    State(state): State<Arc<HttpState>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let origin = header_str(request.headers(), "Origin").map(str::to_owned);
+    let cors_headers = state
+        .cors
+        .as_ref()
+        .map_or_else(Vec::new, |cors| cors.headers(origin.as_deref()));
+    let is_preflight = request.method() == Method::OPTIONS
+        && request
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+    if let (Some(cors), true) = (&state.cors, is_preflight) {
+        let headers = cors.preflight_headers(origin.as_deref());
+        return with_headers(StatusCode::NO_CONTENT.into_response(), &headers);
+    }
+    if let Some(auth) = &state.auth {
+        let path = request.uri().path();
+        let query = query_params(request.uri().query());
+        let authorization = header_str(request.headers(), "Authorization");
+        let is_open = (state.auth_open_levels && LEVEL_PATHS.contains(&path))
+            || dashboard::is_static_asset(path);
+        if !is_open && !auth.is_authorized(authorization, query_param(&query, auth::TOKEN_PARAM)) {
+            let response = (
+                StatusCode::UNAUTHORIZED,
+                [(header::WWW_AUTHENTICATE, auth.challenge())],
+                "unauthorized",
+            );
+            return with_headers(response.into_response(), &cors_headers);
+        }
+    }
+    request.extensions_mut().insert(CorsHeaders(cors_headers));
+    match state.supervisor.run_request(next.run(request)).await {
+        Some(response) => response,
+        None => (StatusCode::INTERNAL_SERVER_ERROR, "internal server error").into_response(),
+    }
+}
+
+/// The dashboard, or the request echoed for any other path.
+async fn dashboard_or_echo(request: Request) -> Response {
+    match dashboard::asset(request.uri().path()) {
+        Some((content_type, content)) => {
+            ([(header::CONTENT_TYPE, content_type)], content).into_response()
+        }
+        None => format!(
+            "received request!\nmethod: {:?}\nurl: {:?}\nheaders: {:?}",
+            request.method(),
+            request.uri(),
+            request.headers()
+        )
+        .into_response(),
+    }
+}
+
+async fn api_levels(
+    State(state): State<Arc<HttpState>>,
+    Extension(cors_headers): Extension<CorsHeaders>,
+) -> Response {
+    let response = match state.level_snapshot.load() {
+        Some(levels) => json_response(levels.to_json()),
+        None => StatusCode::NO_CONTENT.into_response(),
+    };
+    with_headers(response, &cors_headers.0)
+}
+
+/// Statistics of the levels over a time range, merged to the resolution.
+async fn api_history(
+    State(state): State<Arc<HttpState>>,
+    Extension(cors_headers): Extension<CorsHeaders>,
+    uri: Uri,
+) -> Response {
+    let level_history = match &state.level_history {
+        Some(level_history) => level_history,
+        None => {
+            return (
+                StatusCode::NOT_FOUND,
+                "the level history is disabled, enable it with --level-history",
+            )
+                .into_response()
+        }
+    };
+    let response = match history_query(&query_params(uri.query()), level_history) {
+        Ok((from, to, resolution)) => {
+            let body = serde_json::json!({
+                "from": from,
+                "to": to,
+                "resolution": resolution,
+                "points": level_history.query(from, to, resolution),
+            });
+            json_response(body.to_string())
+        }
+        Err(err) => (StatusCode::BAD_REQUEST, err).into_response(),
+    };
+    with_headers(response, &cors_headers.0)
+}
+
+async fn metrics_text(State(state): State<Arc<HttpState>>) -> Response {
+    let text = state.metrics.render(state.level_snapshot.load().as_deref());
+    ([(header::CONTENT_TYPE, metrics::CONTENT_TYPE)], text).into_response()
+}
+
+/// 503 while the input stream is stopped or its buffers are late, for the probes
+/// restarting the server, and until the first buffer or once the recording failed
+/// for the readiness.
+async fn health_status(State(state): State<Arc<HttpState>>, uri: Uri) -> Response {
+    let report = state.health.report();
+    let ok = if uri.path() == "/healthz" {
+        report.is_healthy()
+    } else {
+        report.is_ready()
+    };
+    let status = if ok {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (
+        status,
+        [(header::CONTENT_TYPE, "application/json")],
+        report.to_json(),
+    )
+        .into_response()
+}
+
+/// Live capture as interleaved raw PCM, in the format of the query params.
+async fn stream_raw(State(state): State<Arc<HttpState>>, uri: Uri) -> Response {
+    let query = query_params(uri.query());
+    match PcmFormat::parse(
+        query_param(&query, "format"),
+        query_param(&query, "endianness"),
+    ) {
+        Ok(pcm_format) => {
+            let stream = PcmStream::new(pcm_format, state.samples_broadcast.subscribe());
+            (
+                [(header::CONTENT_TYPE, "application/octet-stream")],
+                Body::from_stream(stream),
+            )
+                .into_response()
+        }
+        Err(err) => (StatusCode::BAD_REQUEST, err).into_response(),
+    }
+}
+
+/// Live capture as a never ending WAV file, in the sample format of the query param
+/// (WAV samples are always little endian).
+async fn stream_wav(State(state): State<Arc<HttpState>>, uri: Uri) -> Response {
+    let query = query_params(uri.query());
+    match PcmFormat::parse(query_param(&query, "format"), None) {
+        Ok(pcm_format) => {
+            let header = wav::streaming_header(
+                state.num_channels,
+                state.sample_rate,
+                pcm_format.sample_format,
+            );
+            let stream = stream::iter([Ok(header)]).chain(PcmStream::new(
+                pcm_format,
+                state.samples_broadcast.subscribe(),
+            ));
+            (
+                [(header::CONTENT_TYPE, "audio/wav")],
+                Body::from_stream(stream),
+            )
+                .into_response()
+        }
+        Err(err) => (StatusCode::BAD_REQUEST, err).into_response(),
+    }
+}
+
+/// Description of the RTP stream for the receivers.
+async fn stream_sdp(State(state): State<Arc<HttpState>>) -> Response {
+    match &state.rtp_sdp {
+        Some(sdp) => ([(header::CONTENT_TYPE, "application/sdp")], sdp.clone()).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            "there is no RTP stream, enable it with --rtp-dest",
+        )
+            .into_response(),
+    }
+}
+
+/// Push the levels of each input buffer as a JSON text message.
+async fn ws_levels(State(state): State<Arc<HttpState>>, upgrade: WebSocketUpgrade) -> Response {
+    upgrade.on_upgrade(move |socket| {
+        let messages = ReceiverStream::new(state.levels_broadcast.subscribe());
+        websocket::serve_messages(
+            socket,
+            messages.map(|levels| Message::text(levels.to_json())),
+        )
+    })
+}
+
+/// Push the mel spectrogram and MFCC of each frame as a JSON text message.
+async fn ws_features(State(state): State<Arc<HttpState>>, upgrade: WebSocketUpgrade) -> Response {
+    let features_broadcast = match &state.features_broadcast {
+        Some(features_broadcast) => features_broadcast.clone(),
+        None => {
+            return (
+                StatusCode::NOT_FOUND,
+                "there are no features, enable them with --features",
+            )
+                .into_response()
+        }
+    };
+    upgrade.on_upgrade(move |socket| {
+        let messages = ReceiverStream::new(features_broadcast.subscribe());
+        websocket::serve_messages(socket, messages.map(|frame| Message::text(frame.to_json())))
+    })
+}
+
+/// Live capture for the listen page: a JSON text message with the format,
+/// then the interleaved samples of each input buffer as a binary message,
+/// little endian in the sample format of the query param.
+async fn ws_audio(
+    State(state): State<Arc<HttpState>>,
+    uri: Uri,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    let query = query_params(uri.query());
+    let pcm_format = match PcmFormat::parse(query_param(&query, "format"), None) {
+        Ok(pcm_format) => pcm_format,
+        Err(err) => return (StatusCode::BAD_REQUEST, err).into_response(),
+    };
+    let format = serde_json::json!({
+        "sample_rate": state.sample_rate,
+        "channels": state.num_channels,
+        "format": query_param(&query, "format").unwrap_or("s16"),
+    })
+    .to_string();
+    upgrade.on_upgrade(move |socket| {
+        let samples =
+            ReceiverStream::new(state.samples_broadcast.subscribe()).map(move |samples| {
+                let mut bytes = Vec::new();
+                pcm_format.encode(&samples, &mut bytes);
+                Message::binary(bytes)
+            });
+        let messages = stream::once(async move { Message::text(format) }).chain(samples);
+        websocket::serve_messages(socket, messages)
+    })
+}
+
+/// Push the levels as Server-Sent Events, at most `rate` events per second.
+async fn sse_events(
+    State(state): State<Arc<HttpState>>,
+    Extension(cors_headers): Extension<CorsHeaders>,
+    uri: Uri,
+) -> Response {
+    let query = query_params(uri.query());
+    let min_interval = match query_param(&query, "rate") {
+        Some(rate) => rate
+            .parse::<f64>()
+            .ok()
+            .and_then(rate_interval)
+            .ok_or_else(|| format!("invalid rate '{}'", rate)),
+        None => Ok(Duration::from_secs_f64(1.0 / sse::DEFAULT_MAX_RATE)),
+    };
+    match min_interval {
+        Ok(min_interval) => {
+            let events = sse::events(
+                state.levels_broadcast.subscribe(),
+                min_interval,
+                Levels::to_json,
+            );
+            let response = (
+                [
+                    (header::CONTENT_TYPE, sse::CONTENT_TYPE),
+                    (header::CACHE_CONTROL, "no-cache"),
+                ],
+                Body::from_stream(events),
+            );
+            with_headers(response.into_response(), &cors_headers.0)
+        }
+        Err(err) => (StatusCode::BAD_REQUEST, err).into_response(),
+    }
+}
+
+/// The state of the controls as JSON, after changing them with the command of the JSON
+/// body of a POST request, the responses have the CORS headers.
+async fn api_control(
+    State(state): State<Arc<HttpState>>,
+    Extension(cors_headers): Extension<CorsHeaders>,
+    request: Request,
+) -> Response {
+    if state.auth.is_none() {
+        return (
+            StatusCode::NOT_FOUND,
+            "the control api is disabled, enable it with --auth",
+        )
+            .into_response();
+    }
+    let error = match *request.method() {
+        Method::GET => None,
+        Method::POST => {
+            let command = match axum::body::to_bytes(request.into_body(), MAX_CONTROL_BODY).await {
+                Ok(body) => serde_json::from_slice::<ControlCommand>(&body)
+                    .map_err(|err| format!("invalid control command: {}", err)),
+                Err(err) => Err(format!("failed to read the control command: {}", err)),
+            };
+            command
+                .and_then(|command| state.controls.apply(command))
+                .err()
+                .map(|err| (StatusCode::BAD_REQUEST, err).into_response())
+        }
+        _ => Some(
+            (
+                StatusCode::METHOD_NOT_ALLOWED,
+                [(header::ALLOW, "GET, POST")],
+                "expected a GET or POST request",
+            )
+                .into_response(),
+        ),
+    };
+    let response = error
+        .unwrap_or_else(|| json_response(serde_json::to_string(&state.controls.state()).unwrap()));
+    with_headers(response, &cors_headers.0)
+}
+
+/// The listing of the recordings directory as JSON, with the CORS headers.
+async fn recordings_list(
+    State(state): State<Arc<HttpState>>,
+    Extension(cors_headers): Extension<CorsHeaders>,
+) -> Response {
+    let directory = match &state.recordings_dir {
+        Some(directory) => directory,
+        None => return (StatusCode::NOT_FOUND, "there is no recordings directory").into_response(),
+    };
+    // the durations are read from the headers of the files
+    let response = match tokio::task::block_in_place(|| archive::list(directory)) {
+        Ok(files) => json_response(serde_json::to_string(&files).unwrap()),
+        Err(err) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("failed to list the recordings directory: {}", err),
+        )
+            .into_response(),
+    };
+    with_headers(response, &cors_headers.0)
+}
+
+/// A file of the recordings directory or the requested range of its bytes, or delete it
+/// if allowed, never without the credentials of `--auth` like the control api.
+async fn recording_file(State(state): State<Arc<HttpState>>, request: Request) -> Response {
+    let directory = match &state.recordings_dir {
+        Some(directory) => directory,
+        None => return (StatusCode::NOT_FOUND, "there is no recordings directory").into_response(),
+    };
+    let name = request.uri().path().trim_start_matches("/recordings/");
+    let file_path = match archive::file_path(directory, name) {
+        Some(file_path) if file_path.is_file() => file_path,
+        _ => return (StatusCode::NOT_FOUND, "recording not found").into_response(),
+    };
+
+    match *request.method() {
+        Method::GET | Method::HEAD => {
+            let range = header_str(request.headers(), "Range");
+            let head = request.method() == Method::HEAD;
+            file_response(&file_path, range, head)
+                .await
+                .unwrap_or_else(|err| {
+                    (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        format!("failed to read the recording: {}", err),
+                    )
+                        .into_response()
+                })
+        }
+        Method::DELETE if state.recordings_delete && state.auth.is_some() => {
+            match tokio::fs::remove_file(&file_path).await {
+                Ok(()) => StatusCode::NO_CONTENT.into_response(),
+                Err(err) => (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("failed to delete the recording: {}", err),
+                )
+                    .into_response(),
+            }
+        }
+        Method::DELETE if state.recordings_delete => {
+            (StatusCode::FORBIDDEN, "deleting recordings requires --auth").into_response()
+        }
+        Method::DELETE => (
+            StatusCode::FORBIDDEN,
+            "deleting recordings is disabled, enable it with --recordings-delete",
+        )
+            .into_response(),
+        _ => (
+            StatusCode::METHOD_NOT_ALLOWED,
+            [(header::ALLOW, "GET, HEAD, DELETE")],
+            "expected a GET, HEAD or DELETE request",
+        )
+            .into_response(),
+    }
+}
+
+/// The file, or the range of its bytes of the `Range` header, streamed from the disk,
+/// without the body for a HEAD request.
+async fn file_response(path: &Path, range: Option<&str>, head: bool) -> io::Result<Response> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let len = file.metadata().await?.len();
+    let (status, first, last) = match archive::file_range(range, len) {
+        FileRange::Whole => (StatusCode::OK, 0, len.saturating_sub(1)),
+        FileRange::Partial(first, last) => (StatusCode::PARTIAL_CONTENT, first, last),
+        FileRange::NotSatisfiable => {
+            let content_range = format!("bytes */{}", len);
+            return Ok((
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                [(header::CONTENT_RANGE, content_range)],
+            )
+                .into_response());
+        }
+    };
+    let range_len = if len == 0 { 0 } else { last - first + 1 };
+    let body = if head {
+        Body::empty()
+    } else {
+        file.seek(SeekFrom::Start(first)).await?;
+        Body::from_stream(ReaderStream::new(file.take(range_len)))
+    };
+    let mut response = Response::new(body);
+    *response.status_mut() = status;
+    let headers = response.headers_mut();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static(archive::content_type(path)),
+    );
+    headers.insert(header::CONTENT_LENGTH, HeaderValue::from(range_len));
+    headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    if status == StatusCode::PARTIAL_CONTENT {
+        let content_range = format!("bytes {}-{}/{}", first, last, len);
+        headers.insert(
+            header::CONTENT_RANGE,
+            HeaderValue::from_str(&content_range).unwrap(),
+        );
+    }
+    Ok(response)
+}
+
+/// Spectrogram of a channel of the last seconds kept in memory for the snapshots.
+async fn spectrogram_image(State(state): State<Arc<HttpState>>, uri: Uri) -> Response {
+    let snapshot = match &state.snapshot {
+        Some((snapshot, _)) => snapshot,
+        None => return (
+            StatusCode::NOT_FOUND,
+            "the spectrogram needs the last seconds kept in memory, enable them with --snapshot",
+        )
+            .into_response(),
+    };
+    match spectrogram_query(&query_params(uri.query()), state.num_channels) {
+        Ok((channel, seconds, config)) => {
+            let png = tokio::task::block_in_place(|| {
+                let samples = snapshot.channel_samples(channel, seconds.unwrap_or(f64::MAX));
+                spectrogram::render(&samples, config)
+            });
+            ([(header::CONTENT_TYPE, "image/png")], png).into_response()
+        }
+        Err(err) => (StatusCode::BAD_REQUEST, err).into_response(),
+    }
+}
+
+/// Min/max waveform of the last seconds, of a channel or all of them one above the other.
+async fn waveform_image(State(state): State<Arc<HttpState>>, uri: Uri) -> Response {
+    match waveform_query(&query_params(uri.query()), state.num_channels) {
+        Ok((channel, seconds, width, height)) => tokio::task::block_in_place(|| {
+            let mut envelope = state.waveform.envelope(seconds.unwrap_or(f64::MAX));
+            if let Some(channel) = channel {
+                envelope.channels = vec![envelope.channels.swap_remove(channel)];
+            }
+            if uri.path() == "/waveform.svg" {
+                let svg = envelope.to_svg(width, height);
+                ([(header::CONTENT_TYPE, "image/svg+xml")], svg).into_response()
+            } else {
+                let png = envelope.to_png(width, height);
+                ([(header::CONTENT_TYPE, "image/png")], png).into_response()
+            }
+        }),
+        Err(err) => (StatusCode::BAD_REQUEST, err).into_response(),
+    }
+}
+
+/// The last seconds of the input as a WAV file,
+/// or saved to the snapshot directory with the save query param.
+async fn api_snapshot(State(state): State<Arc<HttpState>>, method: Method, uri: Uri) -> Response {
+    let query = query_params(uri.query());
+    match &state.snapshot {
+        _ if method != Method::POST => (
+            StatusCode::METHOD_NOT_ALLOWED,
+            [(header::ALLOW, "POST")],
+            "expected a POST request",
+        )
+            .into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            "snapshots are disabled, enable them with --snapshot",
+        )
+            .into_response(),
+        Some((snapshot, directory)) if query_param(&query, "save").is_some() => {
+            match tokio::task::block_in_place(|| snapshot.save(directory)) {
+                Ok(path) => {
+                    let json = serde_json::json!({ "path": path.to_string_lossy() });
+                    json_response(json.to_string())
+                }
+                Err(err) => (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("failed to save the snapshot: {}", err),
+                )
+                    .into_response(),
+            }
+        }
+        Some((snapshot, _)) => match tokio::task::block_in_place(|| snapshot.take()) {
+            Ok((wav, start)) => {
+                let file_name = recording::format_file_name(snapshot::FILE_NAME_TEMPLATE, start);
+                (
+                    [
+                        (header::CONTENT_TYPE, String::from("audio/wav")),
+                        (
+                            header::CONTENT_DISPOSITION,
+                            format!("attachment; filename=\"{}\"", file_name),
+                        ),
+                    ],
+                    wav,
+                )
+                    .into_response()
+            }
+            Err(err) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("failed to take the snapshot: {}", err),
+            )
+                .into_response(),
+        },
+    }
+}
+
+/// The endpoints of the http server, any other path echoes the request.
+pub fn router(state: Arc<HttpState>) -> Router {
+    Router::new()
+        .route("/api/levels", any(api_levels))
+        .route("/api/history", any(api_history))
+        .route("/metrics", any(metrics_text))
+        .route("/healthz", any(health_status))
+        .route("/readyz", any(health_status))
+        .route("/stream.raw", any(stream_raw))
+        .route("/stream.wav", any(stream_wav))
+        .route("/stream.sdp", any(stream_sdp))
+        .route("/stream.ogg", any(stream_ogg))
+        .route("/hls/{*name}", any(hls_file))
+        .route("/ws/levels", get(ws_levels))
+        .route("/ws/features", get(ws_features))
+        .route("/ws/audio", get(ws_audio))
+        .route("/events", any(sse_events))
+        .route("/recordings", any(recordings_list))
+        .route("/recordings/{*name}", any(recording_file))
+        .route("/api/control", any(api_control))
+        .route("/spectrogram.png", any(spectrogram_image))
+        .route("/waveform.svg", any(waveform_image))
+        .route("/waveform.png", any(waveform_image))
+        .route("/api/snapshot", any(api_snapshot))
+        .fallback(dashboard_or_echo)
+        .layer(middleware::from_fn_with_state(
+            Arc::clone(&state),
+            serve_request,
+        ))
+        .with_state(state)
+}
+
+/// Bind a listener of the http server, or of the gRPC api, to the address.
+pub fn bind(listen_addr: &str) -> Result<tokio::net::TcpListener, String> {
+    std::net::TcpListener::bind(listen_addr)
+        .and_then(|listener| {
+            listener.set_nonblocking(true)?;
+            tokio::net::TcpListener::from_std(listener)
+        })
+        .map_err(|err| format!("failed to listen on '{}': {}", listen_addr, err))
+}
+
+/// Serve the router on the listener from a task of the runtime, over HTTPS with a TLS config,
+/// the failed handshakes reported to the supervisor.
+pub fn serve(
+    listener: tokio::net::TcpListener,
+    router: Router,
+    tls_config: Option<Arc<rustls::ServerConfig>>,
+    supervisor: &Supervisor,
+) -> Result<(), String> {
+    match tls_config {
+        Some(tls_config) => {
+            let listener = tls::TlsListener::new(listener, tls_config, supervisor.clone())
+                .map_err(|err| format!("failed to listen over HTTPS: {}", err))?;
+            tokio::spawn(axum::serve(listener, router).into_future());
+        }
+        None => {
+            tokio::spawn(axum::serve(listener, router).into_future());
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,162 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Client of the uploads over HTTP and HTTPS: the webhooks, the recordings to S3 and the
+//! metrics to InfluxDB, with a connection for each request.
+
+use crate::tls;
+use bytes::Bytes;
+use http_body_util::BodyExt;
+use hyper::body::Body;
+use hyper::header::{HOST, USER_AGENT};
+use hyper::{Method, Request, Uri};
+use hyper_util::rt::TokioIo;
+use std::error::Error;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+
+/// A response, with its body as text.
+#[derive(Clone, Debug)]
+pub struct Response {
+    pub status: u16,
+    pub body: String,
+}
+
+impl Response {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+/// The TLS config of all the connections, with the root certificates loaded once.
+fn client_config() -> Arc<rustls::ClientConfig> {
+    static CONFIG: OnceLock<Arc<rustls::ClientConfig>> = OnceLock::new();
+    Arc::clone(CONFIG.get_or_init(tls::client_config))
+}
+
+/// Send the request of `method` to the `http://` or `https://` url, with the headers
+/// and the body, failing if the response has not ended within the `timeout`.
+pub async fn send<B>(
+    method: Method,
+    url: &str,
+    headers: &[(&str, String)],
+    body: B,
+    timeout: Duration,
+) -> Result<Response, String>
+where
+    B: Body + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<Box<dyn Error + Send + Sync>>,
+{
+    match tokio::time::timeout(timeout, send_request(method, url, headers, body)).await {
+        Ok(result) => result,
+        Err(_) => Err(format!("timed out after {} s", timeout.as_secs())),
+    }
+}
+
+async fn send_request<B>(
+    method: Method,
+    url: &str,
+    headers: &[(&str, String)],
+    body: B,
+) -> Result<Response, String>
+where
+    B: Body + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<Box<dyn Error + Send + Sync>>,
+{
+    let uri: Uri = url
+        .parse()
+        .map_err(|err| format!("invalid url '{}': {}", url, err))?;
+    let https = match uri.scheme_str() {
+        Some("https") => true,
+        Some("http") => false,
+        _ => {
+            return Err(format!(
+                "invalid url '{}', expected http:// or https://",
+                url
+            ))
+        }
+    };
+    let (host, authority) = match uri.authority() {
+        // the brackets of an IPv6 address are only kept in the Host header
+        Some(authority) => (
+            authority
+                .host()
+                .trim_start_matches('[')
+                .trim_end_matches(']'),
+            authority.as_str(),
+        ),
+        None => return Err(format!("invalid url '{}', missing host", url)),
+    };
+    let port = uri.port_u16().unwrap_or(if https { 443 } else { 80 });
+
+    let mut request = Request::builder()
+        .method(method)
+        .uri(uri.path_and_query().map_or("/", |path| path.as_str()))
+        .header(HOST, authority)
+        .header(
+            USER_AGENT,
+            concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION")),
+        );
+    for (name, value) in headers {
+        request = request.header(*name, value);
+    }
+    let request = request.body(body).map_err(|err| err.to_string())?;
+
+    let stream = TcpStream::connect((host, port))
+        .await
+        .map_err(|err| err.to_string())?;
+    if https {
+        let stream = tls::connect(stream, host, client_config())
+            .await
+            .map_err(|err| err.to_string())?;
+        exchange(stream, request).await
+    } else {
+        exchange(stream, request).await
+    }
+}
+
+/// Send the request on the connection, and receive the whole response.
+async fn exchange<T, B>(io: T, request: Request<B>) -> Result<Response, String>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    B: Body + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<Box<dyn Error + Send + Sync>>,
+{
+    let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(io))
+        .await
+        .map_err(|err| err.to_string())?;
+    // the connection is driven until the response ends
+    tokio::spawn(connection);
+    let response = sender
+        .send_request(request)
+        .await
+        .map_err(|err| err.to_string())?;
+    let status = response.status().as_u16();
+    let body: Bytes = response
+        .into_body()
+        .collect()
+        .await
+        .map_err(|err| err.to_string())?
+        .to_bytes();
+    Ok(Response {
+        status,
+        body: String::from_utf8_lossy(&body).into_owned(),
+    })
+}
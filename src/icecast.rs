@@ -0,0 +1,189 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Icecast source client, publishing the live capture as Ogg/Opus.
+
+use crate::error::Error;
+use crate::ogg_opus::OggOpusEncoder;
+use crate::supervisor::Supervisor;
+use base64::Engine;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+
+/// delay before reconnecting after the connection to the server is lost
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Icecast mount point, from an url like `http://[user@]host[:port]/mount`
+/// (the user defaults to `source`).
+#[derive(Clone, Debug)]
+pub struct IcecastUrl {
+    pub host: String,
+    pub port: u16,
+    pub mount: String,
+    pub user: String,
+}
+
+impl IcecastUrl {
+    pub fn parse(url: &str) -> Result<Self, String> {
+        let invalid_url = || format!("invalid Icecast url '{}'", url);
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| format!("invalid Icecast url '{}', only http:// is supported", url))?;
+        let (authority, mount) = match rest.find('/') {
+            Some(index) => rest.split_at(index),
+            None => {
+                return Err(format!(
+                    "invalid Icecast url '{}', missing mount point",
+                    url
+                ))
+            }
+        };
+        let (user, host_port) = match authority.rsplit_once('@') {
+            Some((user, host_port)) => (user, host_port),
+            None => ("source", authority),
+        };
+        let (host, port) = match host_port.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => {
+                (host, port.parse().map_err(|_| invalid_url())?)
+            }
+            _ => (host_port, 8000),
+        };
+        if host.is_empty() || mount.len() < 2 {
+            return Err(invalid_url());
+        }
+
+        Ok(IcecastUrl {
+            host: host.to_owned(),
+            port,
+            mount: mount.to_owned(),
+            user: user.to_owned(),
+        })
+    }
+}
+
+/// Connect to the server as the source of the mount point, with an HTTP PUT request.
+async fn connect(url: &IcecastUrl, password: &str) -> io::Result<TcpStream> {
+    let mut stream = TcpStream::connect((url.host.as_str(), url.port)).await?;
+
+    let credentials =
+        base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", url.user, password));
+    let request = format!(
+        "PUT {} HTTP/1.1\r\n\
+         Host: {}:{}\r\n\
+         Authorization: Basic {}\r\n\
+         User-Agent: {}/{}\r\n\
+         Content-Type: audio/ogg\r\n\
+         Ice-Public: 0\r\n\
+         Expect: 100-continue\r\n\
+         \r\n",
+        url.mount,
+        url.host,
+        url.port,
+        credentials,
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION")
+    );
+    stream.write_all(request.as_bytes()).await?;
+
+    // read the response headers, the server answers either 100 Continue or 200 OK
+    let mut response = Vec::new();
+    let mut byte = [0_u8; 1];
+    while !response.ends_with(b"\r\n\r\n") {
+        if stream.read(&mut byte).await? == 0 || response.len() > 8192 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid response from the Icecast server",
+            ));
+        }
+        response.push(byte[0]);
+    }
+    let response = String::from_utf8_lossy(&response);
+    let status_line = response.lines().next().unwrap_or_default();
+    match status_line.split_whitespace().nth(1) {
+        Some("100") | Some("200") => Ok(stream),
+        _ => Err(io::Error::other(format!(
+            "rejected by the Icecast server: {}",
+            status_line
+        ))),
+    }
+}
+
+/// Publish the interleaved samples received from a broadcast to the Icecast server,
+/// reconnecting when the connection is lost, until the sender goes away. The failures
+/// are reported to the supervisor.
+pub async fn run_source_client(
+    url: IcecastUrl,
+    password: String,
+    bitrate: i32,
+    channels: u16,
+    sample_rate: u32,
+    mut samples: mpsc::Receiver<Arc<Vec<f32>>>,
+    supervisor: Supervisor,
+) {
+    loop {
+        let mut stream = match connect(&url, &password).await {
+            Ok(stream) => stream,
+            Err(err) => {
+                supervisor.report(Error::Output(format!(
+                    "failed to connect to Icecast server {}:{}{}: {}",
+                    url.host, url.port, url.mount, err
+                )));
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                continue;
+            }
+        };
+
+        let mut encoder = match OggOpusEncoder::new(channels, sample_rate, bitrate) {
+            Ok(encoder) => encoder,
+            Err(err) => {
+                supervisor.report(Error::Output(format!(
+                    "stopped the Icecast stream: {}",
+                    err
+                )));
+                return;
+            }
+        };
+
+        // discard the samples queued while disconnected
+        while samples.try_recv().is_ok() {}
+
+        loop {
+            let samples = match samples.recv().await {
+                Some(samples) => samples,
+                None => return,
+            };
+            if let Err(err) = encoder.encode(&samples) {
+                supervisor.report(Error::Output(format!(
+                    "stopped the Icecast stream: {}",
+                    err
+                )));
+                return;
+            }
+            if let Err(err) = stream.write_all(&encoder.take_bytes()).await {
+                supervisor.report(Error::Output(format!(
+                    "connection to Icecast server {}:{}{} lost: {}",
+                    url.host, url.port, url.mount, err
+                )));
+                break;
+            }
+        }
+
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
@@ -0,0 +1,171 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Input from the ports of a JACK client, `audio-in-stream:in_1`, `audio-in-stream:in_2`...
+//! instead of a device, so any source can be patched into the monitor.
+
+use crate::ring::RingWriter;
+use std::sync::{Mutex, PoisonError};
+use std::time::SystemTime;
+
+pub const DEFAULT_CLIENT_NAME: &str = "audio-in-stream";
+
+/// A JACK client with an input port for each channel, `in_1` to `in_N`.
+pub struct JackInput {
+    client: jack::Client,
+    ports: Vec<jack::Port<jack::AudioIn>>,
+    /// source ports connected to the input ports once active, in order
+    connect: Vec<String>,
+}
+
+impl JackInput {
+    /// Client named `client_name` with `channels` input ports, connected to the `connect`
+    /// source ports when started, the first one to `in_1` and so on, left unconnected
+    /// to patch them by other means if empty.
+    pub fn new(client_name: &str, channels: u16, connect: Vec<String>) -> Result<Self, String> {
+        if connect.len() > channels as usize {
+            return Err(format!(
+                "{} JACK port(s) to connect to {} input port(s)",
+                connect.len(),
+                channels
+            ));
+        }
+        let (client, _) = jack::Client::new(client_name, jack::ClientOptions::NO_START_SERVER)
+            .map_err(|err| format!("failed to open the JACK client '{}': {}", client_name, err))?;
+        let ports = (1..=channels)
+            .map(|channel| {
+                client
+                    .register_port(&format!("in_{}", channel), jack::AudioIn)
+                    .map_err(|err| format!("failed to register the JACK port: {}", err))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(JackInput {
+            client,
+            ports,
+            connect,
+        })
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.ports.len() as u16
+    }
+
+    /// Sample rate of the JACK server.
+    pub fn sample_rate(&self) -> u32 {
+        self.client.sample_rate() as u32
+    }
+
+    /// Name of the client, which JACK makes unique, e.g. `audio-in-stream-01`.
+    pub fn name(&self) -> String {
+        self.client.name().to_string()
+    }
+
+    /// Activate the client, writing the interleaved samples of its ports to the ring
+    /// from the process callback, and calling `error_callback` if the JACK server
+    /// shuts the client down.
+    ///
+    /// The client is closed, and the readers of the ring end, when the returned stream is dropped.
+    pub fn start<E>(self, ring_writer: RingWriter, error_callback: E) -> Result<JackStream, String>
+    where
+        E: FnMut(String) + Send + 'static,
+    {
+        let port_names = self
+            .ports
+            .iter()
+            .map(|port| port.name())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| format!("failed to get the JACK port name: {}", err))?;
+        let max_frames = self.client.buffer_size() as usize;
+        let process = Process {
+            samples: Vec::with_capacity(max_frames * self.ports.len()),
+            max_frames,
+            ports: self.ports,
+            ring_writer,
+        };
+        let client = self
+            .client
+            .activate_async(
+                Notifications {
+                    error_callback: Mutex::new(Box::new(error_callback)),
+                },
+                process,
+            )
+            .map_err(|err| format!("failed to activate the JACK client: {}", err))?;
+        for (source, destination) in self.connect.iter().zip(&port_names) {
+            client
+                .as_client()
+                .connect_ports_by_name(source, destination)
+                .map_err(|err| {
+                    format!(
+                        "failed to connect the JACK port '{}' to '{}': {}",
+                        source, destination, err
+                    )
+                })?;
+        }
+        Ok(JackStream { _client: client })
+    }
+}
+
+struct Process {
+    ports: Vec<jack::Port<jack::AudioIn>>,
+    /// interleaved samples of up to `max_frames`, so the process callback does not allocate
+    samples: Vec<f32>,
+    max_frames: usize,
+    ring_writer: RingWriter,
+}
+
+impl jack::ProcessHandler for Process {
+    fn process(&mut self, _: &jack::Client, process_scope: &jack::ProcessScope) -> jack::Control {
+        let timestamp = SystemTime::now();
+        let frames = process_scope.n_frames() as usize;
+        // in parts of at most the buffer size when the client was activated
+        let mut start = 0;
+        while start < frames {
+            let end = frames.min(start + self.max_frames.max(1));
+            self.samples.clear();
+            for frame in start..end {
+                self.samples.extend(
+                    self.ports
+                        .iter()
+                        .map(|port| port.as_slice(process_scope)[frame]),
+                );
+            }
+            self.ring_writer.push(&self.samples, timestamp);
+            start = end;
+        }
+        jack::Control::Continue
+    }
+}
+
+struct Notifications {
+    error_callback: Mutex<Box<dyn FnMut(String) + Send>>,
+}
+
+impl jack::NotificationHandler for Notifications {
+    fn shutdown(&mut self, _: jack::ClientStatus, reason: &str) {
+        (self
+            .error_callback
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner))(format!(
+            "the JACK server shut down the client: {}",
+            reason
+        ));
+    }
+}
+
+/// The active JACK client, closed when dropped.
+pub struct JackStream {
+    _client: jack::AsyncClient<Notifications, Process>,
+}
@@ -0,0 +1,334 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Round-trip latency measurement: a chirp played on an output device at each interval
+//! is detected on the input by normalized cross-correlation, the latency being the time
+//! from the output callback playing its first sample to the input callback capturing it.
+
+use cpal::traits::{DeviceTrait, StreamTrait};
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+use std::sync::mpsc;
+use std::time::{Duration, SystemTime};
+
+/// seconds of the chirp
+const CHIRP_DURATION: f64 = 0.01;
+/// Hz, start and end frequencies of the chirp
+const CHIRP_START: f64 = 500.0;
+const CHIRP_END: f64 = 8000.0;
+/// peak of the chirp, -6 dBFS
+const CHIRP_AMPLITUDE: f64 = 0.5;
+/// normalized cross-correlation of the input with the chirp detecting it
+const DETECTION_THRESHOLD: f32 = 0.5;
+
+/// Samples of a linear chirp in a Hann window at the sample rate.
+pub fn chirp(sample_rate: u32) -> Vec<f32> {
+    let len = (CHIRP_DURATION * sample_rate as f64).round() as usize;
+    (0..len)
+        .map(|index| {
+            let time = index as f64 / sample_rate as f64;
+            let phase = 2.0
+                * PI
+                * (CHIRP_START * time
+                    + (CHIRP_END - CHIRP_START) * time * time / (2.0 * CHIRP_DURATION));
+            let window = 0.5 - 0.5 * (2.0 * PI * index as f64 / (len - 1) as f64).cos();
+            (CHIRP_AMPLITUDE * window * phase.sin()) as f32
+        })
+        .collect()
+}
+
+/// the time `offset` seconds from `time`, before it when negative
+fn offset_time(time: SystemTime, offset: f64) -> SystemTime {
+    if offset >= 0.0 {
+        time + Duration::from_secs_f64(offset)
+    } else {
+        time - Duration::from_secs_f64(-offset)
+    }
+}
+
+/// Plays a chirp at each interval until dropped.
+pub struct LatencyOutput {
+    _stream: cpal::Stream,
+}
+
+impl LatencyOutput {
+    /// Play a chirp on all the channels of `device` each `interval` seconds, from one
+    /// interval after the start, sending the time of its first sample to `emissions`,
+    /// and calling `error_callback` with the errors of the output stream.
+    pub fn start<E>(
+        device: &cpal::Device,
+        interval: f64,
+        emissions: mpsc::Sender<SystemTime>,
+        error_callback: E,
+    ) -> Result<Self, String>
+    where
+        E: FnMut(cpal::StreamError) + Send + 'static,
+    {
+        let config = device
+            .default_output_config()
+            .map_err(|err| format!("failed to get the latency output config: {}", err))?;
+        let stream = match config.sample_format() {
+            cpal::SampleFormat::U16 => {
+                build_output_stream::<u16, _>(device, &config, interval, emissions, error_callback)
+            }
+            cpal::SampleFormat::I16 => {
+                build_output_stream::<i16, _>(device, &config, interval, emissions, error_callback)
+            }
+            cpal::SampleFormat::I32 => {
+                build_output_stream::<i32, _>(device, &config, interval, emissions, error_callback)
+            }
+            cpal::SampleFormat::F32 => {
+                build_output_stream::<f32, _>(device, &config, interval, emissions, error_callback)
+            }
+            sample_format => {
+                return Err(format!(
+                    "unsupported latency output sample format {:?}",
+                    sample_format
+                ))
+            }
+        }
+        .map_err(|err| format!("failed to build the latency output stream: {}", err))?;
+        stream
+            .play()
+            .map_err(|err| format!("failed to play the latency output stream: {}", err))?;
+        Ok(LatencyOutput { _stream: stream })
+    }
+}
+
+fn build_output_stream<T, E>(
+    device: &cpal::Device,
+    config: &cpal::SupportedStreamConfig,
+    interval: f64,
+    emissions: mpsc::Sender<SystemTime>,
+    error_callback: E,
+) -> Result<cpal::Stream, cpal::BuildStreamError>
+where
+    T: cpal::SizedSample + cpal::FromSample<f32>,
+    E: FnMut(cpal::StreamError) + Send + 'static,
+{
+    let channels = config.channels() as usize;
+    let sample_rate = config.sample_rate().0;
+    let chirp = chirp(sample_rate);
+    let interval_frames = ((interval * sample_rate as f64).round() as u64).max(1);
+    let mut position = 0u64;
+    let mut chirp_index = chirp.len();
+    device.build_output_stream(
+        &config.config(),
+        move |output: &mut [T], _: &cpal::OutputCallbackInfo| {
+            let now = SystemTime::now();
+            for (index, frame) in output.chunks_exact_mut(channels).enumerate() {
+                position += 1;
+                if position.is_multiple_of(interval_frames) {
+                    chirp_index = 0;
+                    emissions
+                        .send(offset_time(now, index as f64 / sample_rate as f64))
+                        .ok();
+                }
+                let sample = chirp.get(chirp_index).copied().unwrap_or(0.0);
+                chirp_index += 1;
+                frame.fill(T::from_sample(sample));
+            }
+        },
+        error_callback,
+        None,
+    )
+}
+
+/// Detects the chirp in the input by normalized cross-correlation.
+pub struct ChirpDetector {
+    chirp: Vec<f32>,
+    chirp_energy: f32,
+    sample_rate: f64,
+    /// the last input samples twice, so those from `position` are contiguous
+    history: Vec<f32>,
+    position: usize,
+    /// input samples seen, up to the length of the chirp
+    filled: usize,
+    /// the best correlation over the threshold, with the samples since it and its start time
+    candidate: Option<(f32, usize, SystemTime)>,
+}
+
+impl ChirpDetector {
+    pub fn new(sample_rate: u32) -> Self {
+        let chirp = chirp(sample_rate);
+        let chirp_energy = chirp.iter().map(|sample| sample * sample).sum();
+        ChirpDetector {
+            history: vec![0.0; 2 * chirp.len()],
+            chirp,
+            chirp_energy,
+            sample_rate: sample_rate as f64,
+            position: 0,
+            filled: 0,
+            candidate: None,
+        }
+    }
+
+    /// Process the interleaved `samples` of `num_channels` captured from `timestamp`,
+    /// the channels mixed, returns the start time of the chirps detected.
+    pub fn process(
+        &mut self,
+        samples: &[f32],
+        num_channels: usize,
+        timestamp: SystemTime,
+    ) -> Vec<SystemTime> {
+        let len = self.chirp.len();
+        let mut detections = Vec::new();
+        for (index, frame) in samples.chunks_exact(num_channels).enumerate() {
+            let sample = frame.iter().sum::<f32>() / num_channels as f32;
+            self.history[self.position] = sample;
+            self.history[self.position + len] = sample;
+            self.position = (self.position + 1) % len;
+            self.filled = (self.filled + 1).min(len);
+
+            if let Some((_, since, start)) = self.candidate.as_mut() {
+                *since += 1;
+                // the correlation peaked a chirp ago
+                if *since >= len {
+                    detections.push(*start);
+                    self.candidate = None;
+                }
+            }
+            if self.filled < len {
+                continue;
+            }
+            let window = &self.history[self.position..self.position + len];
+            let energy: f32 = window.iter().map(|sample| sample * sample).sum();
+            if energy <= 0.0 {
+                continue;
+            }
+            let correlation = window
+                .iter()
+                .zip(&self.chirp)
+                .map(|(sample, chirp)| sample * chirp)
+                .sum::<f32>()
+                / (energy * self.chirp_energy).sqrt();
+            if correlation >= DETECTION_THRESHOLD
+                && self.candidate.is_none_or(|(best, _, _)| correlation > best)
+            {
+                let start = offset_time(
+                    timestamp,
+                    (index as f64 + 1.0 - len as f64) / self.sample_rate,
+                );
+                self.candidate = Some((correlation, 0, start));
+            }
+        }
+        detections
+    }
+}
+
+/// Trials of the round-trip latency: the chirps detected on the input matched with the
+/// latest one played before them, a chirp not detected within the interval ends its trial
+/// without a latency.
+pub struct LatencyTrials {
+    detector: ChirpDetector,
+    /// play times of the chirps not detected yet
+    pending: VecDeque<SystemTime>,
+    /// seconds
+    interval: f64,
+}
+
+impl LatencyTrials {
+    pub fn new(sample_rate: u32, interval: f64) -> Self {
+        LatencyTrials {
+            detector: ChirpDetector::new(sample_rate),
+            pending: VecDeque::new(),
+            interval,
+        }
+    }
+
+    /// A chirp played at the time of its first sample.
+    pub fn push_emission(&mut self, emission: SystemTime) {
+        self.pending.push_back(emission);
+    }
+
+    /// Process the interleaved `samples` of `num_channels` captured from `timestamp`,
+    /// returns the trials ended in order, with their latency in seconds,
+    /// or `None` if the chirp was not detected.
+    pub fn process(
+        &mut self,
+        samples: &[f32],
+        num_channels: usize,
+        timestamp: SystemTime,
+    ) -> Vec<Option<f64>> {
+        let mut trials = Vec::new();
+        for detection in self.detector.process(samples, num_channels, timestamp) {
+            // the latest chirp played before, those earlier were not detected
+            let mut emission = None;
+            while let Some(front) = self
+                .pending
+                .front()
+                .copied()
+                .filter(|&front| front <= detection)
+            {
+                self.pending.pop_front();
+                if emission.replace(front).is_some() {
+                    trials.push(None);
+                }
+            }
+            if let Some(emission) = emission {
+                let latency = detection.duration_since(emission).unwrap_or_default();
+                trials.push(Some(latency.as_secs_f64()));
+            }
+        }
+        // no latency over the interval
+        while self.pending.front().is_some_and(|&front| {
+            timestamp
+                .duration_since(front)
+                .is_ok_and(|age| age.as_secs_f64() > self.interval)
+        }) {
+            self.pending.pop_front();
+            trials.push(None);
+        }
+        trials
+    }
+}
+
+/// Round-trip latencies measured, in seconds.
+#[derive(Default)]
+pub struct LatencyStats {
+    latencies: Vec<f64>,
+}
+
+impl LatencyStats {
+    pub fn push(&mut self, latency: f64) {
+        self.latencies.push(latency);
+    }
+
+    pub fn len(&self) -> usize {
+        self.latencies.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latencies.is_empty()
+    }
+
+    /// The minimum, average and maximum latency, and the jitter as its standard deviation.
+    pub fn summary(&self) -> Option<(f64, f64, f64, f64)> {
+        if self.latencies.is_empty() {
+            return None;
+        }
+        let count = self.latencies.len() as f64;
+        let min = self.latencies.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = self.latencies.iter().copied().fold(0.0, f64::max);
+        let avg = self.latencies.iter().sum::<f64>() / count;
+        let variance = self
+            .latencies
+            .iter()
+            .map(|latency| (latency - avg) * (latency - avg))
+            .sum::<f64>()
+            / count;
+        Some((min, avg, max, variance.sqrt()))
+    }
+}
@@ -0,0 +1,274 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Acoustic statistics of the levels: equivalent continuous level (Leq)
+//! over windows of whole seconds, and the daily percentile levels L10, L50 and L90.
+
+use crate::{decibels_overload, ChannelData};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// seconds, windows of the Leq unless configured
+pub const DEFAULT_WINDOWS: [u32; 2] = [1, 60];
+
+/// dB, range and resolution of the histogram of the levels of each second,
+/// for the percentile levels
+const HISTOGRAM_MIN_LEVEL: f32 = -150.0;
+const HISTOGRAM_MAX_LEVEL: f32 = 10.0;
+const HISTOGRAM_BIN_WIDTH: f32 = 0.1;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Equivalent continuous level over a window.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct WindowLeq {
+    /// seconds
+    pub window: u32,
+    /// `null` in JSON until a whole window is measured, or for silence (-inf)
+    pub level: Option<f32>,
+}
+
+/// Acoustic statistics of a channel, in dBov, or in dB SPL once calibrated.
+#[derive(Clone, Debug, Serialize)]
+pub struct ChannelLeq {
+    /// Leq over the last seconds of each window
+    pub leq: Vec<WindowLeq>,
+    /// levels exceeded 10%, 50% and 90% of the seconds of the current day (UTC),
+    /// `null` in JSON before the first second of the day, or for silence
+    pub l10: Option<f32>,
+    pub l50: Option<f32>,
+    pub l90: Option<f32>,
+    /// `dBov` or `dB SPL`
+    pub unit: &'static str,
+}
+
+impl ChannelLeq {
+    /// Add the offset of a calibration to the levels in dBov, for levels in dB SPL.
+    pub fn calibrated(mut self, offset: f32) -> Self {
+        for window_leq in &mut self.leq {
+            window_leq.level = window_leq.level.map(|level| level + offset);
+        }
+        for level in [&mut self.l10, &mut self.l50, &mut self.l90] {
+            *level = level.map(|level| level + offset);
+        }
+        self.unit = "dB SPL";
+        self
+    }
+}
+
+#[derive(Clone)]
+struct ChannelState {
+    /// sum of the squares of the samples of the current second
+    square_sum: f64,
+    /// mean square of the samples of each of the last seconds, up to the longest window
+    seconds: VecDeque<f64>,
+    /// seconds of the current day by their level
+    histogram: Vec<u32>,
+}
+
+/// Leq and percentile levels of each channel, from the loudness levels of the input buffers,
+/// so they follow the `--weighting` of the levels.
+pub struct LeqMeter {
+    sample_rate: u32,
+    /// seconds, in ascending order
+    windows: Vec<u32>,
+    channels: Vec<ChannelState>,
+    /// samples of each channel in the current second
+    num_samples: u32,
+    /// seconds measured since the start
+    seconds: u64,
+    /// day of the seconds of the histograms, since the Unix epoch
+    day: u64,
+    /// windows ended with the last input buffer
+    ended_windows: Vec<u32>,
+}
+
+impl LeqMeter {
+    pub fn new(mut windows: Vec<u32>, sample_rate: u32, num_channels: usize) -> Self {
+        windows.sort_unstable();
+        windows.dedup();
+        let max_window = windows.last().copied().unwrap_or(1) as usize;
+        let num_bins =
+            ((HISTOGRAM_MAX_LEVEL - HISTOGRAM_MIN_LEVEL) / HISTOGRAM_BIN_WIDTH).ceil() as usize;
+        LeqMeter {
+            sample_rate,
+            windows,
+            channels: vec![
+                ChannelState {
+                    square_sum: 0.0,
+                    seconds: VecDeque::with_capacity(max_window),
+                    histogram: vec![0; num_bins],
+                };
+                num_channels
+            ],
+            num_samples: 0,
+            seconds: 0,
+            day: 0,
+            ended_windows: Vec::new(),
+        }
+    }
+
+    /// Update the statistics with the levels of an input buffer captured at the given time,
+    /// returns the windows that ended with it, each window ends every its duration.
+    pub fn process(&mut self, channels: &[ChannelData], timestamp: SystemTime) -> &[u32] {
+        self.ended_windows.clear();
+        let buffer_samples = channels.first().map_or(0, |channel| channel.samples.len()) as u32;
+        let mut offset = 0;
+        while offset < buffer_samples {
+            let samples = (self.sample_rate - self.num_samples).min(buffer_samples - offset);
+            for (channel, state) in channels.iter().zip(&mut self.channels) {
+                let level = channel.loudness_level as f64;
+                state.square_sum += level * level * samples as f64;
+            }
+            self.num_samples += samples;
+            offset += samples;
+            if self.num_samples == self.sample_rate {
+                self.end_second(timestamp);
+            }
+        }
+        &self.ended_windows
+    }
+
+    fn end_second(&mut self, timestamp: SystemTime) {
+        let day = timestamp
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs() / SECONDS_PER_DAY);
+        let new_day = day != self.day;
+        self.day = day;
+
+        let max_window = self.windows.last().copied().unwrap_or(1) as usize;
+        for state in &mut self.channels {
+            let mean_square = state.square_sum / self.sample_rate as f64;
+            state.square_sum = 0.0;
+            if state.seconds.len() == max_window {
+                state.seconds.pop_front();
+            }
+            state.seconds.push_back(mean_square);
+
+            if new_day {
+                state.histogram.iter_mut().for_each(|count| *count = 0);
+            }
+            let level = decibels_overload(mean_square.sqrt() as f32)
+                .clamp(HISTOGRAM_MIN_LEVEL, HISTOGRAM_MAX_LEVEL);
+            let bin = ((level - HISTOGRAM_MIN_LEVEL) / HISTOGRAM_BIN_WIDTH) as usize;
+            let last_bin = state.histogram.len() - 1;
+            state.histogram[bin.min(last_bin)] += 1;
+        }
+        self.num_samples = 0;
+        self.seconds += 1;
+
+        let seconds = self.seconds;
+        self.ended_windows.extend(
+            self.windows
+                .iter()
+                .filter(|&&window| seconds.is_multiple_of(window as u64)),
+        );
+    }
+
+    /// Statistics of the channel in the given position, in dBov.
+    pub fn channel_leq(&self, channel: usize) -> ChannelLeq {
+        let state = &self.channels[channel];
+        ChannelLeq {
+            leq: self
+                .windows
+                .iter()
+                .map(|&window| WindowLeq {
+                    window,
+                    level: window_level(state, window),
+                })
+                .collect(),
+            l10: percentile_level(&state.histogram, 0.9),
+            l50: percentile_level(&state.histogram, 0.5),
+            l90: percentile_level(&state.histogram, 0.1),
+            unit: "dBov",
+        }
+    }
+}
+
+/// Leq of the last seconds of the window, `None` until they are measured or for silence.
+fn window_level(state: &ChannelState, window: u32) -> Option<f32> {
+    let window = window as usize;
+    if state.seconds.len() < window {
+        return None;
+    }
+    let mean_square = state.seconds.iter().rev().take(window).sum::<f64>() / window as f64;
+    Some(decibels_overload(mean_square.sqrt() as f32)).filter(|level| level.is_finite())
+}
+
+/// Level below which are the given fraction of the seconds of the histogram,
+/// at the lower edge of its bin, `None` if there are none or for silence.
+fn percentile_level(histogram: &[u32], fraction: f64) -> Option<f32> {
+    let total: u64 = histogram.iter().map(|&count| count as u64).sum();
+    if total == 0 {
+        return None;
+    }
+    let target = ((total as f64 * fraction).ceil() as u64).max(1);
+    let mut count = 0;
+    let bin = histogram.iter().position(|&bin_count| {
+        count += bin_count as u64;
+        count >= target
+    })?;
+    // the first bin is silence
+    Some(HISTOGRAM_MIN_LEVEL + bin as f32 * HISTOGRAM_BIN_WIDTH).filter(|_| bin > 0)
+}
+
+/// CSV log of the Leq of each window when it ends, with the percentile levels at that time.
+pub struct LeqLog {
+    file: File,
+}
+
+impl LeqLog {
+    /// Append to the file, with a header line if it is new.
+    pub fn open(path: &str) -> io::Result<Self> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        if file.metadata()?.len() == 0 {
+            writeln!(file, "timestamp,channel,window,leq,l10,l50,l90,unit")?;
+        }
+        Ok(LeqLog { file })
+    }
+
+    /// One line for the window of a channel, given by its index in the input stream,
+    /// missing levels are empty.
+    pub fn write(
+        &mut self,
+        timestamp: f64,
+        channel: usize,
+        window: u32,
+        channel_leq: &ChannelLeq,
+    ) -> io::Result<()> {
+        let level =
+            |level: Option<f32>| level.map_or(String::new(), |level| format!("{:.1}", level));
+        let leq = channel_leq
+            .leq
+            .iter()
+            .find(|window_leq| window_leq.window == window)
+            .and_then(|window_leq| window_leq.level);
+        writeln!(
+            self.file,
+            "{:.3},{},{},{},{},{},{},{}",
+            timestamp,
+            channel,
+            window,
+            level(leq),
+            level(channel_leq.l10),
+            level(channel_leq.l50),
+            level(channel_leq.l90),
+            channel_leq.unit
+        )
+    }
+}
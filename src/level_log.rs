@@ -0,0 +1,187 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Log of the levels of each interval to a CSV or JSON lines file,
+//! rotated by size or by time.
+
+use crate::history::{HistoryPoint, PeriodLevels};
+use crate::levels::Levels;
+use crate::recording::{format_file_name, unique_path};
+use crate::unix_time;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+pub const DEFAULT_INTERVAL: f64 = 1.0;
+
+/// Format of the log, from the extension of the file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LevelLogFormat {
+    /// one line for each channel of each interval, with a header line
+    Csv,
+    /// the JSON of each interval, with all the channels, as in `/api/history`
+    JsonLines,
+}
+
+impl LevelLogFormat {
+    pub fn from_path(path: &Path) -> Result<Self, String> {
+        match path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase())
+            .as_deref()
+        {
+            Some("csv") => Ok(LevelLogFormat::Csv),
+            Some("jsonl" | "ndjson") => Ok(LevelLogFormat::JsonLines),
+            _ => Err(format!(
+                "unknown level log format of '{}', expected a .csv or .jsonl file",
+                path.display()
+            )),
+        }
+    }
+}
+
+/// When the file is renamed to start a new one, after the time of its first line.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Rotation {
+    /// once the file reaches this size in bytes
+    pub max_bytes: Option<u64>,
+    /// at the multiples of this time in seconds since the Unix epoch, e.g. each day
+    pub interval: Option<f64>,
+}
+
+/// Appends the levels of each interval to the file.
+pub struct LevelLog {
+    path: PathBuf,
+    format: LevelLogFormat,
+    rotation: Rotation,
+    file: File,
+    /// time of the first line of the file, `None` while empty
+    start: Option<f64>,
+    levels: PeriodLevels,
+}
+
+impl LevelLog {
+    /// Append to the file, with a header line if it is new,
+    /// writing the statistics of the levels of each `interval` seconds.
+    pub fn open(path: &Path, interval: f64, rotation: Rotation) -> Result<Self, String> {
+        let format = LevelLogFormat::from_path(path)?;
+        let (file, start) = LevelLog::open_file(path, format)
+            .map_err(|err| format!("failed to open '{}': {}", path.display(), err))?;
+        Ok(LevelLog {
+            path: path.to_owned(),
+            format,
+            rotation,
+            file,
+            start,
+            levels: PeriodLevels::new(interval),
+        })
+    }
+
+    /// The file and the time of its last change if not empty, as the start of the period
+    /// of the rotation it belongs to.
+    fn open_file(path: &Path, format: LevelLogFormat) -> io::Result<(File, Option<f64>)> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        let metadata = file.metadata()?;
+        if metadata.len() > 0 {
+            return Ok((file, metadata.modified().ok().map(unix_time)));
+        }
+        if format == LevelLogFormat::Csv {
+            writeln!(file, "timestamp,channel,rms,peak,short_term")?;
+        }
+        Ok((file, None))
+    }
+
+    /// Add the levels of an input buffer, the lines of an interval are written once it ends.
+    pub fn push(&mut self, levels: &Levels) -> io::Result<()> {
+        match self.levels.push(levels) {
+            Some(point) => self.write(&point),
+            None => Ok(()),
+        }
+    }
+
+    fn write(&mut self, point: &HistoryPoint) -> io::Result<()> {
+        if self.needs_rotation(point.timestamp)? {
+            self.rotate()?;
+        }
+        self.start.get_or_insert(point.timestamp);
+        match self.format {
+            LevelLogFormat::Csv => {
+                let level = |level: f32| {
+                    if level.is_finite() {
+                        format!("{:.1}", level)
+                    } else {
+                        String::new()
+                    }
+                };
+                let mut lines = String::new();
+                for channel in &point.channels {
+                    lines.push_str(&format!(
+                        "{:.3},{},{},{},{}\n",
+                        point.timestamp,
+                        channel.channel,
+                        level(channel.rms),
+                        level(channel.peak),
+                        level(point.short_term)
+                    ));
+                }
+                self.file.write_all(lines.as_bytes())
+            }
+            LevelLogFormat::JsonLines => {
+                let line = serde_json::to_string(point).expect("failed to serialize levels");
+                writeln!(self.file, "{}", line)
+            }
+        }
+    }
+
+    fn needs_rotation(&self, timestamp: f64) -> io::Result<bool> {
+        let start = match self.start {
+            Some(start) => start,
+            None => return Ok(false),
+        };
+        if let Some(interval) = self.rotation.interval {
+            if (timestamp / interval).floor() != (start / interval).floor() {
+                return Ok(true);
+            }
+        }
+        match self.rotation.max_bytes {
+            Some(max_bytes) => Ok(self.file.metadata()?.len() >= max_bytes),
+            None => Ok(false),
+        }
+    }
+
+    /// Rename the file after the time of its first line, e.g. `levels.20201231-235959.csv`,
+    /// and start a new one.
+    fn rotate(&mut self) -> io::Result<()> {
+        let start = self.start.unwrap_or_default();
+        let stem = self.path.file_stem().map_or(String::new(), |stem| {
+            stem.to_string_lossy().replace('%', "%%")
+        });
+        let extension = self.path.extension().map_or(String::new(), |extension| {
+            extension.to_string_lossy().replace('%', "%%")
+        });
+        let file_name = format_file_name(
+            &format!("{}.%Y%m%d-%H%M%S.{}", stem, extension),
+            UNIX_EPOCH + Duration::from_secs_f64(start.max(0.0)),
+        );
+        let rotated = unique_path(&self.path.with_file_name(file_name).to_string_lossy());
+        fs::rename(&self.path, rotated)?;
+        let (file, start) = LevelLog::open_file(&self.path, self.format)?;
+        self.file = file;
+        self.start = start;
+        Ok(())
+    }
+}
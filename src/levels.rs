@@ -0,0 +1,176 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Structured level data of an input buffer, serializable as JSON.
+
+use crate::analyzer::ToneAnalysis;
+use crate::channel_faults::ChannelFault;
+use crate::clipping::ChannelClipping;
+use crate::correlation::PairCorrelation;
+use crate::leq::ChannelLeq;
+use crate::loudness::Loudness;
+use crate::mid_side::PairMidSide;
+use crate::octave_bands::OctaveBands;
+use crate::pitch::ChannelPitch;
+use crate::spectrum::Spectrum;
+use crate::vad::ChannelSpeech;
+use crate::{decibels_overload, unix_time, InputBufferSourceData};
+use arc_swap::ArcSwapOption;
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ChannelLevels {
+    /// index of the channel in the input stream
+    pub channel: usize,
+    /// loudness level, root mean square of the samples
+    pub rms: f32,
+    /// loudness level in decibels relative to overload, `null` in JSON for silence (-inf)
+    pub dbov: f32,
+    /// loudness level in dB SPL, only once calibrated with `--calibrate`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spl: Option<f32>,
+    /// highest peak between samples, 4x oversampled, in decibels relative to full scale (dBTP),
+    /// `null` in JSON for silence (-inf)
+    pub true_peak: f32,
+    pub clipping: ChannelClipping,
+    /// time since the channel is silent, in seconds since the Unix epoch, `null` if not silent
+    pub silent_since: Option<f64>,
+    /// software gain applied to the channel, in decibels
+    pub gain: f32,
+    /// the channel is muted by the control api or the terminal interface
+    pub muted: bool,
+    /// mean of the samples averaged over time, linear with its sign
+    pub dc_offset: f32,
+    /// the DC offset is above the `--dc-threshold`
+    pub dc_offset_warning: bool,
+    /// only when the Leq statistics are enabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub leq: Option<ChannelLeq>,
+    /// only when the voice activity detection is enabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub speech: Option<ChannelSpeech>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Levels {
+    /// capture time, in seconds since the Unix epoch
+    pub timestamp: f64,
+    /// duration of the input buffer, in seconds
+    pub buffer_duration: f64,
+    /// sample format of the input device: `u16`, `i16` or `f32`
+    pub sample_format: String,
+    /// channels of the input stream, not all of them are metered with `--channels-map`
+    pub channel_count: usize,
+    /// frequency weighting of the `rms` and `dbov` levels: `A`, `C` or `Z` (none)
+    pub weighting: &'static str,
+    /// levels of the metered channels
+    pub channels: Vec<ChannelLevels>,
+    /// wiring faults of the metered channels: dual mono, inverted or dead channels
+    pub faults: Vec<ChannelFault>,
+    pub loudness: Loudness,
+    /// phase correlation of the stereo pairs of metered channels, if any
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub correlation: Vec<PairCorrelation>,
+    /// mid/side levels of the stereo pairs of metered channels, only with `--ms`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub mid_side: Vec<PairMidSide>,
+    /// only when the spectrum analysis is enabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spectrum: Option<Spectrum>,
+    /// only when the third octave band analysis is enabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub octave_bands: Option<OctaveBands>,
+    /// test tone measurements of the latest analysis window, only with `--analyze`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub analysis: Vec<ToneAnalysis>,
+    /// pitch of the latest window, only with `--pitch`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub pitch: Vec<ChannelPitch>,
+}
+
+impl Levels {
+    pub fn new(
+        source_data: &InputBufferSourceData,
+        sample_rate: u32,
+        timestamp: SystemTime,
+        loudness: Loudness,
+        true_peaks: &[f32],
+        clippings: &[ChannelClipping],
+        silent_since: &[Option<f64>],
+    ) -> Self {
+        Levels {
+            timestamp: unix_time(timestamp),
+            buffer_duration: (source_data.num_samples / source_data.num_channels) as f64
+                / sample_rate as f64,
+            sample_format: format!("{:?}", source_data.sample_format).to_lowercase(),
+            channel_count: source_data.num_channels,
+            weighting: "Z",
+            channels: source_data
+                .channels
+                .iter()
+                .zip(true_peaks)
+                .zip(clippings)
+                .zip(silent_since)
+                .map(
+                    |(((channel, &true_peak), &clipping), &silent_since)| ChannelLevels {
+                        channel: channel.index,
+                        rms: channel.loudness_level,
+                        dbov: decibels_overload(channel.loudness_level),
+                        spl: None,
+                        true_peak: decibels_overload(true_peak),
+                        clipping,
+                        silent_since,
+                        gain: 0.0,
+                        muted: false,
+                        dc_offset: 0.0,
+                        dc_offset_warning: false,
+                        leq: None,
+                        speech: None,
+                    },
+                )
+                .collect(),
+            faults: Vec::new(),
+            loudness,
+            correlation: Vec::new(),
+            mid_side: Vec::new(),
+            spectrum: None,
+            octave_bands: None,
+            analysis: Vec::new(),
+            pitch: Vec::new(),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("failed to serialize levels")
+    }
+}
+
+/// Levels of the last input buffer, shared without locks:
+/// the audio thread swaps in the new levels, never waiting for the readers.
+#[derive(Clone, Default)]
+pub struct LevelSnapshot(Arc<ArcSwapOption<Levels>>);
+
+impl LevelSnapshot {
+    pub fn store(&self, levels: Arc<Levels>) {
+        self.0.store(Some(levels));
+    }
+
+    /// `None` before the first input buffer
+    pub fn load(&self) -> Option<Arc<Levels>> {
+        self.0.load_full()
+    }
+}
@@ -0,0 +1,905 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Audio input level metering on top of CPAL.
+//!
+//! [`InputMonitor`] captures an input device and hands, for each input buffer,
+//! the deinterleaved samples and the loudness level of each channel to a callback,
+//! or the levels and the audio events of the [`metering::Metering`] of each input buffer.
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+#[cfg(feature = "aac")]
+pub mod aac;
+pub mod agc;
+pub mod analyzer;
+pub mod archive;
+pub mod auth;
+pub mod biquad;
+pub mod broadcast;
+pub mod calibration;
+pub mod channel_faults;
+pub mod clipping;
+pub mod control;
+pub mod correlation;
+pub mod cors;
+pub mod dashboard;
+pub mod dc_offset;
+#[cfg(feature = "rnnoise")]
+pub mod denoise;
+pub mod dtmf;
+pub mod encoder;
+pub mod error;
+pub mod events;
+pub mod features;
+pub mod file_input;
+pub mod filter;
+pub mod flac;
+pub mod fmp4;
+pub mod gain;
+pub mod gate;
+pub mod generator;
+pub mod grpc;
+pub mod health;
+pub mod history;
+#[cfg(any(feature = "aac", feature = "opus"))]
+pub mod hls;
+pub mod http;
+pub mod http_client;
+#[cfg(feature = "opus")]
+pub mod icecast;
+#[cfg(feature = "jack")]
+pub mod jack_input;
+pub mod latency;
+pub mod leq;
+pub mod level_log;
+pub mod levels;
+pub mod loopback;
+pub mod loudness;
+#[cfg(feature = "lv2")]
+pub mod lv2;
+pub mod meter;
+pub mod meter_scale;
+pub mod metering;
+pub mod metric_push;
+pub mod metrics;
+pub mod mid_side;
+pub mod midi;
+pub mod mix;
+pub mod monitor_output;
+#[cfg(feature = "mp3")]
+pub mod mp3;
+pub mod mpegts;
+pub mod mqtt;
+pub mod octave_bands;
+#[cfg(feature = "opus")]
+pub mod ogg_opus;
+pub mod osc;
+pub mod pcm;
+pub mod pilot;
+#[cfg(feature = "pipewire")]
+pub mod pipewire_input;
+pub mod pitch;
+pub mod png;
+pub mod processor;
+pub mod receiver;
+pub mod recording;
+pub mod recording_session;
+pub mod report;
+pub mod resample;
+pub mod retention;
+pub mod ring;
+pub mod rms;
+#[cfg(any(feature = "aac", feature = "mp3"))]
+pub mod rtmp;
+pub mod rtp;
+pub mod s3;
+pub mod schedule;
+#[cfg(feature = "lua")]
+pub mod script;
+pub mod silence;
+pub mod snapshot;
+pub mod spectrogram;
+pub mod spectrum;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+#[cfg(feature = "srt")]
+pub mod srt;
+pub mod sse;
+pub mod supervisor;
+pub mod tls;
+pub mod trigger;
+pub mod true_peak;
+pub mod tui;
+pub mod vad;
+#[cfg(feature = "vorbis")]
+pub mod vorbis;
+pub mod wav;
+pub mod waveform;
+pub mod webhook;
+pub mod websocket;
+pub mod weighting;
+
+/// Root mean square of the values, i.e. the loudness level of a signal.
+pub fn root_mean_square<'a>(values: impl IntoIterator<Item = &'a f32>) -> f32 {
+    let mut n: usize = 0;
+    let mut square_sum: f32 = 0.0;
+    for x in values {
+        n += 1;
+        square_sum += x.powi(2);
+    }
+
+    (square_sum / n as f32).sqrt()
+}
+
+/// Given a loudness level in nominal interval of [0,+1],
+/// compute dBov unit of decibels relative to overload.
+/// A loundness level of 1 is designated as 0 dBov and
+/// a loundness level of 0 is designated as -inf.
+/// Loudness level is usually computed as the root mean square of
+/// a audio signal in the nominal interval of [-1,+1]
+pub fn decibels_overload(loudness_level: f32) -> f32 {
+    20.0 * loudness_level.log10()
+}
+
+/// Time in seconds since the Unix epoch, as in the timestamps of the JSON API.
+pub fn unix_time(time: std::time::SystemTime) -> f64 {
+    time.duration_since(std::time::UNIX_EPOCH)
+        .map_or(0.0, |duration| duration.as_secs_f64())
+}
+
+/// Date and time of the local time zone.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LocalDateTime {
+    pub year: u32,
+    pub month: u32,
+    pub day: u32,
+    pub hours: u32,
+    pub minutes: u32,
+    pub seconds: u32,
+    /// 0 Monday ... 6 Sunday
+    pub weekday: u32,
+}
+
+/// Date and time of the local time zone, UTC where it is unknown.
+pub fn local_date_time(time: std::time::SystemTime) -> LocalDateTime {
+    let seconds = time
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+    #[cfg(unix)]
+    {
+        let time = seconds as libc::time_t;
+        let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+        if !unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+            return LocalDateTime {
+                year: (tm.tm_year + 1900) as u32,
+                month: (tm.tm_mon + 1) as u32,
+                day: tm.tm_mday as u32,
+                hours: tm.tm_hour as u32,
+                minutes: tm.tm_min as u32,
+                seconds: tm.tm_sec as u32,
+                weekday: (tm.tm_wday as u32 + 6) % 7,
+            };
+        }
+    }
+    let (year, month, day, hours, minutes, seconds_of_minute) = recording::utc_date_time(seconds);
+    LocalDateTime {
+        year: year as u32,
+        month: month as u32,
+        day: day as u32,
+        hours: hours as u32,
+        minutes: minutes as u32,
+        seconds: seconds_of_minute as u32,
+        // the Unix epoch was a Thursday
+        weekday: ((seconds / 86400 + 3) % 7) as u32,
+    }
+}
+
+/// Longest duration of the args, 100 years, so that any of them can be added to an instant
+/// or converted to a number of frames.
+pub const MAX_DURATION: Duration = Duration::from_secs(100 * 365 * 24 * 60 * 60);
+
+/// The duration of `seconds`, `None` unless between 0 and [`MAX_DURATION`].
+pub fn duration_from_secs(seconds: f64) -> Option<Duration> {
+    Duration::try_from_secs_f64(seconds)
+        .ok()
+        .filter(|duration| *duration <= MAX_DURATION)
+}
+
+/// The interval between messages sent at most `rate` times per second, `None` unless
+/// the rate is positive and the interval at most [`MAX_DURATION`].
+pub fn rate_interval(rate: f64) -> Option<Duration> {
+    if rate > 0.0 && rate.is_finite() {
+        duration_from_secs(1.0 / rate)
+    } else {
+        None
+    }
+}
+
+/// Parse a duration in seconds, with an optional unit suffix: `ms`, `s`, `m`, `h` or `d`,
+/// e.g. `90s`, `30m` or `1h`, at most [`MAX_DURATION`].
+pub fn parse_duration(duration: &str) -> Result<Duration, String> {
+    let (number, scale) = match duration.char_indices().last() {
+        _ if duration.ends_with("ms") => (&duration[..duration.len() - 2], 0.001),
+        Some((index, 's')) => (&duration[..index], 1.0),
+        Some((index, 'm')) => (&duration[..index], 60.0),
+        Some((index, 'h')) => (&duration[..index], 60.0 * 60.0),
+        Some((index, 'd')) => (&duration[..index], 24.0 * 60.0 * 60.0),
+        _ => (duration, 1.0),
+    };
+    match number.trim().parse::<f64>() {
+        Ok(number) if number >= 0.0 && number.is_finite() => duration_from_secs(number * scale)
+            .ok_or_else(|| {
+                format!(
+                    "invalid duration '{}', it must be at most 100 years",
+                    duration
+                )
+            }),
+        _ => Err(format!("invalid duration '{}'", duration)),
+    }
+}
+
+/// Parse a frequency in Hz, as `1000`, `1000Hz` or `1kHz`.
+pub fn parse_frequency(frequency: &str) -> Result<f64, String> {
+    let lowercase = frequency.trim().to_ascii_lowercase();
+    let (number, scale) = if let Some(number) = lowercase.strip_suffix("khz") {
+        (number, 1000.0)
+    } else {
+        (lowercase.strip_suffix("hz").unwrap_or(&lowercase), 1.0)
+    };
+    match number.trim().parse::<f64>() {
+        Ok(number) if number > 0.0 && number.is_finite() => Ok(number * scale),
+        _ => Err(format!("invalid frequency '{}'", frequency)),
+    }
+}
+
+/// Parse a level in dBFS, as `-20` or `-20dBFS`, at most 0 dBFS.
+pub fn parse_level(level: &str) -> Result<f32, String> {
+    let lowercase = level.trim().to_ascii_lowercase();
+    let number = lowercase.strip_suffix("dbfs").unwrap_or(&lowercase);
+    match number.trim().parse::<f32>() {
+        Ok(number) if number <= 0.0 => Ok(number),
+        _ => Err(format!(
+            "invalid level '{}', it must be at most 0 dBFS",
+            level
+        )),
+    }
+}
+
+/// Signal-to-quantization-noise ratio in decibels of the given bit deep.
+pub fn quantization_noise_ratio(quantization_bits: usize) -> f32 {
+    20.0 * 2.0_f32.log10() * quantization_bits as f32
+}
+
+/// select the CPAL host by name (case insensitive), or the default host
+pub fn select_host(host_name: Option<&str>) -> Result<cpal::Host, error::Error> {
+    let host_name = match host_name {
+        Some(host_name) => host_name,
+        None => return Ok(cpal::default_host()),
+    };
+    #[cfg(not(feature = "asio"))]
+    if host_name.eq_ignore_ascii_case("asio") {
+        return Err(error::Error::HostFeature {
+            host: "ASIO",
+            feature: "asio",
+        });
+    }
+
+    let available_hosts = cpal::available_hosts();
+    let host_id = available_hosts
+        .iter()
+        .find(|host_id| host_id.name().eq_ignore_ascii_case(host_name))
+        .ok_or_else(|| error::Error::UnknownHost {
+            name: host_name.to_owned(),
+            available: available_hosts
+                .iter()
+                .map(|host_id| host_id.name().to_owned())
+                .collect(),
+        })?;
+
+    cpal::host_from_id(*host_id).map_err(|source| error::Error::Host {
+        host: host_id.name(),
+        source,
+    })
+}
+
+/// select an input device of the host by name or by index,
+/// as shown by `--list-input-devices`, or the default input device
+pub fn select_input_device(
+    host: &cpal::Host,
+    device: Option<&str>,
+) -> Result<cpal::Device, error::Error> {
+    let direction = "input";
+    let device = match device {
+        Some(device) => device,
+        None => {
+            return host
+                .default_input_device()
+                .ok_or(error::Error::NoDefaultDevice {
+                    direction,
+                    host: host.id().name(),
+                })
+        }
+    };
+
+    let input_devices: Vec<cpal::Device> = host
+        .input_devices()
+        .map_err(|source| error::Error::Devices {
+            direction,
+            host: host.id().name(),
+            source,
+        })?
+        .collect();
+
+    if let Ok(device_index) = device.parse::<usize>() {
+        let num_devices = input_devices.len();
+        return input_devices
+            .into_iter()
+            .nth(device_index)
+            .ok_or(error::Error::DeviceIndex {
+                direction,
+                index: device_index,
+                host: host.id().name(),
+                count: num_devices,
+            });
+    }
+
+    let device_names = device_names(&input_devices);
+    match device_names.iter().position(|name| name == device) {
+        Some(device_index) => Ok(input_devices.into_iter().nth(device_index).unwrap()),
+        None => Err(error::Error::UnknownDevice {
+            direction,
+            name: device.to_owned(),
+            host: host.id().name(),
+            available: device_names,
+        }),
+    }
+}
+
+/// select an output device of the host by name or by index in its output devices,
+/// or the default output device
+pub fn select_output_device(
+    host: &cpal::Host,
+    device: Option<&str>,
+) -> Result<cpal::Device, error::Error> {
+    let direction = "output";
+    let device = match device {
+        Some(device) => device,
+        None => {
+            return host
+                .default_output_device()
+                .ok_or(error::Error::NoDefaultDevice {
+                    direction,
+                    host: host.id().name(),
+                })
+        }
+    };
+
+    let output_devices: Vec<cpal::Device> = host
+        .output_devices()
+        .map_err(|source| error::Error::Devices {
+            direction,
+            host: host.id().name(),
+            source,
+        })?
+        .collect();
+    let device_names = device_names(&output_devices);
+
+    let device_index = match device.parse::<usize>() {
+        Ok(device_index) if device_index < output_devices.len() => Some(device_index),
+        Ok(_) => None,
+        Err(_) => device_names.iter().position(|name| name == device),
+    };
+    match device_index {
+        Some(device_index) => Ok(output_devices.into_iter().nth(device_index).unwrap()),
+        None => Err(error::Error::UnknownDevice {
+            direction,
+            name: device.to_owned(),
+            host: host.id().name(),
+            available: device_names,
+        }),
+    }
+}
+
+/// The names of the devices, in their order.
+fn device_names(devices: &[cpal::Device]) -> Vec<String> {
+    devices
+        .iter()
+        .map(|dev| {
+            dev.name()
+                .unwrap_or_else(|_| String::from("<failed to get device name>"))
+        })
+        .collect()
+}
+
+/// Sample formats of the input stream supported by [`InputMonitor`].
+pub const SUPPORTED_SAMPLE_FORMATS: &[cpal::SampleFormat] = &[
+    cpal::SampleFormat::U16,
+    cpal::SampleFormat::I16,
+    cpal::SampleFormat::I32,
+    cpal::SampleFormat::F32,
+];
+
+/// Nearest supported input stream configuration of the device to the requested one:
+/// same sample format first, then the closest channel count,
+/// then the closest sample rate.
+pub fn nearest_input_config(
+    dev: &cpal::Device,
+    requested: &cpal::SupportedStreamConfig,
+) -> Result<cpal::SupportedStreamConfig, error::Error> {
+    let supported_input_configs = dev.supported_input_configs()?;
+
+    supported_input_configs
+        .filter(|c| SUPPORTED_SAMPLE_FORMATS.contains(&c.sample_format()))
+        .map(|c| {
+            let sample_rate = requested
+                .sample_rate()
+                .0
+                .max(c.min_sample_rate().0)
+                .min(c.max_sample_rate().0);
+            let distance = (
+                c.sample_format() != requested.sample_format(),
+                (c.channels() as i32 - requested.channels() as i32).abs(),
+                (sample_rate as i64 - requested.sample_rate().0 as i64).abs(),
+            );
+            (distance, c.with_sample_rate(cpal::SampleRate(sample_rate)))
+        })
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, config)| config)
+        .ok_or(error::Error::NoSupportedConfig)
+}
+
+/// Samples and loudness level of one channel of an input buffer.
+pub struct ChannelData {
+    /// index of the channel in the input stream
+    pub index: usize,
+    pub loudness_level: f32,
+    pub samples: Vec<f32>,
+}
+
+/// Processed input buffer, with its samples deinterleaved by channel.
+pub struct InputBufferSourceData {
+    /// number of samples of all channels
+    pub num_samples: usize,
+    pub sample_format: cpal::SampleFormat,
+    /// channels of the input stream
+    pub num_channels: usize,
+    /// processed channels, not necessarily all of them
+    pub channels: Vec<ChannelData>,
+}
+
+impl InputBufferSourceData {
+    /// Samples of the processed channels, interleaved again.
+    pub fn interleaved_samples(&self) -> Vec<f32> {
+        let num_frames = self.channels.first().map_or(0, |c| c.samples.len());
+        let mut samples = Vec::with_capacity(num_frames * self.channels.len());
+        for frame_index in 0..num_frames {
+            for channel in &self.channels {
+                samples.push(channel.samples[frame_index]);
+            }
+        }
+        samples
+    }
+}
+
+/// Deinterleave the input buffer by channel and compute the loudness level of each channel.
+pub fn process_input_buffer<T>(input_buffer: &[T], num_channels: usize) -> Vec<ChannelData>
+where
+    T: cpal::Sample,
+    f32: cpal::FromSample<T>,
+{
+    let mut channel_data = Vec::with_capacity(num_channels);
+    process_input_buffer_into(input_buffer, num_channels, &mut channel_data);
+    channel_data
+}
+
+/// Same as [`process_input_buffer`], reusing the channel data of the previous buffer,
+/// so no memory is allocated unless the buffer is larger than the previous ones.
+pub fn process_input_buffer_into<T>(
+    input_buffer: &[T],
+    num_channels: usize,
+    channel_data: &mut Vec<ChannelData>,
+) where
+    T: cpal::Sample,
+    f32: cpal::FromSample<T>,
+{
+    process_input_channels_into(input_buffer, num_channels, 0..num_channels, channel_data)
+}
+
+/// Same as [`process_input_buffer_into`], but only for the channels with the given indices,
+/// in that order.
+pub fn process_input_channels_into<T>(
+    input_buffer: &[T],
+    num_channels: usize,
+    channel_indices: impl ExactSizeIterator<Item = usize>,
+    channel_data: &mut Vec<ChannelData>,
+) where
+    T: cpal::Sample,
+    f32: cpal::FromSample<T>,
+{
+    assert!(num_channels > 0);
+    assert!(input_buffer.len().is_multiple_of(num_channels));
+    channel_data.truncate(channel_indices.len());
+    while channel_data.len() < channel_indices.len() {
+        channel_data.push(ChannelData {
+            index: 0,
+            loudness_level: 0.0,
+            samples: Vec::with_capacity(input_buffer.len() / num_channels),
+        });
+    }
+
+    for (channel_index, channel) in channel_indices.zip(channel_data.iter_mut()) {
+        assert!(channel_index < num_channels);
+        channel.index = channel_index;
+        channel.samples.clear();
+        channel.samples.extend(
+            input_buffer
+                .iter()
+                // each channel data is interleaved
+                .skip(channel_index)
+                .step_by(num_channels)
+                .map(|s| s.to_sample::<f32>()),
+        );
+        channel.loudness_level = root_mean_square(&channel.samples);
+    }
+}
+
+/// Captures an input device, processing each input buffer.
+pub struct InputMonitor {
+    dev: cpal::Device,
+    config: cpal::SupportedStreamConfig,
+}
+
+impl InputMonitor {
+    pub fn new(dev: cpal::Device, config: cpal::SupportedStreamConfig) -> Self {
+        InputMonitor { dev, config }
+    }
+
+    pub fn config(&self) -> &cpal::SupportedStreamConfig {
+        &self.config
+    }
+
+    pub fn device(&self) -> &cpal::Device {
+        &self.dev
+    }
+
+    /// Start capturing, calling `data_callback` with each processed input buffer
+    /// and `error_callback` with the errors of the input stream,
+    /// both from the audio thread of the host.
+    ///
+    /// The processed input buffer is reused for the next one,
+    /// so the audio thread does not allocate memory for it.
+    ///
+    /// A panic of `data_callback`, or of a processor, does not unwind into the host:
+    /// it is reported to `error_callback` as [`error::Error::Panic`] of the audio thread,
+    /// and the next input buffers are dropped.
+    ///
+    /// Capturing stops when the returned stream is dropped.
+    pub fn start<D, E>(
+        &self,
+        data_callback: D,
+        error_callback: E,
+    ) -> Result<cpal::Stream, error::Error>
+    where
+        D: FnMut(&InputBufferSourceData) + Send + 'static,
+        E: FnMut(error::Error) + Send + 'static,
+    {
+        self.start_processing(
+            processor::ProcessorChain::new(),
+            data_callback,
+            error_callback,
+        )
+    }
+
+    /// Same as [`InputMonitor::start`], processing each input buffer by the stages of
+    /// `processors` before calling `data_callback` with it.
+    pub fn start_processing<D, E>(
+        &self,
+        processors: processor::ProcessorChain,
+        data_callback: D,
+        error_callback: E,
+    ) -> Result<cpal::Stream, error::Error>
+    where
+        D: FnMut(&InputBufferSourceData) + Send + 'static,
+        E: FnMut(error::Error) + Send + 'static,
+    {
+        let stream = match self.config.sample_format() {
+            cpal::SampleFormat::U16 => {
+                self.build_input_stream::<u16, _, _>(processors, data_callback, error_callback)
+            }
+            cpal::SampleFormat::I16 => {
+                self.build_input_stream::<i16, _, _>(processors, data_callback, error_callback)
+            }
+            cpal::SampleFormat::I32 => {
+                self.build_input_stream::<i32, _, _>(processors, data_callback, error_callback)
+            }
+            cpal::SampleFormat::F32 => {
+                self.build_input_stream::<f32, _, _>(processors, data_callback, error_callback)
+            }
+            sample_format => return Err(error::Error::SampleFormat(sample_format)),
+        };
+        play(stream)
+    }
+
+    /// Start capturing, only mixing and copying the samples of each input buffer into the ring,
+    /// to be processed by its readers out of the audio thread,
+    /// and calling `error_callback` with the errors of the input stream.
+    ///
+    /// Capturing stops, and the readers of the ring end, when the returned stream is dropped.
+    pub fn capture<E>(
+        &self,
+        ring_writer: ring::RingWriter,
+        error_callback: E,
+    ) -> Result<cpal::Stream, error::Error>
+    where
+        E: FnMut(error::Error) + Send + 'static,
+    {
+        let stream = match self.config.sample_format() {
+            cpal::SampleFormat::U16 => {
+                self.build_capture_stream::<u16, _>(ring_writer, error_callback)
+            }
+            cpal::SampleFormat::I16 => {
+                self.build_capture_stream::<i16, _>(ring_writer, error_callback)
+            }
+            cpal::SampleFormat::I32 => {
+                self.build_capture_stream::<i32, _>(ring_writer, error_callback)
+            }
+            cpal::SampleFormat::F32 => {
+                self.build_capture_stream::<f32, _>(ring_writer, error_callback)
+            }
+            sample_format => return Err(error::Error::SampleFormat(sample_format)),
+        };
+        play(stream)
+    }
+
+    /// Start capturing, mixing the channels of each input buffer by `mix` into a ring
+    /// metered by `metering` out of the audio thread, calling `frame_callback` with the
+    /// metering of each input buffer from the metering thread, and `error_callback`
+    /// with the errors of the input stream and the panic of the metering thread.
+    ///
+    /// Capturing stops, and the metering thread ends, when the returned stream is dropped.
+    pub fn start_metering<F, E>(
+        &self,
+        mix: mix::ChannelMix,
+        metering: metering::Metering,
+        mut frame_callback: F,
+        error_callback: E,
+    ) -> Result<cpal::Stream, error::Error>
+    where
+        F: FnMut(metering::MeteringFrame) + Send + 'static,
+        E: FnMut(error::Error) + Send + 'static,
+    {
+        if mix.input_channels() != self.config.channels() as usize
+            || mix.output_channels() != metering.config().num_channels
+        {
+            return Err(error::Error::Setup(format!(
+                "the mix of {} to {} channel(s) does not match the {} input channel(s) \
+                 and the {} channel(s) of the metering",
+                mix.input_channels(),
+                mix.output_channels(),
+                self.config.channels(),
+                metering.config().num_channels
+            )));
+        }
+        let sample_rate = self.config.sample_rate().0;
+        let ring = ring::SampleRing::new(
+            mix.output_channels(),
+            sample_rate,
+            (sample_rate / ring::DEFAULT_CHUNKS_PER_SECOND) as usize,
+            ring::DEFAULT_CHUNKS,
+        );
+        let mut metering = metering.with_gains(mix.gains().clone());
+        let mut ring_reader = ring.reader();
+        let supervisor = supervisor::Supervisor::start(error_callback);
+        supervisor.spawn("metering", move || {
+            let mut samples = Vec::new();
+            while let Some(chunk) = ring_reader.read(&mut samples) {
+                frame_callback(metering.process(&samples, chunk.timestamp));
+            }
+        });
+        self.capture(ring.writer(mix), move |err| supervisor.report(err))
+    }
+
+    fn build_input_stream<T, D, E>(
+        &self,
+        mut processors: processor::ProcessorChain,
+        mut data_callback: D,
+        error_callback: E,
+    ) -> Result<cpal::Stream, cpal::BuildStreamError>
+    where
+        T: cpal::SizedSample + 'static,
+        f32: cpal::FromSample<T>,
+        D: FnMut(&InputBufferSourceData) + Send + 'static,
+        E: FnMut(error::Error) + Send + 'static,
+    {
+        let num_channels = self.config.channels() as usize;
+        let mut source_data = InputBufferSourceData {
+            num_samples: 0,
+            sample_format: T::FORMAT,
+            num_channels,
+            channels: Vec::with_capacity(num_channels),
+        };
+        let (data_callback, error_callback) = guard_callbacks(
+            move |input_buffer: &[T], _: &cpal::InputCallbackInfo| {
+                source_data.num_samples = input_buffer.len();
+                process_input_buffer_into(input_buffer, num_channels, &mut source_data.channels);
+                processors.process(&mut source_data);
+                data_callback(&source_data)
+            },
+            error_callback,
+        );
+        self.dev
+            .build_input_stream(&self.config.config(), data_callback, error_callback, None)
+    }
+
+    fn build_capture_stream<T, E>(
+        &self,
+        mut ring_writer: ring::RingWriter,
+        error_callback: E,
+    ) -> Result<cpal::Stream, cpal::BuildStreamError>
+    where
+        T: cpal::SizedSample + 'static,
+        f32: cpal::FromSample<T>,
+        E: FnMut(error::Error) + Send + 'static,
+    {
+        let num_channels = self.config.channels() as usize;
+        let mut clock = CaptureClock::new(self.config.sample_rate().0);
+        let (data_callback, error_callback) = guard_callbacks(
+            move |input_buffer: &[T], info: &cpal::InputCallbackInfo| {
+                let timestamp = clock.time(info, input_buffer.len() / num_channels);
+                ring_writer.push(input_buffer, timestamp)
+            },
+            error_callback,
+        );
+        self.dev
+            .build_input_stream(&self.config.config(), data_callback, error_callback, None)
+    }
+}
+
+/// Wall-clock time of the capture of the input buffers, sample-accurate unlike the time
+/// of the callbacks, which depends on the scheduling of the audio thread:
+/// the time of the capture of the first buffer, and the later ones at their capture instants
+/// since it, or at the frames since it if the capture instants of the host do not advance.
+pub(crate) struct CaptureClock {
+    sample_rate: u32,
+    /// capture time of the first buffer
+    start_time: Option<std::time::SystemTime>,
+    /// capture instant of the first buffer, for the cpal hosts
+    start_instant: Option<cpal::StreamInstant>,
+    /// frames captured since the first buffer
+    frames: u64,
+}
+
+impl CaptureClock {
+    pub(crate) fn new(sample_rate: u32) -> Self {
+        CaptureClock {
+            sample_rate,
+            start_time: None,
+            start_instant: None,
+            frames: 0,
+        }
+    }
+
+    /// The capture time of a buffer of the frames.
+    fn time(&mut self, info: &cpal::InputCallbackInfo, frames: usize) -> std::time::SystemTime {
+        let timestamp = info.timestamp();
+        let time = match (self.start_time, self.start_instant) {
+            (Some(start_time), Some(start_instant)) => {
+                start_time
+                    + timestamp
+                        .capture
+                        .duration_since(&start_instant)
+                        .filter(|duration| !duration.is_zero())
+                        .unwrap_or_else(|| self.elapsed())
+            }
+            _ => {
+                // the first buffer was captured before its callback
+                let latency = timestamp
+                    .callback
+                    .duration_since(&timestamp.capture)
+                    .unwrap_or_default();
+                let start_time = std::time::SystemTime::now() - latency;
+                self.start_time = Some(start_time);
+                self.start_instant = Some(timestamp.capture);
+                start_time
+            }
+        };
+        self.frames += frames as u64;
+        time
+    }
+
+    /// The capture time of a buffer of the frames, at the frames since the first buffer,
+    /// for the inputs without capture instants.
+    #[cfg(feature = "pipewire")]
+    pub(crate) fn frame_time(&mut self, frames: usize) -> std::time::SystemTime {
+        let time = match self.start_time {
+            Some(start_time) => start_time + self.elapsed(),
+            None => {
+                // the frames of the first buffer were captured before it was handed over
+                let duration = Duration::from_secs_f64(frames as f64 / self.sample_rate as f64);
+                let start_time = std::time::SystemTime::now() - duration;
+                self.start_time = Some(start_time);
+                start_time
+            }
+        };
+        self.frames += frames as u64;
+        time
+    }
+
+    /// The duration of the frames captured since the first buffer.
+    fn elapsed(&self) -> Duration {
+        // in seconds and the remaining frames, not to overflow
+        let sample_rate = u64::from(self.sample_rate);
+        let nanos = self.frames % sample_rate * 1_000_000_000 / sample_rate;
+        Duration::new(self.frames / sample_rate, nanos as u32)
+    }
+}
+
+/// Guard the callbacks of an input stream: a panic of `data_callback` is caught
+/// instead of unwinding into the host, and reported to `error_callback`
+/// as [`error::Error::Panic`] of the audio thread, once, the next input buffers being dropped.
+fn guard_callbacks<T, D, E>(
+    mut data_callback: D,
+    error_callback: E,
+) -> (
+    impl FnMut(&[T], &cpal::InputCallbackInfo) + Send + 'static,
+    impl FnMut(cpal::StreamError) + Send + 'static,
+)
+where
+    T: 'static,
+    D: FnMut(&[T], &cpal::InputCallbackInfo) + Send + 'static,
+    E: FnMut(error::Error) + Send + 'static,
+{
+    let error_callback = Arc::new(Mutex::new(error_callback));
+    let panic_callback = error_callback.clone();
+    let mut failed = false;
+    (
+        move |input_buffer: &[T], info: &cpal::InputCallbackInfo| {
+            if failed {
+                return;
+            }
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                data_callback(input_buffer, info)
+            }));
+            if let Err(payload) = result {
+                failed = true;
+                (panic_callback
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner))(
+                    error::Error::Panic {
+                        thread: String::from("audio"),
+                        message: error::panic_message(&*payload),
+                    },
+                );
+            }
+        },
+        move |err: cpal::StreamError| {
+            (error_callback
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner))(err.into())
+        },
+    )
+}
+
+/// Start playing the built input stream.
+fn play(
+    stream: Result<cpal::Stream, cpal::BuildStreamError>,
+) -> Result<cpal::Stream, error::Error> {
+    let stream = stream?;
+    stream.play()?;
+    Ok(stream)
+}
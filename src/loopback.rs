@@ -0,0 +1,148 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Capture of what the machine is playing, instead of an input device.
+//!
+//! - Windows: WASAPI loopback of an output device, in its mix format.
+//! - Linux: the monitor source of a PulseAudio or PipeWire sink, captured through the
+//!   `pulse` ALSA device, which needs the ALSA plugin of PulseAudio (`pipewire-pulse`
+//!   provides it for PipeWire) and `pactl` to find the default sink.
+//! - macOS has no loopback capture: install a virtual device such as BlackHole, create
+//!   a Multi-Output Device of it and of the speakers in Audio MIDI Setup, use it as the
+//!   output, then capture the virtual device with `--device`.
+
+/// Device capturing the output, with its only supported config if it has one.
+pub struct LoopbackDevice {
+    pub device: cpal::Device,
+    /// `None` when the config is negotiated as for any input device
+    pub config: Option<cpal::SupportedStreamConfig>,
+}
+
+/// Loopback of an output device of the WASAPI host by name or by index in its
+/// output devices, or of the default output device.
+#[cfg(target_os = "windows")]
+pub fn select_loopback_device(
+    host: &cpal::Host,
+    device: Option<&str>,
+) -> Result<LoopbackDevice, String> {
+    use cpal::traits::DeviceTrait;
+
+    if host.id() != cpal::HostId::Wasapi {
+        return Err(format!(
+            "--loopback requires the WASAPI host, not '{}'",
+            host.id().name()
+        ));
+    }
+    let device = crate::select_output_device(host, device).map_err(|err| err.to_string())?;
+    // the loopback stream is in the mix format of the output device
+    let config = device
+        .default_output_config()
+        .map_err(|err| format!("failed to get the output config: {}", err))?;
+    Ok(LoopbackDevice {
+        device,
+        config: Some(config),
+    })
+}
+
+/// Monitor source of the sink by name, or of the default sink, captured through the
+/// `pulse` device of the ALSA host.
+#[cfg(target_os = "linux")]
+pub fn select_loopback_device(
+    host: &cpal::Host,
+    device: Option<&str>,
+) -> Result<LoopbackDevice, String> {
+    use cpal::traits::{DeviceTrait, HostTrait};
+
+    if host.id() != cpal::HostId::Alsa {
+        return Err(format!(
+            "--loopback requires the ALSA host, not '{}'",
+            host.id().name()
+        ));
+    }
+    let sink = match device {
+        Some(sink) => sink.to_string(),
+        None => default_sink()?,
+    };
+    let monitor = if sink.ends_with(".monitor") {
+        sink
+    } else {
+        format!("{}.monitor", sink)
+    };
+    // the default source of the PulseAudio clients, as the ALSA plugin is
+    std::env::set_var("PULSE_SOURCE", &monitor);
+
+    let device = host
+        .input_devices()
+        .map_err(|err| format!("failed to get input devices of host 'ALSA': {}", err))?
+        .find(|dev| dev.name().is_ok_and(|name| name == "pulse"))
+        .ok_or_else(|| {
+            format!(
+                "no 'pulse' ALSA device to capture '{}', install the ALSA plugin of PulseAudio",
+                monitor
+            )
+        })?;
+    Ok(LoopbackDevice {
+        device,
+        config: None,
+    })
+}
+
+/// Name of the default sink of PulseAudio, or of PipeWire, from `pactl`.
+#[cfg(target_os = "linux")]
+fn default_sink() -> Result<String, String> {
+    use std::process::Command;
+
+    let pactl = |args: &[&str]| {
+        Command::new("pactl")
+            .args(args)
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).into_owned())
+    };
+    // `get-default-sink` is missing before PulseAudio 15
+    pactl(&["get-default-sink"])
+        .map(|sink| sink.trim().to_string())
+        .or_else(|| {
+            pactl(&["info"])?.lines().find_map(|line| {
+                line.strip_prefix("Default Sink:")
+                    .map(|sink| sink.trim().to_string())
+            })
+        })
+        .filter(|sink| !sink.is_empty())
+        .ok_or_else(|| {
+            String::from("failed to get the default sink with pactl, select the sink with --device")
+        })
+}
+
+#[cfg(target_os = "macos")]
+pub fn select_loopback_device(
+    _host: &cpal::Host,
+    _device: Option<&str>,
+) -> Result<LoopbackDevice, String> {
+    Err(String::from(
+        "--loopback is not supported on macOS, create a Multi-Output Device of a virtual \
+         device such as BlackHole and of the speakers in Audio MIDI Setup, use it as the \
+         output and capture the virtual device with --device",
+    ))
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "linux", target_os = "macos")))]
+pub fn select_loopback_device(
+    _host: &cpal::Host,
+    _device: Option<&str>,
+) -> Result<LoopbackDevice, String> {
+    Err(String::from("--loopback is not supported on this OS"))
+}
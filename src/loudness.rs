@@ -0,0 +1,375 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Loudness metering per ITU-R BS.1770 and EBU R128:
+//! momentary, short-term and integrated loudness in LUFS,
+//! and loudness range in LU per EBU Tech 3342.
+
+use crate::biquad::Biquad;
+use crate::ChannelData;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+
+/// the blocks are measured in steps of 100 ms
+const SUBBLOCKS_PER_SECOND: u32 = 10;
+/// 400 ms
+const MOMENTARY_SUBBLOCKS: usize = 4;
+/// 3 s
+const SHORT_TERM_SUBBLOCKS: usize = 30;
+
+const ABSOLUTE_GATE: f64 = -70.0;
+const RELATIVE_GATE: f64 = -10.0;
+/// the loudness range is the spread of the short-term loudness between these percentiles,
+/// of the values above its own relative gate
+const RANGE_RELATIVE_GATE: f64 = -20.0;
+const RANGE_LOW_PERCENTILE: f64 = 0.10;
+const RANGE_HIGH_PERCENTILE: f64 = 0.95;
+
+/// The gating blocks of the integrated loudness are kept in a histogram,
+/// so the memory use does not grow with time, with bins of 0.1 LU from the
+/// absolute gate to +30 LUFS, each with the count and the sum of their energies.
+const HISTOGRAM_BINS_PER_LU: f64 = 10.0;
+const HISTOGRAM_BINS: usize = 1000;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Loudness {
+    /// loudness of the last 400 ms, in LUFS, `null` in JSON until there is enough samples
+    pub momentary: f32,
+    /// loudness of the last 3 s, in LUFS, `null` in JSON until there is enough samples
+    pub short_term: f32,
+    /// gated loudness since the start, in LUFS, `null` in JSON until a block is above the gate
+    pub integrated: f32,
+}
+
+/// K-weighting filter, a high shelf followed by a high pass,
+/// with the coefficients of BS.1770 adapted to the sample rate.
+fn k_weighting_filter(sample_rate: u32) -> [Biquad; 2] {
+    let sample_rate = sample_rate as f64;
+
+    let f0 = 1681.974450955533;
+    let gain = 3.999843853973347;
+    let q = 0.7071752369554196;
+    let k = (PI * f0 / sample_rate).tan();
+    let vh = 10_f64.powf(gain / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let high_shelf = Biquad::new(
+        [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    );
+
+    let f0 = 38.13547087602444;
+    let q = 0.5003270373238773;
+    let k = (PI * f0 / sample_rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad::new(
+        [1.0, -2.0, 1.0],
+        [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    );
+
+    [high_shelf, high_pass]
+}
+
+/// Position of a channel in the layout of the input, for its weight in the loudness.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChannelPosition {
+    Left,
+    Right,
+    Center,
+    /// low frequency effects, ignored by the loudness
+    Lfe,
+    LeftSurround,
+    RightSurround,
+    /// any other channel, e.g. of a microphone array
+    Other,
+}
+
+impl ChannelPosition {
+    /// `L`, `R`, `C`, `LFE`, `Ls`, `Rs`, or `-` for any other channel.
+    pub fn parse(position: &str) -> Result<Self, String> {
+        match position.trim().to_lowercase().as_str() {
+            "l" => Ok(ChannelPosition::Left),
+            "r" => Ok(ChannelPosition::Right),
+            "c" => Ok(ChannelPosition::Center),
+            "lfe" => Ok(ChannelPosition::Lfe),
+            "ls" => Ok(ChannelPosition::LeftSurround),
+            "rs" => Ok(ChannelPosition::RightSurround),
+            "-" => Ok(ChannelPosition::Other),
+            _ => Err(format!("invalid channel position '{}'", position)),
+        }
+    }
+
+    /// Weight in the sum of the energies of the channels, per BS.1770:
+    /// the LFE is ignored and the surrounds are boosted by 1.5 dB.
+    pub fn weight(self) -> f64 {
+        match self {
+            ChannelPosition::Lfe => 0.0,
+            ChannelPosition::LeftSurround | ChannelPosition::RightSurround => 1.41,
+            _ => 1.0,
+        }
+    }
+}
+
+/// Parse a layout, `mono`, `stereo`, `5.1` (L, R, C, LFE, Ls, Rs)
+/// or the comma separated positions of the channels.
+pub fn parse_layout(layout: &str) -> Result<Vec<ChannelPosition>, String> {
+    let positions = match layout {
+        "mono" => "C",
+        "stereo" => "L,R",
+        "5.1" => "L,R,C,LFE,Ls,Rs",
+        positions => positions,
+    };
+    positions.split(',').map(ChannelPosition::parse).collect()
+}
+
+fn loudness(energy: f64) -> f64 {
+    -0.691 + 10.0 * energy.log10()
+}
+
+fn mean(energies: impl ExactSizeIterator<Item = f64>) -> f64 {
+    let len = energies.len();
+    energies.sum::<f64>() / len as f64
+}
+
+/// bin of the histograms of a loudness above the absolute gate
+fn histogram_bin(loudness: f64) -> usize {
+    (((loudness - ABSOLUTE_GATE).max(0.0) * HISTOGRAM_BINS_PER_LU) as usize).min(HISTOGRAM_BINS - 1)
+}
+
+/// loudness of the mean energy of the histogram from the bin, -inf if it is empty
+fn gated_loudness(histogram: &[(u64, f64)], first_bin: usize) -> f64 {
+    let (count, energy) = histogram[first_bin..]
+        .iter()
+        .fold((0, 0.0), |(count, energy), bin| {
+            (count + bin.0, energy + bin.1)
+        });
+    if count == 0 {
+        f64::NEG_INFINITY
+    } else {
+        loudness(energy / count as f64)
+    }
+}
+
+pub struct LoudnessMeter {
+    filters: Vec<[Biquad; 2]>,
+    weights: Vec<f64>,
+    subblock_len: usize,
+    /// frames and weighted energy in the current sub-block
+    subblock_frames: usize,
+    subblock_energy: f64,
+    /// mean weighted energy of the latest sub-blocks, oldest first
+    subblock_energies: VecDeque<f64>,
+    histogram: Vec<(u64, f64)>,
+    /// short-term loudness of each sub-block, as the gating blocks
+    short_term_histogram: Vec<(u64, f64)>,
+}
+
+impl LoudnessMeter {
+    /// Loudness of channels of an unknown layout, all of them weighted the same.
+    pub fn new(sample_rate: u32, num_channels: usize) -> Self {
+        LoudnessMeter::with_layout(sample_rate, &vec![ChannelPosition::Other; num_channels])
+    }
+
+    /// Loudness of the channels at the positions of the layout.
+    pub fn with_layout(sample_rate: u32, layout: &[ChannelPosition]) -> Self {
+        LoudnessMeter {
+            filters: vec![k_weighting_filter(sample_rate); layout.len()],
+            weights: layout.iter().map(|position| position.weight()).collect(),
+            subblock_len: ((sample_rate as f64 / SUBBLOCKS_PER_SECOND as f64).round() as usize)
+                .max(1),
+            subblock_frames: 0,
+            subblock_energy: 0.0,
+            subblock_energies: VecDeque::with_capacity(SHORT_TERM_SUBBLOCKS + 1),
+            histogram: vec![(0, 0.0); HISTOGRAM_BINS],
+            short_term_histogram: vec![(0, 0.0); HISTOGRAM_BINS],
+        }
+    }
+
+    /// Add the samples of an input buffer, and measure the loudness so far.
+    pub fn process(&mut self, channels: &[ChannelData]) -> Loudness {
+        let num_frames = channels
+            .iter()
+            .map(|channel| channel.samples.len())
+            .min()
+            .unwrap_or(0);
+
+        for frame in 0..num_frames {
+            for ((channel, filter), weight) in
+                channels.iter().zip(&mut self.filters).zip(&self.weights)
+            {
+                let [high_shelf, high_pass] = filter;
+                let y = high_pass.process(high_shelf.process(channel.samples[frame] as f64));
+                self.subblock_energy += weight * y * y;
+            }
+
+            self.subblock_frames += 1;
+            if self.subblock_frames == self.subblock_len {
+                self.end_subblock();
+            }
+        }
+
+        Loudness {
+            momentary: self.sliding_loudness(MOMENTARY_SUBBLOCKS) as f32,
+            short_term: self.sliding_loudness(SHORT_TERM_SUBBLOCKS) as f32,
+            integrated: self.integrated_loudness() as f32,
+        }
+    }
+
+    fn end_subblock(&mut self) {
+        if self.subblock_energies.len() == SHORT_TERM_SUBBLOCKS {
+            self.subblock_energies.pop_front();
+        }
+        self.subblock_energies
+            .push_back(self.subblock_energy / self.subblock_len as f64);
+        self.subblock_frames = 0;
+        self.subblock_energy = 0.0;
+
+        // gating blocks of 400 ms, overlapped by 75%
+        if self.subblock_energies.len() >= MOMENTARY_SUBBLOCKS {
+            let block_energy = mean(
+                self.subblock_energies
+                    .iter()
+                    .rev()
+                    .take(MOMENTARY_SUBBLOCKS)
+                    .cloned(),
+            );
+            let block_loudness = loudness(block_energy);
+            if block_loudness >= ABSOLUTE_GATE {
+                let bin = histogram_bin(block_loudness);
+                self.histogram[bin].0 += 1;
+                self.histogram[bin].1 += block_energy;
+            }
+        }
+
+        if self.subblock_energies.len() == SHORT_TERM_SUBBLOCKS {
+            let short_term_energy = mean(self.subblock_energies.iter().cloned());
+            let short_term = loudness(short_term_energy);
+            if short_term >= ABSOLUTE_GATE {
+                let bin = histogram_bin(short_term);
+                self.short_term_histogram[bin].0 += 1;
+                self.short_term_histogram[bin].1 += short_term_energy;
+            }
+        }
+    }
+
+    /// loudness of the latest sub-blocks, -inf if there are not enough of them yet
+    fn sliding_loudness(&self, num_subblocks: usize) -> f64 {
+        if self.subblock_energies.len() < num_subblocks {
+            return f64::NEG_INFINITY;
+        }
+        loudness(mean(
+            self.subblock_energies
+                .iter()
+                .rev()
+                .take(num_subblocks)
+                .cloned(),
+        ))
+    }
+
+    fn integrated_loudness(&self) -> f64 {
+        let ungated_loudness = gated_loudness(&self.histogram, 0);
+        if ungated_loudness == f64::NEG_INFINITY {
+            return ungated_loudness;
+        }
+        gated_loudness(
+            &self.histogram,
+            histogram_bin(ungated_loudness + RELATIVE_GATE),
+        )
+    }
+
+    /// Loudness range since the start in LU, 0 until there is a short-term loudness
+    /// above the gate.
+    pub fn loudness_range(&self) -> f32 {
+        let ungated_loudness = gated_loudness(&self.short_term_histogram, 0);
+        if ungated_loudness == f64::NEG_INFINITY {
+            return 0.0;
+        }
+        let bins =
+            &self.short_term_histogram[histogram_bin(ungated_loudness + RANGE_RELATIVE_GATE)..];
+        let count: u64 = bins.iter().map(|bin| bin.0).sum();
+        let percentile = |percentile: f64| {
+            let rank = (percentile * (count - 1) as f64).round() as u64;
+            let mut below = 0;
+            let index = bins
+                .iter()
+                .position(|bin| {
+                    below += bin.0;
+                    below > rank
+                })
+                .unwrap_or(bins.len() - 1);
+            index as f64 / HISTOGRAM_BINS_PER_LU
+        };
+        (percentile(RANGE_HIGH_PERCENTILE) - percentile(RANGE_LOW_PERCENTILE)) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Momentary loudness of a 1 kHz tone on a channel of 6, the others silent.
+    fn tone_loudness(meter: &mut LoudnessMeter, tone_channel: usize) -> f32 {
+        let sample_rate = 48000;
+        let channels: Vec<_> = (0..6)
+            .map(|index| ChannelData {
+                index,
+                loudness_level: 0.0,
+                samples: (0..sample_rate)
+                    .map(|frame| match index == tone_channel {
+                        true => 0.1 * (2.0 * PI * 1000.0 * frame as f64 / 48000.0).sin() as f32,
+                        false => 0.0,
+                    })
+                    .collect(),
+            })
+            .collect();
+        meter.process(&channels).momentary
+    }
+
+    #[test]
+    fn unknown_layout_weights_all_channels_the_same() {
+        let front = tone_loudness(&mut LoudnessMeter::new(48000, 6), 0);
+        assert!(front.is_finite());
+        assert_eq!(tone_loudness(&mut LoudnessMeter::new(48000, 6), 3), front);
+        assert_eq!(tone_loudness(&mut LoudnessMeter::new(48000, 6), 4), front);
+    }
+
+    #[test]
+    fn surround_layout_weights() {
+        let layout = parse_layout("5.1").unwrap();
+        let front = tone_loudness(&mut LoudnessMeter::with_layout(48000, &layout), 0);
+        let lfe = tone_loudness(&mut LoudnessMeter::with_layout(48000, &layout), 3);
+        let surround = tone_loudness(&mut LoudnessMeter::with_layout(48000, &layout), 4);
+        assert_eq!(lfe, f32::NEG_INFINITY);
+        assert!((surround - front - 1.49).abs() < 0.01);
+    }
+
+    #[test]
+    fn parse_layouts() {
+        assert_eq!(
+            parse_layout("ls,RS,-").unwrap(),
+            [
+                ChannelPosition::LeftSurround,
+                ChannelPosition::RightSurround,
+                ChannelPosition::Other
+            ]
+        );
+        assert!(parse_layout("L,X").is_err());
+    }
+}
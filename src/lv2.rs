@@ -0,0 +1,340 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Hosting of LV2 plugins with the lilv crate, as the equalizers, limiters or denoisers of the users
+//! in the processing of the streams.
+//!
+//! Plugins with as many audio inputs and outputs as channels process all of them at once,
+//! mono plugins have an instance for each channel. The control inputs are the parameters
+//! of the plugin, by their symbols.
+
+use crate::processor::Processor;
+use crate::InputBufferSourceData;
+use lilv::instance::ActiveInstance;
+use lilv::World;
+use lv2_raw::core::LV2Feature;
+use lv2_raw::urid::{LV2Urid, LV2UridMap, LV2UridMapHandle, LV2_URID__MAP};
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::sync::Mutex;
+
+/// frames of each run of the plugins, longer buffers run in blocks
+const BLOCK_SIZE: usize = 1024;
+
+const INPUT_PORT: &str = "http://lv2plug.in/ns/lv2core#InputPort";
+const AUDIO_PORT: &str = "http://lv2plug.in/ns/lv2core#AudioPort";
+const CONTROL_PORT: &str = "http://lv2plug.in/ns/lv2core#ControlPort";
+const CONNECTION_OPTIONAL: &str = "http://lv2plug.in/ns/lv2core#connectionOptional";
+
+/// The URID map feature, with the URIs mapped so far.
+struct UridMap {
+    feature: LV2UridMap,
+    uris: Mutex<Vec<CString>>,
+}
+
+// the handle of the feature only points to the uris of the same box
+unsafe impl Send for UridMap {}
+
+/// The URIs mapped to the URIDs of the plugins, from 1.
+extern "C" fn map_uri(handle: LV2UridMapHandle, uri: *const c_char) -> LV2Urid {
+    let uris = unsafe { &*(handle as *const Mutex<Vec<CString>>) };
+    let uri = unsafe { CStr::from_ptr(uri) };
+    let mut uris = uris.lock().unwrap_or_else(|err| err.into_inner());
+    let index = match uris.iter().position(|known| known.as_c_str() == uri) {
+        Some(index) => index,
+        None => {
+            uris.push(uri.to_owned());
+            uris.len() - 1
+        }
+    };
+    index as u32 + 1
+}
+
+/// A plugin and its parameters, of the command line.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lv2Spec {
+    pub uri: String,
+    /// value of the control inputs, by symbol
+    pub parameters: Vec<(String, f32)>,
+}
+
+impl Lv2Spec {
+    /// Parse `<uri>[,<symbol>=<value>...]`,
+    /// e.g. `http://lsp-plug.in/plugins/lv2/limiter_stereo,th=-3`.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut fields = spec.split(',');
+        let uri = fields.next().unwrap_or_default().trim();
+        if uri.is_empty() {
+            return Err(format!("invalid LV2 plugin '{}', without its URI", spec));
+        }
+        let parameters = fields
+            .map(|field| {
+                field
+                    .split_once('=')
+                    .and_then(|(symbol, value)| {
+                        Some((symbol.trim().to_owned(), value.trim().parse::<f32>().ok()?))
+                    })
+                    .ok_or_else(|| {
+                        format!("invalid parameter '{}' of the LV2 plugin '{}'", field, uri)
+                    })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Lv2Spec {
+            uri: uri.to_owned(),
+            parameters,
+        })
+    }
+}
+
+struct ControlInput {
+    symbol: String,
+    port: usize,
+    /// NaN if not given by the plugin
+    min: f32,
+    max: f32,
+}
+
+/// An instance of the plugin, for some of the channels.
+struct Instance {
+    instance: ActiveInstance,
+    /// positions of the processed channels, of each audio input and output
+    channels: Vec<usize>,
+    /// connected to the audio inputs and outputs
+    inputs: Vec<Vec<f32>>,
+    outputs: Vec<Vec<f32>>,
+}
+
+/// An LV2 plugin processing the channels.
+pub struct Lv2Plugin {
+    uri: String,
+    /// dropped before the world and the buffers they are connected to
+    instances: Vec<Instance>,
+    /// value of each port, the control ports of all the instances connected to them
+    controls: Box<[f32]>,
+    control_inputs: Vec<ControlInput>,
+    /// for as long as the instances
+    urid_map: Box<UridMap>,
+    _world: World,
+}
+
+impl Lv2Plugin {
+    pub fn new(spec: &Lv2Spec, num_channels: usize, sample_rate: u32) -> Result<Self, String> {
+        let world = World::with_load_all();
+        let plugin = world
+            .plugins()
+            .plugin(&world.new_uri(&spec.uri))
+            .ok_or_else(|| format!("unknown LV2 plugin '{}'", spec.uri))?;
+        let input_port = world.new_uri(INPUT_PORT);
+        let audio_port = world.new_uri(AUDIO_PORT);
+        let control_port = world.new_uri(CONTROL_PORT);
+        let connection_optional = world.new_uri(CONNECTION_OPTIONAL);
+
+        let ranges = plugin.port_ranges_float();
+        let mut controls = vec![0.0; ranges.len()];
+        let mut control_ports = Vec::new();
+        let mut control_inputs = Vec::new();
+        let mut audio_inputs = Vec::new();
+        let mut audio_outputs = Vec::new();
+        for port in plugin.iter_ports() {
+            let index = port.index();
+            let symbol = port
+                .symbol()
+                .and_then(|symbol| symbol.as_str().map(str::to_owned))
+                .unwrap_or_default();
+            let is_input = port.is_a(&input_port);
+            if port.is_a(&audio_port) {
+                if is_input {
+                    audio_inputs.push(index);
+                } else {
+                    audio_outputs.push(index);
+                }
+            } else if port.is_a(&control_port) {
+                let range = &ranges[index];
+                controls[index] = [range.default, range.min, 0.0]
+                    .iter()
+                    .copied()
+                    .find(|value| !value.is_nan())
+                    .unwrap_or_default();
+                control_ports.push(index);
+                if is_input {
+                    control_inputs.push(ControlInput {
+                        symbol,
+                        port: index,
+                        min: range.min,
+                        max: range.max,
+                    });
+                }
+            } else if !port.has_property(&connection_optional) {
+                return Err(format!(
+                    "unsupported port '{}' of the LV2 plugin '{}'",
+                    symbol, spec.uri
+                ));
+            }
+        }
+
+        let mut urid_map = Box::new(UridMap {
+            feature: LV2UridMap {
+                handle: std::ptr::null_mut(),
+                map: map_uri,
+            },
+            uris: Mutex::new(Vec::new()),
+        });
+        urid_map.feature.handle = (&mut urid_map.uris as *mut Mutex<Vec<CString>>).cast();
+        let mut lv2_plugin = Lv2Plugin {
+            uri: spec.uri.clone(),
+            instances: Vec::new(),
+            controls: controls.into_boxed_slice(),
+            control_inputs,
+            urid_map,
+            _world: world,
+        };
+        for (symbol, value) in &spec.parameters {
+            lv2_plugin.set_control(symbol, *value)?;
+        }
+
+        let groups: Vec<Vec<usize>> =
+            if audio_inputs.len() == num_channels && audio_outputs.len() == num_channels {
+                vec![(0..num_channels).collect()]
+            } else if audio_inputs.len() == 1 && audio_outputs.len() == 1 {
+                (0..num_channels).map(|channel| vec![channel]).collect()
+            } else {
+                return Err(format!(
+                    "the LV2 plugin '{}' has {} audio inputs and {} audio outputs, \
+                     it cannot process {} channels",
+                    spec.uri,
+                    audio_inputs.len(),
+                    audio_outputs.len(),
+                    num_channels
+                ));
+            };
+        let urid_map_uri =
+            CString::new(LV2_URID__MAP).map_err(|_| String::from("invalid LV2 feature URI"))?;
+        let urid_map = LV2Feature {
+            uri: urid_map_uri.as_ptr(),
+            data: (&mut lv2_plugin.urid_map.feature as *mut LV2UridMap).cast(),
+        };
+        for channels in groups {
+            let mut instance = unsafe { plugin.instantiate(sample_rate as f64, [&urid_map]) }
+                .ok_or_else(|| {
+                    format!(
+                        "failed to instantiate the LV2 plugin '{}', \
+                         it may require unsupported features",
+                        spec.uri
+                    )
+                })?;
+            let mut inputs = vec![vec![0.0; BLOCK_SIZE]; audio_inputs.len()];
+            let mut outputs = vec![vec![0.0; BLOCK_SIZE]; audio_outputs.len()];
+            // the buffers are on the heap, they do not move with the instance
+            unsafe {
+                for (&port, buffer) in audio_inputs.iter().zip(&mut inputs) {
+                    instance.connect_port_mut(port, buffer.as_mut_ptr());
+                }
+                for (&port, buffer) in audio_outputs.iter().zip(&mut outputs) {
+                    instance.connect_port_mut(port, buffer.as_mut_ptr());
+                }
+                for &port in &control_ports {
+                    instance.connect_port_mut(port, &mut lv2_plugin.controls[port] as *mut f32);
+                }
+            }
+            lv2_plugin.instances.push(Instance {
+                instance: unsafe { instance.activate() },
+                channels,
+                inputs,
+                outputs,
+            });
+        }
+        Ok(lv2_plugin)
+    }
+
+    /// Set a control input of the plugin, within its range.
+    fn set_control(&mut self, symbol: &str, value: f32) -> Result<(), String> {
+        let control = self
+            .control_inputs
+            .iter()
+            .find(|control| control.symbol == symbol)
+            .ok_or_else(|| {
+                format!(
+                    "unknown control '{}' of the LV2 plugin '{}', expected one of: {}",
+                    symbol,
+                    self.uri,
+                    self.control_inputs
+                        .iter()
+                        .map(|control| control.symbol.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            })?;
+        if value.is_nan() || value < control.min || value > control.max {
+            return Err(format!(
+                "invalid value {} of the control '{}' of the LV2 plugin '{}', \
+                 out of its range from {} to {}",
+                value, symbol, self.uri, control.min, control.max
+            ));
+        }
+        self.controls[control.port] = value;
+        Ok(())
+    }
+}
+
+impl Processor for Lv2Plugin {
+    fn name(&self) -> &str {
+        &self.uri
+    }
+
+    fn process(&mut self, buffer: &mut InputBufferSourceData) {
+        let num_frames = buffer
+            .channels
+            .first()
+            .map_or(0, |channel| channel.samples.len());
+        for instance in &mut self.instances {
+            if instance
+                .channels
+                .iter()
+                .any(|&channel| channel >= buffer.channels.len())
+            {
+                continue;
+            }
+            let mut start = 0;
+            while start < num_frames {
+                let len = (num_frames - start).min(BLOCK_SIZE);
+                for (input, &channel) in instance.inputs.iter_mut().zip(&instance.channels) {
+                    input[..len].copy_from_slice(&buffer.channels[channel].samples[start..][..len]);
+                }
+                unsafe { instance.instance.run(len) };
+                for (output, &channel) in instance.outputs.iter().zip(&instance.channels) {
+                    buffer.channels[channel].samples[start..][..len]
+                        .copy_from_slice(&output[..len]);
+                }
+                start += len;
+            }
+        }
+    }
+
+    fn parameters(&self) -> Vec<(String, f64)> {
+        self.control_inputs
+            .iter()
+            .map(|control| {
+                (
+                    control.symbol.clone(),
+                    f64::from(self.controls[control.port]),
+                )
+            })
+            .collect()
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f64) -> Result<(), String> {
+        self.set_control(name, value as f32)
+    }
+}
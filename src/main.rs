@@ -13,45 +13,124 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use cpal::traits::{DeviceTrait, EventLoopTrait, HostTrait};
-use std::sync::{Arc, RwLock};
+use audio_in_stream_rs::agc::{self, AgcConfig, AutomaticGainControl};
+use audio_in_stream_rs::analyzer::{ToneAnalysis, ToneAnalyzer};
+use audio_in_stream_rs::auth::Auth;
+use audio_in_stream_rs::broadcast::Broadcast;
+use audio_in_stream_rs::calibration::Calibration;
+use audio_in_stream_rs::channel_faults::{self, ChannelFault, FaultKind};
+use audio_in_stream_rs::clipping::ClipConfig;
+use audio_in_stream_rs::control::Controls;
+use audio_in_stream_rs::correlation::{CorrelationConfig, PairCorrelation};
+use audio_in_stream_rs::cors::Cors;
+use audio_in_stream_rs::dc_offset;
+#[cfg(feature = "rnnoise")]
+use audio_in_stream_rs::denoise::{self, Denoiser};
+use audio_in_stream_rs::dtmf::ToneDetector;
+use audio_in_stream_rs::encoder;
+use audio_in_stream_rs::error::Error;
+use audio_in_stream_rs::events::{Event, EventQueue, PendingEvents};
+use audio_in_stream_rs::features::{FeatureConfig, FeatureExtractor, FeatureFrame, NpyWriter};
+use audio_in_stream_rs::file_input::{FileInput, RawFormat};
+use audio_in_stream_rs::filter::{FilterChain, FilterSpec};
+use audio_in_stream_rs::flac;
+use audio_in_stream_rs::gate::{GateConfig, NoiseGate};
+use audio_in_stream_rs::generator::{GeneratorOutput, Signal};
+use audio_in_stream_rs::grpc;
+use audio_in_stream_rs::health::{self, Health, RecordingStatus};
+use audio_in_stream_rs::history::{LevelHistory, PeriodLevels};
+#[cfg(any(feature = "aac", feature = "opus"))]
+use audio_in_stream_rs::hls::{self, HlsStream};
+use audio_in_stream_rs::http::{self, HttpState};
+#[cfg(feature = "jack")]
+use audio_in_stream_rs::jack_input::{self, JackInput};
+use audio_in_stream_rs::latency::{LatencyOutput, LatencyStats, LatencyTrials};
+use audio_in_stream_rs::leq::{self, LeqLog, LeqMeter};
+use audio_in_stream_rs::level_log::{self, LevelLog, Rotation};
+use audio_in_stream_rs::levels::{LevelSnapshot, Levels};
+use audio_in_stream_rs::loopback;
+use audio_in_stream_rs::loudness::{self, ChannelPosition};
+use audio_in_stream_rs::meter::{BallisticsConfig, MeterReading, MeterType};
+use audio_in_stream_rs::meter_scale::{self, MeterCell, MeterScale, MeterUnit, Zone};
+use audio_in_stream_rs::metering::{Metering, MeteringConfig, MeteringFrame};
+use audio_in_stream_rs::metric_push::{self, InfluxWriter, MetricPush, StatsdClient};
+use audio_in_stream_rs::metrics::Metrics;
+use audio_in_stream_rs::mid_side::{MidSideMeter, PairMidSide};
+use audio_in_stream_rs::mix::{ChannelMix, Downmix};
+use audio_in_stream_rs::monitor_output::MonitorOutput;
+use audio_in_stream_rs::mqtt::{self, MqttConfig, MqttPublisher, MqttUrl};
+use audio_in_stream_rs::octave_bands::{OctaveBandAnalyzer, OctaveBands};
+use audio_in_stream_rs::osc::{self, OscSender};
+use audio_in_stream_rs::pilot::{PilotFault, PilotToneConfig, PilotToneWatchdog};
+#[cfg(feature = "pipewire")]
+use audio_in_stream_rs::pipewire_input::{self, PipeWireInput};
+use audio_in_stream_rs::pitch::{self, ChannelPitch, PitchDetector};
+use audio_in_stream_rs::processor::{Processor, ProcessorChain};
+use audio_in_stream_rs::receiver::RtpReceiver;
+use audio_in_stream_rs::recording::{
+    CompletedRecording, RecordConfig, RecordFormat, RecordPath, Recorder,
+};
+use audio_in_stream_rs::recording_session::RecordingSession;
+use audio_in_stream_rs::report::ReportConfig;
+use audio_in_stream_rs::retention::{parse_size, Retention, RetentionAction, RetentionConfig};
+use audio_in_stream_rs::ring::{self, SampleRing};
+use audio_in_stream_rs::rms;
+use audio_in_stream_rs::rtp::{self, RtpFormat, RtpSender};
+use audio_in_stream_rs::s3::{self, S3Config, S3Uploader};
+use audio_in_stream_rs::schedule::{self, Schedule};
+use audio_in_stream_rs::silence::SilenceConfig;
+use audio_in_stream_rs::snapshot::{self, SnapshotBuffer};
+use audio_in_stream_rs::spectrum::{
+    Spectrum, SpectrumAnalyzer, Window, DEFAULT_FFT_SIZE, MIN_FFT_SIZE,
+};
+use audio_in_stream_rs::supervisor::Supervisor;
+use audio_in_stream_rs::tls;
+use audio_in_stream_rs::trigger::{self, LevelTrigger, TriggerConfig};
+use audio_in_stream_rs::tui::{Tui, TuiCommand, TuiFrame, TuiSender};
+use audio_in_stream_rs::vad::{self, VoiceActivityDetector};
+use audio_in_stream_rs::wav::Bext;
+use audio_in_stream_rs::waveform::{self, WaveformHistory};
+use audio_in_stream_rs::webhook::Webhooks;
+use audio_in_stream_rs::weighting::Weighting;
+use audio_in_stream_rs::{
+    decibels_overload, duration_from_secs, nearest_input_config, parse_duration, parse_frequency,
+    parse_level, quantization_noise_ratio, rate_interval, select_host, select_input_device,
+    select_output_device, unix_time, InputBufferSourceData, InputMonitor,
+};
+use cpal::traits::{DeviceTrait, HostTrait};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
 use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
+const DEFAULT_LISTEN_ADDR: &str = "0.0.0.0:8000";
+const DEFAULT_HTTP_WORKERS: usize = 4;
+/// time given to deliver the pending events before exiting
+const EXIT_EVENTS_TIMEOUT: Duration = Duration::from_secs(5);
+/// names of the readers of the sample ring, in the metrics
+const METERING_CONSUMER: &str = "metering";
+const RECORDING_CONSUMER: &str = "recording";
+const STREAMING_CONSUMER: &str = "streaming";
 fn clamp(x: f32, min: f32, max: f32) -> f32 {
     x.max(min).min(max)
 }
 
-fn root_mean_square<'a>(values: impl IntoIterator<Item = &'a f32>) -> f32 {
-    let mut n: usize = 0;
-    let mut square_sum: f32 = 0.0;
-    for x in values {
-        n += 1;
-        square_sum += x.powi(2);
-    }
-
-    (square_sum / n as f32).sqrt()
-}
-
-/// Given a loudness level in nominal interval of [0,+1],
-/// compute dBov unit of decibels relative to overload.
-/// A loundness level of 1 is designated as 0 dBov and
-/// a loundness level of 0 is designated as -inf.
-/// Loudness level is usually computed as the root mean square of
-/// a audio signal in the nominal interval of [-1,+1]
-fn decibels_overload(loudness_level: f32) -> f32 {
-    20.0 * loudness_level.log10()
-}
-
-fn quantization_noise_ratio(quantization_bits: usize) -> f32 {
-    20.0 * 2.0_f32.log10() * quantization_bits as f32
-}
-
-fn horizontal_scale(value: f32, num_chars: usize) -> String {
+/// horizontal scale filled up to `value`, with a marker at `marker`,
+/// both normalized between 0 and 1
+fn horizontal_scale(value: f32, marker: f32, num_chars: usize) -> String {
     let mut hscale = String::with_capacity(num_chars);
     let normalized_value = clamp(value, 0.0, 1.0);
     let ivalue = (normalized_value * num_chars as f32) as usize;
+    let imarker = if marker > 0.0 {
+        Some(((clamp(marker, 0.0, 1.0) * num_chars as f32) as usize).min(num_chars - 1))
+    } else {
+        None
+    };
     for i in 0..num_chars {
-        if i < ivalue {
+        if Some(i) == imarker {
+            hscale.push('|');
+        } else if i < ivalue {
             hscale.push('=');
         } else {
             hscale.push(' ');
@@ -77,19 +156,20 @@ fn print_cpal_input_devices() {
     for host_id in cpal::available_hosts() {
         if let Ok(host) = cpal::host_from_id(host_id) {
             if let Ok(input_devices) = host.input_devices() {
-                for dev in input_devices {
-                    if let Ok(supported_input_formats) = dev.supported_input_formats() {
-                        for f in supported_input_formats {
+                for (device_index, dev) in input_devices.enumerate() {
+                    if let Ok(supported_input_configs) = dev.supported_input_configs() {
+                        for c in supported_input_configs {
                             println!(
-                                    "host: '{}', input_device: '{}' channels: {}, sample rate min: {} max: {}, {:?}",
+                                    "host: '{}', input_device {}: '{}' channels: {}, sample rate min: {} max: {}, {:?}",
                                     host_id.name(),
+                                    device_index,
                                     dev.name().unwrap_or_else(|_| String::from(
                                         "<failed to get device name>"
                                     )),
-                                    f.channels,
-                                    f.min_sample_rate.0,
-                                    f.max_sample_rate.0,
-                                    f.data_type
+                                    c.channels(),
+                                    c.min_sample_rate().0,
+                                    c.max_sample_rate().0,
+                                    c.sample_format()
                                 );
                         }
                     }
@@ -99,197 +179,3782 @@ fn print_cpal_input_devices() {
     }
 }
 
-struct ChannelData {
-    loudness_level: f32,
-    samples: Vec<f32>,
+/// value of a command line option given either as `--name value` or `--name=value`
+fn arg_value(args: &[String], name: &str) -> Option<String> {
+    let prefix = format!("{}=", name);
+    for (i, arg) in args.iter().enumerate() {
+        if arg == name {
+            return args.get(i + 1).cloned();
+        }
+        if let Some(value) = arg.strip_prefix(&prefix) {
+            return Some(String::from(value));
+        }
+    }
+    None
 }
 
-struct InputBufferSourceData {
-    num_samples: usize,
-    sample_format: cpal::SampleFormat,
-    channels: Vec<ChannelData>,
+/// values of a command line option that can be repeated
+fn arg_values(args: &[String], name: &str) -> Vec<String> {
+    let prefix = format!("{}=", name);
+    let mut values = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == name {
+            values.extend(args.next().cloned());
+        } else if let Some(value) = arg.strip_prefix(&prefix) {
+            values.push(String::from(value));
+        }
+    }
+    values
 }
 
-fn process_input_buffer<T: cpal::Sample>(
-    input_buffer: cpal::InputBuffer<T>,
-    sample_format: &cpal::Format,
-) -> Vec<ChannelData> {
-    let num_channels = sample_format.channels as usize;
-    assert!(num_channels > 0);
-    assert!(input_buffer.len() % num_channels == 0);
-    let mut channel_data = Vec::with_capacity(num_channels);
+/// command line args of the HLS stream of the live capture served at `/hls/live.m3u8`
+#[cfg(any(feature = "aac", feature = "opus"))]
+fn start_hls(
+    args: &[String],
+    num_channels: u16,
+    sample_rate: u32,
+    samples_broadcast: &Broadcast<Vec<f32>>,
+    supervisor: &Supervisor,
+) -> Result<Option<HlsStream>, String> {
+    use audio_in_stream_rs::hls::{HlsCodec, HlsConfig, HlsContainer};
 
-    for channel_index in 0..num_channels {
-        let samples: Vec<_> = input_buffer
-            .iter()
-            // each channel data is interleaved
-            .skip(channel_index)
-            .step_by(num_channels)
-            .map(|s| s.to_f32())
-            .collect();
-
-        channel_data.push(ChannelData {
-            loudness_level: root_mean_square(&samples),
-            samples: samples,
+    if !args.iter().any(|arg| arg == "--hls") {
+        return Ok(None);
+    }
+    let codec = match arg_value(args, "--hls-codec") {
+        Some(codec) => HlsCodec::parse(&codec)?,
+        None => HlsCodec::default_codec(),
+    };
+    codec.check_feature()?;
+    let container = match arg_value(args, "--hls-container") {
+        Some(container) => HlsContainer::parse(&container)?,
+        None => HlsContainer::default_container(codec),
+    };
+    let bitrate = arg_value(args, "--hls-bitrate")
+        .map(|bitrate| encoder::parse_bitrate(&bitrate))
+        .transpose()?
+        .unwrap_or_else(|| codec.default_bitrate());
+    let segment_duration = arg_value(args, "--hls-segment")
+        .map(|duration| parse_duration(&duration))
+        .transpose()?
+        .map_or(hls::DEFAULT_SEGMENT_DURATION, |duration| {
+            duration.as_secs_f64()
         });
+    let window = arg_value(args, "--hls-window")
+        .map(|duration| parse_duration(&duration))
+        .transpose()?
+        .map_or(hls::DEFAULT_WINDOW, |duration| duration.as_secs_f64());
+    if segment_duration <= 0.0 || window <= 0.0 {
+        return Err("the HLS segment and window durations must be positive".to_owned());
     }
+    let config = HlsConfig {
+        codec,
+        container,
+        bitrate,
+        segment_duration,
+        window,
+    };
+    HlsStream::start(
+        config,
+        num_channels,
+        sample_rate,
+        samples_broadcast.subscribe(),
+        supervisor.clone(),
+    )
+    .map(Some)
+}
 
-    channel_data
+#[cfg(not(any(feature = "aac", feature = "opus")))]
+fn start_hls(
+    args: &[String],
+    _num_channels: u16,
+    _sample_rate: u32,
+    _samples_broadcast: &Broadcast<Vec<f32>>,
+    _supervisor: &Supervisor,
+) -> Result<(), String> {
+    if args.iter().any(|arg| arg == "--hls") {
+        return Err("--hls requires the 'aac' or the 'opus' feature".to_owned());
+    }
+    Ok(())
 }
 
-fn input_buffer_info(source_data: &InputBufferSourceData, sample_rate: u32) -> String {
-    let mut input_buffer_info = format!(
-        "input buffer: {:>6} {:#?} samples * {} channel(s), {:>7.3} ms",
-        source_data.num_samples / source_data.channels.len(),
-        source_data.sample_format,
-        source_data.channels.len(),
-        1000.0 * source_data.num_samples as f32 / sample_rate as f32
-    );
+/// command line args to publish the live capture to an Icecast server, as Ogg/Opus
+#[cfg(feature = "opus")]
+fn start_icecast_source(
+    args: &[String],
+    num_channels: u16,
+    sample_rate: u32,
+    samples_broadcast: &Broadcast<Vec<f32>>,
+    supervisor: &Supervisor,
+) -> Result<(), String> {
+    use audio_in_stream_rs::icecast::{self, IcecastUrl};
+    use audio_in_stream_rs::ogg_opus::DEFAULT_BITRATE;
 
-    for (channel_index, channel) in source_data.channels.iter().enumerate() {
-        let channel_decibels_overload = decibels_overload(channel.loudness_level);
-        input_buffer_info += &format!(
-            ", channel {}: [{}] {:>+5.1} dBov",
-            channel_index,
-            // horizontal scale from 0 dBov
-            // to the quantization noise level for 16 bits, i.e. ~96 dB
-            // (a reasonable bottom level, regardless the bit deep of
-            // the samples)
-            // Also, using 16 chars in the horizontal scale
-            // make each char position an indication of a 1 bit
-            // or ~6 dB, equivalent of factor of change in value relative
-            // to the previous/next char position of 0.5
-            horizontal_scale(
-                1.0 + channel_decibels_overload / quantization_noise_ratio(16),
-                16
-            ),
-            channel_decibels_overload,
-        );
+    let url = match arg_value(args, "--icecast-url") {
+        Some(url) => IcecastUrl::parse(&url)?,
+        None => return Ok(()),
+    };
+    let password = arg_value(args, "--icecast-password")
+        .ok_or_else(|| "--icecast-url requires --icecast-password".to_owned())?;
+    let bitrate = parse_arg_value(args, "--icecast-bitrate")?.unwrap_or(DEFAULT_BITRATE);
+
+    let samples = samples_broadcast.subscribe();
+    tokio::spawn(icecast::run_source_client(
+        url,
+        password,
+        bitrate,
+        num_channels,
+        sample_rate,
+        samples,
+        supervisor.clone(),
+    ));
+    Ok(())
+}
+
+#[cfg(not(feature = "opus"))]
+fn start_icecast_source(
+    args: &[String],
+    _num_channels: u16,
+    _sample_rate: u32,
+    _samples_broadcast: &Broadcast<Vec<f32>>,
+    _supervisor: &Supervisor,
+) -> Result<(), String> {
+    if arg_value(args, "--icecast-url").is_some() {
+        return Err("--icecast-url requires the 'opus' feature".to_owned());
     }
+    Ok(())
+}
+
+/// command line args of the SRT output of the live capture, as a caller or a listener
+#[cfg(feature = "srt")]
+fn start_srt(
+    args: &[String],
+    num_channels: u16,
+    sample_rate: u32,
+    samples_broadcast: &Broadcast<Vec<f32>>,
+    supervisor: &Supervisor,
+) -> Result<(), String> {
+    use audio_in_stream_rs::ogg_opus::DEFAULT_BITRATE;
+    use audio_in_stream_rs::srt::{self, SrtConfig, SrtPayload, SrtUrl};
+
+    let url = match arg_value(args, "--srt-url") {
+        Some(url) => SrtUrl::parse(&url)?,
+        None => return Ok(()),
+    };
+    let payload = match arg_value(args, "--srt-payload") {
+        Some(payload) => SrtPayload::parse(&payload)?,
+        None => SrtPayload::Ts,
+    };
+    let latency = parse_arg_value(args, "--srt-latency")?.unwrap_or(srt::DEFAULT_LATENCY);
+    let passphrase = arg_value(args, "--srt-passphrase")
+        .or_else(|| std::env::var("AUDIO_IN_STREAM_SRT_PASSPHRASE").ok());
+    let bitrate = parse_arg_value(args, "--srt-bitrate")?.unwrap_or(DEFAULT_BITRATE);
+    let config = SrtConfig {
+        url,
+        payload,
+        latency,
+        passphrase,
+    };
+    config.validate()?;
 
-    input_buffer_info
+    let samples = samples_broadcast.subscribe();
+    tokio::spawn(srt::run_sender(
+        config,
+        bitrate,
+        num_channels,
+        sample_rate,
+        samples,
+        supervisor.clone(),
+    ));
+    Ok(())
 }
 
-fn main() {
-    // assume CD Audio sample format
-    let sample_config = cpal::Format {
-        channels: 2,
-        sample_rate: cpal::SampleRate(44100),
-        // data_type: cpal::SampleFormat::I16,
-        data_type: cpal::SampleFormat::F32,
+#[cfg(not(feature = "srt"))]
+fn start_srt(
+    args: &[String],
+    _num_channels: u16,
+    _sample_rate: u32,
+    _samples_broadcast: &Broadcast<Vec<f32>>,
+    _supervisor: &Supervisor,
+) -> Result<(), String> {
+    if arg_value(args, "--srt-url").is_some() {
+        return Err("--srt-url requires the 'srt' feature".to_owned());
+    }
+    Ok(())
+}
+
+/// command line args to publish the live capture to an RTMP server, as AAC or MP3 in FLV
+#[cfg(any(feature = "aac", feature = "mp3"))]
+fn start_rtmp(
+    args: &[String],
+    num_channels: u16,
+    sample_rate: u32,
+    samples_broadcast: &Broadcast<Vec<f32>>,
+    supervisor: &Supervisor,
+) -> Result<(), String> {
+    use audio_in_stream_rs::rtmp::{self, RtmpCodec, RtmpUrl};
+
+    let url = match arg_value(args, "--rtmp-url") {
+        Some(url) => RtmpUrl::parse(&url)?,
+        None => return Ok(()),
+    };
+    let codec = match arg_value(args, "--rtmp-codec") {
+        Some(codec) => RtmpCodec::parse(&codec)?,
+        None => RtmpCodec::default_codec(),
     };
-    let sample_rate = sample_config.sample_rate.0;
+    codec.check_feature()?;
+    let bitrate = arg_value(args, "--rtmp-bitrate")
+        .map(|bitrate| encoder::parse_bitrate(&bitrate))
+        .transpose()?
+        .unwrap_or_else(|| codec.default_bitrate());
 
-    // command line arg to list all supported the sample format in all input devices in all hosts
-    if let Some(_) = std::env::args().find(|arg| arg == "--list-input-devices") {
-        print_cpal_input_devices();
-        return;
-    }
-
-    // audio input thread
-    let input_buffer_source_data_rwlock: Arc<RwLock<Option<InputBufferSourceData>>> =
-        Arc::new(RwLock::new(None));
-    let input_buffer_source_data_wlock = Arc::clone(&input_buffer_source_data_rwlock);
-    thread::spawn(move || {
-        let host = cpal::default_host();
-        // TODO: allow user select different device
-        let dev = host
-            .default_input_device()
-            .expect("failed to get default input device");
-        let event_loop = host.event_loop();
-
-        let stream_id = event_loop
-            .build_input_stream(&dev, &sample_config)
-            .expect("failed to build input stream, maybe invalid input device");
-
-        event_loop
-            .play_stream(stream_id)
-            .expect("failed to play stream");
-
-        let is_tty = atty::is(atty::Stream::Stdout);
-        let mut first_line = true;
-
-        event_loop.run(move |_stream_id, stream_result| {
-            if let cpal::StreamData::Input { buffer } = stream_result.expect("input stream error") {
-                *input_buffer_source_data_wlock.write().unwrap() = match buffer {
-                    cpal::UnknownTypeInputBuffer::U16(input_buffer) => {
-                        Some(InputBufferSourceData {
-                            num_samples: input_buffer.len(),
-                            sample_format: cpal::SampleFormat::U16,
-                            channels: process_input_buffer(input_buffer, &sample_config),
-                        })
-                    }
-                    cpal::UnknownTypeInputBuffer::I16(input_buffer) => {
-                        Some(InputBufferSourceData {
-                            num_samples: input_buffer.len(),
-                            sample_format: cpal::SampleFormat::I16,
-                            channels: process_input_buffer(input_buffer, &sample_config),
-                        })
-                    }
-                    cpal::UnknownTypeInputBuffer::F32(input_buffer) => {
-                        Some(InputBufferSourceData {
-                            num_samples: input_buffer.len(),
-                            sample_format: cpal::SampleFormat::F32,
-                            channels: process_input_buffer(input_buffer, &sample_config),
-                        })
-                    }
-                };
+    let samples = samples_broadcast.subscribe();
+    tokio::spawn(rtmp::run_publisher(
+        url,
+        codec,
+        bitrate,
+        num_channels,
+        sample_rate,
+        samples,
+        supervisor.clone(),
+    ));
+    Ok(())
+}
 
-                if let Some(ref source_data) = *input_buffer_source_data_wlock.read().unwrap() {
-                    if !first_line && is_tty {
-                        // up one line
-                        print!("\x1b[1A");
-                    }
+#[cfg(not(any(feature = "aac", feature = "mp3")))]
+fn start_rtmp(
+    args: &[String],
+    _num_channels: u16,
+    _sample_rate: u32,
+    _samples_broadcast: &Broadcast<Vec<f32>>,
+    _supervisor: &Supervisor,
+) -> Result<(), String> {
+    if arg_value(args, "--rtmp-url").is_some() {
+        return Err("--rtmp-url requires the 'aac' or the 'mp3' feature".to_owned());
+    }
+    Ok(())
+}
 
-                    print!("{}", input_buffer_info(source_data, sample_rate));
+/// handler of the events besides the terminal and the webhooks
+type EventSink = Box<dyn Fn(&Event) + Send>;
 
-                    if is_tty {
-                        // clear the rest of the line
-                        print!("\x1b[0K");
-                    }
+/// command line arg of the SQLite database logging the levels of each second and the events
+#[cfg(feature = "sqlite")]
+fn start_level_database(
+    args: &[String],
+    levels_broadcast: &Broadcast<Levels>,
+    pending: &PendingEvents,
+    supervisor: &Supervisor,
+) -> Result<Option<EventSink>, String> {
+    use audio_in_stream_rs::sqlite::LevelDatabase;
 
-                    println!();
-                    first_line = false;
-                }
-            } else {
-                unimplemented!("invalid audio stream input/output format");
+    let path = match arg_value(args, "--sqlite") {
+        Some(path) => PathBuf::from(path),
+        None => return Ok(None),
+    };
+    let database = LevelDatabase::open(&path, pending.clone(), supervisor.clone())?;
+    let levels_database = database.clone();
+    let mut messages = levels_broadcast.subscribe();
+    tokio::task::spawn_blocking(move || {
+        let mut seconds = PeriodLevels::new(1.0);
+        while let Some(levels) = messages.blocking_recv() {
+            if let Some(point) = seconds.push(&levels) {
+                levels_database.insert_levels(point);
             }
-        })
+        }
     });
+    Ok(Some(Box::new(move |event| database.insert_event(event))))
+}
 
-    // main thread, http server
-    use tiny_http::{Response, Server};
-    let server = Server::http("0.0.0.0:8000").unwrap();
+#[cfg(not(feature = "sqlite"))]
+fn start_level_database(
+    args: &[String],
+    _levels_broadcast: &Broadcast<Levels>,
+    _pending: &PendingEvents,
+    _supervisor: &Supervisor,
+) -> Result<Option<EventSink>, String> {
+    if arg_value(args, "--sqlite").is_some() {
+        return Err("--sqlite requires the 'sqlite' feature".to_owned());
+    }
+    Ok(None)
+}
 
-    for request in server.incoming_requests() {
-        if request.url() == "/info" {
-            if let Some(ref source_data) = *input_buffer_source_data_rwlock.read().unwrap() {
-                let response = Response::from_string(format!(
-                    include_str!("pre-reload.html"),
-                    input_buffer_info(source_data, sample_rate)
-                ))
-                .with_header(
-                    tiny_http::Header::from_bytes(
-                        &b"Content-Type"[..],
-                        &b"text/html; charset=UTF-8"[..],
-                    )
-                    .unwrap(),
-                );
-                request.respond(response).unwrap();
-            } else {
-                let response = Response::empty(tiny_http::StatusCode(204));
-                request.respond(response).unwrap();
+/// command line arg of the Lua script of the levels and the events, run from a blocking thread,
+/// emitting its alarms to the event queue once started and warning once while it fails
+#[cfg(feature = "lua")]
+fn start_script(
+    args: &[String],
+    levels_broadcast: &Broadcast<Levels>,
+    controls: &Controls,
+    event_queue: Arc<OnceLock<EventQueue>>,
+    tui: Arc<OnceLock<TuiSender>>,
+) -> Result<Option<EventSink>, String> {
+    use audio_in_stream_rs::script::{self, Script, ScriptAction};
+    use std::sync::mpsc;
+
+    let path = match arg_value(args, "--script") {
+        Some(path) => PathBuf::from(path),
+        None => return Ok(None),
+    };
+    let mut script = Script::load(&path)?;
+    let controls = controls.clone();
+    let mut messages = levels_broadcast.subscribe();
+    let (event_sender, events) = mpsc::sync_channel::<Event>(script::EVENTS_CAPACITY);
+    let runtime = tokio::runtime::Handle::current();
+    tokio::task::spawn_blocking(move || {
+        let mut last_error = None;
+        loop {
+            let levels = match runtime.block_on(tokio::time::timeout(
+                script::RELOAD_INTERVAL,
+                messages.recv(),
+            )) {
+                Ok(Some(levels)) => Some(levels),
+                Err(_) => None,
+                Ok(None) => break,
             };
-        } else {
-            let response = Response::from_string(format!(
-                "received request!\nmethod: {:?}\nurl: {:?}\nheaders: {:?}",
-                request.method(),
-                request.url(),
-                request.headers()
+            match script.reload_if_modified() {
+                Ok(true) => print_message(tui.get(), format!("script {} reloaded", path.display())),
+                Ok(false) => {}
+                Err(err) => print_message(tui.get(), format!("warning: {}", err)),
+            }
+            let mut results: Vec<_> = events
+                .try_iter()
+                .map(|event| script.on_event(&event))
+                .collect();
+            if let Some(levels) = levels {
+                results.push(script.on_levels(&levels));
+            }
+            for result in results {
+                let actions = match result {
+                    Ok(actions) => actions,
+                    Err(err) => {
+                        if last_error.as_ref() != Some(&err) {
+                            print_message(tui.get(), format!("warning: script error, {}", err));
+                        }
+                        last_error = Some(err);
+                        continue;
+                    }
+                };
+                last_error = None;
+                for action in actions {
+                    match action {
+                        ScriptAction::Alarm(message) => {
+                            if let Some(event_queue) = event_queue.get() {
+                                event_queue.emit(Event::ScriptAlarm {
+                                    timestamp: unix_time(SystemTime::now()),
+                                    message,
+                                });
+                            }
+                        }
+                        ScriptAction::Control(command) => {
+                            if let Err(err) = controls.apply(command) {
+                                print_message(tui.get(), format!("warning: script {}", err));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    });
+    Ok(Some(Box::new(move |event| {
+        event_sender.try_send(event.clone()).ok();
+    })))
+}
+
+#[cfg(not(feature = "lua"))]
+fn start_script(
+    args: &[String],
+    _levels_broadcast: &Broadcast<Levels>,
+    _controls: &Controls,
+    _event_queue: Arc<OnceLock<EventQueue>>,
+    _tui: Arc<OnceLock<TuiSender>>,
+) -> Result<Option<EventSink>, String> {
+    if arg_value(args, "--script").is_some() {
+        return Err("--script requires the 'lua' feature".to_owned());
+    }
+    Ok(None)
+}
+
+/// processing in place of the interleaved samples of a stream of the capture
+type SampleProcess = Box<dyn FnMut(&mut [f32]) + Send>;
+
+/// command line arg of the noise suppression of the streams and the monitor output,
+/// RNNoise only works at 48 kHz
+#[cfg(feature = "rnnoise")]
+fn denoise_arg(args: &[String], sample_rate: u32) -> Result<bool, String> {
+    if !args.iter().any(|arg| arg == "--denoise") {
+        return Ok(false);
+    }
+    if sample_rate != denoise::SAMPLE_RATE {
+        return Err(format!(
+            "--denoise requires a sample rate of {} Hz, not {} Hz",
+            denoise::SAMPLE_RATE,
+            sample_rate
+        ));
+    }
+    Ok(true)
+}
+
+#[cfg(not(feature = "rnnoise"))]
+fn denoise_arg(args: &[String], _sample_rate: u32) -> Result<bool, String> {
+    if args.iter().any(|arg| arg == "--denoise") {
+        return Err("--denoise requires the 'rnnoise' feature".to_owned());
+    }
+    Ok(false)
+}
+
+/// the noise suppression of a stream of the capture while enabled by the controls,
+/// nothing without `--denoise`
+#[cfg(feature = "rnnoise")]
+fn denoise_process(
+    denoise: bool,
+    controls: &Controls,
+    num_channels: usize,
+) -> Result<SampleProcess, String> {
+    if !denoise {
+        return Ok(Box::new(|_| {}));
+    }
+    let mut denoiser = Denoiser::new(num_channels);
+    let controls = controls.clone();
+    Ok(Box::new(move |samples| {
+        denoiser.process(samples, controls.is_denoising())
+    }))
+}
+
+#[cfg(not(feature = "rnnoise"))]
+fn denoise_process(
+    _denoise: bool,
+    _controls: &Controls,
+    _num_channels: usize,
+) -> Result<SampleProcess, String> {
+    Ok(Box::new(|_| {}))
+}
+
+/// parse the command line args of the automatic gain control of the streams and the
+/// monitor output, its `--agc-target` level and `--agc-max-gain`, `None` if not enabled
+fn agc_args(args: &[String]) -> Result<Option<AgcConfig>, String> {
+    let target = arg_value(args, "--agc-target")
+        .map(|target| parse_level(&target))
+        .transpose()?;
+    let max_gain = parse_arg_value::<f32>(args, "--agc-max-gain")?;
+    if let Some(max_gain) = max_gain.filter(|max_gain| !(*max_gain > 0.0 && max_gain.is_finite())) {
+        return Err(format!(
+            "invalid AGC max gain {} dB, it must be above 0 dB",
+            max_gain
+        ));
+    }
+    if !args.iter().any(|arg| arg == "--agc") && target.is_none() && max_gain.is_none() {
+        return Ok(None);
+    }
+    Ok(Some(AgcConfig {
+        target: target.unwrap_or(agc::DEFAULT_TARGET),
+        max_gain: max_gain.unwrap_or(agc::DEFAULT_MAX_GAIN),
+    }))
+}
+
+/// parse the command line args of the noise gate of the recordings and the streams, its
+/// `--gate-threshold`, `--gate-ratio`, `--gate-attack`, `--gate-release` and
+/// `--gate-hysteresis`, `None` if not enabled
+fn gate_args(args: &[String]) -> Result<Option<GateConfig>, String> {
+    let threshold = arg_value(args, "--gate-threshold")
+        .map(|threshold| parse_level(&threshold))
+        .transpose()?;
+    let ratio = parse_arg_value::<f32>(args, "--gate-ratio")?;
+    if let Some(ratio) = ratio.filter(|ratio| ratio.is_nan() || *ratio < 1.0) {
+        return Err(format!(
+            "invalid gate ratio {}, it must be at least 1, or inf for a gate",
+            ratio
+        ));
+    }
+    let duration = |name| {
+        arg_value(args, name)
+            .map(|duration| parse_duration(&duration).map(|duration| duration.as_secs_f32()))
+            .transpose()
+    };
+    let attack = duration("--gate-attack")?;
+    let release = duration("--gate-release")?;
+    let hysteresis = parse_arg_value::<f32>(args, "--gate-hysteresis")?;
+    if let Some(hysteresis) =
+        hysteresis.filter(|hysteresis| !(*hysteresis >= 0.0 && hysteresis.is_finite()))
+    {
+        return Err(format!(
+            "invalid gate hysteresis {} dB, it must be at least 0 dB",
+            hysteresis
+        ));
+    }
+    if !args.iter().any(|arg| arg == "--gate")
+        && threshold.is_none()
+        && ratio.is_none()
+        && attack.is_none()
+        && release.is_none()
+        && hysteresis.is_none()
+    {
+        return Ok(None);
+    }
+    let default = GateConfig::default();
+    Ok(Some(GateConfig {
+        threshold: threshold.unwrap_or(default.threshold),
+        ratio: ratio.unwrap_or(default.ratio),
+        attack: attack.unwrap_or(default.attack),
+        release: release.unwrap_or(default.release),
+        hysteresis: hysteresis.unwrap_or(default.hysteresis),
+    }))
+}
+
+/// parse the command line args of the filter chains, `--filter` of both the metering and
+/// the recordings, the streams and the monitor output, then `--meter-filter` of the
+/// metering only and `--output-filter` of the output only
+fn filter_args(
+    args: &[String],
+    num_channels: usize,
+    sample_rate: u32,
+) -> Result<(FilterChain, FilterChain), String> {
+    let specs = |name| {
+        arg_values(args, "--filter")
+            .into_iter()
+            .chain(arg_values(args, name))
+            .map(|filter| FilterSpec::parse(&filter))
+            .collect::<Result<Vec<_>, _>>()
+    };
+    Ok((
+        FilterChain::new(&specs("--meter-filter")?, num_channels, sample_rate)?,
+        FilterChain::new(&specs("--output-filter")?, num_channels, sample_rate)?,
+    ))
+}
+
+/// the processors of a stream of the capture, the filters of the output then the LV2 plugins
+fn output_processors(
+    args: &[String],
+    filter: &FilterChain,
+    num_channels: usize,
+    sample_rate: u32,
+) -> Result<ProcessorChain, String> {
+    let mut processors = ProcessorChain::new();
+    if !filter.is_empty() {
+        processors.register(Box::new(filter.clone()))?;
+    }
+    for plugin in lv2_plugins(args, num_channels, sample_rate)? {
+        processors.register(plugin)?;
+    }
+    Ok(processors)
+}
+
+/// command line args of the LV2 plugins of the streams and the monitor output, in order
+#[cfg(feature = "lv2")]
+fn lv2_plugins(
+    args: &[String],
+    num_channels: usize,
+    sample_rate: u32,
+) -> Result<Vec<Box<dyn Processor>>, String> {
+    use audio_in_stream_rs::lv2::{Lv2Plugin, Lv2Spec};
+
+    arg_values(args, "--lv2")
+        .iter()
+        .map(|spec| {
+            let plugin = Lv2Plugin::new(&Lv2Spec::parse(spec)?, num_channels, sample_rate)?;
+            Ok(Box::new(plugin) as Box<dyn Processor>)
+        })
+        .collect()
+}
+
+#[cfg(not(feature = "lv2"))]
+fn lv2_plugins(
+    args: &[String],
+    _num_channels: usize,
+    _sample_rate: u32,
+) -> Result<Vec<Box<dyn Processor>>, String> {
+    if !arg_values(args, "--lv2").is_empty() {
+        return Err("--lv2 requires the 'lv2' feature".to_owned());
+    }
+    Ok(Vec::new())
+}
+
+/// the processing of a stream of the capture, listened to rather than metered:
+/// the filters and the LV2 plugins, the noise suppression, the noise gate then the automatic
+/// gain control
+fn stream_process(
+    mut processors: ProcessorChain,
+    denoise: bool,
+    gate_config: Option<GateConfig>,
+    agc_config: Option<AgcConfig>,
+    controls: &Controls,
+    num_channels: usize,
+    sample_rate: u32,
+) -> Result<SampleProcess, String> {
+    let mut denoise_process = denoise_process(denoise, controls, num_channels)?;
+    let mut gate = gate_config.map(|config| NoiseGate::new(config, num_channels, sample_rate));
+    let mut agc =
+        agc_config.map(|config| AutomaticGainControl::new(config, num_channels, sample_rate));
+    Ok(Box::new(move |samples| {
+        processors.process_interleaved(samples, num_channels);
+        denoise_process(samples);
+        if let Some(ref mut gate) = gate {
+            gate.process(samples);
+        }
+        if let Some(ref mut agc) = agc {
+            agc.process(samples);
+        }
+    }))
+}
+
+fn parse_sample_format(format: &str) -> Result<cpal::SampleFormat, String> {
+    match format.to_ascii_lowercase().as_str() {
+        "u16" => Ok(cpal::SampleFormat::U16),
+        "i16" => Ok(cpal::SampleFormat::I16),
+        "i32" => Ok(cpal::SampleFormat::I32),
+        "f32" => Ok(cpal::SampleFormat::F32),
+        _ => Err(format!(
+            "invalid sample format '{}', expected one of: u16, i16, i32, f32",
+            format
+        )),
+    }
+}
+
+/// parse the value of a command line option, if present
+fn parse_arg_value<T: std::str::FromStr>(args: &[String], name: &str) -> Result<Option<T>, String> {
+    match arg_value(args, name) {
+        Some(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|_| format!("invalid value '{}' for {}", value, name)),
+        None => Ok(None),
+    }
+}
+
+/// sample format requested in the command line args,
+/// by default assume CD Audio sample format (but with floating point samples)
+fn requested_input_config(args: &[String]) -> Result<cpal::SupportedStreamConfig, String> {
+    Ok(cpal::SupportedStreamConfig::new(
+        parse_arg_value(args, "--channels")?.unwrap_or(2),
+        cpal::SampleRate(parse_arg_value(args, "--rate")?.unwrap_or(44100)),
+        cpal::SupportedBufferSize::Unknown,
+        match arg_value(args, "--format") {
+            Some(format) => parse_sample_format(&format)?,
+            None => cpal::SampleFormat::F32,
+        },
+    ))
+}
+
+/// the source of the samples, an input device, an RTP stream, a file, a JACK client
+/// or a PipeWire node
+enum Input {
+    Device(InputMonitor),
+    Receiver(RtpReceiver),
+    File(FileInput),
+    #[cfg(feature = "jack")]
+    Jack(JackInput),
+    #[cfg(feature = "pipewire")]
+    PipeWire(PipeWireInput),
+}
+
+impl Input {
+    fn config(&self) -> cpal::SupportedStreamConfig {
+        match self {
+            Input::Device(monitor) => monitor.config().clone(),
+            Input::Receiver(receiver) => cpal::SupportedStreamConfig::new(
+                receiver.channels(),
+                cpal::SampleRate(receiver.sample_rate()),
+                cpal::SupportedBufferSize::Unknown,
+                receiver.sample_format(),
+            ),
+            Input::File(file_input) => cpal::SupportedStreamConfig::new(
+                file_input.channels(),
+                cpal::SampleRate(file_input.sample_rate()),
+                cpal::SupportedBufferSize::Unknown,
+                file_input.sample_format(),
+            ),
+            #[cfg(feature = "jack")]
+            Input::Jack(jack_input) => cpal::SupportedStreamConfig::new(
+                jack_input.channels(),
+                cpal::SampleRate(jack_input.sample_rate()),
+                cpal::SupportedBufferSize::Unknown,
+                cpal::SampleFormat::F32,
+            ),
+            #[cfg(feature = "pipewire")]
+            Input::PipeWire(pipewire_input) => cpal::SupportedStreamConfig::new(
+                pipewire_input.channels(),
+                cpal::SampleRate(pipewire_input.sample_rate()),
+                cpal::SupportedBufferSize::Unknown,
+                cpal::SampleFormat::F32,
+            ),
+        }
+    }
+
+    fn name(&self) -> String {
+        match self {
+            Input::Device(monitor) => monitor
+                .device()
+                .name()
+                .unwrap_or_else(|_| String::from("<failed to get device name>")),
+            Input::Receiver(receiver) => receiver.name(),
+            Input::File(file_input) => file_input.name(),
+            #[cfg(feature = "jack")]
+            Input::Jack(jack_input) => jack_input.name(),
+            #[cfg(feature = "pipewire")]
+            Input::PipeWire(pipewire_input) => pipewire_input.name(),
+        }
+    }
+}
+
+/// command line args of the source of the samples other than an input device, if any
+fn source_input_args(args: &[String]) -> Result<Option<Input>, String> {
+    if let Some(file_input) = file_input_args(args)? {
+        return Ok(Some(Input::File(file_input)));
+    }
+    if let Some(receiver) = rtp_receiver_args(args)? {
+        return Ok(Some(Input::Receiver(receiver)));
+    }
+    if let Some(input) = jack_input_args(args)? {
+        return Ok(Some(input));
+    }
+    pipewire_input_args(args)
+}
+
+/// command line args of the WAV file, or of the standard input, read instead of capturing
+/// from a device, raw PCM when its `--input-format` is given
+fn file_input_args(args: &[String]) -> Result<Option<FileInput>, String> {
+    let format = arg_value(args, "--input-format")
+        .map(|format| RawFormat::parse(&format))
+        .transpose()?;
+    let path = arg_value(args, "--input-file");
+    let stdin = args.iter().any(|arg| arg == "--input-stdin");
+    if (path.is_some() || stdin) && arg_value(args, "--receive").is_some() {
+        return Err(String::from(
+            "--receive can not be used with --input-file or --input-stdin",
+        ));
+    }
+    match (path, stdin) {
+        (Some(_), true) => Err(String::from(
+            "--input-file can not be used with --input-stdin",
+        )),
+        (Some(path), false) => FileInput::open(&path, format).map(Some),
+        (None, true) => FileInput::stdin(format).map(Some),
+        (None, false) if format.is_some() => Err(String::from(
+            "--input-format requires --input-file or --input-stdin",
+        )),
+        (None, false) => Ok(None),
+    }
+}
+
+/// command line args of the RTP stream received instead of capturing from a device,
+/// of `--channels` at `--rate`, 48 kHz by default for Opus
+fn rtp_receiver_args(args: &[String]) -> Result<Option<RtpReceiver>, String> {
+    let address = match arg_value(args, "--receive") {
+        Some(address) => address,
+        None => return Ok(None),
+    };
+    let format = match arg_value(args, "--receive-format") {
+        Some(format) => RtpFormat::parse(&format)?,
+        None => RtpFormat::L24,
+    };
+    let channels = parse_arg_value(args, "--channels")?.unwrap_or(2);
+    if channels == 0 {
+        return Err(String::from("invalid value '0' for --channels"));
+    }
+    let default_rate = match format {
+        RtpFormat::Opus => 48000,
+        RtpFormat::L16 | RtpFormat::L24 => 44100,
+    };
+    let sample_rate = parse_arg_value(args, "--rate")?.unwrap_or(default_rate);
+    if sample_rate == 0 {
+        return Err(String::from("invalid value '0' for --rate"));
+    }
+    RtpReceiver::bind(&address, format, channels, sample_rate).map(Some)
+}
+
+/// command line args of the JACK client read instead of capturing from a device,
+/// with `--channels` input ports, left unconnected for a patchbay
+/// unless connected to the `--jack-connect` ports
+#[cfg(feature = "jack")]
+fn jack_input_args(args: &[String]) -> Result<Option<Input>, String> {
+    if !args.iter().any(|arg| arg == "--jack") {
+        return Ok(None);
+    }
+    let channels = parse_arg_value(args, "--channels")?.unwrap_or(2);
+    if channels == 0 {
+        return Err(String::from("invalid value '0' for --channels"));
+    }
+    let client_name = arg_value(args, "--jack-name")
+        .unwrap_or_else(|| String::from(jack_input::DEFAULT_CLIENT_NAME));
+    let connect = arg_value(args, "--jack-connect")
+        .map(|ports| {
+            ports
+                .split(',')
+                .map(|port| port.trim().to_string())
+                .collect()
+        })
+        .unwrap_or_default();
+    JackInput::new(&client_name, channels, connect).map(|jack_input| Some(Input::Jack(jack_input)))
+}
+
+#[cfg(not(feature = "jack"))]
+fn jack_input_args(args: &[String]) -> Result<Option<Input>, String> {
+    if args.iter().any(|arg| arg == "--jack") {
+        return Err("--jack requires the 'jack' feature".to_owned());
+    }
+    Ok(None)
+}
+
+/// command line args of the PipeWire node read instead of capturing from a device,
+/// of `--channels` at `--rate`, 48 kHz by default, linked to the default source
+/// or to the `--pipewire-target` node
+#[cfg(feature = "pipewire")]
+fn pipewire_input_args(args: &[String]) -> Result<Option<Input>, String> {
+    if !args.iter().any(|arg| arg == "--pipewire") {
+        return Ok(None);
+    }
+    let channels = parse_arg_value(args, "--channels")?.unwrap_or(2);
+    if channels == 0 {
+        return Err(String::from("invalid value '0' for --channels"));
+    }
+    let sample_rate =
+        parse_arg_value(args, "--rate")?.unwrap_or(pipewire_input::DEFAULT_SAMPLE_RATE);
+    if sample_rate == 0 {
+        return Err(String::from("invalid value '0' for --rate"));
+    }
+    let latency =
+        parse_arg_value(args, "--pipewire-latency")?.unwrap_or(pipewire_input::DEFAULT_LATENCY);
+    if latency == 0 {
+        return Err(String::from("invalid value '0' for --pipewire-latency"));
+    }
+    let node_name = arg_value(args, "--pipewire-name")
+        .unwrap_or_else(|| String::from(pipewire_input::DEFAULT_NODE_NAME));
+    Ok(Some(Input::PipeWire(PipeWireInput::new(
+        &node_name,
+        arg_value(args, "--pipewire-target"),
+        channels,
+        sample_rate,
+        latency,
+    ))))
+}
+
+#[cfg(not(feature = "pipewire"))]
+fn pipewire_input_args(args: &[String]) -> Result<Option<Input>, String> {
+    if args.iter().any(|arg| arg == "--pipewire") {
+        return Err("--pipewire requires the 'pipewire' feature".to_owned());
+    }
+    Ok(None)
+}
+
+/// command line args of the output device playing the live capture, by name or index
+/// in the `--host`, or `default`, with its `--monitor-gain` in dB and `--monitor-delay`
+fn monitor_output_args(args: &[String]) -> Result<Option<(cpal::Device, f32, f64)>, Error> {
+    let device = match arg_value(args, "--monitor-output") {
+        Some(device) => device,
+        None => return Ok(None),
+    };
+    let gain = parse_arg_value(args, "--monitor-gain")?.unwrap_or(0.0);
+    let delay = arg_value(args, "--monitor-delay")
+        .map(|delay| parse_duration(&delay))
+        .transpose()?
+        .map_or(0.0, |delay| delay.as_secs_f64());
+    let host = select_host(arg_value(args, "--host").as_deref())?;
+    let device = select_output_device(
+        &host,
+        Some(device.as_str()).filter(|&device| device != "default"),
+    )?;
+    Ok(Some((device, gain, delay)))
+}
+
+/// command line args of the test signal to play, and its `--generate-output` device
+/// by name or index in the `--host`, the default output device when not given
+fn generator_args(args: &[String]) -> Result<Option<(cpal::Device, Signal)>, Error> {
+    let signal = match arg_value(args, "--generate") {
+        Some(signal) => Signal::parse(&signal)?,
+        None => return Ok(None),
+    };
+    let host = select_host(arg_value(args, "--host").as_deref())?;
+    let device = select_output_device(
+        &host,
+        arg_value(args, "--generate-output")
+            .as_deref()
+            .filter(|&device| device != "default"),
+    )?;
+    Ok(Some((device, signal)))
+}
+
+/// command line args of the round-trip latency measurement: its `--latency-output`
+/// device by name or index in the `--host`, the default output device when not given,
+/// the `--latency-trials` and the `--latency-interval` between the chirps
+fn latency_args(args: &[String]) -> Result<Option<(cpal::Device, usize, f64)>, Error> {
+    if !args.iter().any(|arg| arg == "--measure-latency") {
+        return Ok(None);
+    }
+    let trials = parse_arg_value::<usize>(args, "--latency-trials")?.unwrap_or(10);
+    if trials == 0 {
+        return Err(Error::Setup(String::from(
+            "invalid latency trials 0, it must be at least 1",
+        )));
+    }
+    let interval = match arg_value(args, "--latency-interval") {
+        Some(interval) => match parse_duration(&interval)?.as_secs_f64() {
+            interval if interval >= 0.1 => interval,
+            _ => {
+                return Err(Error::Setup(format!(
+                    "invalid latency interval '{}', it must be at least 0.1 s",
+                    interval
+                )))
+            }
+        },
+        None => 1.0,
+    };
+    let host = select_host(arg_value(args, "--host").as_deref())?;
+    let device = select_output_device(
+        &host,
+        arg_value(args, "--latency-output")
+            .as_deref()
+            .filter(|&device| device != "default"),
+    )?;
+    Ok(Some((device, trials, interval)))
+}
+
+/// command line args of the mel spectrogram and MFCC frames: the `--mel-frame` and
+/// `--mel-hop` sizes in samples, the `--mel-bands` from `--mel-fmin` to `--mel-fmax`,
+/// and the `--mfcc` coefficients, `None` if not enabled with `--features` or the files
+/// of `--mel-npy` or `--mfcc-npy`
+fn features_args(args: &[String], sample_rate: u32) -> Result<Option<FeatureConfig>, String> {
+    let mfcc_npy = arg_value(args, "--mfcc-npy").is_some();
+    if !args.iter().any(|arg| arg == "--features")
+        && arg_value(args, "--mel-npy").is_none()
+        && !mfcc_npy
+    {
+        return Ok(None);
+    }
+    let mut config = FeatureConfig::new(sample_rate);
+    if let Some(frame_size) = parse_arg_value::<usize>(args, "--mel-frame")? {
+        config.frame_size = frame_size;
+    }
+    if let Some(hop_size) = parse_arg_value::<usize>(args, "--mel-hop")? {
+        config.hop_size = hop_size;
+    }
+    if config.frame_size < MIN_FFT_SIZE
+        || config.hop_size == 0
+        || config.hop_size > config.frame_size
+    {
+        return Err(format!(
+            "invalid mel frame of {} samples and hop of {}, the frame must be at least {} samples and the hop at most the frame",
+            config.frame_size, config.hop_size, MIN_FFT_SIZE
+        ));
+    }
+    match parse_arg_value::<usize>(args, "--mel-bands")? {
+        Some(0) => return Err(String::from("invalid value '0' for --mel-bands")),
+        Some(mel_bands) => config.mel_bands = mel_bands,
+        None => {}
+    }
+    if let Some(min_frequency) = arg_value(args, "--mel-fmin") {
+        config.min_frequency = parse_frequency(&min_frequency)? as f32;
+    }
+    if let Some(max_frequency) = arg_value(args, "--mel-fmax") {
+        config.max_frequency = parse_frequency(&max_frequency)? as f32;
+    }
+    if config.min_frequency >= config.max_frequency
+        || config.max_frequency > sample_rate as f32 / 2.0
+    {
+        return Err(format!(
+            "invalid mel frequencies from {} Hz to {} Hz, up to the Nyquist frequency of {} Hz",
+            config.min_frequency,
+            config.max_frequency,
+            sample_rate / 2
+        ));
+    }
+    if let Some(mfcc) = parse_arg_value::<usize>(args, "--mfcc")? {
+        if mfcc > config.mel_bands {
+            return Err(format!(
+                "invalid MFCC count {}, at most the {} mel bands",
+                mfcc, config.mel_bands
             ));
-            request.respond(response).unwrap();
+        }
+        config.mfcc = mfcc;
+    }
+    if mfcc_npy && config.mfcc == 0 {
+        return Err(String::from("--mfcc-npy can not be used with --mfcc 0"));
+    }
+    Ok(Some(config))
+}
+
+/// validate the requested config against the supported input configs of the device,
+/// falling back to the nearest supported config with a warning
+fn select_input_config(
+    dev: &cpal::Device,
+    requested: cpal::SupportedStreamConfig,
+) -> cpal::SupportedStreamConfig {
+    match nearest_input_config(dev, &requested) {
+        Ok(config) => {
+            if (
+                config.channels(),
+                config.sample_rate(),
+                config.sample_format(),
+            ) != (
+                requested.channels(),
+                requested.sample_rate(),
+                requested.sample_format(),
+            ) {
+                eprintln!(
+                    "warning: unsupported input format {} channel(s), {} Hz, {:?}, using {} channel(s), {} Hz, {:?}",
+                    requested.channels(),
+                    requested.sample_rate().0,
+                    requested.sample_format(),
+                    config.channels(),
+                    config.sample_rate().0,
+                    config.sample_format()
+                );
+            }
+            config
+        }
+        Err(err) => {
+            eprintln!("warning: {}, trying the requested format", err);
+            requested
+        }
+    }
+}
+
+/// the characters of a level meter, in the ANSI color of their zone if enabled
+fn meter_bar(cells: &[MeterCell], color: bool) -> String {
+    let mut bar = String::new();
+    let mut current_color = None;
+    for &cell in cells {
+        let (character, cell_color) = match cell {
+            MeterCell::Filled(character, zone) | MeterCell::Peak(character, zone) => (
+                character,
+                Some(match zone {
+                    Zone::Green => "\x1b[32m",
+                    Zone::Yellow => "\x1b[33m",
+                    Zone::Red => "\x1b[31m",
+                }),
+            ),
+            MeterCell::Empty(character) => (character, None),
         };
+        if color && cell_color != current_color {
+            bar.push_str(cell_color.unwrap_or("\x1b[0m"));
+            current_color = cell_color;
+        }
+        bar.push(character);
+    }
+    if current_color.is_some() {
+        bar.push_str("\x1b[0m");
+    }
+    bar
+}
+
+/// one line with the input buffer and the loudness, and one line per metered channel,
+/// with the levels in dB SPL too once calibrated
+fn input_buffer_info(
+    source_data: &InputBufferSourceData,
+    sample_rate: u32,
+    levels: &Levels,
+    meter_readings: &[MeterReading],
+    calibration_offset: Option<f32>,
+    meter_scale: &MeterScale,
+    color: bool,
+) -> Vec<String> {
+    let loudness = &levels.loudness;
+    let num_frames = source_data.num_samples / source_data.num_channels;
+    let mut lines = vec![format!(
+        "input buffer: {:>6} {:#?} samples * {} channel(s), {:>7.3} ms, M: {:>+5.1} S: {:>+5.1} I: {:>+5.1} LUFS",
+        num_frames,
+        source_data.sample_format,
+        source_data.num_channels,
+        1000.0 * num_frames as f32 / sample_rate as f32,
+        loudness.momentary,
+        loudness.short_term,
+        loudness.integrated
+    )];
+
+    for (channel, meter_reading) in levels.channels.iter().zip(meter_readings) {
+        let level_decibels_overload = decibels_overload(meter_reading.level);
+        let peak_decibels_overload = decibels_overload(meter_reading.peak);
+        lines.push(format!(
+            "channel {:>2}: [{}] {:>+5.1} {:<4} {}{:>+5.1} dBTP {} {:>4} clips {}",
+            channel.channel,
+            // using 16 chars in the default horizontal scale of ~96 dB
+            // make each char position an indication of a 1 bit
+            // or ~6 dB, equivalent of factor of change in value relative
+            // to the previous/next char position of 0.5,
+            // and each eighth of a char of ~0.75 dB
+            meter_bar(
+                &meter_scale.cells(
+                    level_decibels_overload,
+                    peak_decibels_overload,
+                    meter_scale.width
+                ),
+                color
+            ),
+            meter_scale.convert(level_decibels_overload),
+            meter_scale.unit.name(),
+            calibration_offset.map_or(String::new(), |offset| format!(
+                "{:>5.1} dB SPL ",
+                level_decibels_overload + offset
+            )),
+            peak_decibels_overload,
+            if meter_reading.over { "OVER" } else { "    " },
+            channel.clipping.count,
+            if channel.dc_offset_warning {
+                format!(
+                    "DC {:>+5.1} dBFS",
+                    decibels_overload(channel.dc_offset.abs())
+                )
+            } else {
+                String::new()
+            },
+        ));
+    }
+
+    lines
+}
+
+/// one line per metered channel, given by its index in the input stream,
+/// with the spectrum in bands of logarithmic width, from 20 Hz to the Nyquist frequency
+fn spectrum_info(spectrum: &Spectrum, channels_map: &[usize], sample_rate: u32) -> Vec<String> {
+    const NUM_BANDS: usize = 64;
+    const MIN_FREQUENCY: f32 = 20.0;
+    const LEVEL_CHARS: [char; 9] = [' ', '▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+    let max_frequency = sample_rate as f32 / 2.0;
+    let band_ratio = (max_frequency / MIN_FREQUENCY).powf(1.0 / NUM_BANDS as f32);
+    let bottom_level = -quantization_noise_ratio(16);
+
+    spectrum
+        .channels
+        .iter()
+        .zip(channels_map)
+        .map(|(magnitudes, channel_index)| {
+            let bands: String = (0..NUM_BANDS)
+                .map(|band| {
+                    let low_frequency = MIN_FREQUENCY * band_ratio.powi(band as i32);
+                    let low_bin = (low_frequency / spectrum.bin_width).round() as usize;
+                    let high_bin = ((low_frequency * band_ratio / spectrum.bin_width).round()
+                        as usize)
+                        .clamp(low_bin + 1, magnitudes.len());
+                    // the loudest bin of the band, bands narrower than a bin share it
+                    let magnitude = magnitudes[low_bin.min(magnitudes.len() - 1)..high_bin]
+                        .iter()
+                        .cloned()
+                        .fold(f32::NEG_INFINITY, f32::max);
+                    let level = clamp(1.0 - magnitude / bottom_level, 0.0, 1.0);
+                    LEVEL_CHARS[(level * (LEVEL_CHARS.len() - 1) as f32).round() as usize]
+                })
+                .collect();
+            format!(
+                "channel {:>2} spectrum: {:>5.0} Hz [{}] {:>5.0} Hz",
+                channel_index, MIN_FREQUENCY, bands, max_frequency
+            )
+        })
+        .collect()
+}
+
+/// one line per metered channel, given by its index in the input stream,
+/// with the level of each third octave band from 20 Hz to 20 kHz
+fn octave_bands_info(octave_bands: &OctaveBands, channels_map: &[usize]) -> Vec<String> {
+    const LEVEL_CHARS: [char; 9] = [' ', '▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let bottom_level = -quantization_noise_ratio(16);
+
+    octave_bands
+        .channels
+        .iter()
+        .zip(channels_map)
+        .map(|(bands, channel_index)| {
+            let bands: String = bands
+                .iter()
+                .map(|&band| {
+                    let level = clamp(1.0 - band / bottom_level, 0.0, 1.0);
+                    LEVEL_CHARS[(level * (LEVEL_CHARS.len() - 1) as f32).round() as usize]
+                })
+                .collect();
+            format!(
+                "channel {:>2} 1/3 octave: {:>5.0} Hz [{}] {:>5.0} Hz",
+                channel_index,
+                octave_bands.frequencies[0],
+                bands,
+                octave_bands.frequencies[octave_bands.frequencies.len() - 1]
+            )
+        })
+        .collect()
+}
+
+/// one line per metered channel with its Leq statistics
+fn leq_info(levels: &Levels) -> Vec<String> {
+    let level =
+        |level: Option<f32>| level.map_or(String::from(" -inf"), |level| format!("{:>5.1}", level));
+    levels
+        .channels
+        .iter()
+        .filter_map(|channel| {
+            let channel_leq = channel.leq.as_ref()?;
+            let windows: Vec<String> = channel_leq
+                .leq
+                .iter()
+                .map(|window_leq| format!("{} s {}", window_leq.window, level(window_leq.level)))
+                .collect();
+            Some(format!(
+                "channel {:>2} Leq: {}, L10 {} L50 {} L90 {} {}",
+                channel.channel,
+                windows.join(", "),
+                level(channel_leq.l10),
+                level(channel_leq.l50),
+                level(channel_leq.l90),
+                channel_leq.unit
+            ))
+        })
+        .collect()
+}
+
+/// the channels of a wiring fault with the fault, e.g. `channels 0 and 1 are dual mono`
+fn fault_description(kind: FaultKind, channels: &[usize]) -> String {
+    let names: Vec<String> = channels.iter().map(usize::to_string).collect();
+    if names.len() > 1 {
+        format!(
+            "channels {} are {}",
+            names.join(" and "),
+            kind.description()
+        )
+    } else {
+        format!("channel {} is {}", names.join(""), kind.description())
     }
+}
+
+/// one line with a wiring fault of the channels
+fn fault_info(fault: &ChannelFault) -> String {
+    format!("FAULT: {}", fault_description(fault.kind, &fault.channels))
+}
+
+/// one line per stereo pair with its phase correlation, from -1 to +1
+fn correlation_info(correlation: &[PairCorrelation]) -> Vec<String> {
+    correlation
+        .iter()
+        .map(|pair| match pair.correlation {
+            Some(correlation) => format!(
+                "channels {:>2}-{:<2} correlation: -1 [{}] +1 {:>+5.2} {}",
+                pair.left,
+                pair.right,
+                horizontal_scale(0.0, (1.0 + correlation) / 2.0, 16),
+                correlation,
+                if pair.alarm_since.is_some() {
+                    "OUT OF PHASE"
+                } else {
+                    ""
+                }
+            ),
+            None => format!(
+                "channels {:>2}-{:<2} correlation: -1 [{}] +1",
+                pair.left,
+                pair.right,
+                horizontal_scale(0.0, 0.0, 16)
+            ),
+        })
+        .collect()
+}
+
+/// one line per stereo pair with its mid and side levels
+fn mid_side_info(mid_side: &[PairMidSide]) -> Vec<String> {
+    mid_side
+        .iter()
+        .map(|pair| {
+            format!(
+                "channels {:>2}-{:<2} mid/side: M [{}] {:>+5.1} dBov S [{}] {:>+5.1} dBov, S/M {:>+5.1} dB",
+                pair.left,
+                pair.right,
+                horizontal_scale(1.0 + pair.mid / quantization_noise_ratio(16), 0.0, 16),
+                pair.mid,
+                horizontal_scale(1.0 + pair.side / quantization_noise_ratio(16), 0.0, 16),
+                pair.side,
+                pair.side_to_mid
+            )
+        })
+        .collect()
+}
+
+/// the test tone measurements of each metered channel, with its frequency response
+/// once measured at more than one frequency
+fn analysis_info(analysis: &[ToneAnalysis]) -> Vec<String> {
+    let mut lines = Vec::new();
+    for channel in analysis {
+        lines.push(format!(
+            "channel {:>2} analyzer: {:>7.1} Hz {:>+6.1} dBFS, THD+N {:>+6.1} dB ({:.4} %), THD {:>+6.1} dB, SNR {:>5.1} dB",
+            channel.channel,
+            channel.frequency,
+            channel.level,
+            channel.thd_n,
+            channel.thd_n_percent,
+            channel.thd,
+            channel.snr
+        ));
+        if channel.frequency_response.len() > 1 {
+            let points: Vec<String> = channel
+                .frequency_response
+                .iter()
+                .map(|point| format!("{:.0} Hz {:+.1}", point.frequency, point.level))
+                .collect();
+            lines.push(format!(
+                "channel {:>2} response (dBFS): {}",
+                channel.channel,
+                points.join(", ")
+            ));
+        }
+    }
+    lines
+}
+
+/// the pitch of each metered channel, with its note and cents
+fn pitch_info(pitch: &[ChannelPitch]) -> Vec<String> {
+    pitch
+        .iter()
+        .map(
+            |channel| match (channel.frequency, &channel.note, channel.cents) {
+                (Some(frequency), Some(note), Some(cents)) => format!(
+                    "channel {:>2} pitch: {:>7.1} Hz {:<3} {:>+3.0} cents",
+                    channel.channel, frequency, note, cents
+                ),
+                _ => format!("channel {:>2} pitch: -", channel.channel),
+            },
+        )
+        .collect()
+}
+
+/// the voice activity of each metered channel, with its speech percentages
+fn speech_info(levels: &Levels) -> Vec<String> {
+    levels
+        .channels
+        .iter()
+        .filter_map(|channel| {
+            let speech = channel.speech.as_ref()?;
+            Some(format!(
+                "channel {:>2} speech: {:>5.1}% since the start, {:>5.1}% in the last minute{}",
+                channel.channel,
+                speech.speech_percent,
+                speech.recent_speech_percent,
+                if speech.speech { ", talking" } else { "" }
+            ))
+        })
+        .collect()
+}
+
+/// parse the command line args of the voice activity detection, its aggressiveness
+/// with `--vad-aggressiveness`, `None` if not enabled
+fn vad_args(
+    args: &[String],
+    sample_rate: u32,
+    num_channels: usize,
+) -> Result<Option<VoiceActivityDetector>, String> {
+    let aggressiveness = parse_arg_value::<u8>(args, "--vad-aggressiveness")?;
+    if let Some(aggressiveness) = aggressiveness.filter(|&value| value > vad::MAX_AGGRESSIVENESS) {
+        return Err(format!(
+            "invalid VAD aggressiveness {}, it must be from 0 to {}",
+            aggressiveness,
+            vad::MAX_AGGRESSIVENESS
+        ));
+    }
+    if !args.iter().any(|arg| arg == "--vad") && aggressiveness.is_none() {
+        return Ok(None);
+    }
+    Ok(Some(VoiceActivityDetector::new(
+        sample_rate,
+        num_channels,
+        aggressiveness.unwrap_or(vad::DEFAULT_AGGRESSIVENESS),
+    )))
+}
+
+/// parse the command line args of the pitch detection, the frequency of A4 with
+/// `--pitch-reference`, `None` if not enabled
+fn pitch_detector_args(
+    args: &[String],
+    sample_rate: u32,
+    num_channels: usize,
+) -> Result<Option<PitchDetector>, String> {
+    let reference = arg_value(args, "--pitch-reference")
+        .map(|reference| parse_frequency(&reference))
+        .transpose()?;
+    if !args.iter().any(|arg| arg == "--pitch") && reference.is_none() {
+        return Ok(None);
+    }
+    Ok(Some(PitchDetector::new(
+        sample_rate,
+        num_channels,
+        reference.map_or(pitch::DEFAULT_REFERENCE, |reference| reference as f32),
+    )))
+}
+
+/// parse the command line args of the audio analyzer, the frequency of the test tone
+/// with `--analyze-frequency`, otherwise the strongest, `None` if not enabled
+fn tone_analyzer_args(
+    args: &[String],
+    sample_rate: u32,
+    num_channels: usize,
+) -> Result<Option<ToneAnalyzer>, String> {
+    let expected_frequency = arg_value(args, "--analyze-frequency")
+        .map(|frequency| parse_frequency(&frequency))
+        .transpose()?;
+    if !args.iter().any(|arg| arg == "--analyze") && expected_frequency.is_none() {
+        return Ok(None);
+    }
+    Ok(Some(ToneAnalyzer::new(
+        sample_rate,
+        num_channels,
+        expected_frequency.map(|frequency| frequency as f32),
+    )))
+}
+
+/// parse the command line args of the Leq statistics, the windows in seconds and the log file,
+/// `None` if not enabled
+fn leq_args(
+    args: &[String],
+    sample_rate: u32,
+    num_channels: usize,
+) -> Result<Option<(LeqMeter, Option<LeqLog>)>, String> {
+    let windows = arg_values(args, "--leq-window");
+    let log_path = arg_value(args, "--leq-log");
+    if !args.iter().any(|arg| arg == "--leq") && windows.is_empty() && log_path.is_none() {
+        return Ok(None);
+    }
+
+    let windows = if windows.is_empty() {
+        leq::DEFAULT_WINDOWS.to_vec()
+    } else {
+        windows
+            .iter()
+            .map(|window| match window.parse::<u32>() {
+                Ok(window) if window > 0 => Ok(window),
+                _ => Err(format!(
+                    "invalid Leq window '{}', expected a number of seconds",
+                    window
+                )),
+            })
+            .collect::<Result<_, _>>()?
+    };
+    let log = match log_path {
+        Some(path) => {
+            Some(LeqLog::open(&path).map_err(|err| format!("failed to open '{}': {}", path, err))?)
+        }
+        None => None,
+    };
+    Ok(Some((
+        LeqMeter::new(windows, sample_rate, num_channels),
+        log,
+    )))
+}
+
+/// command line args of the log of the levels of each interval to a CSV or JSON lines file,
+/// `None` if not enabled
+fn level_log_args(args: &[String]) -> Result<Option<LevelLog>, String> {
+    let parse_positive_duration = |name: &str| match arg_value(args, name) {
+        Some(duration) => match parse_duration(&duration)?.as_secs_f64() {
+            seconds if seconds > 0.0 => Ok(Some(seconds)),
+            _ => Err(format!("invalid {} duration '{}'", name, duration)),
+        },
+        None => Ok(None),
+    };
+    let interval = parse_positive_duration("--log-interval")?;
+    let rotation = Rotation {
+        max_bytes: arg_value(args, "--log-rotate-size")
+            .map(|size| parse_size(&size))
+            .transpose()?,
+        interval: parse_positive_duration("--log-rotate-interval")?,
+    };
+    let path = match arg_value(args, "--log-levels") {
+        Some(path) => PathBuf::from(path),
+        None if interval.is_some()
+            || rotation.max_bytes.is_some()
+            || rotation.interval.is_some() =>
+        {
+            return Err(String::from(
+                "--log-interval and --log-rotate-* require --log-levels",
+            ))
+        }
+        None => return Ok(None),
+    };
+    LevelLog::open(
+        &path,
+        interval.unwrap_or(level_log::DEFAULT_INTERVAL),
+        rotation,
+    )
+    .map(Some)
+}
+
+/// command line args of the InfluxDB and StatsD outputs the metrics are pushed to,
+/// with the InfluxDB token from the environment not to show it in the process list,
+/// and the interval between pushes in seconds
+fn metric_push_args(args: &[String]) -> Result<(MetricPush, f64), String> {
+    let token = arg_value(args, "--influx-token")
+        .or_else(|| std::env::var("AUDIO_IN_STREAM_INFLUX_TOKEN").ok());
+    let metric_push = MetricPush {
+        influx: arg_value(args, "--influx-url")
+            .map(|url| InfluxWriter::new(&url, token))
+            .transpose()?,
+        statsd: arg_value(args, "--statsd-addr")
+            .map(|address| StatsdClient::new(&address))
+            .transpose()?,
+    };
+    let interval = match arg_value(args, "--metrics-interval") {
+        Some(interval) => match parse_duration(&interval)?.as_secs_f64() {
+            seconds if seconds > 0.0 => seconds,
+            _ => return Err(format!("invalid metrics interval '{}'", interval)),
+        },
+        None => metric_push::DEFAULT_INTERVAL,
+    };
+    Ok((metric_push, interval))
+}
+
+/// command line arg of the age of the last input buffer above which `/healthz` fails
+fn health_args(args: &[String]) -> Result<Duration, String> {
+    match arg_value(args, "--health-max-buffer-age") {
+        Some(age) => match parse_duration(&age)? {
+            max_buffer_age if max_buffer_age > Duration::ZERO => Ok(max_buffer_age),
+            _ => Err(format!("invalid maximum buffer age '{}'", age)),
+        },
+        None => Ok(Duration::from_secs_f64(health::DEFAULT_MAX_BUFFER_AGE)),
+    }
+}
+
+/// command line args of the MQTT broker the levels of each interval and the events
+/// are published to, with the password from the environment not to show it
+/// in the process list
+fn start_mqtt(
+    args: &[String],
+    channels: &[usize],
+    levels_broadcast: &Broadcast<Levels>,
+    supervisor: &Supervisor,
+) -> Result<Option<EventSink>, String> {
+    let url = match arg_value(args, "--mqtt-url") {
+        Some(url) => MqttUrl::parse(&url)?,
+        None => return Ok(None),
+    };
+    let interval = match arg_value(args, "--mqtt-interval") {
+        Some(interval) => match parse_duration(&interval)?.as_secs_f64() {
+            seconds if seconds > 0.0 => seconds,
+            _ => return Err(format!("invalid MQTT interval '{}'", interval)),
+        },
+        None => mqtt::DEFAULT_INTERVAL,
+    };
+    let publisher = MqttPublisher::start(
+        MqttConfig {
+            url,
+            password: arg_value(args, "--mqtt-password")
+                .or_else(|| std::env::var("AUDIO_IN_STREAM_MQTT_PASSWORD").ok()),
+            client_id: arg_value(args, "--mqtt-client-id")
+                .unwrap_or_else(|| mqtt::DEFAULT_CLIENT_ID.to_owned()),
+            discovery: args.iter().any(|arg| arg == "--mqtt-discovery"),
+            channels: channels.to_vec(),
+        },
+        supervisor.clone(),
+    );
+
+    let levels_publisher = publisher.clone();
+    let mut messages = levels_broadcast.subscribe();
+    tokio::spawn(async move {
+        let mut intervals = PeriodLevels::new(interval);
+        while let Some(levels) = messages.recv().await {
+            if let Some(point) = intervals.push(&levels) {
+                levels_publisher.publish_levels(&point);
+            }
+        }
+    });
+    Ok(Some(Box::new(move |event| publisher.publish_event(event))))
+}
+
+/// command line args of the OSC receiver the levels are sent to, at most `--osc-rate`
+/// times per second, warning once while sending fails
+fn start_osc(
+    args: &[String],
+    levels_broadcast: &Broadcast<Levels>,
+    tui: Arc<OnceLock<TuiSender>>,
+) -> Result<(), String> {
+    let address = match arg_value(args, "--osc-addr") {
+        Some(address) => address,
+        None => return Ok(()),
+    };
+    let prefix = arg_value(args, "--osc-prefix").unwrap_or_else(|| osc::DEFAULT_PREFIX.to_owned());
+    let sender = OscSender::new(&address, &prefix)?;
+    let rate = parse_arg_value::<f64>(args, "--osc-rate")?.unwrap_or(osc::DEFAULT_RATE);
+    let min_interval =
+        rate_interval(rate).ok_or_else(|| format!("invalid OSC rate '{:?}'", rate))?;
+
+    let mut messages = levels_broadcast.subscribe();
+    tokio::spawn(async move {
+        let mut last_sent: Option<Instant> = None;
+        let mut last_error = None;
+        while let Some(levels) = messages.recv().await {
+            if last_sent.is_some_and(|last_sent| last_sent.elapsed() < min_interval) {
+                continue;
+            }
+            last_sent = Some(Instant::now());
+            let result = sender.send(&levels).await;
+            if let Err(err) = &result {
+                if last_error.as_ref() != Some(err) {
+                    print_message(tui.get(), format!("warning: {}", err));
+                }
+            }
+            last_error = result.err();
+        }
+    });
+    Ok(())
+}
+
+/// command line args of the MIDI output of the levels as control changes, on a virtual port
+/// with `--midi` or connected to `--midi-port`, at most `--midi-rate` times per second
+fn start_midi(
+    args: &[String],
+    num_channels: usize,
+    levels_broadcast: &Broadcast<Levels>,
+    tui: Arc<OnceLock<TuiSender>>,
+) -> Result<(), String> {
+    use audio_in_stream_rs::midi::{self, MidiConfig, MidiOutput};
+
+    let port = arg_value(args, "--midi-port");
+    if port.is_none() && !args.iter().any(|arg| arg == "--midi") {
+        return Ok(());
+    }
+    let channel = match parse_arg_value::<u8>(args, "--midi-channel")? {
+        Some(channel) if !(1..=16).contains(&channel) => {
+            return Err(format!(
+                "invalid MIDI channel {}, expected 1 to 16",
+                channel
+            ))
+        }
+        channel => channel.unwrap_or(1) - 1,
+    };
+    let controllers = match arg_value(args, "--midi-cc") {
+        Some(controllers) => controllers
+            .split(',')
+            .map(|controller| match controller.trim().parse::<u8>() {
+                Ok(controller) if controller < 120 => Ok(controller),
+                _ => Err(format!(
+                    "invalid MIDI controller '{}', expected 0 to 119",
+                    controller
+                )),
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+        None => (0..num_channels.min(12))
+            .map(|index| midi::DEFAULT_FIRST_CC + index as u8)
+            .collect(),
+    };
+    let floor_db = match parse_arg_value::<f32>(args, "--midi-floor")? {
+        Some(floor_db) if !(floor_db < 0.0 && floor_db.is_finite()) => {
+            return Err(format!(
+                "invalid MIDI floor {} dB, expected below 0",
+                floor_db
+            ))
+        }
+        floor_db => floor_db.unwrap_or(midi::DEFAULT_FLOOR_DB),
+    };
+    let rate = parse_arg_value::<f64>(args, "--midi-rate")?.unwrap_or(midi::DEFAULT_RATE);
+    let min_interval =
+        rate_interval(rate).ok_or_else(|| format!("invalid MIDI rate '{:?}'", rate))?;
+    let mut output = MidiOutput::open(
+        port.as_deref(),
+        MidiConfig {
+            channel,
+            controllers,
+            floor_db,
+        },
+    )?;
+
+    let mut messages = levels_broadcast.subscribe();
+    tokio::task::spawn_blocking(move || {
+        let mut last_sent: Option<Instant> = None;
+        let mut last_error = None;
+        while let Some(levels) = messages.blocking_recv() {
+            if last_sent.is_some_and(|last_sent| last_sent.elapsed() < min_interval) {
+                continue;
+            }
+            last_sent = Some(Instant::now());
+            let result = output.send(&levels);
+            if let Err(err) = &result {
+                if last_error.as_ref() != Some(err) {
+                    print_message(tui.get(), format!("warning: {}", err));
+                }
+            }
+            last_error = result.err();
+        }
+    });
+    Ok(())
+}
+
+/// command line args of the RTP stream of the live capture to `--rtp-dest`,
+/// returns the SDP description of the stream, also written to `--rtp-sdp`
+fn start_rtp(
+    args: &[String],
+    num_channels: u16,
+    sample_rate: u32,
+    samples_broadcast: &Broadcast<Vec<f32>>,
+) -> Result<Option<String>, String> {
+    let destination = match arg_value(args, "--rtp-dest") {
+        Some(destination) => destination,
+        None => return Ok(None),
+    };
+    let format = match arg_value(args, "--rtp-format") {
+        Some(format) => RtpFormat::parse(&format)?,
+        None => RtpFormat::L24,
+    };
+    let ptime = match parse_arg_value::<f64>(args, "--rtp-ptime")? {
+        Some(ptime) if !(ptime > 0.0 && ptime.is_finite()) => {
+            return Err(format!("invalid RTP packet time {} ms", ptime))
+        }
+        ptime => ptime.unwrap_or(rtp::DEFAULT_PTIME),
+    };
+    let ttl = parse_arg_value(args, "--rtp-ttl")?.unwrap_or(rtp::DEFAULT_TTL);
+    let mut sender = RtpSender::new(&destination, format, num_channels, sample_rate, ptime, ttl)?;
+    let sdp = sender.sdp();
+    if let Some(path) = arg_value(args, "--rtp-sdp") {
+        std::fs::write(&path, &sdp)
+            .map_err(|err| format!("failed to write '{}': {}", path, err))?;
+    }
+
+    let mut samples = samples_broadcast.subscribe();
+    tokio::spawn(async move {
+        while let Some(samples) = samples.recv().await {
+            if let Err(err) = sender.send(&samples).await {
+                eprintln!(
+                    "warning: stopped the RTP stream to {}: {}",
+                    destination, err
+                );
+                return;
+            }
+        }
+    });
+    Ok(Some(sdp))
+}
+
+/// parse the FFT size and window command line args of the spectrum analysis
+fn spectrum_analyzer_args(args: &[String]) -> Result<(usize, Window), String> {
+    let fft_size = parse_arg_value(args, "--fft-size")?.unwrap_or(DEFAULT_FFT_SIZE);
+    if fft_size < MIN_FFT_SIZE {
+        return Err(format!(
+            "invalid FFT size {}, the minimum is {}",
+            fft_size, MIN_FFT_SIZE
+        ));
+    }
+    let window = match arg_value(args, "--window") {
+        Some(window) => Window::parse(&window)?,
+        None => Window::default(),
+    };
+    Ok((fft_size, window))
+}
+
+/// parse the command line args of the scale of the meters: the unit of the levels,
+/// with the dBu of 0 dBFS, the range in that unit and the width in characters
+fn meter_scale_args(args: &[String]) -> Result<MeterScale, String> {
+    let dbu_reference = parse_arg_value::<f32>(args, "--dbu-reference")?
+        .unwrap_or(meter_scale::DEFAULT_DBU_REFERENCE);
+    if !dbu_reference.is_finite() {
+        return Err(format!("invalid dBu reference {}", dbu_reference));
+    }
+    let unit = match arg_value(args, "--meter-unit") {
+        Some(unit) => MeterUnit::parse(&unit, dbu_reference)?,
+        None => MeterUnit::default(),
+    };
+    let mut meter_scale = MeterScale::new(unit);
+    if let Some(range) = arg_value(args, "--meter-range") {
+        meter_scale.parse_range(&range)?;
+    }
+    match parse_arg_value::<usize>(args, "--meter-width")? {
+        Some(0) => return Err(String::from("invalid value '0' for --meter-width")),
+        Some(width) => meter_scale.width = width,
+        None => {}
+    }
+    meter_scale.ascii = args.iter().any(|arg| arg == "--ascii");
+    Ok(meter_scale)
+}
+
+/// parse the command line args of the meter ballistics, the standard meter type
+/// adjusted by the attack and release times in milliseconds and peak hold time in seconds
+fn ballistics_config_args(args: &[String]) -> Result<BallisticsConfig, String> {
+    let mut config = match arg_value(args, "--meter-type") {
+        Some(meter_type) => MeterType::parse(&meter_type)?.ballistics(),
+        None => BallisticsConfig::default(),
+    };
+    // a release time replaces the linear fall of the peak meters
+    if arg_value(args, "--meter-release").is_some() {
+        config.fall_rate = None;
+    }
+    for (name, value, scale) in [
+        ("--meter-attack", &mut config.attack, 1000.0),
+        ("--meter-release", &mut config.release, 1000.0),
+        ("--peak-hold", &mut config.peak_hold, 1.0),
+    ] {
+        if let Some(arg) = parse_arg_value::<f32>(args, name)? {
+            if !(arg >= 0.0 && arg.is_finite()) {
+                return Err(format!("invalid value '{}' for {}", arg, name));
+            }
+            *value = arg / scale;
+        }
+    }
+    Ok(config)
+}
+
+/// parse the command line args of the channel mapping, with the comma separated indices
+/// of the input channels to use, of the downmix to mono or stereo
+/// and of the gain of all or some of the channels
+fn channel_mix_args(args: &[String], input_channels: usize) -> Result<ChannelMix, String> {
+    let map = match arg_value(args, "--map") {
+        Some(map) => Some(
+            map.split(',')
+                .map(|channel| {
+                    channel
+                        .trim()
+                        .parse::<usize>()
+                        .map_err(|_| format!("invalid channel '{}' for --map", channel))
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+        ),
+        None => None,
+    };
+    let downmix = arg_value(args, "--downmix")
+        .map(|downmix| Downmix::parse(&downmix))
+        .transpose()?;
+    let mix = ChannelMix::new(input_channels, map.as_deref(), downmix)?;
+    // the gain of the channels after the mapping and downmix
+    if let Some(gains) = arg_value(args, "--gain") {
+        mix.gains().parse(&gains)?;
+    }
+    Ok(mix)
+}
+
+/// parse the command line arg of the positions of the channels after the mapping and downmix,
+/// `mono`, `stereo`, `5.1` or e.g. `L,R,C,LFE,Ls,Rs`, for their weights in the loudness,
+/// all of them the same without it
+fn layout_arg(
+    args: &[String],
+    num_channels: usize,
+) -> Result<Option<Vec<ChannelPosition>>, String> {
+    let layout = match arg_value(args, "--layout") {
+        Some(layout) => loudness::parse_layout(&layout)?,
+        None => return Ok(None),
+    };
+    if layout.len() != num_channels {
+        return Err(format!(
+            "invalid --layout of {} channel(s), the input has {} channel(s)",
+            layout.len(),
+            num_channels
+        ));
+    }
+    Ok(Some(layout))
+}
+
+/// parse the command line arg with the comma separated indices of the channels to meter,
+/// all of them by default
+fn channels_map_arg(args: &[String], num_channels: usize) -> Result<Vec<usize>, String> {
+    let channels_map = match arg_value(args, "--channels-map") {
+        Some(channels_map) => channels_map,
+        None => return Ok((0..num_channels).collect()),
+    };
+    let mut channels = Vec::new();
+    for channel in channels_map.split(',') {
+        let channel = channel
+            .trim()
+            .parse::<usize>()
+            .map_err(|_| format!("invalid channel '{}' for --channels-map", channel))?;
+        if channel >= num_channels {
+            return Err(format!(
+                "invalid channel {} for --channels-map, the input has {} channel(s)",
+                channel, num_channels
+            ));
+        }
+        if channels.contains(&channel) {
+            return Err(format!("duplicated channel {} for --channels-map", channel));
+        }
+        channels.push(channel);
+    }
+    Ok(channels)
+}
+
+/// parse the command line args of the clipping detection,
+/// the threshold in dBFS and the consecutive samples that make a clip
+fn clip_config_args(args: &[String]) -> Result<ClipConfig, String> {
+    let mut config = ClipConfig::default();
+    if let Some(threshold) = parse_arg_value::<f32>(args, "--clip-threshold")? {
+        if threshold.is_nan() || threshold > 0.0 {
+            return Err(format!(
+                "invalid clip threshold {} dBFS, it must be at most 0 dBFS",
+                threshold
+            ));
+        }
+        config.threshold = 10_f32.powf(threshold / 20.0);
+    }
+    if let Some(consecutive_samples) = parse_arg_value(args, "--clip-samples")? {
+        if consecutive_samples == 0 {
+            return Err(String::from("invalid value '0' for --clip-samples"));
+        }
+        config.consecutive_samples = consecutive_samples;
+    }
+    Ok(config)
+}
+
+/// recording of the input, run by its own thread
+struct Recording {
+    session: RecordingSession,
+    /// command run once each file is completed
+    on_recording: Option<String>,
+    /// upload of each file once completed, after the command
+    s3_config: Option<S3Config>,
+    /// directory of the files
+    directory: PathBuf,
+}
+
+/// parse the command line args of the recording, either to a file or to files named
+/// by a template, in segments of a duration, optionally aligned to the wall clock,
+/// of its retention, of its trigger by the level and of its schedule
+fn recorder_args(
+    args: &[String],
+    stream_config: &cpal::SupportedStreamConfig,
+    layout: Option<&[ChannelPosition]>,
+) -> Result<Option<Recording>, String> {
+    let path = match (
+        arg_value(args, "--record"),
+        arg_value(args, "--record-template"),
+    ) {
+        (Some(_), Some(_)) => {
+            return Err(String::from(
+                "--record and --record-template can not be used together",
+            ))
+        }
+        (Some(path), None) => RecordPath::File(PathBuf::from(path)),
+        (None, Some(template)) => RecordPath::Template(template),
+        (None, None) => return Ok(None),
+    };
+    let segment = match arg_value(args, "--segment") {
+        Some(segment) => {
+            let segment = parse_duration(&segment)?.as_secs_f64();
+            if segment < 1.0 {
+                return Err(format!(
+                    "invalid segment of {} s, the minimum is 1 s",
+                    segment
+                ));
+            }
+            Some(segment)
+        }
+        None => None,
+    };
+    let align = args.iter().any(|arg| arg == "--segment-align");
+    let mut record_config = match &path {
+        RecordPath::Template(template) => record_config_args(args, template)?,
+        RecordPath::File(path) => {
+            if segment.is_some() {
+                return Err(String::from("--segment requires a --record-template"));
+            }
+            record_config_args(args, &path.to_string_lossy())?
+        }
+    };
+    if align && segment.is_none() {
+        return Err(String::from("--segment-align requires a --segment"));
+    }
+    record_config.layout = layout.map(<[ChannelPosition]>::to_vec);
+    let retention = Retention::new(retention_config_args(args, &path)?, &path);
+    let trigger = match trigger_config_args(args)? {
+        Some(_) if matches!(path, RecordPath::File(_)) => {
+            return Err(String::from("--trigger-level requires a --record-template"))
+        }
+        Some(config) => Some(LevelTrigger::new(
+            config,
+            stream_config.channels() as usize,
+            stream_config.sample_rate().0,
+        )?),
+        None => None,
+    };
+    let schedule = match schedule_args(args)? {
+        Some(_) if matches!(path, RecordPath::File(_)) => {
+            return Err(String::from("--schedule requires a --record-template"))
+        }
+        schedule => schedule,
+    };
+    let s3_config = match s3_config_args(args)? {
+        Some(_) if matches!(path, RecordPath::File(_)) => {
+            return Err(String::from("--s3-bucket requires a --record-template"))
+        }
+        s3_config => s3_config,
+    };
+    let directory = path.directory();
+    let recorder = Recorder::new(path, record_config, stream_config, segment, align)
+        .map_err(|err| err.to_string())?;
+    let mut session = RecordingSession::new(recorder, retention);
+    if let Some(trigger) = trigger {
+        session = session.with_trigger(trigger);
+    }
+    if let Some(schedule) = schedule {
+        session = session.with_schedule(schedule);
+    }
+    Ok(Some(Recording {
+        session,
+        on_recording: arg_value(args, "--on-recording"),
+        s3_config,
+        directory,
+    }))
+}
+
+/// parse the command line args of the upload of the recordings to an S3 bucket,
+/// the credentials default to the `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY`
+/// environment variables
+fn s3_config_args(args: &[String]) -> Result<Option<S3Config>, String> {
+    let bucket = match arg_value(args, "--s3-bucket") {
+        Some(bucket) => bucket,
+        None => return Ok(None),
+    };
+    let endpoint =
+        arg_value(args, "--s3-endpoint").ok_or("--s3-bucket requires an --s3-endpoint")?;
+    let access_key = arg_value(args, "--s3-access-key")
+        .or_else(|| std::env::var("AWS_ACCESS_KEY_ID").ok())
+        .ok_or("--s3-bucket requires an --s3-access-key or AWS_ACCESS_KEY_ID")?;
+    let secret_key = arg_value(args, "--s3-secret-key")
+        .or_else(|| std::env::var("AWS_SECRET_ACCESS_KEY").ok())
+        .ok_or("--s3-bucket requires an --s3-secret-key or AWS_SECRET_ACCESS_KEY")?;
+    let config = S3Config {
+        endpoint,
+        bucket,
+        prefix: arg_value(args, "--s3-prefix").unwrap_or_default(),
+        region: arg_value(args, "--s3-region").unwrap_or_else(|| String::from(s3::DEFAULT_REGION)),
+        access_key,
+        secret_key,
+        keep_local: args.iter().any(|arg| arg == "--s3-keep-local"),
+    };
+    config.validate()?;
+    Ok(Some(config))
+}
+
+/// parse the command line args of the recording triggered by the level, the threshold
+/// in dBFS, the pre-roll before it and the hang time below it ending the recording
+fn trigger_config_args(args: &[String]) -> Result<Option<TriggerConfig>, String> {
+    let threshold = match parse_arg_value::<f32>(args, "--trigger-level")? {
+        Some(threshold) if threshold.is_nan() || threshold > 0.0 => {
+            return Err(format!(
+                "invalid trigger level {} dBFS, it must be at most 0 dBFS",
+                threshold
+            ))
+        }
+        Some(threshold) => threshold,
+        None => return Ok(None),
+    };
+    let pre_roll = match arg_value(args, "--pre-roll") {
+        Some(pre_roll) => match parse_duration(&pre_roll)?.as_secs_f64() {
+            seconds if seconds <= trigger::MAX_PRE_ROLL => seconds,
+            _ => {
+                return Err(format!(
+                    "invalid pre-roll '{}', it must be at most {} s",
+                    pre_roll,
+                    trigger::MAX_PRE_ROLL
+                ))
+            }
+        },
+        None => trigger::DEFAULT_PRE_ROLL,
+    };
+    let hang_time = match arg_value(args, "--hang-time") {
+        Some(hang_time) => parse_duration(&hang_time)?.as_secs_f64(),
+        None => trigger::DEFAULT_HANG_TIME,
+    };
+    Ok(Some(TriggerConfig {
+        threshold: 10_f32.powf(threshold / 20.0),
+        pre_roll,
+        hang_time,
+    }))
+}
+
+/// parse the command line args of the windows of the week to record, in the local time,
+/// repeatable for multiple windows, e.g. `--schedule "MON-FRI 06:00-10:00"`
+fn schedule_args(args: &[String]) -> Result<Option<Schedule>, String> {
+    let windows = arg_values(args, "--schedule")
+        .iter()
+        .map(|window| schedule::Window::parse(window))
+        .collect::<Result<Vec<_>, _>>()?;
+    if windows.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(Schedule::new(windows)))
+}
+
+/// parse the command line args of the PEM certificate chain and private key of HTTPS
+fn tls_args(args: &[String]) -> Result<Option<Arc<rustls::ServerConfig>>, String> {
+    match (arg_value(args, "--tls-cert"), arg_value(args, "--tls-key")) {
+        (Some(cert), Some(key)) => tls::server_config(Path::new(&cert), Path::new(&key)).map(Some),
+        (None, None) => Ok(None),
+        _ => Err(String::from(
+            "--tls-cert and --tls-key must be used together",
+        )),
+    }
+}
+
+/// parse the command line args of the snapshots, the seconds of the input kept in memory
+/// and the directory of the saved snapshots
+fn snapshot_args(
+    args: &[String],
+    stream_config: &cpal::SupportedStreamConfig,
+) -> Result<Option<(SnapshotBuffer, PathBuf)>, String> {
+    let duration = match arg_value(args, "--snapshot") {
+        Some(duration) => match parse_duration(&duration)?.as_secs_f64() {
+            seconds if seconds > 0.0 && seconds <= snapshot::MAX_DURATION => seconds,
+            _ => {
+                return Err(format!(
+                    "invalid snapshot duration '{}', it must be at most {} s",
+                    duration,
+                    snapshot::MAX_DURATION
+                ))
+            }
+        },
+        None => {
+            if arg_value(args, "--snapshot-dir").is_some() {
+                return Err(String::from("--snapshot-dir requires --snapshot"));
+            }
+            return Ok(None);
+        }
+    };
+    let directory = PathBuf::from(arg_value(args, "--snapshot-dir").unwrap_or_default());
+    if !directory.as_os_str().is_empty() && !directory.is_dir() {
+        return Err(format!(
+            "snapshot directory '{}' is not a directory",
+            directory.display()
+        ));
+    }
+    Ok(Some((
+        SnapshotBuffer::new(duration, stream_config.clone()),
+        directory,
+    )))
+}
+
+/// parse the command line args of the retention of the segments of the recording,
+/// the maximum size and age, the free disk space to keep, and whether to delete
+/// the oldest segments or stop the recording
+fn retention_config_args(args: &[String], path: &RecordPath) -> Result<RetentionConfig, String> {
+    let mut config = RetentionConfig::default();
+    if let Some(max_disk) = arg_value(args, "--max-disk") {
+        config.max_bytes = Some(parse_size(&max_disk)?);
+    }
+    if let Some(keep_days) = parse_arg_value::<f64>(args, "--keep-days")? {
+        config.keep = match duration_from_secs(keep_days * 86400.0) {
+            Some(keep) if keep > Duration::ZERO => Some(keep),
+            _ => return Err(format!("invalid value '{}' for --keep-days", keep_days)),
+        };
+    }
+    if let RecordPath::File(_) = path {
+        if config.max_bytes.is_some() || config.keep.is_some() {
+            return Err(String::from(
+                "--max-disk and --keep-days require a --record-template",
+            ));
+        }
+    }
+    if let Some(min_free_disk) = arg_value(args, "--min-free-disk") {
+        config.min_free_bytes = parse_size(&min_free_disk)?;
+    }
+    if let Some(action) = arg_value(args, "--retention") {
+        config.action = RetentionAction::parse(&action)?;
+    }
+    Ok(config)
+}
+
+/// parse the command line args of the format of the recording, by default the one
+/// of the extension of the path or WAV, of the FLAC compression level
+/// and of the bitrate of the lossy formats
+fn record_config_args(args: &[String], path: &str) -> Result<RecordConfig, String> {
+    let format = match arg_value(args, "--record-format") {
+        Some(format) => RecordFormat::parse(&format)?,
+        None => RecordFormat::from_path(path).unwrap_or_default(),
+    };
+    let mut config = RecordConfig {
+        format,
+        ..RecordConfig::default()
+    };
+    if let Some(level) = parse_arg_value::<u8>(args, "--compression-level")? {
+        if level > flac::MAX_COMPRESSION_LEVEL {
+            return Err(format!(
+                "invalid compression level {}, it must be from 0 to {}",
+                level,
+                flac::MAX_COMPRESSION_LEVEL
+            ));
+        }
+        config.compression_level = level;
+    }
+    if let Some(bitrate) = arg_value(args, "--bitrate") {
+        if !config.format.is_lossy() {
+            return Err(String::from("--bitrate requires a lossy --record-format"));
+        }
+        config.bitrate = Some(encoder::parse_bitrate(&bitrate)?);
+    }
+    config.bext = bext_args(args)?;
+    // command line arg to record a mono file for each channel, e.g. `take01_ch03.wav`
+    config.split_channels = args.iter().any(|arg| arg == "--split-channels");
+    // command line arg to write the loudness report of each recording to a JSON sidecar,
+    // with the clipping and silence detection of the metering, also passed to the
+    // command run once each recording is completed
+    let sidecar = args.iter().any(|arg| arg == "--loudness-report");
+    if sidecar || arg_value(args, "--on-recording").is_some() {
+        config.report = Some(ReportConfig {
+            clip: clip_config_args(args)?,
+            silence: silence_config_args(args)?,
+            sidecar,
+        });
+    }
+    if config.bext.is_some() && config.format != RecordFormat::Wav {
+        return Err(String::from("--bwf requires the WAV --record-format"));
+    }
+    Ok(config)
+}
+
+/// parse the command line args of the Broadcast Wave metadata of the WAV recordings,
+/// enabled by `--bwf` or any of them
+fn bext_args(args: &[String]) -> Result<Option<Bext>, String> {
+    let field = |name: &str, max_len: usize| match arg_value(args, name) {
+        Some(value) if !value.is_ascii() || value.len() > max_len => Err(format!(
+            "invalid value '{}' for {}, it must be up to {} ASCII characters",
+            value, name, max_len
+        )),
+        value => Ok(value),
+    };
+    let description = field("--bwf-description", 256)?;
+    let originator = field("--bwf-originator", 32)?;
+    let originator_reference = field("--bwf-originator-reference", 32)?;
+    let coding_history = field("--bwf-coding-history", usize::MAX)?;
+    let enabled = args.iter().any(|arg| arg == "--bwf")
+        || description.is_some()
+        || originator.is_some()
+        || originator_reference.is_some()
+        || coding_history.is_some();
+    if !enabled {
+        return Ok(None);
+    }
+    Ok(Some(Bext {
+        description: description.unwrap_or_default(),
+        originator: originator.unwrap_or_else(|| String::from(env!("CARGO_PKG_NAME"))),
+        originator_reference: originator_reference.unwrap_or_default(),
+        coding_history: coding_history.unwrap_or_default(),
+    }))
+}
+
+/// parse the command line arg of the window of the RMS levels, in seconds or e.g. `300ms`,
+/// 0 for the RMS level of each input buffer, returns seconds
+fn rms_window_arg(args: &[String]) -> Result<f32, String> {
+    match arg_value(args, "--rms-window") {
+        Some(window) => match parse_duration(&window)?.as_secs_f32() {
+            seconds if seconds <= rms::MAX_WINDOW => Ok(seconds),
+            _ => Err(format!(
+                "invalid RMS window '{}', it must be at most {} s",
+                window,
+                rms::MAX_WINDOW
+            )),
+        },
+        None => Ok(rms::DEFAULT_WINDOW),
+    }
+}
+
+/// parse the command line arg of the DC offset considered significant, in dBFS
+fn dc_threshold_arg(args: &[String]) -> Result<f32, String> {
+    let threshold =
+        parse_arg_value::<f32>(args, "--dc-threshold")?.unwrap_or(dc_offset::DEFAULT_THRESHOLD);
+    if threshold.is_nan() || threshold > 0.0 {
+        return Err(format!(
+            "invalid DC offset threshold {} dBFS, it must be at most 0 dBFS",
+            threshold
+        ));
+    }
+    Ok(10_f32.powf(threshold / 20.0))
+}
+
+/// parse the command line args of the out of phase alarm of the stereo pairs,
+/// the correlation threshold and the duration, in seconds or e.g. `500ms`
+fn correlation_config_args(args: &[String]) -> Result<CorrelationConfig, String> {
+    let mut config = CorrelationConfig::default();
+    if let Some(threshold) = parse_arg_value::<f32>(args, "--correlation-threshold")? {
+        if !(-1.0..=1.0).contains(&threshold) {
+            return Err(format!(
+                "invalid correlation threshold {}, it must be between -1 and +1",
+                threshold
+            ));
+        }
+        config.threshold = threshold;
+    }
+    if let Some(duration) = arg_value(args, "--correlation-duration") {
+        config.duration = parse_duration(&duration)?;
+    }
+    Ok(config)
+}
+
+/// parse the command line arg of the time a wiring fault has to be present,
+/// in seconds or e.g. `1m`
+fn fault_duration_arg(args: &[String]) -> Result<Duration, String> {
+    match arg_value(args, "--fault-duration") {
+        Some(duration) => parse_duration(&duration),
+        None => Ok(Duration::from_secs_f32(channel_faults::DEFAULT_DURATION)),
+    }
+}
+
+/// parse the command line args of the silence detection,
+/// the threshold in dBov and the duration, in seconds or e.g. `2m`
+fn silence_config_args(args: &[String]) -> Result<SilenceConfig, String> {
+    let mut config = SilenceConfig::default();
+    if let Some(threshold) = parse_arg_value::<f32>(args, "--silence-threshold")? {
+        if threshold.is_nan() || threshold > 0.0 {
+            return Err(format!(
+                "invalid silence threshold {} dBov, it must be at most 0 dBov",
+                threshold
+            ));
+        }
+        config.threshold = 10_f32.powf(threshold / 20.0);
+    }
+    if let Some(duration) = arg_value(args, "--silence-duration") {
+        config.duration = parse_duration(&duration)?;
+    }
+    Ok(config)
+}
+
+/// parse the command line args of the meters of the metered channels, and of the analyses
+/// enabled, with the log of the Leq statistics if any
+fn metering_args(
+    args: &[String],
+    stream_config: &cpal::SupportedStreamConfig,
+    channels_map: &[usize],
+    layout: Option<Vec<ChannelPosition>>,
+    filter: FilterChain,
+) -> Result<(Metering, Option<LeqLog>), String> {
+    let sample_rate = stream_config.sample_rate().0;
+    let num_metered_channels = channels_map.len();
+    let mut config = MeteringConfig::new(
+        sample_rate,
+        stream_config.sample_format(),
+        stream_config.channels() as usize,
+    );
+    config.channels_map = channels_map.to_vec();
+    config.layout = layout;
+    // the frequency weighting of the levels, none by default, and the window of their RMS,
+    // independent of the size of the input buffers, metered after the filters of the metering
+    if let Some(weighting) = arg_value(args, "--weighting") {
+        config.weighting = Weighting::parse(&weighting)?;
+    }
+    config.rms_window = rms_window_arg(args)?;
+    config.filter = Some(filter);
+    config.ballistics = ballistics_config_args(args)?;
+    config.clip = clip_config_args(args)?;
+    config.correlation = correlation_config_args(args)?;
+    config.silence = silence_config_args(args)?;
+    config.fault_duration = fault_duration_arg(args)?;
+    config.dc_threshold = dc_threshold_arg(args)?;
+    let mut metering = Metering::new(config)?;
+
+    // the spectrum analysis, with the FFT size and window
+    if args.iter().any(|arg| arg == "--spectrum") {
+        let (fft_size, window) = spectrum_analyzer_args(args)?;
+        metering = metering.with_spectrum(SpectrumAnalyzer::new(
+            fft_size,
+            window,
+            sample_rate,
+            num_metered_channels,
+        ));
+    }
+    // the third octave band analysis
+    if args.iter().any(|arg| arg == "--octave-bands") {
+        metering =
+            metering.with_octave_bands(OctaveBandAnalyzer::new(sample_rate, num_metered_channels));
+    }
+    // the audio analyzer of a test tone on the input
+    if let Some(tone_analyzer) = tone_analyzer_args(args, sample_rate, num_metered_channels)? {
+        metering = metering.with_tone_analyzer(tone_analyzer);
+    }
+    // the pitch detection of each channel
+    if let Some(pitch_detector) = pitch_detector_args(args, sample_rate, num_metered_channels)? {
+        metering = metering.with_pitch_detector(pitch_detector);
+    }
+    // the voice activity detection of each channel
+    if let Some(detector) = vad_args(args, sample_rate, num_metered_channels)? {
+        metering = metering.with_voice_activity_detector(detector);
+    }
+    // the levels in dB SPL, given the level of a known sound pressure level
+    // or measuring a calibrator tone
+    if let Some(calibration) = arg_value(args, "--calibrate") {
+        metering = metering.with_calibration(Calibration::parse(
+            &calibration,
+            sample_rate,
+            num_metered_channels,
+        )?);
+    }
+    // the Leq statistics, over the given windows, logged to a CSV file
+    let leq_log = match leq_args(args, sample_rate, num_metered_channels)? {
+        Some((leq_meter, leq_log)) => {
+            metering = metering.with_leq_meter(leq_meter);
+            leq_log
+        }
+        None => None,
+    };
+    // the mid/side levels of the stereo pairs
+    if args.iter().any(|arg| arg == "--ms") {
+        metering = metering.with_mid_side(MidSideMeter::new(channels_map));
+    }
+    // the pilot tones expected on the channels
+    let pilot_tones = arg_values(args, "--pilot-tone")
+        .iter()
+        .map(|pilot_tone| PilotToneConfig::parse(pilot_tone))
+        .collect::<Result<Vec<_>, _>>()?;
+    if !pilot_tones.is_empty() {
+        metering = metering.with_pilot_watchdog(PilotToneWatchdog::new(
+            &pilot_tones,
+            sample_rate,
+            channels_map,
+        )?);
+    }
+    // the DTMF digits and call progress tones of each channel
+    if args.iter().any(|arg| arg == "--dtmf") {
+        metering =
+            metering.with_tone_detector(ToneDetector::new(sample_rate, num_metered_channels));
+    }
+    Ok((metering, leq_log))
+}
+
+/// print a message, or show it in the terminal interface if started
+fn print_message(tui: Option<&TuiSender>, message: String) {
+    match tui {
+        Some(tui) => tui.message(message),
+        None => eprintln!("{}", message),
+    }
+}
+
+/// a command run by the shell of the platform
+fn shell_command(command: &str) -> std::process::Command {
+    if cfg!(windows) {
+        let mut shell = std::process::Command::new("cmd");
+        shell.arg("/C").arg(command);
+        shell
+    } else {
+        let mut shell = std::process::Command::new("sh");
+        shell.arg("-c").arg(command);
+        shell
+    }
+}
+
+/// emit the event of a completed file of the recording, and run the `--on-recording`
+/// command with its path as argument, and its path, start, duration and loudness
+/// in the `AUDIO_IN_STREAM_RECORDING_*` environment variables, then upload it
+fn complete_recording(
+    recording: &CompletedRecording,
+    on_recording: Option<&str>,
+    uploader: Option<&S3Uploader>,
+    event_queue: &EventQueue,
+) {
+    let path = recording.path.to_string_lossy().into_owned();
+    let start = unix_time(recording.start);
+    event_queue.emit(Event::RecordingCompleted {
+        timestamp: unix_time(SystemTime::now()),
+        path: path.clone(),
+        start,
+        duration: recording.duration,
+        report: recording.report.clone(),
+    });
+
+    let command = match on_recording {
+        Some(command) => command,
+        None => {
+            if let Some(uploader) = uploader {
+                uploader.upload(recording.path.clone());
+            }
+            return;
+        }
+    };
+    let mut command = shell_command(command);
+    if cfg!(windows) {
+        command.arg(&path);
+    } else {
+        // the path is the first positional parameter, `$1`
+        command.arg("sh").arg(&path);
+    }
+    command
+        .env("AUDIO_IN_STREAM_RECORDING_PATH", &path)
+        .env("AUDIO_IN_STREAM_RECORDING_START", start.to_string())
+        .env(
+            "AUDIO_IN_STREAM_RECORDING_DURATION",
+            recording.duration.to_string(),
+        );
+    if let Some(report) = &recording.report {
+        command
+            .env(
+                "AUDIO_IN_STREAM_RECORDING_INTEGRATED",
+                report.integrated.to_string(),
+            )
+            .env(
+                "AUDIO_IN_STREAM_RECORDING_LOUDNESS_RANGE",
+                report.loudness_range.to_string(),
+            )
+            .env(
+                "AUDIO_IN_STREAM_RECORDING_TRUE_PEAK",
+                report.true_peak.to_string(),
+            )
+            .env("AUDIO_IN_STREAM_RECORDING_CLIPS", report.clips.to_string());
+    }
+    // uploaded once the command exits, as it may use the file
+    let upload = uploader.map(|uploader| (uploader.clone(), recording.path.clone()));
+    match command.spawn() {
+        // wait for the command from its own thread, not to delay the recording
+        Ok(mut child) => {
+            thread::spawn(move || {
+                child.wait().ok();
+                if let Some((uploader, path)) = upload {
+                    uploader.upload(path);
+                }
+            });
+        }
+        Err(err) => {
+            eprintln!("error: failed to run --on-recording command: {}", err);
+            if let Some((uploader, path)) = upload {
+                uploader.upload(path);
+            }
+        }
+    }
+}
+
+/// print a warning for the start and end of a silence, and run the
+/// `--on-silence` command at the start, with the channel and the time since it
+/// is silent in the `AUDIO_IN_STREAM_CHANNEL` and `AUDIO_IN_STREAM_SILENT_SINCE`
+/// environment variables
+fn report_event(event: &Event, on_silence: Option<&str>, tui: Option<&TuiSender>) {
+    match *event {
+        Event::SilenceStart {
+            timestamp,
+            channel,
+            since,
+        } => {
+            print_message(
+                tui,
+                format!(
+                    "warning: channel {} silent for {:.1} s",
+                    channel,
+                    timestamp - since
+                ),
+            );
+            if let Some(command) = on_silence {
+                let mut command = shell_command(command);
+                command
+                    .env("AUDIO_IN_STREAM_CHANNEL", channel.to_string())
+                    .env("AUDIO_IN_STREAM_SILENT_SINCE", since.to_string());
+                match command.spawn() {
+                    // wait for the command from its own thread, not to delay the next events
+                    Ok(mut child) => {
+                        thread::spawn(move || child.wait());
+                    }
+                    Err(err) => print_message(
+                        tui,
+                        format!("error: failed to run --on-silence command: {}", err),
+                    ),
+                }
+            }
+        }
+        Event::SilenceEnd {
+            channel, duration, ..
+        } => {
+            print_message(
+                tui,
+                format!(
+                    "warning: channel {} silence ended after {:.1} s",
+                    channel, duration
+                ),
+            );
+        }
+        Event::CorrelationAlarmStart {
+            timestamp,
+            left,
+            right,
+            correlation,
+            since,
+        } => {
+            print_message(
+                tui,
+                format!(
+                    "warning: channels {} and {} out of phase for {:.1} s, correlation {:+.2}",
+                    left,
+                    right,
+                    timestamp - since,
+                    correlation
+                ),
+            );
+        }
+        Event::ChannelFaultStart {
+            fault,
+            ref channels,
+            ..
+        } => {
+            print_message(
+                tui,
+                format!("warning: {}", fault_description(fault, channels)),
+            );
+        }
+        Event::ChannelFaultEnd {
+            fault,
+            ref channels,
+            duration,
+            ..
+        } => {
+            print_message(
+                tui,
+                format!(
+                    "warning: {} no more, after {:.1} s",
+                    fault_description(fault, channels),
+                    duration
+                ),
+            );
+        }
+        Event::RecordingDeleted {
+            ref path,
+            ref reason,
+            ..
+        } => {
+            print_message(
+                tui,
+                format!("warning: deleted recording '{}', {}", path, reason),
+            );
+        }
+        Event::DtmfDigit { channel, digit, .. } => {
+            print_message(tui, format!("channel {} DTMF digit {}", channel, digit));
+        }
+        Event::CallProgressTone { channel, tone, .. } => {
+            print_message(tui, format!("channel {} {} tone", channel, tone.name()));
+        }
+        Event::SpeechStart { channel, .. } => {
+            print_message(tui, format!("channel {} speech started", channel));
+        }
+        Event::SpeechEnd {
+            channel, duration, ..
+        } => {
+            print_message(
+                tui,
+                format!("channel {} speech ended after {:.1} s", channel, duration),
+            );
+        }
+        Event::PilotToneLost {
+            channel,
+            frequency,
+            fault,
+            measured_frequency,
+            level,
+            ..
+        } => {
+            let reason = match fault {
+                PilotFault::Missing => format!("missing, at {:.1} dBFS", level),
+                PilotFault::Drift => format!("drifted to {:.1} Hz", measured_frequency),
+            };
+            print_message(
+                tui,
+                format!(
+                    "warning: channel {} pilot tone of {:.0} Hz {}",
+                    channel, frequency, reason
+                ),
+            );
+        }
+        Event::PilotToneRestored {
+            channel,
+            frequency,
+            duration,
+            ..
+        } => {
+            print_message(
+                tui,
+                format!(
+                    "warning: channel {} pilot tone of {:.0} Hz back after {:.1} s",
+                    channel, frequency, duration
+                ),
+            );
+        }
+        Event::RecordingStopped { ref reason, .. } => {
+            print_message(tui, format!("error: recording stopped, {}", reason));
+        }
+        Event::ScriptAlarm { ref message, .. } => {
+            print_message(tui, format!("warning: script alarm, {}", message));
+        }
+        Event::CorrelationAlarmEnd {
+            left,
+            right,
+            duration,
+            ..
+        } => {
+            print_message(
+                tui,
+                format!(
+                    "warning: channels {} and {} back in phase after {:.1} s",
+                    left, right, duration
+                ),
+            );
+        }
+        _ => {}
+    }
+}
+
+fn main() {
+    let exit_code = match run() {
+        Ok(()) => 0,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            1
+        }
+    };
+    // without waiting for the threads still processing the input
+    std::process::exit(exit_code);
+}
+
+/// Start the runtime of the http server, the streaming and the uploads, then set up
+/// the capture and serve it until the shutdown.
+fn run() -> Result<(), Error> {
+    let args: Vec<String> = std::env::args().collect();
+
+    // command line arg to list all supported the sample format in all input devices in all hosts
+    if args.iter().any(|arg| arg == "--list-input-devices") {
+        print_cpal_input_devices();
+        return Ok(());
+    }
+
+    // command line arg of the worker threads of the runtime, serving the requests
+    // while the main thread keeps the input stream alive
+    let http_workers = match parse_arg_value(&args, "--http-workers")? {
+        Some(0) => {
+            return Err(Error::Setup(String::from(
+                "invalid value '0' for --http-workers",
+            )))
+        }
+        http_workers => http_workers.unwrap_or(DEFAULT_HTTP_WORKERS),
+    };
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(http_workers)
+        .enable_all()
+        .build()
+        .map_err(|err| format!("failed to start the runtime: {}", err))?;
+    let result = {
+        let _runtime = runtime.enter();
+        capture(args)
+    };
+    // without waiting for the tasks still serving the requests and the streams
+    runtime.shutdown_background();
+    result
+}
+
+/// Set up the capture and serve it until the shutdown, the errors of the setup and the
+/// fatal ones of the capture are returned, once shut down in order.
+fn capture(args: Vec<String>) -> Result<(), Error> {
+    // command line args to read a file or the standard input, to receive an RTP stream,
+    // or to be a JACK client or a PipeWire node, instead of capturing from a device
+    let input = match source_input_args(&args)? {
+        Some(input) => input,
+        None => {
+            // command line args to select the host and the input device, by name or index,
+            // or to capture the output of the output device, or of the sink, with --loopback
+            let host_name = arg_value(&args, "--host");
+            let device = arg_value(&args, "--device");
+            let loopback = args.iter().any(|arg| arg == "--loopback");
+            let (dev, loopback_config) = select_host(host_name.as_deref()).and_then(|host| {
+                if loopback {
+                    loopback::select_loopback_device(&host, device.as_deref())
+                        .map(|loopback| (loopback.device, loopback.config))
+                        .map_err(Error::Setup)
+                } else {
+                    select_input_device(&host, device.as_deref()).map(|dev| (dev, None))
+                }
+            })?;
+
+            // command line args to select the sample format, channels and sample rate,
+            // validated against the supported input formats of the device,
+            // but for the fixed format of a loopback
+            let sample_config = match loopback_config {
+                Some(config) => config,
+                None => select_input_config(&dev, requested_input_config(&args)?),
+            };
+            Input::Device(InputMonitor::new(dev, sample_config))
+        }
+    };
+    let sample_config = input.config();
+    let sample_rate = sample_config.sample_rate().0;
+
+    // command line args to select and reorder the input channels, to downmix them
+    // and to apply a gain, before the metering, the recording and the streaming
+    let mut mix = channel_mix_args(&args, sample_config.channels() as usize)?;
+    // command line arg to remove the DC offset, before the metering and the recording
+    if args.iter().any(|arg| arg == "--dc-block") {
+        mix = mix.with_dc_blocker(dc_offset::DEFAULT_CUTOFF, sample_rate);
+    }
+    let num_channels = mix.output_channels() as u16;
+    let gains = mix.gains().clone();
+    let output_config = cpal::SupportedStreamConfig::new(
+        num_channels,
+        sample_config.sample_rate(),
+        *sample_config.buffer_size(),
+        sample_config.sample_format(),
+    );
+
+    // command line arg to meter only some of the channels, the others are still
+    // recorded and streamed
+    let channels_map = channels_map_arg(&args, num_channels as usize)?;
+    let num_metered_channels = channels_map.len();
+    let layout = layout_arg(&args, num_channels as usize)?;
+
+    // command line args to record the input to a file, or to segments named by a template,
+    // while metering continues
+    let recorder = recorder_args(&args, &output_config, layout.as_deref())?;
+
+    // command line arg of the directory of the recordings served over http,
+    // by default the one of the recording if any
+    let recordings_dir = arg_value(&args, "--recordings-dir")
+        .map(PathBuf::from)
+        .or_else(|| {
+            recorder
+                .as_ref()
+                .map(|recording| recording.directory.clone())
+        });
+
+    // runtime controls of the metering, the recording, the noise suppression and the gains,
+    // the recording can only be stopped and started again in new files of a template
+    let denoise = denoise_arg(&args, sample_rate)?;
+    // command line args of the automatic gain control of the streams and the monitor output,
+    // and of the noise gate of the recordings too
+    let (agc_config, gate_config) =
+        agc_args(&args).and_then(|agc_config| Ok((agc_config, gate_args(&args)?)))?;
+    // command line args of the filters of the metering and of the output
+    let (meter_filter, output_filter) = filter_args(&args, num_channels as usize, sample_rate)?;
+    let controls = Controls::new(
+        gains.clone(),
+        recorder.is_some() && arg_value(&args, "--record-template").is_some(),
+        denoise,
+    );
+    let recording_controls = controls.clone();
+    let metering_controls = controls.clone();
+    let tui_controls = controls.clone();
+    // command line arg of the credentials required by the http server, or from the environment
+    // not to show them in the process list, and to leave the endpoints of the levels open,
+    // the control api is disabled without them
+    let auth = arg_value(&args, "--auth")
+        .or_else(|| std::env::var("AUDIO_IN_STREAM_AUTH").ok())
+        .map(|auth| Auth::parse(&auth))
+        .transpose()?;
+    let auth_open_levels = args.iter().any(|arg| arg == "--auth-open-levels");
+    // command line arg of the origins of the browser dashboards hosted elsewhere,
+    // repeatable for multiple origins, or `*` for any
+    let cors_origins = arg_values(&args, "--cors-origin");
+    let cors = if cors_origins.is_empty() {
+        None
+    } else {
+        Some(Cors::new(cors_origins)?)
+    };
+    if auth_open_levels && auth.is_none() {
+        return Err(Error::Setup(String::from(
+            "--auth-open-levels requires --auth",
+        )));
+    }
+
+    // command line args to keep the last seconds of the input in memory,
+    // saved on demand by the http api or the terminal interface
+    let snapshot = snapshot_args(&args, &output_config)?;
+    let snapshot_writer = snapshot.as_ref().map(|(snapshot, _)| snapshot.clone());
+    // command line arg of the duration of the envelope of the input kept for the waveform
+    let waveform_duration = arg_value(&args, "--waveform-history").map_or(
+        Ok(waveform::DEFAULT_DURATION),
+        |duration| match parse_duration(&duration)?.as_secs_f64() {
+            seconds if seconds > 0.0 => Ok(seconds),
+            _ => Err(format!("invalid waveform history duration '{}'", duration)),
+        },
+    )?;
+    let waveform = WaveformHistory::new(waveform_duration, num_channels as usize, sample_rate);
+    let waveform_writer = waveform.clone();
+    // command line arg of the duration of the statistics of each second of the levels
+    // kept for the history api
+    let level_history = arg_value(&args, "--level-history")
+        .map(|duration| match parse_duration(&duration)?.as_secs_f64() {
+            seconds if seconds > 0.0 => Ok(LevelHistory::new(seconds)),
+            _ => Err(format!("invalid level history duration '{}'", duration)),
+        })
+        .transpose()?;
+    let level_history_writer = level_history.clone();
+    let tui_snapshot = snapshot.clone();
+
+    // the audio thread of the host only copies the input into the sample ring,
+    // read by the threads of the metering, the recording and the streaming
+    let ring = SampleRing::new(
+        num_channels as usize,
+        sample_rate,
+        (sample_rate / ring::DEFAULT_CHUNKS_PER_SECOND) as usize,
+        ring::DEFAULT_CHUNKS,
+    );
+    let level_snapshot = LevelSnapshot::default();
+    let level_snapshot_writer = level_snapshot.clone();
+    let levels_broadcast: Broadcast<Levels> = Broadcast::default();
+    let levels_broadcast_sender = levels_broadcast.clone();
+    let samples_broadcast: Broadcast<Vec<f32>> = Broadcast::default();
+    let samples_broadcast_sender = samples_broadcast.clone();
+    // warnings are shown in the terminal interface once started
+    let tui_sender: Arc<OnceLock<TuiSender>> = Arc::default();
+    // graceful shutdown on SIGINT/SIGTERM (Ctrl-C), done by the main thread
+    // with the fatal error sent to it if any, a second signal exits right away
+    let (shutdown_sender, shutdown) = std::sync::mpsc::channel::<Result<(), Error>>();
+    {
+        let shutdown_sender = shutdown_sender.clone();
+        let mut shutting_down = false;
+        ctrlc::set_handler(move || {
+            if shutting_down {
+                std::process::exit(130);
+            }
+            shutting_down = true;
+            shutdown_sender.send(Ok(())).ok();
+        })
+        .map_err(|err| format!("failed to set the Ctrl-C handler: {}", err))?;
+    }
+    // health of the capture served by `/healthz` and `/readyz`
+    let recording_status = if recorder.is_some() {
+        RecordingStatus::Recording
+    } else {
+        RecordingStatus::Disabled
+    };
+    let health = Arc::new(Health::new(health_args(&args)?, recording_status));
+    // errors of the input and panics of the threads processing it, reported instead of
+    // ending them silently, the fatal ones shut down so the server does not serve stale levels
+    // and are printed once shut down, the errors of the outputs are warnings; they are events
+    // once the event queue, fed by some of the outputs, is started
+    let supervisor_event_queue: Arc<OnceLock<EventQueue>> = Arc::default();
+    let supervisor = {
+        let health = Arc::clone(&health);
+        let shutdown_sender = shutdown_sender.clone();
+        let supervisor_event_queue = Arc::clone(&supervisor_event_queue);
+        let supervisor_tui = Arc::clone(&tui_sender);
+        Supervisor::start(move |err| {
+            if !err.is_fatal() {
+                print_message(supervisor_tui.get(), format!("warning: {}", err));
+            }
+            health.record_error(&err);
+            let timestamp = unix_time(SystemTime::now());
+            let event = match &err {
+                Error::Stream(_) | Error::Input(_) => Some(Event::DeviceError {
+                    timestamp,
+                    message: err.to_string(),
+                }),
+                Error::Panic { thread, message } => {
+                    if thread == "recording" {
+                        health.set_recording(RecordingStatus::Failed);
+                    }
+                    Some(Event::ThreadPanic {
+                        timestamp,
+                        thread: thread.clone(),
+                        message: message.clone(),
+                    })
+                }
+                _ => None,
+            };
+            if let (Some(event), Some(event_queue)) = (event, supervisor_event_queue.get()) {
+                event_queue.emit(event);
+            }
+            if err.is_fatal() {
+                shutdown_sender.send(Err(err)).ok();
+            }
+        })
+    };
+
+    start_icecast_source(
+        &args,
+        num_channels,
+        sample_rate,
+        &samples_broadcast,
+        &supervisor,
+    )?;
+    start_rtmp(
+        &args,
+        num_channels,
+        sample_rate,
+        &samples_broadcast,
+        &supervisor,
+    )?;
+    start_srt(
+        &args,
+        num_channels,
+        sample_rate,
+        &samples_broadcast,
+        &supervisor,
+    )?;
+    #[cfg(any(feature = "aac", feature = "opus"))]
+    let hls = start_hls(
+        &args,
+        num_channels,
+        sample_rate,
+        &samples_broadcast,
+        &supervisor,
+    )?;
+    #[cfg(not(any(feature = "aac", feature = "opus")))]
+    start_hls(
+        &args,
+        num_channels,
+        sample_rate,
+        &samples_broadcast,
+        &supervisor,
+    )?;
+    let rtp_sdp = start_rtp(&args, num_channels, sample_rate, &samples_broadcast)?;
+    // command line args of the unit, range and width of the meters,
+    // and for meters of only ASCII characters
+    let meter_scale = meter_scale_args(&args)?;
+    // command line args of the meters and the analyses of the metered channels
+    let (mut metering, mut leq_log) = metering_args(
+        &args,
+        &output_config,
+        &channels_map,
+        layout.clone(),
+        meter_filter,
+    )?;
+    metering = metering.with_gains(gains.clone());
+    let mut level_log = level_log_args(&args)?;
+
+    // audio events are handled from their own thread, not to block the audio thread,
+    // POSTed to each `--webhook` url from a thread for each one,
+    // logged with the levels to the `--sqlite` database, published to the MQTT broker,
+    // counted in the metrics pushed to InfluxDB and StatsD, passed to the `--script`
+    // and streamed to the subscribers of the gRPC api
+    let pending_events = PendingEvents::default();
+    let webhooks = Webhooks::start(
+        &arg_values(&args, "--webhook"),
+        pending_events.clone(),
+        supervisor.clone(),
+    )?;
+    let level_database =
+        start_level_database(&args, &levels_broadcast, &pending_events, &supervisor)?;
+    let mqtt = start_mqtt(&args, &channels_map, &levels_broadcast, &supervisor)?;
+    let (metric_push, metrics_interval) = metric_push_args(&args)?;
+    let metric_push = Arc::new(metric_push);
+    let event_metric_push = Arc::clone(&metric_push);
+    let on_silence = arg_value(&args, "--on-silence");
+    let event_queue_tui = Arc::clone(&tui_sender);
+    // the alarms of the script are events too
+    let script_event_queue: Arc<OnceLock<EventQueue>> = Arc::default();
+    let script = start_script(
+        &args,
+        &levels_broadcast,
+        &controls,
+        Arc::clone(&script_event_queue),
+        Arc::clone(&tui_sender),
+    )?;
+    let events_broadcast = Broadcast::<Event>::default();
+    let event_queue_broadcast = events_broadcast.clone();
+    let event_queue = EventQueue::start(pending_events.clone(), move |event| {
+        report_event(&event, on_silence.as_deref(), event_queue_tui.get());
+        event_queue_broadcast.send(event.clone());
+        webhooks.send(&event);
+        if let Some(level_database) = &level_database {
+            level_database(&event);
+        }
+        if let Some(mqtt) = &mqtt {
+            mqtt(&event);
+        }
+        if let Err(err) = event_metric_push.send_event(&event) {
+            print_message(event_queue_tui.get(), format!("warning: {}", err));
+        }
+        if let Some(script) = &script {
+            script(&event);
+        }
+    });
+    script_event_queue.set(event_queue.clone()).ok();
+    supervisor_event_queue.set(event_queue.clone()).ok();
+    start_osc(&args, &levels_broadcast, Arc::clone(&tui_sender))?;
+    start_midi(
+        &args,
+        num_metered_channels,
+        &levels_broadcast,
+        Arc::clone(&tui_sender),
+    )?;
+
+    let metrics = Arc::new(Metrics::new(
+        &channels_map,
+        &[METERING_CONSUMER, RECORDING_CONSUMER, STREAMING_CONSUMER],
+    ));
+    let metrics_sender = Arc::clone(&metrics);
+    // the metrics of `/metrics` pushed every interval, warning once while a push fails
+    if !metric_push.is_empty() {
+        let metrics = Arc::clone(&metrics);
+        let level_snapshot = level_snapshot.clone();
+        let push_tui = Arc::clone(&tui_sender);
+        tokio::spawn(async move {
+            let mut last_error = None;
+            loop {
+                tokio::time::sleep(Duration::from_secs_f64(metrics_interval)).await;
+                let families = metrics.families(level_snapshot.load().as_deref());
+                let result = metric_push
+                    .push(&families, unix_time(SystemTime::now()))
+                    .await;
+                if let Err(err) = &result {
+                    if last_error.as_ref() != Some(err) {
+                        print_message(push_tui.get(), format!("warning: {}", err));
+                    }
+                }
+                last_error = result.err();
+            }
+        });
+    }
+    let device_name = input.name();
+    let cpal_sample_format = sample_config.sample_format();
+    let sample_format = format!("{:?}", cpal_sample_format).to_lowercase();
+    let stream_event_queue = event_queue.clone();
+    let error_supervisor = supervisor.clone();
+    let error_tui = Arc::clone(&tui_sender);
+    let tui_shutdown_sender = shutdown_sender.clone();
+    let is_tty = atty::is(atty::Stream::Stdout);
+    let mut printed_lines = 0;
+    // terminal interface on a terminal, unless disabled for the lines of text,
+    // started once the input stream is
+    let use_tui = is_tty && !args.iter().any(|arg| arg == "--no-tui");
+    // command line arg for meters without colors, which are also disabled
+    // by the NO_COLOR environment variable or out of a terminal
+    let color = is_tty
+        && !args.iter().any(|arg| arg == "--no-color")
+        && std::env::var_os("NO_COLOR").is_none_or(|no_color| no_color.is_empty());
+    let metering_tui = Arc::clone(&tui_sender);
+    let metering_health = Arc::clone(&health);
+    let command_tui = Arc::clone(&tui_sender);
+    let reset_peaks = Arc::new(AtomicBool::new(false));
+    let tui_reset_peaks = Arc::clone(&reset_peaks);
+
+    // command line arg to bind the http server, repeatable for multiple addresses,
+    // and args to serve all of them over HTTPS
+    let mut listen_addrs = arg_values(&args, "--listen");
+    if listen_addrs.is_empty() {
+        listen_addrs.push(String::from(DEFAULT_LISTEN_ADDR));
+    }
+    let tls_config = tls_args(&args)?;
+    let listeners = listen_addrs
+        .iter()
+        .map(|listen_addr| http::bind(listen_addr))
+        .collect::<Result<Vec<_>, _>>()?;
+    // command line arg to bind the gRPC api, repeatable for multiple addresses,
+    // over HTTPS too with the args of the http server
+    let grpc_listeners = arg_values(&args, "--grpc-listen")
+        .iter()
+        .map(|listen_addr| http::bind(listen_addr))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // recording, finalized once the input stream is stopped and the ring is drained
+    // and stopped early by the retention policy, before the disk fills,
+    // out of the windows of the schedule, or stopped by the controls,
+    // the recording is paused in between files
+    let recording = recorder.map(|recording| {
+        let Recording {
+            mut session,
+            on_recording,
+            s3_config,
+            ..
+        } = recording;
+        session = session.with_filter(output_filter.clone());
+        if let Some(config) = gate_config {
+            session = session.with_gate(NoiseGate::new(config, num_channels as usize, sample_rate));
+        }
+        let mut ring_reader = ring.reader();
+        let metrics = Arc::clone(&metrics);
+        let recording_event_queue = event_queue.clone();
+        let recording_health = Arc::clone(&health);
+        let uploader = s3_config
+            .map(|s3_config| S3Uploader::start(s3_config, event_queue.clone(), supervisor.clone()));
+        let recording_supervisor = supervisor.clone();
+        supervisor.spawn("recording", move || {
+            let mut samples = Vec::new();
+            while let Some(chunk) = ring_reader.read(&mut samples) {
+                metrics.record_overruns(RECORDING_CONSUMER, chunk.overruns);
+                let update = match session.process(
+                    &mut samples,
+                    chunk.timestamp,
+                    recording_controls.is_recording(),
+                ) {
+                    Ok(update) => update,
+                    // the recording is finalized as far as written, and the capture shut down
+                    Err(err) => {
+                        recording_health.set_recording(RecordingStatus::Failed);
+                        recording_supervisor.report(Error::Input(format!(
+                            "failed to write the recording: {}",
+                            err
+                        )));
+                        break;
+                    }
+                };
+                recording_health.set_recording(if update.paused {
+                    RecordingStatus::Paused
+                } else {
+                    RecordingStatus::Recording
+                });
+                for recording in &update.completed {
+                    complete_recording(
+                        recording,
+                        on_recording.as_deref(),
+                        uploader.as_ref(),
+                        &recording_event_queue,
+                    );
+                }
+                for deletion in update.deleted {
+                    recording_event_queue.emit(Event::RecordingDeleted {
+                        timestamp: unix_time(SystemTime::now()),
+                        path: deletion.path.to_string_lossy().into_owned(),
+                        reason: deletion.reason,
+                    });
+                }
+                if let Some(reason) = update.stopped {
+                    recording_health.set_recording(RecordingStatus::Stopped);
+                    recording_event_queue.emit(Event::RecordingStopped {
+                        timestamp: unix_time(SystemTime::now()),
+                        reason,
+                    });
+                    break;
+                }
+            }
+            match session.finalize() {
+                Ok(completed) => {
+                    for recording in &completed {
+                        complete_recording(
+                            recording,
+                            on_recording.as_deref(),
+                            uploader.as_ref(),
+                            &recording_event_queue,
+                        );
+                    }
+                }
+                Err(err) => {
+                    recording_health.set_recording(RecordingStatus::Failed);
+                    recording_supervisor.report(Error::Input(format!(
+                        "failed to finalize the recording: {}",
+                        err
+                    )));
+                }
+            }
+        })
+    });
+
+    // live capture for the streaming responses, the Icecast source client, the snapshots
+    // and the waveform, the filters, the LV2 plugins, the noise suppression, the noise gate
+    // and the automatic gain control only process the streams
+    {
+        let mut ring_reader = ring.reader();
+        let metrics = Arc::clone(&metrics);
+        let mut stream_process =
+            output_processors(&args, &output_filter, num_channels as usize, sample_rate).and_then(
+                |processors| {
+                    stream_process(
+                        processors,
+                        denoise,
+                        gate_config,
+                        agc_config,
+                        &controls,
+                        num_channels as usize,
+                        sample_rate,
+                    )
+                },
+            )?;
+        supervisor.spawn("streaming", move || {
+            let mut samples = Vec::new();
+            while let Some(chunk) = ring_reader.read(&mut samples) {
+                metrics.record_overruns(STREAMING_CONSUMER, chunk.overruns);
+                if let Some(snapshot_writer) = &snapshot_writer {
+                    snapshot_writer.push(&samples, chunk.timestamp);
+                }
+                waveform_writer.push(&samples);
+                stream_process(&mut samples);
+                if samples_broadcast_sender.has_subscribers() {
+                    metrics.record_dropped_buffers(samples_broadcast_sender.send(samples.clone()));
+                }
+            }
+        });
+    }
+
+    // command line args of the mel spectrogram and MFCC frames of the metered channels
+    // mixed to mono, pushed to /ws/features and written to NPY files
+    let features_broadcast = Broadcast::<FeatureFrame>::default();
+    let features_thread = match features_args(&args, sample_rate)? {
+        Some(config) => {
+            let create_npy = |name: &str, columns| {
+                arg_value(&args, name)
+                    .map(PathBuf::from)
+                    .map(|path| {
+                        NpyWriter::create(&path, columns).map_err(|err| {
+                            format!(
+                                "failed to create the NPY file '{}': {}",
+                                path.display(),
+                                err
+                            )
+                        })
+                    })
+                    .transpose()
+            };
+            let (mut mel_npy, mut mfcc_npy) = create_npy("--mel-npy", config.mel_bands)
+                .and_then(|mel_npy| Ok((mel_npy, create_npy("--mfcc-npy", config.mfcc)?)))?;
+            let mut extractor = FeatureExtractor::new(config, sample_rate);
+            let mut ring_reader = ring.reader();
+            let features_broadcast = features_broadcast.clone();
+            let channels_map = channels_map.clone();
+            let features_tui = Arc::clone(&tui_sender);
+            Some(supervisor.spawn("features", move || {
+                let mut samples = Vec::new();
+                while let Some(chunk) = ring_reader.read(&mut samples) {
+                    for frame in extractor.process_channels(
+                        &samples,
+                        num_channels as usize,
+                        &channels_map,
+                        chunk.timestamp,
+                    ) {
+                        let written = mel_npy
+                            .as_mut()
+                            .map_or(Ok(()), |npy| npy.write_row(&frame.mel))
+                            .and_then(|_| {
+                                mfcc_npy
+                                    .as_mut()
+                                    .map_or(Ok(()), |npy| npy.write_row(&frame.mfcc))
+                            });
+                        if let Err(err) = written {
+                            print_message(
+                                features_tui.get(),
+                                format!("error: failed to write the NPY file: {}, no more frames written", err),
+                            );
+                            mel_npy = None;
+                            mfcc_npy = None;
+                        }
+                        if features_broadcast.has_subscribers() {
+                            features_broadcast.send(frame);
+                        }
+                    }
+                }
+            }))
+        }
+        None => None,
+    };
+
+    // command line args to play the live capture on an output device, a confidence monitor
+    let _monitor_output = monitor_output_args(&args).and_then(|monitor| {
+        monitor
+            .map(|(device, gain, delay)| {
+                let monitor_tui = Arc::clone(&tui_sender);
+                MonitorOutput::start(
+                    &device,
+                    ring.reader(),
+                    gain,
+                    delay,
+                    stream_process(
+                        output_processors(
+                            &args,
+                            &output_filter,
+                            num_channels as usize,
+                            sample_rate,
+                        )?,
+                        denoise,
+                        gate_config,
+                        agc_config,
+                        &controls,
+                        num_channels as usize,
+                        sample_rate,
+                    )?,
+                    move |err| {
+                        print_message(
+                            monitor_tui.get(),
+                            format!("error: monitor output stream error: {}", err),
+                        )
+                    },
+                )
+            })
+            .transpose()
+            .map_err(Error::Setup)
+    })?;
+
+    // command line args to play a test signal on an output device, for loopback tests
+    let _generator_output = generator_args(&args).and_then(|generator| {
+        generator
+            .map(|(device, signal)| {
+                let generator_tui = Arc::clone(&tui_sender);
+                GeneratorOutput::start(&device, signal, move |err| {
+                    print_message(
+                        generator_tui.get(),
+                        format!("error: generator output stream error: {}", err),
+                    )
+                })
+            })
+            .transpose()
+            .map_err(Error::Setup)
+    })?;
+
+    // command line args to measure the round-trip latency, of chirps played on an output
+    // device and detected on the input, exiting with the statistics after the trials
+    let _latency_output = match latency_args(&args)? {
+        Some((device, trials_count, interval)) => {
+            let (emissions_sender, emissions) = std::sync::mpsc::channel();
+            let latency_tui = Arc::clone(&tui_sender);
+            let latency_output =
+                LatencyOutput::start(&device, interval, emissions_sender, move |err| {
+                    print_message(
+                        latency_tui.get(),
+                        format!("error: latency output stream error: {}", err),
+                    )
+                })?;
+            let mut ring_reader = ring.reader();
+            let latency_tui = Arc::clone(&tui_sender);
+            let shutdown_sender = shutdown_sender.clone();
+            supervisor.spawn("latency", move || {
+                let mut trials = LatencyTrials::new(sample_rate, interval);
+                let mut stats = LatencyStats::default();
+                let mut trial = 0;
+                let mut samples = Vec::new();
+                'trials: while let Some(chunk) = ring_reader.read(&mut samples) {
+                    for emission in emissions.try_iter() {
+                        trials.push_emission(emission);
+                    }
+                    for latency in
+                        trials.process(&samples, num_channels as usize, chunk.timestamp)
+                    {
+                        trial += 1;
+                        let message = match latency {
+                            Some(latency) => {
+                                stats.push(latency);
+                                format!(
+                                    "latency trial {}/{}: {:.1} ms",
+                                    trial,
+                                    trials_count,
+                                    latency * 1000.0
+                                )
+                            }
+                            None => format!(
+                                "latency trial {}/{}: chirp not detected",
+                                trial, trials_count
+                            ),
+                        };
+                        print_message(latency_tui.get(), message);
+                        if trial < trials_count {
+                            continue;
+                        }
+                        match stats.summary() {
+                            Some((min, avg, max, jitter)) => {
+                                print_message(
+                                    latency_tui.get(),
+                                    format!(
+                                        "round-trip latency over {}/{} trials: min {:.1} ms, avg {:.1} ms, max {:.1} ms, jitter {:.1} ms",
+                                        stats.len(),
+                                        trials_count,
+                                        min * 1000.0,
+                                        avg * 1000.0,
+                                        max * 1000.0,
+                                        jitter * 1000.0
+                                    ),
+                                );
+                                shutdown_sender.send(Ok(())).ok();
+                            }
+                            None => {
+                                shutdown_sender
+                                    .send(Err(Error::Input(format!(
+                                        "chirp not detected in any of the {} trials",
+                                        trials_count
+                                    ))))
+                                    .ok();
+                            }
+                        }
+                        break 'trials;
+                    }
+                }
+            });
+            Some(latency_output)
+        }
+        None => None,
+    };
+
+    // metering of each input buffer
+    let mut ring_reader = ring.reader();
+    supervisor.spawn("metering", move || {
+        let mut samples = Vec::new();
+        while let Some(chunk) = ring_reader.read(&mut samples) {
+            metrics_sender.record_overruns(METERING_CONSUMER, chunk.overruns);
+            if metering_controls.is_meter_paused() {
+                continue;
+            }
+            if reset_peaks.swap(false, Ordering::Relaxed) {
+                metering.reset_peaks();
+            }
+            let MeteringFrame {
+                levels,
+                meter_readings,
+                clippings,
+                events,
+                calibrated,
+                ended_leq_windows,
+            } = metering.process(&samples, chunk.timestamp);
+            for event in events {
+                stream_event_queue.emit(event);
+            }
+            let calibration = metering.calibration();
+            if let (Some(calibration), Some(dbov)) = (calibration, calibrated) {
+                print_message(
+                    metering_tui.get(),
+                    format!(
+                        "calibration: {:.1} dB SPL at {:.1} dBov, use --calibrate {}@{:.1} next time",
+                        calibration.reference_spl(),
+                        dbov,
+                        calibration.reference_spl(),
+                        dbov
+                    ),
+                );
+            }
+            if let Some(ref mut log) = leq_log {
+                for &window in &ended_leq_windows {
+                    for channel in &levels.channels {
+                        if let Some(ref channel_leq) = channel.leq {
+                            if let Err(err) =
+                                log.write(levels.timestamp, channel.channel, window, channel_leq)
+                            {
+                                print_message(
+                                    metering_tui.get(),
+                                    format!("warning: failed to write the Leq log: {}", err),
+                                );
+                            }
+                        }
+                    }
+                }
+            }
+
+            let mut lines = Vec::new();
+            if let Some(calibration) =
+                calibration.filter(|calibration| calibration.offset().is_none())
+            {
+                lines.push(format!(
+                    "calibration: measuring the {:.1} dB SPL calibrator tone...",
+                    calibration.reference_spl()
+                ));
+            }
+            lines.extend(levels.faults.iter().map(fault_info));
+            lines.extend(correlation_info(&levels.correlation));
+            lines.extend(mid_side_info(&levels.mid_side));
+            lines.extend(leq_info(&levels));
+            if let Some(ref spectrum) = levels.spectrum {
+                lines.extend(spectrum_info(spectrum, &channels_map, sample_rate));
+            }
+            if let Some(ref octave_bands) = levels.octave_bands {
+                lines.extend(octave_bands_info(octave_bands, &channels_map));
+            }
+            lines.extend(analysis_info(&levels.analysis));
+            lines.extend(pitch_info(&levels.pitch));
+            lines.extend(speech_info(&levels));
+
+            metrics_sender.record_buffer(&clippings);
+            metering_health.record_buffer();
+
+            // serialized by the consumers, out of the audio thread
+            let levels = Arc::new(levels);
+
+            match metering_tui.get() {
+                Some(tui) => tui.frame(TuiFrame {
+                    levels: Arc::clone(&levels),
+                    meter_readings,
+                    lines,
+                }),
+                // not displayed until the interface starts
+                None if use_tui => {}
+                None => {
+                    let weighting = metering.config().weighting;
+                    let mut info_lines = input_buffer_info(
+                        metering.source_data(),
+                        sample_rate,
+                        &levels,
+                        &meter_readings,
+                        calibration.and_then(|calibration| calibration.offset()),
+                        &meter_scale,
+                        color,
+                    );
+                    if weighting != Weighting::Z {
+                        info_lines[0].push_str(&format!(", {}-weighted levels", weighting.name()));
+                    }
+                    info_lines.extend(lines);
+
+                    if printed_lines > 0 && is_tty {
+                        // up to the first line printed for the previous buffer
+                        print!("\x1b[{}A", printed_lines);
+                    }
+
+                    for line in &info_lines {
+                        print!("{}", line);
+
+                        if is_tty {
+                            // clear the rest of the line
+                            print!("\x1b[0K");
+                        }
+
+                        println!();
+                    }
+                    printed_lines = info_lines.len();
+                }
+            }
+            if levels_broadcast_sender.has_subscribers() {
+                metrics_sender
+                    .record_dropped_buffers(levels_broadcast_sender.send(Arc::clone(&levels)));
+            }
+
+            if let Some(level_history) = &level_history_writer {
+                level_history.push(&levels);
+            }
+            if let Some(ref mut log) = level_log {
+                if let Err(err) = log.push(&levels) {
+                    print_message(
+                        metering_tui.get(),
+                        format!("warning: failed to write the level log: {}", err),
+                    );
+                }
+            }
+            level_snapshot_writer.store(levels);
+        }
+    });
+
+    // the input stream, or the receiving thread, is kept alive until the shutdown
+    let stream: Box<dyn std::any::Any> = match input {
+        Input::Device(monitor) => {
+            Box::new(monitor.capture(ring.writer(mix), move |err| error_supervisor.report(err))?)
+        }
+        Input::Receiver(receiver) => Box::new(receiver.start(ring.writer(mix), move |message| {
+            print_message(error_tui.get(), format!("warning: {}", message))
+        })),
+        // shut down at the end of the file, in real time unless --no-realtime
+        Input::File(file_input) => Box::new(file_input.start(
+            ring.writer(mix),
+            !args.iter().any(|arg| arg == "--no-realtime"),
+            move |result| match result {
+                Ok(()) => {
+                    print_message(error_tui.get(), String::from("end of the input"));
+                    shutdown_sender.send(Ok(())).ok();
+                }
+                Err(err) => error_supervisor.report(Error::Input(err)),
+            },
+        )),
+        #[cfg(feature = "jack")]
+        Input::Jack(jack_input) => Box::new(jack_input.start(ring.writer(mix), move |err| {
+            error_supervisor.report(Error::Input(err))
+        })?),
+        #[cfg(feature = "pipewire")]
+        Input::PipeWire(pipewire_input) => {
+            Box::new(pipewire_input.start(ring.writer(mix), move |err| {
+                error_supervisor.report(Error::Input(err))
+            })?)
+        }
+    };
+    let tui = if use_tui {
+        let header = format!(
+            "device '{}', {} at {} Hz, {} channel(s)",
+            device_name, sample_format, sample_rate, num_channels
+        );
+        match Tui::start(
+            header,
+            meter_scale,
+            color,
+            controls.clone(),
+            move |command| match command {
+                TuiCommand::Quit => {
+                    tui_shutdown_sender.send(Ok(())).ok();
+                }
+                TuiCommand::ResetPeaks => tui_reset_peaks.store(true, Ordering::Relaxed),
+                TuiCommand::Snapshot => {
+                    let message = match &tui_snapshot {
+                        Some((snapshot, directory)) => match snapshot.save(directory) {
+                            Ok(path) => format!("snapshot saved to '{}'", path.display()),
+                            Err(err) => format!("error: failed to save the snapshot: {}", err),
+                        },
+                        None => String::from(
+                            "error: snapshots are disabled, enable them with --snapshot",
+                        ),
+                    };
+                    print_message(command_tui.get(), message);
+                }
+                TuiCommand::Control(command) => {
+                    if let Err(err) = tui_controls.apply(command) {
+                        print_message(command_tui.get(), format!("error: {}", err));
+                    }
+                }
+            },
+        ) {
+            Ok(tui) => {
+                tui_sender.set(tui.sender()).ok();
+                Some(tui)
+            }
+            Err(err) => {
+                return Err(Error::Setup(format!(
+                    "failed to start the terminal interface: {}",
+                    err
+                )))
+            }
+        }
+    } else {
+        None
+    };
+    event_queue.emit(Event::StreamStart {
+        timestamp: unix_time(SystemTime::now()),
+        device: device_name,
+        sample_format,
+        sample_rate,
+        channel_count: num_channels as usize,
+    });
+
+    // http server and gRPC api, requests handled by the tasks of the runtime
+    let shutdown_levels_broadcast = levels_broadcast.clone();
+    let shutdown_samples_broadcast = samples_broadcast.clone();
+    let shutdown_features_broadcast = features_broadcast.clone();
+    let grpc_router = grpc::MonitorService::new(
+        levels_broadcast.clone(),
+        events_broadcast.clone(),
+        controls.clone(),
+        Arc::clone(&health),
+        auth.clone(),
+        auth_open_levels,
+    )
+    .into_router();
+    let http_state = Arc::new(HttpState {
+        level_snapshot,
+        levels_broadcast,
+        samples_broadcast,
+        features_broadcast: features_thread.is_some().then_some(features_broadcast),
+        metrics,
+        num_channels,
+        sample_rate,
+        snapshot,
+        waveform,
+        level_history,
+        rtp_sdp,
+        #[cfg(any(feature = "aac", feature = "opus"))]
+        hls,
+        recordings_dir,
+        recordings_delete: args.iter().any(|arg| arg == "--recordings-delete"),
+        controls,
+        auth,
+        auth_open_levels,
+        cors,
+        health,
+        supervisor: supervisor.clone(),
+    });
+    let router = http::router(http_state);
+    // the gRPC clients only speak HTTP/2, negotiated over TLS
+    let grpc_tls_config = tls_config.as_ref().map(|tls_config| {
+        let mut grpc_tls_config = rustls::ServerConfig::clone(tls_config);
+        grpc_tls_config.alpn_protocols = vec![b"h2".to_vec()];
+        Arc::new(grpc_tls_config)
+    });
+    let routers = listeners
+        .into_iter()
+        .map(|listener| (listener, router.clone(), tls_config.clone()))
+        .chain(
+            grpc_listeners
+                .into_iter()
+                .map(|listener| (listener, grpc_router.clone(), grpc_tls_config.clone())),
+        );
+    for (listener, router, tls_config) in routers {
+        http::serve(listener, router, tls_config, &supervisor)?;
+    }
+
+    let result = shutdown.recv().unwrap_or(Ok(()));
+    // the warnings shown by the terminal interface are kept in the terminal once restored
+    if let Some(tui) = tui {
+        for message in tui.stop() {
+            eprintln!("{}", message);
+        }
+    }
+
+    // stop the input stream, so the recording is complete when finalized
+    drop(stream);
+    if let Some(recording) = recording {
+        recording.join().ok();
+    }
+    // the NPY files complete
+    if let Some(features_thread) = features_thread {
+        features_thread.join().ok();
+    }
+
+    // end the streaming responses, WebSocket/SSE connections and gRPC calls
+    shutdown_levels_broadcast.close();
+    shutdown_samples_broadcast.close();
+    shutdown_features_broadcast.close();
+
+    event_queue.emit(Event::StreamStop {
+        timestamp: unix_time(SystemTime::now()),
+    });
+    pending_events.wait(EXIT_EVENTS_TIMEOUT);
+    events_broadcast.close();
+    // the first fatal error, the ones while shutting down are printed
+    shutdown
+        .try_iter()
+        .fold(result, |result, next| match (result, next) {
+            (Err(err), Err(next)) => {
+                eprintln!("error: {}", next);
+                Err(err)
+            }
+            (result, next) => result.and(next),
+        })
 
     // tested with 'speaker-test -c2 -l1' in a loopback
     // (audio output connected to the audio input)
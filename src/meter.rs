@@ -0,0 +1,248 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Ballistics of the level meters: attack and release of the displayed level,
+//! peak hold and over indicator.
+
+use crate::ChannelData;
+
+/// seconds
+pub const DEFAULT_ATTACK: f32 = 0.01;
+pub const DEFAULT_RELEASE: f32 = 0.3;
+pub const DEFAULT_PEAK_HOLD: f32 = 2.0;
+
+/// fall rate of the held peak once the hold time is over, in dB per second
+const PEAK_DECAY: f32 = 20.0;
+
+/// seconds, a VU meter reaches 99% of a step in 300 ms
+const VU_INTEGRATION: f32 = 0.3;
+/// seconds, a 10 ms burst reads 2 dB below its steady level in a quasi-peak programme meter
+const PPM_INTEGRATION: f32 = 0.01;
+/// dB per second, the return of a quasi-peak programme meter of 24 dB in 2.8 s
+const PPM_FALL_RATE: f32 = 24.0 / 2.8;
+/// dB per second, the return of a digital peak meter of 20 dB in 1.7 s
+const PEAK_FALL_RATE: f32 = 20.0 / 1.7;
+
+/// Level followed by the meter.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Detector {
+    /// root mean square of the samples of the input buffer
+    #[default]
+    Rms,
+    /// true peak of the input buffer
+    Peak,
+}
+
+/// Standard meters, as a detector and its ballistics.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MeterType {
+    /// RMS level with the attack and release times of `BallisticsConfig::default()`
+    #[default]
+    Rms,
+    /// volume unit meter, IEC 60268-17: RMS level with a 300 ms integration time
+    Vu,
+    /// quasi-peak programme meter, EBU / IEC 60268-10 type IIb:
+    /// 10 ms integration time and a return of 24 dB in 2.8 s
+    Ppm,
+    /// digital peak meter, IEC 60268-18: instant attack and a return of 20 dB in 1.7 s
+    Peak,
+}
+
+impl MeterType {
+    pub fn parse(meter_type: &str) -> Result<Self, String> {
+        match meter_type {
+            "rms" => Ok(MeterType::Rms),
+            "vu" => Ok(MeterType::Vu),
+            "ppm" => Ok(MeterType::Ppm),
+            "peak" => Ok(MeterType::Peak),
+            _ => Err(format!(
+                "invalid meter type '{}', expected one of: vu, ppm, peak, rms",
+                meter_type
+            )),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            MeterType::Rms => "RMS",
+            MeterType::Vu => "VU",
+            MeterType::Ppm => "PPM",
+            MeterType::Peak => "peak",
+        }
+    }
+
+    /// Ballistics of the meter, with the default peak hold time.
+    pub fn ballistics(self) -> BallisticsConfig {
+        let default = BallisticsConfig::default();
+        match self {
+            MeterType::Rms => default,
+            MeterType::Vu => {
+                // symmetrical first order response, 99% of a step is 4.6 time constants
+                let time_constant = VU_INTEGRATION / 100_f32.ln();
+                BallisticsConfig {
+                    attack: time_constant,
+                    release: time_constant,
+                    ..default
+                }
+            }
+            MeterType::Ppm => BallisticsConfig {
+                detector: Detector::Peak,
+                // time constant reaching -2 dB (79.4%) of a burst in the integration time
+                attack: -PPM_INTEGRATION / (1.0 - 10_f32.powf(-2.0 / 20.0)).ln(),
+                fall_rate: Some(PPM_FALL_RATE),
+                ..default
+            },
+            MeterType::Peak => BallisticsConfig {
+                detector: Detector::Peak,
+                attack: 0.0,
+                fall_rate: Some(PEAK_FALL_RATE),
+                ..default
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct BallisticsConfig {
+    pub detector: Detector,
+    /// time constant of the displayed level when rising, in seconds
+    pub attack: f32,
+    /// time constant of the displayed level when falling, in seconds
+    pub release: f32,
+    /// linear fall of the displayed level in dB per second, instead of the release time
+    pub fall_rate: Option<f32>,
+    /// time the peak and the over indicator are held, in seconds
+    pub peak_hold: f32,
+}
+
+impl Default for BallisticsConfig {
+    fn default() -> Self {
+        BallisticsConfig {
+            detector: Detector::Rms,
+            attack: DEFAULT_ATTACK,
+            release: DEFAULT_RELEASE,
+            fall_rate: None,
+            peak_hold: DEFAULT_PEAK_HOLD,
+        }
+    }
+}
+
+/// What the meter of a channel displays, levels are linear absolute values.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MeterReading {
+    pub level: f32,
+    pub peak: f32,
+    /// a peak reached the full scale within the hold time
+    pub over: bool,
+}
+
+#[derive(Clone, Default)]
+struct ChannelState {
+    reading: MeterReading,
+    /// seconds since the held peak was reached
+    peak_age: f32,
+    /// seconds since the last over, `None` if never
+    over_age: Option<f32>,
+}
+
+pub struct MeterBallistics {
+    config: BallisticsConfig,
+    channels: Vec<ChannelState>,
+}
+
+impl MeterBallistics {
+    pub fn new(config: BallisticsConfig, num_channels: usize) -> Self {
+        MeterBallistics {
+            config,
+            channels: vec![ChannelState::default(); num_channels],
+        }
+    }
+
+    /// Update the meters with the RMS and true peak levels of each channel
+    /// in an input buffer of the given duration, in seconds.
+    pub fn update(
+        &mut self,
+        channels: &[ChannelData],
+        peaks: &[f32],
+        duration: f32,
+    ) -> Vec<MeterReading> {
+        let config = self.config;
+        channels
+            .iter()
+            .zip(peaks)
+            .zip(&mut self.channels)
+            .map(|((channel, &peak), state)| {
+                let level = match config.detector {
+                    Detector::Rms => channel.loudness_level,
+                    Detector::Peak => peak,
+                };
+                let rising = level > state.reading.level;
+                match config.fall_rate {
+                    Some(fall_rate) if !rising => {
+                        state.reading.level = (state.reading.level
+                            * 10_f32.powf(-fall_rate * duration / 20.0))
+                        .max(level);
+                    }
+                    _ => {
+                        let time_constant = if rising {
+                            config.attack
+                        } else {
+                            config.release
+                        };
+                        let coefficient = if time_constant > 0.0 {
+                            1.0 - (-duration / time_constant).exp()
+                        } else {
+                            1.0
+                        };
+                        state.reading.level += (level - state.reading.level) * coefficient;
+                    }
+                }
+
+                if peak >= state.reading.peak {
+                    state.reading.peak = peak;
+                    state.peak_age = 0.0;
+                } else {
+                    let decay_time = (state.peak_age + duration - config.peak_hold)
+                        .min(duration)
+                        .max(0.0);
+                    state.peak_age += duration;
+                    state.reading.peak = (state.reading.peak
+                        * 10_f32.powf(-PEAK_DECAY * decay_time / 20.0))
+                    .max(peak);
+                }
+
+                if peak >= 1.0 {
+                    state.over_age = Some(0.0);
+                } else if let Some(ref mut over_age) = state.over_age {
+                    *over_age += duration;
+                }
+                state.reading.over = state
+                    .over_age
+                    .is_some_and(|over_age| over_age < config.peak_hold);
+
+                state.reading
+            })
+            .collect()
+    }
+
+    /// Drop the held peaks and over indicators.
+    pub fn reset_peaks(&mut self) {
+        for state in &mut self.channels {
+            state.reading.peak = 0.0;
+            state.reading.over = false;
+            state.over_age = None;
+        }
+    }
+}
@@ -0,0 +1,216 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Scale of the level meters: the unit and range of the levels, and the characters
+//! of a meter, in color zones and with eighth blocks for a resolution finer than a character.
+
+use crate::quantization_noise_ratio;
+
+/// dBov, the meter starts yellow and red at these levels
+pub const YELLOW_ZONE: f32 = -18.0;
+pub const RED_ZONE: f32 = -6.0;
+
+/// characters of the meters in the lines of text
+pub const DEFAULT_WIDTH: usize = 16;
+/// dBu of a full scale sine wave, 0 dBFS, as in EBU R 68
+pub const DEFAULT_DBU_REFERENCE: f32 = 18.0;
+
+/// dB, level of the RMS of a full scale sine wave relative to overload
+const FULL_SCALE_SINE: f32 = -3.0103;
+
+/// eighths of a character filled, from 1 to 8
+const EIGHTH_BLOCKS: [char; 8] = ['▏', '▎', '▍', '▌', '▋', '▊', '▉', '█'];
+
+/// Unit of the levels of the meters.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum MeterUnit {
+    /// decibels relative to overload, the RMS of a full scale square wave is 0 dBov
+    #[default]
+    Dbov,
+    /// decibels relative to full scale, the RMS of a full scale sine wave is 0 dBFS (AES17)
+    Dbfs,
+    /// decibels relative to 0.775 V, given the dBu of 0 dBFS
+    Dbu(f32),
+}
+
+impl MeterUnit {
+    /// Parse `dbov`, `dbfs` or `dbu`, with the dBu of 0 dBFS for the latter.
+    pub fn parse(unit: &str, dbu_reference: f32) -> Result<Self, String> {
+        match unit.to_ascii_lowercase().as_str() {
+            "dbov" => Ok(MeterUnit::Dbov),
+            "dbfs" => Ok(MeterUnit::Dbfs),
+            "dbu" => Ok(MeterUnit::Dbu(dbu_reference)),
+            _ => Err(format!(
+                "invalid meter unit '{}', expected one of: dbov, dbfs, dbu",
+                unit
+            )),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            MeterUnit::Dbov => "dBov",
+            MeterUnit::Dbfs => "dBFS",
+            MeterUnit::Dbu(_) => "dBu",
+        }
+    }
+
+    /// dB to add to an RMS level in dBov for this unit.
+    pub fn offset(self) -> f32 {
+        self.peak_offset()
+            + match self {
+                MeterUnit::Dbov => 0.0,
+                MeterUnit::Dbfs | MeterUnit::Dbu(_) => -FULL_SCALE_SINE,
+            }
+    }
+
+    /// dB to add to a peak level relative to full scale for this unit,
+    /// a full scale peak is 0 dBov and 0 dBFS.
+    pub fn peak_offset(self) -> f32 {
+        match self {
+            MeterUnit::Dbov | MeterUnit::Dbfs => 0.0,
+            MeterUnit::Dbu(reference) => reference,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Zone {
+    Green,
+    Yellow,
+    Red,
+}
+
+/// A character of a meter, in the zone of its level unless empty.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MeterCell {
+    Filled(char, Zone),
+    Peak(char, Zone),
+    Empty(char),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct MeterScale {
+    pub unit: MeterUnit,
+    /// levels at the bottom and at the top of the meter, in `unit`
+    pub bottom: f32,
+    pub top: f32,
+    /// characters of the meters in the lines of text,
+    /// the terminal interface fits them to its width
+    pub width: usize,
+    /// only ASCII characters, without eighth blocks
+    pub ascii: bool,
+}
+
+impl Default for MeterScale {
+    /// From the quantization noise level for 16 bits, i.e. ~96 dB, to 0 dBov
+    /// (a reasonable bottom level, regardless the bit deep of the samples).
+    fn default() -> Self {
+        MeterScale::new(MeterUnit::default())
+    }
+}
+
+impl MeterScale {
+    /// The default range in another unit.
+    pub fn new(unit: MeterUnit) -> Self {
+        MeterScale {
+            unit,
+            bottom: -quantization_noise_ratio(16) + unit.offset(),
+            top: unit.offset(),
+            width: DEFAULT_WIDTH,
+            ascii: false,
+        }
+    }
+
+    /// Parse the range of the meter in its unit, e.g. `-60..0`.
+    pub fn parse_range(&mut self, range: &str) -> Result<(), String> {
+        let invalid = || {
+            format!(
+                "invalid meter range '{}', expected <bottom>..<top> in {}",
+                range,
+                self.unit.name()
+            )
+        };
+        let (bottom, top) = range.split_once("..").ok_or_else(invalid)?;
+        match (bottom.trim().parse::<f32>(), top.trim().parse::<f32>()) {
+            (Ok(bottom), Ok(top)) if bottom.is_finite() && top.is_finite() && bottom < top => {
+                self.bottom = bottom;
+                self.top = top;
+                Ok(())
+            }
+            _ => Err(invalid()),
+        }
+    }
+
+    /// An RMS level in dBov in the unit of the meter.
+    pub fn convert(&self, dbov: f32) -> f32 {
+        dbov + self.unit.offset()
+    }
+
+    /// A peak level relative to full scale in the unit of the meter.
+    pub fn convert_peak(&self, peak: f32) -> f32 {
+        peak + self.unit.peak_offset()
+    }
+
+    /// Position of a level in the unit of the meter, between 0 and 1.
+    pub fn position(&self, decibels: f32) -> f32 {
+        if decibels.is_nan() {
+            return 0.0;
+        }
+        ((decibels - self.bottom) / (self.top - self.bottom)).clamp(0.0, 1.0)
+    }
+
+    /// Zone of a level in the unit of the meter.
+    pub fn zone(&self, decibels: f32) -> Zone {
+        let decibels = decibels - self.unit.offset();
+        if decibels >= RED_ZONE {
+            Zone::Red
+        } else if decibels >= YELLOW_ZONE {
+            Zone::Yellow
+        } else {
+            Zone::Green
+        }
+    }
+
+    /// The `width` characters of a meter filled up to the RMS `level` in dBov,
+    /// with a marker at `peak` relative to full scale if above the bottom.
+    pub fn cells(&self, level: f32, peak: f32, width: usize) -> Vec<MeterCell> {
+        let (level, peak) = (self.convert(level), self.convert_peak(peak));
+        let eighths = (self.position(level) * (width * 8) as f32) as usize;
+        let peak_index = if peak > self.bottom {
+            Some(((self.position(peak) * width as f32) as usize).min(width.saturating_sub(1)))
+        } else {
+            None
+        };
+        (0..width)
+            .map(|index| {
+                // zone of the level at the middle of the character
+                let zone = self.zone(
+                    self.bottom + (self.top - self.bottom) * (index as f32 + 0.5) / width as f32,
+                );
+                let filled_eighths = eighths.saturating_sub(index * 8).min(8);
+                if Some(index) == peak_index {
+                    MeterCell::Peak('|', zone)
+                } else if filled_eighths == 8 {
+                    MeterCell::Filled(if self.ascii { '=' } else { '█' }, zone)
+                } else if filled_eighths > 0 && !self.ascii {
+                    MeterCell::Filled(EIGHTH_BLOCKS[filled_eighths - 1], zone)
+                } else {
+                    MeterCell::Empty(if self.ascii { ' ' } else { '·' })
+                }
+            })
+            .collect()
+    }
+}
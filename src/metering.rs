@@ -0,0 +1,625 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Metering of the captured samples, out of the audio thread: the levels of the metered
+//! channels with the enabled analyses, and the audio events detected on them.
+
+use crate::analyzer::{ToneAnalysis, ToneAnalyzer};
+use crate::calibration::Calibration;
+use crate::channel_faults::{self, FaultDetector, FaultEvent};
+use crate::clipping::{ChannelClipping, ClipConfig, ClipDetector};
+use crate::correlation::{CorrelationConfig, CorrelationEvent, CorrelationMeter};
+use crate::dc_offset::{self, DcOffsetMeter};
+use crate::dtmf::{ToneDetector, ToneEvent};
+use crate::events::Event;
+use crate::filter::FilterChain;
+use crate::gain::ChannelGains;
+use crate::leq::LeqMeter;
+use crate::levels::Levels;
+use crate::loudness::{ChannelPosition, LoudnessMeter};
+use crate::meter::{BallisticsConfig, MeterBallistics, MeterReading};
+use crate::mid_side::MidSideMeter;
+use crate::octave_bands::OctaveBandAnalyzer;
+use crate::pilot::{PilotEvent, PilotToneWatchdog};
+use crate::pitch::{ChannelPitch, PitchDetector};
+use crate::processor::{Processor, ProcessorChain};
+use crate::rms::{self, RmsMeter};
+use crate::silence::{SilenceConfig, SilenceDetector, SilenceEvent};
+use crate::spectrum::SpectrumAnalyzer;
+use crate::true_peak::TruePeakMeter;
+use crate::vad::{VadEvent, VoiceActivityDetector};
+use crate::weighting::Weighting;
+use crate::{process_input_channels_into, unix_time, InputBufferSourceData};
+use std::time::{Duration, SystemTime};
+
+/// Shortest interval between the clipping events of a channel.
+pub const CLIPPING_EVENT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Configuration of the meters always run.
+#[derive(Clone)]
+pub struct MeteringConfig {
+    pub sample_rate: u32,
+    /// sample format of the input, reported with the levels
+    pub sample_format: cpal::SampleFormat,
+    /// channels of the interleaved samples
+    pub num_channels: usize,
+    /// indices of the metered channels in the samples
+    pub channels_map: Vec<usize>,
+    /// positions of the channels of the samples, for the loudness
+    pub layout: Option<Vec<ChannelPosition>>,
+    /// frequency weighting of the levels
+    pub weighting: Weighting,
+    /// window of the RMS of the levels, in seconds
+    pub rms_window: f32,
+    /// filters of the metered channels, before the levels
+    pub filter: Option<FilterChain>,
+    pub ballistics: BallisticsConfig,
+    pub clip: ClipConfig,
+    pub correlation: CorrelationConfig,
+    pub silence: SilenceConfig,
+    /// time a wiring fault has to be present
+    pub fault_duration: Duration,
+    /// DC offset considered significant, linear
+    pub dc_threshold: f32,
+}
+
+impl MeteringConfig {
+    /// All the channels metered, with the default meters.
+    pub fn new(sample_rate: u32, sample_format: cpal::SampleFormat, num_channels: usize) -> Self {
+        MeteringConfig {
+            sample_rate,
+            sample_format,
+            num_channels,
+            channels_map: (0..num_channels).collect(),
+            layout: None,
+            weighting: Weighting::Z,
+            rms_window: rms::DEFAULT_WINDOW,
+            filter: None,
+            ballistics: BallisticsConfig::default(),
+            clip: ClipConfig::default(),
+            correlation: CorrelationConfig::default(),
+            silence: SilenceConfig::default(),
+            fault_duration: Duration::from_secs_f32(channel_faults::DEFAULT_DURATION),
+            dc_threshold: 10_f32.powf(dc_offset::DEFAULT_THRESHOLD / 20.0),
+        }
+    }
+}
+
+/// The metering of an input buffer.
+pub struct MeteringFrame {
+    pub levels: Levels,
+    /// readings of the meters of the metered channels, with their ballistics
+    pub meter_readings: Vec<MeterReading>,
+    /// clips of the metered channels
+    pub clippings: Vec<ChannelClipping>,
+    /// audio events detected in the input buffer
+    pub events: Vec<Event>,
+    /// level of the calibrator tone in dBov, once measured
+    pub calibrated: Option<f32>,
+    /// windows of the Leq statistics ended with the input buffer, in seconds
+    pub ended_leq_windows: Vec<u32>,
+}
+
+/// Meters the metered channels of each input buffer with the meters always run
+/// and the analyses enabled by the `with_*` methods.
+pub struct Metering {
+    config: MeteringConfig,
+    source_data: InputBufferSourceData,
+    processors: ProcessorChain,
+    gains: ChannelGains,
+    loudness_meter: LoudnessMeter,
+    true_peak_meter: TruePeakMeter,
+    dc_offset_meter: DcOffsetMeter,
+    meter_ballistics: MeterBallistics,
+    clip_detector: ClipDetector,
+    correlation_meter: CorrelationMeter,
+    silence_detector: SilenceDetector,
+    fault_detector: FaultDetector,
+    /// clip count and time of the last clipping event of each channel
+    clipping_events: Vec<(u64, Option<SystemTime>)>,
+    spectrum_analyzer: Option<SpectrumAnalyzer>,
+    octave_band_analyzer: Option<OctaveBandAnalyzer>,
+    tone_analyzer: Option<ToneAnalyzer>,
+    /// the last analysis, until the next one
+    tone_analysis: Vec<ToneAnalysis>,
+    pitch_detector: Option<PitchDetector>,
+    /// the last pitch, until the next one
+    pitch: Vec<ChannelPitch>,
+    voice_activity_detector: Option<VoiceActivityDetector>,
+    calibration: Option<Calibration>,
+    leq_meter: Option<LeqMeter>,
+    mid_side_meter: Option<MidSideMeter>,
+    pilot_watchdog: Option<PilotToneWatchdog>,
+    tone_detector: Option<ToneDetector>,
+}
+
+impl Metering {
+    pub fn new(config: MeteringConfig) -> Result<Self, String> {
+        let num_metered_channels = config.channels_map.len();
+        let sample_rate = config.sample_rate;
+        let mut processors = ProcessorChain::new();
+        if let Some(filter) = config.filter.clone().filter(|filter| !filter.is_empty()) {
+            processors.register(Box::new(filter))?;
+        }
+        processors.register(Box::new(RmsMeter::new(
+            config.weighting,
+            config.rms_window,
+            sample_rate,
+            num_metered_channels,
+        )))?;
+        // the positions of the metered channels
+        let loudness_meter = match &config.layout {
+            Some(layout) => {
+                let layout: Vec<_> = config
+                    .channels_map
+                    .iter()
+                    .map(|&channel| layout[channel])
+                    .collect();
+                LoudnessMeter::with_layout(sample_rate, &layout)
+            }
+            None => LoudnessMeter::new(sample_rate, num_metered_channels),
+        };
+        Ok(Metering {
+            source_data: InputBufferSourceData {
+                num_samples: 0,
+                sample_format: config.sample_format,
+                num_channels: config.num_channels,
+                channels: Vec::with_capacity(num_metered_channels),
+            },
+            processors,
+            gains: ChannelGains::new(config.num_channels),
+            loudness_meter,
+            true_peak_meter: TruePeakMeter::new(num_metered_channels),
+            dc_offset_meter: DcOffsetMeter::new(sample_rate, num_metered_channels),
+            meter_ballistics: MeterBallistics::new(config.ballistics, num_metered_channels),
+            clip_detector: ClipDetector::new(config.clip, num_metered_channels),
+            correlation_meter: CorrelationMeter::new(
+                config.correlation,
+                sample_rate,
+                &config.channels_map,
+            ),
+            silence_detector: SilenceDetector::new(config.silence, num_metered_channels),
+            // a dead channel is silent as in the silence detection
+            fault_detector: FaultDetector::new(
+                &config.channels_map,
+                config.silence.threshold,
+                config.fault_duration,
+            ),
+            clipping_events: vec![(0, None); num_metered_channels],
+            spectrum_analyzer: None,
+            octave_band_analyzer: None,
+            tone_analyzer: None,
+            tone_analysis: Vec::new(),
+            pitch_detector: None,
+            pitch: Vec::new(),
+            voice_activity_detector: None,
+            calibration: None,
+            leq_meter: None,
+            mid_side_meter: None,
+            pilot_watchdog: None,
+            tone_detector: None,
+            config,
+        })
+    }
+
+    /// Report the gains of the channels, and whether they are muted, with the levels.
+    pub fn with_gains(mut self, gains: ChannelGains) -> Self {
+        self.gains = gains;
+        self
+    }
+
+    pub fn with_spectrum(mut self, spectrum_analyzer: SpectrumAnalyzer) -> Self {
+        self.spectrum_analyzer = Some(spectrum_analyzer);
+        self
+    }
+
+    pub fn with_octave_bands(mut self, octave_band_analyzer: OctaveBandAnalyzer) -> Self {
+        self.octave_band_analyzer = Some(octave_band_analyzer);
+        self
+    }
+
+    pub fn with_tone_analyzer(mut self, tone_analyzer: ToneAnalyzer) -> Self {
+        self.tone_analyzer = Some(tone_analyzer);
+        self
+    }
+
+    pub fn with_pitch_detector(mut self, pitch_detector: PitchDetector) -> Self {
+        self.pitch_detector = Some(pitch_detector);
+        self
+    }
+
+    pub fn with_voice_activity_detector(
+        mut self,
+        voice_activity_detector: VoiceActivityDetector,
+    ) -> Self {
+        self.voice_activity_detector = Some(voice_activity_detector);
+        self
+    }
+
+    /// Report the levels in dB SPL too, once calibrated.
+    pub fn with_calibration(mut self, calibration: Calibration) -> Self {
+        self.calibration = Some(calibration);
+        self
+    }
+
+    pub fn with_leq_meter(mut self, leq_meter: LeqMeter) -> Self {
+        self.leq_meter = Some(leq_meter);
+        self
+    }
+
+    pub fn with_mid_side(mut self, mid_side_meter: MidSideMeter) -> Self {
+        self.mid_side_meter = Some(mid_side_meter);
+        self
+    }
+
+    pub fn with_pilot_watchdog(mut self, pilot_watchdog: PilotToneWatchdog) -> Self {
+        self.pilot_watchdog = Some(pilot_watchdog);
+        self
+    }
+
+    pub fn with_tone_detector(mut self, tone_detector: ToneDetector) -> Self {
+        self.tone_detector = Some(tone_detector);
+        self
+    }
+
+    pub fn config(&self) -> &MeteringConfig {
+        &self.config
+    }
+
+    pub fn calibration(&self) -> Option<&Calibration> {
+        self.calibration.as_ref()
+    }
+
+    /// The metered channels of the last input buffer.
+    pub fn source_data(&self) -> &InputBufferSourceData {
+        &self.source_data
+    }
+
+    /// Reset the peaks held by the meters.
+    pub fn reset_peaks(&mut self) {
+        self.meter_ballistics.reset_peaks();
+    }
+
+    /// Meter an input buffer of interleaved samples captured at the given time.
+    pub fn process(&mut self, samples: &[f32], timestamp: SystemTime) -> MeteringFrame {
+        let sample_rate = self.config.sample_rate;
+        let channels_map = &self.config.channels_map;
+        let source_data = &mut self.source_data;
+        source_data.num_samples = samples.len();
+        process_input_channels_into(
+            samples,
+            self.config.num_channels,
+            channels_map.iter().copied(),
+            &mut source_data.channels,
+        );
+        self.processors.process(source_data);
+        let spectrum = self
+            .spectrum_analyzer
+            .as_mut()
+            .and_then(|spectrum_analyzer| {
+                Processor::process(spectrum_analyzer, source_data);
+                spectrum_analyzer.take_spectrum()
+            });
+        let source_data = &self.source_data;
+
+        if let Some(analysis) = self
+            .tone_analyzer
+            .as_mut()
+            .and_then(|tone_analyzer| tone_analyzer.process(&source_data.channels))
+        {
+            self.tone_analysis = analysis;
+        }
+        if let Some(channels_pitch) = self
+            .pitch_detector
+            .as_mut()
+            .and_then(|pitch_detector| pitch_detector.process(&source_data.channels))
+        {
+            self.pitch = channels_pitch;
+        }
+        let octave_bands = self
+            .octave_band_analyzer
+            .as_mut()
+            .map(|octave_band_analyzer| octave_band_analyzer.process(&source_data.channels));
+        let loudness = self.loudness_meter.process(&source_data.channels);
+        let true_peaks = self.true_peak_meter.process(&source_data.channels);
+        let meter_readings = self.meter_ballistics.update(
+            &source_data.channels,
+            &true_peaks,
+            (source_data.num_samples / source_data.num_channels) as f32 / sample_rate as f32,
+        );
+        let clippings = self
+            .clip_detector
+            .process(&source_data.channels, timestamp)
+            .to_vec();
+        let dc_offsets = self.dc_offset_meter.process(&source_data.channels);
+        let ended_leq_windows = self
+            .leq_meter
+            .as_mut()
+            .map_or(&[][..], |leq_meter| {
+                leq_meter.process(&source_data.channels, timestamp)
+            })
+            .to_vec();
+
+        let mut events = Vec::new();
+        for ((channel, clipping), clipping_event) in channels_map
+            .iter()
+            .zip(&clippings)
+            .zip(&mut self.clipping_events)
+        {
+            let is_due = clipping_event.1.is_none_or(|time| {
+                timestamp.duration_since(time).unwrap_or_default() >= CLIPPING_EVENT_INTERVAL
+            });
+            if clipping.count > clipping_event.0 && is_due {
+                *clipping_event = (clipping.count, Some(timestamp));
+                events.push(Event::Clipping {
+                    timestamp: unix_time(timestamp),
+                    channel: *channel,
+                    count: clipping.count,
+                });
+            }
+        }
+        for event in self
+            .silence_detector
+            .process(&source_data.channels, timestamp)
+        {
+            events.push(silence_event(event, channels_map, timestamp));
+        }
+        for event in self
+            .correlation_meter
+            .process(&source_data.channels, timestamp)
+        {
+            events.push(correlation_event(event, timestamp));
+        }
+        for event in self
+            .fault_detector
+            .process(&source_data.channels, timestamp)
+        {
+            events.push(fault_event(event, timestamp));
+        }
+        if let Some(ref mut pilot_watchdog) = self.pilot_watchdog {
+            for event in pilot_watchdog.process(&source_data.channels, timestamp) {
+                events.push(pilot_event(event, timestamp));
+            }
+        }
+        if let Some(ref mut tone_detector) = self.tone_detector {
+            for event in tone_detector.process(&source_data.channels, timestamp) {
+                events.push(tone_event(event, timestamp));
+            }
+        }
+        if let Some(ref mut voice_activity_detector) = self.voice_activity_detector {
+            for event in voice_activity_detector.process(&source_data.channels, timestamp) {
+                events.push(vad_event(event, timestamp));
+            }
+        }
+        let calibrated = self
+            .calibration
+            .as_mut()
+            .and_then(|calibration| calibration.process(&source_data.channels));
+
+        let mut levels = Levels::new(
+            source_data,
+            sample_rate,
+            timestamp,
+            loudness,
+            &true_peaks,
+            &clippings,
+            &self.silence_detector.silent_since(),
+        );
+        levels.weighting = self.config.weighting.name();
+        levels.faults = self.fault_detector.faults();
+        levels.correlation = self.correlation_meter.pairs().to_vec();
+        if let Some(ref mut mid_side_meter) = self.mid_side_meter {
+            levels.mid_side = mid_side_meter.process(&source_data.channels).to_vec();
+        }
+        levels.spectrum = spectrum;
+        levels.octave_bands = octave_bands;
+        levels.analysis = self.tone_analysis.clone();
+        levels.pitch = self.pitch.clone();
+        let calibration = self.calibration.as_ref();
+        for (channel, &dc_offset) in levels.channels.iter_mut().zip(dc_offsets) {
+            channel.spl = calibration.and_then(|calibration| calibration.spl(channel.dbov));
+            channel.gain = self.gains.db(channel.channel);
+            channel.muted = self.gains.is_muted(channel.channel);
+            channel.dc_offset = dc_offset;
+            channel.dc_offset_warning = dc_offset.abs() > self.config.dc_threshold;
+        }
+        if let Some(ref voice_activity_detector) = self.voice_activity_detector {
+            for (channel, speech) in levels
+                .channels
+                .iter_mut()
+                .zip(voice_activity_detector.speech())
+            {
+                channel.speech = Some(speech);
+            }
+        }
+        if let Some(ref leq_meter) = self.leq_meter {
+            let offset = calibration.and_then(|calibration| calibration.offset());
+            for (position, channel) in levels.channels.iter_mut().enumerate() {
+                let channel_leq = leq_meter.channel_leq(position);
+                channel.leq = Some(match offset {
+                    Some(offset) => channel_leq.calibrated(offset),
+                    None => channel_leq,
+                });
+            }
+        }
+
+        MeteringFrame {
+            levels,
+            meter_readings,
+            clippings,
+            events,
+            calibrated,
+            ended_leq_windows,
+        }
+    }
+}
+
+/// The event of a silence of a metered channel, with the index of the channel in the input stream.
+fn silence_event(event: SilenceEvent, channels_map: &[usize], timestamp: SystemTime) -> Event {
+    match event {
+        SilenceEvent::Start { channel, since } => Event::SilenceStart {
+            timestamp: unix_time(timestamp),
+            channel: channels_map[channel],
+            since: unix_time(since),
+        },
+        SilenceEvent::End {
+            channel,
+            since,
+            until,
+        } => Event::SilenceEnd {
+            timestamp: unix_time(timestamp),
+            channel: channels_map[channel],
+            since: unix_time(since),
+            duration: until
+                .duration_since(since)
+                .unwrap_or_default()
+                .as_secs_f64(),
+        },
+    }
+}
+
+/// The event of the start or end of an out of phase alarm of a stereo pair.
+fn correlation_event(event: CorrelationEvent, timestamp: SystemTime) -> Event {
+    match event {
+        CorrelationEvent::AlarmStart { pair, since } => Event::CorrelationAlarmStart {
+            timestamp: unix_time(timestamp),
+            left: pair.left,
+            right: pair.right,
+            correlation: pair.correlation.unwrap_or_default(),
+            since: unix_time(since),
+        },
+        CorrelationEvent::AlarmEnd { pair, since, until } => Event::CorrelationAlarmEnd {
+            timestamp: unix_time(timestamp),
+            left: pair.left,
+            right: pair.right,
+            since: unix_time(since),
+            duration: until
+                .duration_since(since)
+                .unwrap_or_default()
+                .as_secs_f64(),
+        },
+    }
+}
+
+/// The event of a DTMF digit or call progress tone.
+fn tone_event(event: ToneEvent, timestamp: SystemTime) -> Event {
+    match event {
+        ToneEvent::Digit {
+            channel,
+            digit,
+            since,
+            until,
+        } => Event::DtmfDigit {
+            timestamp: unix_time(timestamp),
+            channel,
+            digit,
+            since: unix_time(since),
+            duration: until
+                .duration_since(since)
+                .unwrap_or_default()
+                .as_secs_f64(),
+        },
+        ToneEvent::CallProgress {
+            channel,
+            tone,
+            since,
+        } => Event::CallProgressTone {
+            timestamp: unix_time(timestamp),
+            channel,
+            tone,
+            since: unix_time(since),
+        },
+    }
+}
+
+/// The event of the start or end of the speech of a channel.
+fn vad_event(event: VadEvent, timestamp: SystemTime) -> Event {
+    match event {
+        VadEvent::SpeechStart { channel, since } => Event::SpeechStart {
+            timestamp: unix_time(timestamp),
+            channel,
+            since: unix_time(since),
+        },
+        VadEvent::SpeechEnd {
+            channel,
+            since,
+            until,
+        } => Event::SpeechEnd {
+            timestamp: unix_time(timestamp),
+            channel,
+            since: unix_time(since),
+            duration: until
+                .duration_since(since)
+                .unwrap_or_default()
+                .as_secs_f64(),
+        },
+    }
+}
+
+/// The event of a pilot tone lost or restored.
+fn pilot_event(event: PilotEvent, timestamp: SystemTime) -> Event {
+    match event {
+        PilotEvent::Lost {
+            channel,
+            frequency,
+            fault,
+            measured_frequency,
+            level,
+            since,
+        } => Event::PilotToneLost {
+            timestamp: unix_time(timestamp),
+            channel,
+            frequency,
+            fault,
+            measured_frequency,
+            level,
+            since: unix_time(since),
+        },
+        PilotEvent::Restored {
+            channel,
+            frequency,
+            since,
+            until,
+        } => Event::PilotToneRestored {
+            timestamp: unix_time(timestamp),
+            channel,
+            frequency,
+            since: unix_time(since),
+            duration: until
+                .duration_since(since)
+                .unwrap_or_default()
+                .as_secs_f64(),
+        },
+    }
+}
+
+/// The event of the start or end of a wiring fault of the channels.
+fn fault_event(event: FaultEvent, timestamp: SystemTime) -> Event {
+    match event {
+        FaultEvent::Start { fault } => Event::ChannelFaultStart {
+            timestamp: unix_time(timestamp),
+            fault: fault.kind,
+            channels: fault.channels,
+            since: fault.since,
+        },
+        FaultEvent::End { fault, until } => Event::ChannelFaultEnd {
+            timestamp: unix_time(timestamp),
+            fault: fault.kind,
+            channels: fault.channels,
+            since: fault.since,
+            duration: unix_time(until) - fault.since,
+        },
+    }
+}
@@ -0,0 +1,276 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Push of the metrics of `/metrics` and of the events to InfluxDB, in its line protocol,
+//! and to StatsD, for the sites without a Prometheus server scraping them.
+
+use crate::events::Event;
+use crate::http_client;
+use crate::metrics::{MetricFamily, MetricKind};
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::Method;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::net::UdpSocket;
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+/// Time between pushes of the metrics, in seconds.
+pub const DEFAULT_INTERVAL: f64 = 10.0;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Events kept for the next write to InfluxDB before dropping new ones.
+const MAX_QUEUED_EVENTS: usize = 1000;
+/// Largest StatsD datagram, to fit in the MTU of an Ethernet network.
+const MAX_DATAGRAM: usize = 1432;
+/// Measurement of the events in InfluxDB, and prefix of their StatsD counters.
+const EVENTS_METRIC: &str = "audio_in_stream_events";
+
+/// Name of the event, channel it is about, if any, and time.
+fn event_tags(event: &Event) -> (String, Option<u64>, f64) {
+    let data = serde_json::to_value(event).expect("failed to serialize event");
+    (
+        data["event"].as_str().unwrap_or_default().to_owned(),
+        data["channel"].as_u64(),
+        data["timestamp"].as_f64().unwrap_or_default(),
+    )
+}
+
+/// Writes the metrics to the write endpoint of InfluxDB, with the events since the last write.
+pub struct InfluxWriter {
+    /// e.g. `http://localhost:8086/api/v2/write?org=studio&bucket=audio`,
+    /// with the default precision of nanoseconds
+    url: String,
+    token: Option<String>,
+    events: Mutex<Vec<String>>,
+}
+
+impl InfluxWriter {
+    pub fn new(url: &str, token: Option<String>) -> Result<Self, String> {
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            return Err(format!(
+                "invalid InfluxDB url '{}', expected http:// or https://",
+                url
+            ));
+        }
+        Ok(InfluxWriter {
+            url: url.to_owned(),
+            token,
+            events: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Queue the event for the next write, as a point of `audio_in_stream_events`
+    /// tagged with its name and channel.
+    pub fn queue_event(&self, event: &Event) {
+        let (name, channel, timestamp) = event_tags(event);
+        let mut line = format!("{},event={}", EVENTS_METRIC, name);
+        if let Some(channel) = channel {
+            write!(line, ",channel={}", channel).unwrap();
+        }
+        write!(line, " count=1i {}", nanoseconds(timestamp)).unwrap();
+        let mut events = self.events.lock().unwrap_or_else(PoisonError::into_inner);
+        if events.len() < MAX_QUEUED_EVENTS {
+            events.push(line);
+        }
+    }
+
+    /// Write the metrics, a measurement for each one with its label as a tag
+    /// and its value in the `value` field, without the infinite levels of silence.
+    pub async fn write(&self, families: &[MetricFamily], timestamp: f64) -> Result<(), String> {
+        let timestamp = nanoseconds(timestamp);
+        let mut body = String::new();
+        for family in families {
+            for sample in family
+                .samples
+                .iter()
+                .filter(|sample| sample.value.is_finite())
+            {
+                body.push_str(family.name);
+                if let Some((label, value)) = &sample.label {
+                    write!(body, ",{}={}", label, value).unwrap();
+                }
+                match family.kind {
+                    MetricKind::Counter => write!(body, " value={}i", sample.value as u64),
+                    MetricKind::Gauge => write!(body, " value={}", sample.value),
+                }
+                .unwrap();
+                writeln!(body, " {}", timestamp).unwrap();
+            }
+        }
+        let events =
+            std::mem::take(&mut *self.events.lock().unwrap_or_else(PoisonError::into_inner));
+        for event in &events {
+            writeln!(body, "{}", event).unwrap();
+        }
+
+        let mut headers = vec![("Content-Type", String::from("text/plain; charset=utf-8"))];
+        if let Some(token) = &self.token {
+            headers.push(("Authorization", format!("Token {}", token)));
+        }
+        let body = Full::new(Bytes::from(body));
+        match http_client::send(Method::POST, &self.url, &headers, body, REQUEST_TIMEOUT).await {
+            Ok(response) if response.is_success() => Ok(()),
+            Ok(response) => Err(format!(
+                "failed to write to InfluxDB '{}': status {} {}",
+                self.url,
+                response.status,
+                response.body.trim()
+            )),
+            Err(err) => Err(format!(
+                "failed to write to InfluxDB '{}': {}",
+                self.url, err
+            )),
+        }
+    }
+}
+
+fn nanoseconds(timestamp: f64) -> u64 {
+    (timestamp * 1e9) as u64
+}
+
+/// Sends the metrics to a StatsD server over UDP, the gauges as they are and the counters
+/// as the increment since the last push, named after the metric and its label,
+/// e.g. `audio_in_stream_dbov.channel_0`.
+pub struct StatsdClient {
+    socket: UdpSocket,
+    /// last values of the counters
+    counters: Mutex<HashMap<String, f64>>,
+}
+
+impl StatsdClient {
+    /// Client of the server at `address`, e.g. `localhost:8125`.
+    pub fn new(address: &str) -> Result<Self, String> {
+        let bind = if address.starts_with('[') {
+            "[::]:0"
+        } else {
+            "0.0.0.0:0"
+        };
+        let socket = UdpSocket::bind(bind)
+            .and_then(|socket| socket.connect(address).map(|_| socket))
+            .map_err(|err| format!("invalid StatsD address '{}': {}", address, err))?;
+        Ok(StatsdClient {
+            socket,
+            counters: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Count the event as `audio_in_stream_events.<name>`.
+    pub fn send_event(&self, event: &Event) -> Result<(), String> {
+        let (name, _, _) = event_tags(event);
+        self.send_lines(&[format!("{}.{}:1|c", EVENTS_METRIC, name)])
+    }
+
+    /// Send the metrics, without the infinite levels of silence.
+    pub fn send(&self, families: &[MetricFamily]) -> Result<(), String> {
+        let mut counters = self.counters.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut lines = Vec::new();
+        for family in families {
+            for sample in family
+                .samples
+                .iter()
+                .filter(|sample| sample.value.is_finite())
+            {
+                let name = match &sample.label {
+                    Some((label, value)) => format!("{}.{}_{}", family.name, label, value),
+                    None => family.name.to_owned(),
+                };
+                match family.kind {
+                    MetricKind::Gauge => lines.push(format!("{}:{}|g", name, sample.value)),
+                    MetricKind::Counter => {
+                        let last = counters.insert(name.clone(), sample.value).unwrap_or(0.0);
+                        let increment = sample.value - last;
+                        if increment > 0.0 {
+                            lines.push(format!("{}:{}|c", name, increment));
+                        }
+                    }
+                }
+            }
+        }
+        self.send_lines(&lines)
+    }
+
+    /// Send the lines, as few datagrams as possible.
+    fn send_lines(&self, lines: &[String]) -> Result<(), String> {
+        let mut datagram = String::new();
+        for line in lines {
+            if !datagram.is_empty() && datagram.len() + 1 + line.len() > MAX_DATAGRAM {
+                self.send_datagram(&datagram)?;
+                datagram.clear();
+            }
+            if !datagram.is_empty() {
+                datagram.push('\n');
+            }
+            datagram.push_str(line);
+        }
+        if !datagram.is_empty() {
+            self.send_datagram(&datagram)?;
+        }
+        Ok(())
+    }
+
+    fn send_datagram(&self, datagram: &str) -> Result<(), String> {
+        self.socket
+            .send(datagram.as_bytes())
+            .map(|_| ())
+            .map_err(|err| format!("failed to send to StatsD: {}", err))
+    }
+}
+
+/// The outputs the metrics are pushed to.
+#[derive(Default)]
+pub struct MetricPush {
+    pub influx: Option<InfluxWriter>,
+    pub statsd: Option<StatsdClient>,
+}
+
+impl MetricPush {
+    pub fn is_empty(&self) -> bool {
+        self.influx.is_none() && self.statsd.is_none()
+    }
+
+    /// Push the metrics to all the outputs, even if one of them fails.
+    pub async fn push(&self, families: &[MetricFamily], timestamp: f64) -> Result<(), String> {
+        let influx = match &self.influx {
+            Some(influx) => Some(influx.write(families, timestamp).await),
+            None => None,
+        };
+        let results = vec![
+            influx,
+            self.statsd.as_ref().map(|statsd| statsd.send(families)),
+        ];
+        let errors: Vec<String> = results
+            .into_iter()
+            .flatten()
+            .filter_map(Result::err)
+            .collect();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join(", "))
+        }
+    }
+
+    /// Send the event to StatsD right away, and to InfluxDB with the next metrics.
+    pub fn send_event(&self, event: &Event) -> Result<(), String> {
+        if let Some(influx) = &self.influx {
+            influx.queue_event(event);
+        }
+        match &self.statsd {
+            Some(statsd) => statsd.send_event(event),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,246 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Counters of the input stream, exposed in the Prometheus text format.
+
+use crate::clipping::ChannelClipping;
+use crate::levels::{ChannelLevels, Levels};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+/// Content type of the Prometheus text exposition format.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Type of a metric.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MetricKind {
+    Gauge,
+    /// only increases since the start
+    Counter,
+}
+
+impl MetricKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            MetricKind::Gauge => "gauge",
+            MetricKind::Counter => "counter",
+        }
+    }
+}
+
+/// A value of a metric, with the label telling it from the others of the same metric.
+#[derive(Clone, Debug)]
+pub struct MetricSample {
+    pub label: Option<(&'static str, String)>,
+    pub value: f64,
+}
+
+/// A metric with its description and its values, the same for `/metrics`
+/// and the pushed metrics.
+#[derive(Clone, Debug)]
+pub struct MetricFamily {
+    pub name: &'static str,
+    pub help: &'static str,
+    pub kind: MetricKind,
+    pub samples: Vec<MetricSample>,
+}
+
+/// Counters updated from the audio processing threads, without locking.
+pub struct Metrics {
+    started: Instant,
+    buffers: AtomicU64,
+    dropped_buffers: AtomicU64,
+    /// clips of each metered channel, by its index in the input stream
+    clips: Vec<(usize, AtomicU64)>,
+    /// chunks of the sample ring missed by each of its readers
+    overruns: Vec<(&'static str, AtomicU64)>,
+}
+
+impl Metrics {
+    /// `channels` are the indices of the metered channels in the input stream,
+    /// `consumers` the names of the readers of the sample ring.
+    pub fn new(channels: &[usize], consumers: &[&'static str]) -> Self {
+        Metrics {
+            started: Instant::now(),
+            buffers: AtomicU64::new(0),
+            dropped_buffers: AtomicU64::new(0),
+            clips: channels
+                .iter()
+                .map(|&channel| (channel, AtomicU64::new(0)))
+                .collect(),
+            overruns: consumers
+                .iter()
+                .map(|&consumer| (consumer, AtomicU64::new(0)))
+                .collect(),
+        }
+    }
+
+    /// Count an input buffer, with the clips of each channel so far.
+    pub fn record_buffer(&self, clippings: &[ChannelClipping]) {
+        self.buffers.fetch_add(1, Ordering::Relaxed);
+        for (clipping, (_, clips)) in clippings.iter().zip(&self.clips) {
+            clips.store(clipping.count, Ordering::Relaxed);
+        }
+    }
+
+    /// Count the buffers dropped for slow consumers.
+    pub fn record_dropped_buffers(&self, dropped: usize) {
+        if dropped > 0 {
+            self.dropped_buffers
+                .fetch_add(dropped as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// Count the chunks of the sample ring missed by a slow consumer.
+    pub fn record_overruns(&self, consumer: &str, overruns: u64) {
+        if overruns > 0 {
+            if let Some((_, count)) = self.overruns.iter().find(|(name, _)| *name == consumer) {
+                count.fetch_add(overruns, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// The counters, and the levels of the last input buffer if any.
+    pub fn families(&self, levels: Option<&Levels>) -> Vec<MetricFamily> {
+        let mut families = Vec::new();
+
+        if let Some(levels) = levels {
+            let channel_samples = |value: &dyn Fn(&ChannelLevels) -> Option<f32>| {
+                levels
+                    .channels
+                    .iter()
+                    .filter_map(|channel| {
+                        value(channel).map(|value| MetricSample {
+                            label: Some(("channel", channel.channel.to_string())),
+                            value: value as f64,
+                        })
+                    })
+                    .collect::<Vec<_>>()
+            };
+            families.push(MetricFamily {
+                name: "audio_in_stream_rms",
+                help: "Loudness level of the last input buffer, root mean square of the samples.",
+                kind: MetricKind::Gauge,
+                samples: channel_samples(&|channel| Some(channel.rms)),
+            });
+            families.push(MetricFamily {
+                name: "audio_in_stream_dbov",
+                help: "Loudness level of the last input buffer, in decibels relative to overload.",
+                kind: MetricKind::Gauge,
+                samples: channel_samples(&|channel| Some(channel.dbov)),
+            });
+            let spl = channel_samples(&|channel| channel.spl);
+            if !spl.is_empty() {
+                families.push(MetricFamily {
+                    name: "audio_in_stream_spl",
+                    help: "Loudness level of the last input buffer, in dB SPL.",
+                    kind: MetricKind::Gauge,
+                    samples: spl,
+                });
+            }
+        }
+
+        families.push(MetricFamily {
+            name: "audio_in_stream_clips_total",
+            help: "Runs of consecutive samples at or above the clip threshold.",
+            kind: MetricKind::Counter,
+            samples: self
+                .clips
+                .iter()
+                .map(|(channel, clips)| MetricSample {
+                    label: Some(("channel", channel.to_string())),
+                    value: clips.load(Ordering::Relaxed) as f64,
+                })
+                .collect(),
+        });
+        families.push(MetricFamily {
+            name: "audio_in_stream_buffers_total",
+            help: "Input buffers received from the device.",
+            kind: MetricKind::Counter,
+            samples: vec![MetricSample {
+                label: None,
+                value: self.buffers.load(Ordering::Relaxed) as f64,
+            }],
+        });
+        families.push(MetricFamily {
+            name: "audio_in_stream_dropped_buffers_total",
+            help: "Buffers dropped for slow consumers.",
+            kind: MetricKind::Counter,
+            samples: vec![MetricSample {
+                label: None,
+                value: self.dropped_buffers.load(Ordering::Relaxed) as f64,
+            }],
+        });
+        families.push(MetricFamily {
+            name: "audio_in_stream_overruns_total",
+            help: "Chunks of captured samples overwritten before a consumer read them.",
+            kind: MetricKind::Counter,
+            samples: self
+                .overruns
+                .iter()
+                .map(|(consumer, overruns)| MetricSample {
+                    label: Some(("consumer", consumer.to_string())),
+                    value: overruns.load(Ordering::Relaxed) as f64,
+                })
+                .collect(),
+        });
+        families.push(MetricFamily {
+            name: "audio_in_stream_uptime_seconds",
+            help: "Time since the input stream started.",
+            kind: MetricKind::Gauge,
+            samples: vec![MetricSample {
+                label: None,
+                value: self.started.elapsed().as_secs_f64(),
+            }],
+        });
+
+        families
+    }
+
+    /// Render the counters, and the levels of the last input buffer if any,
+    /// in the Prometheus text exposition format.
+    pub fn render(&self, levels: Option<&Levels>) -> String {
+        let mut text = String::new();
+        for family in self.families(levels) {
+            writeln!(text, "# HELP {} {}", family.name, family.help).unwrap();
+            writeln!(text, "# TYPE {} {}", family.name, family.kind.name()).unwrap();
+            for sample in &family.samples {
+                match &sample.label {
+                    Some((label, value)) => {
+                        write!(text, "{}{{{}=\"{}\"}}", family.name, label, value)
+                    }
+                    None => write!(text, "{}", family.name),
+                }
+                .unwrap();
+                writeln!(text, " {}", float_value(sample.value)).unwrap();
+            }
+        }
+        text
+    }
+}
+
+/// Prometheus spells the infinities and NaN differently than Rust.
+fn float_value(value: f64) -> String {
+    if value.is_nan() {
+        String::from("NaN")
+    } else if value == f64::INFINITY {
+        String::from("+Inf")
+    } else if value == f64::NEG_INFINITY {
+        String::from("-Inf")
+    } else {
+        value.to_string()
+    }
+}
@@ -0,0 +1,77 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Mid/side levels of stereo pairs: the mid is the sum of the channels and the side
+//! their difference, a side much lower than the mid is a mono or dual mono feed.
+
+use crate::{decibels_overload, ChannelData};
+use serde::Serialize;
+
+/// Mid/side levels of a stereo pair, given by the indices of its channels in the input stream.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct PairMidSide {
+    pub left: usize,
+    pub right: usize,
+    /// level of `(left + right) / 2` in dBov, `null` in JSON for silence (-inf)
+    pub mid: f32,
+    /// level of `(left - right) / 2` in dBov, `null` in JSON for silence (-inf)
+    pub side: f32,
+    /// side level relative to the mid level in dB, `null` in JSON if unknown
+    pub side_to_mid: f32,
+}
+
+/// Mid/side levels of each pair of consecutive metered channels, 0 and 1, 2 and 3, and so on.
+pub struct MidSideMeter {
+    pairs: Vec<PairMidSide>,
+}
+
+impl MidSideMeter {
+    /// Pairs of the metered channels, given by their indices in the input stream,
+    /// a last channel without pair is not metered.
+    pub fn new(channels: &[usize]) -> Self {
+        MidSideMeter {
+            pairs: channels
+                .chunks_exact(2)
+                .map(|pair| PairMidSide {
+                    left: pair[0],
+                    right: pair[1],
+                    mid: f32::NEG_INFINITY,
+                    side: f32::NEG_INFINITY,
+                    side_to_mid: f32::NAN,
+                })
+                .collect(),
+        }
+    }
+
+    /// Levels of each pair in an input buffer.
+    pub fn process(&mut self, channels: &[ChannelData]) -> &[PairMidSide] {
+        for (pair_channels, pair) in channels.chunks_exact(2).zip(&mut self.pairs) {
+            let (left, right) = (&pair_channels[0].samples, &pair_channels[1].samples);
+            if left.is_empty() {
+                continue;
+            }
+            let (mut mid_square, mut side_square) = (0.0, 0.0);
+            for (&left, &right) in left.iter().zip(right) {
+                let (mid, side) = ((left + right) / 2.0, (left - right) / 2.0);
+                mid_square += mid * mid;
+                side_square += side * side;
+            }
+            pair.mid = decibels_overload((mid_square / left.len() as f32).sqrt());
+            pair.side = decibels_overload((side_square / left.len() as f32).sqrt());
+            pair.side_to_mid = pair.side - pair.mid;
+        }
+        &self.pairs
+    }
+}
@@ -0,0 +1,144 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! MIDI output of the levels as control changes, with midir, for hardware fader bridges
+//! and DAW controllers mirroring the meter.
+
+use crate::levels::Levels;
+use midir::{MidiOutput as MidirOutput, MidiOutputConnection};
+
+/// Updates per second.
+pub const DEFAULT_RATE: f64 = 20.0;
+/// Controller of the first channel, the next ones for the next channels,
+/// of the undefined controllers 20 to 31.
+pub const DEFAULT_FIRST_CC: u8 = 20;
+/// Level of the value 0, the value 127 is 0 dBFS.
+pub const DEFAULT_FLOOR_DB: f32 = -60.0;
+
+const CLIENT_NAME: &str = "audio-in-stream-rs";
+const PORT_NAME: &str = "levels";
+/// status byte of a control change, with the channel in the low bits
+const CONTROL_CHANGE: u8 = 0xb0;
+
+/// Which controllers the levels of the channels are sent to.
+#[derive(Clone, Debug)]
+pub struct MidiConfig {
+    /// MIDI channel, from 0 to 15
+    pub channel: u8,
+    /// controller of each metered channel, in the order of the levels
+    pub controllers: Vec<u8>,
+    /// level of the value 0, in dBFS
+    pub floor_db: f32,
+}
+
+/// A MIDI port sending the levels, a virtual port that other clients can connect to,
+/// or connected to the given port.
+pub struct MidiOutput {
+    connection: MidiOutputConnection,
+    config: MidiConfig,
+    /// last values sent of each controller, only the changes are sent
+    values: Vec<Option<u8>>,
+}
+
+impl MidiOutput {
+    /// `destination` is a port id like `20:0` of ALSA, or the start of its name,
+    /// or of the name of its client, case insensitive. Without it the port is virtual,
+    /// not available on Windows.
+    pub fn open(destination: Option<&str>, config: MidiConfig) -> Result<Self, String> {
+        let output = MidirOutput::new(CLIENT_NAME)
+            .map_err(|err| format!("failed to open the MIDI output: {}", err))?;
+        let connection = match destination {
+            Some(destination) => {
+                let port = find_port(&output, destination)?;
+                output.connect(&port, PORT_NAME).map_err(|err| {
+                    format!("failed to connect to MIDI port '{}': {}", destination, err)
+                })?
+            }
+            None => create_virtual(output)?,
+        };
+        let values = vec![None; config.controllers.len()];
+        Ok(MidiOutput {
+            connection,
+            config,
+            values,
+        })
+    }
+
+    /// Send the level of each channel, scaled from the floor to 0 dBFS,
+    /// if it changed since the last time.
+    pub fn send(&mut self, levels: &Levels) -> Result<(), String> {
+        for ((channel, &controller), last_value) in levels
+            .channels
+            .iter()
+            .zip(&self.config.controllers)
+            .zip(&mut self.values)
+        {
+            let value = controller_value(channel.dbov, self.config.floor_db);
+            if *last_value == Some(value) {
+                continue;
+            }
+            *last_value = Some(value);
+            self.connection
+                .send(&[CONTROL_CHANGE | self.config.channel, controller, value])
+                .map_err(|err| format!("failed to send MIDI: {}", err))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn create_virtual(output: MidirOutput) -> Result<MidiOutputConnection, String> {
+    use midir::os::unix::VirtualOutput;
+
+    output
+        .create_virtual(PORT_NAME)
+        .map_err(|err| format!("failed to create the MIDI port: {}", err))
+}
+
+#[cfg(not(unix))]
+fn create_virtual(_output: MidirOutput) -> Result<MidiOutputConnection, String> {
+    Err(String::from(
+        "virtual MIDI ports are not supported on this platform, connect to a port instead",
+    ))
+}
+
+/// Value of a control change of a level, 0 at the floor and below, 127 at 0 dB and above.
+fn controller_value(level: f32, floor_db: f32) -> u8 {
+    let value = (level - floor_db) / -floor_db * 127.0;
+    if value.is_nan() {
+        0
+    } else {
+        value.round().clamp(0.0, 127.0) as u8
+    }
+}
+
+/// The output port with the given id or name.
+fn find_port(output: &MidirOutput, destination: &str) -> Result<midir::MidiOutputPort, String> {
+    let name = destination.to_lowercase();
+    output
+        .ports()
+        .into_iter()
+        .find(|port| {
+            port.id() == destination
+                || output.port_name(port).is_ok_and(|port_name| {
+                    // the names of ALSA are `client:port`
+                    port_name
+                        .to_lowercase()
+                        .split(':')
+                        .any(|part| part.trim_start().starts_with(&name))
+                })
+        })
+        .ok_or_else(|| format!("MIDI port '{}' not found", destination))
+}
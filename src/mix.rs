@@ -0,0 +1,172 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Channel mapping, downmix, gain and DC offset removal of the captured input,
+//! before any other processing.
+
+use crate::dc_offset::DcBlocker;
+use crate::gain::ChannelGains;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Downmix {
+    Mono,
+    Stereo,
+}
+
+impl Downmix {
+    pub fn parse(downmix: &str) -> Result<Self, String> {
+        match downmix {
+            "mono" => Ok(Downmix::Mono),
+            "stereo" => Ok(Downmix::Stereo),
+            _ => Err(format!(
+                "invalid downmix '{}', expected one of: mono, stereo",
+                downmix
+            )),
+        }
+    }
+}
+
+/// Each output channel as a weighted sum of input channels,
+/// with the software gain of the output channel, and without DC offset if enabled.
+#[derive(Clone)]
+pub struct ChannelMix {
+    input_channels: usize,
+    /// index and gain of the input channels of each output channel
+    outputs: Vec<Vec<(usize, f32)>>,
+    gains: ChannelGains,
+    dc_blocker: Option<DcBlocker>,
+}
+
+impl ChannelMix {
+    /// All the input channels, unchanged.
+    pub fn identity(input_channels: usize) -> Self {
+        ChannelMix {
+            input_channels,
+            outputs: (0..input_channels)
+                .map(|index| vec![(index, 1.0)])
+                .collect(),
+            gains: ChannelGains::new(input_channels),
+            dc_blocker: None,
+        }
+    }
+
+    /// The input channels selected and ordered by `map`, all of them if `None`,
+    /// and then folded by `downmix`, if any.
+    ///
+    /// A mono downmix is the average of the channels, a stereo downmix
+    /// the average of the channels in even positions on the left and
+    /// of the channels in odd positions on the right (a single channel goes to both),
+    /// so a downmix never clips.
+    pub fn new(
+        input_channels: usize,
+        map: Option<&[usize]>,
+        downmix: Option<Downmix>,
+    ) -> Result<Self, String> {
+        let channels: Vec<usize> = match map {
+            Some(map) => {
+                if map.is_empty() {
+                    return Err(String::from("no channels in the channel map"));
+                }
+                if let Some(&channel) = map.iter().find(|&&channel| channel >= input_channels) {
+                    return Err(format!(
+                        "invalid channel {} in the channel map, the input has {} channel(s)",
+                        channel, input_channels
+                    ));
+                }
+                map.to_vec()
+            }
+            None => (0..input_channels).collect(),
+        };
+
+        let average = |channels: Vec<usize>| -> Vec<(usize, f32)> {
+            let gain = 1.0 / channels.len() as f32;
+            channels
+                .into_iter()
+                .map(|channel| (channel, gain))
+                .collect()
+        };
+        let outputs = match downmix {
+            None => channels
+                .iter()
+                .map(|&channel| vec![(channel, 1.0)])
+                .collect(),
+            Some(Downmix::Mono) => vec![average(channels)],
+            Some(Downmix::Stereo) if channels.len() == 1 => {
+                vec![vec![(channels[0], 1.0)], vec![(channels[0], 1.0)]]
+            }
+            Some(Downmix::Stereo) => {
+                let (left, right): (Vec<_>, Vec<_>) = channels
+                    .iter()
+                    .enumerate()
+                    .partition(|(position, _)| position % 2 == 0);
+                vec![
+                    average(left.into_iter().map(|(_, &channel)| channel).collect()),
+                    average(right.into_iter().map(|(_, &channel)| channel).collect()),
+                ]
+            }
+        };
+
+        Ok(ChannelMix {
+            input_channels,
+            gains: ChannelGains::new(outputs.len()),
+            outputs,
+            dc_blocker: None,
+        })
+    }
+
+    /// Remove the DC offset of the output channels,
+    /// with a high-pass filter of the given cutoff frequency.
+    pub fn with_dc_blocker(mut self, cutoff: f32, sample_rate: u32) -> Self {
+        self.dc_blocker = Some(DcBlocker::new(cutoff, sample_rate, self.outputs.len()));
+        self
+    }
+
+    /// Software gain of the output channels, 0 dB until adjusted.
+    pub fn gains(&self) -> &ChannelGains {
+        &self.gains
+    }
+
+    pub fn input_channels(&self) -> usize {
+        self.input_channels
+    }
+
+    pub fn output_channels(&self) -> usize {
+        self.outputs.len()
+    }
+
+    /// Samples of an output frame, mixed from the next frame of input samples.
+    pub fn mix_frame<'a, T>(&'a mut self, input_frame: &'a [T]) -> impl Iterator<Item = f32> + 'a
+    where
+        T: cpal::Sample,
+        f32: cpal::FromSample<T>,
+    {
+        let gains = &self.gains;
+        let dc_blocker = &mut self.dc_blocker;
+        self.outputs
+            .iter()
+            .enumerate()
+            .map(move |(channel, inputs)| {
+                let sample: f32 = inputs
+                    .iter()
+                    .map(|&(index, gain)| input_frame[index].to_sample::<f32>() * gain)
+                    .sum();
+                let sample = match dc_blocker {
+                    Some(ref mut dc_blocker) => dc_blocker.process(channel, sample),
+                    None => sample,
+                };
+                sample * gains.linear(channel)
+            })
+    }
+}
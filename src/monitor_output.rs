@@ -0,0 +1,216 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Playback of the live capture on an output device, a confidence monitor.
+//!
+//! The captured samples are resampled to the output sample rate and queued in a FIFO,
+//! played by the output callback once it holds the delay plus the largest input block,
+//! so the output does not underrun between the input callbacks. The FIFO is trimmed back
+//! to that latency when the clocks of the devices drift apart. As the ring, the FIFO
+//! never makes the audio thread wait.
+
+use crate::resample::LinearResampler;
+use crate::ring::RingReader;
+use cpal::traits::{DeviceTrait, StreamTrait};
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+/// Blocks of input over the latency before trimming the FIFO, as the input callbacks jitter.
+const MAX_EXCESS_BLOCKS: usize = 2;
+/// Capacity of the FIFO over the delay, in seconds.
+const FIFO_HEADROOM: f64 = 2.0;
+
+/// Single producer single consumer FIFO of interleaved samples.
+struct Fifo {
+    /// bits of the `f32` samples
+    samples: Box<[AtomicU32]>,
+    /// samples written and read since the start
+    written: AtomicUsize,
+    read: AtomicUsize,
+    /// samples held before playing, and after an underrun
+    latency: AtomicUsize,
+    /// samples held over the latency before trimming
+    max_excess: AtomicUsize,
+}
+
+impl Fifo {
+    /// Append the samples, dropping those which do not fit.
+    fn push(&self, samples: impl Iterator<Item = f32>) {
+        let read = self.read.load(Ordering::Acquire);
+        let mut written = self.written.load(Ordering::Relaxed);
+        for sample in samples {
+            if written - read >= self.samples.len() {
+                break;
+            }
+            self.samples[written % self.samples.len()].store(sample.to_bits(), Ordering::Relaxed);
+            written += 1;
+        }
+        self.written.store(written, Ordering::Release);
+    }
+}
+
+/// Plays the captured samples until dropped.
+pub struct MonitorOutput {
+    _stream: cpal::Stream,
+}
+
+impl MonitorOutput {
+    /// Play the chunks of `ring_reader` on `device` with `gain` in dB after `delay` seconds,
+    /// calling `error_callback` with the errors of the output stream.
+    ///
+    /// The captured channels are played on the output channels in turn, a mono capture
+    /// on all of them, once processed in place by `process`, as the noise suppression.
+    pub fn start<E, P>(
+        device: &cpal::Device,
+        mut ring_reader: RingReader,
+        gain: f32,
+        delay: f64,
+        mut process: P,
+        error_callback: E,
+    ) -> Result<Self, String>
+    where
+        E: FnMut(cpal::StreamError) + Send + 'static,
+        P: FnMut(&mut [f32]) + Send + 'static,
+    {
+        let input_channels = ring_reader.num_channels();
+        let input_sample_rate = ring_reader.sample_rate();
+        let config = device
+            .default_output_config()
+            .map_err(|err| format!("failed to get the monitor output config: {}", err))?;
+        let output_channels = config.channels() as usize;
+        let output_sample_rate = config.sample_rate().0;
+        let delay_samples = (delay * output_sample_rate as f64).round() as usize * output_channels;
+        let capacity =
+            ((delay + FIFO_HEADROOM) * output_sample_rate as f64) as usize * output_channels;
+        let fifo = Arc::new(Fifo {
+            samples: (0..capacity).map(|_| AtomicU32::new(0)).collect(),
+            written: AtomicUsize::new(0),
+            read: AtomicUsize::new(0),
+            latency: AtomicUsize::new(delay_samples),
+            max_excess: AtomicUsize::new(0),
+        });
+
+        let stream_fifo = Arc::clone(&fifo);
+        let stream = match config.sample_format() {
+            cpal::SampleFormat::U16 => {
+                build_output_stream::<u16, _>(device, &config, stream_fifo, error_callback)
+            }
+            cpal::SampleFormat::I16 => {
+                build_output_stream::<i16, _>(device, &config, stream_fifo, error_callback)
+            }
+            cpal::SampleFormat::I32 => {
+                build_output_stream::<i32, _>(device, &config, stream_fifo, error_callback)
+            }
+            cpal::SampleFormat::F32 => {
+                build_output_stream::<f32, _>(device, &config, stream_fifo, error_callback)
+            }
+            sample_format => {
+                return Err(format!(
+                    "unsupported monitor output sample format {:?}",
+                    sample_format
+                ))
+            }
+        }
+        .map_err(|err| format!("failed to build the monitor output stream: {}", err))?;
+        stream
+            .play()
+            .map_err(|err| format!("failed to play the monitor output stream: {}", err))?;
+
+        thread::spawn(move || {
+            let gain = 10f32.powf(gain / 20.0);
+            let mut resampler =
+                LinearResampler::new(input_channels, input_sample_rate, output_sample_rate);
+            let mut samples = Vec::new();
+            let mut resampled = Vec::new();
+            let mut max_block_samples = 0;
+            while ring_reader.read(&mut samples).is_some() {
+                process(&mut samples);
+                resampled.clear();
+                if input_sample_rate == output_sample_rate {
+                    resampled.extend_from_slice(&samples);
+                } else {
+                    resampler.process(&samples, &mut resampled);
+                }
+                let block_samples = resampled.len() / input_channels * output_channels;
+                if block_samples > max_block_samples {
+                    max_block_samples = block_samples;
+                    fifo.latency
+                        .store(delay_samples + max_block_samples, Ordering::Relaxed);
+                    fifo.max_excess
+                        .store(MAX_EXCESS_BLOCKS * max_block_samples, Ordering::Relaxed);
+                }
+                fifo.push(
+                    resampled
+                        .chunks_exact(input_channels)
+                        .flat_map(|input_frame| {
+                            (0..output_channels)
+                                .map(move |channel| input_frame[channel % input_channels] * gain)
+                        }),
+                );
+            }
+        });
+
+        Ok(MonitorOutput { _stream: stream })
+    }
+}
+
+fn build_output_stream<T, E>(
+    device: &cpal::Device,
+    config: &cpal::SupportedStreamConfig,
+    fifo: Arc<Fifo>,
+    error_callback: E,
+) -> Result<cpal::Stream, cpal::BuildStreamError>
+where
+    T: cpal::SizedSample + cpal::FromSample<f32>,
+    E: FnMut(cpal::StreamError) + Send + 'static,
+{
+    let channels = config.channels() as usize;
+    let mut playing = false;
+    device.build_output_stream(
+        &config.config(),
+        move |output: &mut [T], _: &cpal::OutputCallbackInfo| {
+            let written = fifo.written.load(Ordering::Acquire);
+            let mut read = fifo.read.load(Ordering::Relaxed);
+            let target = fifo.latency.load(Ordering::Relaxed) + output.len();
+            let available = written - read;
+            if !playing {
+                if available < target {
+                    output.fill(T::EQUILIBRIUM);
+                    return;
+                }
+                playing = true;
+            }
+            // the input runs faster than the output, back to the latency
+            if available > target + fifo.max_excess.load(Ordering::Relaxed) {
+                read += (available - target) / channels * channels;
+            }
+            for sample in output.iter_mut() {
+                *sample = if read < written {
+                    let value = fifo.samples[read % fifo.samples.len()].load(Ordering::Relaxed);
+                    read += 1;
+                    T::from_sample(f32::from_bits(value))
+                } else {
+                    // underrun, held again until the FIFO is filled
+                    playing = false;
+                    T::EQUILIBRIUM
+                };
+            }
+            fifo.read.store(read, Ordering::Release);
+        },
+        error_callback,
+        None,
+    )
+}
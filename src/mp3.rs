@@ -0,0 +1,129 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! MP3 encoding of a stream of interleaved samples, with the LAME of mp3lame-encoder.
+
+use crate::encoder::StreamEncoder;
+use mp3lame_encoder::{
+    max_required_buffer_size, Bitrate, Builder, Encoder, FlushGap, InterleavedPcm, Mode, Quality,
+};
+
+pub const DEFAULT_BITRATE: i32 = 192000;
+
+/// The bitrates of LAME in kbit/s.
+const BITRATES: [(i32, Bitrate); 16] = [
+    (8, Bitrate::Kbps8),
+    (16, Bitrate::Kbps16),
+    (24, Bitrate::Kbps24),
+    (32, Bitrate::Kbps32),
+    (40, Bitrate::Kbps40),
+    (48, Bitrate::Kbps48),
+    (64, Bitrate::Kbps64),
+    (80, Bitrate::Kbps80),
+    (96, Bitrate::Kbps96),
+    (112, Bitrate::Kbps112),
+    (128, Bitrate::Kbps128),
+    (160, Bitrate::Kbps160),
+    (192, Bitrate::Kbps192),
+    (224, Bitrate::Kbps224),
+    (256, Bitrate::Kbps256),
+    (320, Bitrate::Kbps320),
+];
+
+/// The bitrate of LAME nearest to `bitrate` in bit/s, as LAME itself rounds it.
+fn nearest_bitrate(bitrate: i32) -> Bitrate {
+    BITRATES
+        .iter()
+        .min_by_key(|(kbps, _)| (kbps * 1000 - bitrate).abs())
+        .map(|(_, bitrate)| *bitrate)
+        .unwrap_or(Bitrate::Kbps192)
+}
+
+/// Encodes interleaved samples as a constant bitrate MP3 stream, mono for 1 channel and
+/// joint stereo of the first 2 channels otherwise.
+pub struct Mp3Encoder {
+    lame: Encoder,
+    input_channels: usize,
+    mp3_channels: usize,
+    bytes: Vec<u8>,
+}
+
+impl Mp3Encoder {
+    pub fn new(channels: u16, sample_rate: u32, bitrate: i32) -> Result<Self, String> {
+        let input_channels = channels as usize;
+        let mp3_channels = input_channels.min(2);
+        let invalid = |_| {
+            format!(
+                "invalid MP3 encoder parameters, {} Hz at {} bit/s",
+                sample_rate, bitrate
+            )
+        };
+        let mut builder =
+            Builder::new().ok_or_else(|| String::from("failed to create MP3 encoder"))?;
+        builder
+            .set_num_channels(mp3_channels as u8)
+            .map_err(invalid)?;
+        builder.set_sample_rate(sample_rate).map_err(invalid)?;
+        builder
+            .set_brate(nearest_bitrate(bitrate))
+            .map_err(invalid)?;
+        builder
+            .set_mode(if mp3_channels == 1 {
+                Mode::Mono
+            } else {
+                Mode::JointStereo
+            })
+            .map_err(invalid)?;
+        builder.set_quality(Quality::NearBest).map_err(invalid)?;
+        // a constant bitrate stream does not need the Xing header of the first frame
+        builder.set_to_write_vbr_tag(false).map_err(invalid)?;
+        Ok(Mp3Encoder {
+            lame: builder.build().map_err(invalid)?,
+            input_channels,
+            mp3_channels,
+            bytes: Vec::new(),
+        })
+    }
+}
+
+impl StreamEncoder for Mp3Encoder {
+    fn encode(&mut self, samples: &[f32]) -> Result<(), String> {
+        let num_frames = samples.len() / self.input_channels;
+        let mut mp3_input = Vec::with_capacity(num_frames * 2);
+        for frame in samples.chunks_exact(self.input_channels) {
+            // LAME reads interleaved stereo even for mono
+            mp3_input.push(frame[0]);
+            mp3_input.push(frame[self.mp3_channels - 1]);
+        }
+
+        self.bytes.reserve(max_required_buffer_size(num_frames));
+        self.lame
+            .encode_to_vec(InterleavedPcm(&mp3_input), &mut self.bytes)
+            .map_err(|err| format!("failed to encode MP3 frames, {}", err))?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), String> {
+        self.bytes.reserve(max_required_buffer_size(0));
+        self.lame
+            .flush_to_vec::<FlushGap>(&mut self.bytes)
+            .map_err(|err| format!("failed to flush MP3 frames, {}", err))?;
+        Ok(())
+    }
+
+    fn take_bytes(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.bytes)
+    }
+}
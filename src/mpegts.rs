@@ -0,0 +1,495 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! MPEG transport stream (ISO/IEC 13818-1) of a single program of audio, AAC in ADTS
+//! frames, or Opus mapped as in ETSI TS 102 366 annex, as read by ffmpeg and GStreamer.
+
+/// Size of a transport stream packet.
+pub const PACKET_LEN: usize = 188;
+/// Clock of the timestamps of the stream.
+pub const CLOCK_RATE: u64 = 90000;
+
+const SYNC_BYTE: u8 = 0x47;
+const PAT_PID: u16 = 0;
+const PMT_PID: u16 = 0x1000;
+const AUDIO_PID: u16 = 0x100;
+const PROGRAM_NUMBER: u16 = 1;
+/// PES packets of private data, with the Opus access units
+const PRIVATE_STREAM_1: u8 = 0xbd;
+/// PES packets of MPEG audio, with the AAC access units
+const AUDIO_STREAM: u8 = 0xc0;
+const STREAM_TYPE_PRIVATE_PES: u8 = 0x06;
+const STREAM_TYPE_ADTS: u8 = 0x0f;
+/// Access units grouped in each PES packet, about 100 ms of audio
+const PACKETS_PER_PES: usize = 5;
+
+/// CRC-32 of the MPEG-2 sections, not reflected.
+fn crc32_mpeg(bytes: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffff_u32;
+    for &byte in bytes {
+        crc ^= (byte as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// A 33 bits timestamp in the 5 bytes of a PES header, after the 4 bits `prefix`.
+fn put_timestamp(bytes: &mut Vec<u8>, prefix: u8, timestamp: u64) {
+    bytes.push((prefix << 4) | (((timestamp >> 30) & 0x07) as u8) << 1 | 1);
+    bytes.extend_from_slice(&((((timestamp >> 15) & 0x7fff) << 1 | 1) as u16).to_be_bytes());
+    bytes.extend_from_slice(&(((timestamp & 0x7fff) << 1 | 1) as u16).to_be_bytes());
+}
+
+/// Codec of the audio stream.
+#[derive(Clone, Debug)]
+pub enum TsStream {
+    Opus {
+        channels: usize,
+    },
+    /// AAC, with the `AudioSpecificConfig` of the ADTS headers
+    Aac {
+        audio_specific_config: Vec<u8>,
+    },
+}
+
+/// Muxes the access units into a transport stream, with the program tables before
+/// each PES packet so the receivers can join at any time.
+pub struct TsMuxer {
+    stream: TsStream,
+    /// continuity counters of the PAT, the PMT and the audio
+    continuity: [u8; 3],
+    /// access units of the next PES packet, with the timestamp of the first one
+    pending: Vec<u8>,
+    pending_packets: usize,
+    pending_pts: u64,
+}
+
+impl TsMuxer {
+    pub fn new(stream: TsStream) -> Self {
+        TsMuxer {
+            stream,
+            continuity: [0; 3],
+            pending: Vec::new(),
+            pending_packets: 0,
+            pending_pts: 0,
+        }
+    }
+
+    /// Mux an access unit with its timestamp in `CLOCK_RATE` units,
+    /// returns the transport stream packets completed, if any.
+    pub fn push(&mut self, packet: &[u8], pts: u64) -> Vec<u8> {
+        if self.pending_packets == 0 {
+            self.pending_pts = pts & 0x1_ffff_ffff;
+        }
+        match &self.stream {
+            TsStream::Opus { .. } => {
+                // control header of the access unit, without trimming, and its size
+                self.pending.extend_from_slice(&[0x7f, 0xe0]);
+                let mut size = packet.len();
+                while size >= 255 {
+                    self.pending.push(0xff);
+                    size -= 255;
+                }
+                self.pending.push(size as u8);
+            }
+            TsStream::Aac {
+                audio_specific_config,
+            } => {
+                let header = adts_header(audio_specific_config, packet.len());
+                self.pending.extend_from_slice(&header);
+            }
+        }
+        self.pending.extend_from_slice(packet);
+        self.pending_packets += 1;
+        if self.pending_packets < PACKETS_PER_PES {
+            return Vec::new();
+        }
+        self.flush()
+    }
+
+    /// Mux the access units pushed so far, e.g. at the end of a segment.
+    pub fn flush(&mut self) -> Vec<u8> {
+        if self.pending_packets == 0 {
+            return Vec::new();
+        }
+        let mut stream = Vec::new();
+        self.write_section(&mut stream, PAT_PID, 0, &self.pat());
+        self.write_section(&mut stream, PMT_PID, 1, &self.pmt());
+        let stream_id = match self.stream {
+            TsStream::Opus { .. } => PRIVATE_STREAM_1,
+            TsStream::Aac { .. } => AUDIO_STREAM,
+        };
+        let mut pes = vec![0, 0, 1, stream_id];
+        // the length after this field: the flags, the timestamp and the access units
+        pes.extend_from_slice(&((3 + 5 + self.pending.len()) as u16).to_be_bytes());
+        // marker bits, with a PTS
+        pes.extend_from_slice(&[0x80, 0x80, 5]);
+        put_timestamp(&mut pes, 0x2, self.pending_pts);
+        pes.append(&mut self.pending);
+        self.pending_packets = 0;
+        self.write_payload(&mut stream, AUDIO_PID, 2, &pes, Some(self.pending_pts));
+        stream
+    }
+
+    /// Program association table, of the single program.
+    fn pat(&self) -> Vec<u8> {
+        let mut program = Vec::new();
+        program.extend_from_slice(&PROGRAM_NUMBER.to_be_bytes());
+        program.extend_from_slice(&(0xe000 | PMT_PID).to_be_bytes());
+        section(0x00, 1, &program)
+    }
+
+    /// Program map table, of the audio stream.
+    fn pmt(&self) -> Vec<u8> {
+        let mut program = Vec::new();
+        // PCR of the audio, without program descriptors
+        program.extend_from_slice(&(0xe000 | AUDIO_PID).to_be_bytes());
+        program.extend_from_slice(&0xf000_u16.to_be_bytes());
+        let (stream_type, descriptors) = match self.stream {
+            TsStream::Opus { channels } => (
+                STREAM_TYPE_PRIVATE_PES,
+                vec![
+                    // registration descriptor
+                    0x05,
+                    4,
+                    b'O',
+                    b'p',
+                    b'u',
+                    b's',
+                    // DVB extension descriptor of the channel configuration
+                    0x7f,
+                    2,
+                    0x80,
+                    channels as u8,
+                ],
+            ),
+            TsStream::Aac { .. } => (STREAM_TYPE_ADTS, Vec::new()),
+        };
+        program.push(stream_type);
+        program.extend_from_slice(&(0xe000 | AUDIO_PID).to_be_bytes());
+        program.extend_from_slice(&(0xf000 | descriptors.len() as u16).to_be_bytes());
+        program.extend_from_slice(&descriptors);
+        section(0x02, PROGRAM_NUMBER, &program)
+    }
+
+    /// A section in the packets of a PID, after its pointer field.
+    fn write_section(&mut self, stream: &mut Vec<u8>, pid: u16, counter: usize, section: &[u8]) {
+        let mut payload = vec![0];
+        payload.extend_from_slice(section);
+        // the sections are padded with 0xff instead of an adaptation field
+        payload.resize(PACKET_LEN - 4, 0xff);
+        self.write_payload(stream, pid, counter, &payload, None);
+    }
+
+    /// The payload in packets of a PID, the first one with the program clock if any,
+    /// and the last one stuffed to the packet size.
+    fn write_payload(
+        &mut self,
+        stream: &mut Vec<u8>,
+        pid: u16,
+        counter: usize,
+        payload: &[u8],
+        pcr: Option<u64>,
+    ) {
+        let mut offset = 0;
+        while offset < payload.len() {
+            let start = offset == 0;
+            // the adaptation field after its length, if any
+            let mut adaptation = match pcr {
+                // PCR flag, and the base of the clock with the reserved bits and no extension
+                Some(pcr) if start => {
+                    let mut field = vec![0x10];
+                    field.extend_from_slice(&((pcr << 15) | 0x7e00).to_be_bytes()[2..]);
+                    Some(field)
+                }
+                _ => None,
+            };
+            let room = |adaptation: &Option<Vec<u8>>| {
+                PACKET_LEN - 4 - adaptation.as_ref().map_or(0, |field| 1 + field.len())
+            };
+            let remaining = payload.len() - offset;
+            if remaining < room(&adaptation) {
+                // stuffing of the last packet, in the adaptation field
+                let field = adaptation.get_or_insert_with(Vec::new);
+                let stuffing = PACKET_LEN - 4 - 1 - field.len() - remaining;
+                if stuffing > 0 {
+                    if field.is_empty() {
+                        field.push(0x00);
+                    }
+                    field.resize(PACKET_LEN - 4 - 1 - remaining, 0xff);
+                }
+            }
+            let len = remaining.min(room(&adaptation));
+
+            stream.push(SYNC_BYTE);
+            stream.extend_from_slice(&(((start as u16) << 14) | pid).to_be_bytes());
+            let adaptation_control = if adaptation.is_some() { 0x30 } else { 0x10 };
+            stream.push(adaptation_control | self.continuity[counter]);
+            self.continuity[counter] = (self.continuity[counter] + 1) & 0x0f;
+            if let Some(field) = adaptation {
+                stream.push(field.len() as u8);
+                stream.extend_from_slice(&field);
+            }
+            stream.extend_from_slice(&payload[offset..offset + len]);
+            offset += len;
+        }
+    }
+}
+
+/// ADTS header of an AAC access unit of `len` bytes, from the audio object type,
+/// the sample rate index and the channel configuration of the `AudioSpecificConfig`.
+fn adts_header(audio_specific_config: &[u8], len: usize) -> [u8; 7] {
+    let config = match audio_specific_config {
+        [first, second, ..] => u16::from_be_bytes([*first, *second]),
+        _ => 0,
+    };
+    let profile = ((config >> 11).max(1) - 1) as u8 & 0x03;
+    let sample_rate_index = ((config >> 7) & 0x0f) as u8;
+    let channels = ((config >> 3) & 0x0f) as u8;
+    let frame_len = len + 7;
+    [
+        0xff,
+        // MPEG-4, without CRC
+        0xf1,
+        (profile << 6) | (sample_rate_index << 2) | (channels >> 2),
+        ((channels & 0x03) << 6) | ((frame_len >> 11) & 0x03) as u8,
+        (frame_len >> 3) as u8,
+        // and a variable bitrate buffer fullness, 1 frame
+        ((frame_len & 0x07) as u8) << 5 | 0x1f,
+        0xfc,
+    ]
+}
+
+/// A long section of the table with the id `table_id` and extension `id`, with its CRC.
+fn section(table_id: u8, id: u16, data: &[u8]) -> Vec<u8> {
+    let mut section = vec![table_id];
+    // syntax indicator, the length after this field: the header, the data and the CRC
+    section.extend_from_slice(&(0xb000 | (5 + data.len() + 4) as u16).to_be_bytes());
+    section.extend_from_slice(&id.to_be_bytes());
+    // version 0, current, section 0 of 0
+    section.extend_from_slice(&[0xc1, 0, 0]);
+    section.extend_from_slice(data);
+    let crc = crc32_mpeg(&section);
+    section.extend_from_slice(&crc.to_be_bytes());
+    section
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A transport stream packet, split.
+    struct Packet<'a> {
+        pid: u16,
+        start: bool,
+        continuity: u8,
+        adaptation: Option<&'a [u8]>,
+        payload: &'a [u8],
+    }
+
+    fn demux(stream: &[u8]) -> Vec<Packet<'_>> {
+        assert_eq!(stream.len() % PACKET_LEN, 0);
+        stream
+            .chunks(PACKET_LEN)
+            .map(|packet| {
+                assert_eq!(packet[0], SYNC_BYTE);
+                let header = u16::from_be_bytes([packet[1], packet[2]]);
+                let (adaptation, payload) = match packet[3] & 0x30 {
+                    0x10 => (None, &packet[4..]),
+                    0x30 => {
+                        let len = packet[4] as usize;
+                        (Some(&packet[5..5 + len]), &packet[5 + len..])
+                    }
+                    control => panic!("adaptation field control {:#x}", control),
+                };
+                Packet {
+                    pid: header & 0x1fff,
+                    start: header & 0x4000 != 0,
+                    continuity: packet[3] & 0x0f,
+                    adaptation,
+                    payload,
+                }
+            })
+            .collect()
+    }
+
+    /// The section of a packet, after its pointer field, checked with its CRC.
+    fn section_of<'a>(packet: &Packet<'a>) -> &'a [u8] {
+        assert!(packet.start);
+        assert_eq!(packet.payload[0], 0);
+        let len = (u16::from_be_bytes([packet.payload[2], packet.payload[3]]) & 0x0fff) as usize;
+        let section = &packet.payload[1..4 + len];
+        // the CRC of a section with its CRC is 0
+        assert_eq!(crc32_mpeg(section), 0);
+        section
+    }
+
+    /// The PES packet of the audio packets.
+    fn pes_of(packets: &[Packet]) -> Vec<u8> {
+        packets
+            .iter()
+            .filter(|packet| packet.pid == AUDIO_PID)
+            .flat_map(|packet| packet.payload.iter().copied())
+            .collect()
+    }
+
+    fn get_timestamp(bytes: &[u8]) -> u64 {
+        ((bytes[0] as u64 >> 1) & 0x07) << 30
+            | (u16::from_be_bytes([bytes[1], bytes[2]]) as u64 >> 1) << 15
+            | u16::from_be_bytes([bytes[3], bytes[4]]) as u64 >> 1
+    }
+
+    #[test]
+    fn crc_check_value() {
+        assert_eq!(crc32_mpeg(b"123456789"), 0x0376_e6e7);
+    }
+
+    #[test]
+    fn pes_timestamps() {
+        let mut bytes = Vec::new();
+        put_timestamp(&mut bytes, 0x2, 0);
+        assert_eq!(bytes, [0x21, 0x00, 0x01, 0x00, 0x01]);
+        bytes.clear();
+        put_timestamp(&mut bytes, 0x2, 0x1_ffff_ffff);
+        assert_eq!(bytes, [0x2f, 0xff, 0xff, 0xff, 0xff]);
+        bytes.clear();
+        put_timestamp(&mut bytes, 0x2, 123_456_789);
+        assert_eq!(get_timestamp(&bytes), 123_456_789);
+    }
+
+    #[test]
+    fn adts_headers() {
+        // AAC LC, 48 kHz, stereo
+        assert_eq!(
+            adts_header(&[0x11, 0x90], 100),
+            [0xff, 0xf1, 0x4c, 0x80, 0x0d, 0x7f, 0xfc]
+        );
+        // AAC LC, 44.1 kHz, mono, a frame length beyond 11 bits
+        let header = adts_header(&[0x12, 0x08], 3000);
+        assert_eq!(header[2], 0x50);
+        let frame_len = ((header[3] as usize & 0x03) << 11)
+            | (header[4] as usize) << 3
+            | (header[5] as usize) >> 5;
+        assert_eq!(frame_len, 3007);
+    }
+
+    #[test]
+    fn nothing_before_a_pes_packet() {
+        let mut muxer = TsMuxer::new(TsStream::Opus { channels: 2 });
+        for pts in 0..PACKETS_PER_PES as u64 - 1 {
+            assert!(muxer.push(&[0; 10], pts * 1800).is_empty());
+        }
+        assert!(!muxer.flush().is_empty());
+        assert!(muxer.flush().is_empty());
+    }
+
+    #[test]
+    fn opus_program() {
+        let mut muxer = TsMuxer::new(TsStream::Opus { channels: 2 });
+        let mut stream = Vec::new();
+        // a long access unit, with its size in 2 bytes
+        let access_units = [
+            vec![1; 300],
+            vec![2; 20],
+            vec![3; 20],
+            vec![4; 20],
+            vec![5; 20],
+        ];
+        for (index, access_unit) in access_units.iter().enumerate() {
+            stream.extend(muxer.push(access_unit, 90000 + index as u64 * 1800));
+        }
+        let packets = demux(&stream);
+
+        assert_eq!(packets[0].pid, PAT_PID);
+        let pat = section_of(&packets[0]);
+        assert_eq!(pat[0], 0x00);
+        assert_eq!(pat[8..12], [0x00, 0x01, 0xf0, 0x00]);
+
+        assert_eq!(packets[1].pid, PMT_PID);
+        let pmt = section_of(&packets[1]);
+        assert_eq!(pmt[0], 0x02);
+        // the stream, private PES with the Opus registration descriptor and 2 channels
+        assert_eq!(pmt[12], STREAM_TYPE_PRIVATE_PES);
+        assert_eq!(pmt[17..23], [0x05, 4, b'O', b'p', b'u', b's']);
+        assert_eq!(pmt[23..27], [0x7f, 2, 0x80, 2]);
+
+        let audio: Vec<&Packet> = packets
+            .iter()
+            .filter(|packet| packet.pid == AUDIO_PID)
+            .collect();
+        assert!(audio[0].start);
+        assert!(audio[1..].iter().all(|packet| !packet.start));
+        // PCR flag in the first packet
+        assert_eq!(audio[0].adaptation.unwrap()[0], 0x10);
+        for (counter, packet) in audio.iter().enumerate() {
+            assert_eq!(packet.continuity, counter as u8);
+        }
+
+        let pes = pes_of(&packets);
+        assert_eq!(pes[..4], [0, 0, 1, PRIVATE_STREAM_1]);
+        assert_eq!(u16::from_be_bytes([pes[4], pes[5]]) as usize, pes.len() - 6);
+        assert_eq!(get_timestamp(&pes[9..14]), 90000);
+        // control header and size of the first access unit
+        assert_eq!(pes[14..18], [0x7f, 0xe0, 0xff, 45]);
+        assert_eq!(pes[18..318], [1; 300][..]);
+        assert_eq!(pes[318..321], [0x7f, 0xe0, 20]);
+    }
+
+    #[test]
+    fn aac_program() {
+        let mut muxer = TsMuxer::new(TsStream::Aac {
+            audio_specific_config: vec![0x11, 0x90],
+        });
+        muxer.push(&[7; 100], 0);
+        let stream = muxer.flush();
+        let packets = demux(&stream);
+        let pmt = section_of(&packets[1]);
+        assert_eq!(pmt[12], STREAM_TYPE_ADTS);
+        // without descriptors
+        assert_eq!(pmt[15..17], [0xf0, 0x00]);
+
+        let pes = pes_of(&packets);
+        assert_eq!(pes[..4], [0, 0, 1, AUDIO_STREAM]);
+        assert_eq!(pes[14..21], adts_header(&[0x11, 0x90], 100));
+        assert_eq!(pes[21..], [7; 100][..]);
+    }
+
+    #[test]
+    fn continuity_across_pes_packets() {
+        let mut muxer = TsMuxer::new(TsStream::Opus { channels: 1 });
+        let mut stream = Vec::new();
+        for pts in 0..20 * PACKETS_PER_PES as u64 {
+            stream.extend(muxer.push(&[0; 100], pts * 1800));
+        }
+        let packets = demux(&stream);
+        for pid in [PAT_PID, PMT_PID, AUDIO_PID].iter() {
+            let counters: Vec<u8> = packets
+                .iter()
+                .filter(|packet| packet.pid == *pid)
+                .map(|packet| packet.continuity)
+                .collect();
+            assert!(counters.len() > 16);
+            for (index, &counter) in counters.iter().enumerate() {
+                assert_eq!(counter, index as u8 & 0x0f);
+            }
+        }
+    }
+}
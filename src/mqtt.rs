@@ -0,0 +1,667 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! MQTT client publishing the levels and the events, with the discovery of the sensors
+//! by Home Assistant.
+//!
+//! Only what is needed to publish is implemented, of MQTT 3.1.1: the connection
+//! with a last will and the publish of QoS 0 messages.
+
+use crate::error::Error;
+use crate::events::Event;
+use crate::history::HistoryPoint;
+use crate::supervisor::Supervisor;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+
+pub const DEFAULT_PORT: u16 = 1883;
+pub const DEFAULT_TOPIC: &str = "audio-in-stream";
+pub const DEFAULT_CLIENT_ID: &str = "audio-in-stream-rs";
+/// Time between level summaries, in seconds.
+pub const DEFAULT_INTERVAL: f64 = 10.0;
+/// Prefix of the topics of the Home Assistant MQTT discovery.
+pub const DISCOVERY_PREFIX: &str = "homeassistant";
+
+/// delay before reconnecting after the connection to the broker is lost
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+/// time between pings while there is nothing to publish
+const KEEP_ALIVE: Duration = Duration::from_secs(60);
+/// messages queued before dropping new ones
+const QUEUE_CAPACITY: usize = 64;
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const PINGREQ: u8 = 0xc0;
+const RETAIN: u8 = 0x01;
+const CLEAN_SESSION: u8 = 0x02;
+const WILL: u8 = 0x04;
+const WILL_RETAIN: u8 = 0x20;
+const PASSWORD: u8 = 0x40;
+const USERNAME: u8 = 0x80;
+
+/// MQTT broker and topic prefix, from an url like `mqtt://[user@]host[:port][/topic]`.
+#[derive(Clone, Debug)]
+pub struct MqttUrl {
+    pub host: String,
+    pub port: u16,
+    pub user: Option<String>,
+    pub topic: String,
+}
+
+impl MqttUrl {
+    pub fn parse(url: &str) -> Result<Self, String> {
+        let invalid_url = || format!("invalid MQTT url '{}'", url);
+        let rest = url
+            .strip_prefix("mqtt://")
+            .ok_or_else(|| format!("invalid MQTT url '{}', only mqtt:// is supported", url))?;
+        let (authority, topic) = match rest.split_once('/') {
+            Some((authority, topic)) => (authority, topic.trim_end_matches('/')),
+            None => (rest, ""),
+        };
+        let (user, host_port) = match authority.rsplit_once('@') {
+            Some((user, host_port)) => (Some(user), host_port),
+            None => (None, authority),
+        };
+        let (host, port) = match host_port.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => {
+                (host, port.parse().map_err(|_| invalid_url())?)
+            }
+            _ => (host_port, DEFAULT_PORT),
+        };
+        if host.is_empty() || topic.contains(['+', '#']) {
+            return Err(invalid_url());
+        }
+
+        Ok(MqttUrl {
+            host: host.to_owned(),
+            port,
+            user: user.map(str::to_owned),
+            topic: if topic.is_empty() {
+                DEFAULT_TOPIC.to_owned()
+            } else {
+                topic.to_owned()
+            },
+        })
+    }
+}
+
+/// Configuration of the MQTT client.
+#[derive(Clone, Debug)]
+pub struct MqttConfig {
+    pub url: MqttUrl,
+    pub password: Option<String>,
+    /// also the id of the device in Home Assistant, unique for each instance
+    pub client_id: String,
+    /// publish the Home Assistant discovery payloads of the sensors
+    pub discovery: bool,
+    /// indices of the metered channels in the input stream
+    pub channels: Vec<usize>,
+}
+
+impl MqttConfig {
+    /// `<topic>/status`: `online`, or `offline` as the last will, retained
+    fn status_topic(&self) -> String {
+        format!("{}/status", self.url.topic)
+    }
+
+    /// `<topic>/levels`: the levels of each interval, as in `/api/history`
+    fn levels_topic(&self) -> String {
+        format!("{}/levels", self.url.topic)
+    }
+
+    /// `<topic>/channel/<channel>/sound`: `ON` or `OFF` on the end and start of the silence
+    /// of the channel, retained
+    fn sound_topic(&self, channel: usize) -> String {
+        format!("{}/channel/{}/sound", self.url.topic, channel)
+    }
+
+    /// `<topic>/events/<event>`: each event, as in the webhooks
+    fn event_topic(&self, event: &str) -> String {
+        format!("{}/events/{}", self.url.topic, event)
+    }
+
+    /// The retained configurations of the sensors of Home Assistant: the RMS level
+    /// and the true peak of each channel, the short-term loudness and the sound
+    /// of each channel.
+    fn discovery_messages(&self) -> Vec<Message> {
+        let device = serde_json::json!({
+            "identifiers": [self.client_id],
+            "name": self.client_id,
+            "model": "audio-in-stream-rs",
+        });
+        let sensor = |component: &str, object: &str, mut config: serde_json::Value| {
+            config["unique_id"] = format!("{}_{}", self.client_id, object).into();
+            config["availability_topic"] = self.status_topic().into();
+            config["device"] = device.clone();
+            Message {
+                topic: format!(
+                    "{}/{}/{}/{}/config",
+                    DISCOVERY_PREFIX, component, self.client_id, object
+                ),
+                payload: config.to_string(),
+                retain: true,
+            }
+        };
+        let mut messages = vec![sensor(
+            "sensor",
+            "short_term",
+            serde_json::json!({
+                "name": "Short-term loudness",
+                "state_topic": self.levels_topic(),
+                "value_template": "{{ value_json.short_term }}",
+                "unit_of_measurement": "LUFS",
+                "state_class": "measurement",
+            }),
+        )];
+        for (index, channel) in self.channels.iter().enumerate() {
+            messages.push(sensor(
+                "sensor",
+                &format!("channel_{}_rms", channel),
+                serde_json::json!({
+                    "name": format!("Channel {} RMS", channel),
+                    "state_topic": self.levels_topic(),
+                    "value_template": format!("{{{{ value_json.channels[{}].rms }}}}", index),
+                    "unit_of_measurement": "dB",
+                    "state_class": "measurement",
+                }),
+            ));
+            messages.push(sensor(
+                "sensor",
+                &format!("channel_{}_peak", channel),
+                serde_json::json!({
+                    "name": format!("Channel {} peak", channel),
+                    "state_topic": self.levels_topic(),
+                    "value_template": format!("{{{{ value_json.channels[{}].peak }}}}", index),
+                    "unit_of_measurement": "dB",
+                    "state_class": "measurement",
+                }),
+            ));
+            messages.push(sensor(
+                "binary_sensor",
+                &format!("channel_{}_sound", channel),
+                serde_json::json!({
+                    "name": format!("Channel {} sound", channel),
+                    "state_topic": self.sound_topic(*channel),
+                    "device_class": "sound",
+                }),
+            ));
+        }
+        messages
+    }
+}
+
+struct Message {
+    topic: String,
+    payload: String,
+    retain: bool,
+}
+
+/// Publishes the levels and the events from a task of its own, reconnecting when
+/// the connection to the broker is lost, with the messages queued meanwhile
+/// until the queue is full. The failed connections are reported to the supervisor.
+#[derive(Clone)]
+pub struct MqttPublisher {
+    sender: mpsc::Sender<Message>,
+    config: Arc<MqttConfig>,
+}
+
+impl MqttPublisher {
+    pub fn start(config: MqttConfig, supervisor: Supervisor) -> Self {
+        let config = Arc::new(config);
+        let (sender, receiver) = mpsc::channel(QUEUE_CAPACITY);
+        tokio::spawn(run_client(Arc::clone(&config), receiver, supervisor));
+        MqttPublisher { sender, config }
+    }
+
+    fn publish(&self, message: Message) {
+        self.sender.try_send(message).ok();
+    }
+
+    /// Publish the levels of an interval.
+    pub fn publish_levels(&self, point: &HistoryPoint) {
+        self.publish(Message {
+            topic: self.config.levels_topic(),
+            payload: serde_json::to_string(point).expect("failed to serialize levels"),
+            retain: false,
+        });
+    }
+
+    /// Publish the event, and the sound of the channel on the start and end of its silence.
+    pub fn publish_event(&self, event: &Event) {
+        let data = serde_json::to_value(event).expect("failed to serialize event");
+        let name = data["event"].as_str().unwrap_or_default();
+        let sound = match event {
+            Event::SilenceStart { channel, .. } => Some((channel, "OFF")),
+            Event::SilenceEnd { channel, .. } => Some((channel, "ON")),
+            _ => None,
+        };
+        if let Some((&channel, state)) = sound {
+            self.publish(Message {
+                topic: self.config.sound_topic(channel),
+                payload: state.to_owned(),
+                retain: true,
+            });
+        }
+        self.publish(Message {
+            topic: self.config.event_topic(name),
+            payload: data.to_string(),
+            retain: false,
+        });
+    }
+}
+
+/// Append the remaining length of a packet, 7 bits in each byte.
+fn put_length(packet: &mut Vec<u8>, mut length: usize) {
+    loop {
+        let byte = (length % 128) as u8;
+        length /= 128;
+        if length > 0 {
+            packet.push(byte | 0x80);
+        } else {
+            packet.push(byte);
+            return;
+        }
+    }
+}
+
+fn put_string(packet: &mut Vec<u8>, string: &str) {
+    packet.extend_from_slice(&(string.len() as u16).to_be_bytes());
+    packet.extend_from_slice(string.as_bytes());
+}
+
+fn packet(packet_type: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![packet_type];
+    put_length(&mut packet, body.len());
+    packet.extend_from_slice(body);
+    packet
+}
+
+async fn write_publish(stream: &mut OwnedWriteHalf, message: &Message) -> io::Result<()> {
+    let mut body = Vec::with_capacity(2 + message.topic.len() + message.payload.len());
+    put_string(&mut body, &message.topic);
+    body.extend_from_slice(message.payload.as_bytes());
+    let flags = if message.retain { RETAIN } else { 0 };
+    stream.write_all(&packet(PUBLISH | flags, &body)).await
+}
+
+/// Connect to the broker, with `offline` as the last will of the status topic.
+async fn connect(config: &MqttConfig) -> io::Result<TcpStream> {
+    let mut stream = TcpStream::connect((config.url.host.as_str(), config.url.port)).await?;
+    let mut flags = CLEAN_SESSION | WILL | WILL_RETAIN;
+    let mut body = Vec::new();
+    put_string(&mut body, "MQTT");
+    // protocol level of MQTT 3.1.1
+    body.push(4);
+    if config.url.user.is_some() {
+        flags |= USERNAME;
+    }
+    if config.password.is_some() {
+        flags |= PASSWORD;
+    }
+    body.push(flags);
+    body.extend_from_slice(&(KEEP_ALIVE.as_secs() as u16 * 3 / 2).to_be_bytes());
+    put_string(&mut body, &config.client_id);
+    put_string(&mut body, &config.status_topic());
+    put_string(&mut body, "offline");
+    if let Some(user) = &config.url.user {
+        put_string(&mut body, user);
+    }
+    if let Some(password) = &config.password {
+        put_string(&mut body, password);
+    }
+    stream.write_all(&packet(CONNECT, &body)).await?;
+
+    let mut connack = [0; 4];
+    stream.read_exact(&mut connack).await?;
+    if connack[0] != CONNACK {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "unexpected response of the MQTT broker",
+        ));
+    }
+    match connack[3] {
+        0 => Ok(stream),
+        4 | 5 => Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "not authorized by the MQTT broker",
+        )),
+        code => Err(io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            format!("refused by the MQTT broker, return code {}", code),
+        )),
+    }
+}
+
+/// Publish the messages received, reconnecting when the connection is lost,
+/// until the publishers go away.
+async fn run_client(
+    config: Arc<MqttConfig>,
+    mut messages: mpsc::Receiver<Message>,
+    supervisor: Supervisor,
+) {
+    loop {
+        let stream = match connect(&config).await {
+            Ok(stream) => stream,
+            Err(err) => {
+                supervisor.report(Error::Output(format!(
+                    "failed to connect to MQTT broker {}:{}: {}",
+                    config.url.host, config.url.port, err
+                )));
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                continue;
+            }
+        };
+        // the responses to the pings are discarded
+        let (mut reader, mut stream) = stream.into_split();
+        let responses =
+            tokio::spawn(async move { tokio::io::copy(&mut reader, &mut tokio::io::sink()).await });
+
+        let mut connected_messages = vec![Message {
+            topic: config.status_topic(),
+            payload: String::from("online"),
+            retain: true,
+        }];
+        if config.discovery {
+            connected_messages.extend(config.discovery_messages());
+        }
+        let mut result = Ok(());
+        for message in &connected_messages {
+            result = write_publish(&mut stream, message).await;
+            if result.is_err() {
+                break;
+            }
+        }
+        while result.is_ok() {
+            result = match tokio::time::timeout(KEEP_ALIVE, messages.recv()).await {
+                Ok(Some(message)) => write_publish(&mut stream, &message).await,
+                Err(_) => stream.write_all(&packet(PINGREQ, &[])).await,
+                Ok(None) => {
+                    responses.abort();
+                    return;
+                }
+            };
+        }
+        if let Err(err) = result {
+            supervisor.report(Error::Output(format!(
+                "connection to MQTT broker {}:{} lost: {}",
+                config.url.host, config.url.port, err
+            )));
+        }
+
+        responses.abort();
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::ChannelHistory;
+    use tokio::net::TcpListener;
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            tokio::time::timeout(Duration::from_secs(5), future)
+                .await
+                .unwrap()
+        })
+    }
+
+    fn config(url: &str, channels: Vec<usize>) -> MqttConfig {
+        MqttConfig {
+            url: MqttUrl::parse(url).unwrap(),
+            password: None,
+            client_id: DEFAULT_CLIENT_ID.to_owned(),
+            discovery: false,
+            channels,
+        }
+    }
+
+    /// The type and flags, and the body of the next packet.
+    async fn read_packet(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+        let packet_type = stream.read_u8().await.unwrap();
+        let mut len = 0;
+        for shift in (0..28).step_by(7) {
+            let byte = stream.read_u8().await.unwrap();
+            len |= ((byte & 0x7f) as usize) << shift;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        let mut body = vec![0; len];
+        stream.read_exact(&mut body).await.unwrap();
+        (packet_type, body)
+    }
+
+    fn take_string(body: &mut &[u8]) -> String {
+        let len = u16::from_be_bytes([body[0], body[1]]) as usize;
+        let string = String::from_utf8(body[2..2 + len].to_vec()).unwrap();
+        *body = &body[2 + len..];
+        string
+    }
+
+    /// Topic, payload and retain flag of a publish packet.
+    async fn read_publish(stream: &mut TcpStream) -> (String, String, bool) {
+        let (packet_type, body) = read_packet(stream).await;
+        assert_eq!(packet_type & 0xf0, PUBLISH);
+        let mut body = &body[..];
+        let topic = take_string(&mut body);
+        (
+            topic,
+            String::from_utf8(body.to_vec()).unwrap(),
+            packet_type & RETAIN != 0,
+        )
+    }
+
+    #[test]
+    fn parse_urls() {
+        let url = MqttUrl::parse("mqtt://broker").unwrap();
+        assert_eq!((url.host.as_str(), url.port), ("broker", DEFAULT_PORT));
+        assert_eq!((url.user, url.topic.as_str()), (None, DEFAULT_TOPIC));
+
+        let url = MqttUrl::parse("mqtt://user@[::1]:1884/studio/a/").unwrap();
+        assert_eq!((url.host.as_str(), url.port), ("[::1]", 1884));
+        assert_eq!(url.user.as_deref(), Some("user"));
+        assert_eq!(url.topic, "studio/a");
+
+        for url in [
+            "mqtts://broker",
+            "mqtt://",
+            "mqtt://broker:port",
+            "mqtt://broker/a/+",
+            "mqtt://broker/#",
+        ]
+        .iter()
+        {
+            assert!(MqttUrl::parse(url).is_err(), "{}", url);
+        }
+    }
+
+    #[test]
+    fn remaining_lengths() {
+        let encode = |length| {
+            let mut packet = Vec::new();
+            put_length(&mut packet, length);
+            packet
+        };
+        assert_eq!(encode(0), [0x00]);
+        assert_eq!(encode(127), [0x7f]);
+        assert_eq!(encode(128), [0x80, 0x01]);
+        assert_eq!(encode(16_383), [0xff, 0x7f]);
+        assert_eq!(encode(16_384), [0x80, 0x80, 0x01]);
+        assert_eq!(encode(268_435_455), [0xff, 0xff, 0xff, 0x7f]);
+    }
+
+    #[test]
+    fn discovery_of_the_sensors() {
+        let config = config("mqtt://broker/studio", vec![0, 3]);
+        let messages = config.discovery_messages();
+        assert_eq!(messages.len(), 1 + 2 * 3);
+        assert!(messages.iter().all(|message| message.retain));
+        assert_eq!(
+            messages[0].topic,
+            "homeassistant/sensor/audio-in-stream-rs/short_term/config"
+        );
+        assert_eq!(
+            messages[6].topic,
+            "homeassistant/binary_sensor/audio-in-stream-rs/channel_3_sound/config"
+        );
+
+        let rms: serde_json::Value = serde_json::from_str(&messages[4].payload).unwrap();
+        assert_eq!(rms["unique_id"], "audio-in-stream-rs_channel_3_rms");
+        assert_eq!(rms["state_topic"], "studio/levels");
+        assert_eq!(rms["availability_topic"], "studio/status");
+        // the index of the channel in the levels, not the input channel
+        assert_eq!(rms["value_template"], "{{ value_json.channels[1].rms }}");
+        let sound: serde_json::Value = serde_json::from_str(&messages[6].payload).unwrap();
+        assert_eq!(sound["state_topic"], "studio/channel/3/sound");
+        assert_eq!(sound["device"]["identifiers"][0], "audio-in-stream-rs");
+    }
+
+    #[test]
+    fn publish_to_a_broker() {
+        block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let mut config = config(&format!("mqtt://user@127.0.0.1:{}/studio", port), vec![1]);
+            config.password = Some("secret".to_owned());
+            config.discovery = true;
+
+            // queued until connected
+            let publisher = MqttPublisher::start(config, Supervisor::start(|_| {}));
+            publisher.publish_event(&Event::SilenceStart {
+                timestamp: 10.0,
+                channel: 1,
+                since: 8.0,
+            });
+            publisher.publish_levels(&HistoryPoint {
+                timestamp: 0.0,
+                short_term: -23.0,
+                channels: vec![ChannelHistory {
+                    channel: 1,
+                    rms: -20.0,
+                    peak: -6.0,
+                }],
+            });
+
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let (packet_type, body) = read_packet(&mut stream).await;
+            assert_eq!(packet_type, CONNECT);
+            let mut body = &body[..];
+            assert_eq!(take_string(&mut body), "MQTT");
+            assert_eq!(body[0], 4);
+            assert_eq!(
+                body[1],
+                CLEAN_SESSION | WILL | WILL_RETAIN | USERNAME | PASSWORD
+            );
+            assert_eq!(u16::from_be_bytes([body[2], body[3]]), 90);
+            body = &body[4..];
+            assert_eq!(take_string(&mut body), DEFAULT_CLIENT_ID);
+            assert_eq!(take_string(&mut body), "studio/status");
+            assert_eq!(take_string(&mut body), "offline");
+            assert_eq!(take_string(&mut body), "user");
+            assert_eq!(take_string(&mut body), "secret");
+            assert!(body.is_empty());
+            stream.write_all(&[CONNACK, 2, 0, 0]).await.unwrap();
+
+            assert_eq!(
+                read_publish(&mut stream).await,
+                ("studio/status".to_owned(), "online".to_owned(), true)
+            );
+            for _ in 0..4 {
+                let (topic, _, retain) = read_publish(&mut stream).await;
+                assert!(topic.starts_with("homeassistant/"));
+                assert!(retain);
+            }
+            assert_eq!(
+                read_publish(&mut stream).await,
+                ("studio/channel/1/sound".to_owned(), "OFF".to_owned(), true)
+            );
+            let (topic, payload, retain) = read_publish(&mut stream).await;
+            assert_eq!(
+                (topic.as_str(), retain),
+                ("studio/events/silence_start", false)
+            );
+            let event: serde_json::Value = serde_json::from_str(&payload).unwrap();
+            assert_eq!(event["channel"], 1);
+            let (topic, payload, _) = read_publish(&mut stream).await;
+            assert_eq!(topic, "studio/levels");
+            let levels: serde_json::Value = serde_json::from_str(&payload).unwrap();
+            assert_eq!(levels["channels"][0]["peak"], -6.0);
+        });
+    }
+
+    #[test]
+    fn refused_connections() {
+        block_on(async {
+            for &(code, kind) in [
+                (2, io::ErrorKind::ConnectionRefused),
+                (5, io::ErrorKind::PermissionDenied),
+            ]
+            .iter()
+            {
+                let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+                let port = listener.local_addr().unwrap().port();
+                let broker = tokio::spawn(async move {
+                    let (mut stream, _) = listener.accept().await.unwrap();
+                    read_packet(&mut stream).await;
+                    stream.write_all(&[CONNACK, 2, 0, code]).await.unwrap();
+                });
+                let config = config(&format!("mqtt://127.0.0.1:{}", port), Vec::new());
+                let err = connect(&config).await.err().unwrap();
+                assert_eq!(err.kind(), kind);
+                broker.await.unwrap();
+            }
+        });
+    }
+
+    #[test]
+    fn refusal_reported() {
+        block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let (errors, reported) = std::sync::mpsc::channel();
+            let supervisor = Supervisor::start(move |err| errors.send(err).unwrap());
+            let config = config(&format!("mqtt://127.0.0.1:{}", port), Vec::new());
+            let _publisher = MqttPublisher::start(config, supervisor);
+
+            let (mut stream, _) = listener.accept().await.unwrap();
+            read_packet(&mut stream).await;
+            stream.write_all(&[CONNACK, 2, 0, 5]).await.unwrap();
+            let err = tokio::task::spawn_blocking(move || reported.recv().unwrap())
+                .await
+                .unwrap();
+            assert!(!err.is_fatal());
+            assert_eq!(
+                err.to_string(),
+                format!(
+                    "failed to connect to MQTT broker 127.0.0.1:{}: \
+                     not authorized by the MQTT broker",
+                    port
+                )
+            );
+        });
+    }
+}
@@ -0,0 +1,117 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Third octave band analyzer: the level of the 31 bands of IEC 61260 from 20 Hz to
+//! 20 kHz, from the power of the bins of the FFT of the latest samples in each band.
+
+use crate::spectrum::{SpectrumAnalyzer, Window};
+use crate::ChannelData;
+use serde::Serialize;
+
+/// Hz, the nominal center frequencies of the bands
+pub const NOMINAL_FREQUENCIES: [f32; 31] = [
+    20.0, 25.0, 31.5, 40.0, 50.0, 63.0, 80.0, 100.0, 125.0, 160.0, 200.0, 250.0, 315.0, 400.0,
+    500.0, 630.0, 800.0, 1000.0, 1250.0, 1600.0, 2000.0, 2500.0, 3150.0, 4000.0, 5000.0, 6300.0,
+    8000.0, 10000.0, 12500.0, 16000.0, 20000.0,
+];
+/// band of the exact center frequency of 1 kHz, the others are 10^(1/10) apart (base 10)
+const REFERENCE_BAND: i32 = 17;
+/// Hz, the widest bins, a fraction of the width of the 20 Hz band
+const MAX_BIN_WIDTH: f32 = 3.0;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct OctaveBands {
+    /// Hz, nominal center frequency of each band
+    pub frequencies: &'static [f32],
+    /// level of each band of each channel, in dBFS as a sine of the same power,
+    /// `null` in JSON for silence (-inf) and the bands over the Nyquist frequency
+    pub channels: Vec<Vec<f32>>,
+}
+
+/// Computes the level of the third octave bands of the latest samples of each channel.
+pub struct OctaveBandAnalyzer {
+    spectrum_analyzer: SpectrumAnalyzer,
+    /// equivalent noise bandwidth of the window, in bins
+    noise_bandwidth: f32,
+    /// the bins of each band, with the fraction of each bin in the band
+    band_bins: Vec<Vec<(usize, f32)>>,
+}
+
+impl OctaveBandAnalyzer {
+    pub fn new(sample_rate: u32, num_channels: usize) -> Self {
+        let fft_size = ((sample_rate as f32 / MAX_BIN_WIDTH) as usize).next_power_of_two();
+        let window = Window::Hann;
+        let coefficients = window.coefficients(fft_size);
+        let noise_bandwidth = fft_size as f32 * coefficients.iter().map(|w| w * w).sum::<f32>()
+            / coefficients.iter().sum::<f32>().powi(2);
+
+        let bin_width = sample_rate as f32 / fft_size as f32;
+        let num_bins = fft_size / 2 + 1;
+        let band_bins = (0..NOMINAL_FREQUENCIES.len() as i32)
+            .map(|band| {
+                let center = 1000.0 * 10f32.powf((band - REFERENCE_BAND) as f32 / 10.0);
+                let low = center * 10f32.powf(-1.0 / 20.0) / bin_width;
+                let high = center * 10f32.powf(1.0 / 20.0) / bin_width;
+                // bin k spans from k - 0.5 to k + 0.5
+                ((low + 0.5).floor() as usize..=((high + 0.5).floor() as usize))
+                    .filter(|&bin| bin < num_bins)
+                    .map(|bin| {
+                        let overlap =
+                            (high.min(bin as f32 + 0.5) - low.max(bin as f32 - 0.5)).max(0.0);
+                        (bin, overlap)
+                    })
+                    .collect()
+            })
+            .collect();
+
+        OctaveBandAnalyzer {
+            spectrum_analyzer: SpectrumAnalyzer::new(fft_size, window, sample_rate, num_channels),
+            noise_bandwidth,
+            band_bins,
+        }
+    }
+
+    /// Add the samples of an input buffer, and compute the bands of the latest samples.
+    pub fn process(&mut self, channels: &[ChannelData]) -> OctaveBands {
+        let spectrum = self.spectrum_analyzer.process(channels);
+        OctaveBands {
+            frequencies: &NOMINAL_FREQUENCIES,
+            channels: spectrum
+                .channels
+                .iter()
+                .map(|magnitudes| {
+                    self.band_bins
+                        .iter()
+                        .map(|bins| {
+                            // the squares of the amplitudes of the bins over the noise
+                            // bandwidth, the square of the amplitude of a sine of that power
+                            let power: f32 = bins
+                                .iter()
+                                .map(|&(bin, fraction)| {
+                                    fraction * 10f32.powf(magnitudes[bin] / 10.0)
+                                })
+                                .sum();
+                            if bins.is_empty() {
+                                f32::NEG_INFINITY
+                            } else {
+                                10.0 * (power / self.noise_bandwidth).log10()
+                            }
+                        })
+                        .collect()
+                })
+                .collect(),
+        }
+    }
+}
@@ -0,0 +1,292 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Ogg/Opus encoding (RFC 7845) of a stream of interleaved samples.
+
+use crate::encoder::StreamEncoder;
+use crate::resample::LinearResampler;
+use audiopus::coder::Encoder;
+use futures_util::Stream;
+use ogg::writing::{PacketWriteEndInfo, PacketWriter};
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+
+/// Opus always works at 48 kHz, the input is resampled to it.
+pub const OPUS_SAMPLE_RATE: u32 = 48000;
+/// 20 ms frames
+pub const OPUS_FRAME_LEN: usize = 960;
+/// maximum size of an Opus packet recommended by libopus
+const MAX_PACKET_LEN: usize = 4000;
+/// packets grouped in each Ogg page, 100 ms of audio
+/// (a trade-off between the latency and the overhead of the page headers)
+const PACKETS_PER_PAGE: usize = 5;
+
+pub const DEFAULT_BITRATE: i32 = 64000;
+
+/// Encodes interleaved samples as raw Opus packets of 20 ms, mono for 1 channel and
+/// stereo of the first 2 channels otherwise.
+pub struct OpusPacketEncoder {
+    encoder: Encoder,
+    resampler: LinearResampler,
+    input_channels: usize,
+    opus_channels: usize,
+    /// resampled samples not encoded yet
+    pending_samples: Vec<f32>,
+}
+
+impl OpusPacketEncoder {
+    pub fn new(channels: u16, sample_rate: u32, bitrate: i32) -> Result<Self, String> {
+        let input_channels = channels as usize;
+        let (opus_channels, channel_count) = if input_channels == 1 {
+            (1, audiopus::Channels::Mono)
+        } else {
+            (2, audiopus::Channels::Stereo)
+        };
+
+        let mut encoder = Encoder::new(
+            audiopus::SampleRate::Hz48000,
+            channel_count,
+            audiopus::Application::Audio,
+        )
+        .map_err(|err| format!("failed to create Opus encoder: {}", err))?;
+        encoder
+            .set_bitrate(audiopus::Bitrate::BitsPerSecond(bitrate))
+            .map_err(|err| format!("invalid Opus bitrate {}: {}", bitrate, err))?;
+
+        Ok(OpusPacketEncoder {
+            encoder,
+            resampler: LinearResampler::new(opus_channels, sample_rate, OPUS_SAMPLE_RATE),
+            input_channels,
+            opus_channels,
+            pending_samples: Vec::new(),
+        })
+    }
+
+    /// Channels of the Opus stream, 1 or 2.
+    pub fn channels(&self) -> usize {
+        self.opus_channels
+    }
+
+    /// Samples at 48 kHz the decoder skips at the start.
+    pub fn lookahead(&self) -> Result<u32, String> {
+        self.encoder
+            .lookahead()
+            .map_err(|err| format!("failed to get Opus encoder lookahead: {}", err))
+    }
+
+    /// Encode a block of interleaved samples, returns the packets of the whole Opus frames
+    /// there is samples for so far.
+    pub fn encode(&mut self, samples: &[f32]) -> Result<Vec<Vec<u8>>, String> {
+        let num_frames = samples.len() / self.input_channels;
+        let mut opus_input = Vec::with_capacity(num_frames * self.opus_channels);
+        for frame in samples.chunks_exact(self.input_channels) {
+            opus_input.extend_from_slice(&frame[..self.opus_channels]);
+        }
+        self.resampler
+            .process(&opus_input, &mut self.pending_samples);
+
+        let opus_frame_len = OPUS_FRAME_LEN * self.opus_channels;
+        let mut packets = Vec::new();
+        let mut offset = 0;
+        while self.pending_samples.len() - offset >= opus_frame_len {
+            packets.push(self.encode_frame(offset)?);
+            offset += opus_frame_len;
+        }
+        self.pending_samples.drain(..offset);
+        Ok(packets)
+    }
+
+    /// Encode the resampled samples left, padded with silence to a whole Opus frame,
+    /// returns the packet and the number of frames of audio in it.
+    pub fn finish(&mut self) -> Result<(Vec<u8>, usize), String> {
+        let num_frames = self.pending_samples.len() / self.opus_channels;
+        self.pending_samples
+            .resize(OPUS_FRAME_LEN * self.opus_channels, 0.0);
+        let packet = self.encode_frame(0)?;
+        self.pending_samples.clear();
+        Ok((packet, num_frames))
+    }
+
+    /// Encode an Opus frame of the pending samples from `offset`.
+    fn encode_frame(&mut self, offset: usize) -> Result<Vec<u8>, String> {
+        let opus_frame_len = OPUS_FRAME_LEN * self.opus_channels;
+        let mut packet = [0_u8; MAX_PACKET_LEN];
+        let packet_len = self
+            .encoder
+            .encode_float(
+                &self.pending_samples[offset..offset + opus_frame_len],
+                &mut packet[..],
+            )
+            .map_err(|err| format!("failed to encode Opus packet: {}", err))?;
+        Ok(packet[..packet_len].to_vec())
+    }
+}
+
+/// Encodes interleaved samples as Ogg/Opus, mono for 1 channel and
+/// stereo of the first 2 channels otherwise.
+pub struct OggOpusEncoder {
+    encoder: OpusPacketEncoder,
+    packet_writer: PacketWriter<'static, Vec<u8>>,
+    serial: u32,
+    granule_position: u64,
+    packets_in_page: usize,
+}
+
+impl OggOpusEncoder {
+    /// Create the encoder, with the Ogg/Opus headers already written.
+    pub fn new(channels: u16, sample_rate: u32, bitrate: i32) -> Result<Self, String> {
+        let encoder = OpusPacketEncoder::new(channels, sample_rate, bitrate)?;
+        let opus_channels = encoder.channels();
+        let pre_skip = encoder.lookahead()?;
+
+        let serial = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.subsec_nanos());
+
+        let mut ogg_opus_encoder = OggOpusEncoder {
+            encoder,
+            packet_writer: PacketWriter::new(Vec::new()),
+            serial,
+            granule_position: 0,
+            packets_in_page: 0,
+        };
+
+        let mut opus_head = Vec::with_capacity(19);
+        opus_head.extend_from_slice(b"OpusHead");
+        // version
+        opus_head.push(1);
+        opus_head.push(opus_channels as u8);
+        opus_head.extend_from_slice(&(pre_skip as u16).to_le_bytes());
+        // original sample rate, only informative
+        opus_head.extend_from_slice(&sample_rate.to_le_bytes());
+        // output gain
+        opus_head.extend_from_slice(&0_i16.to_le_bytes());
+        // channel mapping family 0, mono or stereo
+        opus_head.push(0);
+        ogg_opus_encoder.write_header_packet(opus_head)?;
+
+        let vendor = concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION"));
+        let mut opus_tags = Vec::new();
+        opus_tags.extend_from_slice(b"OpusTags");
+        opus_tags.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+        opus_tags.extend_from_slice(vendor.as_bytes());
+        // no user comments
+        opus_tags.extend_from_slice(&0_u32.to_le_bytes());
+        ogg_opus_encoder.write_header_packet(opus_tags)?;
+
+        Ok(ogg_opus_encoder)
+    }
+
+    /// each header packet goes in its own page
+    fn write_header_packet(&mut self, packet: Vec<u8>) -> Result<(), String> {
+        self.packet_writer
+            .write_packet(packet, self.serial, PacketWriteEndInfo::EndPage, 0)
+            .map_err(|err| format!("failed to write Ogg page: {}", err))
+    }
+
+    /// Encode a block of interleaved samples,
+    /// the Opus packets are written as soon as there is enough samples.
+    pub fn encode(&mut self, samples: &[f32]) -> Result<(), String> {
+        for packet in self.encoder.encode(samples)? {
+            self.packets_in_page += 1;
+            let end_info = if self.packets_in_page == PACKETS_PER_PAGE {
+                self.packets_in_page = 0;
+                PacketWriteEndInfo::EndPage
+            } else {
+                PacketWriteEndInfo::NormalPacket
+            };
+            self.write_audio_packet(packet, OPUS_FRAME_LEN, end_info)?;
+        }
+        Ok(())
+    }
+
+    /// Encode the resampled samples left, padded with silence to a whole Opus frame,
+    /// and end the stream.
+    pub fn finish(&mut self) -> Result<(), String> {
+        let (packet, num_frames) = self.encoder.finish()?;
+        // the granule position of the last page trims the padding
+        self.write_audio_packet(packet, num_frames, PacketWriteEndInfo::EndStream)
+    }
+
+    /// Write an Opus packet, of which `num_frames` frames are audio.
+    fn write_audio_packet(
+        &mut self,
+        packet: Vec<u8>,
+        num_frames: usize,
+        end_info: PacketWriteEndInfo,
+    ) -> Result<(), String> {
+        self.granule_position += num_frames as u64;
+        self.packet_writer
+            .write_packet(packet, self.serial, end_info, self.granule_position)
+            .map_err(|err| format!("failed to write Ogg page: {}", err))
+    }
+
+    /// Take the Ogg pages completed so far.
+    pub fn take_bytes(&mut self) -> Vec<u8> {
+        std::mem::take(self.packet_writer.inner_mut())
+    }
+}
+
+impl StreamEncoder for OggOpusEncoder {
+    fn encode(&mut self, samples: &[f32]) -> Result<(), String> {
+        OggOpusEncoder::encode(self, samples)
+    }
+
+    fn finish(&mut self) -> Result<(), String> {
+        OggOpusEncoder::finish(self)
+    }
+
+    fn take_bytes(&mut self) -> Vec<u8> {
+        OggOpusEncoder::take_bytes(self)
+    }
+}
+
+/// Never ending stream of the interleaved samples received from a broadcast,
+/// encoded as Ogg/Opus, ends when the sender goes away.
+pub struct OggOpusStream {
+    encoder: OggOpusEncoder,
+    samples: mpsc::Receiver<Arc<Vec<f32>>>,
+}
+
+impl OggOpusStream {
+    pub fn new(encoder: OggOpusEncoder, samples: mpsc::Receiver<Arc<Vec<f32>>>) -> Self {
+        OggOpusStream { encoder, samples }
+    }
+}
+
+impl Stream for OggOpusStream {
+    type Item = io::Result<Vec<u8>>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            let bytes = self.encoder.take_bytes();
+            if !bytes.is_empty() {
+                return Poll::Ready(Some(Ok(bytes)));
+            }
+            match ready!(self.samples.poll_recv(cx)) {
+                Some(samples) => {
+                    if let Err(err) = self.encoder.encode(&samples) {
+                        return Poll::Ready(Some(Err(io::Error::other(err))));
+                    }
+                }
+                None => return Poll::Ready(None),
+            }
+        }
+    }
+}
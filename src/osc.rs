@@ -0,0 +1,126 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! OSC output of the levels, for lighting desks, TouchOSC layouts and show control.
+//!
+//! Each update is a bundle of messages of one float each:
+//! `<prefix>/ch/<channel>/dbov`, `<prefix>/ch/<channel>/peak` in dBTP,
+//! `<prefix>/ch/<channel>/rms` from 0 to 1, `<prefix>/lufs/momentary`
+//! and `<prefix>/lufs/short_term`, with the silence at `FLOOR_DB`.
+
+use crate::levels::Levels;
+use tokio::net::UdpSocket;
+
+pub const DEFAULT_PREFIX: &str = "/audio";
+/// Updates per second.
+pub const DEFAULT_RATE: f64 = 20.0;
+/// Level sent for silence, as the receivers do not expect infinities.
+pub const FLOOR_DB: f32 = -120.0;
+
+/// Append an OSC string: the bytes with a null terminator, padded to 4 bytes.
+fn put_string(packet: &mut Vec<u8>, string: &str) {
+    packet.extend_from_slice(string.as_bytes());
+    let padding = 4 - string.len() % 4;
+    packet.resize(packet.len() + padding, 0);
+}
+
+/// An OSC message with a float argument.
+fn message(address: &str, value: f32) -> Vec<u8> {
+    let mut message = Vec::with_capacity(address.len() + 12);
+    put_string(&mut message, address);
+    put_string(&mut message, ",f");
+    message.extend_from_slice(&value.to_be_bytes());
+    message
+}
+
+/// An OSC bundle of the messages, to be handled immediately.
+fn bundle(messages: &[Vec<u8>]) -> Vec<u8> {
+    let mut bundle = Vec::new();
+    put_string(&mut bundle, "#bundle");
+    // time tag of "immediately"
+    bundle.extend_from_slice(&1_u64.to_be_bytes());
+    for message in messages {
+        bundle.extend_from_slice(&(message.len() as u32).to_be_bytes());
+        bundle.extend_from_slice(message);
+    }
+    bundle
+}
+
+fn level(level: f32) -> f32 {
+    if level.is_nan() {
+        FLOOR_DB
+    } else {
+        level.max(FLOOR_DB)
+    }
+}
+
+/// Sends the levels to an OSC receiver over UDP.
+pub struct OscSender {
+    socket: UdpSocket,
+    prefix: String,
+}
+
+impl OscSender {
+    /// Sender to the receiver at `address`, e.g. `192.168.1.20:8000`, with the addresses
+    /// of the messages starting with `prefix`.
+    pub fn new(address: &str, prefix: &str) -> Result<Self, String> {
+        if !prefix.starts_with('/') || prefix.ends_with('/') || prefix.contains([' ', '#']) {
+            return Err(format!(
+                "invalid OSC prefix '{}', expected an address like /audio",
+                prefix
+            ));
+        }
+        let bind = if address.starts_with('[') {
+            "[::]:0"
+        } else {
+            "0.0.0.0:0"
+        };
+        let socket = std::net::UdpSocket::bind(bind)
+            .and_then(|socket| socket.connect(address).map(|_| socket))
+            .and_then(|socket| socket.set_nonblocking(true).map(|_| socket))
+            .and_then(UdpSocket::from_std)
+            .map_err(|err| format!("invalid OSC address '{}': {}", address, err))?;
+        Ok(OscSender {
+            socket,
+            prefix: prefix.to_owned(),
+        })
+    }
+
+    pub async fn send(&self, levels: &Levels) -> Result<(), String> {
+        let mut messages = Vec::with_capacity(levels.channels.len() * 3 + 2);
+        for channel in &levels.channels {
+            let address = format!("{}/ch/{}", self.prefix, channel.channel);
+            messages.push(message(&format!("{}/dbov", address), level(channel.dbov)));
+            messages.push(message(
+                &format!("{}/peak", address),
+                level(channel.true_peak),
+            ));
+            messages.push(message(&format!("{}/rms", address), channel.rms));
+        }
+        messages.push(message(
+            &format!("{}/lufs/momentary", self.prefix),
+            level(levels.loudness.momentary),
+        ));
+        messages.push(message(
+            &format!("{}/lufs/short_term", self.prefix),
+            level(levels.loudness.short_term),
+        ));
+        self.socket
+            .send(&bundle(&messages))
+            .await
+            .map(|_| ())
+            .map_err(|err| format!("failed to send OSC: {}", err))
+    }
+}
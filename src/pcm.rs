@@ -0,0 +1,138 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Encoding of interleaved samples as raw PCM bytes.
+
+use crate::wav::f32_to_i16;
+use futures_util::Stream;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::sync::mpsc;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PcmSampleFormat {
+    S16,
+    F32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Endianness {
+    Little,
+    Big,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PcmFormat {
+    pub sample_format: PcmSampleFormat,
+    pub endianness: Endianness,
+}
+
+impl Default for PcmFormat {
+    fn default() -> Self {
+        PcmFormat {
+            sample_format: PcmSampleFormat::S16,
+            endianness: Endianness::Little,
+        }
+    }
+}
+
+impl PcmFormat {
+    /// Parse the sample format (`s16` or `f32`) and the endianness (`le` or `be`),
+    /// each one defaults to `s16` and `le`.
+    pub fn parse(sample_format: Option<&str>, endianness: Option<&str>) -> Result<Self, String> {
+        let default = PcmFormat::default();
+        Ok(PcmFormat {
+            sample_format: match sample_format {
+                None => default.sample_format,
+                Some("s16") => PcmSampleFormat::S16,
+                Some("f32") => PcmSampleFormat::F32,
+                Some(sample_format) => {
+                    return Err(format!(
+                        "invalid sample format '{}', expected one of: s16, f32",
+                        sample_format
+                    ))
+                }
+            },
+            endianness: match endianness {
+                None => default.endianness,
+                Some("le") => Endianness::Little,
+                Some("be") => Endianness::Big,
+                Some(endianness) => {
+                    return Err(format!(
+                        "invalid endianness '{}', expected one of: le, be",
+                        endianness
+                    ))
+                }
+            },
+        })
+    }
+
+    pub fn bytes_per_sample(&self) -> usize {
+        match self.sample_format {
+            PcmSampleFormat::S16 => 2,
+            PcmSampleFormat::F32 => 4,
+        }
+    }
+
+    /// Append the samples encoded in this format to `bytes`.
+    pub fn encode(&self, samples: &[f32], bytes: &mut Vec<u8>) {
+        bytes.reserve(samples.len() * self.bytes_per_sample());
+        for &sample in samples {
+            match (self.sample_format, self.endianness) {
+                (PcmSampleFormat::S16, Endianness::Little) => {
+                    bytes.extend_from_slice(&f32_to_i16(sample).to_le_bytes())
+                }
+                (PcmSampleFormat::S16, Endianness::Big) => {
+                    bytes.extend_from_slice(&f32_to_i16(sample).to_be_bytes())
+                }
+                (PcmSampleFormat::F32, Endianness::Little) => {
+                    bytes.extend_from_slice(&sample.to_le_bytes())
+                }
+                (PcmSampleFormat::F32, Endianness::Big) => {
+                    bytes.extend_from_slice(&sample.to_be_bytes())
+                }
+            }
+        }
+    }
+}
+
+/// Never ending stream of the interleaved samples received from a broadcast,
+/// encoded as raw PCM, ends when the sender goes away.
+pub struct PcmStream {
+    format: PcmFormat,
+    samples: mpsc::Receiver<Arc<Vec<f32>>>,
+}
+
+impl PcmStream {
+    pub fn new(format: PcmFormat, samples: mpsc::Receiver<Arc<Vec<f32>>>) -> Self {
+        PcmStream { format, samples }
+    }
+}
+
+impl Stream for PcmStream {
+    type Item = io::Result<Vec<u8>>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.samples.poll_recv(cx).map(|samples| {
+            samples.map(|samples| {
+                let mut bytes = Vec::new();
+                self.format.encode(&samples, &mut bytes);
+                Ok(bytes)
+            })
+        })
+    }
+}
@@ -0,0 +1,338 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Watchdog of pilot tones: a continuous tone expected on a channel, lost when it is
+//! missing or drifts off its frequency, as the absence of the pilot of a transmission
+//! chain means the link is down even if noise keeps the level up.
+//!
+//! The tone is measured from the FFT of windows of the channel, its frequency and level
+//! interpolated between the bins.
+
+use crate::{parse_frequency, parse_level, ChannelData};
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+use serde::Serialize;
+use std::f32::consts::PI;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// Hz
+pub const DEFAULT_TOLERANCE: f32 = 50.0;
+/// dBFS
+pub const DEFAULT_MIN_LEVEL: f32 = -40.0;
+/// time a pilot tone is missing or off frequency before it is lost, and back before
+/// it is restored
+const HOLD_TIME: Duration = Duration::from_secs(1);
+/// samples of the windows, between these
+const MIN_WINDOW_SIZE: usize = 1024;
+const MAX_WINDOW_SIZE: usize = 65536;
+
+/// A pilot tone expected on a channel, or all the metered channels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PilotToneConfig {
+    /// index of the channel in the input stream
+    pub channel: Option<usize>,
+    /// Hz
+    pub frequency: f32,
+    /// Hz, the drift allowed
+    pub tolerance: f32,
+    /// dBFS, the lowest level
+    pub min_level: f32,
+}
+
+impl PilotToneConfig {
+    /// Parse `[<channel>@]<frequency>[:<tolerance>[:<level>]]`, with the frequencies in Hz
+    /// or kHz and the level in dBFS, e.g. `19kHz:50Hz:-40dBFS` or `1@19kHz`.
+    pub fn parse(pilot_tone: &str) -> Result<Self, String> {
+        let invalid = |reason: String| format!("invalid pilot tone '{}', {}", pilot_tone, reason);
+        let (channel, tone) = match pilot_tone.split_once('@') {
+            Some((channel, tone)) => match channel.trim().parse::<usize>() {
+                Ok(channel) => (Some(channel), tone),
+                Err(_) => return Err(invalid(format!("invalid channel '{}'", channel))),
+            },
+            None => (None, pilot_tone),
+        };
+        let mut fields = tone.split(':');
+        let frequency = parse_frequency(fields.next().unwrap_or_default()).map_err(invalid)?;
+        let tolerance = fields
+            .next()
+            .map(parse_frequency)
+            .transpose()
+            .map_err(invalid)?
+            .map_or(DEFAULT_TOLERANCE, |tolerance| tolerance as f32);
+        let min_level = fields
+            .next()
+            .map(parse_level)
+            .transpose()
+            .map_err(invalid)?
+            .unwrap_or(DEFAULT_MIN_LEVEL);
+        if fields.next().is_some() {
+            return Err(invalid(String::from("too many fields")));
+        }
+        Ok(PilotToneConfig {
+            channel,
+            frequency: frequency as f32,
+            tolerance,
+            min_level,
+        })
+    }
+}
+
+/// Why a pilot tone is lost.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PilotFault {
+    /// below the lowest level
+    Missing,
+    /// off its frequency by more than the tolerance
+    Drift,
+}
+
+#[derive(Clone, Debug)]
+pub enum PilotEvent {
+    /// the pilot tone of the channel has been missing or off frequency since the given
+    /// time, at the frequency and level measured last
+    Lost {
+        channel: usize,
+        frequency: f32,
+        fault: PilotFault,
+        measured_frequency: f32,
+        level: f32,
+        since: SystemTime,
+    },
+    /// the pilot tone lost since the given time is back since the given time
+    Restored {
+        channel: usize,
+        frequency: f32,
+        since: SystemTime,
+        until: SystemTime,
+    },
+}
+
+/// A pilot tone watched on a metered channel.
+struct Watch {
+    config: PilotToneConfig,
+    /// of the metered channel
+    position: usize,
+    /// since when it is lost
+    lost_since: Option<SystemTime>,
+    /// since when it is faulty while not lost yet, or fine while lost
+    changed_since: Option<SystemTime>,
+}
+
+pub struct PilotToneWatchdog {
+    fft: Arc<dyn Fft<f32>>,
+    window_coefficients: Vec<f32>,
+    /// scale of the FFT output to the amplitude of a sine
+    amplitude_scale: f32,
+    bin_width: f32,
+    watches: Vec<Watch>,
+    /// samples of the current window of each metered channel
+    windows: Vec<Vec<f32>>,
+    buffer: Vec<Complex<f32>>,
+    scratch: Vec<Complex<f32>>,
+}
+
+impl PilotToneWatchdog {
+    /// Watch the pilot tones on the metered channels of `channels_map`.
+    pub fn new(
+        configs: &[PilotToneConfig],
+        sample_rate: u32,
+        channels_map: &[usize],
+    ) -> Result<Self, String> {
+        let mut watches = Vec::new();
+        for config in configs {
+            if config.frequency + config.tolerance >= sample_rate as f32 / 2.0 {
+                return Err(format!(
+                    "invalid pilot tone of {} Hz, over the Nyquist frequency of {} Hz",
+                    config.frequency,
+                    sample_rate / 2
+                ));
+            }
+            match config.channel {
+                Some(channel) => match channels_map.iter().position(|&index| index == channel) {
+                    Some(position) => watches.push(Watch::new(*config, position)),
+                    None => {
+                        return Err(format!(
+                            "invalid channel {} of the pilot tone, it is not metered",
+                            channel
+                        ))
+                    }
+                },
+                None => watches
+                    .extend((0..channels_map.len()).map(|position| Watch::new(*config, position))),
+            }
+        }
+
+        // bins of half the smallest tolerance at most
+        let min_tolerance = configs
+            .iter()
+            .map(|config| config.tolerance)
+            .fold(f32::INFINITY, f32::min);
+        let window_size = ((2.0 * sample_rate as f32 / min_tolerance).ceil() as usize)
+            .next_power_of_two()
+            .clamp(MIN_WINDOW_SIZE, MAX_WINDOW_SIZE);
+        let fft = FftPlanner::new().plan_fft_forward(window_size);
+        let window_coefficients: Vec<f32> = (0..window_size)
+            .map(|n| 0.5 - 0.5 * (2.0 * PI * n as f32 / window_size as f32).cos())
+            .collect();
+        let amplitude_scale = 2.0 / window_coefficients.iter().sum::<f32>();
+        let scratch = vec![Complex::default(); fft.get_inplace_scratch_len()];
+        Ok(PilotToneWatchdog {
+            fft,
+            window_coefficients,
+            amplitude_scale,
+            bin_width: sample_rate as f32 / window_size as f32,
+            watches,
+            windows: vec![Vec::with_capacity(window_size); channels_map.len()],
+            buffer: vec![Complex::default(); window_size],
+            scratch,
+        })
+    }
+
+    /// Add the samples of an input buffer captured at the given time,
+    /// returns the pilot tones lost and restored.
+    pub fn process(&mut self, channels: &[ChannelData], timestamp: SystemTime) -> Vec<PilotEvent> {
+        let window_size = self.buffer.len();
+        let mut events = Vec::new();
+        for (position, channel) in channels.iter().enumerate() {
+            let mut samples = &channel.samples[..];
+            while !samples.is_empty() {
+                let window = &mut self.windows[position];
+                let len = samples.len().min(window_size - window.len());
+                window.extend_from_slice(&samples[..len]);
+                samples = &samples[len..];
+                if window.len() < window_size {
+                    continue;
+                }
+                let magnitudes = self.magnitudes(position);
+                self.windows[position].clear();
+                for watch in self
+                    .watches
+                    .iter_mut()
+                    .filter(|watch| watch.position == position)
+                {
+                    let (frequency, level) = measure(&watch.config, &magnitudes, self.bin_width);
+                    events.extend(watch.update(channel.index, frequency, level, timestamp));
+                }
+            }
+        }
+        events
+    }
+
+    /// the magnitude of each bin of the window of the channel, in dB relative to the
+    /// amplitude of a full scale sine
+    fn magnitudes(&mut self, position: usize) -> Vec<f32> {
+        for ((value, &sample), coefficient) in self
+            .buffer
+            .iter_mut()
+            .zip(&self.windows[position])
+            .zip(&self.window_coefficients)
+        {
+            *value = Complex::new(sample * coefficient, 0.0);
+        }
+        self.fft
+            .process_with_scratch(&mut self.buffer, &mut self.scratch);
+        let amplitude_scale = self.amplitude_scale;
+        self.buffer[..=self.buffer.len() / 2]
+            .iter()
+            .map(|value| 20.0 * (value.norm() * amplitude_scale).log10())
+            .collect()
+    }
+}
+
+/// the frequency and level of the strongest tone near the pilot tone, searched four
+/// times the tolerance away
+fn measure(config: &PilotToneConfig, magnitudes: &[f32], bin_width: f32) -> (f32, f32) {
+    let search = (4.0 * config.tolerance).max(4.0 * bin_width);
+    let low = (((config.frequency - search) / bin_width).floor().max(1.0)) as usize;
+    let high =
+        (((config.frequency + search) / bin_width).ceil() as usize).min(magnitudes.len() - 2);
+    let peak = (low..=high)
+        .max_by(|&a, &b| magnitudes[a].total_cmp(&magnitudes[b]))
+        .unwrap_or(low);
+    let (previous, current, next) = (magnitudes[peak - 1], magnitudes[peak], magnitudes[peak + 1]);
+    if !(previous.is_finite() && current.is_finite() && next.is_finite()) {
+        return (peak as f32 * bin_width, current);
+    }
+    // parabolic interpolation of the peak
+    let curvature = previous - 2.0 * current + next;
+    let offset = if curvature < 0.0 {
+        0.5 * (previous - next) / curvature
+    } else {
+        0.0
+    };
+    (
+        (peak as f32 + offset) * bin_width,
+        current - 0.25 * (previous - next) * offset,
+    )
+}
+
+impl Watch {
+    fn new(config: PilotToneConfig, position: usize) -> Self {
+        Watch {
+            config,
+            position,
+            lost_since: None,
+            changed_since: None,
+        }
+    }
+
+    fn update(
+        &mut self,
+        channel: usize,
+        frequency: f32,
+        level: f32,
+        timestamp: SystemTime,
+    ) -> Option<PilotEvent> {
+        let fault = if level < self.config.min_level {
+            Some(PilotFault::Missing)
+        } else if (frequency - self.config.frequency).abs() > self.config.tolerance {
+            Some(PilotFault::Drift)
+        } else {
+            None
+        };
+        // the state changes after the hold time
+        if fault.is_some() == self.lost_since.is_some() {
+            self.changed_since = None;
+            return None;
+        }
+        let changed_since = *self.changed_since.get_or_insert(timestamp);
+        if timestamp.duration_since(changed_since).unwrap_or_default() < HOLD_TIME {
+            return None;
+        }
+        self.changed_since = None;
+        match (fault, self.lost_since.take()) {
+            (Some(fault), _) => {
+                self.lost_since = Some(changed_since);
+                Some(PilotEvent::Lost {
+                    channel,
+                    frequency: self.config.frequency,
+                    fault,
+                    measured_frequency: frequency,
+                    level,
+                    since: changed_since,
+                })
+            }
+            (None, Some(since)) => Some(PilotEvent::Restored {
+                channel,
+                frequency: self.config.frequency,
+                since,
+                until: changed_since,
+            }),
+            (None, None) => None,
+        }
+    }
+}
@@ -0,0 +1,427 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Input from a PipeWire capture node, with libpipewire 0.3, instead of a device.
+//!
+//! The node is named, e.g. `audio-in-stream`, and its ports are named after the channel
+//! positions, `input_FL` and `input_FR` in stereo, `input_MONO` in mono and `input_AUX0`
+//! to `input_AUXn` otherwise. The session manager links it to the default source, or to
+//! the `target.object`, and it can be relinked in a patchbay such as Helvum or qpwgraph.
+
+use crate::ring::RingWriter;
+use crate::CaptureClock;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int, c_uint, c_void};
+use std::ptr;
+use std::slice;
+
+pub const DEFAULT_NODE_NAME: &str = "audio-in-stream";
+pub const DEFAULT_SAMPLE_RATE: u32 = 48000;
+/// Frames of the quantum requested to the graph, its latency.
+pub const DEFAULT_LATENCY: u32 = 1024;
+
+/// `spa_type` values
+const SPA_TYPE_ID: u32 = 3;
+const SPA_TYPE_INT: u32 = 4;
+const SPA_TYPE_ARRAY: u32 = 13;
+const SPA_TYPE_OBJECT: u32 = 15;
+const SPA_TYPE_OBJECT_FORMAT: u32 = 0x40003;
+const SPA_PARAM_ENUM_FORMAT: u32 = 3;
+/// `spa_format` keys
+const SPA_FORMAT_MEDIA_TYPE: u32 = 1;
+const SPA_FORMAT_MEDIA_SUBTYPE: u32 = 2;
+const SPA_FORMAT_AUDIO_FORMAT: u32 = 0x10001;
+const SPA_FORMAT_AUDIO_RATE: u32 = 0x10003;
+const SPA_FORMAT_AUDIO_CHANNELS: u32 = 0x10004;
+const SPA_FORMAT_AUDIO_POSITION: u32 = 0x10005;
+const SPA_MEDIA_TYPE_AUDIO: u32 = 1;
+const SPA_MEDIA_SUBTYPE_RAW: u32 = 1;
+const SPA_AUDIO_FORMAT_F32_LE: u32 = 0x11b;
+/// `spa_audio_channel` values
+const SPA_AUDIO_CHANNEL_MONO: u32 = 2;
+const SPA_AUDIO_CHANNEL_FL: u32 = 3;
+const SPA_AUDIO_CHANNEL_FR: u32 = 4;
+const SPA_AUDIO_CHANNEL_AUX0: u32 = 0x1000;
+
+const SPA_DIRECTION_INPUT: c_uint = 0;
+const PW_ID_ANY: u32 = 0xffff_ffff;
+/// `pw_stream_flags` values
+const PW_STREAM_FLAG_AUTOCONNECT: c_uint = 1 << 0;
+const PW_STREAM_FLAG_MAP_BUFFERS: c_uint = 1 << 2;
+const PW_STREAM_FLAG_RT_PROCESS: c_uint = 1 << 4;
+const PW_STREAM_STATE_ERROR: c_int = -1;
+
+/// `pw_stream_events` of version 0
+#[repr(C)]
+struct PwStreamEvents {
+    version: u32,
+    destroy: Option<unsafe extern "C" fn(*mut c_void)>,
+    state_changed: Option<unsafe extern "C" fn(*mut c_void, c_int, c_int, *const c_char)>,
+    control_info: Option<unsafe extern "C" fn(*mut c_void, u32, *const c_void)>,
+    io_changed: Option<unsafe extern "C" fn(*mut c_void, u32, *mut c_void, u32)>,
+    param_changed: Option<unsafe extern "C" fn(*mut c_void, u32, *const c_void)>,
+    add_buffer: Option<unsafe extern "C" fn(*mut c_void, *mut PwBuffer)>,
+    remove_buffer: Option<unsafe extern "C" fn(*mut c_void, *mut PwBuffer)>,
+    process: Option<unsafe extern "C" fn(*mut c_void)>,
+    drained: Option<unsafe extern "C" fn(*mut c_void)>,
+}
+
+#[repr(C)]
+struct PwBuffer {
+    buffer: *mut SpaBuffer,
+    user_data: *mut c_void,
+    size: u64,
+}
+
+#[repr(C)]
+struct SpaBuffer {
+    n_metas: u32,
+    n_datas: u32,
+    metas: *mut c_void,
+    datas: *mut SpaData,
+}
+
+#[repr(C)]
+struct SpaData {
+    type_: u32,
+    flags: u32,
+    fd: i64,
+    mapoffset: u32,
+    maxsize: u32,
+    data: *mut c_void,
+    chunk: *mut SpaChunk,
+}
+
+#[repr(C)]
+struct SpaChunk {
+    offset: u32,
+    size: u32,
+    stride: i32,
+    flags: i32,
+}
+
+#[link(name = "pipewire-0.3")]
+extern "C" {
+    fn pw_init(argc: *mut c_int, argv: *mut *mut *mut c_char);
+    fn pw_thread_loop_new(name: *const c_char, props: *const c_void) -> *mut c_void;
+    fn pw_thread_loop_get_loop(thread_loop: *mut c_void) -> *mut c_void;
+    fn pw_thread_loop_start(thread_loop: *mut c_void) -> c_int;
+    fn pw_thread_loop_stop(thread_loop: *mut c_void);
+    fn pw_thread_loop_lock(thread_loop: *mut c_void);
+    fn pw_thread_loop_unlock(thread_loop: *mut c_void);
+    fn pw_thread_loop_destroy(thread_loop: *mut c_void);
+    fn pw_properties_new(key: *const c_char, ...) -> *mut c_void;
+    fn pw_properties_set(
+        properties: *mut c_void,
+        key: *const c_char,
+        value: *const c_char,
+    ) -> c_int;
+    fn pw_stream_new_simple(
+        pw_loop: *mut c_void,
+        name: *const c_char,
+        props: *mut c_void,
+        events: *const PwStreamEvents,
+        data: *mut c_void,
+    ) -> *mut c_void;
+    fn pw_stream_connect(
+        stream: *mut c_void,
+        direction: c_uint,
+        target_id: u32,
+        flags: c_uint,
+        params: *mut *const c_void,
+        n_params: u32,
+    ) -> c_int;
+    fn pw_stream_dequeue_buffer(stream: *mut c_void) -> *mut PwBuffer;
+    fn pw_stream_queue_buffer(stream: *mut c_void, buffer: *mut PwBuffer) -> c_int;
+    fn pw_stream_destroy(stream: *mut c_void);
+}
+
+/// The `EnumFormat` param of interleaved `f32` samples, as a SPA pod of 32 bits words.
+fn format_pod(channels: u16, sample_rate: u32) -> Vec<u32> {
+    let positions: Vec<u32> = match channels {
+        1 => vec![SPA_AUDIO_CHANNEL_MONO],
+        2 => vec![SPA_AUDIO_CHANNEL_FL, SPA_AUDIO_CHANNEL_FR],
+        _ => (0..channels as u32)
+            .map(|channel| SPA_AUDIO_CHANNEL_AUX0 + channel)
+            .collect(),
+    };
+    // pod header of the object, its size set at the end, and its body
+    let mut words = vec![
+        0,
+        SPA_TYPE_OBJECT,
+        SPA_TYPE_OBJECT_FORMAT,
+        SPA_PARAM_ENUM_FORMAT,
+    ];
+    // each property is its key, its flags and its pod, padded to 8 bytes
+    for &(key, type_, value) in &[
+        (SPA_FORMAT_MEDIA_TYPE, SPA_TYPE_ID, SPA_MEDIA_TYPE_AUDIO),
+        (SPA_FORMAT_MEDIA_SUBTYPE, SPA_TYPE_ID, SPA_MEDIA_SUBTYPE_RAW),
+        (
+            SPA_FORMAT_AUDIO_FORMAT,
+            SPA_TYPE_ID,
+            SPA_AUDIO_FORMAT_F32_LE,
+        ),
+        (SPA_FORMAT_AUDIO_RATE, SPA_TYPE_INT, sample_rate),
+        (SPA_FORMAT_AUDIO_CHANNELS, SPA_TYPE_INT, channels as u32),
+    ] {
+        words.extend_from_slice(&[key, 0, 4, type_, value, 0]);
+    }
+    // an array of ids, the size of its child pod and then its elements
+    words.extend_from_slice(&[
+        SPA_FORMAT_AUDIO_POSITION,
+        0,
+        8 + 4 * positions.len() as u32,
+        SPA_TYPE_ARRAY,
+        4,
+        SPA_TYPE_ID,
+    ]);
+    words.extend_from_slice(&positions);
+    if positions.len() % 2 == 1 {
+        words.push(0);
+    }
+    words[0] = 4 * (words.len() as u32 - 2);
+    words
+}
+
+/// A PipeWire capture node of interleaved `f32` samples, converted by PipeWire
+/// from the format of the source linked to it.
+pub struct PipeWireInput {
+    node_name: String,
+    target: Option<String>,
+    channels: u16,
+    sample_rate: u32,
+    latency: u32,
+}
+
+impl PipeWireInput {
+    /// Node named `node_name` of `channels` at `sample_rate`, requesting a quantum of
+    /// `latency` frames, linked to the `target` node by name or serial, or to the default source.
+    pub fn new(
+        node_name: &str,
+        target: Option<String>,
+        channels: u16,
+        sample_rate: u32,
+        latency: u32,
+    ) -> Self {
+        PipeWireInput {
+            node_name: node_name.to_string(),
+            target,
+            channels,
+            sample_rate,
+            latency,
+        }
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Name of the input, e.g. `pipewire:audio-in-stream`.
+    pub fn name(&self) -> String {
+        format!("pipewire:{}", self.node_name)
+    }
+
+    /// Connect the node, writing the samples of each buffer to the ring from the
+    /// processing thread of PipeWire, and calling `error_callback` if the stream fails.
+    ///
+    /// The node is destroyed, and the readers of the ring end, when the returned stream is dropped.
+    pub fn start<E>(
+        self,
+        ring_writer: RingWriter,
+        error_callback: E,
+    ) -> Result<PipeWireStream, String>
+    where
+        E: FnMut(String) + Send + 'static,
+    {
+        let c_string = |value: &str| {
+            CString::new(value).map_err(|_| format!("invalid PipeWire property '{}'", value))
+        };
+        let mut properties = vec![
+            ("media.type", String::from("Audio")),
+            ("media.category", String::from("Capture")),
+            ("media.role", String::from("Production")),
+            ("node.name", self.node_name.clone()),
+            ("node.description", self.node_name.clone()),
+            ("media.name", self.node_name.clone()),
+            (
+                "node.latency",
+                format!("{}/{}", self.latency, self.sample_rate),
+            ),
+        ];
+        if let Some(target) = &self.target {
+            properties.push(("target.object", target.clone()));
+        }
+        let properties = properties
+            .into_iter()
+            .map(|(key, value)| Ok((c_string(key)?, c_string(&value)?)))
+            .collect::<Result<Vec<_>, String>>()?;
+        let node_name = c_string(&self.node_name)?;
+
+        let mut stream = PipeWireStream {
+            thread_loop: ptr::null_mut(),
+            stream: ptr::null_mut(),
+            state: Box::new(State {
+                stream: ptr::null_mut(),
+                channels: self.channels as usize,
+                ring_writer,
+                clock: CaptureClock::new(self.sample_rate),
+                error_callback: Box::new(error_callback),
+            }),
+            events: Box::new(PwStreamEvents {
+                version: 0,
+                destroy: None,
+                state_changed: Some(on_state_changed),
+                control_info: None,
+                io_changed: None,
+                param_changed: None,
+                add_buffer: None,
+                remove_buffer: None,
+                process: Some(on_process),
+                drained: None,
+            }),
+        };
+        let mut format = format_pod(self.channels, self.sample_rate);
+        unsafe {
+            pw_init(ptr::null_mut(), ptr::null_mut());
+            stream.thread_loop = pw_thread_loop_new(node_name.as_ptr(), ptr::null());
+            if stream.thread_loop.is_null() {
+                return Err(String::from("failed to create the PipeWire loop"));
+            }
+            // owned by the stream
+            let props = pw_properties_new(ptr::null());
+            for (key, value) in &properties {
+                pw_properties_set(props, key.as_ptr(), value.as_ptr());
+            }
+
+            pw_thread_loop_lock(stream.thread_loop);
+            stream.stream = pw_stream_new_simple(
+                pw_thread_loop_get_loop(stream.thread_loop),
+                node_name.as_ptr(),
+                props,
+                &*stream.events,
+                &mut *stream.state as *mut State as *mut c_void,
+            );
+            let result = if stream.stream.is_null() {
+                -1
+            } else {
+                stream.state.stream = stream.stream;
+                let mut params = [format.as_mut_ptr() as *const c_void];
+                pw_stream_connect(
+                    stream.stream,
+                    SPA_DIRECTION_INPUT,
+                    PW_ID_ANY,
+                    PW_STREAM_FLAG_AUTOCONNECT
+                        | PW_STREAM_FLAG_MAP_BUFFERS
+                        | PW_STREAM_FLAG_RT_PROCESS,
+                    params.as_mut_ptr(),
+                    1,
+                )
+            };
+            pw_thread_loop_unlock(stream.thread_loop);
+            if result < 0 {
+                return Err(format!(
+                    "failed to connect the PipeWire stream '{}'",
+                    self.node_name
+                ));
+            }
+            if pw_thread_loop_start(stream.thread_loop) < 0 {
+                return Err(String::from("failed to start the PipeWire loop"));
+            }
+        }
+        Ok(stream)
+    }
+}
+
+/// The data of the stream callbacks.
+struct State {
+    stream: *mut c_void,
+    channels: usize,
+    ring_writer: RingWriter,
+    /// the capture times of the buffers, from their frames
+    clock: CaptureClock,
+    error_callback: Box<dyn FnMut(String) + Send>,
+}
+
+unsafe extern "C" fn on_state_changed(
+    data: *mut c_void,
+    _old: c_int,
+    state: c_int,
+    error: *const c_char,
+) {
+    let state_data = &mut *(data as *mut State);
+    if state == PW_STREAM_STATE_ERROR {
+        let error = if error.is_null() {
+            String::from("unknown error")
+        } else {
+            CStr::from_ptr(error).to_string_lossy().into_owned()
+        };
+        (state_data.error_callback)(format!("PipeWire stream error: {}", error));
+    }
+}
+
+unsafe extern "C" fn on_process(data: *mut c_void) {
+    let state = &mut *(data as *mut State);
+    let buffer = pw_stream_dequeue_buffer(state.stream);
+    if buffer.is_null() {
+        return;
+    }
+    let spa_buffer = &*(*buffer).buffer;
+    if spa_buffer.n_datas > 0 {
+        let data = &*spa_buffer.datas;
+        if !data.data.is_null() && !data.chunk.is_null() && data.maxsize > 0 {
+            let chunk = &*data.chunk;
+            let offset = chunk.offset % data.maxsize;
+            let size = chunk.size.min(data.maxsize - offset) as usize;
+            let samples = slice::from_raw_parts(
+                (data.data as *const u8).add(offset as usize) as *const f32,
+                size / 4,
+            );
+            let len = samples.len() - samples.len() % state.channels;
+            if len > 0 {
+                let timestamp = state.clock.frame_time(len / state.channels);
+                state.ring_writer.push(&samples[..len], timestamp);
+            }
+        }
+    }
+    pw_stream_queue_buffer(state.stream, buffer);
+}
+
+/// The connected node, destroyed when dropped.
+pub struct PipeWireStream {
+    thread_loop: *mut c_void,
+    stream: *mut c_void,
+    state: Box<State>,
+    events: Box<PwStreamEvents>,
+}
+
+impl Drop for PipeWireStream {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.thread_loop.is_null() {
+                pw_thread_loop_stop(self.thread_loop);
+            }
+            if !self.stream.is_null() {
+                pw_stream_destroy(self.stream);
+            }
+            if !self.thread_loop.is_null() {
+                pw_thread_loop_destroy(self.thread_loop);
+            }
+        }
+    }
+}
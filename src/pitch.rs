@@ -0,0 +1,309 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Pitch of each channel per analysis window, with the nearest note of the equal
+//! temperament and its deviation in cents, to check a line-up tone or tune an instrument.
+//!
+//! The pitch is found with the YIN algorithm (de Cheveigné and Kawahara, 2002): the
+//! first period whose cumulative mean normalized difference falls under a threshold,
+//! refined by parabolic interpolation, so the fundamental is found even when a harmonic
+//! is stronger.
+
+use crate::{root_mean_square, ChannelData};
+use serde::Serialize;
+
+/// samples of each analysis window, two periods of the lowest pitch
+pub const WINDOW_SIZE: usize = 2048;
+/// Hz, frequency of A4 when not given
+pub const DEFAULT_REFERENCE: f32 = 440.0;
+/// Hz, the highest pitch detected
+const MAX_FREQUENCY: f32 = 5000.0;
+/// cumulative mean normalized difference of a period
+const THRESHOLD: f32 = 0.15;
+/// RMS of the quietest window analyzed, -60 dBov
+const MIN_RMS: f32 = 0.001;
+
+const NOTE_NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+/// Pitch of a channel, all `null` in JSON when there is none.
+#[derive(Clone, Debug, Serialize)]
+pub struct ChannelPitch {
+    pub channel: usize,
+    /// Hz
+    pub frequency: Option<f32>,
+    /// nearest note in scientific pitch notation, as `A4`
+    pub note: Option<String>,
+    /// deviation of the pitch from the note, from -50 to 50
+    pub cents: Option<f32>,
+}
+
+/// Detects the pitch of each channel in windows of [`WINDOW_SIZE`] samples.
+pub struct PitchDetector {
+    sample_rate: f32,
+    /// Hz, of A4
+    reference: f32,
+    /// samples of the current window of each channel
+    windows: Vec<Vec<f32>>,
+    /// cumulative mean normalized difference of each period
+    difference: Vec<f32>,
+}
+
+impl PitchDetector {
+    pub fn new(sample_rate: u32, num_channels: usize, reference: f32) -> Self {
+        PitchDetector {
+            sample_rate: sample_rate as f32,
+            reference,
+            windows: vec![Vec::with_capacity(WINDOW_SIZE); num_channels],
+            difference: vec![0.0; WINDOW_SIZE / 2],
+        }
+    }
+
+    /// Add the samples of an input buffer, returns the pitch of each channel
+    /// once a window is complete.
+    pub fn process(&mut self, channels: &[ChannelData]) -> Option<Vec<ChannelPitch>> {
+        let mut pitches = None;
+        for (position, channel) in channels.iter().enumerate() {
+            let mut samples = &channel.samples[..];
+            while !samples.is_empty() {
+                let window = &mut self.windows[position];
+                let len = samples.len().min(WINDOW_SIZE - window.len());
+                window.extend_from_slice(&samples[..len]);
+                samples = &samples[len..];
+                if window.len() == WINDOW_SIZE {
+                    let frequency = self.detect(position);
+                    self.windows[position].clear();
+                    let (note, cents) = match frequency {
+                        Some(frequency) => {
+                            let (note, cents) = self.note(frequency);
+                            (Some(note), Some(cents))
+                        }
+                        None => (None, None),
+                    };
+                    pitches
+                        .get_or_insert_with(|| Vec::with_capacity(channels.len()))
+                        .push(ChannelPitch {
+                            channel: channel.index,
+                            frequency,
+                            note,
+                            cents,
+                        });
+                }
+            }
+        }
+        pitches
+    }
+
+    /// The pitch of the window of the channel, if periodic.
+    fn detect(&mut self, position: usize) -> Option<f32> {
+        let window = &self.windows[position];
+        if root_mean_square(window) < MIN_RMS {
+            return None;
+        }
+        let max_period = self.difference.len();
+        let min_period = ((self.sample_rate / MAX_FREQUENCY) as usize).max(2);
+
+        let mut cumulative = 0.0;
+        self.difference[0] = 1.0;
+        for period in 1..max_period {
+            let difference: f32 = window[..max_period]
+                .iter()
+                .zip(&window[period..period + max_period])
+                .map(|(a, b)| (a - b) * (a - b))
+                .sum();
+            cumulative += difference;
+            self.difference[period] = if cumulative > 0.0 {
+                difference * period as f32 / cumulative
+            } else {
+                1.0
+            };
+        }
+
+        // the first dip under the threshold, down to its minimum
+        let difference = &self.difference;
+        let mut period =
+            (min_period..max_period - 1).find(|&period| difference[period] < THRESHOLD)?;
+        while period + 1 < max_period - 1 && difference[period + 1] < difference[period] {
+            period += 1;
+        }
+        let (previous, current, next) = (
+            difference[period - 1],
+            difference[period],
+            difference[period + 1],
+        );
+        let curvature = previous - 2.0 * current + next;
+        let offset = if curvature > 0.0 {
+            0.5 * (previous - next) / curvature
+        } else {
+            0.0
+        };
+        Some(self.sample_rate / (period as f32 + offset))
+    }
+
+    /// The nearest note of the equal temperament to the frequency, and the cents from it.
+    fn note(&self, frequency: f32) -> (String, f32) {
+        // MIDI note numbers, A4 is 69
+        let semitones = 69.0 + 12.0 * (frequency / self.reference).log2();
+        let nearest = semitones.round();
+        let note = nearest as i32;
+        (
+            format!(
+                "{}{}",
+                NOTE_NAMES[note.rem_euclid(12) as usize],
+                note.div_euclid(12) - 1
+            ),
+            100.0 * (semitones - nearest),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    /// The sum of harmonics of a fundamental, with their amplitudes.
+    fn harmonics(fundamental: f32, amplitudes: &[f32], len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|index| {
+                let time = index as f32 / SAMPLE_RATE as f32;
+                amplitudes
+                    .iter()
+                    .enumerate()
+                    .map(|(harmonic, amplitude)| {
+                        amplitude * (2.0 * PI * fundamental * (harmonic + 1) as f32 * time).sin()
+                    })
+                    .sum()
+            })
+            .collect()
+    }
+
+    fn channel(index: usize, samples: Vec<f32>) -> ChannelData {
+        ChannelData {
+            index,
+            loudness_level: 0.0,
+            samples,
+        }
+    }
+
+    /// The pitch of a window of the samples.
+    fn pitch(samples: Vec<f32>) -> Option<f32> {
+        let mut detector = PitchDetector::new(SAMPLE_RATE, 1, DEFAULT_REFERENCE);
+        let pitches = detector.process(&[channel(0, samples)]).unwrap();
+        pitches[0].frequency
+    }
+
+    #[test]
+    fn notes() {
+        let detector = PitchDetector::new(SAMPLE_RATE, 1, DEFAULT_REFERENCE);
+        let (note, cents) = detector.note(440.0);
+        assert_eq!((note.as_str(), cents), ("A4", 0.0));
+        let (note, cents) = detector.note(261.63);
+        assert_eq!(note, "C4");
+        assert!(cents.abs() < 0.1);
+        let (note, cents) = detector.note(445.0);
+        assert_eq!(note, "A4");
+        assert!((cents - 19.56).abs() < 0.01);
+        // the nearest note above
+        let (note, cents) = detector.note(460.0);
+        assert_eq!(note, "A#4");
+        assert!((cents + 23.0).abs() < 0.1);
+        assert_eq!(detector.note(27.5).0, "A0");
+        assert_eq!(detector.note(8.176).0, "C-1");
+
+        let detector = PitchDetector::new(SAMPLE_RATE, 1, 432.0);
+        let (note, cents) = detector.note(432.0);
+        assert_eq!((note.as_str(), cents), ("A4", 0.0));
+    }
+
+    #[test]
+    fn pitch_of_sines() {
+        // the interpolation of the period is coarser for the periods of a few samples
+        let cases = [
+            (50.0, 0.5),
+            (100.0, 0.5),
+            (440.0, 0.5),
+            (997.0, 0.5),
+            (3000.0, 5.0),
+            (4500.0, 5.0),
+        ];
+        for &(frequency, max_cents) in cases.iter() {
+            let detected = pitch(harmonics(frequency, &[0.5], WINDOW_SIZE)).unwrap();
+            let cents = 1200.0 * (detected / frequency).log2();
+            assert!(
+                cents.abs() < max_cents,
+                "{} detected as {}",
+                frequency,
+                detected
+            );
+        }
+    }
+
+    #[test]
+    fn fundamental_weaker_than_its_harmonics() {
+        let detected = pitch(harmonics(220.0, &[0.1, 0.5, 0.3], WINDOW_SIZE)).unwrap();
+        assert!((detected / 220.0 - 1.0).abs() < 0.001, "{}", detected);
+    }
+
+    #[test]
+    fn no_pitch_of_silence_or_noise() {
+        assert_eq!(pitch(vec![0.0; WINDOW_SIZE]), None);
+        // under the minimum level
+        assert_eq!(pitch(harmonics(440.0, &[0.001], WINDOW_SIZE)), None);
+
+        let mut seed = 1_u32;
+        let noise = (0..WINDOW_SIZE)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (seed >> 16) as f32 / 32768.0 - 1.0
+            })
+            .collect();
+        assert_eq!(pitch(noise), None);
+    }
+
+    #[test]
+    fn windows_over_the_buffers() {
+        let mut detector = PitchDetector::new(SAMPLE_RATE, 2, DEFAULT_REFERENCE);
+        let samples = harmonics(440.0, &[0.5], 5000);
+        let mut windows = Vec::new();
+        for buffer in samples.chunks(480) {
+            let channels = [
+                channel(2, buffer.to_vec()),
+                channel(5, vec![0.0; buffer.len()]),
+            ];
+            if let Some(pitches) = detector.process(&channels) {
+                windows.push(pitches);
+            }
+        }
+        assert_eq!(windows.len(), 5000 / WINDOW_SIZE);
+        for pitches in &windows {
+            assert_eq!(pitches.len(), 2);
+            assert_eq!(pitches[0].channel, 2);
+            assert_eq!(pitches[0].note.as_deref(), Some("A4"));
+            assert!(pitches[0].cents.unwrap().abs() < 2.0);
+            assert_eq!(pitches[1].channel, 5);
+            assert_eq!(pitches[1].frequency, None);
+        }
+
+        let json = serde_json::to_value(&windows[0][1]).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"channel": 5, "frequency": null, "note": null, "cents": null})
+        );
+    }
+}
@@ -0,0 +1,158 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Stages of the processing of the input buffers, the built-in ones as the filters, the RMS
+//! levels and the spectrum, and custom ones for analyses or effects of the users of the
+//! library, inserted into the pipeline without changing it.
+
+use crate::{process_input_buffer_into, ChannelData, InputBufferSourceData};
+
+/// Stage of the processing of the input buffers, in turn in a [`ProcessorChain`].
+pub trait Processor: Send {
+    /// Name of the stage, unique in its chain.
+    fn name(&self) -> &str;
+
+    /// Process an input buffer, analyzing its channels or changing their samples and
+    /// loudness levels in place for the next stages.
+    fn process(&mut self, buffer: &mut InputBufferSourceData);
+
+    /// Parameters of the stage and their current values.
+    fn parameters(&self) -> Vec<(String, f64)> {
+        Vec::new()
+    }
+
+    /// Change a parameter of the stage, between two input buffers.
+    fn set_parameter(&mut self, name: &str, value: f64) -> Result<(), String> {
+        let _ = value;
+        Err(unknown_parameter(self, name))
+    }
+}
+
+/// Error of a parameter the processor does not have.
+pub fn unknown_parameter(processor: &(impl Processor + ?Sized), name: &str) -> String {
+    format!(
+        "unknown parameter '{}' of the processor '{}'",
+        name,
+        processor.name()
+    )
+}
+
+/// Stages processing each input buffer in the order of their registration.
+#[derive(Default)]
+pub struct ProcessorChain {
+    processors: Vec<Box<dyn Processor>>,
+    /// of the interleaved samples, reused for the next buffer
+    channels: Vec<ChannelData>,
+}
+
+impl ProcessorChain {
+    pub fn new() -> Self {
+        ProcessorChain::default()
+    }
+
+    /// Register a stage at the end of the chain.
+    pub fn register(&mut self, processor: Box<dyn Processor>) -> Result<(), String> {
+        self.insert(self.processors.len(), processor)
+    }
+
+    /// Register a stage right before the stage of the given name, as to process the samples
+    /// before they are metered.
+    pub fn register_before(
+        &mut self,
+        name: &str,
+        processor: Box<dyn Processor>,
+    ) -> Result<(), String> {
+        let index = self
+            .position(name)
+            .ok_or_else(|| format!("unknown processor '{}'", name))?;
+        self.insert(index, processor)
+    }
+
+    fn insert(&mut self, index: usize, processor: Box<dyn Processor>) -> Result<(), String> {
+        if self.position(processor.name()).is_some() {
+            return Err(format!(
+                "a processor '{}' is already registered",
+                processor.name()
+            ));
+        }
+        self.processors.insert(index, processor);
+        Ok(())
+    }
+
+    /// Remove the stage of the given name.
+    pub fn remove(&mut self, name: &str) -> Option<Box<dyn Processor>> {
+        self.position(name)
+            .map(|index| self.processors.remove(index))
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.processors
+            .iter()
+            .position(|processor| processor.name() == name)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.processors.is_empty()
+    }
+
+    /// Names of the stages, in order.
+    pub fn names(&self) -> Vec<&str> {
+        self.processors
+            .iter()
+            .map(|processor| processor.name())
+            .collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn Processor> {
+        self.position(name)
+            .map(|index| self.processors[index].as_ref())
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut dyn Processor> {
+        match self.position(name) {
+            Some(index) => Some(self.processors[index].as_mut()),
+            None => None,
+        }
+    }
+
+    /// Process an input buffer by each stage in turn.
+    pub fn process(&mut self, buffer: &mut InputBufferSourceData) {
+        for processor in &mut self.processors {
+            processor.process(buffer);
+        }
+    }
+
+    /// Process a buffer of interleaved samples in place by each stage in turn, as the samples
+    /// of a stream.
+    pub fn process_interleaved(&mut self, samples: &mut [f32], num_channels: usize) {
+        if self.is_empty() {
+            return;
+        }
+        let mut buffer = InputBufferSourceData {
+            num_samples: samples.len(),
+            sample_format: cpal::SampleFormat::F32,
+            num_channels,
+            channels: std::mem::take(&mut self.channels),
+        };
+        process_input_buffer_into(samples, num_channels, &mut buffer.channels);
+        self.process(&mut buffer);
+        for (frame_index, frame) in samples.chunks_exact_mut(num_channels).enumerate() {
+            for (sample, channel) in frame.iter_mut().zip(&buffer.channels) {
+                *sample = channel.samples[frame_index];
+            }
+        }
+        self.channels = buffer.channels;
+    }
+}
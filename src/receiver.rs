@@ -0,0 +1,327 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Receiver of an RTP stream (RFC 3550) of big endian linear PCM or of Opus, the input
+//! of the metering, the recording and the streaming instead of a device, e.g. the
+//! stream of another instance sending with `--rtp-dest`.
+
+use crate::ring::RingWriter;
+use crate::rtp::RtpFormat;
+#[cfg(feature = "opus")]
+use audiopus::coder::Decoder;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Duration of the blocks of samples written to the ring, in seconds.
+const BLOCK_DURATION: f64 = 0.02;
+/// Longest wait for a packet before checking whether to stop.
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+const MAX_PACKET_LEN: usize = 65536;
+/// Packets missing before restarting at the sequence number of a new packet,
+/// instead of filling the gap with silence.
+const MAX_GAP: u16 = 100;
+/// Shortest interval between the warnings of lost packets.
+const LOSS_WARNING_INTERVAL: Duration = Duration::from_secs(10);
+/// Longest Opus frame, 120 ms at 48 kHz.
+#[cfg(feature = "opus")]
+const MAX_OPUS_FRAMES: usize = 5760;
+
+/// Decodes the payloads into interleaved samples.
+enum Depayloader {
+    L16,
+    L24,
+    #[cfg(feature = "opus")]
+    Opus(Decoder),
+}
+
+impl Depayloader {
+    fn new(format: RtpFormat, channels: u16, sample_rate: u32) -> Result<Self, String> {
+        match format {
+            RtpFormat::L16 => Ok(Depayloader::L16),
+            RtpFormat::L24 => Ok(Depayloader::L24),
+            #[cfg(feature = "opus")]
+            RtpFormat::Opus => {
+                use audiopus::{Channels, SampleRate};
+                use std::convert::TryFrom;
+
+                let channels = match channels {
+                    1 => Channels::Mono,
+                    2 => Channels::Stereo,
+                    _ => {
+                        return Err(format!(
+                            "invalid Opus channels {}, expected 1 or 2",
+                            channels
+                        ))
+                    }
+                };
+                let sample_rate = SampleRate::try_from(sample_rate as i32)
+                    .map_err(|_| format!("invalid Opus sample rate {} Hz", sample_rate))?;
+                Decoder::new(sample_rate, channels)
+                    .map(Depayloader::Opus)
+                    .map_err(|err| format!("failed to create Opus decoder: {}", err))
+            }
+            #[cfg(not(feature = "opus"))]
+            RtpFormat::Opus => {
+                let _ = (channels, sample_rate);
+                Err(String::from("receiving Opus requires the 'opus' feature"))
+            }
+        }
+    }
+
+    /// Decode a payload, or conceal a lost packet of `frames` if `None`.
+    fn decode(
+        &mut self,
+        payload: Option<&[u8]>,
+        frames: usize,
+        channels: usize,
+        samples: &mut Vec<f32>,
+    ) {
+        match (self, payload) {
+            (Depayloader::L16, Some(payload)) => samples.extend(
+                payload
+                    .chunks_exact(2)
+                    .map(|bytes| i16::from_be_bytes([bytes[0], bytes[1]]) as f32 / 32768.0),
+            ),
+            (Depayloader::L24, Some(payload)) => {
+                samples.extend(payload.chunks_exact(3).map(|bytes| {
+                    i32::from_be_bytes([bytes[0], bytes[1], bytes[2], 0]) as f32 / 2_147_483_648.0
+                }))
+            }
+            #[cfg(feature = "opus")]
+            (Depayloader::Opus(decoder), payload) => {
+                use audiopus::{packet::Packet, MutSignals};
+                use std::convert::TryFrom;
+
+                // a whole packet, or the packet loss concealment of the decoder
+                let frames = match payload {
+                    Some(_) => MAX_OPUS_FRAMES,
+                    None => frames.min(MAX_OPUS_FRAMES),
+                };
+                let packet = match payload.map(Packet::try_from) {
+                    Some(Ok(packet)) => Some(packet),
+                    Some(Err(_)) => return,
+                    None => None,
+                };
+                if frames == 0 {
+                    return;
+                }
+                let start = samples.len();
+                samples.resize(start + frames * channels, 0.0);
+                let decoded = MutSignals::try_from(&mut samples[start..])
+                    .and_then(|output| decoder.decode_float(packet, output, false))
+                    .unwrap_or(0);
+                samples.truncate(start + decoded * channels);
+            }
+            (_, None) => samples.resize(samples.len() + frames * channels, 0.0),
+        }
+    }
+}
+
+/// Payload of an RTP packet with its sequence number, `None` if it is not a valid packet.
+fn rtp_payload(packet: &[u8]) -> Option<(u16, &[u8])> {
+    if packet.len() < 12 || packet[0] >> 6 != 2 {
+        return None;
+    }
+    let csrc_count = (packet[0] & 0x0f) as usize;
+    let mut start = 12 + 4 * csrc_count;
+    if packet[0] & 0x10 != 0 {
+        // header extension, its length in 32 bits words after its 4 bytes header
+        let extension = packet.get(start + 2..start + 4)?;
+        start += 4 + 4 * u16::from_be_bytes([extension[0], extension[1]]) as usize;
+    }
+    let mut end = packet.len();
+    if packet[0] & 0x20 != 0 {
+        end = end.checked_sub(*packet.last()? as usize)?;
+    }
+    let sequence = u16::from_be_bytes([packet[2], packet[3]]);
+    Some((sequence, packet.get(start..end)?))
+}
+
+/// Receives an RTP stream on a local address, or on a multicast group.
+pub struct RtpReceiver {
+    socket: UdpSocket,
+    address: SocketAddr,
+    format: RtpFormat,
+    depayloader: Depayloader,
+    channels: u16,
+    sample_rate: u32,
+}
+
+impl RtpReceiver {
+    /// Receiver on `address`, e.g. `0.0.0.0:5004` or the multicast group `239.69.1.1:5004`,
+    /// of a stream of `channels` at `sample_rate`.
+    pub fn bind(
+        address: &str,
+        format: RtpFormat,
+        channels: u16,
+        sample_rate: u32,
+    ) -> Result<Self, String> {
+        let invalid_address =
+            |err: io::Error| format!("failed to receive RTP on '{}': {}", address, err);
+        let socket_address = address
+            .to_socket_addrs()
+            .map_err(invalid_address)?
+            .next()
+            .ok_or_else(|| format!("invalid RTP address '{}'", address))?;
+        let socket = match socket_address.ip() {
+            IpAddr::V4(group) if group.is_multicast() => {
+                let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, socket_address.port()))
+                    .map_err(invalid_address)?;
+                socket
+                    .join_multicast_v4(&group, &Ipv4Addr::UNSPECIFIED)
+                    .map_err(invalid_address)?;
+                socket
+            }
+            IpAddr::V6(group) if group.is_multicast() => {
+                let socket = UdpSocket::bind((Ipv6Addr::UNSPECIFIED, socket_address.port()))
+                    .map_err(invalid_address)?;
+                socket
+                    .join_multicast_v6(&group, 0)
+                    .map_err(invalid_address)?;
+                socket
+            }
+            _ => UdpSocket::bind(socket_address).map_err(invalid_address)?,
+        };
+        socket
+            .set_read_timeout(Some(READ_TIMEOUT))
+            .map_err(invalid_address)?;
+
+        Ok(RtpReceiver {
+            socket,
+            address: socket_address,
+            format,
+            depayloader: Depayloader::new(format, channels, sample_rate)?,
+            channels,
+            sample_rate,
+        })
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Sample format of the stream, of the decoded samples for Opus.
+    pub fn sample_format(&self) -> cpal::SampleFormat {
+        match self.format {
+            RtpFormat::L16 => cpal::SampleFormat::I16,
+            RtpFormat::L24 | RtpFormat::Opus => cpal::SampleFormat::F32,
+        }
+    }
+
+    /// Name of the input, e.g. `rtp://239.69.1.1:5004`.
+    pub fn name(&self) -> String {
+        format!("rtp://{}", self.address)
+    }
+
+    /// Start receiving, writing the decoded samples to the ring in blocks, with silence
+    /// in place of the lost packets, and calling `warning` with the packets lost.
+    ///
+    /// Receiving stops, and the readers of the ring end, when the returned thread is dropped.
+    pub fn start<W>(self, mut ring_writer: RingWriter, mut warning: W) -> ReceiverThread
+    where
+        W: FnMut(String) + Send + 'static,
+    {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = Arc::clone(&stop);
+        let thread = thread::spawn(move || {
+            let channels = self.channels as usize;
+            let mut depayloader = self.depayloader;
+            let block_len = (self.sample_rate as f64 * BLOCK_DURATION) as usize * channels;
+            let mut samples = Vec::with_capacity(2 * block_len);
+            let mut packet = vec![0; MAX_PACKET_LEN];
+            let mut next_sequence = None;
+            // frames of the last packet, the duration of the lost ones
+            let mut packet_frames = 0;
+            let mut lost = 0;
+            let mut last_warning = Instant::now();
+
+            while !thread_stop.load(Ordering::Relaxed) {
+                let len = match self.socket.recv(&mut packet) {
+                    Ok(len) => len,
+                    Err(err)
+                        if err.kind() == io::ErrorKind::WouldBlock
+                            || err.kind() == io::ErrorKind::TimedOut =>
+                    {
+                        continue;
+                    }
+                    Err(err) => {
+                        warning(format!("failed to receive RTP packet: {}", err));
+                        thread::sleep(READ_TIMEOUT);
+                        continue;
+                    }
+                };
+                let (sequence, payload) = match rtp_payload(&packet[..len]) {
+                    Some(rtp) => rtp,
+                    None => continue,
+                };
+                let gap = next_sequence.map_or(0, |next| sequence.wrapping_sub(next));
+                if gap >= 0x8000 {
+                    // late or duplicated
+                    continue;
+                }
+                if gap > 0 && gap <= MAX_GAP {
+                    lost += gap as u64;
+                    for _ in 0..gap {
+                        depayloader.decode(None, packet_frames, channels, &mut samples);
+                    }
+                }
+                next_sequence = Some(sequence.wrapping_add(1));
+
+                let start = samples.len();
+                depayloader.decode(Some(payload), 0, channels, &mut samples);
+                packet_frames = (samples.len() - start) / channels;
+                if samples.len() >= block_len {
+                    let whole_len = samples.len() - samples.len() % channels;
+                    ring_writer.push(&samples[..whole_len], SystemTime::now());
+                    samples.drain(..whole_len);
+                }
+
+                if lost > 0 && last_warning.elapsed() >= LOSS_WARNING_INTERVAL {
+                    warning(format!("lost {} RTP packet(s)", lost));
+                    lost = 0;
+                    last_warning = Instant::now();
+                }
+            }
+        });
+
+        ReceiverThread {
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+/// The thread receiving the stream, stopped when dropped.
+pub struct ReceiverThread {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for ReceiverThread {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
@@ -0,0 +1,573 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Recording of the input to a file, in any of the supported formats:
+//! lossless WAV and FLAC, and the lossy formats of the streaming encoders,
+//! each one built with its feature.
+
+use crate::encoder::StreamEncoder;
+use crate::flac::FlacWriter;
+use crate::loudness::ChannelPosition;
+use crate::report::{self, LoudnessReport, RecordingAnalyzer, ReportConfig};
+use crate::unix_time;
+use crate::wav::{Bext, WavWriter};
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// UTC date and time of a Unix time in seconds:
+/// year, month, day, hours, minutes and seconds.
+pub(crate) fn utc_date_time(seconds: u64) -> (u64, u64, u64, u64, u64, u64) {
+    let days = seconds / 86400;
+    let time = seconds % 86400;
+    // civil from days, of the proleptic Gregorian calendar with eras of 400 years
+    let z = days + 719468;
+    let era = z / 146097;
+    let day_of_era = z - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as u64;
+    (year, month, day, time / 3600, time / 60 % 60, time % 60)
+}
+
+/// Format the file name of a recording started at the given time,
+/// replacing the fields of the UTC date and time of the template:
+/// `%Y` year, `%m` month, `%d` day, `%H` hours, `%M` minutes, `%S` seconds and `%%` a `%`.
+pub fn format_file_name(template: &str, time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+    let (year, month, day, hours, minutes, seconds) = utc_date_time(seconds);
+    let mut file_name = String::with_capacity(template.len() + 16);
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            file_name.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('Y') => file_name.push_str(&format!("{:04}", year)),
+            Some('m') => file_name.push_str(&format!("{:02}", month)),
+            Some('d') => file_name.push_str(&format!("{:02}", day)),
+            Some('H') => file_name.push_str(&format!("{:02}", hours)),
+            Some('M') => file_name.push_str(&format!("{:02}", minutes)),
+            Some('S') => file_name.push_str(&format!("{:02}", seconds)),
+            Some('%') => file_name.push('%'),
+            Some(c) => {
+                file_name.push('%');
+                file_name.push(c);
+            }
+            None => file_name.push('%'),
+        }
+    }
+    file_name
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RecordFormat {
+    #[default]
+    Wav,
+    Flac,
+    /// Ogg/Opus, with the `opus` feature
+    Opus,
+    /// Ogg/Vorbis, with the `vorbis` feature
+    Vorbis,
+    /// MP3, with the `mp3` feature
+    Mp3,
+}
+
+impl RecordFormat {
+    pub fn parse(format: &str) -> Result<Self, String> {
+        match format {
+            "wav" => Ok(RecordFormat::Wav),
+            "flac" => Ok(RecordFormat::Flac),
+            "opus" => Ok(RecordFormat::Opus),
+            "vorbis" => Ok(RecordFormat::Vorbis),
+            "mp3" => Ok(RecordFormat::Mp3),
+            _ => Err(format!(
+                "invalid record format '{}', expected one of: wav, flac, opus, vorbis, mp3",
+                format
+            )),
+        }
+    }
+
+    /// The format of the extension of the path, if known.
+    pub fn from_path(path: impl AsRef<Path>) -> Option<Self> {
+        let extension = path.as_ref().extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "ogg" | "oga" => Some(RecordFormat::Vorbis),
+            extension => RecordFormat::parse(extension).ok(),
+        }
+    }
+
+    pub fn is_lossy(self) -> bool {
+        matches!(
+            self,
+            RecordFormat::Opus | RecordFormat::Vorbis | RecordFormat::Mp3
+        )
+    }
+}
+
+/// Writer of the interleaved samples of the recording.
+pub trait RecordingSink: Send {
+    fn write_interleaved(&mut self, samples: &[f32]) -> io::Result<()>;
+
+    /// Complete the file once all the samples are written, and flush.
+    fn finalize(&mut self) -> io::Result<()>;
+}
+
+impl<W: Write + Seek + Send> RecordingSink for WavWriter<W> {
+    fn write_interleaved(&mut self, samples: &[f32]) -> io::Result<()> {
+        WavWriter::write_interleaved(self, samples)
+    }
+
+    fn finalize(&mut self) -> io::Result<()> {
+        WavWriter::finalize(self)
+    }
+}
+
+impl<W: Write + Seek + Send> RecordingSink for FlacWriter<W> {
+    fn write_interleaved(&mut self, samples: &[f32]) -> io::Result<()> {
+        FlacWriter::write_interleaved(self, samples)
+    }
+
+    fn finalize(&mut self) -> io::Result<()> {
+        FlacWriter::finalize(self)
+    }
+}
+
+/// Recording of the bytes of a streaming encoder.
+pub struct EncoderSink<E: StreamEncoder> {
+    encoder: E,
+    writer: BufWriter<File>,
+}
+
+impl<E: StreamEncoder> EncoderSink<E> {
+    pub fn create(path: impl AsRef<Path>, encoder: E) -> io::Result<Self> {
+        Ok(EncoderSink {
+            encoder,
+            writer: BufWriter::new(File::create(path)?),
+        })
+    }
+}
+
+impl<E: StreamEncoder> RecordingSink for EncoderSink<E> {
+    fn write_interleaved(&mut self, samples: &[f32]) -> io::Result<()> {
+        self.encoder.encode(samples).map_err(io::Error::other)?;
+        self.writer.write_all(&self.encoder.take_bytes())
+    }
+
+    fn finalize(&mut self) -> io::Result<()> {
+        self.encoder.finish().map_err(io::Error::other)?;
+        self.writer.write_all(&self.encoder.take_bytes())?;
+        self.writer.flush()
+    }
+}
+
+/// Options of the recording formats.
+#[derive(Clone, Debug)]
+pub struct RecordConfig {
+    pub format: RecordFormat,
+    /// FLAC compression level, from 0 to 8
+    pub compression_level: u8,
+    /// bitrate of the lossy formats in bit/s, the default of the encoder if `None`
+    pub bitrate: Option<i32>,
+    /// Broadcast Wave metadata of the WAV recordings
+    pub bext: Option<Bext>,
+    /// analysis of each recording, written to a sidecar file
+    pub report: Option<ReportConfig>,
+    /// a mono file for each channel, named by [`channel_path`]
+    pub split_channels: bool,
+    /// positions of the channels for the loudness of the reports, unknown if `None`
+    pub layout: Option<Vec<ChannelPosition>>,
+}
+
+impl Default for RecordConfig {
+    fn default() -> Self {
+        RecordConfig {
+            format: RecordFormat::default(),
+            compression_level: crate::flac::DEFAULT_COMPRESSION_LEVEL,
+            bitrate: None,
+            bext: None,
+            report: None,
+            split_channels: false,
+            layout: None,
+        }
+    }
+}
+
+/// Error of a format built without its feature.
+#[cfg(not(all(feature = "opus", feature = "vorbis", feature = "mp3")))]
+fn unsupported(format: &str, feature: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("recording {} requires the '{}' feature", format, feature),
+    )
+}
+
+/// Create the file of a recording of the input started at the given time, with the given config.
+pub fn create(
+    path: impl AsRef<Path>,
+    config: &RecordConfig,
+    stream_config: &cpal::SupportedStreamConfig,
+    start: SystemTime,
+) -> io::Result<Box<dyn RecordingSink>> {
+    Ok(match config.format {
+        RecordFormat::Wav => match &config.bext {
+            Some(bext) => Box::new(WavWriter::create_bwf(path, stream_config, bext, start)?),
+            None => Box::new(WavWriter::create(path, stream_config)?),
+        },
+        RecordFormat::Flac => Box::new(FlacWriter::create(
+            path,
+            stream_config,
+            config.compression_level,
+        )?),
+        #[cfg(feature = "opus")]
+        RecordFormat::Opus => {
+            use crate::ogg_opus::{OggOpusEncoder, DEFAULT_BITRATE};
+            let (channels, sample_rate) = (stream_config.channels(), stream_config.sample_rate().0);
+            let bitrate = config.bitrate.unwrap_or(DEFAULT_BITRATE);
+            let encoder =
+                OggOpusEncoder::new(channels, sample_rate, bitrate).map_err(io::Error::other)?;
+            Box::new(EncoderSink::create(path, encoder)?)
+        }
+        #[cfg(not(feature = "opus"))]
+        RecordFormat::Opus => return Err(unsupported("Ogg/Opus", "opus")),
+        #[cfg(feature = "vorbis")]
+        RecordFormat::Vorbis => {
+            use crate::vorbis::{OggVorbisEncoder, DEFAULT_BITRATE};
+            let (channels, sample_rate) = (stream_config.channels(), stream_config.sample_rate().0);
+            let bitrate = config.bitrate.unwrap_or(DEFAULT_BITRATE);
+            let encoder =
+                OggVorbisEncoder::new(channels, sample_rate, bitrate).map_err(io::Error::other)?;
+            Box::new(EncoderSink::create(path, encoder)?)
+        }
+        #[cfg(not(feature = "vorbis"))]
+        RecordFormat::Vorbis => return Err(unsupported("Ogg/Vorbis", "vorbis")),
+        #[cfg(feature = "mp3")]
+        RecordFormat::Mp3 => {
+            use crate::mp3::{Mp3Encoder, DEFAULT_BITRATE};
+            let (channels, sample_rate) = (stream_config.channels(), stream_config.sample_rate().0);
+            let bitrate = config.bitrate.unwrap_or(DEFAULT_BITRATE);
+            let encoder =
+                Mp3Encoder::new(channels, sample_rate, bitrate).map_err(io::Error::other)?;
+            Box::new(EncoderSink::create(path, encoder)?)
+        }
+        #[cfg(not(feature = "mp3"))]
+        RecordFormat::Mp3 => return Err(unsupported("MP3", "mp3")),
+    })
+}
+
+/// The path, or the path with a number before the extension, `-1`, `-2` ...,
+/// not to overwrite an existing file.
+pub fn unique_path(path: &str) -> PathBuf {
+    first_free_path(path, |path| !path.exists())
+}
+
+/// The path, or the path with the first number before the extension that is free.
+fn first_free_path(path: &str, is_free: impl Fn(&Path) -> bool) -> PathBuf {
+    let path = PathBuf::from(path);
+    if is_free(&path) {
+        return path;
+    }
+    let stem = path
+        .file_stem()
+        .map_or_else(String::new, |stem| stem.to_string_lossy().into_owned());
+    let extension = path.extension().map_or_else(String::new, |extension| {
+        format!(".{}", extension.to_string_lossy())
+    });
+    (1..)
+        .map(|number| path.with_file_name(format!("{}-{}{}", stem, number, extension)))
+        .find(|path| is_free(path))
+        .unwrap()
+}
+
+/// Path of the mono file of a channel of a recording split by channel,
+/// with the number of the channel from 1 before the extension, e.g. `take01_ch03.wav`.
+pub fn channel_path(path: &Path, channel: usize) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map_or_else(String::new, |stem| stem.to_string_lossy().into_owned());
+    let extension = path.extension().map_or_else(String::new, |extension| {
+        format!(".{}", extension.to_string_lossy())
+    });
+    path.with_file_name(format!("{}_ch{:02}{}", stem, channel + 1, extension))
+}
+
+/// Where to record.
+#[derive(Clone, Debug)]
+pub enum RecordPath {
+    File(PathBuf),
+    /// a new file for each segment, named with [`format_file_name`],
+    /// never overwriting an existing file
+    Template(String),
+}
+
+impl RecordPath {
+    /// Directory of the recordings.
+    pub fn directory(&self) -> PathBuf {
+        let path = match self {
+            RecordPath::File(path) => path.as_path(),
+            RecordPath::Template(template) => Path::new(template),
+        };
+        match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        }
+    }
+}
+
+/// Recording of the input to a file, or to a new file for each segment of a given duration
+/// without losing any sample between them.
+pub struct Recorder {
+    path: RecordPath,
+    config: RecordConfig,
+    stream_config: cpal::SupportedStreamConfig,
+    /// duration of the segments in seconds, `None` for a single recording
+    segment: Option<f64>,
+    /// segments aligned to the multiples of their duration since the Unix epoch,
+    /// e.g. on the hour
+    align: bool,
+    /// the file being recorded, or the files of each channel, empty between files
+    tracks: Vec<Track>,
+    /// samples of a channel, to write the files of each channel
+    channel_samples: Vec<f32>,
+    /// frames to the end of the segment
+    frames_left: u64,
+}
+
+/// A file being recorded.
+struct Track {
+    path: PathBuf,
+    sink: Box<dyn RecordingSink>,
+    analyzer: Option<RecordingAnalyzer>,
+    channels: usize,
+    start: SystemTime,
+    num_frames: u64,
+}
+
+/// A file of a recording, once finalized.
+#[derive(Clone, Debug)]
+pub struct CompletedRecording {
+    pub path: PathBuf,
+    /// capture time of the first frame
+    pub start: SystemTime,
+    /// seconds
+    pub duration: f64,
+    /// loudness report, with the `report` of the config
+    pub report: Option<LoudnessReport>,
+}
+
+impl Recorder {
+    /// A recording to a file is created now, the files of a template
+    /// once the first samples are written.
+    pub fn new(
+        path: RecordPath,
+        config: RecordConfig,
+        stream_config: &cpal::SupportedStreamConfig,
+        segment: Option<f64>,
+        align: bool,
+    ) -> io::Result<Self> {
+        let mut recorder = Recorder {
+            path,
+            config,
+            stream_config: stream_config.clone(),
+            segment,
+            align,
+            tracks: Vec::new(),
+            channel_samples: Vec::new(),
+            frames_left: 0,
+        };
+        if let RecordPath::File(_) = recorder.path {
+            recorder.open(SystemTime::now())?;
+        }
+        Ok(recorder)
+    }
+
+    fn open(&mut self, start: SystemTime) -> io::Result<()> {
+        let channels = self.stream_config.channels() as usize;
+        let paths = match (&self.path, self.config.split_channels) {
+            (RecordPath::File(path), false) => vec![path.clone()],
+            (RecordPath::File(path), true) => (0..channels)
+                .map(|channel| channel_path(path, channel))
+                .collect(),
+            (RecordPath::Template(template), false) => {
+                vec![unique_path(&format_file_name(template, start))]
+            }
+            (RecordPath::Template(template), true) => {
+                // the same number for the files of all the channels
+                let path = first_free_path(&format_file_name(template, start), |path| {
+                    (0..channels).all(|channel| !channel_path(path, channel).exists())
+                });
+                (0..channels)
+                    .map(|channel| channel_path(&path, channel))
+                    .collect()
+            }
+        };
+        let track_config = if self.config.split_channels {
+            cpal::SupportedStreamConfig::new(
+                1,
+                self.stream_config.sample_rate(),
+                *self.stream_config.buffer_size(),
+                self.stream_config.sample_format(),
+            )
+        } else {
+            self.stream_config.clone()
+        };
+        for path in paths {
+            let sink = create(&path, &self.config, &track_config, start).map_err(|err| {
+                io::Error::new(
+                    err.kind(),
+                    format!("failed to create '{}': {}", path.display(), err),
+                )
+            })?;
+            // the loudness of a single channel of the layout is not weighted
+            let layout = match self.config.split_channels {
+                false => self.config.layout.as_deref(),
+                true => None,
+            };
+            let analyzer = self.config.report.map(|config| {
+                RecordingAnalyzer::new(
+                    config,
+                    track_config.channels() as usize,
+                    layout,
+                    track_config.sample_rate().0,
+                    start,
+                )
+            });
+            self.tracks.push(Track {
+                path,
+                sink,
+                analyzer,
+                channels: track_config.channels() as usize,
+                start,
+                num_frames: 0,
+            });
+        }
+
+        if let Some(segment) = self.segment {
+            let sample_rate = self.stream_config.sample_rate().0 as f64;
+            let mut duration = segment;
+            if self.align {
+                // up to the next multiple of the duration, a whole one if it is too close
+                duration = segment - unix_time(start) % segment;
+                if duration * sample_rate < 0.5 {
+                    duration = segment;
+                }
+            }
+            self.frames_left = ((duration * sample_rate).round() as u64).max(1);
+        }
+        Ok(())
+    }
+
+    /// Finalize the current files and write their reports.
+    fn close(&mut self) -> io::Result<Vec<CompletedRecording>> {
+        let sample_rate = self.stream_config.sample_rate().0 as f64;
+        let sidecar = self.config.report.is_some_and(|config| config.sidecar);
+        let mut completed = Vec::with_capacity(self.tracks.len());
+        for mut track in self.tracks.drain(..) {
+            track.sink.finalize()?;
+            let report = track.analyzer.map(RecordingAnalyzer::finish);
+            if let (true, Some(report)) = (sidecar, &report) {
+                report::write_sidecar(&track.path, report)?;
+            }
+            completed.push(CompletedRecording {
+                path: track.path,
+                start: track.start,
+                duration: track.num_frames as f64 / sample_rate,
+                report,
+            });
+        }
+        Ok(completed)
+    }
+
+    /// Write interleaved samples, captured at the time of the first frame,
+    /// returns the recordings completed.
+    pub fn write(
+        &mut self,
+        samples: &[f32],
+        timestamp: SystemTime,
+    ) -> io::Result<Vec<CompletedRecording>> {
+        let channels = self.stream_config.channels() as usize;
+        let sample_rate = self.stream_config.sample_rate().0 as u64;
+        let num_frames = (samples.len() / channels) as u64;
+        let mut completed = Vec::new();
+        let mut offset = 0;
+        while offset < num_frames {
+            if self.tracks.is_empty() {
+                self.open(timestamp + Duration::from_nanos(offset * 1_000_000_000 / sample_rate))?;
+            }
+            let frames = match self.segment {
+                Some(_) => self.frames_left.min(num_frames - offset),
+                None => num_frames - offset,
+            };
+            let segment_samples =
+                &samples[offset as usize * channels..(offset + frames) as usize * channels];
+            if self.config.split_channels {
+                for (channel, track) in self.tracks.iter_mut().enumerate() {
+                    self.channel_samples.clear();
+                    self.channel_samples
+                        .extend(segment_samples.iter().skip(channel).step_by(channels));
+                    track.write(&self.channel_samples)?;
+                }
+            } else {
+                for track in &mut self.tracks {
+                    track.write(segment_samples)?;
+                }
+            }
+            offset += frames;
+            if self.segment.is_some() {
+                self.frames_left -= frames;
+                if self.frames_left == 0 {
+                    completed.extend(self.close()?);
+                }
+            }
+        }
+        Ok(completed)
+    }
+
+    /// Paths of the files being recorded, if any.
+    pub fn current_paths(&self) -> Vec<&Path> {
+        self.tracks
+            .iter()
+            .map(|track| track.path.as_path())
+            .collect()
+    }
+
+    /// Finalize the current recording, returns its files if any.
+    pub fn finalize(&mut self) -> io::Result<Vec<CompletedRecording>> {
+        self.close()
+    }
+}
+
+impl Track {
+    fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        self.sink.write_interleaved(samples)?;
+        self.num_frames += (samples.len() / self.channels) as u64;
+        if let Some(analyzer) = &mut self.analyzer {
+            analyzer.process(samples);
+        }
+        Ok(())
+    }
+}
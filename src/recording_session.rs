@@ -0,0 +1,146 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Recording of the captured samples, out of the audio thread: filtered and gated,
+//! started by the level trigger, paused out of the windows of the schedule,
+//! and with the retention of its segments enforced.
+
+use crate::filter::FilterChain;
+use crate::gate::NoiseGate;
+use crate::recording::{CompletedRecording, Recorder};
+use crate::retention::{Deletion, Retention};
+use crate::schedule::Schedule;
+use crate::trigger::LevelTrigger;
+use std::io;
+use std::time::{Duration, SystemTime};
+
+/// Time between the checks of the retention, besides each new segment.
+pub const RETENTION_INTERVAL: Duration = Duration::from_secs(10);
+
+/// What happened to the recording with an input buffer.
+#[derive(Debug, Default)]
+pub struct RecordingUpdate {
+    /// whether the input buffer was out of the schedule, or the recording stopped
+    /// by the controls, the current files finalized
+    pub paused: bool,
+    pub completed: Vec<CompletedRecording>,
+    /// segments deleted by the retention
+    pub deleted: Vec<Deletion>,
+    /// why the retention stopped the recording, if so, no more input buffers are recorded
+    pub stopped: Option<String>,
+}
+
+/// A recording with its trigger, schedule, retention and processing.
+pub struct RecordingSession {
+    recorder: Recorder,
+    retention: Retention,
+    trigger: Option<LevelTrigger>,
+    schedule: Option<Schedule>,
+    filter: Option<FilterChain>,
+    gate: Option<NoiseGate>,
+    /// time of the next check of the retention
+    next_retention: Option<SystemTime>,
+}
+
+impl RecordingSession {
+    pub fn new(recorder: Recorder, retention: Retention) -> Self {
+        RecordingSession {
+            recorder,
+            retention,
+            trigger: None,
+            schedule: None,
+            filter: None,
+            gate: None,
+            next_retention: None,
+        }
+    }
+
+    /// Only record once the level exceeds the threshold of the trigger, with its pre-roll.
+    pub fn with_trigger(mut self, trigger: LevelTrigger) -> Self {
+        self.trigger = Some(trigger);
+        self
+    }
+
+    /// Only record within the windows of the schedule.
+    pub fn with_schedule(mut self, schedule: Schedule) -> Self {
+        self.schedule = Some(schedule);
+        self
+    }
+
+    pub fn with_filter(mut self, filter: FilterChain) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    pub fn with_gate(mut self, gate: NoiseGate) -> Self {
+        self.gate = Some(gate);
+        self
+    }
+
+    /// Record an input buffer of interleaved samples captured at the given time,
+    /// processed in place, unless `enabled` is false or it is out of the schedule.
+    ///
+    /// The recording is finalized as far as written on an error.
+    pub fn process(
+        &mut self,
+        samples: &mut [f32],
+        timestamp: SystemTime,
+        enabled: bool,
+    ) -> io::Result<RecordingUpdate> {
+        if let Some(ref mut filter) = self.filter {
+            filter.process(samples);
+        }
+        if let Some(ref mut gate) = self.gate {
+            gate.process(samples);
+        }
+        let scheduled = enabled
+            && self
+                .schedule
+                .as_ref()
+                .is_none_or(|schedule| schedule.contains(timestamp));
+        let completed = match self.trigger.as_mut() {
+            _ if !scheduled => {
+                if let Some(trigger) = self.trigger.as_mut() {
+                    trigger.reset();
+                }
+                self.recorder.finalize()
+            }
+            Some(trigger) => trigger.process(&mut self.recorder, samples, timestamp),
+            None => self.recorder.write(samples, timestamp),
+        }?;
+
+        let mut update = RecordingUpdate {
+            paused: !scheduled,
+            ..RecordingUpdate::default()
+        };
+        if !completed.is_empty()
+            || self
+                .next_retention
+                .is_none_or(|next_retention| timestamp >= next_retention)
+        {
+            self.next_retention = Some(timestamp + RETENTION_INTERVAL);
+            let enforcement = self.retention.enforce(&self.recorder.current_paths());
+            update.deleted = enforcement.deleted;
+            update.stopped = enforcement.stop;
+        }
+        update.completed = completed;
+        Ok(update)
+    }
+
+    /// Finalize the current files, returns the recordings completed.
+    pub fn finalize(&mut self) -> io::Result<Vec<CompletedRecording>> {
+        self.recorder.finalize()
+    }
+}
@@ -0,0 +1,193 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Loudness reports of the recordings, written to a JSON sidecar file next to
+//! each one once it is finalized.
+
+use crate::clipping::{ClipConfig, ClipDetector};
+use crate::loudness::{ChannelPosition, LoudnessMeter};
+use crate::silence::{SilenceConfig, SilenceDetector, SilenceEvent};
+use crate::true_peak::TruePeakMeter;
+use crate::{decibels_overload, process_input_channels_into, unix_time, ChannelData};
+use serde::Serialize;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Options of the analysis of the recordings.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ReportConfig {
+    pub clip: ClipConfig,
+    pub silence: SilenceConfig,
+    /// write the report to the sidecar file of each recording
+    pub sidecar: bool,
+}
+
+/// A silence of a channel, in seconds since the start of the recording.
+#[derive(Clone, Debug, Serialize)]
+pub struct SilenceRegion {
+    pub channel: usize,
+    pub start: f64,
+    pub end: f64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct LoudnessReport {
+    /// capture time of the first frame, in seconds since the Unix epoch
+    pub start: f64,
+    /// seconds
+    pub duration: f64,
+    /// integrated loudness in LUFS, `null` if no block is above the gate
+    pub integrated: f32,
+    /// loudness range in LU
+    pub loudness_range: f32,
+    /// maximum true-peak of all the channels in dBTP, `null` if all the samples are zero
+    pub true_peak: f32,
+    /// clips of all the channels
+    pub clips: u64,
+    pub silences: Vec<SilenceRegion>,
+}
+
+/// Analysis of the interleaved samples of a recording.
+pub struct RecordingAnalyzer {
+    num_channels: usize,
+    sample_rate: u32,
+    start: SystemTime,
+    num_frames: u64,
+    loudness_meter: LoudnessMeter,
+    integrated: f32,
+    true_peak_meter: TruePeakMeter,
+    true_peak: f32,
+    clip_detector: ClipDetector,
+    clips: u64,
+    silence_detector: SilenceDetector,
+    silences: Vec<SilenceRegion>,
+    channels: Vec<ChannelData>,
+}
+
+impl RecordingAnalyzer {
+    /// Analysis of a recording started at the given time,
+    /// of channels at the positions of the layout if known.
+    pub fn new(
+        config: ReportConfig,
+        num_channels: usize,
+        layout: Option<&[ChannelPosition]>,
+        sample_rate: u32,
+        start: SystemTime,
+    ) -> Self {
+        RecordingAnalyzer {
+            num_channels,
+            sample_rate,
+            start,
+            num_frames: 0,
+            loudness_meter: match layout {
+                Some(layout) => LoudnessMeter::with_layout(sample_rate, layout),
+                None => LoudnessMeter::new(sample_rate, num_channels),
+            },
+            integrated: f32::NEG_INFINITY,
+            true_peak_meter: TruePeakMeter::new(num_channels),
+            true_peak: 0.0,
+            clip_detector: ClipDetector::new(config.clip, num_channels),
+            clips: 0,
+            silence_detector: SilenceDetector::new(config.silence, num_channels),
+            silences: Vec::new(),
+            channels: Vec::with_capacity(num_channels),
+        }
+    }
+
+    /// capture time of the frame, from the count of the frames since the start
+    fn time(&self, frame: u64) -> SystemTime {
+        self.start + Duration::from_nanos(frame * 1_000_000_000 / self.sample_rate as u64)
+    }
+
+    /// seconds since the start of the recording
+    fn offset(&self, time: SystemTime) -> f64 {
+        time.duration_since(self.start)
+            .unwrap_or_default()
+            .as_secs_f64()
+    }
+
+    /// Add the interleaved samples written to the recording.
+    pub fn process(&mut self, samples: &[f32]) {
+        let timestamp = self.time(self.num_frames);
+        process_input_channels_into(
+            samples,
+            self.num_channels,
+            0..self.num_channels,
+            &mut self.channels,
+        );
+        self.integrated = self.loudness_meter.process(&self.channels).integrated;
+        for true_peak in self.true_peak_meter.process(&self.channels) {
+            self.true_peak = self.true_peak.max(true_peak);
+        }
+        self.clips = self
+            .clip_detector
+            .process(&self.channels, timestamp)
+            .iter()
+            .map(|clipping| clipping.count)
+            .sum();
+        for event in self.silence_detector.process(&self.channels, timestamp) {
+            if let SilenceEvent::End {
+                channel,
+                since,
+                until,
+            } = event
+            {
+                self.silences.push(SilenceRegion {
+                    channel,
+                    start: self.offset(since),
+                    end: self.offset(until),
+                });
+            }
+        }
+        self.num_frames += (samples.len() / self.num_channels) as u64;
+    }
+
+    /// The report of the recording, with the silences until its end.
+    pub fn finish(mut self) -> LoudnessReport {
+        let end = self.time(self.num_frames);
+        for (channel, since) in self.silence_detector.silent_since().into_iter().enumerate() {
+            if let Some(since) = since {
+                self.silences.push(SilenceRegion {
+                    channel,
+                    start: (since - unix_time(self.start)).max(0.0),
+                    end: self.offset(end),
+                });
+            }
+        }
+        self.silences
+            .sort_by(|a, b| a.start.total_cmp(&b.start).then(a.channel.cmp(&b.channel)));
+        LoudnessReport {
+            start: unix_time(self.start),
+            duration: self.offset(end),
+            integrated: self.integrated,
+            loudness_range: self.loudness_meter.loudness_range(),
+            true_peak: decibels_overload(self.true_peak),
+            clips: self.clips,
+            silences: self.silences,
+        }
+    }
+}
+
+/// Path of the report of a recording, its path with the `json` extension.
+pub fn sidecar_path(path: &Path) -> PathBuf {
+    path.with_extension("json")
+}
+
+/// Write the report of a recording to its sidecar file.
+pub fn write_sidecar(path: &Path, report: &LoudnessReport) -> io::Result<()> {
+    let json = serde_json::to_string_pretty(report).map_err(io::Error::other)?;
+    std::fs::write(sidecar_path(path), json)
+}
@@ -0,0 +1,75 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Sample rate conversion of a stream of interleaved samples.
+
+/// Linear interpolation resampler, good enough for monitoring:
+/// there is no anti-aliasing filter when downsampling.
+pub struct LinearResampler {
+    channels: usize,
+    /// input samples per output sample
+    step: f64,
+    /// time of the next output sample, in input samples
+    /// relative to the first frame of the next input block
+    /// (between -1 and 0 means between the last frame of the previous block and the first one)
+    position: f64,
+    last_frame: Vec<f32>,
+}
+
+impl LinearResampler {
+    pub fn new(channels: usize, input_sample_rate: u32, output_sample_rate: u32) -> Self {
+        LinearResampler {
+            channels,
+            step: input_sample_rate as f64 / output_sample_rate as f64,
+            position: 0.0,
+            last_frame: vec![0.0; channels],
+        }
+    }
+
+    /// Resample a block of interleaved input samples, appending to `output`.
+    pub fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        let num_frames = input.len() / self.channels;
+        if num_frames == 0 {
+            return;
+        }
+
+        let frame = |index: isize| -> &[f32] {
+            if index < 0 {
+                &self.last_frame
+            } else {
+                let start = index as usize * self.channels;
+                &input[start..start + self.channels]
+            }
+        };
+
+        let mut position = self.position;
+        while position <= (num_frames - 1) as f64 {
+            let index = position.floor() as isize;
+            let fraction = (position - index as f64) as f32;
+            let a = frame(index);
+            if fraction > 0.0 {
+                let b = frame(index + 1);
+                output.extend(a.iter().zip(b).map(|(a, b)| a + (b - a) * fraction));
+            } else {
+                output.extend_from_slice(a);
+            }
+            position += self.step;
+        }
+
+        self.position = position - num_frames as f64;
+        self.last_frame
+            .copy_from_slice(&input[(num_frames - 1) * self.channels..num_frames * self.channels]);
+    }
+}
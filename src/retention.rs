@@ -0,0 +1,608 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Retention of the recordings: deletion of the oldest segments above a total size
+//! or an age, and a guard of the free space of the disk, before it fills.
+
+use crate::recording::RecordPath;
+use crate::report;
+use std::collections::VecDeque;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// bytes, free space kept in the disk of the recordings
+pub const DEFAULT_MIN_FREE: u64 = 1 << 30;
+
+/// What to do when the recordings are over the limits.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RetentionAction {
+    /// delete the oldest segments
+    #[default]
+    Delete,
+    /// stop the recording, keeping all the segments
+    Stop,
+}
+
+impl RetentionAction {
+    pub fn parse(action: &str) -> Result<Self, String> {
+        match action {
+            "delete" => Ok(RetentionAction::Delete),
+            "stop" => Ok(RetentionAction::Stop),
+            _ => Err(format!(
+                "invalid retention action '{}', expected one of: delete, stop",
+                action
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RetentionConfig {
+    /// total size of the segments of the recording, in bytes
+    pub max_bytes: Option<u64>,
+    /// age of the oldest segment kept
+    pub keep: Option<Duration>,
+    /// free space kept in the disk, in bytes, only deleting segments to keep it
+    /// if there is a maximum size or age
+    pub min_free_bytes: u64,
+    pub action: RetentionAction,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        RetentionConfig {
+            max_bytes: None,
+            keep: None,
+            min_free_bytes: DEFAULT_MIN_FREE,
+            action: RetentionAction::default(),
+        }
+    }
+}
+
+/// Parse a size in bytes, with an optional binary unit suffix: `K`, `M`, `G` or `T`,
+/// e.g. `500M` or `50G`.
+pub fn parse_size(size: &str) -> Result<u64, String> {
+    let trimmed = size.trim().trim_end_matches(['B', 'b']);
+    let (number, shift) = match trimmed.char_indices().last() {
+        Some((index, 'K' | 'k')) => (&trimmed[..index], 10),
+        Some((index, 'M' | 'm')) => (&trimmed[..index], 20),
+        Some((index, 'G' | 'g')) => (&trimmed[..index], 30),
+        Some((index, 'T' | 't')) => (&trimmed[..index], 40),
+        _ => (trimmed, 0),
+    };
+    match number.trim().parse::<f64>() {
+        Ok(number) if number >= 0.0 && number.is_finite() => {
+            Ok((number * (1_u64 << shift) as f64) as u64)
+        }
+        _ => Err(format!("invalid size '{}'", size)),
+    }
+}
+
+/// Bytes available to the user in the file system of the path,
+/// `None` if unknown.
+#[cfg(unix)]
+pub fn free_space(path: &Path) -> Option<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+pub fn free_space(_path: &Path) -> Option<u64> {
+    None
+}
+
+/// Whether the file name is one of the names of the template, with the digits
+/// of its fields, the number added not to overwrite a file, e.g. `-1`,
+/// and the number of the channel of a recording split by channel, e.g. `_ch03`.
+fn matches_template(template: &str, file_name: &str) -> bool {
+    let (template_stem, extension) = match template.rfind('.') {
+        Some(dot) => template.split_at(dot),
+        None => (template, ""),
+    };
+    let stem = match file_name.strip_suffix(extension) {
+        Some(stem) => stem,
+        None => return false,
+    };
+
+    let mut stem = stem.as_bytes();
+    let mut template = template_stem.chars();
+    while let Some(c) = template.next() {
+        let digits = match c {
+            '%' => match template.next() {
+                Some('Y') => 4,
+                Some('m' | 'd' | 'H' | 'M' | 'S') => 2,
+                Some('%') => 0,
+                _ => return false,
+            },
+            _ => 0,
+        };
+        if digits > 0 {
+            if stem.len() < digits || !stem[..digits].iter().all(u8::is_ascii_digit) {
+                return false;
+            }
+            stem = &stem[digits..];
+        } else {
+            let mut buffer = [0; 4];
+            let literal = c.encode_utf8(&mut buffer).as_bytes();
+            match stem.strip_prefix(literal) {
+                Some(rest) => stem = rest,
+                None => return false,
+            }
+        }
+    }
+    let is_number = |number: &[u8]| !number.is_empty() && number.iter().all(u8::is_ascii_digit);
+    if let Some(channel) = stem.iter().rposition(|&c| c == b'_') {
+        if stem[channel..].starts_with(b"_ch") && is_number(&stem[channel + 3..]) {
+            stem = &stem[..channel];
+        }
+    }
+    match stem {
+        [] => true,
+        [b'-', number @ ..] => is_number(number),
+        _ => false,
+    }
+}
+
+/// A segment deleted, and why.
+#[derive(Clone, Debug)]
+pub struct Deletion {
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+/// Result of the enforcement of the retention.
+#[derive(Clone, Debug, Default)]
+pub struct Enforcement {
+    pub deleted: Vec<Deletion>,
+    /// why the recording must stop, if so
+    pub stop: Option<String>,
+}
+
+struct Segment {
+    path: PathBuf,
+    modified: SystemTime,
+    len: u64,
+}
+
+/// Retention of the segments of a recording, the files of its template in its directory.
+pub struct Retention {
+    config: RetentionConfig,
+    directory: PathBuf,
+    /// template of the file names, `None` for a recording to a single file
+    template: Option<String>,
+}
+
+impl Retention {
+    pub fn new(config: RetentionConfig, path: &RecordPath) -> Self {
+        let template = match path {
+            RecordPath::File(_) => None,
+            RecordPath::Template(template) => Path::new(template)
+                .file_name()
+                .map(|file_name| file_name.to_string_lossy().into_owned()),
+        };
+        Retention {
+            config,
+            directory: path.directory(),
+            template,
+        }
+    }
+
+    /// The segments of the recording but the current ones, the oldest first.
+    fn segments(&self, current: &[&Path]) -> Vec<Segment> {
+        let template = match &self.template {
+            Some(template) => template,
+            None => return Vec::new(),
+        };
+        let entries = match fs::read_dir(&self.directory) {
+            Ok(entries) => entries,
+            Err(_) => return Vec::new(),
+        };
+        let current: Vec<_> = current
+            .iter()
+            .filter_map(|current| current.file_name())
+            .collect();
+        let mut segments: Vec<Segment> = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| {
+                let file_name = entry.file_name();
+                !current.contains(&file_name.as_os_str())
+                    && matches_template(template, &file_name.to_string_lossy())
+            })
+            .filter_map(|entry| {
+                let metadata = entry.metadata().ok()?;
+                if !metadata.is_file() {
+                    return None;
+                }
+                Some(Segment {
+                    path: entry.path(),
+                    modified: metadata.modified().ok()?,
+                    len: metadata.len(),
+                })
+            })
+            .collect();
+        segments.sort_by_key(|segment| segment.modified);
+        segments
+    }
+
+    /// Delete the oldest segments over the maximum age, size or below the free space,
+    /// never the one being recorded, or tell to stop the recording if that is not enough
+    /// or not allowed.
+    pub fn enforce(&self, current: &[&Path]) -> Enforcement {
+        let config = self.config;
+        let delete = config.action == RetentionAction::Delete;
+        let mut segments: VecDeque<Segment> = self.segments(current).into();
+        let mut deleted = Vec::new();
+
+        if let (true, Some(keep)) = (delete, config.keep) {
+            let oldest = SystemTime::now() - keep;
+            let reason = format!("older than {} day(s)", keep.as_secs_f64() / 86400.0);
+            while segments
+                .front()
+                .is_some_and(|segment| segment.modified < oldest)
+            {
+                delete_oldest(&mut segments, &reason, &mut deleted);
+            }
+        }
+
+        if let Some(max_bytes) = config.max_bytes {
+            let current_len: u64 = current
+                .iter()
+                .filter_map(|current| fs::metadata(current).ok())
+                .map(|metadata| metadata.len())
+                .sum();
+            let mut total = current_len + segments.iter().map(|segment| segment.len).sum::<u64>();
+            let reason = format!("the recordings take more than {}", format_size(max_bytes));
+            while total > max_bytes {
+                if !delete {
+                    return Enforcement {
+                        deleted,
+                        stop: Some(reason),
+                    };
+                }
+                match delete_oldest(&mut segments, &reason, &mut deleted) {
+                    Some(len) => total -= len,
+                    None => {
+                        return Enforcement {
+                            deleted,
+                            stop: Some(reason),
+                        }
+                    }
+                }
+            }
+        }
+
+        // only deleting to free space the segments that would be deleted anyway
+        let can_free = delete && (config.max_bytes.is_some() || config.keep.is_some());
+        let mut stop = None;
+        if let Some(mut free) = free_space(&self.directory) {
+            let reason = format!(
+                "less than {} free in the disk",
+                format_size(config.min_free_bytes)
+            );
+            while free < config.min_free_bytes {
+                match Some(&mut segments)
+                    .filter(|_| can_free)
+                    .and_then(|segments| delete_oldest(segments, &reason, &mut deleted))
+                {
+                    Some(len) => free += len,
+                    None => {
+                        stop = Some(reason);
+                        break;
+                    }
+                }
+            }
+        }
+        Enforcement { deleted, stop }
+    }
+}
+
+/// Delete the oldest segment that can be deleted, returns its size.
+fn delete_oldest(
+    segments: &mut VecDeque<Segment>,
+    reason: &str,
+    deleted: &mut Vec<Deletion>,
+) -> Option<u64> {
+    while let Some(segment) = segments.pop_front() {
+        if fs::remove_file(&segment.path).is_ok() {
+            // and its loudness report, if any
+            fs::remove_file(report::sidecar_path(&segment.path)).ok();
+            deleted.push(Deletion {
+                path: segment.path,
+                reason: reason.to_owned(),
+            });
+            return Some(segment.len);
+        }
+    }
+    None
+}
+
+/// Size with a binary unit, e.g. `1.5 GiB`.
+fn format_size(bytes: u64) -> String {
+    let units = ["bytes", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, units[0])
+    } else {
+        format!("{:.1} {}", size, units[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    const TEMPLATE: &str = "rec-%Y%m%d-%H%M%S.wav";
+
+    /// A directory of segments, removed when dropped.
+    struct Segments {
+        directory: PathBuf,
+    }
+
+    impl Segments {
+        /// Segments of `len` bytes, modified `days` ago each.
+        fn new(name: &str, segments: &[(&str, u64, u64)]) -> Self {
+            let directory = std::env::temp_dir().join(format!(
+                "audio-in-stream-rs-retention-{}-{}",
+                name,
+                std::process::id()
+            ));
+            fs::create_dir_all(&directory).unwrap();
+            for &(file_name, len, days) in segments {
+                let file = File::create(directory.join(file_name)).unwrap();
+                file.set_len(len).unwrap();
+                file.set_modified(SystemTime::now() - Duration::from_secs(days * 86400))
+                    .unwrap();
+            }
+            Segments { directory }
+        }
+
+        fn retention(&self, config: RetentionConfig) -> Retention {
+            let template = self.directory.join(TEMPLATE);
+            Retention::new(
+                config,
+                &RecordPath::Template(template.to_string_lossy().into_owned()),
+            )
+        }
+
+        fn path(&self, file_name: &str) -> PathBuf {
+            self.directory.join(file_name)
+        }
+
+        fn file_names(&self) -> Vec<String> {
+            let mut file_names: Vec<String> = fs::read_dir(&self.directory)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+                .collect();
+            file_names.sort();
+            file_names
+        }
+    }
+
+    impl Drop for Segments {
+        fn drop(&mut self) {
+            fs::remove_dir_all(&self.directory).ok();
+        }
+    }
+
+    fn deleted_names(enforcement: &Enforcement) -> Vec<String> {
+        enforcement
+            .deleted
+            .iter()
+            .map(|deletion| {
+                deletion
+                    .path
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect()
+    }
+
+    /// Limits that never ask to free space of the disk.
+    fn config(max_bytes: Option<u64>, keep_days: Option<u64>) -> RetentionConfig {
+        RetentionConfig {
+            max_bytes,
+            keep: keep_days.map(|days| Duration::from_secs(days * 86400)),
+            min_free_bytes: 0,
+            action: RetentionAction::Delete,
+        }
+    }
+
+    #[test]
+    fn parse_sizes() {
+        assert_eq!(parse_size("1000"), Ok(1000));
+        assert_eq!(parse_size("4K"), Ok(4096));
+        assert_eq!(parse_size("500MB"), Ok(500 << 20));
+        assert_eq!(parse_size(" 1.5g "), Ok(3 << 29));
+        assert_eq!(parse_size("2T"), Ok(2 << 40));
+        for size in ["", "G", "-1G", "1X", "infK"].iter() {
+            assert!(parse_size(size).is_err(), "{}", size);
+        }
+    }
+
+    #[test]
+    fn parse_actions() {
+        assert_eq!(
+            RetentionAction::parse("delete"),
+            Ok(RetentionAction::Delete)
+        );
+        assert_eq!(RetentionAction::parse("stop"), Ok(RetentionAction::Stop));
+        assert!(RetentionAction::parse("keep").is_err());
+    }
+
+    #[test]
+    fn format_sizes() {
+        assert_eq!(format_size(1000), "1000 bytes");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(50 << 30), "50.0 GiB");
+        assert_eq!(format_size(3 << 50), "3072.0 TiB");
+    }
+
+    #[test]
+    fn file_names_of_the_template() {
+        assert!(matches_template(TEMPLATE, "rec-20240101-235959.wav"));
+        assert!(matches_template(TEMPLATE, "rec-20240101-235959-2.wav"));
+        assert!(matches_template(TEMPLATE, "rec-20240101-235959_ch03.wav"));
+        assert!(matches_template(TEMPLATE, "rec-20240101-235959-1_ch10.wav"));
+        assert!(matches_template("100%%-%H.flac", "100%-12.flac"));
+        assert!(matches_template("%Y", "2024"));
+
+        assert!(!matches_template(TEMPLATE, "rec-20240101-235959.flac"));
+        assert!(!matches_template(TEMPLATE, "rec-2024010-235959.wav"));
+        assert!(!matches_template(TEMPLATE, "rec-2024010a-235959.wav"));
+        assert!(!matches_template(TEMPLATE, "rec-20240101-235959-.wav"));
+        assert!(!matches_template(TEMPLATE, "rec-20240101-235959_ch.wav"));
+        assert!(!matches_template(TEMPLATE, "rec-20240101-235959 copy.wav"));
+        assert!(!matches_template(TEMPLATE, "rec-20240101-235959.json"));
+    }
+
+    #[test]
+    fn delete_the_oldest_above_the_size() {
+        let segments = Segments::new(
+            "size",
+            &[
+                ("rec-20240101-000000.wav", 100, 3),
+                ("rec-20240101-000000.json", 10, 3),
+                ("rec-20240102-000000.wav", 100, 2),
+                ("rec-20240103-000000.wav", 100, 1),
+                ("rec-20240104-000000.wav", 100, 0),
+                ("notes.txt", 1000, 5),
+                ("other-20240101-000000.wav", 1000, 5),
+            ],
+        );
+        let current = segments.path("rec-20240104-000000.wav");
+        let enforcement = segments
+            .retention(config(Some(250), None))
+            .enforce(&[&current]);
+        assert_eq!(
+            deleted_names(&enforcement),
+            ["rec-20240101-000000.wav", "rec-20240102-000000.wav"]
+        );
+        assert!(enforcement.deleted[0].reason.contains("250 bytes"));
+        assert_eq!(enforcement.stop, None);
+        // with the report of the segment deleted
+        assert_eq!(
+            segments.file_names(),
+            [
+                "notes.txt",
+                "other-20240101-000000.wav",
+                "rec-20240103-000000.wav",
+                "rec-20240104-000000.wav",
+            ]
+        );
+    }
+
+    #[test]
+    fn never_delete_the_current_segment() {
+        let segments = Segments::new(
+            "current",
+            &[
+                ("rec-20240101-000000.wav", 100, 1),
+                ("rec-20240102-000000.wav", 500, 0),
+            ],
+        );
+        let current = segments.path("rec-20240102-000000.wav");
+        let enforcement = segments
+            .retention(config(Some(200), None))
+            .enforce(&[&current]);
+        assert_eq!(deleted_names(&enforcement), ["rec-20240101-000000.wav"]);
+        assert!(enforcement.stop.is_some());
+        assert_eq!(segments.file_names(), ["rec-20240102-000000.wav"]);
+    }
+
+    #[test]
+    fn delete_the_older_segments() {
+        let segments = Segments::new(
+            "age",
+            &[
+                ("rec-20240101-000000.wav", 100, 10),
+                ("rec-20240102-000000.wav", 100, 8),
+                ("rec-20240103-000000.wav", 100, 6),
+            ],
+        );
+        let enforcement = segments.retention(config(None, Some(7))).enforce(&[]);
+        assert_eq!(
+            deleted_names(&enforcement),
+            ["rec-20240101-000000.wav", "rec-20240102-000000.wav"]
+        );
+        assert_eq!(enforcement.deleted[0].reason, "older than 7 day(s)");
+        assert_eq!(segments.file_names(), ["rec-20240103-000000.wav"]);
+    }
+
+    #[test]
+    fn stop_instead_of_deleting() {
+        let segments = Segments::new(
+            "stop",
+            &[
+                ("rec-20240101-000000.wav", 100, 10),
+                ("rec-20240102-000000.wav", 100, 0),
+            ],
+        );
+        let mut config = config(Some(150), Some(1));
+        config.action = RetentionAction::Stop;
+        let enforcement = segments.retention(config).enforce(&[]);
+        assert!(enforcement.deleted.is_empty());
+        assert!(enforcement.stop.unwrap().contains("150 bytes"));
+        assert_eq!(segments.file_names().len(), 2);
+    }
+
+    #[test]
+    fn guard_the_free_space() {
+        let segments = Segments::new(
+            "free",
+            &[
+                ("rec-20240101-000000.wav", 100, 1),
+                ("rec-20240102-000000.wav", 100, 0),
+            ],
+        );
+        let current = segments.path("rec-20240102-000000.wav");
+        // without limits, nothing is deleted to free space
+        let mut config = config(None, None);
+        config.min_free_bytes = u64::MAX;
+        let enforcement = segments.retention(config).enforce(&[&current]);
+        assert!(enforcement.deleted.is_empty());
+        assert!(enforcement.stop.unwrap().contains("free in the disk"));
+
+        // with a limit, the segments but the current one are deleted before stopping
+        config.max_bytes = Some(u64::MAX);
+        let enforcement = segments.retention(config).enforce(&[&current]);
+        assert_eq!(deleted_names(&enforcement), ["rec-20240101-000000.wav"]);
+        assert!(enforcement.stop.is_some());
+    }
+
+    #[test]
+    fn single_file_recordings() {
+        let segments = Segments::new("file", &[("rec.wav", 100, 10)]);
+        let path = RecordPath::File(segments.path("rec.wav"));
+        let enforcement = Retention::new(config(Some(0), Some(1)), &path).enforce(&[]);
+        assert!(enforcement.deleted.is_empty());
+        assert_eq!(segments.file_names(), ["rec.wav"]);
+    }
+}
@@ -0,0 +1,433 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Bounded ring of the captured samples, from the audio thread to any number of consumers.
+//!
+//! The audio thread only copies the samples in, never waiting for the consumers
+//! nor allocating memory: a consumer that falls behind misses the overwritten chunks,
+//! which are counted as overruns.
+//! Only the writers of inputs which are not real time wait for the consumers.
+
+use crate::mix::ChannelMix;
+use std::sync::atomic::{self, AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Longest wait of a consumer for a new chunk, as the writer notifies them
+/// without locking, a notification can be missed.
+const WAIT_TIMEOUT: Duration = Duration::from_millis(10);
+
+/// Polling interval of a writer waiting for the consumers.
+const READERS_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Chunks of a ring of the default size, of up to 1/5 s each,
+/// input buffers that do not fit are split into several chunks.
+pub const DEFAULT_CHUNKS: usize = 64;
+pub const DEFAULT_CHUNKS_PER_SECOND: u32 = 5;
+
+/// Interleaved samples of an input buffer, or of part of it if it did not fit.
+struct Chunk {
+    /// sequence number of the chunk written to the slot, `2 * n + 2` for the chunk `n`,
+    /// odd while it is being written
+    sequence: AtomicU64,
+    /// capture time of the first frame, in nanoseconds since the Unix epoch
+    timestamp: AtomicU64,
+    len: AtomicUsize,
+    /// bits of the `f32` samples
+    samples: Box<[AtomicU32]>,
+}
+
+struct Shared {
+    num_channels: usize,
+    sample_rate: u32,
+    chunks: Box<[Chunk]>,
+    /// chunks written since the start
+    written: AtomicU64,
+    closed: AtomicBool,
+    /// chunks consumed by each reader, read by the writers waiting for them
+    consumed: Mutex<Vec<Arc<AtomicU64>>>,
+    mutex: Mutex<()>,
+    condvar: Condvar,
+}
+
+/// Ring of `num_chunks` chunks of up to `max_frames` frames each.
+pub struct SampleRing {
+    shared: Arc<Shared>,
+}
+
+impl SampleRing {
+    pub fn new(
+        num_channels: usize,
+        sample_rate: u32,
+        max_frames: usize,
+        num_chunks: usize,
+    ) -> Self {
+        assert!(num_channels > 0 && max_frames > 0 && num_chunks > 1);
+        let chunks = (0..num_chunks)
+            .map(|_| Chunk {
+                sequence: AtomicU64::new(0),
+                timestamp: AtomicU64::new(0),
+                len: AtomicUsize::new(0),
+                samples: (0..max_frames * num_channels)
+                    .map(|_| AtomicU32::new(0))
+                    .collect(),
+            })
+            .collect();
+        SampleRing {
+            shared: Arc::new(Shared {
+                num_channels,
+                sample_rate,
+                chunks,
+                written: AtomicU64::new(0),
+                closed: AtomicBool::new(false),
+                consumed: Mutex::new(Vec::new()),
+                mutex: Mutex::new(()),
+                condvar: Condvar::new(),
+            }),
+        }
+    }
+
+    /// A consumer, reading from the next chunk written.
+    pub fn reader(&self) -> RingReader {
+        let next = self.shared.written.load(Ordering::Acquire);
+        let consumed = Arc::new(AtomicU64::new(next));
+        self.shared
+            .consumed
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(Arc::clone(&consumed));
+        RingReader {
+            shared: Arc::clone(&self.shared),
+            next,
+            consumed,
+        }
+    }
+
+    /// The only writer, the readers end when it is dropped.
+    ///
+    /// The input buffers are mixed with `mix` into the channels of the ring.
+    pub fn writer(self, mix: ChannelMix) -> RingWriter {
+        assert_eq!(mix.output_channels(), self.shared.num_channels);
+        RingWriter {
+            shared: self.shared,
+            mix,
+        }
+    }
+}
+
+pub struct RingWriter {
+    shared: Arc<Shared>,
+    mix: ChannelMix,
+}
+
+impl RingWriter {
+    /// Mix and copy the interleaved samples of an input buffer captured at the given time,
+    /// overwriting the oldest chunks.
+    pub fn push<T>(&mut self, input_buffer: &[T], timestamp: SystemTime)
+    where
+        T: cpal::Sample,
+        f32: cpal::FromSample<T>,
+    {
+        let shared = &*self.shared;
+        let timestamp = timestamp
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_nanos() as u64);
+        let max_frames = shared.chunks[0].samples.len() / shared.num_channels;
+        let input_channels = self.mix.input_channels();
+        let mut written = shared.written.load(Ordering::Relaxed);
+        for (index, part) in input_buffer.chunks(max_frames * input_channels).enumerate() {
+            let chunk = &shared.chunks[(written % shared.chunks.len() as u64) as usize];
+            let frames = (index * max_frames) as u64;
+
+            chunk.sequence.swap(2 * written + 1, Ordering::Acquire);
+            atomic::fence(Ordering::Release);
+            chunk.timestamp.store(
+                timestamp + frames * 1_000_000_000 / shared.sample_rate as u64,
+                Ordering::Relaxed,
+            );
+            chunk.len.store(
+                part.len() / input_channels * shared.num_channels,
+                Ordering::Relaxed,
+            );
+            let mut samples = chunk.samples.iter();
+            for input_frame in part.chunks_exact(input_channels) {
+                for (mixed_sample, sample) in self.mix.mix_frame(input_frame).zip(&mut samples) {
+                    sample.store(mixed_sample.to_bits(), Ordering::Relaxed);
+                }
+            }
+            chunk.sequence.store(2 * written + 2, Ordering::Release);
+
+            written += 1;
+            shared.written.store(written, Ordering::Release);
+        }
+        shared.condvar.notify_all();
+    }
+
+    /// Wait until the next chunk can be written without overwriting a chunk
+    /// not yet consumed by every reader, to write faster than real time without overruns.
+    pub fn wait_for_room(&self) {
+        self.wait_for_readers_within(self.shared.chunks.len() as u64 - 2);
+    }
+
+    /// Wait until every reader has consumed all the chunks written.
+    pub fn wait_for_readers(&self) {
+        self.wait_for_readers_within(0);
+    }
+
+    fn wait_for_readers_within(&self, max_unconsumed: u64) {
+        let shared = &*self.shared;
+        let written = shared.written.load(Ordering::Relaxed);
+        while shared
+            .consumed
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .any(|consumed| {
+                written.saturating_sub(consumed.load(Ordering::Acquire)) > max_unconsumed
+            })
+        {
+            std::thread::sleep(READERS_POLL_INTERVAL);
+        }
+    }
+}
+
+impl Drop for RingWriter {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::Release);
+        self.shared.condvar.notify_all();
+    }
+}
+
+/// A chunk read from the ring.
+#[derive(Clone, Copy, Debug)]
+pub struct ReadChunk {
+    /// capture time of the first frame
+    pub timestamp: SystemTime,
+    /// chunks overwritten before they were read, since the previous chunk
+    pub overruns: u64,
+}
+
+pub struct RingReader {
+    shared: Arc<Shared>,
+    /// sequence number of the next chunk to read
+    next: u64,
+    /// chunks consumed, those before the chunk read
+    consumed: Arc<AtomicU64>,
+}
+
+impl RingReader {
+    /// channels of the interleaved samples read
+    pub fn num_channels(&self) -> usize {
+        self.shared.num_channels
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.shared.sample_rate
+    }
+
+    /// Wait for the next chunk and copy its interleaved samples into `samples`,
+    /// returns `None` once the writer is dropped and all the chunks are read.
+    pub fn read(&mut self, samples: &mut Vec<f32>) -> Option<ReadChunk> {
+        let shared = &*self.shared;
+        // the previous chunk was consumed when the next one is read
+        self.consumed.store(self.next, Ordering::Release);
+        let mut overruns = 0;
+        loop {
+            let written = shared.written.load(Ordering::Acquire);
+            if self.next >= written {
+                if shared.closed.load(Ordering::Acquire) {
+                    if self.next >= shared.written.load(Ordering::Acquire) {
+                        return None;
+                    }
+                    continue;
+                }
+                let guard = shared.mutex.lock().unwrap_or_else(PoisonError::into_inner);
+                if self.next >= shared.written.load(Ordering::Acquire) {
+                    let _ = shared.condvar.wait_timeout(guard, WAIT_TIMEOUT);
+                }
+                continue;
+            }
+
+            // the slot of the oldest chunk may be being overwritten by the next one
+            let oldest = (written + 1).saturating_sub(shared.chunks.len() as u64);
+            if self.next < oldest {
+                overruns += oldest - self.next;
+                self.next = oldest;
+            }
+
+            // when the chunk was overwritten while copying, the next loop skips it
+            if let Some(timestamp) = self.copy_chunk(samples) {
+                self.next += 1;
+                return Some(ReadChunk {
+                    timestamp: UNIX_EPOCH + Duration::from_nanos(timestamp),
+                    overruns,
+                });
+            }
+        }
+    }
+
+    /// Copy the samples of the next chunk, returns its timestamp,
+    /// or `None` if it was overwritten.
+    fn copy_chunk(&self, samples: &mut Vec<f32>) -> Option<u64> {
+        let chunk = &self.shared.chunks[(self.next % self.shared.chunks.len() as u64) as usize];
+        let sequence = 2 * self.next + 2;
+        if chunk.sequence.load(Ordering::Acquire) != sequence {
+            return None;
+        }
+        let timestamp = chunk.timestamp.load(Ordering::Relaxed);
+        let len = chunk.len.load(Ordering::Relaxed).min(chunk.samples.len());
+        samples.clear();
+        samples.extend(
+            chunk.samples[..len]
+                .iter()
+                .map(|sample| f32::from_bits(sample.load(Ordering::Relaxed))),
+        );
+        atomic::fence(Ordering::Acquire);
+        if chunk.sequence.load(Ordering::Relaxed) != sequence {
+            return None;
+        }
+        Some(timestamp)
+    }
+}
+
+impl Drop for RingReader {
+    fn drop(&mut self) {
+        self.shared
+            .consumed
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain(|consumed| !Arc::ptr_eq(consumed, &self.consumed));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    /// Ring of mono chunks of up to 4 frames at 1 kHz.
+    fn ring(num_chunks: usize) -> SampleRing {
+        SampleRing::new(1, 1000, 4, num_chunks)
+    }
+
+    /// Push the chunk `index`, its 4 samples all of the value of the index,
+    /// captured at `index` times 4 ms.
+    fn push_chunk(writer: &mut RingWriter, index: u64) {
+        let timestamp = UNIX_EPOCH + Duration::from_millis(4 * index);
+        writer.push(&[index as f32; 4], timestamp);
+    }
+
+    fn read_chunks(reader: &mut RingReader) -> Vec<(f32, SystemTime, u64)> {
+        let mut samples = Vec::new();
+        let mut chunks = Vec::new();
+        while let Some(chunk) = reader.read(&mut samples) {
+            assert_eq!(samples.len(), 4);
+            assert!(samples.iter().all(|&sample| sample == samples[0]));
+            chunks.push((samples[0], chunk.timestamp, chunk.overruns));
+        }
+        chunks
+    }
+
+    #[test]
+    fn wraps_around() {
+        let ring = ring(4);
+        let mut reader = ring.reader();
+        let mut writer = ring.writer(ChannelMix::identity(1));
+        let mut samples = Vec::new();
+        // three times around the ring, read as written
+        for index in 0..12 {
+            push_chunk(&mut writer, index);
+            let chunk = reader.read(&mut samples).unwrap();
+            assert_eq!(samples, [index as f32; 4]);
+            assert_eq!(
+                chunk.timestamp,
+                UNIX_EPOCH + Duration::from_millis(4 * index)
+            );
+            assert_eq!(chunk.overruns, 0);
+        }
+        drop(writer);
+        assert!(reader.read(&mut samples).is_none());
+    }
+
+    #[test]
+    fn splits_input_buffers() {
+        let ring = ring(4);
+        let mut reader = ring.reader();
+        let mut writer = ring.writer(ChannelMix::identity(1));
+        // 10 frames in chunks of 4, 4 and 2 frames, at 1 kHz
+        let input_buffer: Vec<f32> = (0..10).map(|sample| sample as f32).collect();
+        writer.push(&input_buffer, UNIX_EPOCH);
+        drop(writer);
+        let mut samples = Vec::new();
+        for (start, end) in [(0, 4), (4, 8), (8, 10)] {
+            let chunk = reader.read(&mut samples).unwrap();
+            assert_eq!(samples, input_buffer[start..end]);
+            assert_eq!(
+                chunk.timestamp,
+                UNIX_EPOCH + Duration::from_millis(start as u64)
+            );
+        }
+        assert!(reader.read(&mut samples).is_none());
+    }
+
+    #[test]
+    fn lapped_reader_counts_overruns() {
+        let ring = ring(4);
+        let mut reader = ring.reader();
+        let mut writer = ring.writer(ChannelMix::identity(1));
+        for index in 0..10 {
+            push_chunk(&mut writer, index);
+        }
+        drop(writer);
+        // the slot of the oldest chunk is skipped, as it may be being overwritten
+        let chunks = read_chunks(&mut reader);
+        let values: Vec<f32> = chunks.iter().map(|&(value, _, _)| value).collect();
+        assert_eq!(values, [7.0, 8.0, 9.0]);
+        let overruns: Vec<u64> = chunks.iter().map(|&(_, _, overruns)| overruns).collect();
+        assert_eq!(overruns, [7, 0, 0]);
+        assert_eq!(chunks[0].1, UNIX_EPOCH + Duration::from_millis(28));
+    }
+
+    #[test]
+    fn concurrent_readers_read_every_chunk() {
+        const CHUNKS: u64 = 1000;
+        let ring = ring(8);
+        let readers: Vec<_> = (0..3)
+            .map(|_| {
+                let mut reader = ring.reader();
+                thread::spawn(move || read_chunks(&mut reader))
+            })
+            .collect();
+        let mut writer = ring.writer(ChannelMix::identity(1));
+        for index in 0..CHUNKS {
+            writer.wait_for_room();
+            push_chunk(&mut writer, index);
+        }
+        writer.wait_for_readers();
+        drop(writer);
+        for reader in readers {
+            let chunks = reader.join().unwrap();
+            assert_eq!(chunks.len(), CHUNKS as usize);
+            for (index, &(value, timestamp, overruns)) in chunks.iter().enumerate() {
+                assert_eq!(value, index as f32);
+                assert_eq!(
+                    timestamp,
+                    UNIX_EPOCH + Duration::from_millis(4 * index as u64)
+                );
+                assert_eq!(overruns, 0);
+            }
+        }
+    }
+}
@@ -0,0 +1,149 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Loudness level of each channel as the root mean square of its samples
+//! over a sliding window, independent of the size of the input buffers.
+
+use crate::processor::{self, Processor};
+use crate::weighting::{Weighting, WeightingFilter};
+use crate::{ChannelData, InputBufferSourceData};
+
+/// seconds
+pub const DEFAULT_WINDOW: f32 = 0.3;
+/// seconds, the squares of the window being kept for each channel
+pub const MAX_WINDOW: f32 = 60.0;
+
+/// Squares of the last weighted samples of a channel.
+#[derive(Clone)]
+struct ChannelWindow {
+    squares: Vec<f64>,
+    /// position of the oldest square
+    position: usize,
+    sum: f64,
+}
+
+/// Weighted RMS level of each channel.
+pub struct RmsMeter {
+    weighting_filter: WeightingFilter,
+    sample_rate: u32,
+    /// a window of no samples is the whole input buffer
+    channels: Vec<ChannelWindow>,
+}
+
+impl RmsMeter {
+    /// A window of 0 seconds measures each input buffer on its own.
+    pub fn new(weighting: Weighting, window: f32, sample_rate: u32, num_channels: usize) -> Self {
+        let mut rms_meter = RmsMeter {
+            weighting_filter: WeightingFilter::new(weighting, sample_rate, num_channels),
+            sample_rate,
+            channels: vec![
+                ChannelWindow {
+                    squares: Vec::new(),
+                    position: 0,
+                    sum: 0.0,
+                };
+                num_channels
+            ],
+        };
+        rms_meter.set_window(window);
+        rms_meter
+    }
+
+    pub fn weighting(&self) -> Weighting {
+        self.weighting_filter.weighting()
+    }
+
+    /// seconds
+    pub fn window(&self) -> f32 {
+        self.channels
+            .first()
+            .map_or(0.0, |channel| channel.squares.len() as f32)
+            / self.sample_rate as f32
+    }
+
+    /// Change the window, at most [`MAX_WINDOW`], restarting the RMS of each channel.
+    pub fn set_window(&mut self, window: f32) {
+        let len = (window.clamp(0.0, MAX_WINDOW) * self.sample_rate as f32).round() as usize;
+        for channel in &mut self.channels {
+            *channel = ChannelWindow {
+                squares: vec![0.0; len],
+                position: 0,
+                sum: 0.0,
+            };
+        }
+    }
+
+    /// Replace the loudness level of each channel of an input buffer by the root mean square
+    /// of its weighted samples over the window ending with the buffer,
+    /// the samples are not changed.
+    pub fn process(&mut self, channels: &mut [ChannelData]) {
+        let weighting_filter = &mut self.weighting_filter;
+        for (position, (channel, window)) in channels.iter_mut().zip(&mut self.channels).enumerate()
+        {
+            if channel.samples.is_empty() {
+                continue;
+            }
+            let squares = channel.samples.iter().map(|&sample| {
+                let weighted = weighting_filter.filter(position, sample);
+                weighted * weighted
+            });
+
+            if window.squares.is_empty() {
+                let square_sum: f64 = squares.sum();
+                channel.loudness_level = (square_sum / channel.samples.len() as f64).sqrt() as f32;
+                continue;
+            }
+
+            let len = window.squares.len();
+            for square in squares {
+                window.sum += square - window.squares[window.position];
+                window.squares[window.position] = square;
+                window.position += 1;
+                if window.position == len {
+                    window.position = 0;
+                    // sum again once per window, not to accumulate rounding errors
+                    window.sum = window.squares.iter().sum();
+                }
+            }
+            channel.loudness_level = (window.sum.max(0.0) / len as f64).sqrt() as f32;
+        }
+    }
+}
+
+/// Replaces the loudness levels, with the parameter `window` in seconds.
+impl Processor for RmsMeter {
+    fn name(&self) -> &str {
+        "rms"
+    }
+
+    fn process(&mut self, buffer: &mut InputBufferSourceData) {
+        RmsMeter::process(self, &mut buffer.channels)
+    }
+
+    fn parameters(&self) -> Vec<(String, f64)> {
+        vec![(String::from("window"), f64::from(self.window()))]
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f64) -> Result<(), String> {
+        match name {
+            "window" if (0.0..=f64::from(MAX_WINDOW)).contains(&value) => {
+                self.set_window(value as f32);
+                Ok(())
+            }
+            "window" => Err(format!("invalid RMS window of {} seconds", value)),
+            _ => Err(processor::unknown_parameter(self, name)),
+        }
+    }
+}
@@ -0,0 +1,1356 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! RTMP client publishing the live capture as audio-only FLV, AAC with libfdk-aac
+//! or MP3 with libmp3lame, to an ingest server like nginx-rtmp or a streaming service.
+
+#[cfg(feature = "aac")]
+use crate::aac::{self, AacEncoder};
+#[cfg(feature = "mp3")]
+use crate::encoder::StreamEncoder;
+use crate::error::Error;
+#[cfg(feature = "mp3")]
+use crate::mp3::{self, Mp3Encoder};
+use crate::supervisor::Supervisor;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinHandle;
+
+const DEFAULT_PORT: u16 = 1935;
+/// delay before reconnecting after the connection to the server is lost
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+const HANDSHAKE_LEN: usize = 1536;
+/// Size of the chunks sent, set at the start of the connection.
+const CHUNK_SIZE: usize = 4096;
+/// Default size of the chunks received, until the server sets it.
+const DEFAULT_CHUNK_SIZE: usize = 128;
+/// Largest message accepted from the server.
+const MAX_MESSAGE_LEN: usize = 1 << 20;
+/// Timestamps from this value are sent in the extended timestamp field.
+const EXTENDED_TIMESTAMP: u32 = 0xff_ffff;
+
+/// chunk streams of the messages sent
+const CONTROL_CHUNK_STREAM: u32 = 2;
+const COMMAND_CHUNK_STREAM: u32 = 3;
+const AUDIO_CHUNK_STREAM: u32 = 4;
+
+/// message types
+const SET_CHUNK_SIZE: u8 = 1;
+const USER_CONTROL: u8 = 4;
+const AUDIO: u8 = 8;
+const DATA_AMF0: u8 = 18;
+const COMMAND_AMF0: u8 = 20;
+
+/// user control events
+const PING_REQUEST: u16 = 6;
+const PING_RESPONSE: u16 = 7;
+
+/// Audio codec of the FLV tags.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RtmpCodec {
+    Aac,
+    Mp3,
+}
+
+impl RtmpCodec {
+    pub fn parse(codec: &str) -> Result<Self, String> {
+        match codec.to_ascii_lowercase().as_str() {
+            "aac" => Ok(RtmpCodec::Aac),
+            "mp3" => Ok(RtmpCodec::Mp3),
+            _ => Err(format!(
+                "invalid RTMP codec '{}', expected aac or mp3",
+                codec
+            )),
+        }
+    }
+
+    /// Codec of the builds with its encoder, AAC if available.
+    pub fn default_codec() -> Self {
+        if cfg!(feature = "aac") {
+            RtmpCodec::Aac
+        } else {
+            RtmpCodec::Mp3
+        }
+    }
+
+    /// Error if this build has no encoder of the codec.
+    pub fn check_feature(self) -> Result<(), String> {
+        match self {
+            RtmpCodec::Aac if cfg!(not(feature = "aac")) => {
+                Err(String::from("the AAC codec requires the 'aac' feature"))
+            }
+            RtmpCodec::Mp3 if cfg!(not(feature = "mp3")) => {
+                Err(String::from("the MP3 codec requires the 'mp3' feature"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn default_bitrate(self) -> i32 {
+        match self {
+            #[cfg(feature = "aac")]
+            RtmpCodec::Aac => aac::DEFAULT_BITRATE,
+            #[cfg(feature = "mp3")]
+            RtmpCodec::Mp3 => mp3::DEFAULT_BITRATE,
+            #[allow(unreachable_patterns)]
+            _ => 128000,
+        }
+    }
+
+    /// `audiocodecid` of the FLV metadata.
+    fn codec_id(self) -> f64 {
+        match self {
+            RtmpCodec::Aac => 10.0,
+            RtmpCodec::Mp3 => 2.0,
+        }
+    }
+}
+
+/// Publishing point, from an url like `rtmp://host[:port]/app/stream-key`.
+#[derive(Clone, Debug)]
+pub struct RtmpUrl {
+    pub host: String,
+    pub port: u16,
+    pub app: String,
+    pub stream_key: String,
+}
+
+impl RtmpUrl {
+    pub fn parse(url: &str) -> Result<Self, String> {
+        let invalid_url = || format!("invalid RTMP url '{}', expected rtmp://host/app/key", url);
+        let rest = url
+            .strip_prefix("rtmp://")
+            .ok_or_else(|| format!("invalid RTMP url '{}', only rtmp:// is supported", url))?;
+        let (authority, path) = rest.split_once('/').ok_or_else(invalid_url)?;
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => {
+                (host, port.parse().map_err(|_| invalid_url())?)
+            }
+            _ => (authority, DEFAULT_PORT),
+        };
+        let (app, stream_key) = path.split_once('/').ok_or_else(invalid_url)?;
+        if host.is_empty() || app.is_empty() || stream_key.is_empty() {
+            return Err(invalid_url());
+        }
+
+        Ok(RtmpUrl {
+            host: host.to_owned(),
+            port,
+            app: app.to_owned(),
+            stream_key: stream_key.to_owned(),
+        })
+    }
+
+    /// Url of the application, without the stream key, as in the connect command.
+    fn tc_url(&self) -> String {
+        format!("rtmp://{}:{}/{}", self.host, self.port, self.app)
+    }
+}
+
+/// AMF0 value of the commands.
+#[derive(Clone, Debug, PartialEq)]
+enum Amf {
+    Number(f64),
+    Boolean(bool),
+    String(String),
+    Object(Vec<(String, Amf)>),
+    /// an ECMA array, an object with a count of properties
+    Array(Vec<(String, Amf)>),
+    Null,
+}
+
+impl Amf {
+    fn write(&self, bytes: &mut Vec<u8>) {
+        match self {
+            Amf::Number(number) => {
+                bytes.push(0x00);
+                bytes.extend_from_slice(&number.to_be_bytes());
+            }
+            Amf::Boolean(boolean) => bytes.extend_from_slice(&[0x01, *boolean as u8]),
+            Amf::String(string) => {
+                bytes.push(0x02);
+                write_amf_string(bytes, string);
+            }
+            Amf::Object(properties) => {
+                bytes.push(0x03);
+                write_amf_properties(bytes, properties);
+            }
+            Amf::Array(properties) => {
+                bytes.push(0x08);
+                bytes.extend_from_slice(&(properties.len() as u32).to_be_bytes());
+                write_amf_properties(bytes, properties);
+            }
+            Amf::Null => bytes.push(0x05),
+        }
+    }
+
+    /// Read a value, `None` at the end of the bytes or for the types not supported.
+    fn read(bytes: &mut &[u8]) -> Option<Amf> {
+        let (&marker, rest) = bytes.split_first()?;
+        *bytes = rest;
+        match marker {
+            0x00 => Some(Amf::Number(f64::from_be_bytes(
+                take(bytes, 8)?.try_into().ok()?,
+            ))),
+            0x01 => Some(Amf::Boolean(take(bytes, 1)?[0] != 0)),
+            0x02 => Some(Amf::String(read_amf_string(bytes)?)),
+            0x03 => Some(Amf::Object(read_amf_properties(bytes)?)),
+            0x05 | 0x06 => Some(Amf::Null),
+            0x08 => {
+                take(bytes, 4)?;
+                Some(Amf::Array(read_amf_properties(bytes)?))
+            }
+            _ => None,
+        }
+    }
+
+    /// Value of a property of an object.
+    fn get(&self, name: &str) -> Option<&Amf> {
+        match self {
+            Amf::Object(properties) | Amf::Array(properties) => properties
+                .iter()
+                .find(|(property, _)| property == name)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+}
+
+fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+    if bytes.len() < len {
+        return None;
+    }
+    let (taken, rest) = bytes.split_at(len);
+    *bytes = rest;
+    Some(taken)
+}
+
+fn write_amf_string(bytes: &mut Vec<u8>, string: &str) {
+    bytes.extend_from_slice(&(string.len() as u16).to_be_bytes());
+    bytes.extend_from_slice(string.as_bytes());
+}
+
+fn read_amf_string(bytes: &mut &[u8]) -> Option<String> {
+    let len = u16::from_be_bytes(take(bytes, 2)?.try_into().ok()?) as usize;
+    Some(String::from_utf8_lossy(take(bytes, len)?).into_owned())
+}
+
+fn write_amf_properties(bytes: &mut Vec<u8>, properties: &[(String, Amf)]) {
+    for (name, value) in properties {
+        write_amf_string(bytes, name);
+        value.write(bytes);
+    }
+    // empty name and object end marker
+    bytes.extend_from_slice(&[0, 0, 0x09]);
+}
+
+fn read_amf_properties(bytes: &mut &[u8]) -> Option<Vec<(String, Amf)>> {
+    let mut properties = Vec::new();
+    loop {
+        let name = read_amf_string(bytes)?;
+        if name.is_empty() && bytes.first() == Some(&0x09) {
+            *bytes = &bytes[1..];
+            return Some(properties);
+        }
+        properties.push((name, Amf::read(bytes)?));
+    }
+}
+
+fn amf_object(properties: Vec<(&str, Amf)>) -> Amf {
+    Amf::Object(
+        properties
+            .into_iter()
+            .map(|(name, value)| (name.to_owned(), value))
+            .collect(),
+    )
+}
+
+/// A message of the RTMP connection.
+struct Message {
+    message_type: u8,
+    stream_id: u32,
+    timestamp: u32,
+    payload: Vec<u8>,
+}
+
+/// Header of the last message of a chunk stream received, the reference of the
+/// compressed chunk headers, with the payload received so far.
+#[derive(Default)]
+struct ChunkStream {
+    timestamp: u32,
+    timestamp_delta: u32,
+    extended_timestamp: bool,
+    len: usize,
+    message_type: u8,
+    stream_id: u32,
+    payload: Vec<u8>,
+}
+
+/// Reads the messages of the server, reassembling their chunks.
+struct MessageReader<R> {
+    reader: R,
+    chunk_size: usize,
+    chunk_streams: HashMap<u32, ChunkStream>,
+}
+
+impl<R: AsyncRead + Unpin> MessageReader<R> {
+    fn new(reader: R) -> Self {
+        MessageReader {
+            reader,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            chunk_streams: HashMap::new(),
+        }
+    }
+
+    async fn read_bytes<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        let mut bytes = [0; N];
+        self.reader.read_exact(&mut bytes).await?;
+        Ok(bytes)
+    }
+
+    /// Read the next message, handling the changes of the chunk size.
+    async fn read(&mut self) -> io::Result<Message> {
+        loop {
+            let [basic_header] = self.read_bytes::<1>().await?;
+            let format = basic_header >> 6;
+            let chunk_stream_id = match basic_header & 0x3f {
+                0 => 64 + self.read_bytes::<1>().await?[0] as u32,
+                1 => {
+                    let [low, high] = self.read_bytes::<2>().await?;
+                    64 + low as u32 + ((high as u32) << 8)
+                }
+                id => id as u32,
+            };
+
+            let header_len = [11, 7, 3, 0][format as usize];
+            let mut header = [0_u8; 11];
+            self.reader.read_exact(&mut header[..header_len]).await?;
+            let mut chunk_stream = self
+                .chunk_streams
+                .remove(&chunk_stream_id)
+                .unwrap_or_default();
+            let starts_message = chunk_stream.payload.is_empty();
+            if format < 3 {
+                let timestamp = u32::from_be_bytes([0, header[0], header[1], header[2]]);
+                chunk_stream.extended_timestamp = timestamp == EXTENDED_TIMESTAMP;
+                chunk_stream.timestamp_delta = timestamp;
+                if format < 2 {
+                    chunk_stream.len =
+                        u32::from_be_bytes([0, header[3], header[4], header[5]]) as usize;
+                    chunk_stream.message_type = header[6];
+                }
+                if format == 0 {
+                    chunk_stream.stream_id =
+                        u32::from_le_bytes([header[7], header[8], header[9], header[10]]);
+                }
+            }
+            if chunk_stream.extended_timestamp {
+                chunk_stream.timestamp_delta = u32::from_be_bytes(self.read_bytes::<4>().await?);
+            }
+            if starts_message {
+                chunk_stream.timestamp = if format == 0 {
+                    chunk_stream.timestamp_delta
+                } else {
+                    chunk_stream
+                        .timestamp
+                        .wrapping_add(chunk_stream.timestamp_delta)
+                };
+            }
+            if chunk_stream.len > MAX_MESSAGE_LEN {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "RTMP message too large",
+                ));
+            }
+
+            let start = chunk_stream.payload.len();
+            let len = (chunk_stream.len - start).min(self.chunk_size);
+            chunk_stream.payload.resize(start + len, 0);
+            self.reader
+                .read_exact(&mut chunk_stream.payload[start..])
+                .await?;
+            let message = if chunk_stream.payload.len() == chunk_stream.len {
+                Some(Message {
+                    message_type: chunk_stream.message_type,
+                    stream_id: chunk_stream.stream_id,
+                    timestamp: chunk_stream.timestamp,
+                    payload: std::mem::take(&mut chunk_stream.payload),
+                })
+            } else {
+                None
+            };
+            self.chunk_streams.insert(chunk_stream_id, chunk_stream);
+
+            match message {
+                Some(message) if message.message_type == SET_CHUNK_SIZE => {
+                    if let Ok(size) = message.payload[..].try_into() {
+                        self.chunk_size = (u32::from_be_bytes(size) & 0x7fff_ffff).max(1) as usize;
+                    }
+                }
+                Some(message) => return Ok(message),
+                None => (),
+            }
+        }
+    }
+}
+
+/// Write a message in chunks of `CHUNK_SIZE`, the first one with a full header.
+async fn write_message(
+    writer: &mut (impl AsyncWrite + Unpin),
+    chunk_stream_id: u32,
+    message: &Message,
+) -> io::Result<()> {
+    let mut bytes = Vec::with_capacity(message.payload.len() + 16);
+    let extended = message.timestamp >= EXTENDED_TIMESTAMP;
+    let timestamp = message.timestamp.min(EXTENDED_TIMESTAMP);
+    for (index, chunk) in message.payload.chunks(CHUNK_SIZE).enumerate() {
+        if index == 0 {
+            bytes.push(chunk_stream_id as u8);
+            bytes.extend_from_slice(&timestamp.to_be_bytes()[1..]);
+            bytes.extend_from_slice(&(message.payload.len() as u32).to_be_bytes()[1..]);
+            bytes.push(message.message_type);
+            bytes.extend_from_slice(&message.stream_id.to_le_bytes());
+        } else {
+            // continuation of the message, with the header of the first chunk
+            bytes.push(0xc0 | chunk_stream_id as u8);
+        }
+        if extended {
+            bytes.extend_from_slice(&message.timestamp.to_be_bytes());
+        }
+        bytes.extend_from_slice(chunk);
+    }
+    writer.write_all(&bytes).await
+}
+
+fn command(stream_id: u32, values: &[Amf]) -> Message {
+    let mut payload = Vec::new();
+    for value in values {
+        value.write(&mut payload);
+    }
+    Message {
+        message_type: COMMAND_AMF0,
+        stream_id,
+        timestamp: 0,
+        payload,
+    }
+}
+
+/// Wait for the answer of the command of the transaction, or for the `onStatus` of
+/// transaction 0, returns the values after the transaction id, or an error if the
+/// server answered `_error`.
+async fn wait_result(
+    reader: &mut MessageReader<OwnedReadHalf>,
+    writer: &mut OwnedWriteHalf,
+    transaction: f64,
+) -> io::Result<Vec<Amf>> {
+    loop {
+        let message = reader.read().await?;
+        if answer_ping(writer, &message).await? || message.message_type != COMMAND_AMF0 {
+            continue;
+        }
+        let mut payload = &message.payload[..];
+        let mut values = Vec::new();
+        while let Some(value) = Amf::read(&mut payload) {
+            values.push(value);
+        }
+        match (values.first(), values.get(1)) {
+            (Some(Amf::String(name)), Some(Amf::Number(id)))
+                if *id == transaction
+                    && ["_result", "_error", "onStatus"].contains(&name.as_str()) =>
+            {
+                if name == "_error" {
+                    return Err(io::Error::other(format!(
+                        "rejected by the RTMP server: {}",
+                        status_description(values.get(3))
+                    )));
+                }
+                return Ok(values.split_off(2));
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Code and description of a status object of the server.
+fn status_description(status: Option<&Amf>) -> String {
+    let property = |name| match status.and_then(|status| status.get(name)) {
+        Some(Amf::String(value)) => value.clone(),
+        _ => String::new(),
+    };
+    format!("{} {}", property("code"), property("description"))
+        .trim()
+        .to_owned()
+}
+
+/// Answer a ping request of the server, returns whether the message was one.
+async fn answer_ping(
+    writer: &mut (impl AsyncWrite + Unpin),
+    message: &Message,
+) -> io::Result<bool> {
+    if message.message_type != USER_CONTROL
+        || message.payload.len() < 6
+        || message.payload[..2] != PING_REQUEST.to_be_bytes()
+    {
+        return Ok(false);
+    }
+    let mut payload = PING_RESPONSE.to_be_bytes().to_vec();
+    payload.extend_from_slice(&message.payload[2..6]);
+    let pong = Message {
+        message_type: USER_CONTROL,
+        stream_id: 0,
+        timestamp: message.timestamp,
+        payload,
+    };
+    write_message(writer, CONTROL_CHUNK_STREAM, &pong).await?;
+    Ok(true)
+}
+
+/// Connection publishing a stream, answering the pings of the server from a task
+/// of its own until dropped.
+struct Connection {
+    writer: Arc<Mutex<OwnedWriteHalf>>,
+    /// id of the message stream of the audio
+    stream_id: u32,
+    pongs: JoinHandle<()>,
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.pongs.abort();
+    }
+}
+
+/// Connect to the server, with the simple handshake, and start publishing the stream.
+async fn connect(url: &RtmpUrl) -> io::Result<Connection> {
+    let mut stream = TcpStream::connect((url.host.as_str(), url.port)).await?;
+    stream.set_nodelay(true)?;
+
+    // C0 with the version and C1 with the time, zeros and random bytes
+    let mut c0_c1 = vec![0_u8; 1 + HANDSHAKE_LEN];
+    c0_c1[0] = 3;
+    SystemRandom::new()
+        .fill(&mut c0_c1[9..])
+        .map_err(|_| io::Error::other("failed to generate the RTMP handshake"))?;
+    stream.write_all(&c0_c1).await?;
+    let mut s0_s1 = vec![0_u8; 1 + HANDSHAKE_LEN];
+    stream.read_exact(&mut s0_s1).await?;
+    if s0_s1[0] != 3 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unsupported RTMP version {}", s0_s1[0]),
+        ));
+    }
+    // C2 echoes S1
+    stream.write_all(&s0_s1[1..]).await?;
+    let mut s2 = vec![0_u8; HANDSHAKE_LEN];
+    stream.read_exact(&mut s2).await?;
+
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = MessageReader::new(reader);
+    let set_chunk_size = Message {
+        message_type: SET_CHUNK_SIZE,
+        stream_id: 0,
+        timestamp: 0,
+        payload: (CHUNK_SIZE as u32).to_be_bytes().to_vec(),
+    };
+    write_message(&mut writer, CONTROL_CHUNK_STREAM, &set_chunk_size).await?;
+
+    let flash_version = format!(
+        "FMLE/3.0 (compatible; {}/{})",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION")
+    );
+    let connect = command(
+        0,
+        &[
+            Amf::String("connect".to_owned()),
+            Amf::Number(1.0),
+            amf_object(vec![
+                ("app", Amf::String(url.app.clone())),
+                ("type", Amf::String("nonprivate".to_owned())),
+                ("flashVer", Amf::String(flash_version)),
+                ("tcUrl", Amf::String(url.tc_url())),
+            ]),
+        ],
+    );
+    write_message(&mut writer, COMMAND_CHUNK_STREAM, &connect).await?;
+    wait_result(&mut reader, &mut writer, 1.0).await?;
+
+    let stream_key = Amf::String(url.stream_key.clone());
+    for (transaction, name) in [(2.0, "releaseStream"), (3.0, "FCPublish")] {
+        let command = command(
+            0,
+            &[
+                Amf::String(name.to_owned()),
+                Amf::Number(transaction),
+                Amf::Null,
+                stream_key.clone(),
+            ],
+        );
+        write_message(&mut writer, COMMAND_CHUNK_STREAM, &command).await?;
+    }
+    let create_stream = command(
+        0,
+        &[
+            Amf::String("createStream".to_owned()),
+            Amf::Number(4.0),
+            Amf::Null,
+        ],
+    );
+    write_message(&mut writer, COMMAND_CHUNK_STREAM, &create_stream).await?;
+    let stream_id = match wait_result(&mut reader, &mut writer, 4.0).await?.get(1) {
+        Some(Amf::Number(stream_id)) => *stream_id as u32,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid createStream result from the RTMP server",
+            ))
+        }
+    };
+
+    let publish = command(
+        stream_id,
+        &[
+            Amf::String("publish".to_owned()),
+            Amf::Number(5.0),
+            Amf::Null,
+            stream_key,
+            Amf::String("live".to_owned()),
+        ],
+    );
+    write_message(&mut writer, COMMAND_CHUNK_STREAM, &publish).await?;
+    // the server answers with an onStatus command, of transaction 0
+    let status = wait_result(&mut reader, &mut writer, 0.0).await?;
+    let description = status_description(status.get(1));
+    if !description.starts_with("NetStream.Publish.Start") {
+        return Err(io::Error::other(format!(
+            "rejected by the RTMP server: {}",
+            description
+        )));
+    }
+
+    // answer the pings of the server while publishing, until the connection is closed
+    let writer = Arc::new(Mutex::new(writer));
+    let pong_writer = Arc::clone(&writer);
+    let pongs = tokio::spawn(async move {
+        while let Ok(message) = reader.read().await {
+            if answer_ping(&mut *pong_writer.lock().await, &message)
+                .await
+                .is_err()
+            {
+                return;
+            }
+        }
+    });
+    Ok(Connection {
+        writer,
+        stream_id,
+        pongs,
+    })
+}
+
+/// Encodes the interleaved samples into the data of the FLV audio tags.
+trait AudioTags: Send {
+    /// Data of the tag sent before the audio, e.g. the decoder configuration.
+    fn sequence_header(&self) -> Option<Vec<u8>>;
+
+    /// Encode a block of samples, returns the tags completed with their timestamp
+    /// in milliseconds.
+    fn encode(&mut self, samples: &[f32]) -> Result<Vec<(u32, Vec<u8>)>, String>;
+}
+
+/// Timestamp in milliseconds of the frame at `frames` of the stream.
+fn timestamp(frames: u64, sample_rate: u32) -> u32 {
+    (frames * 1000 / sample_rate as u64) as u32
+}
+
+#[cfg(feature = "aac")]
+struct AacTags {
+    encoder: AacEncoder,
+    sample_rate: u32,
+    frames: u64,
+}
+
+#[cfg(feature = "aac")]
+impl AudioTags for AacTags {
+    fn sequence_header(&self) -> Option<Vec<u8>> {
+        // AAC, with the fixed flags of 44 kHz, 16 bits stereo, then the packet type
+        let mut tag = vec![0xaf, 0];
+        tag.extend_from_slice(self.encoder.audio_specific_config());
+        Some(tag)
+    }
+
+    fn encode(&mut self, samples: &[f32]) -> Result<Vec<(u32, Vec<u8>)>, String> {
+        let mut tags = Vec::new();
+        for access_unit in self.encoder.encode(samples)? {
+            let mut tag = vec![0xaf, 1];
+            tag.extend_from_slice(&access_unit);
+            tags.push((timestamp(self.frames, self.sample_rate), tag));
+            self.frames += aac::FRAME_LEN as u64;
+        }
+        Ok(tags)
+    }
+}
+
+/// Length, frames of audio and sample rate of the MPEG audio layer III frame
+/// starting with the header.
+#[cfg(feature = "mp3")]
+fn mp3_frame(header: &[u8]) -> Option<(usize, u64, u32)> {
+    const MPEG1_BITRATES: [u32; 15] = [
+        0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+    ];
+    const MPEG2_BITRATES: [u32; 15] =
+        [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160];
+    const SAMPLE_RATES: [u32; 3] = [44100, 48000, 32000];
+
+    if header.len() < 4 || header[0] != 0xff || header[1] & 0xe0 != 0xe0 {
+        return None;
+    }
+    // version: 3 for MPEG-1, 2 for MPEG-2 and 0 for MPEG-2.5, and layer 1 for layer III
+    let version = (header[1] >> 3) & 0x03;
+    if version == 1 || (header[1] >> 1) & 0x03 != 1 {
+        return None;
+    }
+    let bitrate_index = (header[2] >> 4) as usize;
+    let sample_rate_index = ((header[2] >> 2) & 0x03) as usize;
+    if bitrate_index == 0 || bitrate_index == 15 || sample_rate_index == 3 {
+        return None;
+    }
+    let padding = ((header[2] >> 1) & 0x01) as usize;
+    let (bitrate, sample_rate, frames) = match version {
+        3 => (
+            MPEG1_BITRATES[bitrate_index],
+            SAMPLE_RATES[sample_rate_index],
+            1152,
+        ),
+        2 => (
+            MPEG2_BITRATES[bitrate_index],
+            SAMPLE_RATES[sample_rate_index] / 2,
+            576,
+        ),
+        _ => (
+            MPEG2_BITRATES[bitrate_index],
+            SAMPLE_RATES[sample_rate_index] / 4,
+            576,
+        ),
+    };
+    let len = (frames / 8) as usize * bitrate as usize * 1000 / sample_rate as usize + padding;
+    Some((len, frames, sample_rate))
+}
+
+#[cfg(feature = "mp3")]
+struct Mp3Tags {
+    encoder: Mp3Encoder,
+    /// encoded bytes not split in frames yet
+    bytes: Vec<u8>,
+    frames: u64,
+}
+
+#[cfg(feature = "mp3")]
+impl AudioTags for Mp3Tags {
+    fn sequence_header(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    fn encode(&mut self, samples: &[f32]) -> Result<Vec<(u32, Vec<u8>)>, String> {
+        self.encoder.encode(samples)?;
+        self.bytes.extend_from_slice(&self.encoder.take_bytes());
+
+        // a tag for each whole frame
+        let mut tags = Vec::new();
+        let mut offset = 0;
+        while let Some((len, frames, sample_rate)) = mp3_frame(&self.bytes[offset..]) {
+            if self.bytes.len() - offset < len {
+                break;
+            }
+            // MP3, with the flags of 44 kHz, 16 bits, and of mono or stereo
+            let stereo = (self.bytes[offset + 3] >> 6) != 3;
+            let mut tag = vec![0x2e | stereo as u8];
+            tag.extend_from_slice(&self.bytes[offset..offset + len]);
+            tags.push((timestamp(self.frames, sample_rate), tag));
+            self.frames += frames;
+            offset += len;
+        }
+        self.bytes.drain(..offset);
+        Ok(tags)
+    }
+}
+
+fn audio_tags(
+    codec: RtmpCodec,
+    bitrate: i32,
+    channels: u16,
+    sample_rate: u32,
+) -> Result<Box<dyn AudioTags>, String> {
+    match codec {
+        #[cfg(feature = "aac")]
+        RtmpCodec::Aac => Ok(Box::new(AacTags {
+            encoder: AacEncoder::new(channels, sample_rate, bitrate)?,
+            sample_rate,
+            frames: 0,
+        })),
+        #[cfg(feature = "mp3")]
+        RtmpCodec::Mp3 => Ok(Box::new(Mp3Tags {
+            encoder: Mp3Encoder::new(channels, sample_rate, bitrate)?,
+            bytes: Vec::new(),
+            frames: 0,
+        })),
+        #[allow(unreachable_patterns)]
+        _ => Err(format!("no encoder of {:?} in this build", codec)),
+    }
+}
+
+/// Message of the stream metadata, `@setDataFrame` of `onMetaData`.
+fn metadata(
+    stream_id: u32,
+    codec: RtmpCodec,
+    bitrate: i32,
+    channels: u16,
+    sample_rate: u32,
+) -> Message {
+    let properties = vec![
+        ("audiocodecid", Amf::Number(codec.codec_id())),
+        ("audiodatarate", Amf::Number(bitrate as f64 / 1000.0)),
+        ("audiosamplerate", Amf::Number(sample_rate as f64)),
+        ("audiosamplesize", Amf::Number(16.0)),
+        ("stereo", Amf::Boolean(channels > 1)),
+        (
+            "encoder",
+            Amf::String(format!(
+                "{}/{}",
+                env!("CARGO_PKG_NAME"),
+                env!("CARGO_PKG_VERSION")
+            )),
+        ),
+    ];
+    let mut message = command(
+        stream_id,
+        &[
+            Amf::String("@setDataFrame".to_owned()),
+            Amf::String("onMetaData".to_owned()),
+            Amf::Array(
+                properties
+                    .into_iter()
+                    .map(|(name, value)| (name.to_owned(), value))
+                    .collect(),
+            ),
+        ],
+    );
+    message.message_type = DATA_AMF0;
+    message
+}
+
+/// Publish the interleaved samples received from a broadcast to the RTMP server,
+/// reconnecting when the connection is lost, until the sender goes away. The failures
+/// are reported to the supervisor.
+pub async fn run_publisher(
+    url: RtmpUrl,
+    codec: RtmpCodec,
+    bitrate: i32,
+    channels: u16,
+    sample_rate: u32,
+    mut samples: mpsc::Receiver<Arc<Vec<f32>>>,
+    supervisor: Supervisor,
+) {
+    let endpoint = format!("{}:{}/{}", url.host, url.port, url.app);
+    loop {
+        let connection = match connect(&url).await {
+            Ok(connection) => connection,
+            Err(err) => {
+                supervisor.report(Error::Output(format!(
+                    "failed to publish to RTMP server {}: {}",
+                    endpoint, err
+                )));
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                continue;
+            }
+        };
+
+        let mut tags = match audio_tags(codec, bitrate, channels, sample_rate) {
+            Ok(tags) => tags,
+            Err(err) => {
+                supervisor.report(Error::Output(format!("stopped the RTMP stream: {}", err)));
+                return;
+            }
+        };
+
+        let stream_id = connection.stream_id;
+        let audio = |timestamp, payload| Message {
+            message_type: AUDIO,
+            stream_id,
+            timestamp,
+            payload,
+        };
+        let mut result = write_message(
+            &mut *connection.writer.lock().await,
+            COMMAND_CHUNK_STREAM,
+            &metadata(stream_id, codec, bitrate, channels, sample_rate),
+        )
+        .await;
+        if let (Ok(()), Some(header)) = (&result, tags.sequence_header()) {
+            result = write_message(
+                &mut *connection.writer.lock().await,
+                AUDIO_CHUNK_STREAM,
+                &audio(0, header),
+            )
+            .await;
+        }
+
+        // discard the samples queued while disconnected
+        while samples.try_recv().is_ok() {}
+
+        while result.is_ok() {
+            let samples = match samples.recv().await {
+                Some(samples) => samples,
+                None => return,
+            };
+            let encoded = match tags.encode(&samples) {
+                Ok(encoded) => encoded,
+                Err(err) => {
+                    supervisor.report(Error::Output(format!("stopped the RTMP stream: {}", err)));
+                    return;
+                }
+            };
+            let mut writer = connection.writer.lock().await;
+            for (timestamp, tag) in encoded {
+                result =
+                    write_message(&mut *writer, AUDIO_CHUNK_STREAM, &audio(timestamp, tag)).await;
+                if result.is_err() {
+                    break;
+                }
+            }
+        }
+        if let Err(err) = result {
+            supervisor.report(Error::Output(format!(
+                "connection to RTMP server {} lost: {}",
+                endpoint, err
+            )));
+        }
+
+        drop(connection);
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            tokio::time::timeout(Duration::from_secs(5), future)
+                .await
+                .unwrap()
+        })
+    }
+
+    fn values(message: &Message) -> Vec<Amf> {
+        let mut payload = &message.payload[..];
+        let mut values = Vec::new();
+        while let Some(value) = Amf::read(&mut payload) {
+            values.push(value);
+        }
+        assert!(payload.is_empty());
+        values
+    }
+
+    fn string(string: &str) -> Amf {
+        Amf::String(string.to_owned())
+    }
+
+    #[test]
+    fn parse_urls() {
+        let url = RtmpUrl::parse("rtmp://example.com/live/key").unwrap();
+        assert_eq!(
+            (
+                url.host.as_str(),
+                url.port,
+                url.app.as_str(),
+                url.stream_key.as_str()
+            ),
+            ("example.com", DEFAULT_PORT, "live", "key")
+        );
+        assert_eq!(url.tc_url(), "rtmp://example.com:1935/live");
+
+        // the stream key may have slashes
+        let url = RtmpUrl::parse("rtmp://[::1]:1936/app/a/b?token=1").unwrap();
+        assert_eq!((url.host.as_str(), url.port), ("[::1]", 1936));
+        assert_eq!(url.stream_key, "a/b?token=1");
+
+        for url in [
+            "rtmps://example.com/live/key",
+            "rtmp://example.com/live",
+            "rtmp://example.com//key",
+            "rtmp://example.com:port/live/key",
+            "rtmp:///live/key",
+        ]
+        .iter()
+        {
+            assert!(RtmpUrl::parse(url).is_err(), "{}", url);
+        }
+    }
+
+    #[test]
+    fn amf_values() {
+        let mut bytes = Vec::new();
+        Amf::Number(1.0).write(&mut bytes);
+        string("ab").write(&mut bytes);
+        assert_eq!(
+            bytes,
+            [0x00, 0x3f, 0xf0, 0, 0, 0, 0, 0, 0, 0x02, 0, 2, b'a', b'b']
+        );
+
+        let values = [
+            Amf::Boolean(true),
+            Amf::Null,
+            amf_object(vec![
+                ("code", string("NetStream.Publish.Start")),
+                ("nested", amf_object(vec![("n", Amf::Number(-2.5))])),
+            ]),
+            Amf::Array(vec![("stereo".to_owned(), Amf::Boolean(false))]),
+        ];
+        let mut bytes = Vec::new();
+        for value in &values {
+            value.write(&mut bytes);
+        }
+        let mut bytes = &bytes[..];
+        for value in &values {
+            assert_eq!(Amf::read(&mut bytes).as_ref(), Some(value));
+        }
+        assert_eq!(Amf::read(&mut bytes), None);
+        // undefined is read as null, a truncated value is not read
+        assert_eq!(Amf::read(&mut &[0x06][..]), Some(Amf::Null));
+        assert_eq!(Amf::read(&mut &[0x00, 0x3f][..]), None);
+    }
+
+    #[test]
+    fn status_descriptions() {
+        let status = amf_object(vec![
+            ("level", string("error")),
+            ("code", string("NetStream.Publish.BadName")),
+            ("description", string("stream already publishing")),
+        ]);
+        assert_eq!(
+            status_description(Some(&status)),
+            "NetStream.Publish.BadName stream already publishing"
+        );
+        assert_eq!(status_description(None), "");
+    }
+
+    #[test]
+    fn read_compressed_chunk_headers() {
+        #[rustfmt::skip]
+        let bytes = [
+            // format 0 on chunk stream 3: timestamp 10, 3 bytes, command, stream 1
+            0x03, 0, 0, 10, 0, 0, 3, COMMAND_AMF0, 1, 0, 0, 0, b'a', b'b', b'c',
+            // format 2, delta of 5
+            0x83, 0, 0, 5, b'd', b'e', b'f',
+            // format 3, the same delta again
+            0xc3, b'g', b'h', b'i',
+            // format 1, delta of 1, 2 bytes of audio
+            0x43, 0, 0, 1, 0, 0, 2, AUDIO, b'x', b'y',
+            // format 0 on chunk stream 74, in 2 bytes, with an extended timestamp
+            0x00, 10, 0xff, 0xff, 0xff, 0, 0, 1, COMMAND_AMF0, 0, 0, 0, 0, 1, 0, 0, 0, b'z',
+            // a chunk size of 2, then a message of 3 bytes in 2 chunks
+            0x02, 0, 0, 0, 0, 0, 4, SET_CHUNK_SIZE, 0, 0, 0, 0, 0, 0, 0, 2,
+            0x04, 0, 0, 0, 0, 0, 3, AUDIO, 1, 0, 0, 0, b'1', b'2',
+            0xc4, b'3',
+        ];
+        block_on(async {
+            let mut reader = MessageReader::new(&bytes[..]);
+            let mut expected = vec![
+                (COMMAND_AMF0, 1, 10, &b"abc"[..]),
+                (COMMAND_AMF0, 1, 15, b"def"),
+                (COMMAND_AMF0, 1, 20, b"ghi"),
+                (AUDIO, 1, 21, b"xy"),
+                (COMMAND_AMF0, 0, 0x0100_0000, b"z"),
+                (AUDIO, 1, 0, b"123"),
+            ]
+            .into_iter();
+            while let Ok(message) = reader.read().await {
+                let (message_type, stream_id, timestamp, payload) = expected.next().unwrap();
+                assert_eq!(message.message_type, message_type);
+                assert_eq!(message.stream_id, stream_id);
+                assert_eq!(message.timestamp, timestamp);
+                assert_eq!(message.payload, payload);
+            }
+            assert!(expected.next().is_none());
+        });
+    }
+
+    #[test]
+    fn rejects_large_messages() {
+        let mut bytes = vec![0x03, 0, 0, 0];
+        bytes.extend_from_slice(&(MAX_MESSAGE_LEN as u32 + 1).to_be_bytes()[1..]);
+        bytes.extend_from_slice(&[AUDIO, 0, 0, 0, 0]);
+        block_on(async {
+            let err = MessageReader::new(&bytes[..]).read().await.err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        });
+    }
+
+    #[test]
+    fn write_and_read_chunks() {
+        let set_chunk_size = Message {
+            message_type: SET_CHUNK_SIZE,
+            stream_id: 0,
+            timestamp: 0,
+            payload: (CHUNK_SIZE as u32).to_be_bytes().to_vec(),
+        };
+        // in 3 chunks, with the extended timestamp in each one
+        let audio = Message {
+            message_type: AUDIO,
+            stream_id: 1,
+            timestamp: 0x0123_4567,
+            payload: (0..2 * CHUNK_SIZE + 10).map(|i| i as u8).collect(),
+        };
+        block_on(async {
+            let mut bytes = Vec::new();
+            write_message(&mut bytes, CONTROL_CHUNK_STREAM, &set_chunk_size)
+                .await
+                .unwrap();
+            write_message(&mut bytes, AUDIO_CHUNK_STREAM, &audio)
+                .await
+                .unwrap();
+            assert_eq!(bytes.len(), 16 + 12 + 4 + 2 * (1 + 4) + audio.payload.len());
+
+            let mut reader = MessageReader::new(&bytes[..]);
+            let message = reader.read().await.unwrap();
+            assert_eq!(reader.chunk_size, CHUNK_SIZE);
+            assert_eq!(message.message_type, AUDIO);
+            assert_eq!(message.stream_id, 1);
+            assert_eq!(message.timestamp, audio.timestamp);
+            assert_eq!(message.payload, audio.payload);
+        });
+    }
+
+    #[test]
+    fn answer_pings() {
+        let ping = Message {
+            message_type: USER_CONTROL,
+            stream_id: 0,
+            timestamp: 7,
+            payload: vec![0, 6, 1, 2, 3, 4],
+        };
+        block_on(async {
+            let mut bytes = Vec::new();
+            assert!(answer_ping(&mut bytes, &ping).await.unwrap());
+            let pong = MessageReader::new(&bytes[..]).read().await.unwrap();
+            assert_eq!(pong.message_type, USER_CONTROL);
+            assert_eq!(pong.payload, [0, 7, 1, 2, 3, 4]);
+
+            let other = command(0, &[string("onStatus")]);
+            assert!(!answer_ping(&mut bytes, &other).await.unwrap());
+        });
+    }
+
+    #[test]
+    fn metadata_values() {
+        let message = metadata(1, RtmpCodec::Aac, 128000, 2, 48000);
+        assert_eq!(message.message_type, DATA_AMF0);
+        let values = values(&message);
+        assert_eq!(values[..2], [string("@setDataFrame"), string("onMetaData")]);
+        assert_eq!(values[2].get("audiocodecid"), Some(&Amf::Number(10.0)));
+        assert_eq!(values[2].get("audiodatarate"), Some(&Amf::Number(128.0)));
+        assert_eq!(values[2].get("stereo"), Some(&Amf::Boolean(true)));
+    }
+
+    #[test]
+    fn timestamps_of_frames() {
+        assert_eq!(timestamp(0, 48000), 0);
+        assert_eq!(timestamp(1024, 48000), 21);
+        assert_eq!(
+            timestamp(48000 * 3600 * 24 * 50, 48000),
+            4_320_000_000_u64 as u32
+        );
+    }
+
+    #[cfg(feature = "mp3")]
+    #[test]
+    fn mp3_frame_headers() {
+        // MPEG-1 layer III, 128 kbit/s, 44.1 kHz, with padding
+        assert_eq!(
+            mp3_frame(&[0xff, 0xfb, 0x92, 0x64]),
+            Some((418, 1152, 44100))
+        );
+        // MPEG-2 layer III, 64 kbit/s, 24 kHz
+        assert_eq!(
+            mp3_frame(&[0xff, 0xf3, 0x84, 0xc4]),
+            Some((192, 576, 24000))
+        );
+        // free format, and layer II
+        assert_eq!(mp3_frame(&[0xff, 0xfb, 0x04, 0x64]), None);
+        assert_eq!(mp3_frame(&[0xff, 0xfd, 0x90, 0x64]), None);
+    }
+
+    /// A server accepting to publish as stream 1, that pings the client after it.
+    async fn serve_publish(listener: TcpListener, answer: Amf) -> (RtmpUrl, Vec<Amf>, bool) {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut c0_c1 = vec![0; 1 + HANDSHAKE_LEN];
+        stream.read_exact(&mut c0_c1).await.unwrap();
+        assert_eq!(c0_c1[0], 3);
+        let mut s0_s1_s2 = vec![3];
+        s0_s1_s2.extend_from_slice(&[0; HANDSHAKE_LEN]);
+        s0_s1_s2.extend_from_slice(&c0_c1[1..]);
+        stream.write_all(&s0_s1_s2).await.unwrap();
+        let mut c2 = vec![0; HANDSHAKE_LEN];
+        stream.read_exact(&mut c2).await.unwrap();
+
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = MessageReader::new(reader);
+        let connect = values(&reader.read().await.unwrap());
+        assert_eq!(reader.chunk_size, CHUNK_SIZE);
+        assert_eq!(connect[..2], [string("connect"), Amf::Number(1.0)]);
+        let app = match (connect[2].get("app"), connect[2].get("tcUrl")) {
+            (Some(Amf::String(app)), Some(Amf::String(tc_url))) => {
+                assert!(tc_url.ends_with(app.as_str()));
+                app.clone()
+            }
+            _ => panic!("connect without app"),
+        };
+        let result = command(
+            0,
+            &[string("_result"), Amf::Number(1.0), Amf::Null, Amf::Null],
+        );
+        write_message(&mut writer, COMMAND_CHUNK_STREAM, &result)
+            .await
+            .unwrap();
+
+        let mut names = Vec::new();
+        let publish = loop {
+            let message = reader.read().await.unwrap();
+            let values = values(&message);
+            names.push(values[0].clone());
+            match &values[0] {
+                Amf::String(name) if name == "createStream" => {
+                    let result = command(
+                        0,
+                        &[
+                            string("_result"),
+                            values[1].clone(),
+                            Amf::Null,
+                            Amf::Number(1.0),
+                        ],
+                    );
+                    write_message(&mut writer, COMMAND_CHUNK_STREAM, &result)
+                        .await
+                        .unwrap();
+                }
+                Amf::String(name) if name == "publish" => {
+                    assert_eq!(message.stream_id, 1);
+                    break values;
+                }
+                _ => (),
+            }
+        };
+        let on_status = command(
+            1,
+            &[string("onStatus"), Amf::Number(0.0), Amf::Null, answer],
+        );
+        write_message(&mut writer, COMMAND_CHUNK_STREAM, &on_status)
+            .await
+            .unwrap();
+
+        let ping = Message {
+            message_type: USER_CONTROL,
+            stream_id: 0,
+            timestamp: 0,
+            payload: vec![0, 6, 0, 0, 0, 42],
+        };
+        // a rejected client may have closed the connection already
+        let ponged = write_message(&mut writer, CONTROL_CHUNK_STREAM, &ping)
+            .await
+            .is_ok()
+            && match reader.read().await {
+                Ok(pong) => pong.payload == [0, 7, 0, 0, 0, 42],
+                Err(_) => false,
+            };
+
+        let url = RtmpUrl {
+            host: String::new(),
+            port: 0,
+            app,
+            stream_key: match &publish[3] {
+                Amf::String(key) => key.clone(),
+                _ => panic!("publish without stream key"),
+            },
+        };
+        names.truncate(3);
+        (url, names, ponged)
+    }
+
+    #[test]
+    fn publish() {
+        block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let start = amf_object(vec![("code", string("NetStream.Publish.Start"))]);
+            let server = tokio::spawn(serve_publish(listener, start));
+
+            let url = RtmpUrl::parse(&format!("rtmp://127.0.0.1:{}/live/key", port)).unwrap();
+            let connection = connect(&url).await.unwrap();
+            assert_eq!(connection.stream_id, 1);
+            let (published, names, ponged) = server.await.unwrap();
+            assert_eq!(
+                (published.app.as_str(), published.stream_key.as_str()),
+                ("live", "key")
+            );
+            assert_eq!(
+                names,
+                [
+                    string("releaseStream"),
+                    string("FCPublish"),
+                    string("createStream")
+                ]
+            );
+            assert!(ponged);
+        });
+    }
+
+    #[test]
+    fn publish_rejected() {
+        block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let bad_name = amf_object(vec![
+                ("code", string("NetStream.Publish.BadName")),
+                ("description", string("already publishing")),
+            ]);
+            let server = tokio::spawn(serve_publish(listener, bad_name));
+
+            let url = RtmpUrl::parse(&format!("rtmp://127.0.0.1:{}/live/key", port)).unwrap();
+            let err = connect(&url).await.err().unwrap();
+            assert_eq!(
+                err.to_string(),
+                "rejected by the RTMP server: NetStream.Publish.BadName already publishing"
+            );
+            let (_, _, ponged) = server.await.unwrap();
+            assert!(!ponged);
+        });
+    }
+}
@@ -0,0 +1,456 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! RTP sender of the live capture (RFC 3550), as big endian linear PCM (RFC 3190)
+//! or as Opus (RFC 7587), to a unicast or multicast destination, with the SDP
+//! description (RFC 4566) the receivers open, e.g. `ffplay -protocol_whitelist file,udp,rtp stream.sdp`.
+
+#[cfg(feature = "opus")]
+use crate::ogg_opus::{self, OpusPacketEncoder};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::fmt::Write;
+use std::io;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::net::UdpSocket;
+
+/// Duration of the audio of each PCM packet, in milliseconds, 1 ms as in AES67.
+pub const DEFAULT_PTIME: f64 = 1.0;
+/// Time to live of the multicast packets, the routers they can cross.
+pub const DEFAULT_TTL: u32 = 16;
+/// Dynamic payload type of the stream.
+const PAYLOAD_TYPE: u8 = 96;
+const HEADER_LEN: usize = 12;
+/// Largest payload of a packet, to fit in the MTU of an Ethernet network.
+const MAX_PAYLOAD_LEN: usize = 1440;
+
+/// Encoding of the RTP payload.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RtpFormat {
+    L16,
+    L24,
+    Opus,
+}
+
+impl RtpFormat {
+    pub fn parse(format: &str) -> Result<Self, String> {
+        match format.to_ascii_lowercase().as_str() {
+            "l16" => Ok(RtpFormat::L16),
+            "l24" => Ok(RtpFormat::L24),
+            "opus" => Ok(RtpFormat::Opus),
+            _ => Err(format!(
+                "invalid RTP format '{}', expected l16, l24 or opus",
+                format
+            )),
+        }
+    }
+
+    /// Bytes of each PCM sample.
+    fn sample_len(self) -> usize {
+        match self {
+            RtpFormat::L16 => 2,
+            RtpFormat::L24 => 3,
+            RtpFormat::Opus => 0,
+        }
+    }
+}
+
+/// Sends the interleaved samples as RTP packets.
+pub struct RtpSender {
+    socket: UdpSocket,
+    destination: SocketAddr,
+    ttl: Option<u32>,
+    format: RtpFormat,
+    /// channels of the payload, all the input channels for PCM
+    channels: usize,
+    input_channels: usize,
+    sample_rate: u32,
+    /// frames of each PCM packet
+    frames_per_packet: usize,
+    ptime: f64,
+    ssrc: u32,
+    sequence: u16,
+    timestamp: u32,
+    /// PCM samples not sent yet
+    pending_samples: Vec<f32>,
+    #[cfg(feature = "opus")]
+    opus: Option<OpusPacketEncoder>,
+}
+
+impl RtpSender {
+    /// Sender to `destination`, e.g. `239.69.1.1:5004`, of packets of `ptime` milliseconds
+    /// for PCM, Opus packets are always of 20 ms.
+    pub fn new(
+        destination: &str,
+        format: RtpFormat,
+        channels: u16,
+        sample_rate: u32,
+        ptime: f64,
+        ttl: u32,
+    ) -> Result<Self, String> {
+        #[cfg(not(feature = "opus"))]
+        if format == RtpFormat::Opus {
+            return Err(String::from("RTP Opus requires the 'opus' feature"));
+        }
+        let invalid_destination =
+            |err: io::Error| format!("invalid RTP destination '{}': {}", destination, err);
+        let address = destination
+            .to_socket_addrs()
+            .map_err(invalid_destination)?
+            .next()
+            .ok_or_else(|| format!("invalid RTP destination '{}'", destination))?;
+        let bind = if address.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket = std::net::UdpSocket::bind(bind).map_err(invalid_destination)?;
+        let ttl = if address.ip().is_multicast() && address.is_ipv4() {
+            socket
+                .set_multicast_ttl_v4(ttl)
+                .map_err(invalid_destination)?;
+            Some(ttl)
+        } else {
+            None
+        };
+        socket.connect(address).map_err(invalid_destination)?;
+        let socket = socket
+            .set_nonblocking(true)
+            .and_then(|_| UdpSocket::from_std(socket))
+            .map_err(invalid_destination)?;
+
+        let input_channels = channels as usize;
+        let (channels, frames_per_packet, ptime) = match format {
+            RtpFormat::Opus => {
+                let opus_channels = input_channels.min(2);
+                (opus_channels, 0, 20.0)
+            }
+            RtpFormat::L16 | RtpFormat::L24 => {
+                let frames_per_packet = (sample_rate as f64 * ptime / 1000.0).round().max(1.0);
+                let payload_len = frames_per_packet as usize * input_channels * format.sample_len();
+                if payload_len > MAX_PAYLOAD_LEN {
+                    return Err(format!(
+                        "RTP packets of {} ms of {} channels do not fit in a network packet, use a shorter --rtp-ptime",
+                        ptime, input_channels
+                    ));
+                }
+                (input_channels, frames_per_packet as usize, ptime)
+            }
+        };
+
+        let random = SystemRandom::new();
+        let mut bytes = [0; 10];
+        random
+            .fill(&mut bytes)
+            .map_err(|_| String::from("failed to get random bytes"))?;
+
+        Ok(RtpSender {
+            socket,
+            destination: address,
+            ttl,
+            format,
+            channels,
+            input_channels,
+            sample_rate,
+            frames_per_packet,
+            ptime,
+            ssrc: u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            sequence: u16::from_be_bytes([bytes[4], bytes[5]]),
+            timestamp: u32::from_be_bytes([bytes[6], bytes[7], bytes[8], bytes[9]]),
+            pending_samples: Vec::new(),
+            #[cfg(feature = "opus")]
+            opus: match format {
+                RtpFormat::Opus => Some(OpusPacketEncoder::new(
+                    input_channels as u16,
+                    sample_rate,
+                    ogg_opus::DEFAULT_BITRATE,
+                )?),
+                _ => None,
+            },
+        })
+    }
+
+    /// The SDP description of the stream.
+    pub fn sdp(&self) -> String {
+        let session = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+        let address_type = |address: IpAddr| if address.is_ipv4() { "IP4" } else { "IP6" };
+        let source = self
+            .socket
+            .local_addr()
+            .map_or(IpAddr::from([0, 0, 0, 0]), |address| address.ip());
+        let destination = self.destination.ip();
+
+        let mut sdp = String::new();
+        write!(sdp, "v=0\r\n").unwrap();
+        write!(
+            sdp,
+            "o=- {} {} IN {} {}\r\n",
+            session,
+            session,
+            address_type(source),
+            source
+        )
+        .unwrap();
+        write!(sdp, "s={}\r\n", env!("CARGO_PKG_NAME")).unwrap();
+        match self.ttl {
+            Some(ttl) => write!(sdp, "c=IN IP4 {}/{}\r\n", destination, ttl),
+            None => write!(
+                sdp,
+                "c=IN {} {}\r\n",
+                address_type(destination),
+                destination
+            ),
+        }
+        .unwrap();
+        write!(sdp, "t=0 0\r\n").unwrap();
+        write!(
+            sdp,
+            "m=audio {} RTP/AVP {}\r\n",
+            self.destination.port(),
+            PAYLOAD_TYPE
+        )
+        .unwrap();
+        match self.format {
+            RtpFormat::L16 | RtpFormat::L24 => write!(
+                sdp,
+                "a=rtpmap:{} L{}/{}/{}\r\n",
+                PAYLOAD_TYPE,
+                self.format.sample_len() * 8,
+                self.sample_rate,
+                self.channels
+            ),
+            // always 2 channels at 48 kHz in the SDP of Opus, stereo or not in the format params
+            RtpFormat::Opus => write!(
+                sdp,
+                "a=rtpmap:{} opus/48000/2\r\na=fmtp:{} stereo={}; sprop-stereo={}\r\n",
+                PAYLOAD_TYPE,
+                PAYLOAD_TYPE,
+                (self.channels == 2) as u8,
+                (self.channels == 2) as u8
+            ),
+        }
+        .unwrap();
+        write!(sdp, "a=ptime:{}\r\n", self.ptime).unwrap();
+        write!(sdp, "a=sendonly\r\n").unwrap();
+        sdp
+    }
+
+    /// Send the packets of a block of interleaved samples,
+    /// the samples left are sent with the next block.
+    pub async fn send(&mut self, samples: &[f32]) -> io::Result<()> {
+        match self.format {
+            RtpFormat::L16 | RtpFormat::L24 => {
+                self.pending_samples.extend_from_slice(samples);
+                let packet_len = self.frames_per_packet * self.input_channels;
+                let mut offset = 0;
+                while self.pending_samples.len() - offset >= packet_len {
+                    let mut payload = Vec::with_capacity(packet_len * self.format.sample_len());
+                    for &sample in &self.pending_samples[offset..offset + packet_len] {
+                        let sample = sample.clamp(-1.0, 1.0);
+                        if self.format == RtpFormat::L16 {
+                            payload.extend_from_slice(&((sample * 32767.0) as i16).to_be_bytes());
+                        } else {
+                            payload.extend_from_slice(
+                                &((sample * 8388607.0) as i32).to_be_bytes()[1..],
+                            );
+                        }
+                    }
+                    self.send_packet(&payload, self.frames_per_packet as u32)
+                        .await?;
+                    offset += packet_len;
+                }
+                self.pending_samples.drain(..offset);
+            }
+            #[cfg(feature = "opus")]
+            RtpFormat::Opus => {
+                let packets = self
+                    .opus
+                    .as_mut()
+                    .expect("Opus encoder")
+                    .encode(samples)
+                    .map_err(io::Error::other)?;
+                for packet in packets {
+                    self.send_packet(&packet, ogg_opus::OPUS_FRAME_LEN as u32)
+                        .await?;
+                }
+            }
+            #[cfg(not(feature = "opus"))]
+            RtpFormat::Opus => {}
+        }
+        Ok(())
+    }
+
+    /// Send a packet, with the timestamp advanced by its `frames` after it.
+    async fn send_packet(&mut self, payload: &[u8], frames: u32) -> io::Result<()> {
+        let mut packet = Vec::with_capacity(HEADER_LEN + payload.len());
+        // version 2, without padding, extension nor contributing sources
+        packet.push(0x80);
+        packet.push(PAYLOAD_TYPE);
+        packet.extend_from_slice(&self.sequence.to_be_bytes());
+        packet.extend_from_slice(&self.timestamp.to_be_bytes());
+        packet.extend_from_slice(&self.ssrc.to_be_bytes());
+        packet.extend_from_slice(payload);
+        self.sequence = self.sequence.wrapping_add(1);
+        self.timestamp = self.timestamp.wrapping_add(frames);
+        match self.socket.send(&packet).await {
+            // nobody listening to a unicast destination yet
+            Err(err) if err.kind() == io::ErrorKind::ConnectionRefused => Ok(()),
+            result => result.map(|_| ()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            tokio::time::timeout(Duration::from_secs(5), future)
+                .await
+                .unwrap()
+        })
+    }
+
+    /// A receiver on the loopback interface and a sender to it.
+    async fn sender(format: RtpFormat, channels: u16, ptime: f64) -> (RtpSender, UdpSocket) {
+        let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let destination = receiver.local_addr().unwrap().to_string();
+        let sender =
+            RtpSender::new(&destination, format, channels, 48000, ptime, DEFAULT_TTL).unwrap();
+        (sender, receiver)
+    }
+
+    async fn receive(receiver: &UdpSocket) -> Vec<u8> {
+        let mut packet = vec![0; 2048];
+        let len = receiver.recv(&mut packet).await.unwrap();
+        packet.truncate(len);
+        packet
+    }
+
+    fn sequence(packet: &[u8]) -> u16 {
+        u16::from_be_bytes([packet[2], packet[3]])
+    }
+
+    fn timestamp(packet: &[u8]) -> u32 {
+        u32::from_be_bytes([packet[4], packet[5], packet[6], packet[7]])
+    }
+
+    fn ssrc(packet: &[u8]) -> u32 {
+        u32::from_be_bytes([packet[8], packet[9], packet[10], packet[11]])
+    }
+
+    #[test]
+    fn parse_formats() {
+        assert_eq!(RtpFormat::parse("L16"), Ok(RtpFormat::L16));
+        assert_eq!(RtpFormat::parse("l24"), Ok(RtpFormat::L24));
+        assert_eq!(RtpFormat::parse("Opus"), Ok(RtpFormat::Opus));
+        assert!(RtpFormat::parse("pcm").is_err());
+    }
+
+    #[test]
+    fn l16_packets() {
+        block_on(async {
+            // 1 ms at 48 kHz, 48 frames of 2 channels per packet
+            let (mut sender, receiver) = sender(RtpFormat::L16, 2, 1.0).await;
+            let mut samples = vec![0.0; 2 * 100];
+            samples[0] = 0.5;
+            samples[1] = -1.0;
+            samples[2 * 48] = 2.0;
+            sender.send(&samples).await.unwrap();
+
+            let first = receive(&receiver).await;
+            assert_eq!(first.len(), HEADER_LEN + 48 * 2 * 2);
+            assert_eq!(first[0], 0x80);
+            assert_eq!(first[1], PAYLOAD_TYPE);
+            assert_eq!(first[12..14], 16383_i16.to_be_bytes());
+            assert_eq!(first[14..16], (-32767_i16).to_be_bytes());
+
+            let second = receive(&receiver).await;
+            assert_eq!(sequence(&second), sequence(&first).wrapping_add(1));
+            assert_eq!(timestamp(&second), timestamp(&first).wrapping_add(48));
+            assert_eq!(ssrc(&second), ssrc(&first));
+            // clamped to full scale
+            assert_eq!(second[12..14], 32767_i16.to_be_bytes());
+
+            // the 4 frames left go out with the next block
+            sender.send(&vec![0.0; 2 * 44]).await.unwrap();
+            let third = receive(&receiver).await;
+            assert_eq!(timestamp(&third), timestamp(&first).wrapping_add(96));
+        });
+    }
+
+    #[test]
+    fn l24_packets() {
+        block_on(async {
+            let (mut sender, receiver) = sender(RtpFormat::L24, 1, 0.5).await;
+            let mut samples = vec![0.0; 24];
+            samples[0] = -0.5;
+            sender.send(&samples).await.unwrap();
+
+            let packet = receive(&receiver).await;
+            assert_eq!(packet.len(), HEADER_LEN + 24 * 3);
+            assert_eq!(packet[12..15], (-4194303_i32).to_be_bytes()[1..]);
+        });
+    }
+
+    #[test]
+    fn rejects_packets_beyond_the_mtu() {
+        block_on(async {
+            // 10 ms of 2 channels in 16 bits is 1920 bytes
+            assert!(RtpSender::new(
+                "127.0.0.1:5004",
+                RtpFormat::L16,
+                2,
+                48000,
+                10.0,
+                DEFAULT_TTL
+            )
+            .is_err());
+            assert!(RtpSender::new("invalid", RtpFormat::L16, 2, 48000, 1.0, DEFAULT_TTL).is_err());
+        });
+    }
+
+    #[test]
+    fn unicast_sdp() {
+        block_on(async {
+            let (sender, receiver) = sender(RtpFormat::L24, 2, 1.0).await;
+            let sdp = sender.sdp();
+            let port = receiver.local_addr().unwrap().port();
+            assert!(sdp.starts_with("v=0\r\no=- "));
+            assert!(sdp.contains("c=IN IP4 127.0.0.1\r\n"));
+            assert!(sdp.contains(&format!("m=audio {} RTP/AVP 96\r\n", port)));
+            assert!(sdp.contains("a=rtpmap:96 L24/48000/2\r\n"));
+            assert!(sdp.contains("a=ptime:1\r\n"));
+            assert!(sdp.ends_with("a=sendonly\r\n"));
+        });
+    }
+
+    #[test]
+    fn multicast_sdp() {
+        block_on(async {
+            let sender =
+                RtpSender::new("239.69.1.1:5004", RtpFormat::L16, 1, 48000, 1.0, 32).unwrap();
+            assert!(sender.sdp().contains("c=IN IP4 239.69.1.1/32\r\n"));
+        });
+    }
+}
@@ -0,0 +1,217 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Weekly windows of the local time, e.g. to record only a morning show.
+
+use crate::local_date_time;
+use std::time::SystemTime;
+
+const DAY_NAMES: [&str; 7] = ["MON", "TUE", "WED", "THU", "FRI", "SAT", "SUN"];
+
+/// A window of the days of the week, e.g. `MON-FRI 06:00-10:00`, ending on the next day
+/// when its end is not after its start, e.g. `FRI 22:00-02:00`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Window {
+    /// bit 0 Monday ... bit 6 Sunday, the days of the start of the window
+    days: u8,
+    /// seconds since midnight
+    start: u32,
+    end: u32,
+}
+
+impl Window {
+    /// Parse the optional days, comma separated days or ranges of days
+    /// (every day when missing), and the start and end of the window, `HH:MM-HH:MM`.
+    pub fn parse(window: &str) -> Result<Self, String> {
+        let invalid = || format!("invalid schedule '{}', e.g. 'MON-FRI 06:00-10:00'", window);
+        let (days, times) = match window.trim().rsplit_once(char::is_whitespace) {
+            Some((days, times)) => (parse_days(days.trim()).ok_or_else(invalid)?, times),
+            None => (0b111_1111, window.trim()),
+        };
+        let (start, end) = times.split_once('-').ok_or_else(invalid)?;
+        let start = parse_time(start).ok_or_else(invalid)?;
+        let end = parse_time(end).ok_or_else(invalid)?;
+        if start == end || start == 24 * 3600 {
+            return Err(invalid());
+        }
+        Ok(Window { days, start, end })
+    }
+
+    fn contains(&self, weekday: usize, time: u32) -> bool {
+        let on = |weekday: usize| self.days & (1 << weekday) != 0;
+        if self.start < self.end {
+            on(weekday) && self.start <= time && time < self.end
+        } else {
+            (on(weekday) && self.start <= time) || (on((weekday + 6) % 7) && time < self.end)
+        }
+    }
+}
+
+/// Index of the day of the week, 0 Monday ... 6 Sunday.
+fn parse_day(day: &str) -> Option<usize> {
+    DAY_NAMES
+        .iter()
+        .position(|name| name.eq_ignore_ascii_case(day.trim()))
+}
+
+/// Bits of the comma separated days and ranges of days, e.g. `MON,WED,FRI-SUN`.
+fn parse_days(days: &str) -> Option<u8> {
+    if days == "*" {
+        return Some(0b111_1111);
+    }
+    let mut bits = 0;
+    for range in days.split(',') {
+        let (first, last) = match range.split_once('-') {
+            Some((first, last)) => (parse_day(first)?, parse_day(last)?),
+            None => (parse_day(range)?, parse_day(range)?),
+        };
+        // ranges over the end of the week, e.g. SAT-MON
+        let mut day = first;
+        loop {
+            bits |= 1 << day;
+            if day == last {
+                break;
+            }
+            day = (day + 1) % 7;
+        }
+    }
+    Some(bits)
+}
+
+/// Seconds since midnight of `HH:MM`, up to `24:00`.
+fn parse_time(time: &str) -> Option<u32> {
+    let (hours, minutes) = time.split_once(':')?;
+    let hours: u32 = hours.parse().ok()?;
+    let minutes: u32 = minutes.parse().ok()?;
+    if minutes >= 60 || hours * 60 + minutes > 24 * 60 {
+        return None;
+    }
+    Some((hours * 60 + minutes) * 60)
+}
+
+/// The windows of a week when something runs, in the local time.
+#[derive(Clone, Debug, Default)]
+pub struct Schedule {
+    windows: Vec<Window>,
+}
+
+impl Schedule {
+    pub fn new(windows: Vec<Window>) -> Self {
+        Schedule { windows }
+    }
+
+    /// Whether the time is in any of the windows.
+    pub fn contains(&self, time: SystemTime) -> bool {
+        let time = local_date_time(time);
+        let seconds = (time.hours * 60 + time.minutes) * 60 + time.seconds;
+        self.windows
+            .iter()
+            .any(|window| window.contains(time.weekday as usize, seconds))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MON: usize = 0;
+    const FRI: usize = 4;
+    const SAT: usize = 5;
+    const SUN: usize = 6;
+
+    fn hours(hours: u32, minutes: u32) -> u32 {
+        (hours * 60 + minutes) * 60
+    }
+
+    #[test]
+    fn parse_windows() {
+        assert_eq!(
+            Window::parse("MON-FRI 06:00-10:30"),
+            Ok(Window {
+                days: 0b001_1111,
+                start: hours(6, 0),
+                end: hours(10, 30),
+            })
+        );
+        assert_eq!(Window::parse(" 22:00-24:00 ").unwrap().days, 0b111_1111);
+        assert_eq!(Window::parse("* 22:00-02:00").unwrap().days, 0b111_1111);
+        assert_eq!(
+            Window::parse("mon,wed 08:00-09:00").unwrap().days,
+            0b000_0101
+        );
+        // over the end of the week
+        assert_eq!(
+            Window::parse("SAT-MON 08:00-09:00").unwrap().days,
+            0b110_0001
+        );
+        assert_eq!(Window::parse("SUN 08:00-09:00").unwrap().days, 0b100_0000);
+
+        for window in [
+            "",
+            "MON",
+            "MON 06:00",
+            "MON 06:00-06:00",
+            "XYZ 06:00-10:00",
+            "MON-XYZ 06:00-10:00",
+            "MON 06:60-10:00",
+            "MON 06:00-24:01",
+            "MON 24:00-02:00",
+            "MON 6-10",
+        ]
+        .iter()
+        {
+            assert!(Window::parse(window).is_err(), "{}", window);
+        }
+    }
+
+    #[test]
+    fn windows_within_a_day() {
+        let window = Window::parse("MON-FRI 06:00-10:00").unwrap();
+        assert!(!window.contains(MON, hours(5, 59)));
+        assert!(window.contains(MON, hours(6, 0)));
+        assert!(window.contains(FRI, hours(9, 59)));
+        assert!(!window.contains(FRI, hours(10, 0)));
+        assert!(!window.contains(SAT, hours(8, 0)));
+
+        let window = Window::parse("SUN 20:00-24:00").unwrap();
+        assert!(window.contains(SUN, hours(23, 59) + 59));
+        assert!(!window.contains(MON, 0));
+    }
+
+    #[test]
+    fn windows_over_midnight() {
+        let window = Window::parse("FRI 22:00-02:00").unwrap();
+        assert!(window.contains(FRI, hours(22, 0)));
+        assert!(window.contains(SAT, hours(1, 59)));
+        assert!(!window.contains(SAT, hours(2, 0)));
+        assert!(!window.contains(FRI, hours(1, 0)));
+        assert!(!window.contains(SAT, hours(22, 0)));
+
+        // and over the end of the week
+        let window = Window::parse("SUN 23:00-01:00").unwrap();
+        assert!(window.contains(SUN, hours(23, 30)));
+        assert!(window.contains(MON, hours(0, 30)));
+        assert!(!window.contains(SUN, hours(0, 30)));
+    }
+
+    #[test]
+    fn schedules() {
+        let now = SystemTime::now();
+        assert!(!Schedule::default().contains(now));
+        let all_day = Window::parse("00:00-24:00").unwrap();
+        let sunday = Window::parse("SUN 00:00-00:01").unwrap();
+        assert!(Schedule::new(vec![sunday, all_day]).contains(now));
+    }
+}
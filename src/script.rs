@@ -0,0 +1,366 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Lua scripts of site specific automation, as custom alarms or gain decisions, reloaded
+//! when their file changes.
+//!
+//! The script defines the functions `on_levels(levels)`, called with the levels of each
+//! input buffer, and `on_event(event)`, called with each event, both as tables of their
+//! JSON objects. It acts by calling `alarm(message)`, emitting a `script_alarm` event, and
+//! `control(command)` with a command of the control api, e.g.
+//! `control{action = "gain", channel = 0, db = -6}`.
+use crate::control::ControlCommand;
+use crate::events::Event;
+use crate::levels::Levels;
+use mlua::{Lua, Value as LuaValue};
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+/// how often the file of the script is checked for changes
+pub const RELOAD_INTERVAL: Duration = Duration::from_secs(1);
+/// events queued for the script before dropping new ones
+pub const EVENTS_CAPACITY: usize = 64;
+/// deeper tables of the script are `null`, as the cycles
+const MAX_DEPTH: usize = 32;
+
+/// the functions of the actions of the script, queuing them in `__actions`
+const PRELUDE: &str = r#"
+__actions = {}
+function alarm(message)
+    __actions[#__actions + 1] = {alarm = tostring(message)}
+end
+function control(command)
+    __actions[#__actions + 1] = {control = command}
+end
+"#;
+
+/// An action of the script.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ScriptAction {
+    Alarm(String),
+    Control(ControlCommand),
+}
+
+/// A loaded script, with its own Lua state.
+pub struct Script {
+    path: PathBuf,
+    lua: Lua,
+    /// of the file loaded last
+    modified: Option<SystemTime>,
+    checked: Instant,
+}
+
+impl Script {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let modified = modified_time(path);
+        let lua = new_state(path)?;
+        Ok(Script {
+            path: path.to_path_buf(),
+            lua,
+            modified,
+            checked: Instant::now(),
+        })
+    }
+
+    /// Reload the script if its file changed, at most once every [`RELOAD_INTERVAL`],
+    /// returns whether it was reloaded. The script loaded before is kept on error.
+    pub fn reload_if_modified(&mut self) -> Result<bool, String> {
+        if self.checked.elapsed() < RELOAD_INTERVAL {
+            return Ok(false);
+        }
+        self.checked = Instant::now();
+        let modified = modified_time(&self.path);
+        if modified == self.modified {
+            return Ok(false);
+        }
+        // not to try again until it changes again
+        self.modified = modified;
+        self.lua = new_state(&self.path)?;
+        Ok(true)
+    }
+
+    /// Call `on_levels` of the script, if defined.
+    pub fn on_levels(&mut self, levels: &Levels) -> Result<Vec<ScriptAction>, String> {
+        let levels = serde_json::to_value(levels).map_err(|err| err.to_string())?;
+        self.call("on_levels", &levels)
+    }
+
+    /// Call `on_event` of the script, if defined.
+    pub fn on_event(&mut self, event: &Event) -> Result<Vec<ScriptAction>, String> {
+        let event = serde_json::to_value(event).map_err(|err| err.to_string())?;
+        self.call("on_event", &event)
+    }
+
+    /// Call a global function of the script and take the actions queued by it.
+    fn call(&mut self, function: &str, argument: &Value) -> Result<Vec<ScriptAction>, String> {
+        let function = match self.lua.globals().get::<LuaValue>(function) {
+            Ok(LuaValue::Function(function)) => function,
+            _ => return Ok(Vec::new()),
+        };
+        let result = lua_value(&self.lua, argument, 0)
+            .and_then(|argument| function.call::<()>(argument))
+            .map_err(|err| err.to_string());
+        let actions = take_actions(&self.lua);
+        result.and(actions)
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// New Lua state with the standard libraries, the prelude and the script run.
+fn new_state(path: &Path) -> Result<Lua, String> {
+    let code = fs::read(path)
+        .map_err(|err| format!("failed to read the script {}: {}", path.display(), err))?;
+    let lua = Lua::new();
+    lua.load(PRELUDE)
+        .set_name("=prelude")
+        .exec()
+        .and_then(|()| {
+            lua.load(&code[..])
+                .set_name(format!("@{}", path.display()))
+                .exec()
+        })
+        .map_err(|err| err.to_string())?;
+    Ok(lua)
+}
+
+/// A JSON value as a Lua value, the arrays as sequences from 1.
+fn lua_value(lua: &Lua, value: &Value, depth: usize) -> mlua::Result<LuaValue> {
+    if depth > MAX_DEPTH {
+        return Err(mlua::Error::runtime("too deep a value for the Lua stack"));
+    }
+    Ok(match value {
+        Value::Null => LuaValue::Nil,
+        Value::Bool(value) => LuaValue::Boolean(*value),
+        Value::Number(number) => match number.as_i64() {
+            Some(number) => LuaValue::Integer(number),
+            None => LuaValue::Number(number.as_f64().unwrap_or(f64::NAN)),
+        },
+        Value::String(string) => LuaValue::String(lua.create_string(string)?),
+        Value::Array(values) => {
+            let table = lua.create_table_with_capacity(values.len(), 0)?;
+            for (index, value) in values.iter().enumerate() {
+                table.raw_set(index + 1, lua_value(lua, value, depth + 1)?)?;
+            }
+            LuaValue::Table(table)
+        }
+        Value::Object(fields) => {
+            let table = lua.create_table_with_capacity(0, fields.len())?;
+            for (name, value) in fields {
+                table.raw_set(name.as_str(), lua_value(lua, value, depth + 1)?)?;
+            }
+            LuaValue::Table(table)
+        }
+    })
+}
+
+/// Key of a Lua table.
+enum Key {
+    Index(i64),
+    Name(String),
+}
+
+/// A Lua value as a JSON value, the sequences from 1 as arrays and the other tables as
+/// objects, `null` for the functions and the other values.
+fn json_value(value: LuaValue, depth: usize) -> Value {
+    match value {
+        LuaValue::Boolean(value) => Value::Bool(value),
+        LuaValue::Integer(value) => Value::from(value),
+        LuaValue::Number(value) => {
+            serde_json::Number::from_f64(value).map_or(Value::Null, Value::Number)
+        }
+        LuaValue::String(string) => Value::String(string.to_string_lossy()),
+        LuaValue::Table(table) if depth < MAX_DEPTH => {
+            let entries = table
+                .pairs::<LuaValue, LuaValue>()
+                .filter_map(Result::ok)
+                .filter_map(|(key, value)| {
+                    let key = match key {
+                        LuaValue::Integer(index) => Key::Index(index),
+                        LuaValue::String(name) => Key::Name(name.to_string_lossy()),
+                        _ => return None,
+                    };
+                    Some((key, json_value(value, depth + 1)))
+                })
+                .collect();
+            table_value(entries)
+        }
+        _ => Value::Null,
+    }
+}
+
+fn table_value(mut entries: Vec<(Key, Value)>) -> Value {
+    entries.sort_by_key(|(key, _)| match key {
+        Key::Index(index) => *index,
+        Key::Name(_) => i64::MAX,
+    });
+    let is_sequence = entries.iter().enumerate().all(
+        |(position, (key, _))| matches!(key, Key::Index(index) if *index == position as i64 + 1),
+    );
+    if is_sequence {
+        return Value::Array(entries.into_iter().map(|(_, value)| value).collect());
+    }
+    Value::Object(
+        entries
+            .into_iter()
+            .map(|(key, value)| match key {
+                Key::Index(index) => (index.to_string(), value),
+                Key::Name(name) => (name, value),
+            })
+            .collect(),
+    )
+}
+
+/// Take the actions queued by the script since the last call.
+fn take_actions(lua: &Lua) -> Result<Vec<ScriptAction>, String> {
+    let globals = lua.globals();
+    let actions = globals
+        .get::<LuaValue>("__actions")
+        .map(|actions| json_value(actions, 0))
+        .unwrap_or_default();
+    lua.create_table()
+        .and_then(|table| globals.set("__actions", table))
+        .map_err(|err| err.to_string())?;
+    let actions = match actions {
+        Value::Array(actions) => actions,
+        _ => return Ok(Vec::new()),
+    };
+    actions
+        .into_iter()
+        .map(|action| {
+            if let Some(message) = action.get("alarm").and_then(Value::as_str) {
+                return Ok(ScriptAction::Alarm(message.to_owned()));
+            }
+            let command = action.get("control").cloned().unwrap_or_default();
+            serde_json::from_value(command.clone())
+                .map(ScriptAction::Control)
+                .map_err(|err| format!("invalid control command {}: {}", command, err))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn script(name: &str, code: &str) -> (Script, PathBuf) {
+        let path = std::env::temp_dir().join(format!(
+            "audio-in-stream-rs-{}-{}.lua",
+            name,
+            std::process::id()
+        ));
+        fs::write(&path, code).unwrap();
+        (Script::load(&path).unwrap(), path)
+    }
+
+    #[test]
+    fn queues_the_actions() {
+        let (mut script, path) = script(
+            "queues_the_actions",
+            "function on_test(value)\n\
+                 alarm('level ' .. value.level)\n\
+                 control{action = 'gain', channel = 0, db = -6}\n\
+             end\n",
+        );
+        let actions = script.call("on_test", &json!({"level": 3})).unwrap();
+        assert_eq!(
+            actions,
+            vec![
+                ScriptAction::Alarm(String::from("level 3")),
+                ScriptAction::Control(ControlCommand::Gain {
+                    channel: Some(0),
+                    db: -6.0
+                }),
+            ]
+        );
+        assert_eq!(
+            script.call("on_test", &json!({"level": 4})).unwrap().len(),
+            2
+        );
+        assert!(script.call("undefined", &json!(null)).unwrap().is_empty());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn converts_the_json_values() {
+        let (mut script, path) = script(
+            "converts_the_json_values",
+            "function on_test(value)\n\
+                 control{action = value.action, channel = value.channels[2], db = value.db}\n\
+                 result = {value.channels, {a = 1}, on_test, 0.5}\n\
+             end\n",
+        );
+        let value = json!({"action": "adjust_gain", "channels": [7, 8], "db": 1.5});
+        assert_eq!(
+            script.call("on_test", &value).unwrap(),
+            vec![ScriptAction::Control(ControlCommand::AdjustGain {
+                channel: Some(8),
+                db: 1.5
+            })]
+        );
+        let result = script.lua.globals().get::<LuaValue>("result").unwrap();
+        assert_eq!(json_value(result, 0), json!([[7, 8], {"a": 1}, null, 0.5]));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn reports_the_errors() {
+        let (mut script, path) = script(
+            "reports_the_errors",
+            "function on_test(value)\n\
+                 alarm('before')\n\
+                 control{action = 'louder'}\n\
+                 error('failed')\n\
+             end\n",
+        );
+        let err = script.call("on_test", &json!(null)).unwrap_err();
+        assert!(err.contains("failed"), "{}", err);
+        // the actions queued before the error are dropped with it
+        assert!(script.call("undefined", &json!(null)).unwrap().is_empty());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn keeps_the_script_on_reload_errors() {
+        let (mut script, path) = script(
+            "keeps_the_script_on_reload_errors",
+            "function on_test() alarm('old') end\n",
+        );
+        fs::write(&path, "function on_test( alarm('new') end\n").unwrap();
+        script.checked -= RELOAD_INTERVAL;
+        script.modified = None;
+        assert!(script.reload_if_modified().is_err());
+        assert_eq!(
+            script.call("on_test", &json!(null)).unwrap(),
+            vec![ScriptAction::Alarm(String::from("old"))]
+        );
+        fs::write(&path, "function on_test() alarm('new') end\n").unwrap();
+        script.checked -= RELOAD_INTERVAL;
+        script.modified = None;
+        assert_eq!(script.reload_if_modified(), Ok(true));
+        assert_eq!(
+            script.call("on_test", &json!(null)).unwrap(),
+            vec![ScriptAction::Alarm(String::from("new"))]
+        );
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,121 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Silence detection: the level of a channel below a threshold for too long.
+
+use crate::{unix_time, ChannelData};
+use std::time::{Duration, SystemTime};
+
+/// dBov
+pub const DEFAULT_THRESHOLD: f32 = -60.0;
+/// seconds
+pub const DEFAULT_DURATION: f32 = 10.0;
+
+#[derive(Clone, Copy, Debug)]
+pub struct SilenceConfig {
+    /// loudness level below which a channel is quiet, root mean square of the samples
+    pub threshold: f32,
+    /// time a channel has to be quiet to be silent
+    pub duration: Duration,
+}
+
+impl Default for SilenceConfig {
+    fn default() -> Self {
+        SilenceConfig {
+            threshold: 10_f32.powf(DEFAULT_THRESHOLD / 20.0),
+            duration: Duration::from_secs_f32(DEFAULT_DURATION),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum SilenceEvent {
+    /// the channel has been quiet since the given time, for long enough to be silent
+    Start { channel: usize, since: SystemTime },
+    /// the channel was silent since the given time, until the given time
+    End {
+        channel: usize,
+        since: SystemTime,
+        until: SystemTime,
+    },
+}
+
+#[derive(Clone, Default)]
+struct ChannelSilence {
+    quiet_since: Option<SystemTime>,
+    silent: bool,
+}
+
+pub struct SilenceDetector {
+    config: SilenceConfig,
+    channels: Vec<ChannelSilence>,
+}
+
+impl SilenceDetector {
+    pub fn new(config: SilenceConfig, num_channels: usize) -> Self {
+        SilenceDetector {
+            config,
+            channels: vec![ChannelSilence::default(); num_channels],
+        }
+    }
+
+    /// Update the state of each channel with the levels of an input buffer
+    /// captured at the given time, returns the start and end of silences.
+    pub fn process(
+        &mut self,
+        channels: &[ChannelData],
+        timestamp: SystemTime,
+    ) -> Vec<SilenceEvent> {
+        let mut events = Vec::new();
+        for (index, (channel, silence)) in channels.iter().zip(&mut self.channels).enumerate() {
+            if channel.loudness_level < self.config.threshold {
+                let quiet_since = *silence.quiet_since.get_or_insert(timestamp);
+                let quiet_for = timestamp.duration_since(quiet_since).unwrap_or_default();
+                if !silence.silent && quiet_for >= self.config.duration {
+                    silence.silent = true;
+                    events.push(SilenceEvent::Start {
+                        channel: index,
+                        since: quiet_since,
+                    });
+                }
+            } else if let Some(quiet_since) = silence.quiet_since.take() {
+                if silence.silent {
+                    silence.silent = false;
+                    events.push(SilenceEvent::End {
+                        channel: index,
+                        since: quiet_since,
+                        until: timestamp,
+                    });
+                }
+            }
+        }
+        events
+    }
+
+    /// Time since each channel is silent, in seconds since the Unix epoch,
+    /// `None` if it is not silent.
+    pub fn silent_since(&self) -> Vec<Option<f64>> {
+        self.channels
+            .iter()
+            .map(|silence| {
+                if silence.silent {
+                    silence.quiet_since.map(unix_time)
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,121 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Snapshots of the last seconds of the input, kept in memory
+//! to be saved after the fact.
+
+use crate::recording::{format_file_name, unique_path};
+use crate::wav::WavWriter;
+use std::collections::VecDeque;
+use std::io::{self, Cursor};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, SystemTime};
+
+/// file name template of the saved snapshots, with the UTC time of their first frame
+pub const FILE_NAME_TEMPLATE: &str = "snapshot-%Y%m%d-%H%M%S.wav";
+/// longest snapshot in seconds, the input being kept in memory
+pub const MAX_DURATION: f64 = 600.0;
+
+struct History {
+    /// interleaved samples of the last seconds
+    samples: VecDeque<f32>,
+    /// capture time of the frame after the last one
+    end: SystemTime,
+}
+
+/// The interleaved samples of the last seconds of the input, shared between
+/// the thread reading them from the sample ring and the takers of snapshots.
+#[derive(Clone)]
+pub struct SnapshotBuffer {
+    history: Arc<Mutex<History>>,
+    config: cpal::SupportedStreamConfig,
+    max_len: usize,
+}
+
+impl SnapshotBuffer {
+    pub fn new(duration: f64, config: cpal::SupportedStreamConfig) -> Self {
+        let max_len = ((duration * config.sample_rate().0 as f64) as usize)
+            .saturating_mul(config.channels() as usize);
+        SnapshotBuffer {
+            history: Arc::new(Mutex::new(History {
+                samples: VecDeque::new(),
+                end: SystemTime::UNIX_EPOCH,
+            })),
+            config,
+            max_len,
+        }
+    }
+
+    /// Keep the interleaved samples captured at the time of the first frame,
+    /// dropping the oldest ones.
+    pub fn push(&self, samples: &[f32], timestamp: SystemTime) {
+        let duration = (samples.len() / self.config.channels() as usize) as f64
+            / self.config.sample_rate().0 as f64;
+        let mut history = self.history.lock().unwrap_or_else(PoisonError::into_inner);
+        history.samples.extend(samples);
+        let excess = history.samples.len().saturating_sub(self.max_len);
+        history.samples.drain(..excess);
+        history.end = timestamp + Duration::from_secs_f64(duration);
+    }
+
+    pub fn config(&self) -> &cpal::SupportedStreamConfig {
+        &self.config
+    }
+
+    /// The samples of a channel of the last seconds, up to `duration` seconds of them.
+    pub fn channel_samples(&self, channel: usize, duration: f64) -> Vec<f32> {
+        let channels = self.config.channels() as usize;
+        let max_frames = (duration * self.config.sample_rate().0 as f64) as usize;
+        let history = self.history.lock().unwrap_or_else(PoisonError::into_inner);
+        let frames = history.samples.len() / channels;
+        history
+            .samples
+            .iter()
+            .skip(frames.saturating_sub(max_frames) * channels + channel)
+            .step_by(channels)
+            .copied()
+            .collect()
+    }
+
+    /// The last seconds as a WAV file, and the capture time of its first frame.
+    pub fn take(&self) -> io::Result<(Vec<u8>, SystemTime)> {
+        // copied out, not to hold the lock while encoding
+        let (samples, end) = {
+            let history = self.history.lock().unwrap_or_else(PoisonError::into_inner);
+            let (front, back) = history.samples.as_slices();
+            ([front, back].concat(), history.end)
+        };
+        let duration = (samples.len() / self.config.channels() as usize) as f64
+            / self.config.sample_rate().0 as f64;
+        let start = end
+            .checked_sub(Duration::from_secs_f64(duration))
+            .unwrap_or(end);
+
+        let mut writer = WavWriter::new(Cursor::new(Vec::new()), &self.config)?;
+        writer.write_interleaved(&samples)?;
+        writer.finalize()?;
+        Ok((writer.into_inner().into_inner(), start))
+    }
+
+    /// Save the last seconds to a new file of the directory, returns its path.
+    pub fn save(&self, directory: &Path) -> io::Result<PathBuf> {
+        let (wav, start) = self.take()?;
+        let template = directory.join(FILE_NAME_TEMPLATE);
+        let path = unique_path(&format_file_name(&template.to_string_lossy(), start));
+        std::fs::write(&path, wav)?;
+        Ok(path)
+    }
+}
@@ -0,0 +1,106 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Spectrogram image of a channel of the recent input: time from left to right,
+//! frequency from the bottom up to the Nyquist frequency, and the magnitude as a color.
+
+use crate::png;
+use crate::spectrum::Window;
+use rustfft::num_complex::Complex;
+use rustfft::FftPlanner;
+
+pub const DEFAULT_WIDTH: u32 = 800;
+pub const DEFAULT_HEIGHT: u32 = 256;
+/// largest images, not to take too long to render
+pub const MAX_WIDTH: u32 = 4096;
+pub const MAX_HEIGHT: u32 = 2048;
+pub const MAX_FFT_SIZE: usize = 65536;
+/// magnitudes from a full scale sine down to this level have a color, below it is black
+const FLOOR_DECIBELS: f32 = -120.0;
+/// colors of the magnitudes from the floor up to the full scale, interpolated between them
+const PALETTE: [[f32; 3]; 5] = [
+    [0.0, 0.0, 0.0],
+    [40.0, 10.0, 100.0],
+    [180.0, 30.0, 110.0],
+    [250.0, 140.0, 30.0],
+    [255.0, 255.0, 200.0],
+];
+
+/// Size of the image and of the FFT.
+#[derive(Clone, Copy, Debug)]
+pub struct SpectrogramConfig {
+    pub width: u32,
+    pub height: u32,
+    pub fft_size: usize,
+    pub window: Window,
+}
+
+/// Render the spectrogram of the samples of a channel as a PNG image,
+/// each column is the FFT of the samples at its time.
+pub fn render(samples: &[f32], config: SpectrogramConfig) -> Vec<u8> {
+    let SpectrogramConfig {
+        width,
+        height,
+        fft_size,
+        window,
+    } = config;
+    let fft = FftPlanner::new().plan_fft_forward(fft_size);
+    let window_coefficients = window.coefficients(fft_size);
+    // scale of the FFT output to the amplitude of a sine
+    let amplitude_scale = 2.0 / window_coefficients.iter().sum::<f32>();
+    let mut buffer = vec![Complex::default(); fft_size];
+    let mut scratch = vec![Complex::default(); fft.get_inplace_scratch_len()];
+    let num_bins = fft_size / 2 + 1;
+
+    let mut pixels = vec![0; width as usize * height as usize * 3];
+    for x in 0..width as usize {
+        // zero padded if there are less samples than the FFT size
+        let start = match samples.len().checked_sub(fft_size) {
+            Some(last_start) if width > 1 => last_start * x / (width as usize - 1),
+            _ => 0,
+        };
+        for (n, (value, coefficient)) in buffer.iter_mut().zip(&window_coefficients).enumerate() {
+            let sample = samples.get(start + n).copied().unwrap_or(0.0);
+            *value = Complex::new(sample * coefficient, 0.0);
+        }
+        fft.process_with_scratch(&mut buffer, &mut scratch);
+
+        for y in 0..height as usize {
+            // the highest magnitude of the bins of the row, the top row is the Nyquist frequency
+            let row = height as usize - 1 - y;
+            let first_bin = row * num_bins / height as usize;
+            let last_bin = ((row + 1) * num_bins / height as usize).max(first_bin + 1);
+            let magnitude = buffer[first_bin..last_bin]
+                .iter()
+                .map(|value| value.norm() * amplitude_scale)
+                .fold(0.0, f32::max);
+            let pixel = (y * width as usize + x) * 3;
+            pixels[pixel..pixel + 3].copy_from_slice(&color(20.0 * magnitude.log10()));
+        }
+    }
+    png::encode_rgb(width, height, &pixels)
+}
+
+/// Color of the magnitude in decibels relative to a full scale sine.
+fn color(decibels: f32) -> [u8; 3] {
+    let position = ((decibels - FLOOR_DECIBELS) / -FLOOR_DECIBELS).clamp(0.0, 1.0)
+        * (PALETTE.len() - 1) as f32;
+    let index = (position as usize).min(PALETTE.len() - 2);
+    let fraction = position - index as f32;
+    let (low, high) = (PALETTE[index], PALETTE[index + 1]);
+    [0, 1, 2].map(|component| {
+        (low[component] + (high[component] - low[component]) * fraction).round() as u8
+    })
+}
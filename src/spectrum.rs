@@ -0,0 +1,191 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Magnitude spectrum of each channel, from the FFT of the latest samples.
+
+use crate::processor::Processor;
+use crate::{ChannelData, InputBufferSourceData};
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+use serde::Serialize;
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+pub const DEFAULT_FFT_SIZE: usize = 2048;
+/// smaller FFTs have too few bins to tell anything apart
+pub const MIN_FFT_SIZE: usize = 16;
+
+/// Window applied to the samples before the FFT.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Window {
+    Rectangular,
+    #[default]
+    Hann,
+    Hamming,
+    Blackman,
+}
+
+impl Window {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name.to_ascii_lowercase().as_str() {
+            "rectangular" => Ok(Window::Rectangular),
+            "hann" => Ok(Window::Hann),
+            "hamming" => Ok(Window::Hamming),
+            "blackman" => Ok(Window::Blackman),
+            _ => Err(format!(
+                "invalid window '{}', expected one of: rectangular, hann, hamming, blackman",
+                name
+            )),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Window::Rectangular => "rectangular",
+            Window::Hann => "hann",
+            Window::Hamming => "hamming",
+            Window::Blackman => "blackman",
+        }
+    }
+
+    pub(crate) fn coefficients(self, size: usize) -> Vec<f32> {
+        (0..size)
+            .map(|n| {
+                let phase = 2.0 * PI * n as f32 / size as f32;
+                match self {
+                    Window::Rectangular => 1.0,
+                    Window::Hann => 0.5 - 0.5 * phase.cos(),
+                    Window::Hamming => 0.54 - 0.46 * phase.cos(),
+                    Window::Blackman => 0.42 - 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos(),
+                }
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Spectrum {
+    pub fft_size: usize,
+    pub window: &'static str,
+    /// width of each frequency bin, in Hz
+    pub bin_width: f32,
+    /// magnitude of each frequency bin of each channel, from 0 Hz to the Nyquist frequency,
+    /// in decibels relative to the amplitude of a full scale sine,
+    /// `null` in JSON for silence (-inf)
+    pub channels: Vec<Vec<f32>>,
+}
+
+/// Computes the spectrum of the latest `fft_size` samples of each channel,
+/// regardless of the size of the input buffers.
+pub struct SpectrumAnalyzer {
+    fft: Arc<dyn Fft<f32>>,
+    window: Window,
+    window_coefficients: Vec<f32>,
+    /// scale of the FFT output to the amplitude of a sine
+    amplitude_scale: f32,
+    bin_width: f32,
+    /// latest `fft_size` samples of each channel, oldest first
+    history: Vec<Vec<f32>>,
+    buffer: Vec<Complex<f32>>,
+    scratch: Vec<Complex<f32>>,
+    /// of the last input buffer processed as a [`Processor`]
+    spectrum: Option<Spectrum>,
+}
+
+impl SpectrumAnalyzer {
+    pub fn new(fft_size: usize, window: Window, sample_rate: u32, num_channels: usize) -> Self {
+        let fft = FftPlanner::new().plan_fft_forward(fft_size);
+        let window_coefficients = window.coefficients(fft_size);
+        let amplitude_scale = 2.0 / window_coefficients.iter().sum::<f32>();
+        let scratch = vec![Complex::default(); fft.get_inplace_scratch_len()];
+        SpectrumAnalyzer {
+            fft,
+            window,
+            window_coefficients,
+            amplitude_scale,
+            bin_width: sample_rate as f32 / fft_size as f32,
+            history: vec![vec![0.0; fft_size]; num_channels],
+            buffer: vec![Complex::default(); fft_size],
+            scratch,
+            spectrum: None,
+        }
+    }
+
+    /// Take the spectrum of the last input buffer processed as a [`Processor`].
+    pub fn take_spectrum(&mut self) -> Option<Spectrum> {
+        self.spectrum.take()
+    }
+
+    /// Add the samples of an input buffer, and compute the spectrum of the latest samples.
+    pub fn process(&mut self, channels: &[ChannelData]) -> Spectrum {
+        let fft_size = self.buffer.len();
+        let amplitude_scale = self.amplitude_scale;
+        let mut spectrum = Spectrum {
+            fft_size,
+            window: self.window.name(),
+            bin_width: self.bin_width,
+            channels: Vec::with_capacity(channels.len()),
+        };
+
+        for (channel, history) in channels.iter().zip(&mut self.history) {
+            let samples = &channel.samples;
+            if samples.len() >= fft_size {
+                history.copy_from_slice(&samples[samples.len() - fft_size..]);
+            } else {
+                history.copy_within(samples.len().., 0);
+                history[fft_size - samples.len()..].copy_from_slice(samples);
+            }
+
+            for ((value, sample), coefficient) in self
+                .buffer
+                .iter_mut()
+                .zip(history.iter())
+                .zip(&self.window_coefficients)
+            {
+                *value = Complex::new(sample * coefficient, 0.0);
+            }
+            self.fft
+                .process_with_scratch(&mut self.buffer, &mut self.scratch);
+
+            spectrum.channels.push(
+                self.buffer[..=fft_size / 2]
+                    .iter()
+                    .map(|value| 20.0 * (value.norm() * amplitude_scale).log10())
+                    .collect(),
+            );
+        }
+
+        spectrum
+    }
+}
+
+/// Computes the spectrum of each input buffer, to be taken with
+/// [`SpectrumAnalyzer::take_spectrum`], with the parameters `fft_size` and `bin_width` in Hz.
+impl Processor for SpectrumAnalyzer {
+    fn name(&self) -> &str {
+        "spectrum"
+    }
+
+    fn process(&mut self, buffer: &mut InputBufferSourceData) {
+        self.spectrum = Some(SpectrumAnalyzer::process(self, &buffer.channels));
+    }
+
+    fn parameters(&self) -> Vec<(String, f64)> {
+        vec![
+            (String::from("fft_size"), self.buffer.len() as f64),
+            (String::from("bin_width"), f64::from(self.bin_width)),
+        ]
+    }
+}
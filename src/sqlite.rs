@@ -0,0 +1,189 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Logging of the levels of each second and of the events to an SQLite database,
+//! with a bundled SQLite, kept across restarts and queried with SQL.
+//!
+//! The tables are `levels` (`timestamp`, `channel`, `rms`, `peak`), `loudness`
+//! (`timestamp`, `short_term`) and `events` (`timestamp`, `event`, `channel`, `data`),
+//! with the times in seconds since the Unix epoch, the levels in dB, `NULL` for
+//! silence, and the JSON of the event in `data`.
+
+use crate::error::Error;
+use crate::events::{Event, PendingEvents};
+use crate::history::HistoryPoint;
+use crate::supervisor::Supervisor;
+use rusqlite::{params, Connection, OpenFlags};
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+/// Records queued before dropping new ones.
+const QUEUE_CAPACITY: usize = 64;
+/// Time to wait for the lock of the database held by another connection, e.g. a query.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+const SCHEMA: &str = "
+    PRAGMA journal_mode = WAL;
+    CREATE TABLE IF NOT EXISTS levels (
+        timestamp REAL NOT NULL,
+        channel INTEGER NOT NULL,
+        rms REAL,
+        peak REAL
+    );
+    CREATE INDEX IF NOT EXISTS levels_timestamp ON levels (timestamp);
+    CREATE TABLE IF NOT EXISTS loudness (
+        timestamp REAL NOT NULL,
+        short_term REAL
+    );
+    CREATE INDEX IF NOT EXISTS loudness_timestamp ON loudness (timestamp);
+    CREATE TABLE IF NOT EXISTS events (
+        timestamp REAL NOT NULL,
+        event TEXT NOT NULL,
+        channel INTEGER,
+        data TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS events_timestamp ON events (timestamp);
+";
+
+/// A level in dB, `NULL` for silence (-inf).
+fn level(level: f32) -> Option<f64> {
+    if level.is_finite() {
+        Some(level as f64)
+    } else {
+        None
+    }
+}
+
+enum Record {
+    Levels(HistoryPoint),
+    Event(Event),
+}
+
+/// Writes the levels and the events to the database from its own thread,
+/// so a slow disk does not delay the metering.
+#[derive(Clone)]
+pub struct LevelDatabase {
+    sender: mpsc::SyncSender<Record>,
+    pending: PendingEvents,
+}
+
+impl LevelDatabase {
+    /// Open or create the database and its tables, `pending` counts the events
+    /// queued until written. A failure to write stops the logging, reported to the
+    /// supervisor.
+    pub fn open(
+        path: &Path,
+        pending: PendingEvents,
+        supervisor: Supervisor,
+    ) -> Result<Self, String> {
+        let connection = Connection::open_with_flags(
+            path,
+            OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_CREATE,
+        )
+        .and_then(|connection| {
+            connection.busy_timeout(BUSY_TIMEOUT)?;
+            Ok(connection)
+        })
+        .map_err(|err| format!("failed to open database {}, {}", path.display(), err))?;
+        connection.execute_batch(SCHEMA).map_err(|err| {
+            format!(
+                "failed to create the tables of database {}, {}",
+                path.display(),
+                err
+            )
+        })?;
+        let (sender, receiver) = mpsc::sync_channel(QUEUE_CAPACITY);
+        let writer_pending = pending.clone();
+        let path = path.to_owned();
+        thread::spawn(move || {
+            if let Err(err) = write_records(connection, receiver, &writer_pending) {
+                supervisor.report(Error::Output(format!(
+                    "stopped logging to database {}, {}",
+                    path.display(),
+                    err
+                )));
+            }
+        });
+        Ok(LevelDatabase { sender, pending })
+    }
+
+    /// Queue the levels of a second, dropped if the queue is full.
+    pub fn insert_levels(&self, point: HistoryPoint) {
+        self.sender.try_send(Record::Levels(point)).ok();
+    }
+
+    /// Queue an event, dropped if the queue is full.
+    pub fn insert_event(&self, event: &Event) {
+        self.pending.begin();
+        if self.sender.try_send(Record::Event(event.clone())).is_err() {
+            self.pending.end();
+        }
+    }
+}
+
+fn write_records(
+    mut connection: Connection,
+    receiver: mpsc::Receiver<Record>,
+    pending: &PendingEvents,
+) -> rusqlite::Result<()> {
+    for record in receiver {
+        match record {
+            Record::Levels(point) => {
+                // the rows of a second in a single transaction, rolled back when dropped
+                // on an error
+                let transaction = connection.transaction()?;
+                {
+                    let mut insert_levels = transaction.prepare_cached(
+                        "INSERT INTO levels (timestamp, channel, rms, peak) VALUES (?, ?, ?, ?)",
+                    )?;
+                    for channel in &point.channels {
+                        insert_levels.execute(params![
+                            point.timestamp,
+                            channel.channel as i64,
+                            level(channel.rms),
+                            level(channel.peak),
+                        ])?;
+                    }
+                    transaction
+                        .prepare_cached(
+                            "INSERT INTO loudness (timestamp, short_term) VALUES (?, ?)",
+                        )?
+                        .execute(params![point.timestamp, level(point.short_term)])?;
+                }
+                transaction.commit()?;
+            }
+            Record::Event(event) => {
+                let data = serde_json::to_value(&event).expect("failed to serialize event");
+                let result = connection
+                    .prepare_cached(
+                        "INSERT INTO events (timestamp, event, channel, data) VALUES (?, ?, ?, ?)",
+                    )
+                    .and_then(|mut insert_event| {
+                        insert_event.execute(params![
+                            data["timestamp"].as_f64().unwrap_or_default(),
+                            data["event"].as_str().unwrap_or_default(),
+                            data["channel"].as_i64(),
+                            data.to_string(),
+                        ])
+                    });
+                pending.end();
+                result?;
+            }
+        }
+    }
+    Ok(())
+}
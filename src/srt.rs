@@ -0,0 +1,297 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! SRT output of the live capture, with srt-tokio, as an MPEG transport stream of Opus
+//! (`ffplay srt://host:port`) or as raw Opus packets, one in each SRT message.
+
+use crate::error::Error;
+use crate::mpegts::{self, TsMuxer, TsStream};
+use crate::ogg_opus::{OpusPacketEncoder, OPUS_FRAME_LEN, OPUS_SAMPLE_RATE};
+use crate::supervisor::Supervisor;
+use bytes::Bytes;
+use futures_util::SinkExt;
+use srt_tokio::SrtSocket;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+/// Latency of the receiver buffer, in milliseconds, the default of libsrt.
+pub const DEFAULT_LATENCY: u32 = 120;
+/// Default port of the listener, when the url has none.
+const DEFAULT_PORT: u16 = 9000;
+/// delay before reconnecting after the connection to the listener is lost
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+/// Largest payload of an SRT message in live mode, 7 transport stream packets.
+const MAX_MESSAGE_LEN: usize = 7 * mpegts::PACKET_LEN;
+/// AES-128, the key length of libsrt for a passphrase
+const KEY_SIZE: u16 = 16;
+
+/// Whether to connect to a listener or to wait for the callers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SrtMode {
+    Caller,
+    Listener,
+}
+
+/// SRT endpoint, from an url like `srt://host:port` to connect to a listener,
+/// `srt://:port` to listen on all the addresses, or `srt://host:port?mode=listener`.
+#[derive(Clone, Debug)]
+pub struct SrtUrl {
+    pub host: String,
+    pub port: u16,
+    pub mode: SrtMode,
+    pub stream_id: Option<String>,
+}
+
+impl SrtUrl {
+    pub fn parse(url: &str) -> Result<Self, String> {
+        let invalid_url = || format!("invalid SRT url '{}'", url);
+        let rest = url
+            .strip_prefix("srt://")
+            .ok_or_else(|| format!("invalid SRT url '{}', expected srt://host:port", url))?;
+        let (host_port, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let host_port = host_port.trim_end_matches('/');
+        let (host, port) = match host_port.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => {
+                (host, port.parse().map_err(|_| invalid_url())?)
+            }
+            _ => (host_port, DEFAULT_PORT),
+        };
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+
+        let mut mode = if host.is_empty() {
+            SrtMode::Listener
+        } else {
+            SrtMode::Caller
+        };
+        let mut stream_id = None;
+        for param in query.split('&').filter(|param| !param.is_empty()) {
+            match param.split_once('=').unwrap_or((param, "")) {
+                ("mode", "caller") => mode = SrtMode::Caller,
+                ("mode", "listener") => mode = SrtMode::Listener,
+                ("streamid", id) => stream_id = Some(id.to_owned()),
+                _ => return Err(format!("invalid SRT url '{}', unknown '{}'", url, param)),
+            }
+        }
+        if host.is_empty() && mode == SrtMode::Caller {
+            return Err(format!("invalid SRT url '{}', missing host", url));
+        }
+
+        Ok(SrtUrl {
+            host: if host.is_empty() { "0.0.0.0" } else { host }.to_owned(),
+            port,
+            mode,
+            stream_id,
+        })
+    }
+}
+
+/// Payload of the SRT messages.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SrtPayload {
+    /// MPEG transport stream of Opus, read by most SRT receivers
+    Ts,
+    /// one Opus packet of 20 ms in each message
+    Opus,
+}
+
+impl SrtPayload {
+    pub fn parse(payload: &str) -> Result<Self, String> {
+        match payload.to_ascii_lowercase().as_str() {
+            "ts" | "mpegts" => Ok(SrtPayload::Ts),
+            "opus" => Ok(SrtPayload::Opus),
+            _ => Err(format!(
+                "invalid SRT payload '{}', expected ts or opus",
+                payload
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SrtConfig {
+    pub url: SrtUrl,
+    pub payload: SrtPayload,
+    /// milliseconds
+    pub latency: u32,
+    /// AES encryption key, of 10 to 79 characters
+    pub passphrase: Option<String>,
+}
+
+impl SrtConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        match &self.passphrase {
+            Some(passphrase) if !(10..=79).contains(&passphrase.len()) => Err(String::from(
+                "invalid SRT passphrase, it must have 10 to 79 characters",
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+async fn resolve(url: &SrtUrl) -> io::Result<SocketAddr> {
+    tokio::net::lookup_host((url.host.as_str(), url.port))
+        .await?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address for the host"))
+}
+
+/// Connect to the listener of the url, or wait on its address for the next caller.
+async fn connect(config: &SrtConfig) -> io::Result<SrtSocket> {
+    let address = resolve(&config.url).await?;
+    let mut builder = SrtSocket::builder().latency(Duration::from_millis(config.latency as u64));
+    if let Some(passphrase) = &config.passphrase {
+        builder = builder.encryption(KEY_SIZE, passphrase.as_str());
+    }
+    match config.url.mode {
+        SrtMode::Caller => builder.call(address, config.url.stream_id.as_deref()).await,
+        SrtMode::Listener => builder.listen_on(address).await,
+    }
+}
+
+/// Encodes the interleaved samples as Opus and packs them in the SRT messages.
+struct Packetizer {
+    encoder: OpusPacketEncoder,
+    muxer: Option<TsMuxer>,
+    /// transport stream packets not sent yet
+    stream: Vec<u8>,
+    /// timestamp of the next Opus packet, in `mpegts::CLOCK_RATE` units
+    pts: u64,
+}
+
+impl Packetizer {
+    fn new(
+        payload: SrtPayload,
+        channels: u16,
+        sample_rate: u32,
+        bitrate: i32,
+    ) -> Result<Self, String> {
+        let encoder = OpusPacketEncoder::new(channels, sample_rate, bitrate)?;
+        let muxer = match payload {
+            SrtPayload::Ts => Some(TsMuxer::new(TsStream::Opus {
+                channels: encoder.channels(),
+            })),
+            SrtPayload::Opus => None,
+        };
+        Ok(Packetizer {
+            encoder,
+            muxer,
+            stream: Vec::new(),
+            pts: 0,
+        })
+    }
+
+    /// Encode a block of samples, returns the messages completed.
+    fn push(&mut self, samples: &[f32]) -> Result<Vec<Vec<u8>>, String> {
+        let packets = self.encoder.encode(samples)?;
+        let muxer = match &mut self.muxer {
+            Some(muxer) => muxer,
+            None => return Ok(packets),
+        };
+        for packet in packets {
+            self.stream
+                .extend_from_slice(&muxer.push(&packet, self.pts));
+            self.pts += OPUS_FRAME_LEN as u64 * mpegts::CLOCK_RATE / OPUS_SAMPLE_RATE as u64;
+        }
+        let len = self.stream.len() - self.stream.len() % MAX_MESSAGE_LEN;
+        Ok(self
+            .stream
+            .drain(..len)
+            .collect::<Vec<_>>()
+            .chunks(MAX_MESSAGE_LEN)
+            .map(|message| message.to_vec())
+            .collect())
+    }
+}
+
+/// Send the interleaved samples received from a broadcast over SRT, to each caller in
+/// turn or to the listener, reconnecting when the connection is lost, until the sender
+/// goes away. The failures are reported to the supervisor.
+pub async fn run_sender(
+    config: SrtConfig,
+    bitrate: i32,
+    channels: u16,
+    sample_rate: u32,
+    mut samples: mpsc::Receiver<Arc<Vec<f32>>>,
+    supervisor: Supervisor,
+) {
+    let endpoint = format!("{}:{}", config.url.host, config.url.port);
+    loop {
+        let mut socket = match connect(&config).await {
+            Ok(socket) => socket,
+            Err(err) => {
+                let message = match config.url.mode {
+                    SrtMode::Listener => {
+                        format!("failed to accept SRT caller on {}: {}", endpoint, err)
+                    }
+                    SrtMode::Caller => {
+                        format!("failed to connect to SRT listener {}: {}", endpoint, err)
+                    }
+                };
+                supervisor.report(Error::Output(message));
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                continue;
+            }
+        };
+
+        let mut packetizer = match Packetizer::new(config.payload, channels, sample_rate, bitrate) {
+            Ok(packetizer) => packetizer,
+            Err(err) => {
+                supervisor.report(Error::Output(format!("stopped the SRT stream: {}", err)));
+                return;
+            }
+        };
+
+        // discard the samples queued while disconnected
+        while samples.try_recv().is_ok() {}
+
+        loop {
+            let samples = match samples.recv().await {
+                Some(samples) => samples,
+                None => {
+                    socket.close().await.ok();
+                    return;
+                }
+            };
+            let messages = match packetizer.push(&samples) {
+                Ok(messages) => messages,
+                Err(err) => {
+                    supervisor.report(Error::Output(format!("stopped the SRT stream: {}", err)));
+                    return;
+                }
+            };
+            let mut result = Ok(());
+            for message in messages {
+                result = socket.send((Instant::now(), Bytes::from(message))).await;
+                if result.is_err() {
+                    break;
+                }
+            }
+            if let Err(err) = result {
+                supervisor.report(Error::Output(format!(
+                    "SRT connection on {} lost: {}",
+                    endpoint, err
+                )));
+                break;
+            }
+        }
+
+        if config.url.mode == SrtMode::Caller {
+            tokio::time::sleep(RECONNECT_DELAY).await;
+        }
+    }
+}
@@ -0,0 +1,69 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Server side of Server-Sent Events (`text/event-stream`),
+//! to push messages to the clients at a limited rate.
+
+use futures_util::{stream, Stream};
+use std::fmt::Write;
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+/// Events per second sent to each client, by default.
+pub const DEFAULT_MAX_RATE: f64 = 10.0;
+
+pub const CONTENT_TYPE: &str = "text/event-stream";
+
+/// A message event, with one `data` field per line of the message.
+pub fn event(data: &str) -> String {
+    let mut event = String::with_capacity(data.len() + 8);
+    for line in data.lines() {
+        writeln!(event, "data: {}", line).unwrap();
+    }
+    event.push('\n');
+    event
+}
+
+/// The events of the received messages, the body of the response to a client,
+/// ending when the sender goes away.
+///
+/// Messages received less than `min_interval` after the last event are dropped,
+/// the others are converted to the event data with `to_data`.
+pub fn events<T, F>(
+    messages: mpsc::Receiver<Arc<T>>,
+    min_interval: Duration,
+    to_data: F,
+) -> impl Stream<Item = io::Result<String>> + Send
+where
+    T: Send + Sync + 'static,
+    F: Fn(&T) -> String + Send + 'static,
+{
+    stream::unfold(
+        (messages, None::<Instant>, to_data),
+        move |(mut messages, last_event, to_data)| async move {
+            loop {
+                let message = messages.recv().await?;
+                let now = Instant::now();
+                if last_event.is_some_and(|last_event| now - last_event < min_interval) {
+                    continue;
+                }
+                let event = event(&to_data(&message));
+                return Some((Ok(event), (messages, Some(now), to_data)));
+            }
+        },
+    )
+}
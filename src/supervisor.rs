@@ -0,0 +1,97 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Supervision of the capture and of the threads processing it: their errors, and their
+//! panics, are reported to a handler deciding whether to go on, rather than ending a thread
+//! silently while the others keep serving stale levels.
+
+use crate::error::{panic_message, Error};
+use futures_util::FutureExt;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc;
+use std::thread;
+
+/// Reports the errors to the thread handling them, cloned for each thread reporting them.
+#[derive(Clone)]
+pub struct Supervisor {
+    sender: mpsc::Sender<Error>,
+}
+
+impl Supervisor {
+    /// Start the thread handling the errors, in the order reported.
+    pub fn start<F>(mut handler: F) -> Self
+    where
+        F: FnMut(Error) + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for error in receiver {
+                handler(error);
+            }
+        });
+        Supervisor { sender }
+    }
+
+    pub fn report(&self, error: Error) {
+        self.sender.send(error).ok();
+    }
+
+    /// Spawn a thread, reporting its panic as [`Error::Panic`] of the thread `name`.
+    pub fn spawn<F>(&self, name: &str, f: F) -> thread::JoinHandle<()>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let supervisor = self.clone();
+        let name = name.to_owned();
+        thread::spawn(move || {
+            if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(f)) {
+                supervisor.report(Error::Panic {
+                    thread: name,
+                    message: panic_message(&*payload),
+                });
+            }
+        })
+    }
+
+    /// Spawn a task on the runtime, reporting its panic as [`Error::Panic`] of the task `name`.
+    pub fn spawn_task<F>(&self, name: &str, future: F) -> tokio::task::JoinHandle<()>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let supervisor = self.clone();
+        let name = name.to_owned();
+        tokio::spawn(async move {
+            if let Err(payload) = AssertUnwindSafe(future).catch_unwind().await {
+                supervisor.report(Error::Panic {
+                    thread: name,
+                    message: panic_message(&*payload),
+                });
+            }
+        })
+    }
+
+    /// Run the handling of a request, reporting its panic as [`Error::Request`],
+    /// `None` if it panicked.
+    pub async fn run_request<F: Future>(&self, future: F) -> Option<F::Output> {
+        match AssertUnwindSafe(future).catch_unwind().await {
+            Ok(output) => Some(output),
+            Err(payload) => {
+                self.report(Error::Request(panic_message(&*payload)));
+                None
+            }
+        }
+    }
+}
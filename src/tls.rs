@@ -0,0 +1,354 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! HTTPS and WSS for the http server, and HTTPS for the uploads: the configs of rustls,
+//! and the handshakes of tokio-rustls over the connections of the runtime.
+
+use crate::error::Error;
+use crate::supervisor::Supervisor;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::{ClientConfig, RootCertStore, ServerConfig};
+use std::convert::TryFrom;
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::{client, server, TlsAcceptor, TlsConnector};
+
+/// Time for the clients to complete the handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Delay before accepting again after failing to, e.g. out of file descriptors.
+const ACCEPT_ERROR_DELAY: Duration = Duration::from_secs(1);
+/// Clients with the handshake completed, waiting for the server.
+const ACCEPTED_CAPACITY: usize = 64;
+
+/// TLS config of the PEM certificate chain and private key.
+pub fn server_config(cert: &Path, key: &Path) -> Result<Arc<ServerConfig>, String> {
+    let certs = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|err| format!("failed to read '{}': {}", cert.display(), err))?;
+    let key = PrivateKeyDer::from_pem_file(key)
+        .map_err(|err| format!("failed to read '{}': {}", key.display(), err))?;
+    ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .and_then(|builder| builder.with_no_client_auth().with_single_cert(certs, key))
+        .map(Arc::new)
+        .map_err(|err| format!("invalid certificate or key: {}", err))
+}
+
+/// TLS config of the clients of the uploads, trusting the Mozilla root certificates.
+pub fn client_config() -> Arc<ClientConfig> {
+    let roots = RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+    ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .expect("the default protocol versions are supported")
+        .with_root_certificates(roots)
+        .with_no_client_auth()
+        .into()
+}
+
+/// Complete the handshake with the server `host`, verifying its certificate.
+pub async fn connect<T>(
+    io: T,
+    host: &str,
+    config: Arc<ClientConfig>,
+) -> io::Result<client::TlsStream<T>>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    let server_name = ServerName::try_from(host.to_owned())
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    TlsConnector::from(config).connect(server_name, io).await
+}
+
+/// The clients of the server over TLS, each handshake completed by a task of its own
+/// so a slow client does not delay the others. The failed handshakes are reported to the
+/// supervisor, but for the clients gone or not speaking TLS.
+pub struct TlsListener {
+    local_addr: SocketAddr,
+    accepted: mpsc::Receiver<(server::TlsStream<TcpStream>, SocketAddr)>,
+}
+
+impl TlsListener {
+    pub fn new(
+        listener: TcpListener,
+        config: Arc<ServerConfig>,
+        supervisor: Supervisor,
+    ) -> io::Result<Self> {
+        let acceptor = TlsAcceptor::from(config);
+        let local_addr = listener.local_addr()?;
+        let (sender, accepted) = mpsc::channel(ACCEPTED_CAPACITY);
+        tokio::spawn(async move {
+            while !sender.is_closed() {
+                let (client, address) = match listener.accept().await {
+                    Ok(client) => client,
+                    Err(_) => {
+                        tokio::time::sleep(ACCEPT_ERROR_DELAY).await;
+                        continue;
+                    }
+                };
+                let acceptor = acceptor.clone();
+                let sender = sender.clone();
+                let supervisor = supervisor.clone();
+                tokio::spawn(async move {
+                    match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(client)).await {
+                        Ok(Ok(stream)) => {
+                            sender.send((stream, address)).await.ok();
+                        }
+                        // the clients of the streams usually end with a broken connection,
+                        // and invalid data is from the clients that do not speak TLS
+                        Ok(Err(err))
+                            if !matches!(
+                                err.kind(),
+                                io::ErrorKind::BrokenPipe
+                                    | io::ErrorKind::ConnectionReset
+                                    | io::ErrorKind::InvalidData
+                                    | io::ErrorKind::UnexpectedEof
+                            ) =>
+                        {
+                            supervisor.report(Error::Output(format!("TLS connection: {}", err)));
+                        }
+                        _ => {}
+                    }
+                });
+            }
+        });
+        Ok(TlsListener {
+            local_addr,
+            accepted,
+        })
+    }
+}
+
+impl axum::serve::Listener for TlsListener {
+    type Io = server::TlsStream<TcpStream>;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self.accepted.recv().await {
+            Some(client) => client,
+            None => std::future::pending().await,
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        Ok(self.local_addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::serve::Listener;
+    use std::path::PathBuf;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            tokio::time::timeout(Duration::from_secs(5), future)
+                .await
+                .unwrap()
+        })
+    }
+
+    /// A self-signed certificate of `localhost` and its key, as PEM files.
+    struct Certificate {
+        directory: PathBuf,
+        cert: PathBuf,
+        key: PathBuf,
+        der: CertificateDer<'static>,
+    }
+
+    impl Certificate {
+        fn new(name: &str) -> Self {
+            let certified =
+                rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+            let directory = std::env::temp_dir().join(format!(
+                "audio-in-stream-rs-tls-{}-{}",
+                name,
+                std::process::id()
+            ));
+            std::fs::create_dir_all(&directory).unwrap();
+            let cert = directory.join("cert.pem");
+            let key = directory.join("key.pem");
+            std::fs::write(&cert, certified.cert.pem()).unwrap();
+            std::fs::write(&key, certified.signing_key.serialize_pem()).unwrap();
+            Certificate {
+                directory,
+                cert,
+                key,
+                der: certified.cert.der().clone(),
+            }
+        }
+
+        fn server_config(&self) -> Arc<ServerConfig> {
+            server_config(&self.cert, &self.key).unwrap()
+        }
+
+        /// A client config trusting only the certificate.
+        fn client_config(&self) -> Arc<ClientConfig> {
+            let mut roots = RootCertStore::empty();
+            roots.add(self.der.clone()).unwrap();
+            ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_root_certificates(roots)
+                .with_no_client_auth()
+                .into()
+        }
+    }
+
+    impl Drop for Certificate {
+        fn drop(&mut self) {
+            std::fs::remove_dir_all(&self.directory).ok();
+        }
+    }
+
+    #[test]
+    fn pem_files() {
+        let certificate = Certificate::new("pem");
+        assert!(server_config(&certificate.cert, &certificate.key).is_ok());
+
+        let missing = certificate.directory.join("missing.pem");
+        let err = server_config(&missing, &certificate.key).err().unwrap();
+        assert!(err.starts_with("failed to read '"), "{}", err);
+        assert!(err.contains("missing.pem"), "{}", err);
+        // a certificate is not a key
+        let err = server_config(&certificate.cert, &certificate.cert)
+            .err()
+            .unwrap();
+        assert!(err.contains("cert.pem"), "{}", err);
+        // the key of another certificate
+        let other = Certificate::new("pem-other");
+        let err = server_config(&certificate.cert, &other.key).err().unwrap();
+        assert!(err.starts_with("invalid certificate or key"), "{}", err);
+    }
+
+    #[test]
+    fn exchange() {
+        let certificate = Certificate::new("exchange");
+        block_on(async {
+            let (client, server) = tokio::io::duplex(1024);
+            let acceptor = TlsAcceptor::from(certificate.server_config());
+            let server = tokio::spawn(async move {
+                let mut stream = acceptor.accept(server).await.unwrap();
+                let mut request = [0; 4];
+                stream.read_exact(&mut request).await.unwrap();
+                assert_eq!(&request, b"ping");
+                // more than the buffer of the connection
+                stream.write_all(&[7; 4096]).await.unwrap();
+                stream.shutdown().await.unwrap();
+            });
+
+            let mut stream = connect(client, "localhost", certificate.client_config())
+                .await
+                .unwrap();
+            stream.write_all(b"ping").await.unwrap();
+            stream.flush().await.unwrap();
+            let mut response = Vec::new();
+            stream.read_to_end(&mut response).await.unwrap();
+            assert_eq!(response, vec![7; 4096]);
+            server.await.unwrap();
+        });
+    }
+
+    #[test]
+    fn rejected_servers() {
+        let certificate = Certificate::new("rejected");
+        block_on(async {
+            // not signed by the Mozilla root certificates
+            let (client, server) = tokio::io::duplex(1024);
+            let acceptor = TlsAcceptor::from(certificate.server_config());
+            let server = tokio::spawn(async move { acceptor.accept(server).await.is_err() });
+            let err = connect(client, "localhost", client_config())
+                .await
+                .err()
+                .unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            assert!(server.await.unwrap());
+
+            // the certificate of another name
+            let (client, server) = tokio::io::duplex(1024);
+            let acceptor = TlsAcceptor::from(certificate.server_config());
+            let server = tokio::spawn(async move { acceptor.accept(server).await.is_err() });
+            let err = connect(client, "example.com", certificate.client_config())
+                .await
+                .err()
+                .unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            assert!(server.await.unwrap());
+
+            let (client, _server) = tokio::io::duplex(1024);
+            let err = connect(client, "not a host", certificate.client_config())
+                .await
+                .err()
+                .unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        });
+    }
+
+    #[test]
+    fn listener() {
+        let certificate = Certificate::new("listener");
+        let (errors, reported) = std::sync::mpsc::channel();
+        let supervisor = Supervisor::start(move |err| errors.send(err).unwrap());
+        block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = listener.local_addr().unwrap();
+            let mut listener =
+                TlsListener::new(listener, certificate.server_config(), supervisor).unwrap();
+            assert_eq!(listener.local_addr().unwrap(), address);
+
+            // a client not speaking TLS, and a client never completing the handshake,
+            // do not delay the others
+            let mut plain = TcpStream::connect(address).await.unwrap();
+            plain.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+            let _silent = TcpStream::connect(address).await.unwrap();
+
+            let client = TcpStream::connect(address).await.unwrap();
+            let client_address = client.local_addr().unwrap();
+            let client = tokio::spawn(async move {
+                let mut stream = connect(client, "localhost", certificate.client_config())
+                    .await
+                    .unwrap();
+                stream.write_all(b"ping").await.unwrap();
+                stream.shutdown().await.unwrap();
+            });
+            let (mut stream, accepted_address) = listener.accept().await;
+            assert_eq!(accepted_address, client_address);
+            let mut request = Vec::new();
+            stream.read_to_end(&mut request).await.unwrap();
+            assert_eq!(request, b"ping");
+            client.await.unwrap();
+
+            // the plaintext client is answered with an alert and closed
+            let mut response = Vec::new();
+            plain.read_to_end(&mut response).await.ok();
+        });
+        // not speaking TLS is not an error of the server
+        assert!(reported.try_recv().is_err());
+    }
+}
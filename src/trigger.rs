@@ -0,0 +1,139 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Level-triggered recording: a new file from a few seconds before the level
+//! exceeds a threshold, until it stays below it for a hang time.
+
+use crate::recording::{CompletedRecording, Recorder};
+use std::collections::VecDeque;
+use std::io;
+use std::time::{Duration, SystemTime};
+
+/// seconds
+pub const DEFAULT_PRE_ROLL: f64 = 5.0;
+pub const DEFAULT_HANG_TIME: f64 = 10.0;
+/// seconds, the pre-roll being kept in memory
+pub const MAX_PRE_ROLL: f64 = 300.0;
+
+#[derive(Clone, Copy, Debug)]
+pub struct TriggerConfig {
+    /// linear RMS level starting the recording
+    pub threshold: f32,
+    /// seconds recorded before the level exceeds the threshold
+    pub pre_roll: f64,
+    /// seconds below the threshold ending the recording
+    pub hang_time: f64,
+}
+
+/// Starts and ends the files of a recorder with the RMS level of the loudest channel
+/// of each block of samples.
+pub struct LevelTrigger {
+    config: TriggerConfig,
+    channels: usize,
+    sample_rate: u32,
+    /// interleaved samples of the last `pre_roll` seconds, while not recording
+    pre_roll: VecDeque<f32>,
+    /// number of samples of the pre-roll
+    max_len: usize,
+    /// `None` while not recording, otherwise the seconds below the threshold
+    below: Option<f64>,
+}
+
+impl LevelTrigger {
+    pub fn new(config: TriggerConfig, channels: usize, sample_rate: u32) -> Result<Self, String> {
+        let max_len = ((config.pre_roll * sample_rate as f64) as usize)
+            .checked_mul(channels)
+            .ok_or_else(|| format!("the pre-roll of {} s is too long", config.pre_roll))?;
+        Ok(LevelTrigger {
+            config,
+            channels,
+            sample_rate,
+            pre_roll: VecDeque::new(),
+            max_len,
+            below: None,
+        })
+    }
+
+    /// Forget the pre-roll and the recording in progress, once the recorder is finalized.
+    pub fn reset(&mut self) {
+        self.pre_roll.clear();
+        self.below = None;
+    }
+
+    /// RMS level of the loudest channel of the interleaved samples.
+    fn level(&self, samples: &[f32]) -> f32 {
+        let num_frames = samples.len() / self.channels;
+        if num_frames == 0 {
+            return 0.0;
+        }
+        (0..self.channels)
+            .map(|channel| {
+                let square_sum: f32 = samples
+                    .iter()
+                    .skip(channel)
+                    .step_by(self.channels)
+                    .map(|sample| sample * sample)
+                    .sum();
+                (square_sum / num_frames as f32).sqrt()
+            })
+            .fold(0.0, f32::max)
+    }
+
+    /// Write to the recorder the interleaved samples captured at the time of the first frame,
+    /// with the pre-roll when the level exceeds the threshold, or keep them as pre-roll,
+    /// returns the recordings completed.
+    pub fn process(
+        &mut self,
+        recorder: &mut Recorder,
+        samples: &[f32],
+        timestamp: SystemTime,
+    ) -> io::Result<Vec<CompletedRecording>> {
+        let duration = (samples.len() / self.channels) as f64 / self.sample_rate as f64;
+        let above = self.level(samples) >= self.config.threshold;
+
+        let below = match self.below {
+            Some(below) => below,
+            None if above => {
+                let pre_roll: Vec<f32> = self.pre_roll.drain(..).collect();
+                let pre_roll_duration =
+                    (pre_roll.len() / self.channels) as f64 / self.sample_rate as f64;
+                let pre_roll_start = timestamp
+                    .checked_sub(Duration::from_secs_f64(pre_roll_duration))
+                    .unwrap_or(timestamp);
+                let mut completed = recorder.write(&pre_roll, pre_roll_start)?;
+                completed.extend(recorder.write(samples, timestamp)?);
+                self.below = Some(0.0);
+                return Ok(completed);
+            }
+            None => {
+                // keep only the last frames of the pre-roll
+                self.pre_roll.extend(samples);
+                let excess = self.pre_roll.len().saturating_sub(self.max_len);
+                self.pre_roll.drain(..excess);
+                return Ok(Vec::new());
+            }
+        };
+
+        let mut completed = recorder.write(samples, timestamp)?;
+        let below = if above { 0.0 } else { below + duration };
+        if below >= self.config.hang_time {
+            completed.extend(recorder.finalize()?);
+            self.below = None;
+        } else {
+            self.below = Some(below);
+        }
+        Ok(completed)
+    }
+}
@@ -0,0 +1,115 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! True-peak metering per ITU-R BS.1770 Annex 2, the peaks between samples
+//! found by 4x oversampling.
+
+use crate::ChannelData;
+
+const TAPS_PER_PHASE: usize = 12;
+
+/// Polyphase FIR interpolation filter of BS.1770, one row for each of the 4 phases.
+const INTERPOLATION_FILTER: [[f64; TAPS_PER_PHASE]; 4] = [
+    [
+        0.0017089843750,
+        0.0109863281250,
+        -0.0196533203125,
+        0.0332031250000,
+        -0.0594482421875,
+        0.1373291015625,
+        0.9721679687500,
+        -0.1022949218750,
+        0.0476074218750,
+        -0.0266113281250,
+        0.0148925781250,
+        -0.0083007812500,
+    ],
+    [
+        -0.0291748046875,
+        0.0292968750000,
+        -0.0517578125000,
+        0.0891113281250,
+        -0.1665039062500,
+        0.4650878906250,
+        0.7797851562500,
+        -0.2003173828125,
+        0.1015625000000,
+        -0.0582275390625,
+        0.0330810546875,
+        -0.0189208984375,
+    ],
+    [
+        -0.0189208984375,
+        0.0330810546875,
+        -0.0582275390625,
+        0.1015625000000,
+        -0.2003173828125,
+        0.7797851562500,
+        0.4650878906250,
+        -0.1665039062500,
+        0.0891113281250,
+        -0.0517578125000,
+        0.0292968750000,
+        -0.0291748046875,
+    ],
+    [
+        -0.0083007812500,
+        0.0148925781250,
+        -0.0266113281250,
+        0.0476074218750,
+        -0.1022949218750,
+        0.9721679687500,
+        0.1373291015625,
+        -0.0594482421875,
+        0.0332031250000,
+        -0.0196533203125,
+        0.0109863281250,
+        0.0017089843750,
+    ],
+];
+
+pub struct TruePeakMeter {
+    /// latest samples of each channel, newest first
+    history: Vec<[f64; TAPS_PER_PHASE]>,
+}
+
+impl TruePeakMeter {
+    pub fn new(num_channels: usize) -> Self {
+        TruePeakMeter {
+            history: vec![[0.0; TAPS_PER_PHASE]; num_channels],
+        }
+    }
+
+    /// True-peak of each channel in an input buffer, as a linear absolute value.
+    pub fn process(&mut self, channels: &[ChannelData]) -> Vec<f32> {
+        channels
+            .iter()
+            .zip(&mut self.history)
+            .map(|(channel, history)| {
+                let mut peak = 0.0_f64;
+                for &sample in &channel.samples {
+                    history.copy_within(..TAPS_PER_PHASE - 1, 1);
+                    history[0] = sample as f64;
+                    for phase in &INTERPOLATION_FILTER {
+                        let value: f64 = phase.iter().zip(history.iter()).map(|(a, b)| a * b).sum();
+                        peak = peak.max(value.abs());
+                    }
+                    peak = peak.max(history[0].abs());
+                }
+                peak as f32
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,426 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Terminal user interface: a meter per channel with color zones and peak hold,
+//! the device and format, the analysis of the levels, the recent warnings,
+//! and keyboard shortcuts to pause the meter, reset the peaks, start and stop the recording,
+//! mute the channels, adjust their gain and quit.
+
+use crate::control::{ControlCommand, Controls};
+use crate::decibels_overload;
+use crate::levels::Levels;
+use crate::meter::MeterReading;
+use crate::meter_scale::{MeterCell, MeterScale, Zone};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Paragraph};
+use ratatui::DefaultTerminal;
+use std::collections::VecDeque;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// characters of the flags after the levels of a channel
+const FLAGS_WIDTH: usize = " OVER SILENT DC MUTE".len();
+/// longest wait for a key, a new frame is drawn within it
+const POLL_TIMEOUT: Duration = Duration::from_millis(50);
+/// warnings kept, and printed once the terminal is restored
+const MAX_MESSAGES: usize = 100;
+/// warnings shown
+const SHOWN_MESSAGES: usize = 5;
+/// step of the gain of the `+` and `-` keys, in dB
+const GAIN_STEP: f32 = 1.0;
+
+/// What is displayed for an input buffer.
+pub struct TuiFrame {
+    pub levels: Arc<Levels>,
+    pub meter_readings: Vec<MeterReading>,
+    /// more lines of the analysis of the levels, e.g. the correlation or the spectrum
+    pub lines: Vec<String>,
+}
+
+/// Commands of the keyboard shortcuts, handled out of the interface.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TuiCommand {
+    Quit,
+    ResetPeaks,
+    Snapshot,
+    Control(ControlCommand),
+}
+
+/// Sender of the frames and warnings to the interface, from any thread.
+#[derive(Clone)]
+pub struct TuiSender {
+    frames: mpsc::SyncSender<TuiFrame>,
+    messages: mpsc::Sender<String>,
+}
+
+impl TuiSender {
+    /// Display the frame, unless the interface is still drawing the previous one.
+    pub fn frame(&self, frame: TuiFrame) {
+        self.frames.try_send(frame).ok();
+    }
+
+    /// Show a warning, written to the standard error would break the interface.
+    pub fn message(&self, message: String) {
+        self.messages.send(message).ok();
+    }
+}
+
+/// The interface, drawn from its own thread until stopped.
+pub struct Tui {
+    sender: TuiSender,
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<Vec<String>>,
+}
+
+impl Tui {
+    /// Take over the terminal, `header` describes the device and its format,
+    /// the meters are drawn in `meter_scale` and in color if `color`,
+    /// the state of the `controls` is shown and changed with [`TuiCommand::Control`],
+    /// `on_command` is called from the thread of the interface.
+    pub fn start<F>(
+        header: String,
+        meter_scale: MeterScale,
+        color: bool,
+        controls: Controls,
+        on_command: F,
+    ) -> io::Result<Self>
+    where
+        F: FnMut(TuiCommand) + Send + 'static,
+    {
+        let terminal = ratatui::try_init()?;
+        let (frames, frame_receiver) = mpsc::sync_channel(1);
+        let (messages, message_receiver) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = Arc::clone(&stop);
+        let thread = thread::spawn(move || {
+            let mut state = TuiState {
+                header,
+                meter_scale,
+                color,
+                frame: None,
+                messages: VecDeque::new(),
+                controls,
+            };
+            if let Err(err) = state.run(
+                terminal,
+                &frame_receiver,
+                &message_receiver,
+                &thread_stop,
+                on_command,
+            ) {
+                state.push_message(format!("error: terminal interface: {}", err));
+            }
+            ratatui::restore();
+            state.messages.into_iter().collect()
+        });
+        Ok(Tui {
+            sender: TuiSender { frames, messages },
+            stop,
+            thread,
+        })
+    }
+
+    pub fn sender(&self) -> TuiSender {
+        self.sender.clone()
+    }
+
+    /// Restore the terminal, returns the warnings shown to print them once restored.
+    pub fn stop(self) -> Vec<String> {
+        self.stop.store(true, Ordering::Relaxed);
+        self.thread.join().unwrap_or_default()
+    }
+}
+
+struct TuiState {
+    header: String,
+    meter_scale: MeterScale,
+    color: bool,
+    frame: Option<TuiFrame>,
+    messages: VecDeque<String>,
+    controls: Controls,
+}
+
+impl TuiState {
+    fn run<F>(
+        &mut self,
+        mut terminal: DefaultTerminal,
+        frames: &mpsc::Receiver<TuiFrame>,
+        messages: &mpsc::Receiver<String>,
+        stop: &AtomicBool,
+        mut on_command: F,
+    ) -> io::Result<()>
+    where
+        F: FnMut(TuiCommand),
+    {
+        while !stop.load(Ordering::Relaxed) {
+            // the latest frame, unless paused
+            for frame in frames.try_iter() {
+                if !self.controls.is_meter_paused() {
+                    self.frame = Some(frame);
+                }
+            }
+            for message in messages.try_iter() {
+                self.push_message(message);
+            }
+            terminal.draw(|frame| self.draw(frame))?;
+
+            if event::poll(POLL_TIMEOUT)? {
+                if let Event::Key(key) = event::read()? {
+                    if key.kind != KeyEventKind::Press {
+                        continue;
+                    }
+                    match key.code {
+                        KeyCode::Char('q') | KeyCode::Esc => on_command(TuiCommand::Quit),
+                        KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                            on_command(TuiCommand::Quit)
+                        }
+                        KeyCode::Char('p') | KeyCode::Char(' ') => {
+                            on_command(TuiCommand::Control(if self.controls.is_meter_paused() {
+                                ControlCommand::ResumeMeter
+                            } else {
+                                ControlCommand::PauseMeter
+                            }))
+                        }
+                        KeyCode::Char('r') => on_command(TuiCommand::ResetPeaks),
+                        KeyCode::Char('s') => on_command(TuiCommand::Snapshot),
+                        KeyCode::Char('c') => {
+                            on_command(TuiCommand::Control(if self.controls.is_recording() {
+                                ControlCommand::StopRecording
+                            } else {
+                                ControlCommand::StartRecording
+                            }))
+                        }
+                        KeyCode::Char(digit @ '0'..='9') => {
+                            let channel = digit as usize - '0' as usize;
+                            let muted = channel < self.controls.gains().num_channels()
+                                && self.controls.gains().is_muted(channel);
+                            on_command(TuiCommand::Control(if muted {
+                                ControlCommand::Unmute {
+                                    channel: Some(channel),
+                                }
+                            } else {
+                                ControlCommand::Mute {
+                                    channel: Some(channel),
+                                }
+                            }))
+                        }
+                        KeyCode::Char('+') | KeyCode::Char('=') => {
+                            on_command(TuiCommand::Control(ControlCommand::AdjustGain {
+                                channel: None,
+                                db: GAIN_STEP,
+                            }))
+                        }
+                        KeyCode::Char('-') => {
+                            on_command(TuiCommand::Control(ControlCommand::AdjustGain {
+                                channel: None,
+                                db: -GAIN_STEP,
+                            }))
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn push_message(&mut self, message: String) {
+        if self.messages.len() == MAX_MESSAGES {
+            self.messages.pop_front();
+        }
+        self.messages.push_back(message);
+    }
+
+    fn draw(&self, frame: &mut ratatui::Frame) {
+        let num_channels = self
+            .frame
+            .as_ref()
+            .map_or(0, |tui_frame| tui_frame.levels.channels.len());
+        let num_messages = self.messages.len().min(SHOWN_MESSAGES);
+        let [header_area, channels_area, lines_area, messages_area, keys_area] =
+            Layout::vertical([
+                Constraint::Length(2),
+                Constraint::Length(num_channels as u16 + 2),
+                Constraint::Min(0),
+                Constraint::Length(num_messages as u16),
+                Constraint::Length(1),
+            ])
+            .areas(frame.area());
+
+        let mut header = vec![Span::raw(self.header.as_str())];
+        if self.controls.is_meter_paused() {
+            header.push(Span::styled(
+                "  PAUSED",
+                Style::default().add_modifier(Modifier::REVERSED),
+            ));
+        }
+        if !self.controls.is_recording() {
+            header.push(Span::styled(
+                "  RECORDING STOPPED",
+                Style::default().add_modifier(Modifier::REVERSED),
+            ));
+        }
+        let mut header_lines = vec![Line::from(header)];
+        if let Some(ref tui_frame) = self.frame {
+            let levels = &tui_frame.levels;
+            let loudness = &levels.loudness;
+            header_lines.push(Line::from(format!(
+                "M: {:>+5.1} S: {:>+5.1} I: {:>+5.1} LUFS{}",
+                loudness.momentary,
+                loudness.short_term,
+                loudness.integrated,
+                if levels.weighting == "Z" {
+                    String::new()
+                } else {
+                    format!(", {}-weighted levels", levels.weighting)
+                }
+            )));
+        }
+        frame.render_widget(Paragraph::new(header_lines), header_area);
+
+        let block = Block::bordered().title(" levels ");
+        let meters_area = block.inner(channels_area);
+        frame.render_widget(block, channels_area);
+        if let Some(ref tui_frame) = self.frame {
+            let meter_lines: Vec<Line> = tui_frame
+                .levels
+                .channels
+                .iter()
+                .zip(&tui_frame.meter_readings)
+                .map(|(channel, meter_reading)| {
+                    let level = decibels_overload(meter_reading.level);
+                    let peak = decibels_overload(meter_reading.peak);
+                    let mut status = format!(
+                        " {:>+5.1} {:<4} {:>+5.1} dBTP",
+                        self.meter_scale.convert(level),
+                        self.meter_scale.unit.name(),
+                        peak
+                    );
+                    if let Some(spl) = channel.spl {
+                        status.push_str(&format!(" {:>5.1} dB SPL", spl));
+                    }
+                    status.push_str(&format!(" {:>4} clips", channel.clipping.count));
+                    let label = format!("ch {:>2} ", channel.channel);
+                    let width = (meters_area.width as usize)
+                        .saturating_sub(label.len() + "[]".len() + status.len() + FLAGS_WIDTH);
+                    let mut spans = vec![Span::raw(label), Span::raw("[")];
+                    spans.extend(meter_spans(
+                        &self.meter_scale.cells(level, peak, width),
+                        self.color,
+                    ));
+                    spans.push(Span::raw("]"));
+                    spans.push(Span::raw(status));
+                    let flag = |text: &'static str, color: Color, on: bool| {
+                        let style = Style::default().add_modifier(Modifier::BOLD);
+                        Span::styled(
+                            if on { text } else { "" },
+                            if self.color { style.fg(color) } else { style },
+                        )
+                    };
+                    spans.push(flag(" OVER", Color::Red, meter_reading.over));
+                    spans.push(flag(
+                        " SILENT",
+                        Color::Yellow,
+                        channel.silent_since.is_some(),
+                    ));
+                    spans.push(flag(" DC", Color::Yellow, channel.dc_offset_warning));
+                    spans.push(flag(
+                        " MUTE",
+                        Color::Yellow,
+                        self.controls.gains().is_muted(channel.channel),
+                    ));
+                    Line::from(spans)
+                })
+                .collect();
+            frame.render_widget(Paragraph::new(meter_lines), meters_area);
+
+            let lines: Vec<Line> = tui_frame
+                .lines
+                .iter()
+                .map(|line| Line::from(line.as_str()))
+                .collect();
+            frame.render_widget(Paragraph::new(lines), lines_area);
+        }
+
+        let messages: Vec<Line> = self
+            .messages
+            .iter()
+            .skip(self.messages.len() - num_messages)
+            .map(|message| {
+                let style = Style::default();
+                Line::styled(
+                    message.as_str(),
+                    if self.color {
+                        style.fg(Color::Yellow)
+                    } else {
+                        style
+                    },
+                )
+            })
+            .collect();
+        frame.render_widget(Paragraph::new(messages), messages_area);
+
+        frame.render_widget(
+            Paragraph::new(
+                "q: quit  p: pause  r: reset peaks  s: snapshot  c: record  0-9: mute  +/-: gain",
+            )
+            .style(Style::default().add_modifier(Modifier::DIM)),
+            keys_area,
+        );
+    }
+}
+
+/// Spans of the characters of a meter, in the color of their zone if enabled.
+fn meter_spans(cells: &[MeterCell], color: bool) -> Vec<Span<'static>> {
+    let zone_color = |zone: Zone| match zone {
+        Zone::Green => Color::Green,
+        Zone::Yellow => Color::Yellow,
+        Zone::Red => Color::Red,
+    };
+    // runs of characters of the same style
+    let mut spans: Vec<Span<'static>> = Vec::new();
+    let mut run = String::new();
+    let mut run_style = Style::default();
+    for &cell in cells {
+        let (character, style) = match cell {
+            MeterCell::Filled(character, zone) => {
+                (character, Style::default().fg(zone_color(zone)))
+            }
+            MeterCell::Peak(character, zone) => (
+                character,
+                Style::default()
+                    .fg(zone_color(zone))
+                    .add_modifier(Modifier::BOLD),
+            ),
+            MeterCell::Empty(character) => (character, Style::default().fg(Color::DarkGray)),
+        };
+        let style = if color { style } else { Style::default() };
+        if style != run_style && !run.is_empty() {
+            spans.push(Span::styled(std::mem::take(&mut run), run_style));
+        }
+        run_style = style;
+        run.push(character);
+    }
+    if !run.is_empty() {
+        spans.push(Span::styled(run, run_style));
+    }
+    spans
+}
@@ -0,0 +1,359 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Voice activity detection: whether someone is talking on each channel, unlike the
+//! silence detection misled by the noise of a line.
+//!
+//! Each frame of 30 to 60 ms is speech when the level of its speech band (300 Hz to
+//! 4 kHz) is above the noise floor, tracked as its minimum, and the band is periodic
+//! as a voice is, unlike noise: the autocorrelation of the band, from its power
+//! spectrum, peaks at a period of the pitch of a voice once divided by the one of the
+//! window (Boersma, 1993). The speech starts after a few such frames and ends after a
+//! hangover time without any, both stricter with the aggressiveness, from 0 to 3 as
+//! the modes of the WebRTC VAD.
+
+use crate::{unix_time, ChannelData};
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+pub const DEFAULT_AGGRESSIVENESS: u8 = 1;
+pub const MAX_AGGRESSIVENESS: u8 = 3;
+/// seconds at least, rounded up to a power of two of samples
+const FRAME_DURATION: f32 = 0.03;
+/// Hz
+const SPEECH_BAND: (f32, f32) = (300.0, 4000.0);
+/// Hz, of the pitch of a voice
+const PITCH_RANGE: (f32, f32) = (80.0, 400.0);
+/// dBFS, the level of the speech band below which a frame is never speech
+const MIN_LEVEL: f32 = -60.0;
+/// dB per second, the rise of the noise floor while the level is above it
+const NOISE_FLOOR_RISE: f32 = 3.0;
+/// seconds of the recent speech percentage
+const RECENT_DURATION: f32 = 60.0;
+
+/// Thresholds of each aggressiveness.
+struct Mode {
+    /// dB, of the level of the speech band above the noise floor
+    margin: f32,
+    /// lowest normalized autocorrelation of the speech band at the period of the
+    /// pitch, 1 for a periodic signal and about 0 for noise
+    periodicity: f32,
+    /// time of consecutive speech frames for the speech to start
+    onset: Duration,
+    /// time without speech frames for the speech to end
+    hangover: Duration,
+}
+
+const MODES: [Mode; MAX_AGGRESSIVENESS as usize + 1] = [
+    Mode {
+        margin: 6.0,
+        periodicity: 0.5,
+        onset: Duration::from_millis(60),
+        hangover: Duration::from_millis(400),
+    },
+    Mode {
+        margin: 9.0,
+        periodicity: 0.55,
+        onset: Duration::from_millis(80),
+        hangover: Duration::from_millis(300),
+    },
+    Mode {
+        margin: 12.0,
+        periodicity: 0.6,
+        onset: Duration::from_millis(100),
+        hangover: Duration::from_millis(250),
+    },
+    Mode {
+        margin: 15.0,
+        periodicity: 0.65,
+        onset: Duration::from_millis(120),
+        hangover: Duration::from_millis(200),
+    },
+];
+
+#[derive(Clone, Debug)]
+pub enum VadEvent {
+    /// someone is talking on the channel since the given time
+    SpeechStart { channel: usize, since: SystemTime },
+    /// the speech of the channel since the given time ended at the given time
+    SpeechEnd {
+        channel: usize,
+        since: SystemTime,
+        until: SystemTime,
+    },
+}
+
+/// Voice activity of a channel.
+#[derive(Clone, Debug, Serialize)]
+pub struct ChannelSpeech {
+    /// someone is talking
+    pub speech: bool,
+    /// time since the speech, in seconds since the Unix epoch, `null` if there is none
+    pub speech_since: Option<f64>,
+    /// percentage of the frames with speech since the start
+    pub speech_percent: f32,
+    /// percentage of the frames with speech in the last minute
+    pub recent_speech_percent: f32,
+}
+
+struct ChannelVad {
+    /// samples of the current frame
+    frame: Vec<f32>,
+    /// dBFS, of the speech band
+    noise_floor: Option<f32>,
+    /// start of the consecutive speech frames
+    onset_since: Option<SystemTime>,
+    /// end of the last speech frame
+    last_speech: Option<SystemTime>,
+    speech_since: Option<SystemTime>,
+    frames: u64,
+    speech_frames: u64,
+    /// whether each frame of the last minute is speech, and how many
+    recent: VecDeque<bool>,
+    recent_speech_frames: usize,
+}
+
+pub struct VoiceActivityDetector {
+    mode: &'static Mode,
+    sample_rate: u32,
+    frame_size: usize,
+    /// of twice the frame size, the frames padded with zeros for their autocorrelation
+    fft: Arc<dyn Fft<f32>>,
+    window_coefficients: Vec<f32>,
+    /// autocorrelation of the window, normalized
+    window_autocorrelation: Vec<f32>,
+    /// scale of the power of the bins to the mean square of the samples
+    power_scale: f32,
+    /// bins of the speech band
+    band: (usize, usize),
+    /// lags of the periods of the pitch of a voice
+    lags: (usize, usize),
+    recent_frames: usize,
+    channels: Vec<ChannelVad>,
+    buffer: Vec<Complex<f32>>,
+    scratch: Vec<Complex<f32>>,
+}
+
+impl VoiceActivityDetector {
+    /// `aggressiveness` from 0, the most speech, to [`MAX_AGGRESSIVENESS`], the least.
+    pub fn new(sample_rate: u32, num_channels: usize, aggressiveness: u8) -> Self {
+        let frame_size =
+            ((sample_rate as f32 * FRAME_DURATION).ceil() as usize).next_power_of_two();
+        let fft_size = 2 * frame_size;
+        let fft = FftPlanner::new().plan_fft_forward(fft_size);
+        let window_coefficients: Vec<f32> = (0..frame_size)
+            .map(|n| 0.5 - 0.5 * (2.0 * PI * n as f32 / frame_size as f32).cos())
+            .collect();
+        let window_energy: f32 = window_coefficients.iter().map(|w| w * w).sum();
+        let power_scale = 2.0 / (fft_size as f32 * window_energy);
+        let bin_width = sample_rate as f32 / fft_size as f32;
+        let band = (
+            (SPEECH_BAND.0 / bin_width).round().max(1.0) as usize,
+            ((SPEECH_BAND.1 / bin_width).round() as usize).min(fft_size / 2 - 1),
+        );
+        // up to half the frame, beyond which the autocorrelation of the window vanishes
+        let lags = (
+            (sample_rate as f32 / PITCH_RANGE.1).round() as usize,
+            ((sample_rate as f32 / PITCH_RANGE.0).round() as usize).min(frame_size / 2),
+        );
+        let window_autocorrelation = (0..=lags.1)
+            .map(|lag| {
+                window_coefficients[lag..]
+                    .iter()
+                    .zip(&window_coefficients)
+                    .map(|(a, b)| a * b)
+                    .sum::<f32>()
+                    / window_energy
+            })
+            .collect();
+        let recent_frames =
+            (RECENT_DURATION * sample_rate as f32 / frame_size as f32).round() as usize;
+        let scratch = vec![Complex::default(); fft.get_inplace_scratch_len()];
+        VoiceActivityDetector {
+            mode: &MODES[aggressiveness.min(MAX_AGGRESSIVENESS) as usize],
+            sample_rate,
+            frame_size,
+            fft,
+            window_coefficients,
+            window_autocorrelation,
+            power_scale,
+            band,
+            lags,
+            recent_frames,
+            channels: (0..num_channels)
+                .map(|_| ChannelVad {
+                    frame: Vec::with_capacity(frame_size),
+                    noise_floor: None,
+                    onset_since: None,
+                    last_speech: None,
+                    speech_since: None,
+                    frames: 0,
+                    speech_frames: 0,
+                    recent: VecDeque::with_capacity(recent_frames),
+                    recent_speech_frames: 0,
+                })
+                .collect(),
+            buffer: vec![Complex::default(); fft_size],
+            scratch,
+        }
+    }
+
+    /// Add the samples of an input buffer captured at the given time,
+    /// returns the start and end of the speech of each channel.
+    pub fn process(&mut self, channels: &[ChannelData], timestamp: SystemTime) -> Vec<VadEvent> {
+        let frame_size = self.frame_size;
+        let frame_duration = Duration::from_secs_f64(frame_size as f64 / self.sample_rate as f64);
+        let mut events = Vec::new();
+        for (position, channel) in channels.iter().enumerate() {
+            let mut offset = 0;
+            while offset < channel.samples.len() {
+                let frame = &mut self.channels[position].frame;
+                let len = (channel.samples.len() - offset).min(frame_size - frame.len());
+                frame.extend_from_slice(&channel.samples[offset..offset + len]);
+                offset += len;
+                if frame.len() < frame_size {
+                    continue;
+                }
+                // capture time of the end of the frame
+                let end =
+                    timestamp + Duration::from_secs_f64(offset as f64 / self.sample_rate as f64);
+                let (level, periodicity) = self.measure(position);
+                let mode = self.mode;
+                let recent_frames = self.recent_frames;
+                let vad = &mut self.channels[position];
+                vad.frame.clear();
+
+                let noise_floor = vad.noise_floor.get_or_insert(level);
+                let is_speech = level > MIN_LEVEL
+                    && level > *noise_floor + mode.margin
+                    && periodicity > mode.periodicity;
+                if level < *noise_floor {
+                    *noise_floor = level;
+                } else {
+                    *noise_floor += NOISE_FLOOR_RISE * frame_duration.as_secs_f32();
+                }
+
+                if is_speech {
+                    let onset_since = *vad.onset_since.get_or_insert(end - frame_duration);
+                    vad.last_speech = Some(end);
+                    if vad.speech_since.is_none()
+                        && end.duration_since(onset_since).unwrap_or_default() >= mode.onset
+                    {
+                        vad.speech_since = Some(onset_since);
+                        events.push(VadEvent::SpeechStart {
+                            channel: channel.index,
+                            since: onset_since,
+                        });
+                    }
+                } else {
+                    vad.onset_since = None;
+                    if let (Some(since), Some(last_speech)) = (vad.speech_since, vad.last_speech) {
+                        if end.duration_since(last_speech).unwrap_or_default() >= mode.hangover {
+                            vad.speech_since = None;
+                            events.push(VadEvent::SpeechEnd {
+                                channel: channel.index,
+                                since,
+                                until: last_speech,
+                            });
+                        }
+                    }
+                }
+
+                let speech = vad.speech_since.is_some();
+                vad.frames += 1;
+                vad.speech_frames += speech as u64;
+                vad.recent.push_back(speech);
+                vad.recent_speech_frames += speech as usize;
+                if vad.recent.len() > recent_frames {
+                    vad.recent_speech_frames -= vad.recent.pop_front().unwrap_or_default() as usize;
+                }
+            }
+        }
+        events
+    }
+
+    /// Voice activity of each channel.
+    pub fn speech(&self) -> Vec<ChannelSpeech> {
+        let percent = |frames: f32, total: f32| {
+            if total > 0.0 {
+                100.0 * frames / total
+            } else {
+                0.0
+            }
+        };
+        self.channels
+            .iter()
+            .map(|vad| ChannelSpeech {
+                speech: vad.speech_since.is_some(),
+                speech_since: vad.speech_since.map(unix_time),
+                speech_percent: percent(vad.speech_frames as f32, vad.frames as f32),
+                recent_speech_percent: percent(
+                    vad.recent_speech_frames as f32,
+                    vad.recent.len() as f32,
+                ),
+            })
+            .collect()
+    }
+
+    /// the level in dBFS of the speech band of the frame of the channel, and its
+    /// periodicity
+    fn measure(&mut self, position: usize) -> (f32, f32) {
+        let frame = &self.channels[position].frame;
+        for (position, value) in self.buffer.iter_mut().enumerate() {
+            *value = match (frame.get(position), self.window_coefficients.get(position)) {
+                (Some(&sample), Some(&coefficient)) => Complex::new(sample * coefficient, 0.0),
+                _ => Complex::default(),
+            };
+        }
+        self.fft
+            .process_with_scratch(&mut self.buffer, &mut self.scratch);
+
+        // the power spectrum of the band, the bins of negative frequencies mirrored
+        let fft_size = self.buffer.len();
+        let mut sum = 0.0;
+        for bin in 0..fft_size {
+            let frequency_bin = bin.min(fft_size - bin);
+            let power = if (self.band.0..=self.band.1).contains(&frequency_bin) {
+                self.buffer[bin].norm_sqr()
+            } else {
+                0.0
+            };
+            if bin <= fft_size / 2 {
+                sum += power;
+            }
+            self.buffer[bin] = Complex::new(power, 0.0);
+        }
+        // the mean square of a sine is 3 dB under its level in dBFS
+        let level = (10.0 * (sum * self.power_scale * 2.0).log10()).max(-120.0);
+        if sum <= 0.0 {
+            return (level, 0.0);
+        }
+
+        // the forward FFT of the real and even power spectrum is its inverse, the
+        // autocorrelation, scaled by the FFT size
+        self.fft
+            .process_with_scratch(&mut self.buffer, &mut self.scratch);
+        let energy = self.buffer[0].re;
+        let periodicity = (self.lags.0..=self.lags.1)
+            .map(|lag| self.buffer[lag].re / energy / self.window_autocorrelation[lag])
+            .fold(0.0, f32::max);
+        (level, periodicity)
+    }
+}
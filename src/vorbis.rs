@@ -0,0 +1,137 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Ogg/Vorbis encoding of a stream of interleaved samples, with vorbis_rs.
+
+use crate::encoder::StreamEncoder;
+use std::convert::TryFrom;
+use std::io::{self, Write};
+use std::num::{NonZeroU32, NonZeroU8};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use vorbis_rs::{VorbisBitrateManagementStrategy, VorbisEncoder, VorbisEncoderBuilder};
+
+pub const DEFAULT_BITRATE: i32 = 192000;
+
+/// The Ogg pages written by the encoder, until taken.
+#[derive(Clone, Default)]
+struct Pages(Arc<Mutex<Vec<u8>>>);
+
+impl Pages {
+    fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.0.lock().unwrap_or_else(|err| err.into_inner()))
+    }
+}
+
+impl Write for Pages {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Encodes interleaved samples as Ogg/Vorbis, at an average bitrate.
+pub struct OggVorbisEncoder {
+    /// `None` once finished
+    encoder: Option<VorbisEncoder<Pages>>,
+    channels: usize,
+    pages: Pages,
+}
+
+// the libvorbis state has no thread affinity, it is only used from one thread at a time
+unsafe impl Send for OggVorbisEncoder {}
+
+impl OggVorbisEncoder {
+    /// Create the encoder, with the Ogg/Vorbis headers already written.
+    pub fn new(channels: u16, sample_rate: u32, bitrate: i32) -> Result<Self, String> {
+        let invalid = || {
+            format!(
+                "invalid Vorbis encoder parameters, {} channel(s) at {} Hz and {} bit/s",
+                channels, sample_rate, bitrate
+            )
+        };
+        let serial = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.subsec_nanos());
+        let pages = Pages::default();
+        let encoder = VorbisEncoderBuilder::new_with_serial(
+            NonZeroU32::new(sample_rate).ok_or_else(invalid)?,
+            u8::try_from(channels)
+                .ok()
+                .and_then(NonZeroU8::new)
+                .ok_or_else(invalid)?,
+            pages.clone(),
+            serial as i32,
+        )
+        .bitrate_management_strategy(VorbisBitrateManagementStrategy::Abr {
+            average_bitrate: u32::try_from(bitrate)
+                .ok()
+                .and_then(NonZeroU32::new)
+                .ok_or_else(invalid)?,
+        })
+        .build()
+        .map_err(|err| format!("{}, {}", invalid(), err))?;
+        Ok(OggVorbisEncoder {
+            encoder: Some(encoder),
+            channels: channels as usize,
+            pages,
+        })
+    }
+}
+
+impl StreamEncoder for OggVorbisEncoder {
+    fn encode(&mut self, samples: &[f32]) -> Result<(), String> {
+        let channels = self.channels;
+        let encoder = match &mut self.encoder {
+            Some(encoder) => encoder,
+            None => return Err(String::from("the Vorbis stream is already finished")),
+        };
+        if samples.len() < channels {
+            return Ok(());
+        }
+        let block: Vec<Vec<f32>> = (0..channels)
+            .map(|channel| {
+                samples
+                    .chunks_exact(channels)
+                    .map(|frame| frame[channel])
+                    .collect()
+            })
+            .collect();
+        encoder
+            .encode_audio_block(&block)
+            .map_err(|err| format!("failed to encode Vorbis block, {}", err))
+    }
+
+    fn finish(&mut self) -> Result<(), String> {
+        match self.encoder.take() {
+            Some(encoder) => encoder
+                .finish()
+                .map(|_| ())
+                .map_err(|err| format!("failed to finish Vorbis stream, {}", err)),
+            None => Ok(()),
+        }
+    }
+
+    fn take_bytes(&mut self) -> Vec<u8> {
+        self.pages.take()
+    }
+}
@@ -0,0 +1,491 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! RIFF/WAVE file writer, with the `bext` chunk of the Broadcast Wave Format.
+
+use crate::pcm::PcmSampleFormat;
+use crate::{local_date_time, ChannelData};
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+
+/// Largest RIFF chunk size of a WAV file, a larger file is written as RF64.
+const MAX_RIFF_LEN: u64 = u32::MAX as u64;
+
+/// Convert a sample in the nominal interval of [-1,+1] to a 16 bits signed sample,
+/// the inverse of the CPAL conversion of 16 bits samples to floating point.
+pub fn f32_to_i16(sample: f32) -> i16 {
+    (sample * -(i16::MIN as f32))
+        .round()
+        .max(i16::MIN as f32)
+        .min(i16::MAX as f32) as i16
+}
+
+/// Size of the `ds64` chunk of RF64 files, without its id and size.
+const DS64_LEN: u32 = 28;
+
+/// RIFF/WAVE header up to the `data` chunk size, including a `fact` chunk for IEEE float
+/// and the given chunks of even size, for the given size in bytes of the sample data,
+/// `None` for a never ending stream.
+///
+/// The header of a file has room for a `ds64` chunk, a `JUNK` chunk while the RIFF
+/// chunk size is up to `max_riff_len`, and the `ds64` chunk of a RF64 file (EBU Tech 3306)
+/// with the 64 bits sizes beyond it, so the header keeps its size when rewritten.
+fn header(
+    format_tag: u16,
+    bits_per_sample: u16,
+    channels: u16,
+    sample_rate: u32,
+    chunks: &[u8],
+    data_len: Option<u64>,
+    max_riff_len: u64,
+) -> Vec<u8> {
+    let block_align = channels * bits_per_sample / 8;
+    let is_float = format_tag == WAVE_FORMAT_IEEE_FLOAT;
+    let mut header = Vec::with_capacity(94 + chunks.len());
+
+    // the RIFF chunk size is the header size, minus the RIFF chunk id and size, plus the data
+    // (a chunk with odd size is followed by a pad byte,
+    // it never happens with whole 16 or 32 bits samples)
+    let ds64_len = data_len.map_or(0, |_| 8 + DS64_LEN as u64);
+    let riff_len = data_len.map(|data_len| {
+        (if is_float { 50 } else { 36 }) + ds64_len + chunks.len() as u64 + data_len
+    });
+    let is_rf64 = riff_len.is_some_and(|riff_len| riff_len > max_riff_len);
+    let sample_count = data_len.map(|data_len| data_len / block_align as u64);
+    // the 32 bits sizes of a RF64 file are in its `ds64` chunk, as those of a stream unknown
+    let size32 = |len: Option<u64>| match len {
+        Some(len) if !is_rf64 => len as u32,
+        _ => u32::MAX,
+    };
+    header.extend_from_slice(if is_rf64 { b"RF64" } else { b"RIFF" });
+    header.extend_from_slice(&size32(riff_len).to_le_bytes());
+    header.extend_from_slice(b"WAVE");
+
+    if let (Some(riff_len), Some(data_len)) = (riff_len, data_len) {
+        if is_rf64 {
+            header.extend_from_slice(b"ds64");
+            header.extend_from_slice(&DS64_LEN.to_le_bytes());
+            header.extend_from_slice(&riff_len.to_le_bytes());
+            header.extend_from_slice(&data_len.to_le_bytes());
+            header.extend_from_slice(&sample_count.unwrap_or(0).to_le_bytes());
+            // no table of the sizes of other chunks
+            header.extend_from_slice(&0_u32.to_le_bytes());
+        } else {
+            header.extend_from_slice(b"JUNK");
+            header.extend_from_slice(&DS64_LEN.to_le_bytes());
+            header.extend_from_slice(&[0; DS64_LEN as usize]);
+        }
+    }
+
+    header.extend_from_slice(b"fmt ");
+    header.extend_from_slice(&(if is_float { 18_u32 } else { 16_u32 }).to_le_bytes());
+    header.extend_from_slice(&format_tag.to_le_bytes());
+    header.extend_from_slice(&channels.to_le_bytes());
+    header.extend_from_slice(&sample_rate.to_le_bytes());
+    header.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+    header.extend_from_slice(&block_align.to_le_bytes());
+    header.extend_from_slice(&bits_per_sample.to_le_bytes());
+
+    // non-PCM formats require the size of the format extension and a fact chunk
+    if is_float {
+        header.extend_from_slice(&0_u16.to_le_bytes());
+        header.extend_from_slice(b"fact");
+        header.extend_from_slice(&4_u32.to_le_bytes());
+        header.extend_from_slice(&size32(sample_count).to_le_bytes());
+    }
+
+    header.extend_from_slice(chunks);
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&size32(data_len).to_le_bytes());
+    header
+}
+
+/// RIFF/WAVE header of unknown length, for a never ending stream of little endian samples.
+pub fn streaming_header(
+    channels: u16,
+    sample_rate: u32,
+    sample_format: PcmSampleFormat,
+) -> Vec<u8> {
+    match sample_format {
+        PcmSampleFormat::S16 => header(
+            WAVE_FORMAT_PCM,
+            16,
+            channels,
+            sample_rate,
+            &[],
+            None,
+            MAX_RIFF_LEN,
+        ),
+        PcmSampleFormat::F32 => header(
+            WAVE_FORMAT_IEEE_FLOAT,
+            32,
+            channels,
+            sample_rate,
+            &[],
+            None,
+            MAX_RIFF_LEN,
+        ),
+    }
+}
+
+/// Broadcast Wave Format metadata, written in a `bext` chunk (EBU Tech 3285).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Bext {
+    /// up to 256 ASCII characters
+    pub description: String,
+    /// up to 32 ASCII characters, the name of the originator
+    pub originator: String,
+    /// up to 32 ASCII characters, an unique identifier of the recording
+    pub originator_reference: String,
+    /// lines of the coding history, a line of the format of the recording if empty
+    pub coding_history: String,
+}
+
+/// Append the ASCII string truncated or padded with zeros to the length.
+fn push_ascii(chunk: &mut Vec<u8>, text: &str, len: usize) {
+    let start = chunk.len();
+    chunk.extend(text.bytes().filter(u8::is_ascii).take(len));
+    chunk.resize(start + len, 0);
+}
+
+/// The `bext` chunk of a recording started at the given time, with its origination
+/// date and time in the local time zone and its time reference in samples since midnight.
+fn bext_chunk(
+    bext: &Bext,
+    start: SystemTime,
+    bits_per_sample: u16,
+    channels: u16,
+    sample_rate: u32,
+) -> Vec<u8> {
+    let time = local_date_time(start);
+    let subsec = start
+        .duration_since(UNIX_EPOCH)
+        .map_or(0.0, |duration| duration.subsec_nanos() as f64 / 1e9);
+    let time_reference = ((((time.hours * 60 + time.minutes) * 60 + time.seconds) as f64 + subsec)
+        * sample_rate as f64)
+        .round() as u64;
+
+    let mut coding_history = bext
+        .coding_history
+        .replace("\r\n", "\n")
+        .replace('\n', "\r\n");
+    if coding_history.is_empty() {
+        let mode = match channels {
+            1 => ",M=mono",
+            2 => ",M=stereo",
+            _ => "",
+        };
+        coding_history = format!(
+            "A=PCM,F={},W={}{},T={}",
+            sample_rate,
+            bits_per_sample,
+            mode,
+            env!("CARGO_PKG_NAME")
+        );
+    }
+    if !coding_history.ends_with("\r\n") {
+        coding_history.push_str("\r\n");
+    }
+
+    let mut chunk = Vec::with_capacity(610 + coding_history.len());
+    chunk.extend_from_slice(b"bext");
+    chunk.extend_from_slice(&[0; 4]);
+    push_ascii(&mut chunk, &bext.description, 256);
+    push_ascii(&mut chunk, &bext.originator, 32);
+    push_ascii(&mut chunk, &bext.originator_reference, 32);
+    push_ascii(
+        &mut chunk,
+        &format!("{:04}-{:02}-{:02}", time.year, time.month, time.day),
+        10,
+    );
+    push_ascii(
+        &mut chunk,
+        &format!("{:02}:{:02}:{:02}", time.hours, time.minutes, time.seconds),
+        8,
+    );
+    chunk.extend_from_slice(&time_reference.to_le_bytes());
+    // version 1, without the loudness fields of the version 2
+    chunk.extend_from_slice(&1_u16.to_le_bytes());
+    // UMID and reserved
+    chunk.extend_from_slice(&[0; 64 + 190]);
+    chunk.extend(coding_history.bytes().filter(u8::is_ascii));
+    // the coding history is terminated by a zero, and the chunk padded to an even size
+    chunk.push(0);
+    if chunk.len() % 2 != 0 {
+        chunk.push(0);
+    }
+    let chunk_len = (chunk.len() - 8) as u32;
+    chunk[4..8].copy_from_slice(&chunk_len.to_le_bytes());
+    chunk
+}
+
+/// Writes interleaved samples to a RIFF/WAVE file:
+/// 16 bits PCM for integer sample formats and 32 bits IEEE float for floating point samples.
+///
+/// The chunk sizes in the header are only valid after [`WavWriter::finalize`],
+/// and a file beyond 4 GiB is finalized as RF64.
+pub struct WavWriter<W: Write + Seek> {
+    writer: W,
+    format_tag: u16,
+    bits_per_sample: u16,
+    channels: u16,
+    sample_rate: u32,
+    sample_format: cpal::SampleFormat,
+    /// chunks between the format and the data, e.g. `bext`
+    chunks: Vec<u8>,
+    data_len: u64,
+    /// RIFF chunk size beyond which the file is RF64
+    max_riff_len: u64,
+}
+
+impl WavWriter<BufWriter<File>> {
+    pub fn create(
+        path: impl AsRef<Path>,
+        config: &cpal::SupportedStreamConfig,
+    ) -> io::Result<Self> {
+        WavWriter::new(BufWriter::new(File::create(path)?), config)
+    }
+
+    /// Create a Broadcast Wave file of a recording started at the given time.
+    pub fn create_bwf(
+        path: impl AsRef<Path>,
+        config: &cpal::SupportedStreamConfig,
+        bext: &Bext,
+        start: SystemTime,
+    ) -> io::Result<Self> {
+        WavWriter::new_bwf(BufWriter::new(File::create(path)?), config, bext, start)
+    }
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(writer: W, config: &cpal::SupportedStreamConfig) -> io::Result<Self> {
+        WavWriter::with_chunks(writer, config, |_| Vec::new())
+    }
+
+    /// Broadcast Wave of a recording started at the given time.
+    pub fn new_bwf(
+        writer: W,
+        config: &cpal::SupportedStreamConfig,
+        bext: &Bext,
+        start: SystemTime,
+    ) -> io::Result<Self> {
+        WavWriter::with_chunks(writer, config, |bits_per_sample| {
+            bext_chunk(
+                bext,
+                start,
+                bits_per_sample,
+                config.channels(),
+                config.sample_rate().0,
+            )
+        })
+    }
+
+    /// The chunks between the format and the data, for the bits per sample.
+    fn with_chunks(
+        mut writer: W,
+        config: &cpal::SupportedStreamConfig,
+        chunks: impl FnOnce(u16) -> Vec<u8>,
+    ) -> io::Result<Self> {
+        let (format_tag, bits_per_sample) = match config.sample_format() {
+            cpal::SampleFormat::U16 | cpal::SampleFormat::I16 => (WAVE_FORMAT_PCM, 16),
+            // 32 bits integers, e.g. of ASIO interfaces, are mostly 24 bits samples
+            cpal::SampleFormat::I32 | cpal::SampleFormat::F32 => (WAVE_FORMAT_IEEE_FLOAT, 32),
+            sample_format => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("unsupported sample format {:?}", sample_format),
+                ))
+            }
+        };
+        let channels = config.channels();
+        let sample_rate = config.sample_rate().0;
+        let chunks = chunks(bits_per_sample);
+
+        // chunk sizes written on finalize
+        writer.write_all(&header(
+            format_tag,
+            bits_per_sample,
+            channels,
+            sample_rate,
+            &chunks,
+            Some(0),
+            MAX_RIFF_LEN,
+        ))?;
+
+        Ok(WavWriter {
+            writer,
+            format_tag,
+            bits_per_sample,
+            channels,
+            sample_rate,
+            sample_format: config.sample_format(),
+            chunks,
+            data_len: 0,
+            max_riff_len: MAX_RIFF_LEN,
+        })
+    }
+
+    /// Interleave and write the samples of all the channels of an input buffer.
+    pub fn write_channels(&mut self, channels: &[ChannelData]) -> io::Result<()> {
+        assert_eq!(channels.len(), self.channels as usize);
+        let num_frames = channels.first().map_or(0, |channel| channel.samples.len());
+
+        for frame_index in 0..num_frames {
+            for channel in channels {
+                self.write_sample(channel.samples[frame_index])?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write the samples of an input buffer, already interleaved.
+    pub fn write_interleaved(&mut self, samples: &[f32]) -> io::Result<()> {
+        assert!(samples.len().is_multiple_of(self.channels as usize));
+        for &sample in samples {
+            self.write_sample(sample)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_sample(&mut self, sample: f32) -> io::Result<()> {
+        match self.sample_format {
+            cpal::SampleFormat::U16 | cpal::SampleFormat::I16 => {
+                self.writer.write_all(&f32_to_i16(sample).to_le_bytes())?;
+                self.data_len += 2;
+            }
+            _ => {
+                self.writer.write_all(&sample.to_le_bytes())?;
+                self.data_len += 4;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the chunk sizes in the header and flush, the writer can be used after that.
+    pub fn finalize(&mut self) -> io::Result<()> {
+        let end_pos = self.writer.stream_position()?;
+
+        self.writer.seek(SeekFrom::Start(0))?;
+        self.writer.write_all(&header(
+            self.format_tag,
+            self.bits_per_sample,
+            self.channels,
+            self.sample_rate,
+            &self.chunks,
+            Some(self.data_len),
+            self.max_riff_len,
+        ))?;
+
+        self.writer.seek(SeekFrom::Start(end_pos))?;
+        self.writer.flush()
+    }
+
+    /// The writer, after [`WavWriter::finalize`].
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryInto;
+    use std::io::Cursor;
+
+    fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+        u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+    }
+
+    /// The file of the 16 bits mono samples, finalized with the RIFF chunk size limit.
+    fn write_file(num_samples: usize, max_riff_len: u64) -> Vec<u8> {
+        let config = cpal::SupportedStreamConfig::new(
+            1,
+            cpal::SampleRate(48000),
+            cpal::SupportedBufferSize::Unknown,
+            cpal::SampleFormat::I16,
+        );
+        let mut writer = WavWriter::new(Cursor::new(Vec::new()), &config).unwrap();
+        writer.max_riff_len = max_riff_len;
+        writer.write_interleaved(&vec![0.5; num_samples]).unwrap();
+        writer.finalize().unwrap();
+        writer.into_inner().into_inner()
+    }
+
+    #[test]
+    fn riff_up_to_the_limit() {
+        // header of 80 bytes, of which 72 in the RIFF chunk, and 2 samples of 2 bytes
+        let file = write_file(2, 76);
+        assert_eq!(file.len(), 84);
+        assert_eq!(&file[..4], b"RIFF");
+        assert_eq!(read_u32(&file, 4), 76);
+        assert_eq!(&file[12..16], b"JUNK");
+        assert_eq!(&file[72..76], b"data");
+        assert_eq!(read_u32(&file, 76), 4);
+    }
+
+    #[test]
+    fn rf64_beyond_the_limit() {
+        let file = write_file(3, 76);
+        assert_eq!(file.len(), 86);
+        assert_eq!(&file[..4], b"RF64");
+        assert_eq!(read_u32(&file, 4), u32::MAX);
+        assert_eq!(&file[12..16], b"ds64");
+        assert_eq!(read_u32(&file, 16), DS64_LEN);
+        assert_eq!(read_u64(&file, 20), 78);
+        assert_eq!(read_u64(&file, 28), 6);
+        assert_eq!(read_u64(&file, 36), 3);
+        assert_eq!(&file[72..76], b"data");
+        assert_eq!(read_u32(&file, 76), u32::MAX);
+        assert_eq!(f32_to_i16(0.5).to_le_bytes(), file[80..82]);
+    }
+
+    #[test]
+    fn float_rf64_sample_count() {
+        let header = header(
+            WAVE_FORMAT_IEEE_FLOAT,
+            32,
+            2,
+            48000,
+            &[],
+            Some(1 << 33),
+            MAX_RIFF_LEN,
+        );
+        assert_eq!(&header[..4], b"RF64");
+        assert_eq!(read_u64(&header, 28), 1 << 33);
+        assert_eq!(read_u64(&header, 36), 1 << 30);
+        // the sample length of the fact chunk is in the ds64 chunk
+        assert_eq!(&header[74..78], b"fact");
+        assert_eq!(read_u32(&header, 82), u32::MAX);
+    }
+
+    #[test]
+    fn streaming_header_without_ds64() {
+        let header = streaming_header(2, 48000, PcmSampleFormat::S16);
+        assert_eq!(header.len(), 44);
+        assert_eq!(&header[..4], b"RIFF");
+        assert_eq!(read_u32(&header, 40), u32::MAX);
+    }
+}
@@ -0,0 +1,209 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Min/max waveform of the recent input: a downsampled peak envelope of each channel,
+//! kept for longer than the raw samples would fit in memory, rendered as SVG or PNG.
+
+use crate::png;
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Resolution of the envelope, enough for a waveform of a few seconds.
+pub const BUCKETS_PER_SECOND: u32 = 100;
+pub const DEFAULT_DURATION: f64 = 300.0;
+pub const DEFAULT_WIDTH: u32 = 800;
+pub const DEFAULT_HEIGHT: u32 = 200;
+pub const MAX_WIDTH: u32 = 4096;
+pub const MAX_HEIGHT: u32 = 2048;
+const BACKGROUND: [u8; 3] = [0x18, 0x18, 0x18];
+const FOREGROUND: [u8; 3] = [0x33, 0xaa, 0x33];
+
+struct Buckets {
+    /// min and max of each bucket of each channel, interleaved, oldest first
+    buckets: VecDeque<(f32, f32)>,
+    /// min and max of each channel of the bucket being filled
+    current: Vec<(f32, f32)>,
+    /// frames in the bucket being filled
+    current_frames: usize,
+}
+
+/// The envelope of the last seconds of the input, shared between the thread
+/// reading the samples from the sample ring and the renderers.
+#[derive(Clone)]
+pub struct WaveformHistory {
+    buckets: Arc<Mutex<Buckets>>,
+    channels: usize,
+    frames_per_bucket: usize,
+    max_buckets: usize,
+}
+
+/// Min and max of each bucket of each channel, the first one the oldest.
+pub struct Envelope {
+    pub channels: Vec<Vec<(f32, f32)>>,
+}
+
+impl WaveformHistory {
+    pub fn new(duration: f64, channels: usize, sample_rate: u32) -> Self {
+        let max_buckets = (duration * BUCKETS_PER_SECOND as f64).ceil() as usize;
+        WaveformHistory {
+            buckets: Arc::new(Mutex::new(Buckets {
+                buckets: VecDeque::with_capacity(max_buckets * channels),
+                current: vec![(f32::MAX, f32::MIN); channels],
+                current_frames: 0,
+            })),
+            channels,
+            frames_per_bucket: (sample_rate / BUCKETS_PER_SECOND).max(1) as usize,
+            max_buckets,
+        }
+    }
+
+    /// Add the interleaved samples, dropping the oldest buckets.
+    pub fn push(&self, samples: &[f32]) {
+        let mut buckets = self.buckets.lock().unwrap_or_else(PoisonError::into_inner);
+        let buckets = &mut *buckets;
+        for frame in samples.chunks_exact(self.channels) {
+            for (&sample, (min, max)) in frame.iter().zip(&mut buckets.current) {
+                *min = min.min(sample);
+                *max = max.max(sample);
+            }
+            buckets.current_frames += 1;
+            if buckets.current_frames == self.frames_per_bucket {
+                if buckets.buckets.len() == self.max_buckets * self.channels {
+                    buckets.buckets.drain(..self.channels);
+                }
+                buckets.buckets.extend(buckets.current.iter().copied());
+                buckets.current.fill((f32::MAX, f32::MIN));
+                buckets.current_frames = 0;
+            }
+        }
+    }
+
+    /// The envelope of the last seconds, up to `duration` seconds of them.
+    pub fn envelope(&self, duration: f64) -> Envelope {
+        let max_buckets = (duration * BUCKETS_PER_SECOND as f64).ceil() as usize;
+        let buckets = self.buckets.lock().unwrap_or_else(PoisonError::into_inner);
+        let num_buckets = buckets.buckets.len() / self.channels;
+        let mut channels = vec![Vec::with_capacity(num_buckets.min(max_buckets)); self.channels];
+        let skip = num_buckets.saturating_sub(max_buckets) * self.channels;
+        for (index, &bucket) in buckets.buckets.iter().skip(skip).enumerate() {
+            channels[index % self.channels].push(bucket);
+        }
+        Envelope { channels }
+    }
+}
+
+impl Envelope {
+    /// Min and max of the buckets of each column of the image, `None` without buckets.
+    fn columns(buckets: &[(f32, f32)], width: u32) -> Vec<Option<(f32, f32)>> {
+        (0..width as usize)
+            .map(|x| {
+                let first = x * buckets.len() / width as usize;
+                let last = ((x + 1) * buckets.len() / width as usize).max(first + 1);
+                buckets
+                    .get(first..last.min(buckets.len()))
+                    .filter(|buckets| !buckets.is_empty())
+                    .map(|buckets| {
+                        buckets
+                            .iter()
+                            .fold((f32::MAX, f32::MIN), |(min, max), bucket| {
+                                (min.min(bucket.0), max.max(bucket.1))
+                            })
+                    })
+            })
+            .collect()
+    }
+
+    /// Vertical position of the sample in a lane of the height, full scale at the edges.
+    fn y(sample: f32, lane_top: f32, lane_height: f32) -> f32 {
+        lane_top + lane_height * (1.0 - sample.clamp(-1.0, 1.0)) / 2.0
+    }
+
+    /// SVG image of the waveform of each channel, one above the other.
+    pub fn to_svg(&self, width: u32, height: u32) -> String {
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" \
+             viewBox=\"0 0 {} {}\">\n<rect width=\"100%\" height=\"100%\" fill=\"#181818\"/>\n",
+            width, height, width, height
+        );
+        let lane_height = height as f32 / self.channels.len().max(1) as f32;
+        for (channel, buckets) in self.channels.iter().enumerate() {
+            let lane_top = channel as f32 * lane_height;
+            let center = Self::y(0.0, lane_top, lane_height);
+            writeln!(
+                svg,
+                "<line x1=\"0\" y1=\"{:.1}\" x2=\"{}\" y2=\"{:.1}\" stroke=\"#444\"/>",
+                center, width, center
+            )
+            .unwrap();
+            // along the maxima from the left, and back along the minima
+            let columns = Self::columns(buckets, width);
+            let points: Vec<(usize, (f32, f32))> = columns
+                .iter()
+                .enumerate()
+                .filter_map(|(x, column)| column.map(|column| (x, column)))
+                .collect();
+            if points.is_empty() {
+                continue;
+            }
+            let mut path = String::new();
+            for (x, (_, max)) in &points {
+                let command = if path.is_empty() { 'M' } else { 'L' };
+                write!(
+                    path,
+                    "{}{} {:.1}",
+                    command,
+                    x,
+                    Self::y(*max, lane_top, lane_height)
+                )
+                .unwrap();
+            }
+            for (x, (min, max)) in points.iter().rev() {
+                // at least a pixel high, for silence
+                let y = Self::y(*min, lane_top, lane_height)
+                    .max(Self::y(*max, lane_top, lane_height) + 1.0);
+                write!(path, "L{} {:.1}", x + 1, y).unwrap();
+            }
+            writeln!(svg, "<path d=\"{}Z\" fill=\"#3a3\"/>", path).unwrap();
+        }
+        svg.push_str("</svg>\n");
+        svg
+    }
+
+    /// PNG image of the waveform of each channel, one above the other.
+    pub fn to_png(&self, width: u32, height: u32) -> Vec<u8> {
+        let mut pixels = BACKGROUND.repeat(width as usize * height as usize);
+        let lane_height = height as f32 / self.channels.len().max(1) as f32;
+        for (channel, buckets) in self.channels.iter().enumerate() {
+            let lane_top = channel as f32 * lane_height;
+            let lane_bottom = ((lane_top + lane_height) as usize).min(height as usize);
+            for (x, column) in Self::columns(buckets, width).into_iter().enumerate() {
+                let (min, max) = match column {
+                    Some(column) => column,
+                    None => continue,
+                };
+                let top = Self::y(max, lane_top, lane_height) as usize;
+                let bottom = (Self::y(min, lane_top, lane_height) as usize)
+                    .max(top)
+                    .min(lane_bottom.saturating_sub(1));
+                for y in top..=bottom {
+                    let pixel = (y * width as usize + x) * 3;
+                    pixels[pixel..pixel + 3].copy_from_slice(&FOREGROUND);
+                }
+            }
+        }
+        png::encode_rgb(width, height, &pixels)
+    }
+}
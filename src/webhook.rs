@@ -0,0 +1,118 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Webhook notifications: each event is POSTed as JSON to the configured URLs.
+
+use crate::error::Error;
+use crate::events::{Event, PendingEvents};
+use crate::http_client;
+use crate::supervisor::Supervisor;
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::Method;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Events queued for each URL before dropping new ones.
+const QUEUE_CAPACITY: usize = 64;
+/// Attempts to deliver an event, doubling the delay between them.
+const MAX_ATTEMPTS: u32 = 5;
+const FIRST_RETRY_DELAY: Duration = Duration::from_secs(1);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Delivery of the events to each URL from a task of its own,
+/// so a slow or failing URL does not delay the others. The events not delivered are
+/// reported to the supervisor.
+pub struct Webhooks {
+    queues: Vec<mpsc::Sender<Arc<String>>>,
+    pending: PendingEvents,
+}
+
+impl Webhooks {
+    pub fn start(
+        urls: &[String],
+        pending: PendingEvents,
+        supervisor: Supervisor,
+    ) -> Result<Self, String> {
+        let mut queues = Vec::with_capacity(urls.len());
+        for url in urls {
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                return Err(format!(
+                    "invalid webhook url '{}', expected http:// or https://",
+                    url
+                ));
+            }
+
+            let (sender, mut receiver) = mpsc::channel::<Arc<String>>(QUEUE_CAPACITY);
+            let url = url.clone();
+            let pending = pending.clone();
+            let supervisor = supervisor.clone();
+            tokio::spawn(async move {
+                while let Some(body) = receiver.recv().await {
+                    deliver(&url, &body, &supervisor).await;
+                    pending.end();
+                }
+            });
+            queues.push(sender);
+        }
+        Ok(Webhooks { queues, pending })
+    }
+
+    pub fn send(&self, event: &Event) {
+        if self.queues.is_empty() {
+            return;
+        }
+        let body = Arc::new(event.to_json());
+        for queue in &self.queues {
+            self.pending.begin();
+            if queue.try_send(Arc::clone(&body)).is_err() {
+                self.pending.end();
+            }
+        }
+    }
+}
+
+/// POST the JSON body, retrying with backoff on failure.
+async fn deliver(url: &str, body: &str, supervisor: &Supervisor) {
+    let mut delay = FIRST_RETRY_DELAY;
+    for attempt in 1..=MAX_ATTEMPTS {
+        let headers = [("Content-Type", String::from("application/json"))];
+        let body = Full::new(Bytes::copy_from_slice(body.as_bytes()));
+        let err = match http_client::send(Method::POST, url, &headers, body, REQUEST_TIMEOUT).await
+        {
+            Ok(response) if response.is_success() => return,
+            // the request is not going to succeed by retrying it
+            Ok(response) if response.status < 500 && response.status != 429 => {
+                supervisor.report(Error::Output(format!(
+                    "webhook '{}' rejected the event with status {}",
+                    url, response.status
+                )));
+                return;
+            }
+            Ok(response) => format!("status {}", response.status),
+            Err(err) => err,
+        };
+        if attempt == MAX_ATTEMPTS {
+            supervisor.report(Error::Output(format!(
+                "failed to deliver webhook to '{}', giving up after {} attempts: {}",
+                url, MAX_ATTEMPTS, err
+            )));
+        } else {
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+        }
+    }
+}
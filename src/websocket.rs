@@ -0,0 +1,59 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Pushing messages to the WebSocket clients of the http server, over the connections
+//! upgraded by axum.
+
+use axum::extract::ws::{Message, WebSocket};
+use futures_util::future::Either;
+use futures_util::{stream, SinkExt, Stream, StreamExt};
+
+/// Send each message to the WebSocket client, until either side goes away.
+///
+/// The frames of the client are read meanwhile: its pings are answered and its close
+/// ends the connection, and its messages are ignored.
+pub async fn serve_messages(socket: WebSocket, messages: impl Stream<Item = Message>) {
+    let (mut sender, receiver) = socket.split();
+    let client_frames = receiver.map(Either::Right);
+    // the messages end the merged stream when they end, the frames of the client do not
+    let messages = messages
+        .map(|message| Either::Left(Some(message)))
+        .chain(stream::once(async { Either::Left(None) }));
+    let mut events = std::pin::pin!(stream::select(messages, client_frames));
+    while let Some(event) = events.next().await {
+        match event {
+            Either::Left(Some(message)) => {
+                if sender.send(message).await.is_err() {
+                    return;
+                }
+            }
+            Either::Left(None) => break,
+            // the answer of the close is queued when read, as the pongs of the pings,
+            // and sent on the next flush
+            Either::Right(Ok(Message::Close(_))) => {
+                sender.flush().await.ok();
+                return;
+            }
+            Either::Right(Ok(Message::Ping(_))) => {
+                if sender.flush().await.is_err() {
+                    return;
+                }
+            }
+            Either::Right(Err(_)) => return,
+            Either::Right(Ok(_)) => (),
+        }
+    }
+    sender.send(Message::Close(None)).await.ok();
+}
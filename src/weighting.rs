@@ -0,0 +1,124 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Frequency weightings of IEC 61672 for the level measurements:
+//! A-weighting, C-weighting and Z-weighting (none).
+
+use crate::biquad::Biquad;
+
+/// Hz, poles of the analog weighting filters
+const F1: f64 = 20.598997;
+const F2: f64 = 107.65265;
+const F3: f64 = 737.86223;
+const F4: f64 = 12194.217;
+
+/// Hz, frequency at which the weightings have 0 dB of gain
+const REFERENCE_FREQUENCY: f64 = 1000.0;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Weighting {
+    A,
+    C,
+    #[default]
+    Z,
+}
+
+impl Weighting {
+    pub fn parse(weighting: &str) -> Result<Self, String> {
+        match weighting.to_ascii_uppercase().as_str() {
+            "A" => Ok(Weighting::A),
+            "C" => Ok(Weighting::C),
+            "Z" => Ok(Weighting::Z),
+            _ => Err(format!(
+                "invalid weighting '{}', expected one of: A, C, Z",
+                weighting
+            )),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Weighting::A => "A",
+            Weighting::C => "C",
+            Weighting::Z => "Z",
+        }
+    }
+}
+
+/// Cascade of second order sections of the weighting,
+/// by the bilinear transform of its analog filter.
+fn weighting_filter(weighting: Weighting, sample_rate: u32) -> Vec<Biquad> {
+    let sample_rate = sample_rate as f64;
+    let w = |f: f64| 2.0 * std::f64::consts::PI * f;
+
+    // s² / (s + w1)²
+    let low_high_pass = || {
+        Biquad::bilinear(
+            [1.0, 0.0, 0.0],
+            [1.0, 2.0 * w(F1), w(F1) * w(F1)],
+            sample_rate,
+        )
+    };
+    // 1 / (s + w4)², pre-warped so the bilinear transform keeps the pole frequency
+    let w4 = 2.0 * sample_rate * (std::f64::consts::PI * F4 / sample_rate).tan();
+    let high_low_pass = || Biquad::bilinear([0.0, 0.0, 1.0], [1.0, 2.0 * w4, w4 * w4], sample_rate);
+    let mut filter = match weighting {
+        Weighting::A => vec![
+            low_high_pass(),
+            // s² / ((s + w2) (s + w3))
+            Biquad::bilinear(
+                [1.0, 0.0, 0.0],
+                [1.0, w(F2) + w(F3), w(F2) * w(F3)],
+                sample_rate,
+            ),
+            high_low_pass(),
+        ],
+        Weighting::C => vec![low_high_pass(), high_low_pass()],
+        Weighting::Z => return Vec::new(),
+    };
+
+    let gain: f64 = filter
+        .iter()
+        .map(|section| section.magnitude(REFERENCE_FREQUENCY, sample_rate))
+        .product();
+    filter[0] = filter[0].clone().with_gain(1.0 / gain);
+    filter
+}
+
+/// Weighting filter of each channel, to measure weighted loudness levels.
+pub struct WeightingFilter {
+    weighting: Weighting,
+    filters: Vec<Vec<Biquad>>,
+}
+
+impl WeightingFilter {
+    pub fn new(weighting: Weighting, sample_rate: u32, num_channels: usize) -> Self {
+        WeightingFilter {
+            weighting,
+            filters: vec![weighting_filter(weighting, sample_rate); num_channels],
+        }
+    }
+
+    pub fn weighting(&self) -> Weighting {
+        self.weighting
+    }
+
+    /// Weighted value of the next sample of the channel in the given position.
+    pub fn filter(&mut self, position: usize, sample: f32) -> f64 {
+        self.filters[position]
+            .iter_mut()
+            .fold(sample as f64, |x, section| section.process(x))
+    }
+}
@@ -0,0 +1,177 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! The helpers of the integration tests: a silent input file, and the server
+//! run on it with its http requests.
+
+// each test uses only some of the helpers
+#![allow(dead_code)]
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+/// A silent mono WAV file of 16 bits samples.
+pub fn write_silence(path: &Path, seconds: u32) {
+    let sample_rate = 8000_u32;
+    let data_len = sample_rate * seconds * 2;
+    let mut wav = Vec::new();
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16_u32.to_le_bytes());
+    wav.extend_from_slice(&1_u16.to_le_bytes());
+    wav.extend_from_slice(&1_u16.to_le_bytes());
+    wav.extend_from_slice(&sample_rate.to_le_bytes());
+    wav.extend_from_slice(&(sample_rate * 2).to_le_bytes());
+    wav.extend_from_slice(&2_u16.to_le_bytes());
+    wav.extend_from_slice(&16_u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+    wav.resize(wav.len() + data_len as usize, 0);
+    std::fs::write(path, wav).unwrap();
+}
+
+/// A free port of the loopback interface.
+pub fn free_address() -> String {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .to_string()
+}
+
+/// The server run with the args on 30 seconds of silence, killed when dropped,
+/// with its recordings directory and a recording `take.wav` in it.
+pub struct Server {
+    child: Child,
+    /// of the http server
+    pub address: String,
+    pub directory: PathBuf,
+}
+
+/// The status code, the headers in lowercase and the body of a response.
+pub struct Response {
+    pub status: u16,
+    pub headers: String,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.lines().find_map(|line| {
+            let (header_name, value) = line.split_once(':')?;
+            (header_name == name).then(|| value.trim())
+        })
+    }
+}
+
+impl Server {
+    pub fn start(name: &str, args: &[&str]) -> Self {
+        let directory = std::env::temp_dir().join(format!(
+            "audio-in-stream-rs-{}-{}",
+            name,
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&directory).unwrap();
+        let input = directory.join("input.wav");
+        write_silence(&input, 30);
+        std::fs::write(directory.join("take.wav"), b"recording").unwrap();
+        let address = free_address();
+        let child = Command::new(env!("CARGO_BIN_EXE_audio-in-stream-rs"))
+            .arg("--input-file")
+            .arg(&input)
+            .arg("--recordings-dir")
+            .arg(&directory)
+            .args(["--no-tui", "--listen", &address])
+            .args(args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        Server {
+            child,
+            address,
+            directory,
+        }
+    }
+
+    /// The response to a request of the method and path, with the first `body_len` bytes
+    /// of its body, or all of it if it ends before. The connection is closed after it,
+    /// unless the headers have their own.
+    pub fn request(&self, method: &str, path: &str, headers: &str, body_len: usize) -> Response {
+        let mut stream = (0..100)
+            .find_map(|_| {
+                TcpStream::connect(&self.address)
+                    .map_err(|_| thread::sleep(Duration::from_millis(50)))
+                    .ok()
+            })
+            .expect("the server is not listening");
+        stream
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        // the upgrades bring their own connection header
+        let connection = if headers.to_lowercase().contains("connection:") {
+            ""
+        } else {
+            "Connection: close\r\n"
+        };
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: localhost\r\n{}{}\r\n",
+            method, path, headers, connection
+        )
+        .unwrap();
+        let mut response = Vec::new();
+        let mut buffer = [0; 4096];
+        let header_end = loop {
+            let header_end = response
+                .windows(4)
+                .position(|window| window == b"\r\n\r\n")
+                .map(|position| position + 4);
+            match header_end {
+                Some(header_end) if response.len() >= header_end.saturating_add(body_len) => {
+                    break header_end
+                }
+                _ => match stream.read(&mut buffer).unwrap() {
+                    0 => break header_end.expect("invalid response"),
+                    read => response.extend_from_slice(&buffer[..read]),
+                },
+            }
+        };
+        let head = String::from_utf8_lossy(&response[..header_end]).to_lowercase();
+        let (status_line, headers) = head.split_once("\r\n").unwrap();
+        Response {
+            status: status_line
+                .split(' ')
+                .nth(1)
+                .and_then(|status| status.parse().ok())
+                .expect("invalid status line"),
+            headers: headers.to_owned(),
+            body: response[header_end..].to_vec(),
+        }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.child.kill().ok();
+        self.child.wait().ok();
+        std::fs::remove_dir_all(&self.directory).ok();
+    }
+}
@@ -0,0 +1,110 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! The errors of the setup and the fatal ones of the capture end the program with an
+//! exit status of 1, once shut down.
+
+mod common;
+
+use common::write_silence;
+use std::process::{Command, Output};
+
+/// Run the program on a second of silence, as fast as read, with the args.
+fn run(name: &str, args: &[&str]) -> Output {
+    let input = std::env::temp_dir().join(format!(
+        "audio-in-stream-rs-{}-{}.wav",
+        name,
+        std::process::id()
+    ));
+    write_silence(&input, 1);
+    let output = Command::new(env!("CARGO_BIN_EXE_audio-in-stream-rs"))
+        .arg("--input-file")
+        .arg(&input)
+        .args(["--no-realtime", "--no-tui", "--listen", "127.0.0.1:0"])
+        .args(args)
+        .output()
+        .unwrap();
+    std::fs::remove_file(&input).ok();
+    output
+}
+
+fn errors(output: &Output) -> Vec<String> {
+    String::from_utf8_lossy(&output.stderr)
+        .lines()
+        .filter(|line| line.starts_with("error: "))
+        .map(String::from)
+        .collect()
+}
+
+#[test]
+fn end_of_the_input() {
+    let output = run("end-of-the-input", &[]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(errors(&output).is_empty());
+}
+
+#[test]
+fn invalid_arg() {
+    let output = run("invalid-arg", &["--rms-window", "foo"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(errors(&output), ["error: invalid duration 'foo'"]);
+    let output = run("too-long-rms-window", &["--rms-window", "1h"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        errors(&output),
+        ["error: invalid RMS window '1h', it must be at most 60 s"]
+    );
+}
+
+#[test]
+fn too_long_duration() {
+    let output = run("too-long-duration", &["--silence-duration", "1e300"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        errors(&output),
+        ["error: invalid duration '1e300', it must be at most 100 years"]
+    );
+    let output = run(
+        "too-low-rate",
+        &["--osc-addr", "127.0.0.1:9", "--osc-rate", "1e-300"],
+    );
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(errors(&output), ["error: invalid OSC rate '1e-300'"]);
+}
+
+#[test]
+fn invalid_http_workers() {
+    let output = run("invalid-http-workers", &["--http-workers", "0"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        errors(&output),
+        ["error: invalid value '0' for --http-workers"]
+    );
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn failed_recording() {
+    let output = run("failed-recording", &["--record", "/dev/full"]);
+    assert_eq!(output.status.code(), Some(1));
+    let errors = errors(&output);
+    assert!(
+        errors
+            .iter()
+            .any(|error| error.starts_with("error: failed to write the recording: ")),
+        "{:?}",
+        errors
+    );
+}
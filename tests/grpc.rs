@@ -0,0 +1,218 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! The gRPC api served by `--grpc-listen`, called with the generated client.
+
+mod common;
+
+use audio_in_stream_rs::grpc::proto::control_request::Action;
+use audio_in_stream_rs::grpc::proto::monitor_client::MonitorClient;
+use audio_in_stream_rs::grpc::proto::{
+    status, ControlRequest, GetControlsRequest, GetStatusRequest, SubscribeEventsRequest,
+    SubscribeLevelsRequest,
+};
+use common::{free_address, Server};
+use std::future::Future;
+use std::time::Duration;
+use tonic::transport::Channel;
+use tonic::{Code, Request};
+
+/// Longest wait of a response or a streamed message.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The server run with the args and the gRPC api.
+struct GrpcServer {
+    _server: Server,
+    address: String,
+}
+
+impl GrpcServer {
+    fn start(name: &str, args: &[&str]) -> Self {
+        let address = free_address();
+        let mut grpc_args = vec!["--grpc-listen", &address];
+        grpc_args.extend_from_slice(args);
+        GrpcServer {
+            _server: Server::start(name, &grpc_args),
+            address,
+        }
+    }
+
+    /// A client of the gRPC api, once the server is listening.
+    async fn client(&self) -> MonitorClient<Channel> {
+        let url = format!("http://{}", self.address);
+        for _ in 0..100 {
+            if let Ok(channel) = Channel::from_shared(url.clone()).unwrap().connect().await {
+                return MonitorClient::new(channel);
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("the server is not listening");
+    }
+}
+
+fn block_on<F: Future>(future: F) -> F::Output {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async { tokio::time::timeout(TIMEOUT, future).await.unwrap() })
+}
+
+/// The request with the bearer token of `--auth token:secret`.
+fn authorized<T>(message: T) -> Request<T> {
+    let mut request = Request::new(message);
+    request
+        .metadata_mut()
+        .insert("authorization", "Bearer secret".parse().unwrap());
+    request
+}
+
+#[test]
+fn levels_events_and_status() {
+    let server = GrpcServer::start("grpc-levels", &["--silence-duration", "1"]);
+    block_on(async {
+        let mut client = server.client().await;
+        let mut levels = client
+            .subscribe_levels(SubscribeLevelsRequest { max_rate: 0.0 })
+            .await
+            .unwrap()
+            .into_inner();
+        let first = levels.message().await.unwrap().unwrap();
+        assert_eq!(first.channel_count, 1);
+        assert_eq!(first.channels.len(), 1);
+        assert_eq!(first.channels[0].channel, 0);
+        assert_eq!(first.channels[0].dbov, f32::NEG_INFINITY);
+        assert!(first.json.starts_with('{'));
+
+        let mut events = client
+            .subscribe_events(SubscribeEventsRequest {
+                events: vec![String::from("silence_start")],
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        let event = events.message().await.unwrap().unwrap();
+        assert_eq!(event.event, "silence_start");
+        assert!(event.timestamp > 0.0);
+        assert!(event.json.contains("\"channel\":0"));
+
+        let status = client
+            .get_status(GetStatusRequest {})
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(status.healthy);
+        assert!(status.ready);
+        assert_eq!(status.recording, status::Recording::Disabled as i32);
+
+        let invalid = client
+            .subscribe_levels(SubscribeLevelsRequest { max_rate: -1.0 })
+            .await
+            .unwrap_err();
+        assert_eq!(invalid.code(), Code::InvalidArgument);
+        // the controls need the credentials
+        let disabled = client
+            .get_controls(GetControlsRequest {})
+            .await
+            .unwrap_err();
+        assert_eq!(disabled.code(), Code::FailedPrecondition);
+    });
+}
+
+#[test]
+fn controls_with_auth() {
+    let server = GrpcServer::start("grpc-controls", &["--auth", "token:secret"]);
+    block_on(async {
+        let mut client = server.client().await;
+        let unauthenticated = client.get_status(GetStatusRequest {}).await.unwrap_err();
+        assert_eq!(unauthenticated.code(), Code::Unauthenticated);
+        client
+            .get_status(authorized(GetStatusRequest {}))
+            .await
+            .unwrap();
+
+        let gain = ControlRequest {
+            action: Action::Gain as i32,
+            channel: Some(0),
+            db: -6.0,
+        };
+        let unauthenticated = client.control(gain).await.unwrap_err();
+        assert_eq!(unauthenticated.code(), Code::Unauthenticated);
+        let state = client.control(authorized(gain)).await.unwrap().into_inner();
+        assert_eq!(state.channels.len(), 1);
+        assert_eq!(state.channels[0].gain, -6.0);
+        assert!(!state.channels[0].muted);
+
+        let mute = ControlRequest {
+            action: Action::Mute as i32,
+            channel: None,
+            db: 0.0,
+        };
+        client.control(authorized(mute)).await.unwrap();
+        let state = client
+            .get_controls(authorized(GetControlsRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(state.channels[0].muted);
+        assert_eq!(state.channels[0].gain, -6.0);
+        assert_eq!(state.recording, None);
+
+        let invalid_channel = ControlRequest {
+            action: Action::Unmute as i32,
+            channel: Some(1),
+            db: 0.0,
+        };
+        let invalid = client
+            .control(authorized(invalid_channel))
+            .await
+            .unwrap_err();
+        assert_eq!(invalid.code(), Code::InvalidArgument);
+        let too_loud = ControlRequest {
+            action: Action::Gain as i32,
+            channel: None,
+            db: 60.0,
+        };
+        let invalid = client.control(authorized(too_loud)).await.unwrap_err();
+        assert_eq!(invalid.code(), Code::InvalidArgument);
+        let recording = ControlRequest {
+            action: Action::StopRecording as i32,
+            channel: None,
+            db: 0.0,
+        };
+        let invalid = client.control(authorized(recording)).await.unwrap_err();
+        assert_eq!(invalid.code(), Code::InvalidArgument);
+    });
+}
+
+#[test]
+fn open_levels() {
+    let server = GrpcServer::start(
+        "grpc-open-levels",
+        &["--auth", "token:secret", "--auth-open-levels"],
+    );
+    block_on(async {
+        let mut client = server.client().await;
+        let mut levels = client
+            .subscribe_levels(SubscribeLevelsRequest { max_rate: 5.0 })
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(levels.message().await.unwrap().is_some());
+        client.get_status(GetStatusRequest {}).await.unwrap();
+        let events = client
+            .subscribe_events(SubscribeEventsRequest { events: Vec::new() })
+            .await
+            .unwrap_err();
+        assert_eq!(events.code(), Code::Unauthenticated);
+    });
+}
@@ -0,0 +1,68 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! The responses of the http server: ranges of the recordings, the never ending
+//! streams and the Server-Sent Events.
+
+mod common;
+
+use common::Server;
+
+#[test]
+fn recording_ranges() {
+    let server = Server::start("http-ranges", &[]);
+    let whole = server.request("GET", "/recordings/take.wav", "", usize::MAX);
+    assert_eq!(whole.status, 200);
+    assert_eq!(whole.header("accept-ranges"), Some("bytes"));
+    assert_eq!(whole.body, b"recording");
+
+    let range = server.request("GET", "/recordings/take.wav", "Range: bytes=2-5\r\n", 4);
+    assert_eq!(range.status, 206);
+    assert_eq!(range.header("content-range"), Some("bytes 2-5/9"));
+    assert_eq!(range.header("content-length"), Some("4"));
+    assert_eq!(range.body, b"cord");
+
+    let suffix = server.request("GET", "/recordings/take.wav", "Range: bytes=-3\r\n", 3);
+    assert_eq!(suffix.status, 206);
+    assert_eq!(suffix.body, b"ing");
+
+    let unsatisfiable = server.request("GET", "/recordings/take.wav", "Range: bytes=20-\r\n", 0);
+    assert_eq!(unsatisfiable.status, 416);
+    assert_eq!(unsatisfiable.header("content-range"), Some("bytes */9"));
+
+    let head = server.request("HEAD", "/recordings/take.wav", "", 0);
+    assert_eq!(head.status, 200);
+    assert_eq!(head.header("content-length"), Some("9"));
+
+    let missing = server.request("GET", "/recordings/missing.wav", "", usize::MAX);
+    assert_eq!(missing.status, 404);
+}
+
+#[test]
+fn streams_and_events() {
+    let server = Server::start("http-streams", &[]);
+    let wav = server.request("GET", "/stream.wav", "", 64);
+    assert_eq!(wav.status, 200);
+    assert_eq!(wav.header("content-type"), Some("audio/wav"));
+    assert!(wav.body.windows(4).any(|window| window == b"RIFF"));
+
+    let invalid = server.request("GET", "/stream.raw?format=s12", "", usize::MAX);
+    assert_eq!(invalid.status, 400);
+
+    let events = server.request("GET", "/events", "", 64);
+    assert_eq!(events.status, 200);
+    assert_eq!(events.header("content-type"), Some("text/event-stream"));
+    assert!(events.body.windows(6).any(|window| window == b"data: "));
+}
@@ -0,0 +1,52 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Deleting recordings over http requires the credentials of `--auth`.
+
+mod common;
+
+use common::Server;
+
+/// The server allowing to delete the recordings, with the args.
+fn start(name: &str, args: &[&str]) -> Server {
+    let mut delete_args = vec!["--recordings-delete"];
+    delete_args.extend_from_slice(args);
+    Server::start(name, &delete_args)
+}
+
+/// The status code of the response to a request of the method and path.
+fn status(server: &Server, method: &str, path: &str, headers: &str) -> u16 {
+    server.request(method, path, headers, usize::MAX).status
+}
+
+#[test]
+fn delete_without_auth_is_forbidden() {
+    let server = start("delete-no-auth", &[]);
+    assert_eq!(status(&server, "DELETE", "/recordings/take.wav", ""), 403);
+    assert!(server.directory.join("take.wav").exists());
+}
+
+#[test]
+fn delete_requires_the_credentials() {
+    let server = start("delete-auth", &["--auth", "token:secret"]);
+    assert_eq!(status(&server, "DELETE", "/recordings/take.wav", ""), 401);
+    assert!(server.directory.join("take.wav").exists());
+    let authorization = "Authorization: Bearer secret\r\n";
+    assert_eq!(
+        status(&server, "DELETE", "/recordings/take.wav", authorization),
+        204
+    );
+    assert!(!server.directory.join("take.wav").exists());
+}
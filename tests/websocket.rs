@@ -0,0 +1,161 @@
+// Copyright 2020  Israel Basurto
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! The WebSockets of the http server, with a real client and with raw handshakes.
+
+mod common;
+
+use common::Server;
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+use tungstenite::{Message, WebSocket};
+
+/// Connect a client to the path, retrying while the server starts.
+fn connect(server: &Server, path: &str) -> WebSocket<TcpStream> {
+    let stream = (0..100)
+        .find_map(|_| {
+            TcpStream::connect(&server.address)
+                .map_err(|_| thread::sleep(Duration::from_millis(50)))
+                .ok()
+        })
+        .expect("the server is not listening");
+    stream
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    let url = format!("ws://{}{}", server.address, path);
+    let (socket, response) = tungstenite::client(url, stream).unwrap();
+    assert_eq!(response.status(), 101);
+    socket
+}
+
+#[test]
+fn levels_messages() {
+    let server = Server::start("websocket-levels", &[]);
+    let mut socket = connect(&server, "/ws/levels");
+    for _ in 0..3 {
+        match socket.read().unwrap() {
+            Message::Text(text) => {
+                let levels: serde_json::Value = serde_json::from_str(&text).unwrap();
+                assert!(levels.is_object(), "{}", text);
+            }
+            message => panic!("not a text message: {:?}", message),
+        }
+    }
+}
+
+#[test]
+fn ping_and_close() {
+    let server = Server::start("websocket-close", &[]);
+    let mut socket = connect(&server, "/ws/levels");
+    socket.send(Message::Ping(b"ping".to_vec().into())).unwrap();
+    // the pong comes between the levels
+    loop {
+        match socket.read().unwrap() {
+            Message::Pong(payload) => {
+                assert_eq!(&payload[..], b"ping");
+                break;
+            }
+            Message::Text(_) => (),
+            message => panic!("unexpected message: {:?}", message),
+        }
+    }
+    // the messages of the client are ignored
+    socket.send(Message::text("hello")).unwrap();
+
+    socket.close(None).unwrap();
+    let closed = loop {
+        match socket.read() {
+            Ok(_) => (),
+            Err(err) => break err,
+        }
+    };
+    // the close was answered, not only the connection dropped
+    assert!(
+        matches!(closed, tungstenite::Error::ConnectionClosed),
+        "{:?}",
+        closed
+    );
+}
+
+#[test]
+fn audio_format_then_samples() {
+    let server = Server::start("websocket-audio", &[]);
+    let mut socket = connect(&server, "/ws/audio?format=f32");
+    let format = match socket.read().unwrap() {
+        Message::Text(text) => serde_json::from_str::<serde_json::Value>(&text).unwrap(),
+        message => panic!("not the format: {:?}", message),
+    };
+    assert_eq!(format["format"], "f32");
+    let channels = format["channels"].as_u64().unwrap() as usize;
+    assert!(channels > 0);
+    match socket.read().unwrap() {
+        Message::Binary(samples) => {
+            assert!(!samples.is_empty());
+            assert_eq!(samples.len() % (4 * channels), 0);
+        }
+        message => panic!("not the samples: {:?}", message),
+    }
+}
+
+#[test]
+fn rejected_handshakes() {
+    let server = Server::start("websocket-rejected", &[]);
+    // the example handshake of RFC 6455
+    let upgrade = "Connection: Upgrade\r\n\
+                   Upgrade: websocket\r\n\
+                   Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n";
+    let accepted = server.request(
+        "GET",
+        "/ws/levels",
+        &format!("{}Sec-WebSocket-Version: 13\r\n", upgrade),
+        0,
+    );
+    assert_eq!(accepted.status, 101);
+    assert_eq!(
+        accepted.header("sec-websocket-accept"),
+        Some("s3pplmbitxaq9kygzzhzrbk+xoo=")
+    );
+
+    let post = server.request(
+        "POST",
+        "/ws/levels",
+        &format!("{}Sec-WebSocket-Version: 13\r\n", upgrade),
+        0,
+    );
+    assert_eq!(post.status, 405);
+
+    let no_version = server.request("GET", "/ws/levels", upgrade, 0);
+    assert_eq!(no_version.status, 400);
+
+    let old_version = server.request(
+        "GET",
+        "/ws/levels",
+        &format!("{}Sec-WebSocket-Version: 8\r\n", upgrade),
+        0,
+    );
+    assert_eq!(old_version.status, 400);
+
+    let not_upgrade = server.request("GET", "/ws/levels", "", usize::MAX);
+    assert_eq!(not_upgrade.status, 400);
+
+    let invalid = server.request(
+        "GET",
+        "/ws/audio?format=s12",
+        &format!("{}Sec-WebSocket-Version: 13\r\n", upgrade),
+        0,
+    );
+    assert_eq!(invalid.status, 400);
+}